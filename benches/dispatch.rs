@@ -0,0 +1,145 @@
+// Copyright 2023 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Benchmarks of the dispatch path against a simulated cluster, which serves requests in-process,
+//! so that they measure the client rather than the network: buffering a transaction's writes,
+//! merging batch gets with buffered writes, splitting requests across regions, and encoding
+//! requests.
+//!
+//! Run with `cargo bench --features simulation`.
+
+use criterion::criterion_group;
+use criterion::criterion_main;
+use criterion::BenchmarkId;
+use criterion::Criterion;
+use criterion::Throughput;
+use prost::Message;
+use tikv_client::simulation::Simulation;
+use tikv_client::transaction::lowering::new_batch_get_request;
+use tikv_client::Key;
+use tikv_client::Timestamp;
+use tokio::runtime::Runtime;
+
+const KEY_COUNT: u32 = 1000;
+
+fn key(i: u32) -> Key {
+    format!("key{i:06}").into()
+}
+
+fn runtime() -> Runtime {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+}
+
+/// Writing keys to a transaction's buffer, and reading them back from it.
+fn buffer(c: &mut Criterion) {
+    let rt = runtime();
+    let sim = &Simulation::new(0);
+    let mut group = c.benchmark_group("buffer");
+    group.throughput(Throughput::Elements(KEY_COUNT as u64));
+    group.bench_function("put_get", |b| {
+        b.to_async(&rt).iter(|| async move {
+            let mut txn = sim.begin_optimistic().await.unwrap();
+            for i in 0..KEY_COUNT {
+                txn.put(key(i), b"value".to_vec()).await.unwrap();
+            }
+            for i in 0..KEY_COUNT {
+                txn.get(key(i)).await.unwrap();
+            }
+            txn.rollback().await.unwrap();
+        })
+    });
+    group.finish();
+}
+
+/// A batch get of keys, some of which are buffered, and the rest read from the cluster.
+fn batch_get(c: &mut Criterion) {
+    let rt = runtime();
+    let sim = &Simulation::new(0);
+    rt.block_on(async {
+        let mut txn = sim.begin_optimistic().await.unwrap();
+        for i in 0..KEY_COUNT {
+            txn.put(key(i), b"committed".to_vec()).await.unwrap();
+        }
+        txn.commit().await.unwrap();
+    });
+
+    let mut group = c.benchmark_group("batch_get");
+    group.throughput(Throughput::Elements(KEY_COUNT as u64));
+    for buffered in [0, KEY_COUNT / 2, KEY_COUNT] {
+        let id = BenchmarkId::new("buffered", buffered);
+        group.bench_with_input(id, &buffered, |b, &buffered| {
+            b.to_async(&rt).iter(|| async move {
+                let mut txn = sim.begin_optimistic().await.unwrap();
+                for i in 0..buffered {
+                    txn.put(key(i), b"buffered".to_vec()).await.unwrap();
+                }
+                let pairs = txn.batch_get((0..KEY_COUNT).map(key)).await.unwrap();
+                assert_eq!(pairs.count(), KEY_COUNT as usize);
+                txn.rollback().await.unwrap();
+            })
+        });
+    }
+    group.finish();
+}
+
+/// Raw requests for keys spread over many regions, which are split into a request per region.
+fn region_split(c: &mut Criterion) {
+    let rt = runtime();
+    let mut group = c.benchmark_group("region_split");
+    group.throughput(Throughput::Elements(KEY_COUNT as u64));
+    for regions in [1, 10, 100] {
+        let sim = Simulation::new(0);
+        for i in 1..regions {
+            sim.split(key(i * KEY_COUNT / regions));
+        }
+        let client = &sim.raw_client();
+        let pairs: Vec<_> = (0..KEY_COUNT)
+            .map(|i| (key(i), b"value".to_vec()))
+            .collect();
+        rt.block_on(client.batch_put(pairs)).unwrap();
+
+        let id = BenchmarkId::new("batch_get", regions);
+        group.bench_with_input(id, &regions, |b, _| {
+            b.to_async(&rt).iter(|| async move {
+                let pairs = client.batch_get((0..KEY_COUNT).map(key)).await.unwrap();
+                assert_eq!(pairs.len(), KEY_COUNT as usize);
+            })
+        });
+        let id = BenchmarkId::new("scan", regions);
+        group.bench_with_input(id, &regions, |b, _| {
+            b.to_async(&rt).iter(|| async move {
+                let pairs = client.scan(key(0).., KEY_COUNT).await.unwrap();
+                assert_eq!(pairs.len(), KEY_COUNT as usize);
+            })
+        });
+    }
+    group.finish();
+}
+
+/// Encoding keys, and encoding and decoding the protobuf messages of requests.
+fn serialization(c: &mut Criterion) {
+    let keys: Vec<Key> = (0..KEY_COUNT).map(key).collect();
+    let request = new_batch_get_request(keys.clone().into_iter(), Timestamp::default());
+    let encoded = request.encode_to_vec();
+
+    let mut group = c.benchmark_group("serialization");
+    group.throughput(Throughput::Elements(KEY_COUNT as u64));
+    group.bench_function("encode_keys", |b| {
+        b.iter(|| keys.iter().map(Key::to_encoded).count())
+    });
+    group.bench_function("encode_request", |b| {
+        b.iter(|| {
+            let request = new_batch_get_request(keys.clone().into_iter(), Timestamp::default());
+            request.encode_to_vec()
+        })
+    });
+    group.bench_function("decode_request", |b| {
+        b.iter(|| tikv_client_proto::kvrpcpb::BatchGetRequest::decode(encoded.as_slice()).unwrap())
+    });
+    group.finish();
+}
+
+criterion_group!(benches, buffer, batch_get, region_split, serialization);
+criterion_main!(benches);
@@ -0,0 +1,247 @@
+// Copyright 2023 TiKV Project Authors. Licensed under Apache-2.0.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::Arc;
+
+use rand::Rng;
+
+use crate::Error;
+use crate::ErrorCode;
+use crate::Key;
+use crate::KvPair;
+use crate::Result;
+
+/// How a key was accessed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AuditOperation {
+    /// The key was read by a `get`.
+    Get,
+    /// The key was read by a `batch_get`.
+    BatchGet,
+    /// The key was returned by a scan.
+    Scan,
+    /// The key was written.
+    Put,
+    /// The key was deleted.
+    Delete,
+    /// The key was written by a `compare_and_swap`, if its value was the expected one.
+    CompareAndSwap,
+    /// The time to live of the key was reset by a `touch`.
+    Touch,
+    /// The keys of a range were deleted. The event carries the start key of the range.
+    DeleteRange,
+}
+
+/// What came of an access to a key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AuditOutcome {
+    /// The key was read, and has a value.
+    Found,
+    /// The key was read, and has no value.
+    NotFound,
+    /// The key was written or deleted.
+    Written,
+    /// The operation failed.
+    Failed,
+    /// The write failed in a way which leaves it unknown whether it was applied, e.g., its RPC
+    /// timed out.
+    Undetermined,
+}
+
+impl AuditOutcome {
+    /// The outcome of a write which finished with `result`.
+    pub fn of_write<T>(result: &Result<T>) -> AuditOutcome {
+        match result {
+            Ok(_) => AuditOutcome::Written,
+            Err(e) if is_undetermined(e) => AuditOutcome::Undetermined,
+            Err(_) => AuditOutcome::Failed,
+        }
+    }
+}
+
+fn is_undetermined(e: &Error) -> bool {
+    matches!(
+        e.code(),
+        ErrorCode::Undetermined | ErrorCode::DeadlineExceeded
+    ) || matches!(e.root(), Error::Grpc(_) | Error::GrpcAPI(_))
+}
+
+/// An access to a key, sampled for an [`AuditSink`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AuditEvent {
+    pub operation: AuditOperation,
+    pub key: Key,
+    pub outcome: AuditOutcome,
+}
+
+/// Records a sample of the keys a client reads and writes.
+///
+/// Register a sink using [`Config::with_audit_sink`](crate::Config::with_audit_sink). The sink is
+/// called on the task executing the operation, once the operation has finished, so it should not
+/// block. Transactions report their reads as they happen, and their writes when they commit.
+pub trait AuditSink: Send + Sync {
+    fn on_access(&self, event: AuditEvent);
+}
+
+impl<F: Fn(AuditEvent) + Send + Sync> AuditSink for F {
+    fn on_access(&self, event: AuditEvent) {
+        self(event)
+    }
+}
+
+/// An `AuditSink`, and the fraction of accesses it is told about.
+#[derive(Clone)]
+pub struct AuditLog {
+    sink: Arc<dyn AuditSink>,
+    sample_rate: f64,
+}
+
+impl AuditLog {
+    pub fn new(sink: Arc<dyn AuditSink>, sample_rate: f64) -> AuditLog {
+        AuditLog {
+            sink,
+            sample_rate: sample_rate.clamp(0.0, 1.0),
+        }
+    }
+
+    /// Tell the sink about the access of `key`, if the access is sampled.
+    pub fn record(&self, operation: AuditOperation, key: &Key, outcome: AuditOutcome) {
+        if self.sample_rate < 1.0 && !rand::thread_rng().gen_bool(self.sample_rate) {
+            return;
+        }
+        self.sink.on_access(AuditEvent {
+            operation,
+            key: key.clone(),
+            outcome,
+        });
+    }
+
+    /// Record the reads of `keys`, which found the pairs of `result` if it succeeded.
+    pub fn record_reads<'a>(
+        &self,
+        operation: AuditOperation,
+        keys: impl IntoIterator<Item = &'a Key>,
+        result: &Result<Vec<KvPair>>,
+    ) {
+        let found: Option<HashSet<&Key>> = result
+            .as_ref()
+            .ok()
+            .map(|pairs| pairs.iter().map(KvPair::key).collect());
+        for key in keys {
+            let outcome = match &found {
+                Some(found) if found.contains(key) => AuditOutcome::Found,
+                Some(_) => AuditOutcome::NotFound,
+                None => AuditOutcome::Failed,
+            };
+            self.record(operation, key, outcome);
+        }
+    }
+
+    /// Record the keys returned by a scan.
+    pub fn record_scan<'a>(&self, keys: impl IntoIterator<Item = &'a Key>) {
+        for key in keys {
+            self.record(AuditOperation::Scan, key, AuditOutcome::Found);
+        }
+    }
+
+    /// Record the writes of `keys`, which all came to `outcome`.
+    pub fn record_writes<'a>(
+        &self,
+        operation: AuditOperation,
+        keys: impl IntoIterator<Item = &'a Key>,
+        outcome: AuditOutcome,
+    ) {
+        for key in keys {
+            self.record(operation, key, outcome);
+        }
+    }
+}
+
+impl fmt::Debug for AuditLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuditLog")
+            .field("sample_rate", &self.sample_rate)
+            .finish_non_exhaustive()
+    }
+}
+
+impl PartialEq for AuditLog {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::as_ptr(&self.sink) as *const () == Arc::as_ptr(&other.sink) as *const ()
+            && self.sample_rate == other.sample_rate
+    }
+}
+
+impl Eq for AuditLog {}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    fn events_of(log: impl FnOnce(&AuditLog), sample_rate: f64) -> Vec<AuditEvent> {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let recorded = events.clone();
+        let sink = move |event| recorded.lock().unwrap().push(event);
+        log(&AuditLog::new(Arc::new(sink), sample_rate));
+        let events = events.lock().unwrap();
+        events.clone()
+    }
+
+    #[test]
+    fn test_record_reads() {
+        let keys: Vec<Key> = vec![vec![1].into(), vec![2].into()];
+        let found = Ok(vec![KvPair::new(vec![2], vec![20])]);
+        let events = events_of(
+            |log| log.record_reads(AuditOperation::BatchGet, &keys, &found),
+            1.0,
+        );
+        let outcomes: Vec<AuditOutcome> = events.iter().map(|event| event.outcome).collect();
+        assert_eq!(outcomes, vec![AuditOutcome::NotFound, AuditOutcome::Found]);
+
+        let failed = Err(crate::Error::Unimplemented);
+        let events = events_of(
+            |log| log.record_reads(AuditOperation::BatchGet, &keys, &failed),
+            1.0,
+        );
+        assert!(events
+            .iter()
+            .all(|event| event.outcome == AuditOutcome::Failed));
+    }
+
+    #[test]
+    fn test_write_outcome() {
+        assert_eq!(AuditOutcome::of_write(&Ok(())), AuditOutcome::Written);
+        let failed: Result<()> = Err(Error::Unimplemented);
+        assert_eq!(AuditOutcome::of_write(&failed), AuditOutcome::Failed);
+        let timed_out: Result<()> = Err(Error::GrpcAPI(tonic::Status::deadline_exceeded("")));
+        assert_eq!(
+            AuditOutcome::of_write(&timed_out),
+            AuditOutcome::Undetermined
+        );
+        let undetermined: Result<()> = Err(Error::UndeterminedError {
+            source: Box::new(Error::Unimplemented),
+            primary_key: vec![1],
+            start_ts: 1,
+        });
+        assert_eq!(
+            AuditOutcome::of_write(&undetermined),
+            AuditOutcome::Undetermined
+        );
+    }
+
+    #[test]
+    fn test_sampling() {
+        let keys: Vec<Key> = (0..1000u32)
+            .map(|i| i.to_be_bytes().to_vec().into())
+            .collect();
+        let record =
+            |log: &AuditLog| log.record_writes(AuditOperation::Put, &keys, AuditOutcome::Written);
+        assert_eq!(events_of(record, 0.0).len(), 0);
+        assert_eq!(events_of(record, 1.0).len(), 1000);
+        let sampled = events_of(record, 0.5).len();
+        assert!((300..700).contains(&sampled), "{sampled}");
+    }
+}
@@ -83,6 +83,11 @@ impl Backoff {
         self.current_attempts
     }
 
+    /// The longest delay between attempts.
+    pub fn max_delay(&self) -> Duration {
+        Duration::from_millis(self.max_delay_ms)
+    }
+
     /// Don't wait. Usually indicates that we should not retry a request.
     pub const fn no_backoff() -> Backoff {
         Backoff {
@@ -0,0 +1,344 @@
+// Copyright 2023 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! A command line client for TiKV, built on the `tikv-client` crate.
+//!
+//! It reads and writes keys in raw or transactional mode, and shows which regions cover a range
+//! of keys. This makes it handy for smoke testing a cluster, and an example of using the client.
+//!
+//! Build it with `cargo build --features cli --bin tikv-cli`, then, e.g.:
+//!
+//! ```text
+//! tikv-cli --pd 127.0.0.1:2379 put hello world
+//! tikv-cli --pd 127.0.0.1:2379 get hello
+//! tikv-cli --pd 127.0.0.1:2379 --mode txn scan --start a --end z --limit 10
+//! tikv-cli --pd 127.0.0.1:2379 --hex delete 68656c6c6f
+//! tikv-cli --pd 127.0.0.1:2379 regions --start a
+//! ```
+//!
+//! Keys and values are given and printed as UTF-8, with non-printable bytes escaped, or as hex
+//! with `--hex`.
+
+use std::fmt::Write;
+use std::path::PathBuf;
+use std::process;
+
+use clap::crate_version;
+use clap::App;
+use clap::AppSettings;
+use clap::Arg;
+use clap::ArgMatches;
+use clap::SubCommand;
+use tikv_client::BoundRange;
+use tikv_client::Config;
+use tikv_client::Key;
+use tikv_client::KvPair;
+use tikv_client::RawClient;
+use tikv_client::RegionInfo;
+use tikv_client::Result;
+use tikv_client::TransactionClient;
+use tikv_client::TransactionOptions;
+
+const DEFAULT_SCAN_LIMIT: &str = "100";
+
+enum Mode {
+    Raw,
+    Txn,
+}
+
+enum Command {
+    Get(Key),
+    Put(Key, Vec<u8>),
+    Delete(Key),
+    Scan(BoundRange, u32),
+    Regions(BoundRange),
+}
+
+struct Args {
+    pd: Vec<String>,
+    config: Config,
+    mode: Mode,
+    hex: bool,
+    command: Command,
+}
+
+#[tokio::main]
+async fn main() {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("error: {e}");
+            process::exit(2);
+        }
+    };
+    if let Err(e) = run(args).await {
+        eprintln!("error: {e}");
+        process::exit(1);
+    }
+}
+
+async fn run(args: Args) -> Result<()> {
+    let hex = args.hex;
+    let logger = slog::Logger::root(slog::Discard, slog::o!());
+    match args.mode {
+        Mode::Raw => {
+            let client = RawClient::new_with_config(args.pd, args.config, Some(logger)).await?;
+            match args.command {
+                Command::Get(key) => print_value(client.get(key).await?, hex),
+                Command::Put(key, value) => client.put(key, value).await?,
+                Command::Delete(key) => client.delete(key).await?,
+                Command::Scan(range, limit) => print_pairs(client.scan(range, limit).await?, hex),
+                Command::Regions(range) => print_regions(client.regions(range).await?, hex),
+            }
+        }
+        Mode::Txn => {
+            let client =
+                TransactionClient::new_with_config(args.pd, args.config, Some(logger)).await?;
+            match args.command {
+                Command::Get(key) => {
+                    let mut snapshot = snapshot(&client).await?;
+                    print_value(snapshot.get(key).await?, hex)
+                }
+                Command::Put(key, value) => {
+                    let mut txn = client.begin_optimistic().await?;
+                    txn.put(key, value).await?;
+                    txn.commit().await?;
+                }
+                Command::Delete(key) => {
+                    let mut txn = client.begin_optimistic().await?;
+                    txn.delete(key).await?;
+                    txn.commit().await?;
+                }
+                Command::Scan(range, limit) => {
+                    let mut snapshot = snapshot(&client).await?;
+                    print_pairs(snapshot.scan(range, limit).await?, hex)
+                }
+                Command::Regions(range) => print_regions(client.regions(range).await?, hex),
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn snapshot(client: &TransactionClient) -> Result<tikv_client::Snapshot> {
+    let timestamp = client.current_timestamp().await?;
+    Ok(client.snapshot(timestamp, TransactionOptions::new_optimistic().read_only()))
+}
+
+fn print_value(value: Option<Vec<u8>>, hex: bool) {
+    match value {
+        Some(value) => println!("{}", format_bytes(&value, hex)),
+        None => println!("(not found)"),
+    }
+}
+
+fn print_pairs(pairs: impl IntoIterator<Item = KvPair>, hex: bool) {
+    for pair in pairs {
+        let key: &[u8] = pair.key().into();
+        println!(
+            "{}\t{}",
+            format_bytes(key, hex),
+            format_bytes(pair.value(), hex)
+        );
+    }
+}
+
+fn print_regions(regions: Vec<RegionInfo>, hex: bool) {
+    println!("id\tstart\tend\tleader store\taddress");
+    for region in regions {
+        let leader = match region.leader_store_id {
+            Some(store_id) => store_id.to_string(),
+            None => "-".to_owned(),
+        };
+        println!(
+            "{}\t{}\t{}\t{}\t{}",
+            region.id,
+            format_bytes((&region.start_key).into(), hex),
+            format_bytes((&region.end_key).into(), hex),
+            leader,
+            region.store_address
+        );
+    }
+}
+
+fn format_bytes(bytes: &[u8], hex: bool) -> String {
+    if hex {
+        bytes.iter().fold(String::new(), |mut s, byte| {
+            let _ = write!(s, "{byte:02x}");
+            s
+        })
+    } else {
+        bytes.escape_ascii().to_string()
+    }
+}
+
+fn parse_bytes(s: &str, hex: bool) -> std::result::Result<Vec<u8>, String> {
+    if !hex {
+        return Ok(s.as_bytes().to_vec());
+    }
+    s.as_bytes()
+        .chunks(2)
+        .map(|digits| {
+            std::str::from_utf8(digits)
+                .ok()
+                .filter(|digits| digits.len() == 2)
+                .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+                .ok_or_else(|| format!("invalid hex {s:?}"))
+        })
+        .collect()
+}
+
+fn parse_args() -> std::result::Result<Args, String> {
+    let range_args = || {
+        [
+            Arg::with_name("start")
+                .long("start")
+                .value_name("KEY")
+                .help("The first key of the range; the start of all keys if not given")
+                .takes_value(true),
+            Arg::with_name("end")
+                .long("end")
+                .value_name("KEY")
+                .help("The key after the range; the end of all keys if not given")
+                .takes_value(true),
+        ]
+    };
+    let matches = App::new("tikv-cli")
+        .version(crate_version!())
+        .author("The TiKV Project Authors")
+        .about("Reads and writes keys of a TiKV cluster")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .arg(
+            Arg::with_name("pd")
+                .long("pd")
+                .value_name("PD_URL")
+                .help("Sets PD endpoints, separated by `,`")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_delimiter(",")
+                .default_value("127.0.0.1:2379"),
+        )
+        .arg(
+            Arg::with_name("mode")
+                .long("mode")
+                .help("Whether to use raw or transactional requests")
+                .takes_value(true)
+                .possible_values(&["raw", "txn"])
+                .default_value("raw"),
+        )
+        .arg(
+            Arg::with_name("hex")
+                .long("hex")
+                .help("Gives and prints keys and values as hex"),
+        )
+        .arg(
+            Arg::with_name("ca")
+                .long("ca")
+                .value_name("CA_PATH")
+                .help("Sets the CA. Must be used with --cert and --key")
+                .takes_value(true)
+                .requires_all(&["cert", "key"]),
+        )
+        .arg(
+            Arg::with_name("cert")
+                .long("cert")
+                .value_name("CERT_PATH")
+                .help("Sets the certificate. Must be used with --ca and --key")
+                .takes_value(true)
+                .requires_all(&["ca", "key"]),
+        )
+        .arg(
+            Arg::with_name("key")
+                .long("key")
+                .value_name("KEY_PATH")
+                .help("Sets the private key. Must be used with --ca and --cert")
+                .takes_value(true)
+                .requires_all(&["ca", "cert"]),
+        )
+        .subcommand(
+            SubCommand::with_name("get")
+                .about("Prints the value of a key")
+                .arg(Arg::with_name("KEY").required(true)),
+        )
+        .subcommand(
+            SubCommand::with_name("put")
+                .about("Sets the value of a key")
+                .arg(Arg::with_name("KEY").required(true))
+                .arg(Arg::with_name("VALUE").required(true)),
+        )
+        .subcommand(
+            SubCommand::with_name("delete")
+                .about("Deletes a key")
+                .arg(Arg::with_name("KEY").required(true)),
+        )
+        .subcommand(
+            SubCommand::with_name("scan")
+                .about("Prints the keys and values in a range")
+                .args(&range_args())
+                .arg(
+                    Arg::with_name("limit")
+                        .long("limit")
+                        .help("The maximum number of pairs to print")
+                        .takes_value(true)
+                        .default_value(DEFAULT_SCAN_LIMIT),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("regions")
+                .about("Prints the regions covering a range, and where their leaders are")
+                .args(&range_args()),
+        )
+        .get_matches();
+
+    let hex = matches.is_present("hex");
+    let key = |matches: &ArgMatches| -> std::result::Result<Key, String> {
+        parse_bytes(matches.value_of("KEY").unwrap(), hex).map(Into::into)
+    };
+    let range = |matches: &ArgMatches| -> std::result::Result<BoundRange, String> {
+        let start = parse_bytes(matches.value_of("start").unwrap_or(""), hex)?;
+        let end = match matches.value_of("end") {
+            Some(end) => Some(parse_bytes(end, hex)?),
+            None => None,
+        };
+        Ok((start, end).into())
+    };
+    let command = match matches.subcommand() {
+        ("get", Some(matches)) => Command::Get(key(matches)?),
+        ("put", Some(matches)) => Command::Put(
+            key(matches)?,
+            parse_bytes(matches.value_of("VALUE").unwrap(), hex)?,
+        ),
+        ("delete", Some(matches)) => Command::Delete(key(matches)?),
+        ("scan", Some(matches)) => {
+            let limit = matches.value_of("limit").unwrap();
+            let limit = limit
+                .parse()
+                .map_err(|_| format!("invalid limit {limit:?}"))?;
+            Command::Scan(range(matches)?, limit)
+        }
+        ("regions", Some(matches)) => Command::Regions(range(matches)?),
+        _ => unreachable!("a subcommand is required"),
+    };
+
+    let config = match (
+        matches.value_of("ca"),
+        matches.value_of("cert"),
+        matches.value_of("key"),
+    ) {
+        (Some(ca), Some(cert), Some(key)) => {
+            Config::default().with_security(PathBuf::from(ca), cert, key)
+        }
+        _ => Config::default(),
+    };
+    let mode = match matches.value_of("mode") {
+        Some("txn") => Mode::Txn,
+        _ => Mode::Raw,
+    };
+    Ok(Args {
+        pd: matches.values_of("pd").unwrap().map(String::from).collect(),
+        config,
+        mode,
+        hex,
+        command,
+    })
+}
@@ -0,0 +1,433 @@
+// Copyright 2023 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Failing fast on requests to unhealthy TiKV stores.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::Weak;
+use std::time::Duration;
+use std::time::Instant;
+
+use futures::future;
+use serde_derive::Deserialize;
+use serde_derive::Serialize;
+use tikv_client_store::KvClient;
+use tokio::sync::RwLock;
+use tokio_util::sync::CancellationToken;
+
+use crate::clock::Clock;
+use crate::Error;
+use crate::Result;
+
+/// When to stop sending requests to a TiKV store which keeps failing.
+///
+/// A store is deemed unhealthy after `failure_threshold` consecutive requests to it fail, or are
+/// slower than `slow_request_threshold`. Requests to an unhealthy store fail immediately with
+/// [`Error::StoreUnavailable`] for `cooldown`, rather than each waiting out its timeout. After the
+/// cooldown, one request is let through; if it succeeds the store is healthy again, otherwise it
+/// is avoided for another cooldown.
+///
+/// # Examples
+/// ```rust
+/// # use tikv_client::{CircuitBreaker, Config};
+/// # use std::time::Duration;
+/// let config = Config::default().with_circuit_breaker(
+///     CircuitBreaker::default()
+///         .failure_threshold(3)
+///         .slow_request_threshold(Duration::from_secs(1))
+///         .cooldown(Duration::from_secs(5)),
+/// );
+/// ```
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+#[serde(rename_all = "kebab-case")]
+pub struct CircuitBreaker {
+    pub failure_threshold: u32,
+    pub slow_request_threshold: Option<Duration>,
+    pub cooldown: Duration,
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        CircuitBreaker {
+            failure_threshold: 5,
+            slow_request_threshold: None,
+            cooldown: Duration::from_secs(10),
+        }
+    }
+}
+
+impl CircuitBreaker {
+    /// Set how many consecutive failures make a store unhealthy.
+    #[must_use]
+    pub fn failure_threshold(mut self, failure_threshold: u32) -> Self {
+        self.failure_threshold = failure_threshold.max(1);
+        self
+    }
+
+    /// Count requests which succeed, but take longer than `threshold`, as failures.
+    #[must_use]
+    pub fn slow_request_threshold(mut self, threshold: Duration) -> Self {
+        self.slow_request_threshold = Some(threshold);
+        self
+    }
+
+    /// Set how long an unhealthy store is avoided.
+    #[must_use]
+    pub fn cooldown(mut self, cooldown: Duration) -> Self {
+        self.cooldown = cooldown;
+        self
+    }
+}
+
+/// How to check the connections to TiKV stores which are not in use.
+///
+/// Every `interval`, each connection which no request has used for an `interval` is probed with a
+/// cheap request. If a store doesn't respond within `timeout`, it is deemed unhealthy, as by a
+/// [`CircuitBreaker`], so that the next request to it fails immediately with
+/// [`Error::StoreUnavailable`] and is retried elsewhere, rather than waiting out its timeout. If an
+/// unhealthy store responds, its cooldown ends early: one request is let through, and the store is
+/// healthy again only if that request succeeds.
+///
+/// # Examples
+/// ```rust
+/// # use tikv_client::{Config, HealthProbe};
+/// # use std::time::Duration;
+/// let config = Config::default().with_health_probe(
+///     HealthProbe::default()
+///         .interval(Duration::from_secs(5))
+///         .timeout(Duration::from_millis(500)),
+/// );
+/// ```
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+#[serde(rename_all = "kebab-case")]
+pub struct HealthProbe {
+    pub interval: Duration,
+    pub timeout: Duration,
+}
+
+impl Default for HealthProbe {
+    fn default() -> Self {
+        HealthProbe {
+            interval: Duration::from_secs(10),
+            timeout: Duration::from_secs(1),
+        }
+    }
+}
+
+impl HealthProbe {
+    /// Set how often idle connections are probed.
+    #[must_use]
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Set how long a store has to respond to a probe.
+    #[must_use]
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+/// The health of each store, according to a [`CircuitBreaker`], shared by all requests of a
+/// client.
+pub struct StoreHealth {
+    config: CircuitBreaker,
+    clock: Arc<dyn Clock>,
+    stores: Mutex<HashMap<String, StoreState>>,
+    /// When a request to each store last finished.
+    last_used: Mutex<HashMap<String, Instant>>,
+}
+
+#[derive(Default)]
+struct StoreState {
+    consecutive_failures: u32,
+    unhealthy_until: Option<Instant>,
+}
+
+impl StoreHealth {
+    pub fn new(config: CircuitBreaker, clock: Arc<dyn Clock>) -> StoreHealth {
+        StoreHealth {
+            config,
+            clock,
+            stores: Mutex::new(HashMap::new()),
+            last_used: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Fail if requests to the store at `address` should not be sent.
+    pub fn check(&self, address: &str) -> Result<()> {
+        self.check_at(address, self.clock.now())
+    }
+
+    /// Record the outcome of a request to the store at `address` which took `latency`.
+    pub fn record(&self, address: &str, success: bool, latency: Duration) {
+        self.record_at(address, success, latency, self.clock.now())
+    }
+
+    fn check_at(&self, address: &str, now: Instant) -> Result<()> {
+        let mut stores = self.stores.lock().unwrap();
+        let state = match stores.get_mut(address) {
+            Some(state) => state,
+            None => return Ok(()),
+        };
+        match state.unhealthy_until {
+            Some(until) if now < until => Err(Error::StoreUnavailable {
+                address: address.to_owned(),
+            }),
+            Some(_) => {
+                // Let one request through to probe the store; the rest wait for its outcome.
+                state.unhealthy_until = Some(now + self.config.cooldown);
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Record the outcome of a probe of the store at `address`. An unhealthy store which responds
+    /// has its cooldown ended, so that the next request tries it; one which doesn't respond is
+    /// avoided for a cooldown straight away.
+    pub fn record_probe(&self, address: &str, success: bool) {
+        self.record_probe_at(address, success, self.clock.now())
+    }
+
+    /// Whether no request to the store at `address` has finished within `interval` of `now`.
+    fn is_idle(&self, address: &str, interval: Duration, now: Instant) -> bool {
+        self.last_used
+            .lock()
+            .unwrap()
+            .get(address)
+            .is_none_or(|last_used| now.saturating_duration_since(*last_used) >= interval)
+    }
+
+    fn record_probe_at(&self, address: &str, success: bool, now: Instant) {
+        let mut stores = self.stores.lock().unwrap();
+        if success {
+            // Responding to a probe says less about a store than serving a request, so its
+            // failures are kept until a request succeeds.
+            if let Some(state) = stores.get_mut(address) {
+                if state.unhealthy_until.is_some() {
+                    state.unhealthy_until = Some(now);
+                }
+            }
+            return;
+        }
+        let state = stores.entry(address.to_owned()).or_default();
+        state.consecutive_failures = state
+            .consecutive_failures
+            .max(self.config.failure_threshold);
+        state.unhealthy_until = Some(now + self.config.cooldown);
+    }
+
+    fn record_at(&self, address: &str, success: bool, latency: Duration, now: Instant) {
+        self.last_used
+            .lock()
+            .unwrap()
+            .insert(address.to_owned(), now);
+        let slow = self
+            .config
+            .slow_request_threshold
+            .is_some_and(|threshold| latency > threshold);
+        let mut stores = self.stores.lock().unwrap();
+        if success && !slow {
+            stores.remove(address);
+            return;
+        }
+        let state = stores.entry(address.to_owned()).or_default();
+        state.consecutive_failures += 1;
+        if state.consecutive_failures >= self.config.failure_threshold {
+            state.unhealthy_until = Some(now + self.config.cooldown);
+        }
+    }
+}
+
+/// The connections of a client to each store, by address.
+pub(crate) type Connections<C> = RwLock<HashMap<String, C>>;
+
+/// Probe the idle connections in `connections` as configured by `probe`, recording the outcomes in
+/// `health`, until the connections are dropped or `closed` is cancelled.
+pub(crate) async fn run_health_probes<C: KvClient + Clone + Send + Sync + 'static>(
+    probe: HealthProbe,
+    connections: Weak<Connections<C>>,
+    health: Arc<StoreHealth>,
+    closed: CancellationToken,
+) {
+    loop {
+        tokio::select! {
+            _ = closed.cancelled() => return,
+            _ = health.clock.sleep(probe.interval) => {}
+        }
+        let connections = match connections.upgrade() {
+            Some(connections) => connections,
+            None => return,
+        };
+        probe_idle(&probe, &connections, &health).await;
+    }
+}
+
+/// Probe the connections in `connections` which are idle, concurrently.
+async fn probe_idle<C: KvClient + Clone + Send + Sync + 'static>(
+    probe: &HealthProbe,
+    connections: &Connections<C>,
+    health: &StoreHealth,
+) {
+    let now = health.clock.now();
+    let idle: Vec<(String, C)> = connections
+        .read()
+        .await
+        .iter()
+        .filter(|(address, _)| health.is_idle(address, probe.interval, now))
+        .map(|(address, client)| (address.clone(), client.clone()))
+        .collect();
+    let probes = idle.into_iter().map(|(address, client)| async move {
+        let success = client.probe(probe.timeout).await.is_ok();
+        health.record_probe(&address, success);
+    });
+    future::join_all(probes).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::any::Any;
+
+    use async_trait::async_trait;
+    use tikv_client_store::Request;
+
+    use super::*;
+    use crate::clock::MockClock;
+    use crate::clock::SystemClock;
+
+    #[test]
+    fn test_store_health() {
+        let health = StoreHealth::new(
+            CircuitBreaker::default()
+                .failure_threshold(2)
+                .slow_request_threshold(Duration::from_millis(100))
+                .cooldown(Duration::from_secs(1)),
+            Arc::new(SystemClock),
+        );
+        let start = Instant::now();
+        let fast = Duration::from_millis(1);
+        let slow = Duration::from_millis(200);
+
+        // A success resets the count of failures.
+        health.record_at("a", false, fast, start);
+        health.record_at("a", true, fast, start);
+        health.record_at("a", false, fast, start);
+        assert!(health.check_at("a", start).is_ok());
+
+        // Slow requests count as failures.
+        health.record_at("a", true, slow, start);
+        assert!(matches!(
+            health.check_at("a", start),
+            Err(Error::StoreUnavailable { .. })
+        ));
+        assert!(health.check_at("b", start).is_ok());
+
+        // After the cooldown, one request probes the store.
+        let later = start + Duration::from_secs(2);
+        assert!(health.check_at("a", later).is_ok());
+        assert!(health.check_at("a", later).is_err());
+        health.record_at("a", false, fast, later);
+        let during_cooldown = later + Duration::from_millis(500);
+        assert!(health.check_at("a", during_cooldown).is_err());
+
+        let later = later + Duration::from_secs(2);
+        assert!(health.check_at("a", later).is_ok());
+        health.record_at("a", true, fast, later);
+        assert!(health.check_at("a", later).is_ok());
+    }
+
+    /// Responds to probes if `up`.
+    #[derive(Clone)]
+    struct ProbedClient {
+        up: bool,
+    }
+
+    #[async_trait]
+    impl KvClient for ProbedClient {
+        async fn dispatch(&self, _: &dyn Request) -> Result<Box<dyn Any>> {
+            unimplemented!()
+        }
+
+        async fn probe(&self, _: Duration) -> Result<()> {
+            if self.up {
+                Ok(())
+            } else {
+                Err(Error::Unimplemented)
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_probe_idle() {
+        let probe = HealthProbe::default().interval(Duration::from_secs(60));
+        let health = StoreHealth::new(CircuitBreaker::default(), Arc::new(SystemClock));
+        let connections = RwLock::new(HashMap::new());
+        for (address, up) in [("up", true), ("down", false), ("busy", false)] {
+            connections
+                .write()
+                .await
+                .insert(address.to_owned(), ProbedClient { up });
+        }
+        // A store used within the interval is not probed.
+        health.record("busy", true, Duration::from_millis(1));
+
+        probe_idle(&probe, &connections, &health).await;
+        assert!(health.check("up").is_ok());
+        assert!(matches!(
+            health.check("down"),
+            Err(Error::StoreUnavailable { .. })
+        ));
+        assert!(health.check("busy").is_ok());
+
+        // A store which responds again is tried by one request straight away, which decides
+        // whether it is healthy.
+        connections
+            .write()
+            .await
+            .insert("down".to_owned(), ProbedClient { up: true });
+        probe_idle(&probe, &connections, &health).await;
+        assert!(health.check("down").is_ok());
+        assert!(health.check("down").is_err());
+        health.record("down", true, Duration::from_millis(1));
+        assert!(health.check("down").is_ok());
+        assert!(health.check("down").is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_health_probes() {
+        let clock = Arc::new(MockClock::new());
+        let interval = Duration::from_secs(10);
+        let health = Arc::new(StoreHealth::new(CircuitBreaker::default(), clock.clone()));
+        let connections = Arc::new(RwLock::new(HashMap::new()));
+        connections
+            .write()
+            .await
+            .insert("down".to_owned(), ProbedClient { up: false });
+        let closed = CancellationToken::new();
+        let probes = tokio::spawn(run_health_probes(
+            HealthProbe::default().interval(interval),
+            Arc::downgrade(&connections),
+            health.clone(),
+            closed.clone(),
+        ));
+
+        // The store is only probed once the interval has passed on the client's clock.
+        while clock.sleepers() == 0 {
+            tokio::task::yield_now().await;
+        }
+        assert!(health.check("down").is_ok());
+        clock.advance(interval);
+        while health.check("down").is_ok() {
+            tokio::task::yield_now().await;
+        }
+
+        closed.cancel();
+        probes.await.unwrap();
+    }
+}
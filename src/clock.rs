@@ -0,0 +1,181 @@
+// Copyright 2023 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Time, as seen by the client's time-dependent logic.
+
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use async_trait::async_trait;
+use futures::channel::oneshot;
+use futures::future::select;
+use futures::future::Either;
+
+/// A source of time for transaction heartbeats and lock TTLs, retry backoff, and deadlines.
+///
+/// Clients use the [`SystemClock`]. Tests can use a [`MockClock`] instead, to control time rather
+/// than wait for it to pass.
+#[async_trait]
+pub trait Clock: Send + Sync + 'static {
+    /// The current time.
+    fn now(&self) -> Instant;
+
+    /// Wait until `deadline`.
+    async fn sleep_until(&self, deadline: Instant);
+
+    /// Wait for `duration`.
+    async fn sleep(&self, duration: Duration) {
+        self.sleep_until(self.now() + duration).await
+    }
+}
+
+/// The real time, as kept by tokio.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+#[async_trait]
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    async fn sleep_until(&self, deadline: Instant) {
+        tokio::time::sleep_until(deadline.into()).await
+    }
+}
+
+/// A clock which only moves when [advanced](MockClock::advance).
+///
+/// # Examples
+///
+/// ```rust
+/// # use tikv_client::{Clock, MockClock};
+/// # use std::time::Duration;
+/// # use futures::prelude::*;
+/// # futures::executor::block_on(async {
+/// let clock = MockClock::new();
+/// let start = clock.now();
+/// let mut sleep = clock.sleep(Duration::from_secs(10));
+/// assert!(futures::poll!(&mut sleep).is_pending());
+/// clock.advance(Duration::from_secs(10));
+/// sleep.await;
+/// assert_eq!(clock.now() - start, Duration::from_secs(10));
+/// # });
+/// ```
+pub struct MockClock {
+    start: Instant,
+    state: Mutex<MockClockState>,
+}
+
+struct MockClockState {
+    elapsed: Duration,
+    sleepers: Vec<(Instant, oneshot::Sender<()>)>,
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        MockClock {
+            start: Instant::now(),
+            state: Mutex::new(MockClockState {
+                elapsed: Duration::ZERO,
+                sleepers: Vec::new(),
+            }),
+        }
+    }
+}
+
+impl MockClock {
+    pub fn new() -> MockClock {
+        MockClock::default()
+    }
+
+    /// Move the clock forward by `duration`, waking everything sleeping until then.
+    pub fn advance(&self, duration: Duration) {
+        let mut state = self.state.lock().unwrap();
+        state.elapsed += duration;
+        let now = self.start + state.elapsed;
+        let (woken, sleeping) = state
+            .sleepers
+            .drain(..)
+            .partition(|(deadline, _)| *deadline <= now);
+        state.sleepers = sleeping;
+        for (_, sender) in woken {
+            let _ = sender.send(());
+        }
+    }
+
+    /// The number of tasks sleeping on the clock.
+    pub fn sleepers(&self) -> usize {
+        let mut state = self.state.lock().unwrap();
+        state.sleepers.retain(|(_, sender)| !sender.is_canceled());
+        state.sleepers.len()
+    }
+}
+
+#[async_trait]
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.start + self.state.lock().unwrap().elapsed
+    }
+
+    async fn sleep_until(&self, deadline: Instant) {
+        let receiver = {
+            let mut state = self.state.lock().unwrap();
+            if deadline <= self.start + state.elapsed {
+                return;
+            }
+            let (sender, receiver) = oneshot::channel();
+            state.sleepers.push((deadline, sender));
+            receiver
+        };
+        let _ = receiver.await;
+    }
+}
+
+/// Run `future` until `deadline` on `clock`. Returns `None` if the deadline passes first.
+pub(crate) async fn timeout_at<F: Future>(
+    clock: &dyn Clock,
+    deadline: Instant,
+    future: F,
+) -> Option<F::Output> {
+    match select(Box::pin(future), clock.sleep_until(deadline)).await {
+        Either::Left((output, _)) => Some(output),
+        Either::Right(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::future::pending;
+    use futures::future::ready;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_mock_clock() {
+        let clock = MockClock::new();
+        let start = clock.now();
+        let mut first = clock.sleep(Duration::from_secs(1));
+        let mut second = clock.sleep_until(start + Duration::from_secs(2));
+        assert!(futures::poll!(&mut first).is_pending());
+        assert!(futures::poll!(&mut second).is_pending());
+        assert_eq!(clock.sleepers(), 2);
+
+        clock.advance(Duration::from_millis(1500));
+        first.await;
+        assert!(futures::poll!(&mut second).is_pending());
+        drop(second);
+        assert_eq!(clock.sleepers(), 0);
+
+        // Sleeping until a time in the past doesn't wait.
+        clock.sleep_until(start).await;
+
+        let deadline = clock.now() + Duration::from_secs(1);
+        assert_eq!(timeout_at(&clock, deadline, ready(1)).await, Some(1));
+        let mut timeout = Box::pin(timeout_at(&clock, deadline, pending::<()>()));
+        assert!(futures::poll!(&mut timeout).is_pending());
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(timeout.await, None);
+    }
+}
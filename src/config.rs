@@ -1,11 +1,34 @@
 // Copyright 2019 TiKV Project Authors. Licensed under Apache-2.0.
 
 use std::path::PathBuf;
+use std::sync::Arc;
 use std::time::Duration;
 
 use serde_derive::Deserialize;
 use serde_derive::Serialize;
 
+use crate::audit::AuditLog;
+#[cfg(any(test, feature = "fault-injection"))]
+use crate::fault_injection::FaultInjector;
+use crate::retry_observer::RetryObserverHandle;
+use crate::spawner::SpawnerHandle;
+use crate::timestamp::TimestampProviderHandle;
+use crate::value_format::ValueCipherHandle;
+use crate::AuditSink;
+use crate::CircuitBreaker;
+use crate::Compression;
+use crate::GroupCommit;
+use crate::HealthProbe;
+use crate::HotKeyTracking;
+use crate::Proxy;
+use crate::RateLimit;
+use crate::Redaction;
+use crate::RetryObserver;
+use crate::Spawner;
+use crate::TimestampPrefetch;
+use crate::TimestampProvider;
+use crate::ValueCipher;
+
 /// The configuration for either a [`RawClient`](crate::RawClient) or a
 /// [`TransactionClient`](crate::TransactionClient).
 ///
@@ -19,6 +42,33 @@ pub struct Config {
     pub cert_path: Option<PathBuf>,
     pub key_path: Option<PathBuf>,
     pub timeout: Duration,
+    pub pd_timeout: Option<Duration>,
+    pub pd_retry_budget: Option<Duration>,
+    pub rate_limit: Option<RateLimit>,
+    pub circuit_breaker: Option<CircuitBreaker>,
+    pub health_probe: Option<HealthProbe>,
+    pub max_in_flight_per_store: Option<usize>,
+    pub group_commit: Option<GroupCommit>,
+    pub read_cache_capacity: Option<usize>,
+    pub hot_key_tracking: Option<HotKeyTracking>,
+    pub timestamp_prefetch: Option<TimestampPrefetch>,
+    pub compression: Option<Compression>,
+    pub value_checksum: bool,
+    pub redaction: Redaction,
+    pub proxy: Option<Proxy>,
+    #[serde(skip)]
+    pub(crate) value_cipher: Option<ValueCipherHandle>,
+    #[serde(skip)]
+    pub(crate) retry_observer: Option<RetryObserverHandle>,
+    #[serde(skip)]
+    pub(crate) spawner: Option<SpawnerHandle>,
+    #[serde(skip)]
+    pub(crate) audit_log: Option<AuditLog>,
+    #[serde(skip)]
+    pub(crate) timestamp_provider: Option<TimestampProviderHandle>,
+    #[cfg(any(test, feature = "fault-injection"))]
+    #[serde(skip)]
+    pub(crate) fault_injector: Option<FaultInjector>,
 }
 
 const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
@@ -30,6 +80,27 @@ impl Default for Config {
             cert_path: None,
             key_path: None,
             timeout: DEFAULT_REQUEST_TIMEOUT,
+            pd_timeout: None,
+            pd_retry_budget: None,
+            rate_limit: None,
+            circuit_breaker: None,
+            health_probe: None,
+            max_in_flight_per_store: None,
+            group_commit: None,
+            read_cache_capacity: None,
+            hot_key_tracking: None,
+            timestamp_prefetch: None,
+            compression: None,
+            value_checksum: false,
+            redaction: Redaction::Off,
+            proxy: None,
+            value_cipher: None,
+            retry_observer: None,
+            spawner: None,
+            audit_log: None,
+            timestamp_provider: None,
+            #[cfg(any(test, feature = "fault-injection"))]
+            fault_injector: None,
         }
     }
 }
@@ -83,4 +154,384 @@ impl Config {
         self.timeout = timeout;
         self
     }
+
+    /// Set the timeout of each request to PD, in place of the [`timeout`](Config::with_timeout)
+    /// shared with requests to TiKV.
+    ///
+    /// # Examples
+    /// ```rust
+    /// # use tikv_client::Config;
+    /// # use std::time::Duration;
+    /// let config = Config::default().with_pd_timeout(Duration::from_millis(500));
+    /// ```
+    #[must_use]
+    pub fn with_pd_timeout(mut self, timeout: Duration) -> Self {
+        self.pd_timeout = Some(timeout);
+        self
+    }
+
+    /// Limit how long a request to PD, such as a region lookup or fetching a timestamp, may take,
+    /// retries and reconnections included.
+    ///
+    /// A request which runs over the budget fails with
+    /// [`Error::PdTimeout`](crate::Error::PdTimeout), rather than taking up the time a
+    /// transaction has for its requests to TiKV. The retries and overruns of PD requests are
+    /// counted by the `pd_request_retry_total` and `pd_request_budget_exceeded_total` metrics.
+    /// By default, requests to PD are retried without a time limit.
+    ///
+    /// # Examples
+    /// ```rust
+    /// # use tikv_client::Config;
+    /// # use std::time::Duration;
+    /// let config = Config::default().with_pd_retry_budget(Duration::from_secs(3));
+    /// ```
+    #[must_use]
+    pub fn with_pd_retry_budget(mut self, budget: Duration) -> Self {
+        self.pd_retry_budget = Some(budget);
+        self
+    }
+
+    /// Throttle the requests a client sends to TiKV.
+    ///
+    /// Requests which would exceed the limit are delayed until they fit. By default, requests are
+    /// not throttled.
+    ///
+    /// # Examples
+    /// ```rust
+    /// # use tikv_client::{Config, RateLimit};
+    /// let config = Config::default().with_rate_limit(RateLimit::default().requests_per_second(100));
+    /// ```
+    #[must_use]
+    pub fn with_rate_limit(mut self, rate_limit: RateLimit) -> Self {
+        self.rate_limit = Some(rate_limit);
+        self
+    }
+
+    /// Stop sending requests to TiKV stores which keep failing or responding slowly.
+    ///
+    /// Requests to such a store fail immediately with
+    /// [`Error::StoreUnavailable`](crate::Error::StoreUnavailable) until its cooldown ends. Reads
+    /// are not yet routed to other replicas of a region, so they fail too. By default, requests
+    /// are always sent.
+    ///
+    /// # Examples
+    /// ```rust
+    /// # use tikv_client::{CircuitBreaker, Config};
+    /// let config = Config::default().with_circuit_breaker(CircuitBreaker::default());
+    /// ```
+    #[must_use]
+    pub fn with_circuit_breaker(mut self, circuit_breaker: CircuitBreaker) -> Self {
+        self.circuit_breaker = Some(circuit_breaker);
+        self
+    }
+
+    /// Probe the connections to TiKV stores which are not in use, so that a store which has gone
+    /// down is avoided before a request has to time out to find out.
+    ///
+    /// Stores which fail a probe are avoided as by the
+    /// [circuit breaker](Config::with_circuit_breaker), with the default [`CircuitBreaker`] if none
+    /// is set. See [`HealthProbe`] for the details. By default, connections are not probed.
+    ///
+    /// # Examples
+    /// ```rust
+    /// # use tikv_client::{Config, HealthProbe};
+    /// let config = Config::default().with_health_probe(HealthProbe::default());
+    /// ```
+    #[must_use]
+    pub fn with_health_probe(mut self, health_probe: HealthProbe) -> Self {
+        self.health_probe = Some(health_probe);
+        self
+    }
+
+    /// Allow at most `limit` requests from a client to be in flight to each TiKV store.
+    ///
+    /// Further requests to a store wait until one of its requests completes, so that a burst of
+    /// requests, e.g., from a single caller spawning thousands of tasks, queues in the client
+    /// rather than overwhelming the store and getting `ServerIsBusy` errors. The wait counts
+    /// towards a request's deadline. A limit of zero is taken as one. By default, the requests in
+    /// flight are not limited.
+    ///
+    /// # Examples
+    /// ```rust
+    /// # use tikv_client::Config;
+    /// let config = Config::default().with_max_in_flight_per_store(128);
+    /// ```
+    #[must_use]
+    pub fn with_max_in_flight_per_store(mut self, limit: usize) -> Self {
+        self.max_in_flight_per_store = Some(limit);
+        self
+    }
+
+    /// Coalesce the prewrite and commit requests which concurrent transactions send to each TiKV
+    /// store into batches, waiting a little for a batch to fill.
+    ///
+    /// See [`GroupCommit`] for how batches are formed. Worthwhile when many small transactions
+    /// commit at once. By default, each request is sent on its own.
+    ///
+    /// # Examples
+    /// ```rust
+    /// # use tikv_client::{Config, GroupCommit};
+    /// let config = Config::default().with_group_commit(GroupCommit::default());
+    /// ```
+    #[must_use]
+    pub fn with_group_commit(mut self, group_commit: GroupCommit) -> Self {
+        self.group_commit = Some(group_commit);
+        self
+    }
+
+    /// Cache the values read by a [`TransactionClient`](crate::TransactionClient)'s transactions
+    /// and snapshots, keeping at most `capacity` values.
+    ///
+    /// Values are cached by key and the timestamp they were read at, so only reads at the same
+    /// timestamp, such as those of snapshots sharing a timestamp, hit the cache. The values of keys
+    /// written by the client's transactions are dropped when the transactions commit. Reads which
+    /// lock keys, such as [`get_for_update`](crate::Transaction::get_for_update), are not cached.
+    /// By default, reads are not cached.
+    ///
+    /// # Examples
+    /// ```rust
+    /// # use tikv_client::Config;
+    /// let config = Config::default().with_read_cache(10_000);
+    /// ```
+    #[must_use]
+    pub fn with_read_cache(mut self, capacity: usize) -> Self {
+        self.read_cache_capacity = Some(capacity);
+        self
+    }
+
+    /// Sample the keys accessed by a client, so that the keys its requests contend on most, or
+    /// wait longest for, can be listed with
+    /// [`TransactionClient::hot_keys`](crate::TransactionClient::hot_keys) or
+    /// [`RawClient::hot_keys`](crate::RawClient::hot_keys).
+    ///
+    /// Reads, scans and committed writes are sampled with the latency of the request which
+    /// accessed them, and write conflicts are counted against the conflicting key. The raw
+    /// client's compare-and-swaps are sampled too, and its range deletes as the start key of their
+    /// range. See [`HotKeyTracking`]
+    /// for the details. By default, keys are not tracked.
+    ///
+    /// # Examples
+    /// ```rust
+    /// # use tikv_client::{Config, HotKeyTracking};
+    /// let config = Config::default().with_hot_key_tracking(HotKeyTracking::default());
+    /// ```
+    #[must_use]
+    pub fn with_hot_key_tracking(mut self, tracking: HotKeyTracking) -> Self {
+        self.hot_key_tracking = Some(tracking);
+        self
+    }
+
+    /// Prefetch the start timestamps of a [`TransactionClient`](crate::TransactionClient)'s
+    /// transactions from PD, and begin transactions with timestamps from the pool.
+    ///
+    /// This bounds the latency of beginning transactions in bursts, at the cost of transactions
+    /// possibly not seeing the writes committed shortly before they begin. See
+    /// [`TimestampPrefetch`] for the details. By default, every transaction gets its start
+    /// timestamp from PD when it begins.
+    ///
+    /// # Examples
+    /// ```rust
+    /// # use tikv_client::{Config, TimestampPrefetch};
+    /// let config = Config::default().with_timestamp_prefetch(TimestampPrefetch::default());
+    /// ```
+    #[must_use]
+    pub fn with_timestamp_prefetch(mut self, prefetch: TimestampPrefetch) -> Self {
+        self.timestamp_prefetch = Some(prefetch);
+        self
+    }
+
+    /// Compress the values written by a [`TransactionClient`](crate::TransactionClient)'s
+    /// transactions, and decompress the values they read.
+    ///
+    /// Every value is stored with a header recording whether and how it was compressed, so values
+    /// written with compression can't be read without it, and vice versa. Enable it only for keys
+    /// which every client reads and writes with compression. [`RawClient`](crate::RawClient)s
+    /// don't encode values, so they reject a configuration with compression, a value cipher or
+    /// value checksums. By default, values are stored as they are.
+    ///
+    /// # Examples
+    /// ```rust
+    /// # use tikv_client::{Compression, Config};
+    /// let config = Config::default().with_compression(Compression::default());
+    /// ```
+    #[must_use]
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = Some(compression);
+        self
+    }
+
+    /// Encrypt the values written by a [`TransactionClient`](crate::TransactionClient)'s
+    /// transactions with `cipher`, and decrypt the values they read.
+    ///
+    /// As with [compression](Config::with_compression), values written with a cipher can only be
+    /// read with it. The cipher is not part of the serialized config. By default, values are not
+    /// encrypted.
+    #[must_use]
+    pub fn with_value_cipher(mut self, cipher: impl ValueCipher + 'static) -> Self {
+        self.value_cipher = Some(ValueCipherHandle(Arc::new(cipher)));
+        self
+    }
+
+    /// Append a CRC32 checksum to the values written by a
+    /// [`TransactionClient`](crate::TransactionClient)'s transactions, and verify it when they
+    /// are read.
+    ///
+    /// A value which doesn't match its checksum fails the read with
+    /// [`Error::ChecksumMismatch`](crate::Error::ChecksumMismatch). The checksum covers the value
+    /// as stored, after any compression or encryption. As with
+    /// [compression](Config::with_compression), values written with a checksum can only be read
+    /// with it. By default, values have no checksum.
+    ///
+    /// # Examples
+    /// ```rust
+    /// # use tikv_client::Config;
+    /// let config = Config::default().with_value_checksum();
+    /// ```
+    #[must_use]
+    pub fn with_value_checksum(mut self) -> Self {
+        self.value_checksum = true;
+        self
+    }
+
+    /// Redact or hash the content of keys and values wherever it appears in errors and logs, e.g.,
+    /// because it holds personal data.
+    ///
+    /// This covers the messages of errors, including those relayed from TiKV, which may quote
+    /// keys, and the `Debug` representations of [`Key`](crate::Key)s,
+    /// [`KvPair`](crate::KvPair)s and ranges. By default, content is shown.
+    ///
+    /// Redaction is process-wide, not per client: errors don't know which client they come from.
+    /// Creating a client with redaction sets it for the whole process, as
+    /// [`set_redaction`](crate::set_redaction) does, and it stays set after the client is dropped.
+    /// Clients created without redaction don't turn it off.
+    ///
+    /// # Examples
+    /// ```rust
+    /// # use tikv_client::{Config, Redaction};
+    /// let config = Config::default().with_redaction(Redaction::Hash);
+    /// ```
+    #[must_use]
+    pub fn with_redaction(mut self, redaction: Redaction) -> Self {
+        self.redaction = redaction;
+        self
+    }
+
+    /// Tunnel the connections to PD and TiKV through `proxy`.
+    ///
+    /// Useful when the client runs outside the cluster's network. Connections are made to the
+    /// proxy directly, and TLS, if configured, runs through the tunnel. By default, connections
+    /// are made directly.
+    ///
+    /// # Examples
+    /// ```rust
+    /// # use tikv_client::{Config, Proxy};
+    /// let config = Config::default().with_proxy(Proxy::http_connect("bastion:3128"));
+    /// ```
+    #[must_use]
+    pub fn with_proxy(mut self, proxy: Proxy) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    /// Call `observer` whenever a request is retried, or given up on, by a client created with
+    /// this config.
+    ///
+    /// The observer is told why the request failed, how many times it has failed, how long the
+    /// client waits before retrying, and where the request was sent. It is not part of the
+    /// serialized config. By default, there is no observer.
+    ///
+    /// # Examples
+    /// ```rust
+    /// # use tikv_client::{Config, RetryEvent};
+    /// let config = Config::default().with_retry_observer(|event: RetryEvent| {
+    ///     println!("{:?} retry #{}", event.reason, event.attempt);
+    /// });
+    /// ```
+    #[must_use]
+    pub fn with_retry_observer(mut self, observer: impl RetryObserver + 'static) -> Self {
+        self.retry_observer = Some(RetryObserverHandle(Arc::new(observer)));
+        self
+    }
+
+    /// Tell `sink` about a sample of the keys read and written by clients created with this
+    /// config, with how each was accessed and what came of it.
+    ///
+    /// Each access is told about with probability `sample_rate`, which is clamped to `0.0..=1.0`.
+    /// Every read and write of the raw client is audited, including compare-and-swaps, touches,
+    /// atomic batches and range deletes, as are the reads and committed writes of transactions.
+    /// Scans, including batch scans, report the keys they return, and a range delete reports the
+    /// start key of its range. A write whose RPC failed without an answer, e.g., because it timed
+    /// out, is reported as [`Undetermined`](crate::AuditOutcome::Undetermined), since it may have
+    /// been applied. The sink is not part of the serialized config. By default, there is no sink.
+    ///
+    /// # Examples
+    /// ```rust
+    /// # use tikv_client::{AuditEvent, Config};
+    /// let config = Config::default().with_audit_sink(
+    ///     |event: AuditEvent| println!("{:?} {:?}: {:?}", event.operation, event.key, event.outcome),
+    ///     0.01,
+    /// );
+    /// ```
+    #[must_use]
+    pub fn with_audit_sink(mut self, sink: impl AuditSink + 'static, sample_rate: f64) -> Self {
+        self.audit_log = Some(AuditLog::new(Arc::new(sink), sample_rate));
+        self
+    }
+
+    /// Get the timestamps of transactions from `provider`, rather than from PD's timestamp oracle.
+    ///
+    /// See [`TimestampProvider`] for what a provider must guarantee. The provider is not part of
+    /// the serialized config. By default, timestamps come from PD.
+    ///
+    /// # Examples
+    /// ```rust
+    /// # use tikv_client::{Config, LogicalClock};
+    /// let config = Config::default().with_timestamp_provider(LogicalClock::new(1));
+    /// ```
+    #[must_use]
+    pub fn with_timestamp_provider(mut self, provider: impl TimestampProvider) -> Self {
+        self.timestamp_provider = Some(TimestampProviderHandle(Arc::new(provider)));
+        self
+    }
+
+    /// Run the background tasks of a client created with this config with `spawner`.
+    ///
+    /// Background tasks are transaction heartbeats, the commit of secondary keys, the flushes of
+    /// buffered writers, and the tasks serving the stream of timestamps from PD and the streams of
+    /// batched requests to each store. Pass a [`tokio::runtime::Handle`] to run them on a particular runtime.
+    /// The spawner is not part of the serialized config. By default, tasks are spawned on the
+    /// tokio runtime of the task which starts them.
+    ///
+    /// # Examples
+    /// ```rust
+    /// # use tikv_client::Config;
+    /// let runtime = tokio::runtime::Runtime::new().unwrap();
+    /// let config = Config::default().with_spawner(runtime.handle().clone());
+    /// ```
+    #[must_use]
+    pub fn with_spawner(mut self, spawner: impl Spawner + 'static) -> Self {
+        self.spawner = Some(SpawnerHandle(Arc::new(spawner)));
+        self
+    }
+
+    /// Inject the faults of `injector` into the requests of a client created with this config.
+    ///
+    /// This is for chaos tests of services using the client. The injector is not part of the
+    /// serialized config. Requires the `fault-injection` feature.
+    ///
+    /// # Examples
+    /// ```rust
+    /// # use std::time::Duration;
+    /// # use tikv_client::Config;
+    /// # use tikv_client::fault_injection::{FaultInjector, StoreFaults};
+    /// let injector = FaultInjector::new();
+    /// injector.set_all_stores(StoreFaults::default().jitter(Duration::from_millis(100)));
+    /// let config = Config::default().with_fault_injection(injector);
+    /// ```
+    #[cfg(any(test, feature = "fault-injection"))]
+    #[must_use]
+    pub fn with_fault_injection(mut self, injector: FaultInjector) -> Self {
+        self.fault_injector = Some(injector);
+        self
+    }
 }
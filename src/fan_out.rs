@@ -0,0 +1,138 @@
+// Copyright 2023 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Tracing which regions and stores the requests of an operation were sent to.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::region::RegionId;
+
+/// Records which regions the requests of operations were sent to, how long each region took, and
+/// how often its requests were retried.
+///
+/// Attach a tracer to a transaction with
+/// [`TransactionOptions::trace_fan_out`](crate::TransactionOptions::trace_fan_out), or to a raw
+/// client with [`RawClient::with_fan_out_tracer`](crate::RawClient::with_fan_out_tracer), then
+/// [`take`](FanOutTracer::take) the trace of the operations run since. Clones of a tracer share
+/// its trace.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// # use tikv_client::{FanOutTracer, RawClient};
+/// # use futures::prelude::*;
+/// # futures::executor::block_on(async {
+/// let client = RawClient::new(vec!["192.168.0.100"], None).await.unwrap();
+/// let tracer = FanOutTracer::new();
+/// let traced = client.with_fan_out_tracer(tracer.clone());
+/// traced.scan("a".to_owned().."z".to_owned(), 1000).await.unwrap();
+/// if let Some(region) = tracer.take().slowest() {
+///     println!("region {} at {:?} took {:?}", region.region_id, region.stores, region.max_latency);
+/// }
+/// # });
+/// ```
+#[derive(Clone, Default)]
+pub struct FanOutTracer {
+    regions: Arc<Mutex<BTreeMap<RegionId, RegionTrace>>>,
+}
+
+impl FanOutTracer {
+    pub fn new() -> FanOutTracer {
+        FanOutTracer::default()
+    }
+
+    /// The trace of the requests sent so far.
+    pub fn trace(&self) -> FanOutTrace {
+        FanOutTrace {
+            regions: self.regions.lock().unwrap().values().cloned().collect(),
+        }
+    }
+
+    /// The trace of the requests sent so far, clearing it so that later requests are traced
+    /// afresh.
+    pub fn take(&self) -> FanOutTrace {
+        let regions = std::mem::take(&mut *self.regions.lock().unwrap());
+        FanOutTrace {
+            regions: regions.into_values().collect(),
+        }
+    }
+
+    /// Record a request to `region_id` at the store at `store_address`, which took `latency`.
+    pub(crate) fn on_request(&self, region_id: RegionId, store_address: &str, latency: Duration) {
+        let mut regions = self.regions.lock().unwrap();
+        let region = regions.entry(region_id).or_insert_with(|| RegionTrace {
+            region_id,
+            ..Default::default()
+        });
+        if !region.stores.iter().any(|store| store == store_address) {
+            region.stores.push(store_address.to_owned());
+        }
+        region.requests += 1;
+        region.total_latency += latency;
+        region.max_latency = region.max_latency.max(latency);
+    }
+
+    /// Record a retry of a request to `region_id` after a region error.
+    pub(crate) fn on_retry(&self, region_id: RegionId) {
+        let mut regions = self.regions.lock().unwrap();
+        let region = regions.entry(region_id).or_insert_with(|| RegionTrace {
+            region_id,
+            ..Default::default()
+        });
+        region.retries += 1;
+    }
+}
+
+impl fmt::Debug for FanOutTracer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FanOutTracer").finish_non_exhaustive()
+    }
+}
+
+impl PartialEq for FanOutTracer {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.regions, &other.regions)
+    }
+}
+
+impl Eq for FanOutTracer {}
+
+/// The regions the requests of traced operations were sent to, as recorded by a
+/// [`FanOutTracer`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FanOutTrace {
+    /// The regions, in order of their ids.
+    pub regions: Vec<RegionTrace>,
+}
+
+impl FanOutTrace {
+    /// The region whose slowest request took longest, if any request was sent.
+    pub fn slowest(&self) -> Option<&RegionTrace> {
+        self.regions.iter().max_by_key(|region| region.max_latency)
+    }
+
+    /// The total number of retries after region errors, across regions.
+    pub fn retries(&self) -> u32 {
+        self.regions.iter().map(|region| region.retries).sum()
+    }
+}
+
+/// The requests sent to one region, as recorded by a [`FanOutTracer`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RegionTrace {
+    pub region_id: RegionId,
+    /// The addresses of the stores the requests were sent to, in the order they were first
+    /// contacted. There is more than one if the region's leader moved.
+    pub stores: Vec<String>,
+    /// The number of requests sent to the region, retries included.
+    pub requests: u32,
+    /// The number of times a request to the region was retried after a region error.
+    pub retries: u32,
+    /// The total time the requests took, including resolving the locks they found.
+    pub total_latency: Duration,
+    /// The longest time a request took.
+    pub max_latency: Duration,
+}
@@ -0,0 +1,260 @@
+// Copyright 2023 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Injecting latency and failures into the requests a client sends to TiKV stores, for chaos
+//! tests of the services using the client.
+
+use std::any::Any;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::Arc;
+use std::sync::RwLock;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use rand::Rng;
+use tikv_client_store::KvClient;
+use tikv_client_store::Request;
+
+use crate::clock::Clock;
+use crate::region::RegionId;
+use crate::Error;
+use crate::Result;
+
+/// The faults injected into the requests sent to a store.
+///
+/// Each request is delayed by `latency`, plus a random duration of up to `jitter`, then fails if
+/// its region is one of `failed_regions`, or otherwise with probability `error_rate`. Failed
+/// requests are never sent, and fail as if the store were unavailable.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StoreFaults {
+    pub latency: Duration,
+    pub jitter: Duration,
+    pub error_rate: f64,
+    pub failed_regions: HashSet<RegionId>,
+}
+
+impl StoreFaults {
+    /// Delay each request by `latency`.
+    #[must_use]
+    pub fn latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    /// Delay each request by a further random duration of up to `jitter`.
+    #[must_use]
+    pub fn jitter(mut self, jitter: Duration) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Fail each request with probability `error_rate`, clamped to between 0 and 1.
+    #[must_use]
+    pub fn error_rate(mut self, error_rate: f64) -> Self {
+        self.error_rate = error_rate.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Fail every request to the region `region_id`.
+    #[must_use]
+    pub fn fail_region(mut self, region_id: RegionId) -> Self {
+        self.failed_regions.insert(region_id);
+        self
+    }
+
+    fn delay(&self) -> Duration {
+        if self.jitter.is_zero() {
+            return self.latency;
+        }
+        let jitter = rand::thread_rng().gen_range(Duration::ZERO..=self.jitter);
+        self.latency + jitter
+    }
+
+    fn failure(&self, region_id: RegionId) -> Option<String> {
+        if self.failed_regions.contains(&region_id) {
+            Some(format!("region {region_id} is failing"))
+        } else if self.error_rate > 0.0 && rand::thread_rng().gen_bool(self.error_rate) {
+            Some("random failure".to_owned())
+        } else {
+            None
+        }
+    }
+}
+
+/// Injects [faults](StoreFaults) into the requests a client sends to TiKV stores.
+///
+/// Attach an injector to a client with
+/// [`Config::with_fault_injection`](crate::Config::with_fault_injection), or to the clients of a
+/// simulated cluster with `Simulation::set_fault_injection`, then change the faults of stores at
+/// any time while the clients run. Faults set for a store replace those set for all stores.
+/// Clones of an injector share its faults.
+///
+/// Requires the `fault-injection` feature.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// # use std::time::Duration;
+/// # use tikv_client::{Config, RawClient};
+/// # use tikv_client::fault_injection::{FaultInjector, StoreFaults};
+/// # use futures::prelude::*;
+/// # futures::executor::block_on(async {
+/// let injector = FaultInjector::new();
+/// let config = Config::default().with_fault_injection(injector.clone());
+/// let client = RawClient::new_with_config(vec!["192.168.0.100"], config, None)
+///     .await
+///     .unwrap();
+/// injector.set_all_stores(StoreFaults::default().latency(Duration::from_millis(50)));
+/// injector.set_store(
+///     "192.168.0.101:20160",
+///     StoreFaults::default().error_rate(0.1).fail_region(42),
+/// );
+/// // ... exercise the service using the client ...
+/// injector.clear();
+/// # });
+/// ```
+#[derive(Clone, Default)]
+pub struct FaultInjector {
+    faults: Arc<RwLock<Faults>>,
+}
+
+#[derive(Default)]
+struct Faults {
+    all_stores: StoreFaults,
+    stores: HashMap<String, StoreFaults>,
+}
+
+impl FaultInjector {
+    pub fn new() -> FaultInjector {
+        FaultInjector::default()
+    }
+
+    /// Inject `faults` into the requests to every store without faults of its own.
+    pub fn set_all_stores(&self, faults: StoreFaults) {
+        self.faults.write().unwrap().all_stores = faults;
+    }
+
+    /// Inject `faults` into the requests to the store at `address`.
+    pub fn set_store(&self, address: impl Into<String>, faults: StoreFaults) {
+        let mut all = self.faults.write().unwrap();
+        all.stores.insert(address.into(), faults);
+    }
+
+    /// Stop injecting faults.
+    pub fn clear(&self) {
+        *self.faults.write().unwrap() = Faults::default();
+    }
+
+    /// The faults injected into the requests to the store at `address`.
+    pub fn faults(&self, address: &str) -> StoreFaults {
+        let faults = self.faults.read().unwrap();
+        faults
+            .stores
+            .get(address)
+            .unwrap_or(&faults.all_stores)
+            .clone()
+    }
+
+    /// Wrap `client`, the client of the region `region_id` at the store at `address`, so that
+    /// faults are injected into its requests.
+    pub(crate) fn wrap(
+        &self,
+        client: Arc<dyn KvClient + Send + Sync>,
+        region_id: RegionId,
+        address: &str,
+        clock: Arc<dyn Clock>,
+    ) -> Arc<dyn KvClient + Send + Sync> {
+        Arc::new(FaultyKvClient {
+            inner: client,
+            injector: self.clone(),
+            region_id,
+            address: address.to_owned(),
+            clock,
+        })
+    }
+}
+
+impl fmt::Debug for FaultInjector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FaultInjector").finish_non_exhaustive()
+    }
+}
+
+impl PartialEq for FaultInjector {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.faults, &other.faults)
+    }
+}
+
+impl Eq for FaultInjector {}
+
+/// A client of a store whose requests are subject to the faults of a `FaultInjector`.
+struct FaultyKvClient {
+    inner: Arc<dyn KvClient + Send + Sync>,
+    injector: FaultInjector,
+    region_id: RegionId,
+    address: String,
+    clock: Arc<dyn Clock>,
+}
+
+#[async_trait]
+impl KvClient for FaultyKvClient {
+    async fn dispatch(&self, req: &dyn Request) -> Result<Box<dyn Any>> {
+        let faults = self.injector.faults(&self.address);
+        let delay = faults.delay();
+        if !delay.is_zero() {
+            self.clock.sleep(delay).await;
+        }
+        if let Some(failure) = faults.failure(self.region_id) {
+            return Err(Error::GrpcAPI(tonic::Status::unavailable(format!(
+                "fault injected into {} request to {}: {failure}",
+                req.label(),
+                self.address
+            ))));
+        }
+        self.inner.dispatch(req).await
+    }
+
+    async fn probe(&self, timeout: Duration) -> Result<()> {
+        self.inner.probe(timeout).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::Simulation;
+    use crate::ErrorCode;
+
+    #[tokio::test]
+    async fn test_fault_injection() {
+        let sim = Simulation::new(5);
+        let injector = FaultInjector::new();
+        sim.set_fault_injection(injector.clone());
+        let client = sim.raw_client();
+        client.put(b"a".to_vec(), b"1".to_vec()).await.unwrap();
+        client.put(b"x".to_vec(), b"2".to_vec()).await.unwrap();
+        // The new region covers the keys before the split key.
+        let left = sim.split(b"m".to_vec()).unwrap();
+
+        // Only requests to the failing region fail.
+        let leader = "simulation://store-1";
+        injector.set_store(leader, StoreFaults::default().fail_region(left));
+        assert_eq!(client.get(b"x".to_vec()).await.unwrap(), Some(b"2".to_vec()));
+        let err = client.get(b"a".to_vec()).await.unwrap_err();
+        assert_eq!(err.code(), ErrorCode::Unavailable);
+        assert!(client.scan(.., 10).await.is_err());
+
+        // Store faults replace those of all stores.
+        injector.set_all_stores(StoreFaults::default().error_rate(1.0));
+        assert!(injector.faults(leader).failed_regions.contains(&left));
+        assert_eq!(injector.faults("other").error_rate, 1.0);
+
+        injector.clear();
+        injector.set_all_stores(StoreFaults::default().latency(Duration::from_millis(20)));
+        let start = std::time::Instant::now();
+        assert_eq!(client.get(b"x".to_vec()).await.unwrap(), Some(b"2".to_vec()));
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+}
@@ -0,0 +1,68 @@
+// Copyright 2023 TiKV Project Authors. Licensed under Apache-2.0.
+
+use std::time::Duration;
+
+use serde_derive::Deserialize;
+use serde_derive::Serialize;
+use tikv_client_store::BatchConfig;
+
+/// Coalescing of the prewrite and commit requests of concurrent transactions into batches, like
+/// the group commit of a database's log.
+///
+/// Prewrite and commit requests to a store are sent over a single `BatchCommands` stream. The
+/// first request of a batch waits up to `max_delay` for other transactions' requests to the same
+/// store to join it, and the batch is sent once it holds `max_batch_size` requests, or the delay
+/// is over. This trades a bounded latency for throughput when many small transactions commit at
+/// once. Each request is still processed, and can fail, on its own.
+///
+/// # Examples
+/// ```rust
+/// # use tikv_client::{Config, GroupCommit};
+/// # use std::time::Duration;
+/// let config = Config::default().with_group_commit(
+///     GroupCommit::default()
+///         .max_delay(Duration::from_micros(500))
+///         .max_batch_size(64),
+/// );
+/// ```
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+#[serde(rename_all = "kebab-case")]
+pub struct GroupCommit {
+    pub max_delay: Duration,
+    pub max_batch_size: usize,
+}
+
+impl Default for GroupCommit {
+    fn default() -> Self {
+        GroupCommit {
+            max_delay: Duration::from_millis(1),
+            max_batch_size: 128,
+        }
+    }
+}
+
+impl GroupCommit {
+    /// Set how long the first request of a batch waits for other requests to join it.
+    #[must_use]
+    pub fn max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = max_delay;
+        self
+    }
+
+    /// Set the most requests sent in a batch. A size of zero is taken as one.
+    #[must_use]
+    pub fn max_batch_size(mut self, max_batch_size: usize) -> Self {
+        self.max_batch_size = max_batch_size.max(1);
+        self
+    }
+}
+
+impl From<GroupCommit> for BatchConfig {
+    fn from(group_commit: GroupCommit) -> BatchConfig {
+        BatchConfig {
+            max_delay: group_commit.max_delay,
+            max_batch_size: group_commit.max_batch_size,
+        }
+    }
+}
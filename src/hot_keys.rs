@@ -0,0 +1,320 @@
+// Copyright 2023 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Finding the keys a client's requests contend on, or wait long for.
+
+use std::cmp::Reverse;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use rand::Rng;
+use serde_derive::Deserialize;
+use serde_derive::Serialize;
+
+use crate::Error;
+use crate::Key;
+
+/// How a client samples the keys it accesses, to report the hottest with `hot_keys`.
+///
+/// One in every `sample_interval` accesses of a key is sampled, recording how long the request
+/// accessing it took. Write conflicts are always recorded. At most `capacity` keys are tracked;
+/// once that many are, a new key replaces the coldest one, and is ranked as if it had been
+/// accessed as much as the key it replaced, since it may have been while it was not tracked. Every
+/// `decay_interval` samples, the statistics of every key are halved, so that keys which are no
+/// longer accessed cool down; an interval of zero never halves them.
+///
+/// # Examples
+/// ```rust
+/// # use tikv_client::{Config, HotKeyTracking};
+/// let config = Config::default()
+///     .with_hot_key_tracking(HotKeyTracking::default().sample_interval(100).capacity(256));
+/// ```
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+#[serde(rename_all = "kebab-case")]
+pub struct HotKeyTracking {
+    pub sample_interval: u32,
+    pub capacity: usize,
+    pub decay_interval: u64,
+}
+
+impl Default for HotKeyTracking {
+    fn default() -> Self {
+        HotKeyTracking {
+            sample_interval: 10,
+            capacity: 1024,
+            decay_interval: 100_000,
+        }
+    }
+}
+
+impl HotKeyTracking {
+    /// Sample one in every `sample_interval` accesses. An interval of zero is taken as one.
+    #[must_use]
+    pub fn sample_interval(mut self, sample_interval: u32) -> Self {
+        self.sample_interval = sample_interval.max(1);
+        self
+    }
+
+    /// Set how many keys are tracked at most.
+    #[must_use]
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity.max(1);
+        self
+    }
+
+    /// Halve the statistics of every key once every `decay_interval` samples, or never if it is
+    /// zero.
+    #[must_use]
+    pub fn decay_interval(mut self, decay_interval: u64) -> Self {
+        self.decay_interval = decay_interval;
+        self
+    }
+}
+
+/// The statistics of a key, as sampled by a client with
+/// [hot key tracking](crate::Config::with_hot_key_tracking).
+///
+/// Keys are ranked by their conflicts, then by the total latency of their sampled accesses,
+/// including those estimated for the key it replaced when it started being tracked.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HotKey {
+    pub key: Key,
+    /// The number of sampled accesses of the key.
+    pub sampled_accesses: u64,
+    /// The number of times writing the key conflicted with another transaction.
+    pub conflicts: u64,
+    /// The total time the sampled accesses took.
+    pub total_latency: Duration,
+    /// The longest time a sampled access took.
+    pub max_latency: Duration,
+}
+
+impl HotKey {
+    /// The mean time a sampled access took.
+    pub fn mean_latency(&self) -> Duration {
+        match self.sampled_accesses {
+            0 => Duration::ZERO,
+            n => Duration::from_nanos((self.total_latency.as_nanos() / u128::from(n)) as u64),
+        }
+    }
+}
+
+/// How hot a key is: its conflicts, then the total latency of its sampled accesses.
+type Heat = (u64, Duration);
+
+/// A tracked key.
+struct Entry {
+    stats: HotKey,
+    /// The heat of the key this one replaced, which this one is assumed to have had too.
+    inherited: Heat,
+}
+
+impl Entry {
+    fn heat(&self) -> Heat {
+        (
+            self.stats.conflicts + self.inherited.0,
+            self.stats.total_latency + self.inherited.1,
+        )
+    }
+
+    fn decay(&mut self) {
+        self.stats.sampled_accesses /= 2;
+        self.stats.conflicts /= 2;
+        self.stats.total_latency /= 2;
+        self.inherited = (self.inherited.0 / 2, self.inherited.1 / 2);
+    }
+}
+
+#[derive(Default)]
+struct Tracked {
+    entries: HashMap<Key, Entry>,
+    /// The tracked keys, coldest first. Keys of the same heat are in descending order, so that the
+    /// hottest keys are listed in ascending order of keys.
+    by_heat: BTreeSet<(Heat, Reverse<Key>)>,
+    /// The samples recorded since the statistics were last halved.
+    samples: u64,
+}
+
+/// The statistics of the hottest keys accessed through a client.
+pub(crate) struct HotKeyTracker {
+    config: HotKeyTracking,
+    tracked: Mutex<Tracked>,
+}
+
+impl HotKeyTracker {
+    pub(crate) fn new(config: HotKeyTracking) -> HotKeyTracker {
+        HotKeyTracker {
+            config,
+            tracked: Mutex::new(Tracked::default()),
+        }
+    }
+
+    /// Record the accesses of `keys` by a request which took `latency`, if they are sampled.
+    pub(crate) fn record_accesses<'a>(
+        &self,
+        keys: impl IntoIterator<Item = &'a Key>,
+        latency: Duration,
+    ) {
+        let interval = self.config.sample_interval;
+        let mut rng = rand::thread_rng();
+        for key in keys {
+            if interval > 1 && !rng.gen_ratio(1, interval) {
+                continue;
+            }
+            self.update(key, |hot_key| {
+                hot_key.sampled_accesses += 1;
+                hot_key.total_latency += latency;
+                hot_key.max_latency = hot_key.max_latency.max(latency);
+            });
+        }
+    }
+
+    /// Record the write conflict reported by `error`, if any.
+    pub(crate) fn record_error(&self, error: &Error) {
+        if let Some(conflict) = error.write_conflict() {
+            let key = Key::from(conflict.key);
+            self.update(&key, |hot_key| hot_key.conflicts += 1);
+        }
+    }
+
+    /// The `top_n` hottest keys, hottest first.
+    pub(crate) fn hot_keys(&self, top_n: usize) -> Vec<HotKey> {
+        let tracked = self.tracked.lock().unwrap();
+        tracked
+            .by_heat
+            .iter()
+            .rev()
+            .take(top_n)
+            .map(|(_, Reverse(key))| tracked.entries[key].stats.clone())
+            .collect()
+    }
+
+    fn update(&self, key: &Key, f: impl FnOnce(&mut HotKey)) {
+        let mut tracked = self.tracked.lock().unwrap();
+        let Tracked {
+            entries,
+            by_heat,
+            samples,
+        } = &mut *tracked;
+        let entry = match entries.get_mut(key) {
+            Some(entry) => {
+                by_heat.remove(&(entry.heat(), Reverse(key.clone())));
+                entry
+            }
+            None => {
+                let mut inherited = (0, Duration::ZERO);
+                if entries.len() >= self.config.capacity.max(1) {
+                    if let Some((heat, Reverse(coldest))) = by_heat.pop_first() {
+                        entries.remove(&coldest);
+                        inherited = heat;
+                    }
+                }
+                entries.entry(key.clone()).or_insert(Entry {
+                    stats: HotKey {
+                        key: key.clone(),
+                        ..Default::default()
+                    },
+                    inherited,
+                })
+            }
+        };
+        f(&mut entry.stats);
+        by_heat.insert((entry.heat(), Reverse(key.clone())));
+
+        *samples += 1;
+        if self.config.decay_interval > 0 && *samples >= self.config.decay_interval {
+            *samples = 0;
+            entries.values_mut().for_each(Entry::decay);
+            *by_heat = entries
+                .iter()
+                .map(|(key, entry)| (entry.heat(), Reverse(key.clone())))
+                .collect();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tikv_client_proto::kvrpcpb;
+
+    use super::*;
+    use crate::ConflictKind;
+
+    #[test]
+    fn test_hot_keys() {
+        let tracker = HotKeyTracker::new(HotKeyTracking::default().sample_interval(1).capacity(2));
+        let key = |k: u8| Key::from(vec![k]);
+        let ms = Duration::from_millis;
+        tracker.record_accesses(&[key(1), key(2)], ms(10));
+        tracker.record_accesses(&[key(2)], ms(30));
+        let conflict = Error::KeyError(Box::new(kvrpcpb::KeyError {
+            conflict: Some(kvrpcpb::WriteConflict {
+                key: vec![1],
+                ..Default::default()
+            }),
+            ..Default::default()
+        }))
+        .with_conflict_kind(ConflictKind::Optimistic);
+        tracker.record_error(&conflict);
+
+        // Conflicts rank above latency.
+        let hot_keys = tracker.hot_keys(5);
+        assert_eq!(hot_keys.len(), 2);
+        assert_eq!((hot_keys[0].key.clone(), hot_keys[0].conflicts), (key(1), 1));
+        assert_eq!(hot_keys[1].key, key(2));
+        assert_eq!(hot_keys[1].sampled_accesses, 2);
+        assert_eq!(hot_keys[1].mean_latency(), ms(20));
+        assert_eq!(hot_keys[1].max_latency, ms(30));
+
+        // A new key replaces the coldest.
+        tracker.record_accesses(&[key(3)], ms(50));
+        let keys: Vec<Key> = tracker.hot_keys(1).into_iter().map(|k| k.key).collect();
+        assert_eq!(keys, vec![key(1)]);
+        let keys: Vec<Key> = tracker.hot_keys(5).into_iter().map(|k| k.key).collect();
+        assert_eq!(keys, vec![key(1), key(3)]);
+    }
+
+    #[test]
+    fn test_new_keys_accumulate() {
+        let tracker = HotKeyTracker::new(HotKeyTracking::default().sample_interval(1).capacity(2));
+        let key = |k: u8| Key::from(vec![k]);
+        let ms = Duration::from_millis;
+        tracker.record_accesses(&[key(1)], ms(100));
+        tracker.record_accesses(&[key(2)], ms(50));
+
+        // Keys 3 and 4 keep replacing each other, but each inherits the heat of the other, so
+        // that together they are found to be hotter than key 1.
+        for _ in 0..10 {
+            tracker.record_accesses(&[key(3)], ms(5));
+            tracker.record_accesses(&[key(4)], ms(5));
+        }
+        let hot_keys = tracker.hot_keys(5);
+        let keys: Vec<Key> = hot_keys.iter().map(|k| k.key.clone()).collect();
+        assert_eq!(keys, vec![key(4), key(3)]);
+        // Only the accesses since a key was last tracked are reported.
+        assert_eq!(hot_keys[0].sampled_accesses, 5);
+        assert_eq!(hot_keys[0].total_latency, ms(25));
+    }
+
+    #[test]
+    fn test_decay() {
+        let tracker = HotKeyTracker::new(
+            HotKeyTracking::default()
+                .sample_interval(1)
+                .decay_interval(4),
+        );
+        let key = |k: u8| Key::from(vec![k]);
+        let ms = Duration::from_millis;
+        tracker.record_accesses(&[key(1), key(1), key(1)], ms(10));
+        tracker.record_accesses(&[key(2)], ms(10));
+        let hot_keys = tracker.hot_keys(2);
+        assert_eq!(hot_keys[0].key, key(1));
+        assert_eq!(hot_keys[0].sampled_accesses, 1);
+        assert_eq!(hot_keys[0].total_latency, ms(15));
+        assert_eq!(hot_keys[0].max_latency, ms(10));
+        assert_eq!(hot_keys[1].sampled_accesses, 0);
+        assert_eq!(hot_keys[1].total_latency, ms(5));
+    }
+}
@@ -0,0 +1,220 @@
+// Copyright 2023 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Managing keyspaces, the unit of multi-tenancy in a TiKV cluster.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+#[cfg(feature = "keyspace-create")]
+use tikv_client_common::internal_err;
+use tikv_client_proto::keyspacepb;
+
+use crate::pd::PdRpcClient;
+#[cfg(feature = "keyspace-create")]
+use crate::Error;
+use crate::Result;
+#[cfg(feature = "keyspace-create")]
+use crate::SecurityManager;
+
+const LIST_BATCH_SIZE: u32 = 128;
+
+/// The state of a keyspace.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyspaceState {
+    Enabled,
+    Disabled,
+    Archived,
+    Tombstone,
+}
+
+/// A keyspace's metadata, as stored by PD.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Keyspace {
+    pub id: u32,
+    pub name: String,
+    pub state: KeyspaceState,
+    /// When the keyspace was created, in seconds since the Unix epoch.
+    pub created_at: i64,
+    /// When the keyspace's state last changed, in seconds since the Unix epoch.
+    pub state_changed_at: i64,
+    pub config: HashMap<String, String>,
+}
+
+impl From<keyspacepb::KeyspaceMeta> for Keyspace {
+    fn from(meta: keyspacepb::KeyspaceMeta) -> Keyspace {
+        let state = match meta.state() {
+            keyspacepb::KeyspaceState::Enabled => KeyspaceState::Enabled,
+            keyspacepb::KeyspaceState::Disabled => KeyspaceState::Disabled,
+            keyspacepb::KeyspaceState::Archived => KeyspaceState::Archived,
+            keyspacepb::KeyspaceState::Tombstone => KeyspaceState::Tombstone,
+        };
+        Keyspace {
+            id: meta.id,
+            name: meta.name,
+            state,
+            created_at: meta.created_at,
+            state_changed_at: meta.state_changed_at,
+            config: meta.config,
+        }
+    }
+}
+
+/// Creates, lists, and describes the keyspaces of a cluster.
+///
+/// Get one from [`TransactionClient::keyspaces`](crate::TransactionClient::keyspaces) or
+/// [`RawClient::keyspaces`](crate::RawClient::keyspaces).
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// # use tikv_client::{Config, TransactionClient};
+/// # use futures::prelude::*;
+/// # futures::executor::block_on(async {
+/// let client = TransactionClient::new(vec!["192.168.0.100"], None).await.unwrap();
+/// let keyspaces = client.keyspaces();
+/// let tenant = keyspaces.get("tenant-42").await.unwrap();
+/// assert!(keyspaces.list().await.unwrap().contains(&tenant));
+/// # });
+/// ```
+#[derive(Clone)]
+pub struct Keyspaces {
+    pd: Arc<PdRpcClient>,
+}
+
+impl Keyspaces {
+    pub(crate) fn new(pd: Arc<PdRpcClient>) -> Keyspaces {
+        Keyspaces { pd }
+    }
+
+    /// Describe the keyspace called `name`.
+    pub async fn get(&self, name: &str) -> Result<Keyspace> {
+        Ok(self.pd.load_keyspace(name.to_owned()).await?.into())
+    }
+
+    /// List all keyspaces, in order of id.
+    pub async fn list(&self) -> Result<Vec<Keyspace>> {
+        let mut keyspaces = Vec::new();
+        let mut start_id = 0;
+        loop {
+            let batch = self
+                .pd
+                .get_all_keyspaces(start_id, LIST_BATCH_SIZE)
+                .await?;
+            let done = batch.len() < LIST_BATCH_SIZE as usize;
+            if let Some(last) = batch.last() {
+                start_id = last.id + 1;
+            }
+            keyspaces.extend(batch.into_iter().map(Keyspace::from));
+            if done {
+                return Ok(keyspaces);
+            }
+        }
+    }
+
+    /// Create a keyspace called `name`, with the given configuration.
+    ///
+    /// PD only supports creating keyspaces through its HTTP API, which this connects to at the PD
+    /// leader's client URL. If the client is configured with TLS, the request is sent over HTTPS
+    /// with the same certificates.
+    ///
+    /// Requires the `keyspace-create` feature.
+    #[cfg(feature = "keyspace-create")]
+    pub async fn create(&self, name: &str, config: HashMap<String, String>) -> Result<Keyspace> {
+        let leader_url = self
+            .pd
+            .pd_leader_url()
+            .await
+            .ok_or_else(|| internal_err!("PD leader has no client URL"))?;
+        let security_mgr = self.pd.security_mgr();
+        let url = keyspaces_url(&leader_url, security_mgr.is_tls());
+        let body = serde_json::json!({ "name": name, "config": config });
+        let response = http_client(security_mgr)?
+            .post(&url)
+            .json(&body)
+            .send()
+            .await
+            .map_err(|e| internal_err!("failed to create keyspace {}: {}", name, e))?;
+        let status = response.status();
+        if !status.is_success() {
+            let message = response.text().await.unwrap_or_default();
+            return Err(Error::InternalError {
+                message: format!("failed to create keyspace {name}: {status} {message}"),
+            });
+        }
+        self.get(name).await
+    }
+}
+
+/// The URL of PD's keyspace API, given the client URL of the PD leader.
+#[cfg(feature = "keyspace-create")]
+fn keyspaces_url(leader_url: &str, tls: bool) -> String {
+    let leader_url = leader_url.trim_end_matches('/');
+    let leader_url = match leader_url.strip_prefix("http://") {
+        Some(authority) if tls => format!("https://{authority}"),
+        _ => leader_url.to_owned(),
+    };
+    format!("{leader_url}/pd/api/v2/keyspaces")
+}
+
+/// An HTTP client which trusts, and identifies itself with, the client's TLS certificates.
+#[cfg(feature = "keyspace-create")]
+fn http_client(security_mgr: &SecurityManager) -> Result<reqwest::Client> {
+    let mut builder = reqwest::Client::builder();
+    if security_mgr.is_tls() {
+        let ca = reqwest::Certificate::from_pem(security_mgr.ca_pem())
+            .map_err(|e| internal_err!("failed to load the CA certificate: {}", e))?;
+        let identity = reqwest::Identity::from_pem(&security_mgr.identity_pem()?)
+            .map_err(|e| internal_err!("failed to load the client certificate: {}", e))?;
+        builder = builder
+            .use_rustls_tls()
+            .tls_built_in_root_certs(false)
+            .add_root_certificate(ca)
+            .identity(identity);
+    }
+    builder
+        .build()
+        .map_err(|e| internal_err!("failed to build the HTTP client: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keyspace_from_meta() {
+        let meta = keyspacepb::KeyspaceMeta {
+            id: 7,
+            name: "tenant".to_owned(),
+            state: keyspacepb::KeyspaceState::Archived.into(),
+            created_at: 100,
+            state_changed_at: 200,
+            config: HashMap::from([("gc_life_time".to_owned(), "600".to_owned())]),
+        };
+        let keyspace = Keyspace::from(meta);
+        assert_eq!(keyspace.id, 7);
+        assert_eq!(keyspace.name, "tenant");
+        assert_eq!(keyspace.state, KeyspaceState::Archived);
+        assert_eq!(keyspace.created_at, 100);
+        assert_eq!(keyspace.state_changed_at, 200);
+        assert_eq!(keyspace.config["gc_life_time"], "600");
+    }
+
+    #[cfg(feature = "keyspace-create")]
+    #[test]
+    fn test_keyspaces_url() {
+        let cases = [
+            ("http://pd-0:2379", false, "http://pd-0:2379/pd/api/v2/keyspaces"),
+            ("http://pd-0:2379/", true, "https://pd-0:2379/pd/api/v2/keyspaces"),
+            ("https://pd-0:2379", true, "https://pd-0:2379/pd/api/v2/keyspaces"),
+        ];
+        for (leader_url, tls, url) in cases {
+            assert_eq!(keyspaces_url(leader_url, tls), url, "{leader_url}");
+        }
+    }
+
+    #[cfg(feature = "keyspace-create")]
+    #[test]
+    fn test_http_client_without_tls() {
+        assert!(http_client(&SecurityManager::default()).is_ok());
+    }
+}
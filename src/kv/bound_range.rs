@@ -34,7 +34,8 @@ use super::Key;
 /// **But, you should not need to worry about all this:** Most functions which operate
 /// on ranges will accept any types which implement `Into<BoundRange>`.
 /// Common range types like `a..b`, `a..=b` has implemented `Into<BoundRange>`where `a` and `b`
-/// `impl Into<Key>`. You can implement `Into<BoundRange>` for your own types by using `try_from`.
+/// `impl Into<Key>`, including borrowed `&str` and `&[u8]`. You can implement `Into<BoundRange>`
+/// for your own types by using `try_from`.
 /// It means all of the following types in the example can be passed directly to those functions.
 ///
 /// # Examples
@@ -57,6 +58,9 @@ use super::Key;
 ///     (Bound::Included(Key::from("Rust".to_owned())), Bound::Included(Key::from("TiKV".to_owned()))),
 /// );
 ///
+/// let range: Range<&str> = "Rust".."TiKV";
+/// assert_eq!(from_explict_range, BoundRange::from(range));
+///
 /// let range_from: RangeFrom<String> = "Rust".to_owned()..;
 /// let from_range_from: BoundRange = range_from.into();
 /// assert_eq!(
@@ -87,6 +91,188 @@ impl BoundRange {
         }
     }
 
+    /// Create a new BoundRange of all keys which start with `prefix`.
+    ///
+    /// The range ends at the smallest key greater than every key with the prefix: the prefix with
+    /// any trailing `0xff` bytes removed and its last byte incremented. A prefix of only `0xff`
+    /// bytes gives a range unbounded above.
+    ///
+    /// # Examples
+    /// ```rust
+    /// # use tikv_client::{BoundRange, Key};
+    /// assert_eq!(
+    ///     BoundRange::prefix(b"user/".to_vec()).into_keys(),
+    ///     (Key::from(b"user/".to_vec()), Some(Key::from(b"user0".to_vec()))),
+    /// );
+    /// assert_eq!(
+    ///     BoundRange::prefix(vec![1, 0xff, 0xff]).into_keys(),
+    ///     (Key::from(vec![1, 0xff, 0xff]), Some(Key::from(vec![2]))),
+    /// );
+    /// assert_eq!(
+    ///     BoundRange::prefix(vec![0xff]).into_keys(),
+    ///     (Key::from(vec![0xff]), None),
+    /// );
+    /// ```
+    pub fn prefix(prefix: impl Into<Key>) -> BoundRange {
+        let prefix = prefix.into();
+        let mut end: Vec<u8> = prefix.clone().into();
+        while end.last() == Some(&0xff) {
+            end.pop();
+        }
+        let to = match end.last_mut() {
+            Some(last) => {
+                *last += 1;
+                Bound::Excluded(end.into())
+            }
+            None => Bound::Unbounded,
+        };
+        BoundRange::new(Bound::Included(prefix), to)
+    }
+
+    /// Return whether the range contains no keys.
+    ///
+    /// # Examples
+    /// ```rust
+    /// # use tikv_client::BoundRange;
+    /// assert!(BoundRange::from("b".."a").is_empty());
+    /// assert!(BoundRange::from("a".."a").is_empty());
+    /// assert!(!BoundRange::from("a"..="a").is_empty());
+    /// assert!(!BoundRange::from("a".."").is_empty());
+    /// ```
+    pub fn is_empty(&self) -> bool {
+        match self.end_key() {
+            Some(end) => end <= self.start_key(),
+            None => false,
+        }
+    }
+
+    /// Return whether `key` is in the range.
+    ///
+    /// # Examples
+    /// ```rust
+    /// # use tikv_client::{BoundRange, Key};
+    /// let range = BoundRange::from("a".."c");
+    /// assert!(range.contains_key(&Key::from("b")));
+    /// assert!(!range.contains_key(&Key::from("c")));
+    /// ```
+    pub fn contains_key(&self, key: &Key) -> bool {
+        self.contains(key)
+    }
+
+    /// Return the keys in both `self` and `other`, or `None` if there are none.
+    ///
+    /// # Examples
+    /// ```rust
+    /// # use tikv_client::BoundRange;
+    /// let range = BoundRange::from("a".."c");
+    /// assert_eq!(
+    ///     range.intersect(&BoundRange::from("b"..)),
+    ///     Some(BoundRange::from("b".."c")),
+    /// );
+    /// assert_eq!(range.intersect(&BoundRange::from("c"..)), None);
+    /// ```
+    pub fn intersect(&self, other: &BoundRange) -> Option<BoundRange> {
+        let from = if self.start_key() >= other.start_key() {
+            &self.from
+        } else {
+            &other.from
+        };
+        let to = match (self.end_key(), other.end_key()) {
+            (Some(end), Some(other_end)) if end > other_end => &other.to,
+            (Some(_), _) => &self.to,
+            (None, _) => &other.to,
+        };
+        let range = BoundRange::new(from.clone(), to.clone());
+        if range.is_empty() { None } else { Some(range) }
+    }
+
+    /// Return the keys in either `self` or `other`, if they overlap or are adjacent, so that the
+    /// keys form a single range, or `None` if there is a gap between them.
+    ///
+    /// # Examples
+    /// ```rust
+    /// # use tikv_client::BoundRange;
+    /// let range = BoundRange::from("a".."c");
+    /// assert_eq!(
+    ///     range.union_adjacent(&BoundRange::from("c"..="d")),
+    ///     Some(BoundRange::from("a"..="d")),
+    /// );
+    /// assert_eq!(range.union_adjacent(&BoundRange::from("d"..)), None);
+    /// ```
+    pub fn union_adjacent(&self, other: &BoundRange) -> Option<BoundRange> {
+        if other.is_empty() {
+            return Some(self.clone());
+        }
+        if self.is_empty() {
+            return Some(other.clone());
+        }
+        let (lower, upper) = if self.start_key() <= other.start_key() {
+            (self, other)
+        } else {
+            (other, self)
+        };
+        let to = match (lower.end_key(), upper.end_key()) {
+            (Some(end), _) if end < upper.start_key() => return None,
+            (Some(end), Some(upper_end)) if end >= upper_end => &lower.to,
+            (Some(_), _) => &upper.to,
+            (None, _) => &lower.to,
+        };
+        Some(BoundRange::new(lower.from.clone(), to.clone()))
+    }
+
+    /// Split the range into the keys before `key` and the keys from `key` on, either of which is
+    /// `None` if it has no keys.
+    ///
+    /// # Examples
+    /// ```rust
+    /// # use tikv_client::{BoundRange, Key};
+    /// let range = BoundRange::from("a".."c");
+    /// assert_eq!(
+    ///     range.split_at(Key::from("b")),
+    ///     (Some(BoundRange::from("a".."b")), Some(BoundRange::from("b".."c"))),
+    /// );
+    /// assert_eq!(range.split_at(Key::from("a")), (None, Some(range.clone())));
+    /// ```
+    pub fn split_at(&self, key: impl Into<Key>) -> (Option<BoundRange>, Option<BoundRange>) {
+        let key = key.into();
+        // No key is before the empty key, though an empty end key would mean unbounded.
+        let before = if key.is_empty() {
+            None
+        } else {
+            self.intersect(&BoundRange::new(
+                Bound::Unbounded,
+                Bound::Excluded(key.clone()),
+            ))
+        };
+        (before, self.intersect(&BoundRange::range_from(key)))
+    }
+
+    /// The smallest key in the range, were it not empty.
+    fn start_key(&self) -> Key {
+        match &self.from {
+            Bound::Included(key) => key.clone(),
+            Bound::Excluded(key) => {
+                let mut key = key.clone();
+                key.push_zero();
+                key
+            }
+            Bound::Unbounded => Key::EMPTY,
+        }
+    }
+
+    /// The smallest key after the range, or `None` if the range is unbounded above.
+    fn end_key(&self) -> Option<Key> {
+        match self.end_bound() {
+            Bound::Included(key) => {
+                let mut key = key.clone();
+                key.push_zero();
+                Some(key)
+            }
+            Bound::Excluded(key) => Some(key.clone()),
+            Bound::Unbounded => None,
+        }
+    }
+
     /// Ranges used in scanning TiKV have a particularity to them.
     ///
     /// The **start** of a scan is inclusive, unless appended with an '\0', then it is exclusive.
@@ -379,3 +565,75 @@ fn convert_to_bound_key<K: Into<Key>>(b: Bound<K>) -> Bound<Key> {
         Bound::Unbounded => Bound::Unbounded,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    // Bounds are drawn from few, short keys, so that ranges often overlap or touch.
+    fn bound() -> impl Strategy<Value = Bound<Key>> {
+        let key = proptest::collection::vec(0..3u8, 0..3).prop_map(Key::from);
+        prop_oneof![
+            key.clone().prop_map(Bound::Included),
+            key.prop_map(Bound::Excluded),
+            Just(Bound::Unbounded),
+        ]
+    }
+
+    fn range() -> impl Strategy<Value = BoundRange> {
+        (bound(), bound()).prop_map(|(from, to)| BoundRange::new(from, to))
+    }
+
+    fn keys() -> Vec<Key> {
+        let mut keys = vec![Key::EMPTY];
+        for a in 0..4 {
+            keys.push(vec![a].into());
+            for b in 0..4 {
+                keys.push(vec![a, b].into());
+                for c in 0..4 {
+                    keys.push(vec![a, b, c].into());
+                }
+            }
+        }
+        keys
+    }
+
+    fn contains(range: &Option<BoundRange>, key: &Key) -> bool {
+        range.as_ref().is_some_and(|range| range.contains_key(key))
+    }
+
+    proptest! {
+        #[test]
+        fn test_range_algebra(a in range(), b in range(), split in bound()) {
+            prop_assert_eq!(a.is_empty(), !keys().iter().any(|key| a.contains_key(key)));
+
+            let intersection = a.intersect(&b);
+            prop_assert!(intersection.as_ref().is_none_or(|range| !range.is_empty()));
+            for key in keys() {
+                let expected = a.contains_key(&key) && b.contains_key(&key);
+                prop_assert_eq!(contains(&intersection, &key), expected);
+            }
+
+            match a.union_adjacent(&b) {
+                Some(union) => {
+                    for key in keys() {
+                        let expected = a.contains_key(&key) || b.contains_key(&key);
+                        prop_assert_eq!(union.contains_key(&key), expected);
+                    }
+                }
+                // There is a key between them, which neither contains.
+                None => prop_assert!(a.intersect(&b).is_none()),
+            }
+
+            if let Bound::Included(split) = split {
+                let (before, after) = a.split_at(split.clone());
+                for key in keys() {
+                    prop_assert_eq!(contains(&before, &key), a.contains_key(&key) && key < split);
+                    prop_assert_eq!(contains(&after, &key), a.contains_key(&key) && key >= split);
+                }
+            }
+        }
+    }
+}
@@ -17,6 +17,8 @@ use tikv_client_proto::kvrpcpb;
 use super::HexRepr;
 use crate::kv::codec::BytesEncoder;
 use crate::kv::codec::{self};
+use crate::Error;
+use crate::Result;
 
 const _PROPTEST_KEY_MAX: usize = 1024 * 2; // 2 KB
 
@@ -46,6 +48,9 @@ const _PROPTEST_KEY_MAX: usize = 1024 * 2; // 2 KB
 /// let bytes = static_str.as_bytes().to_vec();
 /// let from_bytes = Key::from(bytes);
 /// assert_eq!(from_static_str, from_bytes);
+///
+/// let borrowed = Key::from(static_str);
+/// assert_eq!(from_static_str, borrowed);
 /// ```
 ///
 /// While `.into()` is usually sufficient for obtaining the buffer itself, sometimes type inference
@@ -100,7 +105,7 @@ impl Key {
     ///
     /// Extending a zero makes the new key the smallest key that is greater than than the original one, i.e. the succeeder.
     #[inline]
-    pub(super) fn push_zero(&mut self) {
+    pub(crate) fn push_zero(&mut self) {
         self.0.push(0)
     }
 
@@ -139,6 +144,172 @@ impl Key {
     pub fn len(&self) -> usize {
         self.0.len()
     }
+
+    /// Return the key in uppercase hexadecimal, as printed by `tikv-ctl --to-hex`.
+    ///
+    /// # Examples
+    /// ```rust
+    /// # use tikv_client::Key;
+    /// assert_eq!(Key::from(vec![b't', 0x80, 0]).to_hex(), "748000");
+    /// ```
+    pub fn to_hex(&self) -> String {
+        self.0.iter().map(|byte| format!("{byte:02X}")).collect()
+    }
+
+    /// Parse a key from hexadecimal, in either case and optionally prefixed with `0x`, as accepted
+    /// by `tikv-ctl`.
+    ///
+    /// # Examples
+    /// ```rust
+    /// # use tikv_client::Key;
+    /// let key = Key::from(vec![b't', 0x80, 0]);
+    /// assert_eq!(Key::from_hex("748000").unwrap(), key);
+    /// assert_eq!(Key::from_hex("0x748000").unwrap(), key);
+    /// assert!(Key::from_hex("7480x").is_err());
+    /// ```
+    pub fn from_hex(hex: &str) -> Result<Key> {
+        let hex = hex
+            .strip_prefix("0x")
+            .or_else(|| hex.strip_prefix("0X"))
+            .unwrap_or(hex);
+        let mut key = Vec::with_capacity(hex.len() / 2);
+        for digits in hex.as_bytes().chunks(2) {
+            match digits {
+                [high, low] => key.push(hex_digit(*high)? << 4 | hex_digit(*low)?),
+                _ => return Err(invalid_encoding("odd number of hex digits")),
+            }
+        }
+        Ok(Key(key))
+    }
+
+    /// The key of `n` as 8 big-endian bytes, so that keys of integers are ordered as the integers
+    /// are, and ranges of integers can be scanned. See [`with_u64`](Key::with_u64) to append an
+    /// integer to a key.
+    ///
+    /// # Examples
+    /// ```rust
+    /// # use tikv_client::Key;
+    /// assert!(Key::from_u64(255) < Key::from_u64(256));
+    /// assert_eq!(Key::from_u64(256).u64_at(0).unwrap(), 256);
+    /// ```
+    pub fn from_u64(n: u64) -> Key {
+        Key::EMPTY.with_u64(n)
+    }
+
+    /// The key of `n` as 8 big-endian bytes with the sign bit flipped, so that keys of integers
+    /// are ordered as the integers are, negative ones first.
+    ///
+    /// # Examples
+    /// ```rust
+    /// # use tikv_client::Key;
+    /// assert!(Key::from_i64(-1) < Key::from_i64(0));
+    /// assert_eq!(Key::from_i64(-1).i64_at(0).unwrap(), -1);
+    /// ```
+    pub fn from_i64(n: i64) -> Key {
+        Key::EMPTY.with_i64(n)
+    }
+
+    /// Append `n` to the key, encoded as by [`from_u64`](Key::from_u64).
+    ///
+    /// # Examples
+    /// ```rust
+    /// # use tikv_client::{BoundRange, Key};
+    /// let key = Key::from("orders/".to_owned()).with_u64(42);
+    /// assert_eq!(key.u64_at(7).unwrap(), 42);
+    /// // The orders with ids 10 to 19.
+    /// let prefix = Key::from("orders/".to_owned());
+    /// let range = BoundRange::from(prefix.clone().with_u64(10)..prefix.with_u64(20));
+    /// ```
+    #[must_use]
+    pub fn with_u64(mut self, n: u64) -> Key {
+        self.0.extend_from_slice(&n.to_be_bytes());
+        self
+    }
+
+    /// Append `n` to the key, encoded as by [`from_i64`](Key::from_i64).
+    #[must_use]
+    pub fn with_i64(self, n: i64) -> Key {
+        self.with_u64(n as u64 ^ SIGN_BIT)
+    }
+
+    /// Decode the integer encoded as by [`with_u64`](Key::with_u64) in the 8 bytes of the key
+    /// starting at `offset`.
+    pub fn u64_at(&self, offset: usize) -> Result<u64> {
+        self.0
+            .get(offset..)
+            .and_then(|bytes| bytes.get(..8))
+            .map(|bytes| u64::from_be_bytes(bytes.try_into().unwrap()))
+            .ok_or_else(|| invalid_encoding("key too short for an integer at the offset"))
+    }
+
+    /// Decode the integer encoded as by [`with_i64`](Key::with_i64) in the 8 bytes of the key
+    /// starting at `offset`.
+    pub fn i64_at(&self, offset: usize) -> Result<i64> {
+        Ok((self.u64_at(offset)? ^ SIGN_BIT) as i64)
+    }
+
+    /// Show the key escaped as by `tikv-ctl --to-escaped`: printable ASCII as is, except that
+    /// quotes and backslashes are escaped, and other bytes as `\n`, `\r`, `\t`, or three octal
+    /// digits.
+    ///
+    /// Unlike the key's `Debug` representation, the escaped key is never
+    /// [redacted](crate::set_redaction).
+    ///
+    /// # Examples
+    /// ```rust
+    /// # use tikv_client::Key;
+    /// let key = Key::from(vec![b't', 0x80, 0, b'"']);
+    /// assert_eq!(key.escaped().to_string(), r#"t\200\000\""#);
+    /// ```
+    pub fn escaped(&self) -> EscapedKey<'_> {
+        EscapedKey(&self.0)
+    }
+
+    /// Parse a key escaped as by [`escaped`](Key::escaped) or `tikv-ctl`. Besides the escapes it
+    /// produces, two hexadecimal digits after `\x` are accepted.
+    ///
+    /// # Examples
+    /// ```rust
+    /// # use tikv_client::Key;
+    /// let key = Key::from(vec![b't', 0x80, 0, b'"']);
+    /// assert_eq!(Key::from_escaped(r#"t\200\000\""#).unwrap(), key);
+    /// assert_eq!(Key::from_escaped(r#"t\x80\x00""#).unwrap(), key);
+    /// assert!(Key::from_escaped(r"t\").is_err());
+    /// ```
+    pub fn from_escaped(escaped: &str) -> Result<Key> {
+        let mut bytes = escaped.bytes();
+        let mut key = Vec::with_capacity(escaped.len());
+        while let Some(byte) = bytes.next() {
+            if byte != b'\\' {
+                key.push(byte);
+                continue;
+            }
+            let mut next = || {
+                bytes
+                    .next()
+                    .ok_or_else(|| invalid_encoding("incomplete escape"))
+            };
+            let byte = match next()? {
+                b'n' => b'\n',
+                b'r' => b'\r',
+                b't' => b'\t',
+                byte @ (b'"' | b'\'' | b'\\') => byte,
+                b'x' => hex_digit(next()?)? << 4 | hex_digit(next()?)?,
+                first => {
+                    let mut byte: u16 = 0;
+                    for digit in [first, next()?, next()?] {
+                        if !(b'0'..=b'7').contains(&digit) {
+                            return Err(invalid_encoding("invalid escape"));
+                        }
+                        byte = byte << 3 | (digit - b'0') as u16;
+                    }
+                    u8::try_from(byte).map_err(|_| invalid_encoding("octal escape too large"))?
+                }
+            };
+            key.push(byte);
+        }
+        Ok(Key(key))
+    }
 }
 
 impl From<Vec<u8>> for Key {
@@ -153,6 +324,18 @@ impl From<String> for Key {
     }
 }
 
+impl From<&[u8]> for Key {
+    fn from(v: &[u8]) -> Key {
+        Key(v.to_vec())
+    }
+}
+
+impl From<&str> for Key {
+    fn from(v: &str) -> Key {
+        Key(v.as_bytes().to_vec())
+    }
+}
+
 impl From<Key> for Vec<u8> {
     fn from(key: Key) -> Self {
         key.0
@@ -187,3 +370,90 @@ impl fmt::Debug for Key {
         write!(f, "Key({})", HexRepr(&self.0))
     }
 }
+
+/// A key escaped as by `tikv-ctl`. See [`Key::escaped`].
+#[derive(Clone, Copy)]
+pub struct EscapedKey<'a>(&'a [u8]);
+
+impl fmt::Display for EscapedKey<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for &byte in self.0 {
+            match byte {
+                b'\n' => f.write_str("\\n")?,
+                b'\r' => f.write_str("\\r")?,
+                b'\t' => f.write_str("\\t")?,
+                b'"' | b'\'' | b'\\' => write!(f, "\\{}", byte as char)?,
+                0x20..=0x7e => write!(f, "{}", byte as char)?,
+                _ => write!(f, "\\{:03o}", byte)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for EscapedKey<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "\"{self}\"")
+    }
+}
+
+/// The bit flipped in encoded `i64`s, so that negative integers are ordered before positive ones.
+const SIGN_BIT: u64 = 1 << 63;
+
+fn hex_digit(digit: u8) -> Result<u8> {
+    match digit {
+        b'0'..=b'9' => Ok(digit - b'0'),
+        b'a'..=b'f' => Ok(digit - b'a' + 10),
+        b'A'..=b'F' => Ok(digit - b'A' + 10),
+        _ => Err(invalid_encoding("invalid hex digit")),
+    }
+}
+
+fn invalid_encoding(message: &str) -> Error {
+    Error::InvalidKeyEncoding {
+        message: message.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    proptest! {
+        #[test]
+        fn test_key_encodings(key: Key) {
+            prop_assert_eq!(Key::from_hex(&key.to_hex()).unwrap(), key.clone());
+            prop_assert_eq!(Key::from_hex(&key.to_hex().to_lowercase()).unwrap(), key.clone());
+            prop_assert_eq!(Key::from_escaped(&key.escaped().to_string()).unwrap(), key);
+        }
+
+        #[test]
+        fn test_integer_keys(a: u64, b: u64, c: i64, d: i64) {
+            prop_assert_eq!(Key::from_u64(a).u64_at(0).unwrap(), a);
+            prop_assert_eq!(Key::from_i64(c).i64_at(0).unwrap(), c);
+            prop_assert_eq!(Key::from_u64(a).cmp(&Key::from_u64(b)), a.cmp(&b));
+            prop_assert_eq!(Key::from_i64(c).cmp(&Key::from_i64(d)), c.cmp(&d));
+            let key = Key::from(b"k".to_vec()).with_i64(c).with_u64(a);
+            prop_assert_eq!(key.i64_at(1).unwrap(), c);
+            prop_assert_eq!(key.u64_at(9).unwrap(), a);
+        }
+    }
+
+    #[test]
+    fn test_invalid_encodings() {
+        assert!(Key::from_hex("abc").is_err());
+        assert!(Key::from_hex("zz").is_err());
+        assert!(Key::from_escaped(r"\8").is_err());
+        assert!(Key::from_escaped(r"\400").is_err());
+        assert!(Key::from_escaped(r"\x1").is_err());
+        assert!(Key::from_u64(1).u64_at(1).is_err());
+        assert!(Key::from_u64(1).i64_at(9).is_err());
+        assert_eq!(
+            Key::from_escaped("a\\tb\\\\").unwrap(),
+            Key::from(b"a\tb\\".to_vec())
+        );
+        assert_eq!(Key::from(b"it's".to_vec()).escaped().to_string(), r"it\'s");
+    }
+}
@@ -1,10 +1,15 @@
 // Copyright 2019 TiKV Project Authors. Licensed under Apache-2.0.
 
+use std::collections::BTreeMap;
+use std::collections::HashMap;
 use std::fmt;
+use std::hash::BuildHasher;
 use std::str;
 
 #[cfg(test)]
 use proptest_derive::Arbitrary;
+use tikv_client_common::redaction;
+use tikv_client_common::Redaction;
 use tikv_client_proto::kvrpcpb;
 
 use super::HexRepr;
@@ -48,6 +53,22 @@ impl KvPair {
         &self.1
     }
 
+    /// Borrow the `Key` and `Value` parts of the `KvPair` together.
+    ///
+    /// # Examples
+    /// ```rust
+    /// # use tikv_client::{Key, KvPair};
+    /// let pairs = vec![KvPair::new(b"k1".to_vec(), b"v1".to_vec())];
+    /// for (key, value) in pairs.iter().map(KvPair::as_tuple) {
+    ///     assert_eq!(key, &Key::from(b"k1".to_vec()));
+    ///     assert_eq!(value, b"v1");
+    /// }
+    /// ```
+    #[inline]
+    pub fn as_tuple(&self) -> (&Key, &Value) {
+        (&self.0, &self.1)
+    }
+
     /// Consume `self` and return the `Key` part.
     #[inline]
     pub fn into_key(self) -> Key {
@@ -101,6 +122,12 @@ impl From<KvPair> for (Key, Value) {
     }
 }
 
+impl<'a> From<&'a KvPair> for (&'a Key, &'a Value) {
+    fn from(pair: &'a KvPair) -> Self {
+        pair.as_tuple()
+    }
+}
+
 impl From<KvPair> for Key {
     fn from(pair: KvPair) -> Self {
         pair.0
@@ -123,6 +150,56 @@ impl From<KvPair> for kvrpcpb::KvPair {
     }
 }
 
+/// Collect pairs into a map from keys to values, e.g., the result of a scan or batch get.
+///
+/// # Examples
+/// ```rust
+/// # use std::collections::HashMap;
+/// # use tikv_client::{Key, KvPair, Value};
+/// let pairs = vec![
+///     KvPair::new(b"k1".to_vec(), b"v1".to_vec()),
+///     KvPair::new(b"k2".to_vec(), b"v2".to_vec()),
+/// ];
+/// let map: HashMap<Key, Value> = pairs.into_iter().collect();
+/// assert_eq!(map[&Key::from(b"k2".to_vec())], b"v2".to_vec());
+/// ```
+impl<S: BuildHasher + Default> FromIterator<KvPair> for HashMap<Key, Value, S> {
+    fn from_iter<I: IntoIterator<Item = KvPair>>(pairs: I) -> Self {
+        pairs.into_iter().map(<(Key, Value)>::from).collect()
+    }
+}
+
+impl<S: BuildHasher> Extend<KvPair> for HashMap<Key, Value, S> {
+    fn extend<I: IntoIterator<Item = KvPair>>(&mut self, pairs: I) {
+        self.extend(pairs.into_iter().map(<(Key, Value)>::from))
+    }
+}
+
+/// Collect pairs into a map from keys to values, ordered by key.
+///
+/// # Examples
+/// ```rust
+/// # use std::collections::BTreeMap;
+/// # use tikv_client::{Key, KvPair, Value};
+/// let pairs = vec![
+///     KvPair::new(b"k2".to_vec(), b"v2".to_vec()),
+///     KvPair::new(b"k1".to_vec(), b"v1".to_vec()),
+/// ];
+/// let map: BTreeMap<Key, Value> = pairs.into_iter().collect();
+/// assert_eq!(map.keys().next(), Some(&Key::from(b"k1".to_vec())));
+/// ```
+impl FromIterator<KvPair> for BTreeMap<Key, Value> {
+    fn from_iter<I: IntoIterator<Item = KvPair>>(pairs: I) -> Self {
+        pairs.into_iter().map(<(Key, Value)>::from).collect()
+    }
+}
+
+impl Extend<KvPair> for BTreeMap<Key, Value> {
+    fn extend<I: IntoIterator<Item = KvPair>>(&mut self, pairs: I) {
+        self.extend(pairs.into_iter().map(<(Key, Value)>::from))
+    }
+}
+
 impl AsRef<Key> for KvPair {
     fn as_ref(&self) -> &Key {
         &self.0
@@ -139,8 +216,10 @@ impl fmt::Debug for KvPair {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let KvPair(key, value) = self;
         match str::from_utf8(value) {
-            Ok(s) => write!(f, "KvPair({}, {:?})", HexRepr(&key.0), s),
-            Err(_) => write!(f, "KvPair({}, {})", HexRepr(&key.0), HexRepr(value)),
+            Ok(s) if redaction() == Redaction::Off => {
+                write!(f, "KvPair({}, {:?})", HexRepr(&key.0), s)
+            }
+            _ => write!(f, "KvPair({}, {})", HexRepr(&key.0), HexRepr(value)),
         }
     }
 }
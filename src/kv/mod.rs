@@ -2,25 +2,35 @@
 use std::fmt;
 use std::u8;
 
+use tikv_client_common::Redacted;
+
 mod bound_range;
 pub mod codec;
 mod key;
 mod kvpair;
+mod namespace;
 mod value;
+mod value_codec;
 
 pub use bound_range::BoundRange;
 pub use bound_range::IntoOwnedRange;
+pub use key::EscapedKey;
 pub use key::Key;
 pub use kvpair::KvPair;
+pub(crate) use namespace::prefixed_key;
+pub(crate) use namespace::prefixed_range;
+pub use namespace::Namespace;
+pub use namespace::TypedKey;
+pub use namespace::TypedRange;
 pub use value::Value;
+pub use value_codec::JsonCodec;
+pub use value_codec::ValueCodec;
 
+/// Shows bytes in hexadecimal, unless they are [redacted](crate::set_redaction).
 struct HexRepr<'a>(pub &'a [u8]);
 
 impl<'a> fmt::Display for HexRepr<'a> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        for byte in self.0 {
-            write!(f, "{byte:02X}")?;
-        }
-        Ok(())
+        Redacted(self.0).fmt(f)
     }
 }
@@ -0,0 +1,236 @@
+// Copyright 2023 TiKV Project Authors. Licensed under Apache-2.0.
+
+use std::fmt;
+use std::hash::Hash;
+use std::hash::Hasher;
+use std::marker::PhantomData;
+use std::ops::Bound;
+use std::ops::RangeBounds;
+
+use super::BoundRange;
+use super::HexRepr;
+use super::Key;
+
+/// A part of a cluster's keys, shared by several users, which belongs to one of them.
+///
+/// Every key of a namespace starts with its [prefix](Namespace::PREFIX). Name a namespace by a type
+/// implementing this trait, and use [`TypedKey`]s and [`TypedRange`]s of the type for its keys and
+/// ranges: they are prefixed when they are created, so keys of one namespace can't be mixed up
+/// with those of another, and scans and deletions of a range can't reach outside the namespace.
+///
+/// # Examples
+///
+/// ```rust
+/// # use tikv_client::{BoundRange, Key, Namespace, TypedKey, TypedRange};
+/// struct Users;
+///
+/// impl Namespace for Users {
+///     const PREFIX: &'static [u8] = b"users/";
+/// }
+///
+/// let key = TypedKey::<Users>::new(b"alice".to_vec());
+/// assert_eq!(Key::from(key), Key::from(b"users/alice".to_vec()));
+/// // Ranges are of the keys after the prefix, and never leave the namespace.
+/// let range = TypedRange::<Users>::new(b"a".to_vec()..);
+/// assert_eq!(
+///     BoundRange::from(range).into_keys(),
+///     (Key::from(b"users/a".to_vec()), Some(Key::from(b"users0".to_vec()))),
+/// );
+/// ```
+pub trait Namespace: Send + Sync + 'static {
+    /// The prefix of the namespace's keys.
+    ///
+    /// It must not be empty, and namespaces sharing a cluster must not have prefixes which are
+    /// prefixes of each other, or their keys would overlap.
+    const PREFIX: &'static [u8];
+}
+
+/// A key of the [`Namespace`] `N`.
+pub struct TypedKey<N: Namespace> {
+    /// The whole key, starting with the prefix.
+    key: Key,
+    namespace: PhantomData<N>,
+}
+
+impl<N: Namespace> TypedKey<N> {
+    /// The key of `N` which is `suffix` after the namespace's prefix.
+    pub fn new(suffix: impl Into<Key>) -> TypedKey<N> {
+        TypedKey {
+            key: prefixed_key(N::PREFIX, suffix),
+            namespace: PhantomData,
+        }
+    }
+
+    /// The key of `N` which `key` is, or `None` if `key` does not start with the namespace's
+    /// prefix.
+    pub fn from_key(key: impl Into<Key>) -> Option<TypedKey<N>> {
+        let key = key.into();
+        if key.0.starts_with(N::PREFIX) {
+            Some(TypedKey {
+                key,
+                namespace: PhantomData,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// The part of the key after the namespace's prefix.
+    pub fn suffix(&self) -> &[u8] {
+        &self.key.0[N::PREFIX.len()..]
+    }
+}
+
+impl<N: Namespace> From<TypedKey<N>> for Key {
+    fn from(key: TypedKey<N>) -> Key {
+        key.key
+    }
+}
+
+impl<N: Namespace> AsRef<Key> for TypedKey<N> {
+    fn as_ref(&self) -> &Key {
+        &self.key
+    }
+}
+
+impl<N: Namespace> Clone for TypedKey<N> {
+    fn clone(&self) -> Self {
+        TypedKey {
+            key: self.key.clone(),
+            namespace: PhantomData,
+        }
+    }
+}
+
+impl<N: Namespace> PartialEq for TypedKey<N> {
+    fn eq(&self, other: &Self) -> bool {
+        self.key == other.key
+    }
+}
+
+impl<N: Namespace> Eq for TypedKey<N> {}
+
+impl<N: Namespace> Hash for TypedKey<N> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key.hash(state)
+    }
+}
+
+impl<N: Namespace> fmt::Debug for TypedKey<N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "TypedKey({}, {})",
+            HexRepr(N::PREFIX),
+            HexRepr(self.suffix())
+        )
+    }
+}
+
+/// A range of keys of the [`Namespace`] `N`.
+pub struct TypedRange<N: Namespace> {
+    /// The range of whole keys, within the namespace.
+    range: BoundRange,
+    namespace: PhantomData<N>,
+}
+
+impl<N: Namespace> TypedRange<N> {
+    /// The keys of `N` whose suffixes, after the namespace's prefix, are in `suffixes`.
+    pub fn new(suffixes: impl Into<BoundRange>) -> TypedRange<N> {
+        TypedRange {
+            range: prefixed_range(N::PREFIX, suffixes.into()),
+            namespace: PhantomData,
+        }
+    }
+
+    /// All keys of `N`.
+    pub fn all() -> TypedRange<N> {
+        TypedRange {
+            range: BoundRange::prefix(N::PREFIX.to_vec()),
+            namespace: PhantomData,
+        }
+    }
+}
+
+impl<N: Namespace> From<TypedRange<N>> for BoundRange {
+    fn from(range: TypedRange<N>) -> BoundRange {
+        range.range
+    }
+}
+
+impl<N: Namespace> Clone for TypedRange<N> {
+    fn clone(&self) -> Self {
+        TypedRange {
+            range: self.range.clone(),
+            namespace: PhantomData,
+        }
+    }
+}
+
+impl<N: Namespace> fmt::Debug for TypedRange<N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "TypedRange({:?})", self.range)
+    }
+}
+
+/// The key which is `suffix` after `prefix`.
+pub(crate) fn prefixed_key(prefix: &[u8], suffix: impl Into<Key>) -> Key {
+    let mut key = prefix.to_vec();
+    key.extend_from_slice(&suffix.into().0);
+    key.into()
+}
+
+/// The range of the keys which start with `prefix`, and whose suffixes after it are in
+/// `suffixes`.
+pub(crate) fn prefixed_range(prefix: &[u8], suffixes: BoundRange) -> BoundRange {
+    let prefixed = |suffix: &Key| prefixed_key(prefix, suffix.clone());
+    let start = match suffixes.start_bound() {
+        Bound::Included(suffix) => Bound::Included(prefixed(suffix)),
+        Bound::Excluded(suffix) => Bound::Excluded(prefixed(suffix)),
+        Bound::Unbounded => Bound::Included(prefix.to_vec().into()),
+    };
+    let end = match suffixes.end_bound() {
+        Bound::Included(suffix) => Bound::Included(prefixed(suffix)),
+        Bound::Excluded(suffix) => Bound::Excluded(prefixed(suffix)),
+        Bound::Unbounded => BoundRange::prefix(prefix.to_vec()).end_bound().cloned(),
+    };
+    (start, end).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Users;
+
+    impl Namespace for Users {
+        const PREFIX: &'static [u8] = b"users/";
+    }
+
+    #[test]
+    fn test_typed_key() {
+        let key = TypedKey::<Users>::new(b"alice".to_vec());
+        assert_eq!(key.suffix(), b"alice");
+        assert_eq!(TypedKey::from_key(b"users/alice".to_vec()), Some(key));
+        assert_eq!(TypedKey::<Users>::from_key(b"user/alice".to_vec()), None);
+    }
+
+    #[test]
+    fn test_typed_range() {
+        let keys = |range: TypedRange<Users>| BoundRange::from(range).into_keys();
+        let key = |key: &[u8]| Key::from(key.to_vec());
+        assert_eq!(
+            keys(TypedRange::all()),
+            (key(b"users/"), Some(key(b"users0")))
+        );
+        assert_eq!(
+            keys(TypedRange::new(b"a".to_vec()..=b"b".to_vec())),
+            (key(b"users/a"), Some(key(b"users/b\0")))
+        );
+        assert_eq!(
+            keys(TypedRange::new(..b"b".to_vec())),
+            (key(b"users/"), Some(key(b"users/b")))
+        );
+        assert_eq!(keys(TypedRange::new(..)), keys(TypedRange::all()));
+    }
+}
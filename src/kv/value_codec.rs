@@ -0,0 +1,54 @@
+// Copyright 2023 TiKV Project Authors. Licensed under Apache-2.0.
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use super::Value;
+use crate::Error;
+use crate::Result;
+
+/// Converts between values of type `T` and the bytes stored in TiKV.
+///
+/// Implement this to store values in a format of your choice (e.g., bincode or protobuf) with
+/// [`Transaction::put_with_codec`](crate::Transaction::put_with_codec) and
+/// [`Transaction::get_with_codec`](crate::Transaction::get_with_codec).
+///
+/// # Examples
+/// ```rust
+/// # use tikv_client::{Error, Result, Value, ValueCodec};
+/// struct Utf8Codec;
+///
+/// impl ValueCodec<String> for Utf8Codec {
+///     fn encode(&self, value: &String) -> Result<Value> {
+///         Ok(value.clone().into_bytes())
+///     }
+///
+///     fn decode(&self, value: Value) -> Result<String> {
+///         String::from_utf8(value).map_err(|e| Error::ValueCodecError {
+///             message: e.to_string(),
+///         })
+///     }
+/// }
+/// ```
+pub trait ValueCodec<T> {
+    fn encode(&self, value: &T) -> Result<Value>;
+    fn decode(&self, value: Value) -> Result<T>;
+}
+
+/// Stores values as JSON, using serde.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct JsonCodec;
+
+impl<T: Serialize + DeserializeOwned> ValueCodec<T> for JsonCodec {
+    fn encode(&self, value: &T) -> Result<Value> {
+        serde_json::to_vec(value).map_err(|e| Error::ValueCodecError {
+            message: e.to_string(),
+        })
+    }
+
+    fn decode(&self, value: Value) -> Result<T> {
+        serde_json::from_slice(&value).map_err(|e| Error::ValueCodecError {
+            message: e.to_string(),
+        })
+    }
+}
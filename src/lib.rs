@@ -101,19 +101,42 @@ pub mod request;
 #[doc(hidden)]
 pub mod transaction;
 
+mod audit;
 mod backoff;
+mod circuit_breaker;
+mod clock;
 mod compat;
 mod config;
+mod fan_out;
+#[cfg(any(test, feature = "fault-injection"))]
+pub mod fault_injection;
+mod group_commit;
+mod hot_keys;
+mod keyspace;
 mod kv;
+#[cfg(feature = "mock-server")]
+pub mod mock_server;
 mod pd;
+mod presplit;
+mod rate_limit;
 #[doc(hidden)]
 pub mod raw;
+pub mod recipes;
 mod region;
 mod region_cache;
+mod retry_observer;
+mod runtime_config;
+mod router;
+#[cfg(any(test, feature = "simulation"))]
+pub mod simulation;
+mod spawner;
 mod stats;
 mod store;
+pub mod testing;
 mod timestamp;
+mod timestamp_pool;
 mod util;
+mod value_format;
 
 #[cfg(test)]
 mod mock;
@@ -129,43 +152,203 @@ pub use config::Config;
 #[doc(inline)]
 pub use tikv_client_common::security::SecurityManager;
 #[doc(inline)]
+pub use tikv_client_common::redaction;
+#[doc(inline)]
+pub use tikv_client_common::set_redaction;
+#[doc(inline)]
+pub use tikv_client_common::set_redaction_key;
+#[doc(inline)]
+pub use tikv_client_common::ConflictKind;
+#[doc(inline)]
 pub use tikv_client_common::Error;
 #[doc(inline)]
+pub use tikv_client_common::ErrorCode;
+#[doc(inline)]
+pub use tikv_client_common::ErrorContext;
+#[doc(inline)]
+pub use tikv_client_common::Proxy;
+#[doc(inline)]
+pub use tikv_client_common::ProxyProtocol;
+#[doc(inline)]
+pub use tikv_client_common::Redaction;
+#[doc(inline)]
 pub use tikv_client_common::Result;
+#[doc(inline)]
+pub use tikv_client_common::WriteConflict;
+pub use tokio_util::sync::CancellationToken;
 
+#[doc(inline)]
+pub use crate::audit::AuditEvent;
+#[doc(inline)]
+pub use crate::audit::AuditOperation;
+#[doc(inline)]
+pub use crate::audit::AuditOutcome;
+#[doc(inline)]
+pub use crate::audit::AuditSink;
 #[doc(inline)]
 pub use crate::backoff::Backoff;
 #[doc(inline)]
+pub use crate::circuit_breaker::CircuitBreaker;
+#[doc(inline)]
+pub use crate::circuit_breaker::HealthProbe;
+#[doc(inline)]
+pub use crate::clock::Clock;
+#[doc(inline)]
+pub use crate::clock::MockClock;
+#[doc(inline)]
+pub use crate::clock::SystemClock;
+#[doc(inline)]
+pub use crate::fan_out::FanOutTrace;
+#[doc(inline)]
+pub use crate::fan_out::FanOutTracer;
+#[doc(inline)]
+pub use crate::fan_out::RegionTrace;
+#[doc(inline)]
+pub use crate::group_commit::GroupCommit;
+#[doc(inline)]
+pub use crate::hot_keys::HotKey;
+#[doc(inline)]
+pub use crate::hot_keys::HotKeyTracking;
+#[doc(inline)]
+pub use crate::keyspace::Keyspace;
+#[doc(inline)]
+pub use crate::keyspace::KeyspaceState;
+#[doc(inline)]
+pub use crate::keyspace::Keyspaces;
+#[doc(inline)]
 pub use crate::kv::BoundRange;
 #[doc(inline)]
+pub use crate::kv::EscapedKey;
+#[doc(inline)]
 pub use crate::kv::IntoOwnedRange;
 #[doc(inline)]
+pub use crate::kv::JsonCodec;
+#[doc(inline)]
 pub use crate::kv::Key;
 #[doc(inline)]
 pub use crate::kv::KvPair;
 #[doc(inline)]
+pub use crate::kv::Namespace;
+#[doc(inline)]
+pub use crate::kv::TypedKey;
+#[doc(inline)]
+pub use crate::kv::TypedRange;
+#[doc(inline)]
 pub use crate::kv::Value;
 #[doc(inline)]
+pub use crate::kv::ValueCodec;
+#[doc(inline)]
+pub use crate::pd::SingleNodePdClient;
+#[doc(inline)]
 pub use crate::raw::lowering as raw_lowering;
 #[doc(inline)]
+pub use crate::raw::BufferedWriter;
+#[doc(inline)]
 pub use crate::raw::Client as RawClient;
 #[doc(inline)]
 pub use crate::raw::ColumnFamily;
 #[doc(inline)]
+pub use crate::raw::NamespacedClient as NamespacedRawClient;
+#[doc(inline)]
+pub use crate::raw::PrefixedClient as PrefixedRawClient;
+#[doc(inline)]
+pub use crate::raw::RawMutation;
+#[doc(inline)]
+pub use crate::rate_limit::RateLimit;
+#[doc(inline)]
+pub use crate::rate_limit::ScanPacing;
+#[doc(inline)]
+pub use crate::region::RegionInfo;
+#[doc(inline)]
+pub use crate::region_cache::RegionCacheStats;
+#[doc(inline)]
 pub use crate::request::RetryOptions;
 #[doc(inline)]
+pub use crate::retry_observer::RetryEvent;
+#[doc(inline)]
+pub use crate::retry_observer::RetryObserver;
+#[doc(inline)]
+pub use crate::retry_observer::RetryReason;
+#[doc(inline)]
+pub use crate::router::ClusterRouter;
+#[doc(inline)]
+pub use crate::runtime_config::ConfigUpdate;
+#[doc(inline)]
+pub use crate::spawner::Spawner;
+#[doc(inline)]
+pub use crate::timestamp::LogicalClock;
+#[doc(inline)]
 pub use crate::timestamp::Timestamp;
 #[doc(inline)]
 pub use crate::timestamp::TimestampExt;
 #[doc(inline)]
+pub use crate::timestamp::TimestampProvider;
+#[doc(inline)]
+pub use crate::timestamp_pool::TimestampPrefetch;
+#[doc(inline)]
 pub use crate::transaction::lowering as transaction_lowering;
 #[doc(inline)]
+pub use crate::transaction::BufferObserver;
+#[doc(inline)]
+pub use crate::transaction::BufferedMutation;
+#[doc(inline)]
+pub use crate::transaction::BulkWriteChunk;
+#[doc(inline)]
+pub use crate::transaction::BulkWriter;
+#[doc(inline)]
 pub use crate::transaction::CheckLevel;
 #[doc(inline)]
+pub use crate::transaction::CheckpointedScan;
+#[doc(inline)]
 pub use crate::transaction::Client as TransactionClient;
 #[doc(inline)]
+pub use crate::transaction::CloseReport;
+#[doc(inline)]
+pub use crate::transaction::CommitStats;
+#[doc(inline)]
+pub use crate::transaction::ExportFormat;
+#[doc(inline)]
+pub use crate::transaction::ExportOptions;
+#[doc(inline)]
+pub use crate::transaction::ExportedFile;
+#[doc(inline)]
+pub use crate::transaction::ImportProgress;
+#[doc(inline)]
+pub use crate::transaction::KeyChange;
+#[doc(inline)]
+pub use crate::transaction::LockEvent;
+#[doc(inline)]
+pub use crate::transaction::LockObserver;
+#[doc(inline)]
+pub use crate::transaction::MergedScanner;
+#[doc(inline)]
+pub use crate::transaction::MutationKind;
+#[doc(inline)]
+pub use crate::transaction::Participant;
+#[doc(inline)]
+pub use crate::transaction::PrimaryKeyStrategy;
+#[doc(inline)]
+pub use crate::transaction::ScanCheckpoint;
+#[doc(inline)]
+pub use crate::transaction::ScanForUpdate;
+#[doc(inline)]
 pub use crate::transaction::Snapshot;
 #[doc(inline)]
 pub use crate::transaction::Transaction;
 #[doc(inline)]
 pub use crate::transaction::TransactionOptions;
+#[doc(inline)]
+pub use crate::transaction::TransactionState;
+#[doc(inline)]
+pub use crate::transaction::TxnStatus;
+#[doc(inline)]
+pub use crate::transaction::VALUE_CHUNK_SIZE;
+#[cfg(feature = "aes-gcm")]
+#[doc(inline)]
+pub use crate::value_format::AesGcmCipher;
+#[doc(inline)]
+pub use crate::value_format::Compression;
+#[doc(inline)]
+pub use crate::value_format::CompressionAlgorithm;
+#[doc(inline)]
+pub use crate::value_format::ValueCipher;
@@ -18,12 +18,18 @@ use tikv_client_store::KvClient;
 use tikv_client_store::KvConnect;
 use tikv_client_store::Request;
 
+use crate::audit::AuditLog;
+use crate::clock::Clock;
+use crate::clock::SystemClock;
 use crate::pd::PdClient;
 use crate::pd::PdRpcClient;
 use crate::pd::RetryClient;
 use crate::region::RegionId;
 use crate::region::RegionWithLeader;
+use crate::region_cache::RegionCacheStats;
 use crate::store::RegionStore;
+use crate::AuditSink;
+use crate::BoundRange;
 use crate::Config;
 use crate::Error;
 use crate::Key;
@@ -83,6 +89,10 @@ pub struct MockCluster;
 #[derive(new)]
 pub struct MockPdClient {
     client: MockKvClient,
+    #[new(value = "Arc::new(SystemClock)")]
+    clock: Arc<dyn Clock>,
+    #[new(default)]
+    audit_log: Option<Arc<AuditLog>>,
 }
 
 #[async_trait]
@@ -111,9 +121,21 @@ impl MockPdClient {
     pub fn default() -> MockPdClient {
         MockPdClient {
             client: MockKvClient::default(),
+            clock: Arc::new(SystemClock),
+            audit_log: None,
         }
     }
 
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> MockPdClient {
+        self.clock = clock;
+        self
+    }
+
+    pub fn with_audit_sink(mut self, sink: impl AuditSink + 'static) -> MockPdClient {
+        self.audit_log = Some(Arc::new(AuditLog::new(Arc::new(sink), 1.0)));
+        self
+    }
+
     pub fn region1() -> RegionWithLeader {
         let mut region = RegionWithLeader::default();
         region.region.id = 1;
@@ -177,7 +199,11 @@ impl PdClient for MockPdClient {
     type KvClient = MockKvClient;
 
     async fn map_region_to_store(self: Arc<Self>, region: RegionWithLeader) -> Result<RegionStore> {
-        Ok(RegionStore::new(region, Arc::new(self.client.clone())))
+        Ok(RegionStore::new(
+            region,
+            Arc::new(self.client.clone()),
+            "mock://tikv".to_owned(),
+        ))
     }
 
     async fn region_for_key(&self, key: &Key) -> Result<RegionWithLeader> {
@@ -210,6 +236,15 @@ impl PdClient for MockPdClient {
         unimplemented!()
     }
 
+    async fn update_service_safepoint(
+        self: Arc<Self>,
+        _service_id: String,
+        _ttl: i64,
+        _safepoint: u64,
+    ) -> Result<u64> {
+        unimplemented!()
+    }
+
     async fn update_leader(
         &self,
         _ver_id: crate::region::RegionVerId,
@@ -219,4 +254,20 @@ impl PdClient for MockPdClient {
     }
 
     async fn invalidate_region_cache(&self, _ver_id: crate::region::RegionVerId) {}
+
+    async fn invalidate_region_cache_range(&self, _range: BoundRange) -> usize {
+        0
+    }
+
+    async fn region_cache_stats(&self) -> RegionCacheStats {
+        RegionCacheStats::default()
+    }
+
+    fn clock(&self) -> Arc<dyn Clock> {
+        self.clock.clone()
+    }
+
+    fn audit_log(&self) -> Option<Arc<AuditLog>> {
+        self.audit_log.clone()
+    }
 }
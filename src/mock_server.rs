@@ -0,0 +1,624 @@
+// Copyright 2023 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! A mock TiKV cluster served over gRPC, for running examples and tests of code which connects to
+//! PD like a production client.
+//!
+//! A [`MockServer`] serves a [`Simulation`] on a local port, which clients connect to as their PD
+//! endpoint: it answers PD's requests for members, regions, stores, and timestamps, and the stores'
+//! raw and transactional requests. Every simulated store is served at the same address.
+//!
+//! This module requires the `mock-server` feature.
+//!
+//! # Examples
+//!
+//! ```rust
+//! # use tikv_client::mock_server::MockServer;
+//! # use tikv_client::{RawClient, TransactionClient};
+//! # #[tokio::main]
+//! # async fn main() {
+//! let server = MockServer::start().await.unwrap();
+//!
+//! let client = RawClient::new(vec![server.pd_endpoint()], None).await.unwrap();
+//! client.put("key".to_owned(), "value".to_owned()).await.unwrap();
+//!
+//! let client = TransactionClient::new(vec![server.pd_endpoint()], None)
+//!     .await
+//!     .unwrap();
+//! let mut txn = client.begin_optimistic().await.unwrap();
+//! txn.put("key".to_owned(), "value".to_owned()).await.unwrap();
+//! txn.commit().await.unwrap();
+//! # }
+//! ```
+
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::task::Context;
+use std::task::Poll;
+
+use futures::channel::oneshot;
+use futures::future::BoxFuture;
+use futures::prelude::*;
+use futures::stream::BoxStream;
+use tikv_client_proto::kvrpcpb;
+use tikv_client_proto::metapb;
+use tikv_client_proto::pdpb;
+use tikv_client_store::KvClient;
+use tokio::net::TcpListener;
+use tonic::body::BoxBody;
+use tonic::codec::Codec;
+use tonic::codec::ProstCodec;
+use tonic::codec::Streaming;
+use tonic::codegen::http;
+use tonic::codegen::Service;
+use tonic::server::Grpc;
+use tonic::server::NamedService;
+use tonic::transport::Body;
+use tonic::transport::Server;
+use tonic::Status;
+
+use crate::pd::RetryClientTrait;
+use crate::region::StoreId;
+use crate::simulation::Simulation;
+use crate::Error;
+use crate::Result;
+
+/// The id of the mock cluster, as reported to clients.
+const CLUSTER_ID: u64 = 1;
+
+/// Serves a [`Simulation`] over gRPC until dropped.
+pub struct MockServer {
+    address: SocketAddr,
+    simulation: Arc<Simulation>,
+    leader: Arc<Mutex<Option<String>>>,
+    shutdown: Option<oneshot::Sender<()>>,
+}
+
+impl MockServer {
+    /// Serve a new simulation on a free port of the loopback interface.
+    pub async fn start() -> Result<MockServer> {
+        MockServer::serve(Simulation::new(0)).await
+    }
+
+    /// Serve `simulation` on a free port of the loopback interface.
+    ///
+    /// Faults can be injected into the requests of clients of the server through the simulation,
+    /// see [`MockServer::simulation`].
+    pub async fn serve(simulation: Simulation) -> Result<MockServer> {
+        MockServer::serve_cluster(simulation, CLUSTER_ID).await
+    }
+
+    /// Serve `simulation` as a member of the cluster with id `cluster_id`.
+    pub(crate) async fn serve_cluster(
+        simulation: Simulation,
+        cluster_id: u64,
+    ) -> Result<MockServer> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let address = listener.local_addr()?;
+        let simulation = Arc::new(simulation);
+        let leader = Arc::new(Mutex::new(None));
+        let handler = Handler {
+            simulation: simulation.clone(),
+            address,
+            cluster_id,
+            leader: leader.clone(),
+        };
+        let incoming = stream::unfold(listener, |listener| async move {
+            let stream = listener.accept().await.map(|(stream, _)| stream);
+            Some((stream, listener))
+        });
+        let (shutdown, stopped) = oneshot::channel::<()>();
+        let server = Server::builder()
+            .add_service(PdService(handler.clone()))
+            .add_service(TikvService(handler))
+            .serve_with_incoming_shutdown(incoming, stopped.map(|_| ()));
+        tokio::spawn(async move {
+            if let Err(e) = server.await {
+                log::warn!("mock server failed: {}", e);
+            }
+        });
+        Ok(MockServer {
+            address,
+            simulation,
+            leader,
+            shutdown: Some(shutdown),
+        })
+    }
+
+    /// The endpoint for clients to connect to as PD.
+    pub fn pd_endpoint(&self) -> String {
+        self.address.to_string()
+    }
+
+    /// The simulation which is served.
+    pub fn simulation(&self) -> &Simulation {
+        &self.simulation
+    }
+
+    /// Report the PD member at `endpoint` as the cluster's leader, or this server if `None`.
+    #[cfg(test)]
+    pub(crate) fn set_leader(&self, endpoint: Option<String>) {
+        *self.leader.lock().unwrap() = endpoint;
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Handler {
+    simulation: Arc<Simulation>,
+    address: SocketAddr,
+    cluster_id: u64,
+    /// The endpoint of the member reported as the leader, if not this server.
+    leader: Arc<Mutex<Option<String>>>,
+}
+
+impl Handler {
+    fn member(&self) -> pdpb::Member {
+        pdpb::Member {
+            name: "mock-pd".to_owned(),
+            member_id: 1,
+            client_urls: vec![format!("http://{}", self.address)],
+            ..Default::default()
+        }
+    }
+
+    fn leader(&self) -> pdpb::Member {
+        match self.leader.lock().unwrap().clone() {
+            Some(endpoint) => pdpb::Member {
+                name: "mock-pd-leader".to_owned(),
+                member_id: 2,
+                client_urls: vec![format!("http://{endpoint}")],
+                ..Default::default()
+            },
+            None => self.member(),
+        }
+    }
+
+    fn store(&self, store: metapb::Store) -> metapb::Store {
+        metapb::Store {
+            address: self.address.to_string(),
+            ..store
+        }
+    }
+}
+
+fn header(cluster_id: u64) -> Option<pdpb::ResponseHeader> {
+    Some(pdpb::ResponseHeader {
+        cluster_id,
+        error: None,
+    })
+}
+
+fn status(e: Error) -> Status {
+    match e.root() {
+        Error::GrpcAPI(status) => status.clone(),
+        _ => Status::internal(e.to_string()),
+    }
+}
+
+/// Serves PD's requests.
+#[derive(Clone)]
+struct PdService(Handler);
+
+impl NamedService for PdService {
+    const NAME: &'static str = "pdpb.PD";
+}
+
+impl Service<http::Request<Body>> for PdService {
+    type Response = http::Response<BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<'static, std::result::Result<Self::Response, Infallible>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<std::result::Result<(), Infallible>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: http::Request<Body>) -> Self::Future {
+        let handler = self.0.clone();
+        let pd = handler.simulation.pd();
+        let cluster_id = handler.cluster_id;
+        Box::pin(async move {
+            let response = match req.uri().path().strip_prefix("/pdpb.PD/") {
+                Some("GetMembers") => {
+                    unary(req, move |_: pdpb::GetMembersRequest| async move {
+                        Ok(pdpb::GetMembersResponse {
+                            header: header(cluster_id),
+                            members: vec![handler.member()],
+                            leader: Some(handler.leader()),
+                            ..Default::default()
+                        })
+                    })
+                    .await
+                }
+                Some("Tso") => {
+                    let simulation = handler.simulation.clone();
+                    Grpc::new(ProstCodec::<pdpb::TsoResponse, pdpb::TsoRequest>::default())
+                        .streaming(Tso(simulation, cluster_id), req)
+                        .await
+                }
+                Some("GetRegion") => {
+                    unary(req, move |req: pdpb::GetRegionRequest| async move {
+                        let region = pd.get_region(req.region_key).await.map_err(status)?;
+                        Ok(pdpb::GetRegionResponse {
+                            header: header(cluster_id),
+                            region: Some(region.region),
+                            leader: region.leader,
+                            ..Default::default()
+                        })
+                    })
+                    .await
+                }
+                Some("GetRegionByID") => {
+                    unary(req, move |req: pdpb::GetRegionByIdRequest| async move {
+                        let region = pd.get_region_by_id(req.region_id).await.ok();
+                        Ok(pdpb::GetRegionResponse {
+                            header: header(cluster_id),
+                            leader: region.as_ref().and_then(|region| region.leader.clone()),
+                            region: region.map(|region| region.region),
+                            ..Default::default()
+                        })
+                    })
+                    .await
+                }
+                Some("GetStore") => {
+                    unary(req, move |req: pdpb::GetStoreRequest| async move {
+                        let store = pd.get_store(req.store_id).await;
+                        let store = store.map_err(|e| Status::not_found(e.to_string()))?;
+                        Ok(pdpb::GetStoreResponse {
+                            header: header(cluster_id),
+                            store: Some(handler.store(store)),
+                            stats: None,
+                        })
+                    })
+                    .await
+                }
+                Some("GetAllStores") => {
+                    unary(req, move |_: pdpb::GetAllStoresRequest| async move {
+                        let stores = pd.get_all_stores().await.map_err(status)?;
+                        Ok(pdpb::GetAllStoresResponse {
+                            header: header(cluster_id),
+                            stores: stores
+                                .into_iter()
+                                .map(|store| handler.store(store))
+                                .collect(),
+                        })
+                    })
+                    .await
+                }
+                Some("UpdateGCSafePoint") => {
+                    unary(req, move |req: pdpb::UpdateGcSafePointRequest| async move {
+                        Ok(pdpb::UpdateGcSafePointResponse {
+                            header: header(cluster_id),
+                            new_safe_point: req.safe_point,
+                        })
+                    })
+                    .await
+                }
+                Some("UpdateServiceGCSafePoint") => {
+                    unary(req, move |req: pdpb::UpdateServiceGcSafePointRequest| async move {
+                        let service_id = String::from_utf8_lossy(&req.service_id).into_owned();
+                        let min_safe_point = pd
+                            .update_service_safepoint(service_id, req.ttl, req.safe_point)
+                            .await
+                            .map_err(status)?;
+                        Ok(pdpb::UpdateServiceGcSafePointResponse {
+                            header: header(cluster_id),
+                            service_id: req.service_id,
+                            ttl: req.ttl,
+                            min_safe_point,
+                        })
+                    })
+                    .await
+                }
+                _ => Status::unimplemented("not supported by the mock server").to_http(),
+            };
+            Ok(response)
+        })
+    }
+}
+
+/// Serves the stores' requests.
+#[derive(Clone)]
+struct TikvService(Handler);
+
+impl NamedService for TikvService {
+    const NAME: &'static str = "tikvpb.Tikv";
+}
+
+impl Service<http::Request<Body>> for TikvService {
+    type Response = http::Response<BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<'static, std::result::Result<Self::Response, Infallible>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<std::result::Result<(), Infallible>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: http::Request<Body>) -> Self::Future {
+        let simulation = self.0.simulation.clone();
+        Box::pin(serve_kv(simulation, req).map(Ok))
+    }
+}
+
+/// The store a request was sent to. Every store is served at the same address, so this is taken
+/// from the peer the client addressed.
+fn store_of(context: Option<&kvrpcpb::Context>) -> StoreId {
+    context
+        .and_then(|context| context.peer.as_ref())
+        .map_or(0, |peer| peer.store_id)
+}
+
+// Generates `serve_kv`, which passes each kind of request the simulation supports to the store it
+// was sent to, by the name of its method.
+macro_rules! kv_methods {
+    ($($method:literal => $request:ident => $response:ident,)*) => {
+        async fn serve_kv(
+            simulation: Arc<Simulation>,
+            req: http::Request<Body>,
+        ) -> http::Response<BoxBody> {
+            match req.uri().path().strip_prefix("/tikvpb.Tikv/") {
+                $(
+                    Some($method) => {
+                        unary(req, move |req: kvrpcpb::$request| async move {
+                            let client = simulation.kv_client(store_of(req.context.as_ref()));
+                            let resp = client.dispatch(&req).await.map_err(status)?;
+                            let resp = resp
+                                .downcast::<kvrpcpb::$response>()
+                                .map_err(|_| Status::internal("unexpected response type"))?;
+                            Ok(*resp)
+                        })
+                        .await
+                    }
+                )*
+                _ => Status::unimplemented("not supported by the mock server").to_http(),
+            }
+        }
+    };
+}
+
+kv_methods! {
+    "KvGet" => GetRequest => GetResponse,
+    "KvBatchGet" => BatchGetRequest => BatchGetResponse,
+    "KvScan" => ScanRequest => ScanResponse,
+    "KvPrewrite" => PrewriteRequest => PrewriteResponse,
+    "KvCommit" => CommitRequest => CommitResponse,
+    "KvBatchRollback" => BatchRollbackRequest => BatchRollbackResponse,
+    "KvCleanup" => CleanupRequest => CleanupResponse,
+    "KvCheckTxnStatus" => CheckTxnStatusRequest => CheckTxnStatusResponse,
+    "KvResolveLock" => ResolveLockRequest => ResolveLockResponse,
+    "KvTxnHeartBeat" => TxnHeartBeatRequest => TxnHeartBeatResponse,
+    "KvScanLock" => ScanLockRequest => ScanLockResponse,
+    "MvccGetByKey" => MvccGetByKeyRequest => MvccGetByKeyResponse,
+    "KvPessimisticLock" => PessimisticLockRequest => PessimisticLockResponse,
+    "KVPessimisticRollback" => PessimisticRollbackRequest => PessimisticRollbackResponse,
+    "RawGet" => RawGetRequest => RawGetResponse,
+    "RawBatchGet" => RawBatchGetRequest => RawBatchGetResponse,
+    "RawPut" => RawPutRequest => RawPutResponse,
+    "RawBatchPut" => RawBatchPutRequest => RawBatchPutResponse,
+    "RawDelete" => RawDeleteRequest => RawDeleteResponse,
+    "RawBatchDelete" => RawBatchDeleteRequest => RawBatchDeleteResponse,
+    "RawDeleteRange" => RawDeleteRangeRequest => RawDeleteRangeResponse,
+    "RawScan" => RawScanRequest => RawScanResponse,
+    "RawCompareAndSwap" => RawCasRequest => RawCasResponse,
+}
+
+/// Decode a unary request, and encode the response of `handle` to it.
+async fn unary<Req, Resp, F, Fut>(req: http::Request<Body>, handle: F) -> http::Response<BoxBody>
+where
+    Resp: 'static,
+    ProstCodec<Resp, Req>: Codec<Encode = Resp, Decode = Req>,
+    F: FnOnce(Req) -> Fut,
+    Fut: Future<Output = std::result::Result<Resp, Status>> + Send + 'static,
+{
+    Grpc::new(ProstCodec::<Resp, Req>::default())
+        .unary(Unary(Some(handle)), req)
+        .await
+}
+
+/// A unary service which handles a single request.
+struct Unary<F>(Option<F>);
+
+impl<Req, Resp, F, Fut> Service<tonic::Request<Req>> for Unary<F>
+where
+    Resp: 'static,
+    F: FnOnce(Req) -> Fut,
+    Fut: Future<Output = std::result::Result<Resp, Status>> + Send + 'static,
+{
+    type Response = tonic::Response<Resp>;
+    type Error = Status;
+    type Future = BoxFuture<'static, std::result::Result<tonic::Response<Resp>, Status>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<std::result::Result<(), Status>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: tonic::Request<Req>) -> Self::Future {
+        let handle = self.0.take().expect("a unary service handles one request");
+        Box::pin(handle(req.into_inner()).map_ok(tonic::Response::new))
+    }
+}
+
+type TsoResponses = BoxStream<'static, std::result::Result<pdpb::TsoResponse, Status>>;
+
+/// Serves the stream of timestamp requests of a client, for the cluster with the given id.
+struct Tso(Arc<Simulation>, u64);
+
+impl Service<tonic::Request<Streaming<pdpb::TsoRequest>>> for Tso {
+    type Response = tonic::Response<TsoResponses>;
+    type Error = Status;
+    type Future = future::Ready<std::result::Result<Self::Response, Status>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<std::result::Result<(), Status>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: tonic::Request<Streaming<pdpb::TsoRequest>>) -> Self::Future {
+        let simulation = self.0.clone();
+        let cluster_id = self.1;
+        let responses = req.into_inner().map_ok(move |req| pdpb::TsoResponse {
+            header: header(cluster_id),
+            count: req.count,
+            timestamp: Some(simulation.allocate_timestamps(req.count)),
+        });
+        future::ok(tonic::Response::new(responses.boxed()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tikv_client_pd::Connection;
+
+    use super::*;
+    use crate::pd::PdClient;
+    use crate::pd::SingleNodePdClient;
+    use crate::simulation::Fault;
+    use crate::Config;
+    use crate::LogicalClock;
+    use crate::RawClient;
+    use crate::SecurityManager;
+    use crate::TimestampExt;
+    use crate::TransactionClient;
+
+    #[tokio::test]
+    async fn test_mock_server() {
+        let server = MockServer::start().await.unwrap();
+        let client = RawClient::new(vec![server.pd_endpoint()], None)
+            .await
+            .unwrap();
+        client.put(b"k1".to_vec(), b"v1".to_vec()).await.unwrap();
+        let value = client.get(b"k1".to_vec()).await.unwrap();
+        assert_eq!(value, Some(b"v1".to_vec()));
+        let pairs = vec![(b"k2".to_vec(), b"v2".to_vec())];
+        client.batch_put(pairs).await.unwrap();
+        let pairs = client.scan(b"k".to_vec().., 10).await.unwrap();
+        assert_eq!(pairs.len(), 2);
+
+        let client = TransactionClient::new(vec![server.pd_endpoint()], None)
+            .await
+            .unwrap();
+        let mut dropped = false;
+        server.simulation().inject_faults(move |request| {
+            if request.label == "kv_commit" && !dropped {
+                dropped = true;
+                vec![Fault::DropRequest]
+            } else {
+                Vec::new()
+            }
+        });
+        let mut txn = client.begin_optimistic().await.unwrap();
+        txn.put(b"a".to_vec(), b"1".to_vec()).await.unwrap();
+        txn.put(b"b".to_vec(), b"2".to_vec()).await.unwrap();
+        assert!(txn.commit().await.is_err());
+        txn.commit().await.unwrap();
+
+        let mut txn = client.begin_optimistic().await.unwrap();
+        assert_eq!(txn.get(b"b".to_vec()).await.unwrap(), Some(b"2".to_vec()));
+        txn.rollback().await.unwrap();
+        let ts = client.current_timestamp().await.unwrap();
+        assert!(client.gc(ts).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_timestamp_provider() {
+        let server = MockServer::start().await.unwrap();
+        let config = Config::default().with_timestamp_provider(LogicalClock::new(100));
+        let client = TransactionClient::new_with_config(vec![server.pd_endpoint()], config, None)
+            .await
+            .unwrap();
+        assert_eq!(client.current_timestamp().await.unwrap().version(), 100);
+        let mut txn = client.begin_optimistic().await.unwrap();
+        assert_eq!(txn.start_timestamp().version(), 101);
+        txn.rollback().await.unwrap();
+
+        let config = Config::default().with_timestamp_provider(LogicalClock::new(200));
+        let pd = SingleNodePdClient::connect(server.pd_endpoint(), &config)
+            .await
+            .unwrap();
+        let pd = Arc::new(pd);
+        assert_eq!(pd.clone().get_timestamp().await.unwrap().version(), 200);
+        assert_eq!(pd.get_timestamp().await.unwrap().version(), 201);
+    }
+
+    #[tokio::test]
+    async fn test_single_node() {
+        // The simulation starts with a single region, id 1, covering all keys, which is what a
+        // single node is assumed to serve.
+        let server = MockServer::start().await.unwrap();
+        let client = RawClient::connect_single_node(server.pd_endpoint())
+            .await
+            .unwrap();
+        client.put(b"k1".to_vec(), b"v1".to_vec()).await.unwrap();
+        assert_eq!(
+            client.get(b"k1".to_vec()).await.unwrap(),
+            Some(b"v1".to_vec())
+        );
+
+        let config = Config::default().with_timestamp_provider(LogicalClock::new(1 << 40));
+        let client =
+            TransactionClient::connect_single_node_with_config(server.pd_endpoint(), config)
+                .await
+                .unwrap();
+        let mut txn = client.begin_optimistic().await.unwrap();
+        txn.put(b"a".to_vec(), b"1".to_vec()).await.unwrap();
+        txn.put(b"b".to_vec(), b"2".to_vec()).await.unwrap();
+        txn.commit().await.unwrap();
+        let mut txn = client.begin_optimistic().await.unwrap();
+        assert_eq!(txn.get(b"a".to_vec()).await.unwrap(), Some(b"1".to_vec()));
+        assert_eq!(txn.get(b"b".to_vec()).await.unwrap(), Some(b"2".to_vec()));
+        txn.rollback().await.unwrap();
+    }
+    #[tokio::test]
+    async fn test_cluster_mismatch() {
+        let server = MockServer::start().await.unwrap();
+        let other = MockServer::serve_cluster(Simulation::new(0), CLUSTER_ID + 1)
+            .await
+            .unwrap();
+        let connection = Connection::new(Arc::new(SecurityManager::default()));
+        let timeout = Duration::from_secs(2);
+        let is_mismatch = |e: &Error| {
+            matches!(
+                e,
+                Error::ClusterMismatch {
+                    expected: CLUSTER_ID,
+                    actual,
+                } if *actual == CLUSTER_ID + 1
+            )
+        };
+
+        let endpoints = [server.pd_endpoint(), other.pd_endpoint()];
+        let e = connection
+            .connect_cluster(&endpoints, timeout)
+            .await
+            .err()
+            .unwrap();
+        assert!(is_mismatch(&e), "{e:?}");
+
+        // The cluster's member now reports a leader in another cluster, as if DNS pointed it at
+        // the wrong cluster. Reconnecting fails, rather than retrying as if PD were unreachable.
+        let mut cluster = connection
+            .connect_cluster(&[server.pd_endpoint()], timeout)
+            .await
+            .unwrap();
+        server.set_leader(Some(other.pd_endpoint()));
+        let e = connection
+            .reconnect(&mut cluster, timeout)
+            .await
+            .unwrap_err();
+        assert!(is_mismatch(&e), "{e:?}");
+
+        server.set_leader(None);
+        connection.reconnect(&mut cluster, timeout).await.unwrap();
+        assert_eq!(cluster.id(), CLUSTER_ID);
+    }
+}
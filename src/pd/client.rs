@@ -1,30 +1,56 @@
 // Copyright 2018 TiKV Project Authors. Licensed under Apache-2.0.
 
-use std::collections::HashMap;
 use std::sync::Arc;
+use std::sync::RwLock;
 
 use async_trait::async_trait;
 use futures::prelude::*;
 use futures::stream::BoxStream;
 use slog::Logger;
+use tikv_client_common::internal_err;
+use tikv_client_common::set_redaction;
+use tikv_client_common::Redaction;
 use tikv_client_pd::Cluster;
+use tikv_client_proto::keyspacepb;
 use tikv_client_proto::kvrpcpb;
 use tikv_client_proto::metapb;
 use tikv_client_store::KvClient;
 use tikv_client_store::KvConnect;
+use tikv_client_store::RequestTimeout;
 use tikv_client_store::TikvConnect;
-use tokio::sync::RwLock;
-
+use tokio_util::sync::CancellationToken;
+
+use crate::audit::AuditLog;
+use crate::circuit_breaker::run_health_probes;
+use crate::circuit_breaker::Connections;
+use crate::circuit_breaker::StoreHealth;
+use crate::clock::Clock;
+use crate::clock::SystemClock;
 use crate::compat::stream_fn;
+#[cfg(any(test, feature = "fault-injection"))]
+use crate::fault_injection::FaultInjector;
+use crate::hot_keys::HotKeyTracker;
 use crate::kv::codec;
+use crate::pd::retry::watch_endpoint;
 use crate::pd::retry::RetryClientTrait;
 use crate::pd::RetryClient;
+use crate::rate_limit::InFlightLimiter;
+use crate::rate_limit::RateLimiter;
 use crate::region::RegionId;
+use crate::region::RegionInfo;
 use crate::region::RegionVerId;
 use crate::region::RegionWithLeader;
 use crate::region_cache::RegionCache;
+use crate::region_cache::RegionCacheStats;
+use crate::retry_observer::RetryObserver;
+use crate::runtime_config::ConfigUpdate;
+use crate::runtime_config::LogLevel;
+use crate::spawner::spawn_with;
+use crate::spawner::Spawner;
 use crate::store::RegionStore;
+use crate::timestamp::TimestampProvider;
 use crate::BoundRange;
+use crate::CircuitBreaker;
 use crate::Config;
 use crate::Key;
 use crate::Result;
@@ -65,6 +91,16 @@ pub trait PdClient: Send + Sync + 'static {
 
     async fn update_safepoint(self: Arc<Self>, safepoint: u64) -> Result<bool>;
 
+    /// Keep GC from passing `safepoint` for the next `ttl` seconds on behalf of `service_id`, or
+    /// release the service's safepoint if `ttl` is not positive. Returns the minimum safepoint of
+    /// all services, which is above `safepoint` if GC has already passed it.
+    async fn update_service_safepoint(
+        self: Arc<Self>,
+        service_id: String,
+        ttl: i64,
+        safepoint: u64,
+    ) -> Result<u64>;
+
     /// In transactional API, `key` is in raw format
     async fn store_for_key(self: Arc<Self>, key: &Key) -> Result<RegionStore> {
         let region = self.region_for_key(key).await?;
@@ -200,6 +236,83 @@ pub trait PdClient: Send + Sync + 'static {
     async fn update_leader(&self, ver_id: RegionVerId, leader: metapb::Peer) -> Result<()>;
 
     async fn invalidate_region_cache(&self, ver_id: RegionVerId);
+
+    /// Remove all cached regions which overlap `range`, returning how many were removed.
+    ///
+    /// In transactional API, `range` is in raw format.
+    async fn invalidate_region_cache_range(&self, range: BoundRange) -> usize;
+
+    async fn region_cache_stats(&self) -> RegionCacheStats;
+
+    /// The regions covering `range`, and the stores of their leaders.
+    async fn regions_for_range(self: Arc<Self>, range: BoundRange) -> Result<Vec<RegionInfo>> {
+        self.stores_for_range(range)
+            .map_ok(|store| {
+                let region = &store.region_with_leader;
+                RegionInfo {
+                    id: region.id(),
+                    start_key: region.start_key(),
+                    end_key: region.end_key(),
+                    leader_store_id: region.leader.as_ref().map(|leader| leader.store_id),
+                    store_address: store.address,
+                }
+            })
+            .try_collect()
+            .await
+    }
+
+    /// Make sure the regions covering `range`, their stores, and connections to those stores are
+    /// cached, returning the number of regions.
+    async fn warm_region_cache(self: Arc<Self>, range: BoundRange) -> Result<usize> {
+        self.stores_for_range(range)
+            .try_fold(0, |count, _| future::ready(Ok(count + 1)))
+            .await
+    }
+
+    /// The limiter every request dispatched through this client must pass, if any.
+    fn rate_limiter(&self) -> Option<Arc<RateLimiter>> {
+        None
+    }
+
+    /// The health of the stores requests are dispatched to, if it is tracked.
+    fn store_health(&self) -> Option<Arc<StoreHealth>> {
+        None
+    }
+
+    /// The limiter of requests in flight to each store, if they are limited.
+    fn in_flight_limiter(&self) -> Option<Arc<InFlightLimiter>> {
+        None
+    }
+
+    /// The clock for heartbeats, backoff, and deadlines of requests through this client.
+    fn clock(&self) -> Arc<dyn Clock> {
+        Arc::new(SystemClock)
+    }
+
+    /// The observer of the retries of requests through this client, if any.
+    fn retry_observer(&self) -> Option<Arc<dyn RetryObserver>> {
+        None
+    }
+
+    /// The spawner of the client's background tasks, or `None` to spawn them on the current
+    /// tokio runtime.
+    fn spawner(&self) -> Option<Arc<dyn Spawner>> {
+        None
+    }
+
+    /// Where to record the keys accessed through this client, if anywhere.
+    fn audit_log(&self) -> Option<Arc<AuditLog>> {
+        None
+    }
+
+    /// Where to record the statistics of the keys accessed through this client, if anywhere.
+    fn hot_key_tracker(&self) -> Option<Arc<HotKeyTracker>> {
+        None
+    }
+
+    /// Stop the client's store health probes, and drop its connections to TiKV stores. The
+    /// connection to PD, and its timestamp task, live until the client is dropped.
+    async fn close(&self) {}
 }
 
 /// This client converts requests for the logical TiKV cluster into requests
@@ -207,9 +320,28 @@ pub trait PdClient: Send + Sync + 'static {
 pub struct PdRpcClient<KvC: KvConnect + Send + Sync + 'static = TikvConnect, Cl = Cluster> {
     pd: Arc<RetryClient<Cl>>,
     kv_connect: KvC,
-    kv_client_cache: Arc<RwLock<HashMap<String, KvC::KvClient>>>,
+    kv_client_cache: Arc<Connections<KvC::KvClient>>,
     enable_codec: bool,
     region_cache: RegionCache<RetryClient<Cl>>,
+    // Replaced when the client's rate limit is updated.
+    rate_limiter: RwLock<Option<Arc<RateLimiter>>>,
+    store_health: Option<Arc<StoreHealth>>,
+    in_flight_limiter: Option<Arc<InFlightLimiter>>,
+    retry_observer: Option<Arc<dyn RetryObserver>>,
+    spawner: Option<Arc<dyn Spawner>>,
+    audit_log: Option<Arc<AuditLog>>,
+    hot_key_tracker: Option<Arc<HotKeyTracker>>,
+    #[cfg(any(test, feature = "fault-injection"))]
+    fault_injector: Option<FaultInjector>,
+    // For connecting to PD's HTTP API.
+    #[cfg(feature = "keyspace-create")]
+    security_mgr: Arc<SecurityManager>,
+    timestamp_provider: Option<Arc<dyn TimestampProvider>>,
+    // Cancelled once the client is closed, stopping its background tasks.
+    closed: CancellationToken,
+    // The timeout of requests to TiKV, if it can be changed.
+    request_timeout: Option<RequestTimeout>,
+    log_level: Option<LogLevel>,
     logger: Logger,
 }
 
@@ -220,8 +352,14 @@ impl<KvC: KvConnect + Send + Sync + 'static> PdClient for PdRpcClient<KvC> {
     async fn map_region_to_store(self: Arc<Self>, region: RegionWithLeader) -> Result<RegionStore> {
         let store_id = region.get_store_id()?;
         let store = self.region_cache.get_store_by_id(store_id).await?;
-        let kv_client = self.kv_client(&store.address).await?;
-        Ok(RegionStore::new(region, Arc::new(kv_client)))
+        let kv_client: Arc<dyn KvClient + Send + Sync> =
+            Arc::new(self.kv_client(&store.address).await?);
+        #[cfg(any(test, feature = "fault-injection"))]
+        let kv_client = match &self.fault_injector {
+            Some(injector) => injector.wrap(kv_client, region.id(), &store.address, self.clock()),
+            None => kv_client,
+        };
+        Ok(RegionStore::new(region, kv_client, store.address))
     }
 
     async fn region_for_key(&self, key: &Key) -> Result<RegionWithLeader> {
@@ -242,13 +380,28 @@ impl<KvC: KvConnect + Send + Sync + 'static> PdClient for PdRpcClient<KvC> {
     }
 
     async fn get_timestamp(self: Arc<Self>) -> Result<Timestamp> {
-        self.pd.clone().get_timestamp().await
+        match &self.timestamp_provider {
+            Some(provider) => provider.get_timestamp().await,
+            None => self.pd.clone().get_timestamp().await,
+        }
     }
 
     async fn update_safepoint(self: Arc<Self>, safepoint: u64) -> Result<bool> {
         self.pd.clone().update_safepoint(safepoint).await
     }
 
+    async fn update_service_safepoint(
+        self: Arc<Self>,
+        service_id: String,
+        ttl: i64,
+        safepoint: u64,
+    ) -> Result<u64> {
+        self.pd
+            .clone()
+            .update_service_safepoint(service_id, ttl, safepoint)
+            .await
+    }
+
     async fn update_leader(&self, ver_id: RegionVerId, leader: metapb::Peer) -> Result<()> {
         self.region_cache.update_leader(ver_id, leader).await
     }
@@ -256,6 +409,59 @@ impl<KvC: KvConnect + Send + Sync + 'static> PdClient for PdRpcClient<KvC> {
     async fn invalidate_region_cache(&self, ver_id: RegionVerId) {
         self.region_cache.invalidate_region_cache(ver_id).await
     }
+
+    async fn invalidate_region_cache_range(&self, range: BoundRange) -> usize {
+        let (start_key, end_key) = range.into_keys();
+        let mut end_key = end_key.unwrap_or_default();
+        let mut start_key = start_key;
+        if self.enable_codec {
+            start_key = start_key.to_encoded();
+            // An empty end key means unbounded, so must not be encoded.
+            if !end_key.is_empty() {
+                end_key = end_key.to_encoded();
+            }
+        }
+        self.region_cache
+            .invalidate_range(&start_key, &end_key)
+            .await
+    }
+
+    async fn region_cache_stats(&self) -> RegionCacheStats {
+        self.region_cache.stats().await
+    }
+
+    fn rate_limiter(&self) -> Option<Arc<RateLimiter>> {
+        self.rate_limiter.read().unwrap().clone()
+    }
+
+    fn store_health(&self) -> Option<Arc<StoreHealth>> {
+        self.store_health.clone()
+    }
+
+    fn in_flight_limiter(&self) -> Option<Arc<InFlightLimiter>> {
+        self.in_flight_limiter.clone()
+    }
+
+    fn retry_observer(&self) -> Option<Arc<dyn RetryObserver>> {
+        self.retry_observer.clone()
+    }
+
+    fn spawner(&self) -> Option<Arc<dyn Spawner>> {
+        self.spawner.clone()
+    }
+
+    fn audit_log(&self) -> Option<Arc<AuditLog>> {
+        self.audit_log.clone()
+    }
+
+    fn hot_key_tracker(&self) -> Option<Arc<HotKeyTracker>> {
+        self.hot_key_tracker.clone()
+    }
+
+    async fn close(&self) {
+        self.closed.cancel();
+        self.kv_client_cache.write().await.clear();
+    }
 }
 
 impl PdRpcClient<TikvConnect, Cluster> {
@@ -265,14 +471,94 @@ impl PdRpcClient<TikvConnect, Cluster> {
         enable_codec: bool,
         logger: Logger,
     ) -> Result<PdRpcClient> {
-        PdRpcClient::new(
+        // Errors don't know which client they come from, so redaction applies to the whole process.
+        if config.redaction != Redaction::Off {
+            set_redaction(config.redaction);
+        }
+        let mut request_timeout = None;
+        let spawner = config.spawner.as_ref().map(|handle| handle.0.clone());
+        let mut client = PdRpcClient::new(
             config.clone(),
-            |security_mgr| TikvConnect::new(security_mgr, config.timeout),
-            |security_mgr| RetryClient::connect(pd_endpoints, security_mgr, config.timeout),
+            |security_mgr| {
+                let connect = TikvConnect::new(security_mgr, config.timeout)
+                    .with_batching(config.group_commit.clone().map(Into::into))
+                    .with_spawner(spawner.clone());
+                request_timeout = Some(connect.request_timeout());
+                connect
+            },
+            |security_mgr| {
+                let timeout = config.pd_timeout.unwrap_or(config.timeout);
+                RetryClient::connect(pd_endpoints, security_mgr, timeout, spawner.clone())
+            },
             enable_codec,
             logger,
         )
-        .await
+        .await?;
+        client.request_timeout = request_timeout;
+        // Resolving the endpoint's host again is left to the background, so that requests are not
+        // held up by the lookups.
+        let watch = watch_endpoint(
+            Arc::downgrade(&client.pd),
+            client.clock(),
+            client.closed.clone(),
+        );
+        spawn_with(client.spawner.as_deref(), watch);
+        Ok(client)
+    }
+
+    pub async fn load_keyspace(&self, name: String) -> Result<keyspacepb::KeyspaceMeta> {
+        self.pd.clone().load_keyspace(name).await
+    }
+
+    pub async fn get_all_keyspaces(
+        &self,
+        start_id: u32,
+        limit: u32,
+    ) -> Result<Vec<keyspacepb::KeyspaceMeta>> {
+        self.pd.clone().get_all_keyspaces(start_id, limit).await
+    }
+
+    /// Split regions at `split_keys`, then scatter the new regions across stores. Returns the ids
+    /// of the new regions.
+    pub async fn split_and_scatter_regions(&self, split_keys: Vec<Key>) -> Result<Vec<RegionId>> {
+        let split_keys = split_keys
+            .into_iter()
+            .map(|key| {
+                if self.enable_codec {
+                    key.to_encoded().into()
+                } else {
+                    key.into()
+                }
+            })
+            .collect();
+        let resp = self
+            .pd
+            .clone()
+            .split_and_scatter_regions(split_keys, String::new())
+            .await?;
+        if resp.split_finished_percentage < 100 {
+            return Err(internal_err!(
+                "only {}% of regions were split",
+                resp.split_finished_percentage
+            ));
+        }
+        if resp.scatter_finished_percentage < 100 {
+            warn!(
+                self.logger,
+                "only {}% of split regions were scattered", resp.scatter_finished_percentage
+            );
+        }
+        Ok(resp.regions_id)
+    }
+
+    #[cfg(feature = "keyspace-create")]
+    pub async fn pd_leader_url(&self) -> Option<String> {
+        self.pd.leader_url().await
+    }
+
+    #[cfg(feature = "keyspace-create")]
+    pub fn security_mgr(&self) -> &SecurityManager {
+        &self.security_mgr
     }
 }
 
@@ -296,21 +582,97 @@ impl<KvC: KvConnect + Send + Sync + 'static, Cl> PdRpcClient<KvC, Cl> {
                 SecurityManager::load(ca_path, cert_path, key_path)?
             } else {
                 SecurityManager::default()
-            },
+            }
+            .with_proxy(config.proxy.clone()),
         );
 
-        let pd = Arc::new(pd(security_mgr.clone()).await?);
-        let kv_client_cache = Default::default();
+        let retry_observer = config
+            .retry_observer
+            .as_ref()
+            .map(|handle| handle.0.clone());
+        let pd = pd(security_mgr.clone()).await?;
+        let pd = Arc::new(
+            pd.with_retry_observer(retry_observer.clone())
+                .with_retry_budget(config.pd_retry_budget),
+        );
+        let kv_client_cache: Arc<Connections<KvC::KvClient>> = Default::default();
+        // Probed stores need their health tracked, even without a circuit breaker.
+        let circuit_breaker = match (&config.circuit_breaker, &config.health_probe) {
+            (None, Some(_)) => Some(CircuitBreaker::default()),
+            (circuit_breaker, _) => circuit_breaker.clone(),
+        };
+        let store_health = circuit_breaker
+            .map(|config| Arc::new(StoreHealth::new(config, Arc::new(SystemClock))));
+        let spawner = config.spawner.as_ref().map(|handle| handle.0.clone());
+        let closed = CancellationToken::new();
+        if let (Some(probe), Some(health)) = (&config.health_probe, &store_health) {
+            let probes = run_health_probes(
+                probe.clone(),
+                Arc::downgrade(&kv_client_cache),
+                health.clone(),
+                closed.clone(),
+            );
+            spawn_with(spawner.as_deref(), probes);
+        }
         Ok(PdRpcClient {
             pd: pd.clone(),
             kv_client_cache,
-            kv_connect: kv_connect(security_mgr),
+            kv_connect: kv_connect(security_mgr.clone()),
             enable_codec,
             region_cache: RegionCache::new(pd),
+            rate_limiter: RwLock::new(
+                config
+                    .rate_limit
+                    .as_ref()
+                    .and_then(RateLimiter::new)
+                    .map(Arc::new),
+            ),
+            store_health,
+            in_flight_limiter: config
+                .max_in_flight_per_store
+                .map(|limit| Arc::new(InFlightLimiter::new(limit))),
+            retry_observer,
+            spawner,
+            audit_log: config.audit_log.clone().map(Arc::new),
+            hot_key_tracker: config
+                .hot_key_tracking
+                .clone()
+                .map(|tracking| Arc::new(HotKeyTracker::new(tracking))),
+            #[cfg(any(test, feature = "fault-injection"))]
+            fault_injector: config.fault_injector.clone(),
+            #[cfg(feature = "keyspace-create")]
+            security_mgr,
+            timestamp_provider: config
+                .timestamp_provider
+                .as_ref()
+                .map(|handle| handle.0.clone()),
+            closed,
+            request_timeout: None,
+            log_level: None,
             logger,
         })
     }
 
+    /// Let `log_level` be changed by [`update_config`](Self::update_config).
+    pub(crate) fn with_log_level(mut self, log_level: LogLevel) -> Self {
+        self.log_level = Some(log_level);
+        self
+    }
+
+    /// Apply `update` to the requests sent from now on.
+    pub(crate) fn update_config(&self, update: &ConfigUpdate) {
+        if let (Some(timeout), Some(request_timeout)) = (update.timeout, &self.request_timeout) {
+            request_timeout.set(timeout);
+        }
+        if let Some(rate_limit) = &update.rate_limit {
+            *self.rate_limiter.write().unwrap() = RateLimiter::new(rate_limit).map(Arc::new);
+        }
+        if let (Some(level), Some(log_level)) = (update.log_level, &self.log_level) {
+            log_level.set(level);
+        }
+        info!(self.logger, "updated client config: {:?}", update);
+    }
+
     async fn kv_client(&self, address: &str) -> Result<KvC::KvClient> {
         if let Some(client) = self.kv_client_cache.read().await.get(address) {
             return Ok(client.clone());
@@ -338,6 +700,8 @@ fn make_key_range(start_key: Vec<u8>, end_key: Vec<u8>) -> kvrpcpb::KeyRange {
 
 #[cfg(test)]
 pub mod test {
+    use std::time::Duration;
+
     use futures::executor;
     use futures::executor::block_on;
 
@@ -358,6 +722,36 @@ pub mod test {
         assert_eq!(kv2.addr, kv3.addr);
     }
 
+    #[tokio::test]
+    async fn test_update_config() {
+        let client = pd_rpc_client()
+            .await
+            .with_log_level(LogLevel::new(slog::Level::Info));
+        let timeout = RequestTimeout::new(Duration::from_secs(2));
+        let client = PdRpcClient {
+            request_timeout: Some(timeout.clone()),
+            ..client
+        };
+        assert!(client.rate_limiter.read().unwrap().is_none());
+
+        client.update_config(
+            &ConfigUpdate::default()
+                .timeout(Duration::from_secs(5))
+                .rate_limit(crate::RateLimit::default().requests_per_second(100)),
+        );
+        assert_eq!(timeout.get(), Duration::from_secs(5));
+        assert!(client.rate_limiter.read().unwrap().is_some());
+
+        // Settings left unset are kept.
+        client.update_config(&ConfigUpdate::default().log_level(slog::Level::Debug));
+        assert_eq!(timeout.get(), Duration::from_secs(5));
+        assert!(client.rate_limiter.read().unwrap().is_some());
+
+        // A limit which doesn't limit anything removes the limit.
+        client.update_config(&ConfigUpdate::default().rate_limit(crate::RateLimit::default()));
+        assert!(client.rate_limiter.read().unwrap().is_none());
+    }
+
     #[test]
     fn test_group_keys_by_region() {
         let client = MockPdClient::default();
@@ -408,6 +802,23 @@ pub mod test {
         assert!(stream.next().is_none());
     }
 
+    #[tokio::test]
+    async fn test_regions_for_range() {
+        let client = Arc::new(MockPdClient::default());
+        let regions = client
+            .regions_for_range((vec![5]..vec![11]).into())
+            .await
+            .unwrap();
+        assert_eq!(
+            regions.iter().map(|region| region.id).collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+        assert_eq!(regions[0].start_key, Key::EMPTY);
+        assert_eq!(regions[0].end_key, vec![10].into());
+        assert_eq!(regions[0].leader_store_id, Some(41));
+        assert_eq!(regions[1].store_address, "mock://tikv");
+    }
+
     #[test]
     fn test_group_ranges_by_region() {
         let client = Arc::new(MockPdClient::default());
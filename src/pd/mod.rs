@@ -1,7 +1,9 @@
 mod client;
 mod retry;
+mod single_node;
 
 pub use client::PdClient;
 pub use client::PdRpcClient;
 pub use retry::RetryClient;
 pub use retry::RetryClientTrait;
+pub use single_node::SingleNodePdClient;
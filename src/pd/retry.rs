@@ -4,21 +4,33 @@
 
 use std::fmt;
 use std::sync::Arc;
+use std::sync::Weak;
 use std::time::Duration;
 use std::time::Instant;
 
 use async_trait::async_trait;
+use tikv_client_common::internal_err;
 use tikv_client_pd::Cluster;
 use tikv_client_pd::Connection;
+use tikv_client_proto::keyspacepb;
 use tikv_client_proto::metapb;
 use tikv_client_proto::pdpb::Timestamp;
 use tikv_client_proto::pdpb::{self};
 use tokio::sync::RwLock;
 use tokio::time::sleep;
+use tokio::time::timeout;
+use tokio_util::sync::CancellationToken;
 
+use crate::clock::Clock;
 use crate::region::RegionId;
 use crate::region::RegionWithLeader;
 use crate::region::StoreId;
+use crate::retry_observer::RetryEvent;
+use crate::retry_observer::RetryObserver;
+use crate::retry_observer::RetryReason;
+use crate::spawner::Spawner;
+use crate::stats::pd_budget_exceeded;
+use crate::stats::pd_retry;
 use crate::stats::pd_stats;
 use crate::Error;
 use crate::Result;
@@ -29,6 +41,9 @@ use crate::SecurityManager;
 const RECONNECT_INTERVAL_SEC: u64 = 1;
 const MAX_REQUEST_COUNT: usize = 5;
 const LEADER_CHANGE_RETRY: usize = 10;
+// How often the host of the PD member the client is connected to is resolved again, to notice it
+// moving to another address.
+const ENDPOINT_CHECK_INTERVAL: Duration = Duration::from_secs(30);
 
 #[async_trait]
 pub trait RetryClientTrait {
@@ -45,6 +60,14 @@ pub trait RetryClientTrait {
     async fn get_timestamp(self: Arc<Self>) -> Result<Timestamp>;
 
     async fn update_safepoint(self: Arc<Self>, safepoint: u64) -> Result<bool>;
+
+    // Returns the minimum service safepoint after the update.
+    async fn update_service_safepoint(
+        self: Arc<Self>,
+        service_id: String,
+        ttl: i64,
+        safepoint: u64,
+    ) -> Result<u64>;
 }
 /// Client for communication with a PD cluster. Has the facility to reconnect to the cluster.
 pub struct RetryClient<Cl = Cluster> {
@@ -52,6 +75,9 @@ pub struct RetryClient<Cl = Cluster> {
     cluster: RwLock<(Cl, Instant)>,
     connection: Connection,
     timeout: Duration,
+    // How long a request, retries included, may take before it fails.
+    retry_budget: Option<Duration>,
+    observer: Option<Arc<dyn RetryObserver>>,
 }
 
 #[cfg(test)]
@@ -66,40 +92,83 @@ impl<Cl> RetryClient<Cl> {
             cluster: RwLock::new((cluster, Instant::now())),
             connection,
             timeout,
+            retry_budget: None,
+            observer: None,
         }
     }
 }
 
+impl<Cl> RetryClient<Cl> {
+    /// Tell `observer` about the retries of requests to PD.
+    pub fn with_retry_observer(mut self, observer: Option<Arc<dyn RetryObserver>>) -> Self {
+        self.observer = observer;
+        self
+    }
+
+    /// Fail requests to PD which, retries included, take longer than `budget`.
+    pub fn with_retry_budget(mut self, budget: Option<Duration>) -> Self {
+        self.retry_budget = budget;
+        self
+    }
+}
+
 macro_rules! retry {
     ($self: ident, $tag: literal, |$cluster: ident| $call: expr) => {{
-        let stats = pd_stats($tag);
-        let mut last_err = Ok(());
-        for _ in 0..LEADER_CHANGE_RETRY {
-            // use the block here to drop the guard of the read lock,
-            // otherwise `reconnect` will try to acquire the write lock and results in a deadlock
-            let res = {
-                let $cluster = &mut $self.cluster.write().await.0;
-                let res = $call.await;
-                res
-            };
+        let start = Instant::now();
+        let attempts = async {
+            let stats = pd_stats($tag);
+            let mut last_err = Ok(());
+            for attempt in 1..=LEADER_CHANGE_RETRY {
+                // use the block here to drop the guard of the read lock,
+                // otherwise `reconnect` will try to acquire the write lock and results in a deadlock
+                let res = {
+                    let $cluster = &mut $self.cluster.write().await.0;
+                    let res = $call.await;
+                    res
+                };
+
+                match stats.done(res) {
+                    Ok(r) => return Ok(r),
+                    Err(e) => last_err = Err(e),
+                }
+                pd_retry($tag);
+                if let Some(observer) = &$self.observer {
+                    observer.on_retry(RetryEvent {
+                        reason: RetryReason::Pd,
+                        attempt: attempt as u32,
+                        delay: None,
+                        will_retry: attempt < LEADER_CHANGE_RETRY,
+                        region_id: None,
+                        store_address: None,
+                    });
+                }
 
-            match stats.done(res) {
-                Ok(r) => return Ok(r),
-                Err(e) => last_err = Err(e),
+                let mut reconnect_count = MAX_REQUEST_COUNT;
+                while let Err(e) = $self.reconnect(RECONNECT_INTERVAL_SEC).await {
+                    reconnect_count -= 1;
+                    if reconnect_count == 0 {
+                        return Err(e);
+                    }
+                    sleep(Duration::from_secs(RECONNECT_INTERVAL_SEC)).await;
+                }
             }
 
-            let mut reconnect_count = MAX_REQUEST_COUNT;
-            while let Err(e) = $self.reconnect(RECONNECT_INTERVAL_SEC).await {
-                reconnect_count -= 1;
-                if reconnect_count == 0 {
-                    return Err(e);
+            last_err?;
+            unreachable!();
+        };
+        match $self.retry_budget {
+            Some(budget) => match timeout(budget, attempts).await {
+                Ok(result) => result,
+                Err(_) => {
+                    pd_budget_exceeded($tag, start.elapsed());
+                    Err(Error::PdTimeout {
+                        operation: $tag,
+                        budget,
+                    })
                 }
-                sleep(Duration::from_secs(RECONNECT_INTERVAL_SEC)).await;
-            }
+            },
+            None => attempts.await,
         }
-
-        last_err?;
-        unreachable!();
     }};
 }
 
@@ -108,8 +177,9 @@ impl RetryClient<Cluster> {
         endpoints: &[String],
         security_mgr: Arc<SecurityManager>,
         timeout: Duration,
+        spawner: Option<Arc<dyn Spawner>>,
     ) -> Result<RetryClient> {
-        let connection = Connection::new(security_mgr);
+        let connection = Connection::new(security_mgr).with_spawner(spawner);
         let cluster = RwLock::new((
             connection.connect_cluster(endpoints, timeout).await?,
             Instant::now(),
@@ -118,8 +188,60 @@ impl RetryClient<Cluster> {
             cluster,
             connection,
             timeout,
+            retry_budget: None,
+            observer: None,
         })
     }
+
+    pub async fn load_keyspace(self: Arc<Self>, name: String) -> Result<keyspacepb::KeyspaceMeta> {
+        retry!(self, "load_keyspace", |cluster| {
+            let name = name.clone();
+            async {
+                cluster
+                    .load_keyspace(name, self.timeout)
+                    .await
+                    .and_then(|resp| {
+                        resp.keyspace
+                            .ok_or_else(|| internal_err!("no keyspace in LoadKeyspaceResponse"))
+                    })
+            }
+        })
+    }
+
+    pub async fn get_all_keyspaces(
+        self: Arc<Self>,
+        start_id: u32,
+        limit: u32,
+    ) -> Result<Vec<keyspacepb::KeyspaceMeta>> {
+        retry!(self, "get_all_keyspaces", |cluster| async {
+            cluster
+                .get_all_keyspaces(start_id, limit, self.timeout)
+                .await
+                .map(|resp| resp.keyspaces)
+        })
+    }
+
+    pub async fn split_and_scatter_regions(
+        self: Arc<Self>,
+        split_keys: Vec<Vec<u8>>,
+        group: String,
+    ) -> Result<pdpb::SplitAndScatterRegionsResponse> {
+        retry!(self, "split_and_scatter_regions", |cluster| {
+            let split_keys = split_keys.clone();
+            let group = group.clone();
+            async {
+                cluster
+                    .split_and_scatter_regions(split_keys, group, self.timeout)
+                    .await
+            }
+        })
+    }
+
+    /// The client URL of the PD leader, as of the last (re)connection.
+    #[cfg(feature = "keyspace-create")]
+    pub async fn leader_url(&self) -> Option<String> {
+        self.cluster.read().await.0.leader_url().map(ToOwned::to_owned)
+    }
 }
 
 #[async_trait]
@@ -182,12 +304,27 @@ impl RetryClientTrait for RetryClient<Cluster> {
                 .map(|resp| resp.new_safe_point == safepoint)
         })
     }
+
+    async fn update_service_safepoint(
+        self: Arc<Self>,
+        service_id: String,
+        ttl: i64,
+        safepoint: u64,
+    ) -> Result<u64> {
+        retry!(self, "update_service_gc_safepoint", |cluster| async {
+            cluster
+                .update_service_safepoint(service_id.clone(), ttl, safepoint, self.timeout)
+                .await
+                .map(|resp| resp.min_safe_point)
+        })
+    }
 }
 
 impl fmt::Debug for RetryClient {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         fmt.debug_struct("pd::RetryClient")
             .field("timeout", &self.timeout)
+            .field("retry_budget", &self.retry_budget)
             .finish()
     }
 }
@@ -205,6 +342,9 @@ fn region_from_response(
 trait Reconnect {
     type Cl;
     async fn reconnect(&self, interval_sec: u64) -> Result<()>;
+
+    /// Reconnect if the endpoint connected to has moved.
+    async fn check_endpoint(&self) {}
 }
 
 #[async_trait]
@@ -224,6 +364,52 @@ impl Reconnect for RetryClient<Cluster> {
         }
         Ok(())
     }
+
+    async fn check_endpoint(&self) {
+        // Resolve without holding the lock, so that requests are not held up.
+        let endpoint = self.cluster.read().await.0.endpoint().clone();
+        if !endpoint.has_moved().await {
+            return;
+        }
+        log::info!("PD endpoint {} has moved, reconnecting", endpoint.url());
+        let mut lock = self.cluster.write().await;
+        let (cluster, last_connected) = &mut *lock;
+        // A concurrent reconnect may have connected elsewhere already.
+        if *cluster.endpoint() != endpoint {
+            return;
+        }
+        match self.connection.reconnect(cluster, self.timeout).await {
+            Ok(()) => *last_connected = Instant::now(),
+            Err(e) => log::warn!("failed to reconnect to PD: {:?}", e),
+        }
+    }
+}
+
+/// Reconnect `client` whenever the endpoint it is connected to has moved, checking every
+/// `ENDPOINT_CHECK_INTERVAL` by `clock`, until the client is dropped or `closed` is cancelled.
+pub(crate) async fn watch_endpoint(
+    client: Weak<RetryClient>,
+    clock: Arc<dyn Clock>,
+    closed: CancellationToken,
+) {
+    check_endpoints(client, clock, closed).await
+}
+
+async fn check_endpoints<C: Reconnect>(
+    client: Weak<C>,
+    clock: Arc<dyn Clock>,
+    closed: CancellationToken,
+) {
+    loop {
+        tokio::select! {
+            _ = closed.cancelled() => return,
+            _ = clock.sleep(ENDPOINT_CHECK_INTERVAL) => {}
+        }
+        match client.upgrade() {
+            Some(client) => client.check_endpoint().await,
+            None => return,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -233,16 +419,20 @@ mod test {
     use std::sync::Mutex;
 
     use futures::executor;
+    use futures::future;
     use futures::future::ready;
     use tikv_client_common::internal_err;
 
     use super::*;
+    use crate::MockClock;
 
     #[tokio::test(flavor = "multi_thread")]
     async fn test_reconnect() {
         struct MockClient {
             reconnect_count: AtomicUsize,
             cluster: RwLock<((), Instant)>,
+            retry_budget: Option<Duration>,
+            observer: Option<Arc<dyn RetryObserver>>,
         }
 
         #[async_trait]
@@ -269,6 +459,8 @@ mod test {
             let client = Arc::new(MockClient {
                 reconnect_count: AtomicUsize::new(0),
                 cluster: RwLock::new(((), Instant::now())),
+                retry_budget: None,
+                observer: None,
             });
 
             assert!(retry_err(client.clone()).await.is_err());
@@ -296,6 +488,8 @@ mod test {
     fn test_retry() {
         struct MockClient {
             cluster: RwLock<(AtomicUsize, Instant)>,
+            retry_budget: Option<Duration>,
+            observer: Option<Arc<dyn RetryObserver>>,
         }
 
         #[async_trait]
@@ -342,6 +536,8 @@ mod test {
         executor::block_on(async {
             let client = Arc::new(MockClient {
                 cluster: RwLock::new((AtomicUsize::new(0), Instant::now())),
+                retry_budget: None,
+                observer: None,
             });
             let max_retries = Arc::new(AtomicUsize::new(1000));
 
@@ -351,13 +547,128 @@ mod test {
                 LEADER_CHANGE_RETRY
             );
 
+            let events = Arc::new(Mutex::new(Vec::new()));
+            let observed = events.clone();
+            let observer = move |event| observed.lock().unwrap().push(event);
             let client = Arc::new(MockClient {
                 cluster: RwLock::new((AtomicUsize::new(0), Instant::now())),
+                retry_budget: None,
+                observer: Some(Arc::new(observer)),
             });
             let max_retries = Arc::new(AtomicUsize::new(2));
 
             assert!(retry_max_ok(client.clone(), max_retries).await.is_ok());
             assert_eq!(client.cluster.read().await.0.load(Ordering::SeqCst), 2);
+            let event = RetryEvent {
+                reason: RetryReason::Pd,
+                attempt: 1,
+                delay: None,
+                will_retry: true,
+                region_id: None,
+                store_address: None,
+            };
+            assert_eq!(*events.lock().unwrap(), vec![event]);
         })
     }
+
+    #[tokio::test]
+    async fn test_retry_budget() {
+        struct MockClient {
+            cluster: RwLock<((), Instant)>,
+            retry_budget: Option<Duration>,
+            observer: Option<Arc<dyn RetryObserver>>,
+        }
+
+        #[async_trait]
+        impl Reconnect for MockClient {
+            type Cl = ();
+
+            async fn reconnect(&self, _: u64) -> Result<()> {
+                Ok(())
+            }
+        }
+
+        async fn retry_hung(client: Arc<MockClient>) -> Result<()> {
+            retry!(client, "test", |_c| future::pending::<Result<()>>())
+        }
+
+        let client = Arc::new(MockClient {
+            cluster: RwLock::new(((), Instant::now())),
+            retry_budget: Some(Duration::from_millis(20)),
+            observer: None,
+        });
+        let err = retry_hung(client).await.unwrap_err();
+        assert!(matches!(
+            err,
+            Error::PdTimeout {
+                operation: "test",
+                ..
+            }
+        ));
+        assert_eq!(err.code(), crate::ErrorCode::DeadlineExceeded);
+    }
+
+    #[tokio::test]
+    async fn test_watch_endpoint() {
+        struct MockClient {
+            checks: AtomicUsize,
+        }
+
+        #[async_trait]
+        impl Reconnect for MockClient {
+            type Cl = ();
+
+            async fn reconnect(&self, _: u64) -> Result<()> {
+                Ok(())
+            }
+
+            async fn check_endpoint(&self) {
+                self.checks.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        let clock = Arc::new(MockClock::new());
+        let client = Arc::new(MockClient {
+            checks: AtomicUsize::new(0),
+        });
+        let watch = tokio::spawn(check_endpoints(
+            Arc::downgrade(&client),
+            clock.clone(),
+            CancellationToken::new(),
+        ));
+
+        // The endpoint is only checked once the interval has passed on the client's clock.
+        for checks in 1..=2 {
+            while clock.sleepers() == 0 {
+                tokio::task::yield_now().await;
+            }
+            assert_eq!(client.checks.load(Ordering::SeqCst), checks - 1);
+            clock.advance(ENDPOINT_CHECK_INTERVAL);
+            while client.checks.load(Ordering::SeqCst) < checks {
+                tokio::task::yield_now().await;
+            }
+        }
+
+        // Checking stops once the client is dropped.
+        drop(client);
+        while clock.sleepers() == 0 {
+            tokio::task::yield_now().await;
+        }
+        clock.advance(ENDPOINT_CHECK_INTERVAL);
+        watch.await.unwrap();
+
+        // Or once the client is closed.
+        let client = Arc::new(MockClient {
+            checks: AtomicUsize::new(0),
+        });
+        let closed = CancellationToken::new();
+        let watch = tokio::spawn(check_endpoints(
+            Arc::downgrade(&client),
+            clock.clone(),
+            closed.clone(),
+        ));
+        closed.cancel();
+        watch.await.unwrap();
+        assert_eq!(client.checks.load(Ordering::SeqCst), 0);
+    }
 }
@@ -0,0 +1,217 @@
+// Copyright 2023 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! A development mode which talks to a single TiKV instance without PD.
+
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use async_trait::async_trait;
+use tikv_client_proto::metapb;
+use tikv_client_store::KvConnect;
+use tikv_client_store::TikvConnect;
+
+use crate::pd::PdClient;
+use crate::region::RegionId;
+use crate::region::RegionVerId;
+use crate::region::RegionWithLeader;
+use crate::region_cache::RegionCacheStats;
+use crate::store::RegionStore;
+use crate::timestamp::TimestampProvider;
+use crate::BoundRange;
+use crate::Config;
+use crate::Error;
+use crate::Key;
+use crate::Result;
+use crate::SecurityManager;
+use crate::Timestamp;
+
+/// The id of the only region, and of the store and peer serving it.
+const SINGLE_NODE_ID: u64 = 1;
+/// The number of bits of the logical part of a timestamp.
+const LOGICAL_BITS: u32 = 18;
+
+/// Routes every request to a single TiKV instance, without PD.
+///
+/// The instance is taken to serve one region, with id 1, covering all keys, and timestamps are
+/// issued by the client from the system clock, unless the config has a [`TimestampProvider`].
+/// This is only meant for development, e.g., for running examples and tests against a mock TiKV:
+/// timestamps are not coordinated with other clients, and GC safepoints are not stored anywhere.
+///
+/// Create clients in this mode using
+/// [`RawClient::connect_single_node`](crate::RawClient::connect_single_node) or
+/// [`TransactionClient::connect_single_node`](crate::TransactionClient::connect_single_node), or
+/// their `connect_single_node_with_config` variants.
+pub struct SingleNodePdClient {
+    address: String,
+    client: <TikvConnect as KvConnect>::KvClient,
+    oracle: StubOracle,
+    timestamp_provider: Option<Arc<dyn TimestampProvider>>,
+}
+
+impl SingleNodePdClient {
+    pub async fn connect(address: String, config: &Config) -> Result<SingleNodePdClient> {
+        let security_mgr = if let (Some(ca_path), Some(cert_path), Some(key_path)) =
+            (&config.ca_path, &config.cert_path, &config.key_path)
+        {
+            SecurityManager::load(ca_path, cert_path, key_path)?
+        } else {
+            SecurityManager::default()
+        }
+        .with_proxy(config.proxy.clone());
+        let client = TikvConnect::new(Arc::new(security_mgr), config.timeout)
+            .with_batching(config.group_commit.clone().map(Into::into))
+            .with_spawner(config.spawner.as_ref().map(|handle| handle.0.clone()))
+            .connect(&address)
+            .await?;
+        Ok(SingleNodePdClient {
+            address,
+            client,
+            oracle: StubOracle::default(),
+            timestamp_provider: config
+                .timestamp_provider
+                .as_ref()
+                .map(|handle| handle.0.clone()),
+        })
+    }
+}
+
+#[async_trait]
+impl PdClient for SingleNodePdClient {
+    type KvClient = <TikvConnect as KvConnect>::KvClient;
+
+    async fn map_region_to_store(self: Arc<Self>, region: RegionWithLeader) -> Result<RegionStore> {
+        Ok(RegionStore::new(
+            region,
+            Arc::new(self.client.clone()),
+            self.address.clone(),
+        ))
+    }
+
+    async fn region_for_key(&self, _key: &Key) -> Result<RegionWithLeader> {
+        Ok(single_region())
+    }
+
+    async fn region_for_id(&self, id: RegionId) -> Result<RegionWithLeader> {
+        if id == SINGLE_NODE_ID {
+            Ok(single_region())
+        } else {
+            Err(Error::RegionNotFoundInResponse { region_id: id })
+        }
+    }
+
+    async fn get_timestamp(self: Arc<Self>) -> Result<Timestamp> {
+        match &self.timestamp_provider {
+            Some(provider) => provider.get_timestamp().await,
+            None => Ok(self.oracle.next()),
+        }
+    }
+
+    /// There is nowhere to store the safepoint, so it is never updated.
+    async fn update_safepoint(self: Arc<Self>, _safepoint: u64) -> Result<bool> {
+        Ok(false)
+    }
+
+    /// Without PD there is no GC safepoint to hold back, so `safepoint` is reported as the
+    /// minimum.
+    async fn update_service_safepoint(
+        self: Arc<Self>,
+        _service_id: String,
+        _ttl: i64,
+        safepoint: u64,
+    ) -> Result<u64> {
+        Ok(safepoint)
+    }
+
+    async fn update_leader(&self, _ver_id: RegionVerId, _leader: metapb::Peer) -> Result<()> {
+        Ok(())
+    }
+
+    async fn invalidate_region_cache(&self, _ver_id: RegionVerId) {}
+
+    async fn invalidate_region_cache_range(&self, _range: BoundRange) -> usize {
+        0
+    }
+
+    async fn region_cache_stats(&self) -> RegionCacheStats {
+        RegionCacheStats::default()
+    }
+}
+
+/// The region covering all keys, led by the single store.
+fn single_region() -> RegionWithLeader {
+    let mut region = RegionWithLeader::default();
+    region.region.id = SINGLE_NODE_ID;
+    region.region.region_epoch = Some(metapb::RegionEpoch {
+        conf_ver: 1,
+        version: 1,
+    });
+    let peer = metapb::Peer {
+        id: SINGLE_NODE_ID,
+        store_id: SINGLE_NODE_ID,
+        ..Default::default()
+    };
+    region.region.peers = vec![peer.clone()];
+    region.leader = Some(peer);
+    region
+}
+
+/// Issues increasing timestamps from the system clock, in place of PD's timestamp oracle.
+#[derive(Default)]
+struct StubOracle {
+    /// The last timestamp issued, as (physical, logical).
+    last: Mutex<(i64, i64)>,
+}
+
+impl StubOracle {
+    fn next(&self) -> Timestamp {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as i64;
+        let mut last = self.last.lock().unwrap();
+        *last = if now > last.0 {
+            (now, 0)
+        } else if last.1 + 1 < 1 << LOGICAL_BITS {
+            (last.0, last.1 + 1)
+        } else {
+            // The logical part is exhausted, so borrow the next millisecond.
+            (last.0 + 1, 0)
+        };
+        Timestamp {
+            physical: last.0,
+            logical: last.1,
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::timestamp::TimestampExt;
+
+    #[test]
+    fn test_stub_oracle() {
+        let oracle = StubOracle::default();
+        let mut last = oracle.next().version();
+        for _ in 0..1000 {
+            let version = oracle.next().version();
+            assert!(version > last);
+            last = version;
+        }
+
+        *oracle.last.lock().unwrap() = (i64::MAX >> 20, (1 << LOGICAL_BITS) - 1);
+        let ts = oracle.next();
+        assert_eq!((ts.physical, ts.logical), ((i64::MAX >> 20) + 1, 0));
+    }
+
+    #[test]
+    fn test_single_region() {
+        let region = single_region();
+        assert!(region.contains(&Key::from(vec![])));
+        assert!(region.contains(&Key::from(vec![255; 8])));
+        assert_eq!(region.get_store_id().unwrap(), SINGLE_NODE_ID);
+    }
+}
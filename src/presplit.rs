@@ -0,0 +1,140 @@
+// Copyright 2023 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Choosing split keys for pre-splitting a range before a bulk load.
+
+use std::sync::Arc;
+
+use crate::pd::PdClient;
+use crate::pd::PdRpcClient;
+use crate::BoundRange;
+use crate::Key;
+use crate::Result;
+
+// How many bytes after the common prefix of the range's bounds are used to interpolate split keys.
+const INTERPOLATION_BYTES: usize = 8;
+
+/// Split keys which divide `range` into `shard_count` shards of evenly spaced keys.
+///
+/// Keys are interpolated as if they were fractions, so this suits ranges whose keys are evenly
+/// distributed, e.g., random or hashed ids. Fewer keys are returned if the range is too narrow to
+/// be divided into `shard_count` shards.
+pub(crate) fn even_split_keys(range: BoundRange, shard_count: usize) -> Vec<Key> {
+    let (start, end) = range.into_keys();
+    let start: Vec<u8> = start.into();
+    let end: Option<Vec<u8>> = end.map(Into::into);
+    if shard_count <= 1 {
+        return Vec::new();
+    }
+
+    let prefix_len = match &end {
+        Some(end) => start
+            .iter()
+            .zip(end)
+            .take_while(|(start, end)| start == end)
+            .count(),
+        None => 0,
+    };
+    let low = interpolation_value(&start[prefix_len..]);
+    let high = match &end {
+        Some(end) => interpolation_value(&end[prefix_len..]),
+        None => 1 << (INTERPOLATION_BYTES * 8),
+    };
+    if high <= low {
+        return Vec::new();
+    }
+
+    let mut keys: Vec<Vec<u8>> = Vec::with_capacity(shard_count - 1);
+    for i in 1..shard_count {
+        let value = low + (high - low) * i as u128 / shard_count as u128;
+        let mut key = start[..prefix_len].to_vec();
+        key.extend_from_slice(&value.to_be_bytes()[16 - INTERPOLATION_BYTES..]);
+        // Trailing zeros only make the key longer; removing them keeps it above `start`.
+        while key.len() > prefix_len + 1 && key.last() == Some(&0) {
+            key.pop();
+        }
+        let in_range = key > start && end.as_ref().is_none_or(|end| &key < end);
+        if in_range && keys.last().is_none_or(|last| last < &key) {
+            keys.push(key);
+        }
+    }
+    keys.into_iter().map(Into::into).collect()
+}
+
+/// Split keys which divide sorted `samples` of a range's keys into `shard_count` shards with
+/// roughly the same number of keys.
+pub(crate) fn sampled_split_keys(samples: Vec<Key>, shard_count: usize) -> Vec<Key> {
+    if samples.is_empty() {
+        return Vec::new();
+    }
+    let mut keys: Vec<Key> = Vec::with_capacity(shard_count.saturating_sub(1));
+    for i in 1..shard_count {
+        let key = &samples[i * samples.len() / shard_count];
+        if keys.last().is_none_or(|last| last < key) && Some(key) != samples.first() {
+            keys.push(key.clone());
+        }
+    }
+    keys
+}
+
+/// Split the regions of `range` at `split_keys` and scatter them, then drop the range's stale
+/// regions from the region cache. Returns `split_keys`.
+pub(crate) async fn split_and_scatter(
+    pd: &Arc<PdRpcClient>,
+    range: BoundRange,
+    split_keys: Vec<Key>,
+) -> Result<Vec<Key>> {
+    if split_keys.is_empty() {
+        return Ok(split_keys);
+    }
+    pd.split_and_scatter_regions(split_keys.clone()).await?;
+    pd.invalidate_region_cache_range(range).await;
+    Ok(split_keys)
+}
+
+fn interpolation_value(key: &[u8]) -> u128 {
+    let mut bytes = [0; 16];
+    let len = key.len().min(INTERPOLATION_BYTES);
+    bytes[16 - INTERPOLATION_BYTES..16 - INTERPOLATION_BYTES + len].copy_from_slice(&key[..len]);
+    u128::from_be_bytes(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_even_split_keys() {
+        let keys = even_split_keys((..).into(), 4);
+        assert_eq!(
+            keys,
+            vec![
+                Key::from(vec![0x40u8]),
+                vec![0x80u8].into(),
+                vec![0xc0u8].into()
+            ]
+        );
+
+        let keys = even_split_keys((b"user0".to_vec()..b"user9".to_vec()).into(), 3);
+        assert_eq!(keys.len(), 2);
+        assert!(keys
+            .iter()
+            .all(|key| key.len() >= 5 && key > &Key::from(b"user0".to_vec())));
+        assert!(keys[0] < keys[1] && keys[1] < Key::from(b"user9".to_vec()));
+
+        // Too narrow to split.
+        assert!(even_split_keys((b"a".to_vec()..b"a\0".to_vec()).into(), 4).is_empty());
+        assert!(even_split_keys((b"a".to_vec()..b"b".to_vec()).into(), 1).is_empty());
+    }
+
+    #[test]
+    fn test_sampled_split_keys() {
+        let samples = (0..10u8).map(|i| Key::from(vec![i])).collect::<Vec<_>>();
+        assert_eq!(
+            sampled_split_keys(samples.clone(), 2),
+            vec![Key::from(vec![5])]
+        );
+        assert_eq!(sampled_split_keys(samples.clone(), 20).len(), 9);
+        assert!(sampled_split_keys(samples[..1].to_vec(), 4).is_empty());
+        assert!(sampled_split_keys(Vec::new(), 4).is_empty());
+    }
+}
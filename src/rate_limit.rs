@@ -0,0 +1,376 @@
+// Copyright 2023 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Client-side throttling of requests sent to TiKV.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use futures::prelude::*;
+use serde_derive::Deserialize;
+use serde_derive::Serialize;
+use tokio::sync::OwnedSemaphorePermit;
+use tokio::sync::Semaphore;
+
+use crate::BoundRange;
+use crate::Clock;
+use crate::KvPair;
+use crate::Result;
+
+/// How many keys a paced scan reads at a time, unless it is limited to fewer keys per second.
+const PACED_SCAN_BATCH_SIZE: u32 = 256;
+
+/// Limits on the rate at which a client sends requests to TiKV.
+///
+/// The limits apply to every request sent by the client, including retries. Each limit allows
+/// bursts of up to one second's worth of traffic. A request which would exceed a limit is delayed
+/// until it fits, rather than failed.
+///
+/// # Examples
+/// ```rust
+/// # use tikv_client::{Config, RateLimit};
+/// let config = Config::default().with_rate_limit(
+///     RateLimit::default()
+///         .requests_per_second(1000)
+///         .bytes_per_second(16 * 1024 * 1024),
+/// );
+/// ```
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+#[serde(rename_all = "kebab-case")]
+pub struct RateLimit {
+    pub requests_per_second: Option<u64>,
+    pub bytes_per_second: Option<u64>,
+}
+
+impl RateLimit {
+    /// Limit the number of requests sent per second.
+    #[must_use]
+    pub fn requests_per_second(mut self, requests: u64) -> Self {
+        self.requests_per_second = Some(requests);
+        self
+    }
+
+    /// Limit the number of request bytes sent per second.
+    #[must_use]
+    pub fn bytes_per_second(mut self, bytes: u64) -> Self {
+        self.bytes_per_second = Some(bytes);
+        self
+    }
+}
+
+/// Token buckets enforcing a [`RateLimit`], shared by all requests of a client.
+pub struct RateLimiter {
+    requests: Option<Mutex<TokenBucket>>,
+    bytes: Option<Mutex<TokenBucket>>,
+}
+
+impl RateLimiter {
+    /// Returns `None` if `limit` does not limit anything.
+    pub fn new(limit: &RateLimit) -> Option<RateLimiter> {
+        let requests = limit
+            .requests_per_second
+            .map(|rate| Mutex::new(TokenBucket::new(rate)));
+        let bytes = limit
+            .bytes_per_second
+            .map(|rate| Mutex::new(TokenBucket::new(rate)));
+        if requests.is_none() && bytes.is_none() {
+            return None;
+        }
+        Some(RateLimiter { requests, bytes })
+    }
+
+    /// Wait until a request of `bytes` bytes may be sent.
+    pub async fn acquire(&self, bytes: usize) {
+        let now = Instant::now();
+        let mut delay = Duration::ZERO;
+        if let Some(bucket) = &self.requests {
+            delay = delay.max(bucket.lock().unwrap().take(1, now));
+        }
+        if let Some(bucket) = &self.bytes {
+            delay = delay.max(bucket.lock().unwrap().take(bytes as u64, now));
+        }
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+/// Caps the requests a client has in flight to each TiKV store, shared by all requests of a
+/// client.
+pub struct InFlightLimiter {
+    limit: usize,
+    stores: Mutex<HashMap<String, Arc<Semaphore>>>,
+}
+
+impl InFlightLimiter {
+    pub fn new(limit: usize) -> InFlightLimiter {
+        InFlightLimiter {
+            limit: limit.max(1),
+            stores: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Wait until a request may be sent to the store at `address`. The request is in flight until
+    /// the returned permit is dropped.
+    pub async fn acquire(&self, address: &str) -> OwnedSemaphorePermit {
+        let semaphore = self
+            .stores
+            .lock()
+            .unwrap()
+            .entry(address.to_owned())
+            .or_insert_with(|| Arc::new(Semaphore::new(self.limit)))
+            .clone();
+        semaphore
+            .acquire_owned()
+            .await
+            .expect("Unreachable: the semaphore is never closed")
+    }
+}
+
+/// Limits on the rate at which a paced scan, such as
+/// [`RawClient::scan_paced`](crate::RawClient::scan_paced), reads from TiKV.
+///
+/// Unlike a [`RateLimit`], which applies to every request of a client, pacing applies to a single
+/// scan, so that a long-running maintenance scan can be slowed down without affecting the rest of
+/// the client's traffic. Each limit allows bursts of up to one second's worth of reads. The default
+/// is unlimited.
+///
+/// # Examples
+/// ```rust
+/// # use tikv_client::ScanPacing;
+/// let pacing = ScanPacing::default()
+///     .keys_per_second(10_000)
+///     .bytes_per_second(4 * 1024 * 1024);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ScanPacing {
+    pub keys_per_second: Option<u64>,
+    pub bytes_per_second: Option<u64>,
+}
+
+impl ScanPacing {
+    /// Limit the number of keys read per second.
+    #[must_use]
+    pub fn keys_per_second(mut self, keys: u64) -> Self {
+        self.keys_per_second = Some(keys);
+        self
+    }
+
+    /// Limit the number of key and value bytes read per second.
+    #[must_use]
+    pub fn bytes_per_second(mut self, bytes: u64) -> Self {
+        self.bytes_per_second = Some(bytes);
+        self
+    }
+
+    /// How many keys to read at a time, so that a batch doesn't use more than a second's worth of
+    /// the key limit.
+    fn batch_size(&self) -> u32 {
+        match self.keys_per_second {
+            Some(keys) => (keys.clamp(1, PACED_SCAN_BATCH_SIZE as u64)) as u32,
+            None => PACED_SCAN_BATCH_SIZE,
+        }
+    }
+}
+
+/// Token buckets enforcing a [`ScanPacing`], owned by a single scan.
+struct Pacer {
+    keys: Option<TokenBucket>,
+    bytes: Option<TokenBucket>,
+}
+
+impl Pacer {
+    fn new(pacing: &ScanPacing, now: Instant) -> Pacer {
+        let bucket = |rate| {
+            let mut bucket = TokenBucket::new(rate);
+            bucket.last_refill = now;
+            bucket
+        };
+        Pacer {
+            keys: pacing.keys_per_second.map(bucket),
+            bytes: pacing.bytes_per_second.map(bucket),
+        }
+    }
+
+    /// Record that `pairs` were read at `now`, returning how long to wait before reading more.
+    fn take(&mut self, pairs: &[KvPair], now: Instant) -> Duration {
+        let mut delay = Duration::ZERO;
+        if let Some(bucket) = &mut self.keys {
+            delay = delay.max(bucket.take(pairs.len() as u64, now));
+        }
+        if let Some(bucket) = &mut self.bytes {
+            let bytes: usize = pairs
+                .iter()
+                .map(|pair| pair.key().len() + pair.value().len())
+                .sum();
+            delay = delay.max(bucket.take(bytes as u64, now));
+        }
+        delay
+    }
+}
+
+/// Scan `range` in batches, read by `scan`, waiting between batches as `pacing` requires.
+///
+/// `scan` is passed `state`, the range left to scan and the number of pairs to read, and returns
+/// `state` along with the pairs. The stream ends after the first error.
+pub(crate) fn paced_scan<S, F, Fut>(
+    state: S,
+    range: BoundRange,
+    pacing: ScanPacing,
+    clock: Arc<dyn Clock>,
+    mut scan: F,
+) -> impl Stream<Item = Result<KvPair>>
+where
+    F: FnMut(S, BoundRange, u32) -> Fut,
+    Fut: Future<Output = (S, Result<Vec<KvPair>>)>,
+{
+    let (start, end) = range.into_keys();
+    let limit = pacing.batch_size();
+    let pacer = Pacer::new(&pacing, clock.now());
+    let initial = (state, Some(start), Duration::ZERO, pacer);
+    stream::unfold(initial, move |(state, start, delay, mut pacer)| {
+        let start = start.map(|start| (start, end.clone()));
+        let clock = clock.clone();
+        let batch = start.map(|range| scan(state, range.into(), limit));
+        async move {
+            let batch = batch?;
+            if !delay.is_zero() {
+                clock.sleep(delay).await;
+            }
+            let (state, pairs) = batch.await;
+            let pairs = match pairs {
+                Ok(pairs) => pairs,
+                Err(e) => return Some((Err(e), (state, None, Duration::ZERO, pacer))),
+            };
+            let next = match pairs.last() {
+                Some(last) if pairs.len() as u32 == limit => {
+                    let mut next = last.key().clone();
+                    next.push_zero();
+                    Some(next)
+                }
+                _ => None,
+            };
+            let delay = pacer.take(&pairs, clock.now());
+            Some((Ok(pairs), (state, next, delay, pacer)))
+        }
+    })
+    .map_ok(|pairs| stream::iter(pairs.into_iter().map(Ok)))
+    .try_flatten()
+}
+
+struct TokenBucket {
+    rate: f64,
+    // May be negative, in which case requests have been admitted ahead of the tokens they need.
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: u64) -> TokenBucket {
+        let rate = rate.max(1) as f64;
+        TokenBucket {
+            rate,
+            tokens: rate,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Take `amount` tokens, returning how long the caller must wait before the tokens are
+    /// available.
+    fn take(&mut self, amount: u64, now: Instant) -> Duration {
+        let elapsed = now.saturating_duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.last_refill = now;
+        self.tokens -= amount as f64;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::pin_mut;
+
+    use super::*;
+    use crate::MockClock;
+
+    #[test]
+    fn test_token_bucket() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(10);
+        bucket.last_refill = start;
+        for _ in 0..10 {
+            assert_eq!(bucket.take(1, start), Duration::ZERO);
+        }
+        assert_eq!(bucket.take(5, start), Duration::from_millis(500));
+        // Tokens borrowed by a delayed request are repaid before later requests are admitted.
+        assert_eq!(
+            bucket.take(1, start + Duration::from_millis(500)),
+            Duration::from_millis(100)
+        );
+        // Idle time refills the bucket, but only up to one second's worth of tokens.
+        assert_eq!(bucket.take(10, start + Duration::from_secs(10)), Duration::ZERO);
+        assert!(!bucket.take(1, start + Duration::from_secs(10)).is_zero());
+    }
+
+    #[test]
+    fn test_unlimited() {
+        assert!(RateLimiter::new(&RateLimit::default()).is_none());
+        assert!(RateLimiter::new(&RateLimit::default().bytes_per_second(100)).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_in_flight_limiter() {
+        let limiter = InFlightLimiter::new(2);
+        let first = limiter.acquire("a").await;
+        let _second = limiter.acquire("a").await;
+        let third = limiter.acquire("a");
+        pin_mut!(third);
+        assert!(futures::poll!(third.as_mut()).is_pending());
+        // Other stores have limits of their own.
+        let _other = limiter.acquire("b").await;
+
+        drop(first);
+        assert!(futures::poll!(third.as_mut()).is_ready());
+    }
+
+    #[tokio::test]
+    async fn test_paced_scan() {
+        let clock = Arc::new(MockClock::new());
+        let pairs: Vec<KvPair> = (0..10u8).map(|i| (vec![i], vec![i]).into()).collect();
+        let pacing = ScanPacing::default().keys_per_second(4);
+        let scan = |batches: usize, range: BoundRange, limit: u32| {
+            let (start, end) = range.into_keys();
+            let batch = pairs
+                .iter()
+                .filter(|pair| pair.key() >= &start)
+                .filter(|pair| end.as_ref().is_none_or(|end| pair.key() < end))
+                .take(limit as usize)
+                .cloned()
+                .collect();
+            future::ready((batches + 1, Ok(batch)))
+        };
+        let scanned = paced_scan(0, (..).into(), pacing, clock.clone(), scan);
+        pin_mut!(scanned);
+
+        // The first second's worth of keys is read at once, the next is read ahead of its tokens,
+        // and then the scan waits for the tokens to be repaid.
+        for i in 0..8 {
+            let pair = scanned.next().await.unwrap().unwrap();
+            assert_eq!(pair.key(), &vec![i].into());
+        }
+        let mut next = scanned.next();
+        assert!(futures::poll!(&mut next).is_pending());
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(next.await.unwrap().unwrap().key(), &vec![8].into());
+        assert!(scanned.next().await.unwrap().is_ok());
+        assert!(scanned.next().await.is_none());
+    }
+}
@@ -0,0 +1,201 @@
+// Copyright 2023 TiKV Project Authors. Licensed under Apache-2.0.
+
+use std::mem;
+use std::sync::Arc;
+use std::sync::Weak;
+use std::time::Duration;
+
+use futures::future::abortable;
+use futures::future::AbortHandle;
+use futures::FutureExt;
+use tokio::sync::Mutex;
+
+use crate::pd::PdClient;
+use crate::pd::PdRpcClient;
+use crate::raw::Client;
+use crate::spawner::spawn;
+use crate::Error;
+use crate::Key;
+use crate::KvPair;
+use crate::Result;
+use crate::Value;
+
+const DEFAULT_MAX_BATCH_PAIRS: usize = 1024;
+const DEFAULT_MAX_BATCH_BYTES: usize = 1024 * 1024;
+const DEFAULT_FLUSH_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Buffers raw puts and writes them in batches, to amortize the cost of each request.
+///
+/// Buffered pairs are written when the buffer reaches a number of pairs or bytes, when the flush
+/// interval elapses, or when [`flush`](BufferedWriter::flush) is called. Batches are written in
+/// the order they were buffered, one at a time.
+///
+/// A failed background write is reported by the next call to [`put`](BufferedWriter::put) or
+/// [`flush`](BufferedWriter::flush); the failed pairs are not retried. Pairs still buffered when
+/// the writer is dropped are written in the background, and any error is lost, so call
+/// [`flush`](BufferedWriter::flush) before dropping the writer.
+///
+/// Create one with [`RawClient::buffered_writer`](crate::RawClient::buffered_writer).
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// # use tikv_client::{Config, RawClient};
+/// # use std::time::Duration;
+/// # use futures::prelude::*;
+/// # futures::executor::block_on(async {
+/// let client = RawClient::new(vec!["192.168.0.100"], None).await.unwrap();
+/// let mut writer = client
+///     .buffered_writer()
+///     .max_batch_pairs(512)
+///     .flush_interval(Duration::from_millis(50));
+/// for i in 0..10_000u32 {
+///     writer.put(i.to_be_bytes().to_vec(), b"sample".to_vec()).await.unwrap();
+/// }
+/// writer.flush().await.unwrap();
+/// # });
+/// ```
+pub struct BufferedWriter<PdC: PdClient = PdRpcClient> {
+    client: Arc<Client<PdC>>,
+    buffer: Arc<Mutex<Buffer>>,
+    max_batch_pairs: usize,
+    max_batch_bytes: usize,
+    flush_interval: Duration,
+    flusher: Option<AbortHandle>,
+}
+
+#[derive(Default)]
+struct Buffer {
+    pairs: Vec<KvPair>,
+    bytes: usize,
+    error: Option<Error>,
+}
+
+impl<PdC: PdClient> BufferedWriter<PdC> {
+    pub(crate) fn new(client: Client<PdC>) -> BufferedWriter<PdC> {
+        BufferedWriter {
+            client: Arc::new(client),
+            buffer: Default::default(),
+            max_batch_pairs: DEFAULT_MAX_BATCH_PAIRS,
+            max_batch_bytes: DEFAULT_MAX_BATCH_BYTES,
+            flush_interval: DEFAULT_FLUSH_INTERVAL,
+            flusher: None,
+        }
+    }
+
+    /// Set the number of buffered pairs which triggers a write.
+    ///
+    /// The default is 1024.
+    #[must_use]
+    pub fn max_batch_pairs(mut self, max_batch_pairs: usize) -> Self {
+        self.max_batch_pairs = max_batch_pairs.max(1);
+        self
+    }
+
+    /// Set the total size of buffered keys and values which triggers a write.
+    ///
+    /// The default is 1 MiB.
+    #[must_use]
+    pub fn max_batch_bytes(mut self, max_batch_bytes: usize) -> Self {
+        self.max_batch_bytes = max_batch_bytes;
+        self
+    }
+
+    /// Set how often buffered pairs are written in the background.
+    ///
+    /// The default is 100 milliseconds.
+    #[must_use]
+    pub fn flush_interval(mut self, flush_interval: Duration) -> Self {
+        self.flush_interval = flush_interval;
+        self
+    }
+
+    /// Buffer a put, writing the buffer if it is full.
+    ///
+    /// Returns the error of a failed background write, if there was one since the last call to
+    /// `put` or [`flush`](BufferedWriter::flush).
+    pub async fn put(&mut self, key: impl Into<Key>, value: impl Into<Value>) -> Result<()> {
+        self.start_flusher();
+        let pair = KvPair::new(key, value);
+        let mut buffer = self.buffer.lock().await;
+        if let Some(e) = buffer.error.take() {
+            return Err(e);
+        }
+        buffer.bytes += pair.key().len() + pair.value().len();
+        buffer.pairs.push(pair);
+        if buffer.pairs.len() >= self.max_batch_pairs || buffer.bytes >= self.max_batch_bytes {
+            buffer.write(&self.client).await?;
+        }
+        Ok(())
+    }
+
+    /// Write all buffered pairs.
+    ///
+    /// Returns the error of a failed background write, if there was one since the last call to
+    /// [`put`](BufferedWriter::put) or `flush`.
+    pub async fn flush(&mut self) -> Result<()> {
+        let mut buffer = self.buffer.lock().await;
+        if let Some(e) = buffer.error.take() {
+            return Err(e);
+        }
+        buffer.write(&self.client).await
+    }
+
+    fn start_flusher(&mut self) {
+        if self.flusher.is_some() {
+            return;
+        }
+        let client = self.client.clone();
+        let buffer = Arc::downgrade(&self.buffer);
+        let flush_interval = self.flush_interval;
+        let (flusher, abort_handle) = abortable(flush_periodically(client, buffer, flush_interval));
+        spawn(self.client.rpc.as_ref(), flusher.map(|_| ()));
+        self.flusher = Some(abort_handle);
+    }
+}
+
+impl<PdC: PdClient> Drop for BufferedWriter<PdC> {
+    fn drop(&mut self) {
+        if let Some(flusher) = self.flusher.take() {
+            flusher.abort();
+        }
+        let client = self.client.clone();
+        let buffer = self.buffer.clone();
+        let write = async move {
+            let _ = buffer.lock().await.write(&client).await;
+        };
+        // Without a spawner, the pairs can only be written from within a runtime.
+        if self.client.rpc.spawner().is_some() || tokio::runtime::Handle::try_current().is_ok() {
+            spawn(self.client.rpc.as_ref(), write);
+        }
+    }
+}
+
+impl Buffer {
+    async fn write<PdC: PdClient>(&mut self, client: &Client<PdC>) -> Result<()> {
+        if self.pairs.is_empty() {
+            return Ok(());
+        }
+        let pairs = mem::take(&mut self.pairs);
+        self.bytes = 0;
+        client.batch_put(pairs).await
+    }
+}
+
+async fn flush_periodically<PdC: PdClient>(
+    client: Arc<Client<PdC>>,
+    buffer: Weak<Mutex<Buffer>>,
+    flush_interval: Duration,
+) {
+    loop {
+        tokio::time::sleep(flush_interval).await;
+        let buffer = match buffer.upgrade() {
+            Some(buffer) => buffer,
+            None => return,
+        };
+        let mut buffer = buffer.lock().await;
+        if let Err(e) = buffer.write(&client).await {
+            buffer.error.get_or_insert(e);
+        }
+    }
+}
@@ -1,32 +1,61 @@
 // Copyright 2019 TiKV Project Authors. Licensed under Apache-2.0.
 
 use core::ops::Range;
+use std::cmp::max;
+use std::cmp::min;
+use std::collections::BTreeMap;
 use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
 use std::u32;
 
-use slog::Drain;
+use futures::stream;
+use futures::Stream;
+use futures::StreamExt;
+use futures::TryStreamExt;
 use slog::Logger;
 use tikv_client_common::Error;
 use tikv_client_proto::metapb;
 
+use crate::audit::AuditOperation;
+use crate::audit::AuditOutcome;
 use crate::backoff::DEFAULT_REGION_BACKOFF;
 use crate::config::Config;
+use crate::keyspace::Keyspaces;
 use crate::pd::PdClient;
 use crate::pd::PdRpcClient;
+use crate::pd::SingleNodePdClient;
+use crate::presplit;
+use crate::rate_limit::paced_scan;
 use crate::raw::lowering::*;
+use crate::raw::BufferedWriter;
+use crate::raw::NamespacedClient;
+use crate::raw::PrefixedClient;
+use crate::raw::RawMutation;
+use crate::region::RegionInfo;
+use crate::region_cache::RegionCacheStats;
+use crate::request::plan::MULTI_REGION_CONCURRENCY;
 use crate::request::Collect;
 use crate::request::CollectSingle;
 use crate::request::Plan;
+use crate::runtime_config::client_logger;
+use crate::value_format::ValueFormat;
 use crate::Backoff;
 use crate::BoundRange;
 use crate::ColumnFamily;
+use crate::ConfigUpdate;
+use crate::FanOutTracer;
+use crate::HotKey;
 use crate::Key;
 use crate::KvPair;
+use crate::Namespace;
 use crate::Result;
+use crate::ScanPacing;
 use crate::Value;
 
 const MAX_RAW_KV_SCAN_LIMIT: u32 = 10240;
+const DELETE_PREFIX_BATCH_SIZE: u32 = 1024;
 
 /// The TiKV raw `Client` is used to interact with TiKV using raw requests.
 ///
@@ -36,19 +65,22 @@ const MAX_RAW_KV_SCAN_LIMIT: u32 = 10240;
 /// The returned results of raw request methods are [`Future`](std::future::Future)s that must be
 /// awaited to execute.
 pub struct Client<PdC: PdClient = PdRpcClient> {
-    rpc: Arc<PdC>,
+    pub(super) rpc: Arc<PdC>,
     cf: Option<ColumnFamily>,
     /// Whether to use the [`atomic mode`](Client::with_atomic_for_cas).
     atomic: bool,
+    /// Where to record the regions requests are sent to, if anywhere.
+    tracer: Option<FanOutTracer>,
     logger: Logger,
 }
 
-impl Clone for Client {
+impl<PdC: PdClient> Clone for Client<PdC> {
     fn clone(&self) -> Self {
         Self {
             rpc: self.rpc.clone(),
             cf: self.cf.clone(),
             atomic: self.atomic,
+            tracer: self.tracer.clone(),
             logger: self.logger.clone(),
         }
     }
@@ -83,6 +115,10 @@ impl Client<PdRpcClient> {
     /// PD must be provided, not the TiKV nodes. It's important to include more than one PD endpoint
     /// (include all endpoints, if possible), this helps avoid having a single point of failure.
     ///
+    /// Raw clients don't encode values, so a configuration with
+    /// [compression](Config::with_compression), a [value cipher](Config::with_value_cipher) or
+    /// [value checksums](Config::with_value_checksum) is rejected.
+    ///
     /// # Examples
     ///
     /// ```rust,no_run
@@ -104,27 +140,181 @@ impl Client<PdRpcClient> {
         config: Config,
         optional_logger: Option<Logger>,
     ) -> Result<Self> {
-        let logger = optional_logger.unwrap_or_else(|| {
-            let plain = slog_term::PlainSyncDecorator::new(std::io::stdout());
-            Logger::root(
-                slog_term::FullFormat::new(plain)
-                    .build()
-                    .filter_level(slog::Level::Info)
-                    .fuse(),
-                o!(),
-            )
-        });
+        if ValueFormat::new(&config).is_some() {
+            return Err(Error::StringError(
+                "compression, value ciphers and value checksums are only supported by TransactionClient"
+                    .to_owned(),
+            ));
+        }
+        let (logger, log_level) = client_logger(optional_logger);
         debug!(logger, "creating new raw client");
         let pd_endpoints: Vec<String> = pd_endpoints.into_iter().map(Into::into).collect();
-        let rpc =
-            Arc::new(PdRpcClient::connect(&pd_endpoints, config, false, logger.clone()).await?);
+        let rpc = PdRpcClient::connect(&pd_endpoints, config, false, logger.clone()).await?;
+        let rpc = Arc::new(rpc.with_log_level(log_level));
         Ok(Client {
             rpc,
             cf: None,
             atomic: false,
+            tracer: None,
+            logger,
+        })
+    }
+
+    /// Manage the cluster's keyspaces.
+    pub fn keyspaces(&self) -> Keyspaces {
+        Keyspaces::new(self.rpc.clone())
+    }
+
+    /// Change settings of the client without reconnecting. Settings which `update` leaves unset
+    /// are kept.
+    ///
+    /// The change applies to the requests sent afterwards, including those of clones of the
+    /// client.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use tikv_client::{ConfigUpdate, RawClient};
+    /// # use futures::prelude::*;
+    /// # use std::time::Duration;
+    /// # futures::executor::block_on(async {
+    /// let client = RawClient::new(vec!["192.168.0.100"], None).await.unwrap();
+    /// client.update_config(
+    ///     ConfigUpdate::default()
+    ///         .timeout(Duration::from_secs(5))
+    ///         .log_level(slog::Level::Debug),
+    /// );
+    /// # });
+    /// ```
+    pub fn update_config(&self, update: ConfigUpdate) {
+        self.rpc.update_config(&update);
+    }
+
+    /// Split `range` into `target_shard_count` regions with evenly spaced boundaries, and scatter
+    /// the new regions across stores. Returns the keys the range was split at.
+    ///
+    /// Use this before loading a lot of data into an empty range, so that the load is spread over
+    /// the cluster from the start. Boundaries are interpolated between the bounds of the range,
+    /// which suits evenly distributed keys; otherwise use
+    /// [`presplit_range_by_sample`](Client::presplit_range_by_sample).
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use tikv_client::{Config, RawClient};
+    /// # use futures::prelude::*;
+    /// # futures::executor::block_on(async {
+    /// let client = RawClient::new(vec!["192.168.0.100"], None).await.unwrap();
+    /// let split_keys = client
+    ///     .presplit_range("user0".to_owned().."user9".to_owned(), 16)
+    ///     .await
+    ///     .unwrap();
+    /// # });
+    /// ```
+    pub async fn presplit_range(
+        &self,
+        range: impl Into<BoundRange>,
+        target_shard_count: usize,
+    ) -> Result<Vec<Key>> {
+        debug!(self.logger, "invoking raw presplit_range request");
+        let range = range.into();
+        let split_keys = presplit::even_split_keys(range.clone(), target_shard_count);
+        presplit::split_and_scatter(&self.rpc, range, split_keys).await
+    }
+
+    /// Split `range` into `target_shard_count` regions holding roughly the same number of keys,
+    /// and scatter the new regions across stores. Returns the keys the range was split at.
+    ///
+    /// Boundaries are chosen from the first `sample_limit` existing keys in the range.
+    pub async fn presplit_range_by_sample(
+        &self,
+        range: impl Into<BoundRange>,
+        target_shard_count: usize,
+        sample_limit: u32,
+    ) -> Result<Vec<Key>> {
+        debug!(self.logger, "invoking raw presplit_range_by_sample request");
+        let range = range.into();
+        let samples = self.scan_keys(range.clone(), sample_limit).await?;
+        let split_keys = presplit::sampled_split_keys(samples, target_shard_count);
+        presplit::split_and_scatter(&self.rpc, range, split_keys).await
+    }
+}
+
+impl Client<SingleNodePdClient> {
+    /// Create a raw [`Client`] which talks to a single TiKV instance at `address`, without PD.
+    ///
+    /// This is a development mode, for running examples and tests against one TiKV instance, e.g.,
+    /// a mock, rather than a full cluster. The instance must serve a single region, with id 1,
+    /// which covers all keys. See [`SingleNodePdClient`] for the limitations of the mode.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use tikv_client::RawClient;
+    /// # use futures::prelude::*;
+    /// # futures::executor::block_on(async {
+    /// let client = RawClient::connect_single_node("127.0.0.1:20160").await.unwrap();
+    /// client.put("key".to_owned(), "value".to_owned()).await.unwrap();
+    /// # });
+    /// ```
+    pub async fn connect_single_node(address: impl Into<String>) -> Result<Self> {
+        Self::connect_single_node_with_config(address, Config::default()).await
+    }
+
+    /// Create a raw [`Client`] which talks to a single TiKV instance at `address`, without PD,
+    /// configured by `config`.
+    ///
+    /// See [`connect_single_node`](Client::connect_single_node). Settings which only concern PD,
+    /// such as the PD timeout and retry budget, are ignored.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use tikv_client::{Config, RawClient};
+    /// # use std::time::Duration;
+    /// # use futures::prelude::*;
+    /// # futures::executor::block_on(async {
+    /// let client = RawClient::connect_single_node_with_config(
+    ///     "127.0.0.1:20160",
+    ///     Config::default().with_timeout(Duration::from_secs(5)),
+    /// )
+    /// .await
+    /// .unwrap();
+    /// # });
+    /// ```
+    pub async fn connect_single_node_with_config(
+        address: impl Into<String>,
+        config: Config,
+    ) -> Result<Self> {
+        if ValueFormat::new(&config).is_some() {
+            return Err(Error::StringError(
+                "compression, value ciphers and value checksums are only supported by TransactionClient"
+                    .to_owned(),
+            ));
+        }
+        let logger = Logger::root(slog::Discard, o!());
+        let rpc = SingleNodePdClient::connect(address.into(), &config).await?;
+        Ok(Client {
+            rpc: Arc::new(rpc),
+            cf: None,
+            atomic: false,
+            tracer: None,
             logger,
         })
     }
+}
+
+impl<PdC: PdClient> Client<PdC> {
+    #[cfg(any(test, feature = "simulation"))]
+    pub(crate) fn new_with_pd_client(rpc: Arc<PdC>, logger: Logger) -> Client<PdC> {
+        Client {
+            rpc,
+            cf: None,
+            atomic: false,
+            tracer: None,
+            logger,
+        }
+    }
 
     /// Create a new client which is a clone of `self`, but which uses an explicit column family for
     /// all requests.
@@ -156,6 +346,22 @@ impl Client<PdRpcClient> {
             rpc: self.rpc.clone(),
             cf: Some(cf),
             atomic: self.atomic,
+            tracer: self.tracer.clone(),
+            logger: self.logger.clone(),
+        }
+    }
+
+    /// Create a client which records which regions and stores its requests are sent to, with
+    /// the latency and retries of each region, into `tracer`.
+    ///
+    /// See [`FanOutTracer`] for an example.
+    #[must_use]
+    pub fn with_fan_out_tracer(&self, tracer: FanOutTracer) -> Self {
+        Client {
+            rpc: self.rpc.clone(),
+            cf: self.cf.clone(),
+            atomic: self.atomic,
+            tracer: Some(tracer),
             logger: self.logger.clone(),
         }
     }
@@ -173,12 +379,11 @@ impl Client<PdRpcClient> {
             rpc: self.rpc.clone(),
             cf: self.cf.clone(),
             atomic: true,
+            tracer: self.tracer.clone(),
             logger: self.logger.clone(),
         }
     }
-}
 
-impl<PdC: PdClient> Client<PdC> {
     /// Create a new 'get' request.
     ///
     /// Once resolved this request will result in the fetching of the value associated with the
@@ -204,13 +409,26 @@ impl<PdC: PdClient> Client<PdC> {
     /// Same as [`get`](Client::get) but with custom [`backoff`](crate::Backoff) strategy.
     pub async fn get_opt(&self, key: impl Into<Key>, backoff: Backoff) -> Result<Option<Value>> {
         debug!(self.logger, "invoking raw get request");
-        let request = new_raw_get_request(key.into(), self.cf.clone());
+        let key = key.into();
+        let request = new_raw_get_request(key.clone(), self.cf.clone());
         let plan = crate::request::PlanBuilder::new(self.rpc.clone(), request)
+            .trace_fan_out(self.tracer.clone())
             .retry_multi_region(backoff)
             .merge(CollectSingle)
             .post_process_default()
             .plan();
-        plan.execute().await
+        let start = self.rpc.clock().now();
+        let result = plan.execute().await;
+        if let Some(audit) = self.rpc.audit_log() {
+            let outcome = match &result {
+                Ok(Some(_)) => AuditOutcome::Found,
+                Ok(None) => AuditOutcome::NotFound,
+                Err(_) => AuditOutcome::Failed,
+            };
+            audit.record(AuditOperation::Get, &key, outcome);
+        }
+        self.track_hot_keys([&key], start);
+        result
     }
 
     /// Create a new 'batch get' request.
@@ -245,14 +463,86 @@ impl<PdC: PdClient> Client<PdC> {
         backoff: Backoff,
     ) -> Result<Vec<KvPair>> {
         debug!(self.logger, "invoking raw batch_get request");
-        let request = new_raw_batch_get_request(keys.into_iter().map(Into::into), self.cf.clone());
+        let keys: Vec<Key> = keys.into_iter().map(Into::into).collect();
+        let request = new_raw_batch_get_request(keys.clone().into_iter(), self.cf.clone());
         let plan = crate::request::PlanBuilder::new(self.rpc.clone(), request)
+            .trace_fan_out(self.tracer.clone())
             .retry_multi_region(backoff)
             .merge(Collect)
             .plan();
-        plan.execute()
+        let start = self.rpc.clock().now();
+        let result = plan
+            .execute()
             .await
-            .map(|r| r.into_iter().map(Into::into).collect())
+            .map(|r| r.into_iter().map(Into::into).collect());
+        if let Some(audit) = self.rpc.audit_log() {
+            audit.record_reads(AuditOperation::BatchGet, &keys, &result);
+        }
+        self.track_hot_keys(&keys, start);
+        result
+    }
+
+    /// Get the values of `keys` region by region, so that a failure in one region does not fail the
+    /// reads in the others.
+    ///
+    /// The keys are grouped by the region they are in, and each group is read as a
+    /// [`batch_get`](Client::batch_get). Returns each group of keys, in key order, with the pairs
+    /// found or the error its read failed with, so that only the keys of the failed groups need to
+    /// be retried. Duplicate keys are only read once.
+    ///
+    /// Fails as a whole only if the regions of the keys cannot be found.
+    ///
+    /// # Examples
+    /// ```rust,no_run
+    /// # use tikv_client::{Key, Config, RawClient};
+    /// # use futures::prelude::*;
+    /// # futures::executor::block_on(async {
+    /// # let client = RawClient::new(vec!["192.168.0.100"], None).await.unwrap();
+    /// let keys = vec!["TiKV".to_owned(), "TiDB".to_owned()];
+    /// let mut retry: Vec<Key> = Vec::new();
+    /// for (keys, result) in client.try_batch_get(keys).await.unwrap() {
+    ///     match result {
+    ///         Ok(pairs) => println!("found {} of {} keys", pairs.len(), keys.len()),
+    ///         Err(_) => retry.extend(keys),
+    ///     }
+    /// }
+    /// # });
+    /// ```
+    pub async fn try_batch_get(
+        &self,
+        keys: impl IntoIterator<Item = impl Into<Key>>,
+    ) -> Result<Vec<(Vec<Key>, Result<Vec<KvPair>>)>> {
+        self.try_batch_get_opt(keys, DEFAULT_REGION_BACKOFF).await
+    }
+
+    /// Same as [`try_batch_get`](Client::try_batch_get) but with custom [`backoff`](crate::Backoff) strategy.
+    pub async fn try_batch_get_opt(
+        &self,
+        keys: impl IntoIterator<Item = impl Into<Key>>,
+        backoff: Backoff,
+    ) -> Result<Vec<(Vec<Key>, Result<Vec<KvPair>>)>> {
+        debug!(self.logger, "invoking raw try_batch_get request");
+        let mut keys: Vec<Key> = keys.into_iter().map(Into::into).collect();
+        keys.sort();
+        keys.dedup();
+        let groups: Vec<Vec<Key>> = self
+            .rpc
+            .clone()
+            .group_keys_by_region(keys.into_iter())
+            .map_ok(|(_, keys)| keys)
+            .try_collect()
+            .await?;
+        let reads = groups.into_iter().map(|keys| {
+            let backoff = backoff.clone();
+            async move {
+                let result = self.batch_get_opt(keys.clone(), backoff).await;
+                (keys, result)
+            }
+        });
+        Ok(stream::iter(reads)
+            .buffered(MULTI_REGION_CONCURRENCY)
+            .collect()
+            .await)
     }
 
     /// Create a new 'put' request.
@@ -283,13 +573,21 @@ impl<PdC: PdClient> Client<PdC> {
         backoff: Backoff,
     ) -> Result<()> {
         debug!(self.logger, "invoking raw put request");
-        let request = new_raw_put_request(key.into(), value.into(), self.cf.clone(), self.atomic);
+        let key = key.into();
+        let request = new_raw_put_request(key.clone(), value.into(), self.cf.clone(), self.atomic);
         let plan = crate::request::PlanBuilder::new(self.rpc.clone(), request)
+            .trace_fan_out(self.tracer.clone())
             .retry_multi_region(backoff)
             .merge(CollectSingle)
             .extract_error()
             .plan();
-        plan.execute().await?;
+        let start = self.rpc.clock().now();
+        let result = plan.execute().await;
+        if let Some(audit) = self.rpc.audit_log() {
+            audit.record_writes(AuditOperation::Put, [&key], AuditOutcome::of_write(&result));
+        }
+        self.track_hot_keys([&key], start);
+        result?;
         Ok(())
     }
 
@@ -324,19 +622,89 @@ impl<PdC: PdClient> Client<PdC> {
         backoff: Backoff,
     ) -> Result<()> {
         debug!(self.logger, "invoking raw batch_put request");
-        let request = new_raw_batch_put_request(
-            pairs.into_iter().map(Into::into),
-            self.cf.clone(),
-            self.atomic,
-        );
+        let pairs: Vec<KvPair> = pairs.into_iter().map(Into::into).collect();
+        let keys: Vec<Key> = pairs.iter().map(|pair| pair.key().clone()).collect();
+        let request = new_raw_batch_put_request(pairs.into_iter(), self.cf.clone(), self.atomic);
         let plan = crate::request::PlanBuilder::new(self.rpc.clone(), request)
+            .trace_fan_out(self.tracer.clone())
             .retry_multi_region(backoff)
             .extract_error()
             .plan();
-        plan.execute().await?;
+        let start = self.rpc.clock().now();
+        let result = plan.execute().await;
+        if let Some(audit) = self.rpc.audit_log() {
+            audit.record_writes(AuditOperation::Put, &keys, AuditOutcome::of_write(&result));
+        }
+        self.track_hot_keys(&keys, start);
+        result?;
         Ok(())
     }
 
+    /// Put `pairs` region by region, so that a failure in one region does not fail the writes in the
+    /// others.
+    ///
+    /// The pairs are grouped by the region of their keys, and each group is written as a
+    /// [`batch_put`](Client::batch_put). Returns each group of pairs, in key order, with the result
+    /// of its write, so that the pairs of the failed groups can be passed straight back to
+    /// `try_batch_put` to retry them.
+    ///
+    /// Fails as a whole only if the regions of the keys cannot be found, in which case nothing was
+    /// written.
+    ///
+    /// # Examples
+    /// ```rust,no_run
+    /// # use tikv_client::{KvPair, Config, RawClient};
+    /// # use futures::prelude::*;
+    /// # futures::executor::block_on(async {
+    /// # let client = RawClient::new(vec!["192.168.0.100"], None).await.unwrap();
+    /// let mut pairs: Vec<KvPair> = vec![("PD".to_owned(), "Go".to_owned()).into()];
+    /// while !pairs.is_empty() {
+    ///     let written = client.try_batch_put(pairs).await.unwrap();
+    ///     pairs = written
+    ///         .into_iter()
+    ///         .filter(|(_, result)| result.is_err())
+    ///         .flat_map(|(pairs, _)| pairs)
+    ///         .collect();
+    /// }
+    /// # });
+    /// ```
+    pub async fn try_batch_put(
+        &self,
+        pairs: impl IntoIterator<Item = impl Into<KvPair>>,
+    ) -> Result<Vec<(Vec<KvPair>, Result<()>)>> {
+        self.try_batch_put_opt(pairs, DEFAULT_REGION_BACKOFF).await
+    }
+
+    /// Same as [`try_batch_put`](Client::try_batch_put) but with custom [`backoff`](crate::Backoff) strategy.
+    pub async fn try_batch_put_opt(
+        &self,
+        pairs: impl IntoIterator<Item = impl Into<KvPair>>,
+        backoff: Backoff,
+    ) -> Result<Vec<(Vec<KvPair>, Result<()>)>> {
+        debug!(self.logger, "invoking raw try_batch_put request");
+        let mut pairs: Vec<KvPair> = pairs.into_iter().map(Into::into).collect();
+        // A stable sort, so a key put more than once keeps the order of its puts.
+        pairs.sort_by(|a, b| a.key().cmp(b.key()));
+        let groups: Vec<Vec<KvPair>> = self
+            .rpc
+            .clone()
+            .group_keys_by_region(pairs.into_iter())
+            .map_ok(|(_, pairs)| pairs)
+            .try_collect()
+            .await?;
+        let writes = groups.into_iter().map(|pairs| {
+            let backoff = backoff.clone();
+            async move {
+                let result = self.batch_put_opt(pairs.clone(), backoff).await;
+                (pairs, result)
+            }
+        });
+        Ok(stream::iter(writes)
+            .buffered(MULTI_REGION_CONCURRENCY)
+            .collect()
+            .await)
+    }
+
     /// Create a new 'delete' request.
     ///
     /// Once resolved this request will result in the deletion of the given key.
@@ -361,13 +729,25 @@ impl<PdC: PdClient> Client<PdC> {
     /// Same as [`delete`](Client::delete) but with custom [`backoff`](crate::Backoff) strategy.
     pub async fn delete_opt(&self, key: impl Into<Key>, backoff: Backoff) -> Result<()> {
         debug!(self.logger, "invoking raw delete request");
-        let request = new_raw_delete_request(key.into(), self.cf.clone(), self.atomic);
+        let key = key.into();
+        let request = new_raw_delete_request(key.clone(), self.cf.clone(), self.atomic);
         let plan = crate::request::PlanBuilder::new(self.rpc.clone(), request)
+            .trace_fan_out(self.tracer.clone())
             .retry_multi_region(backoff)
             .merge(CollectSingle)
             .extract_error()
             .plan();
-        plan.execute().await?;
+        let start = self.rpc.clock().now();
+        let result = plan.execute().await;
+        if let Some(audit) = self.rpc.audit_log() {
+            audit.record_writes(
+                AuditOperation::Delete,
+                [&key],
+                AuditOutcome::of_write(&result),
+            );
+        }
+        self.track_hot_keys([&key], start);
+        result?;
         Ok(())
     }
 
@@ -400,13 +780,142 @@ impl<PdC: PdClient> Client<PdC> {
     ) -> Result<()> {
         debug!(self.logger, "invoking raw batch_delete request");
         self.assert_non_atomic()?;
-        let request =
-            new_raw_batch_delete_request(keys.into_iter().map(Into::into), self.cf.clone());
+        let keys: Vec<Key> = keys.into_iter().map(Into::into).collect();
+        let request = new_raw_batch_delete_request(keys.clone().into_iter(), self.cf.clone());
         let plan = crate::request::PlanBuilder::new(self.rpc.clone(), request)
+            .trace_fan_out(self.tracer.clone())
             .retry_multi_region(backoff)
             .extract_error()
             .plan();
-        plan.execute().await?;
+        let start = self.rpc.clock().now();
+        let result = plan.execute().await;
+        if let Some(audit) = self.rpc.audit_log() {
+            audit.record_writes(
+                AuditOperation::Delete,
+                &keys,
+                AuditOutcome::of_write(&result),
+            );
+        }
+        self.track_hot_keys(&keys, start);
+        result?;
+        Ok(())
+    }
+
+    /// Write `mutations` so that, within each region, either all of them apply or none do.
+    ///
+    /// The writes of each region are sent as a single batch in [atomic
+    /// mode](Client::with_atomic_for_cas), which TiKV applies atomically, so this requires a client
+    /// in atomic mode. Writes to different regions are independent: some regions may be written
+    /// and others not if the batch fails. If a key is written more than once, the last write wins.
+    ///
+    /// TiKV's raw batches either put or delete keys, so a batch which both puts and deletes keys of
+    /// one region fails with [`Error::MixedAtomicBatch`] before anything is written.
+    ///
+    /// # Examples
+    /// ```rust,no_run
+    /// # use tikv_client::{RawClient, RawMutation};
+    /// # use futures::prelude::*;
+    /// # futures::executor::block_on(async {
+    /// # let client = RawClient::new(vec!["192.168.0.100"], None).await.unwrap();
+    /// let client = client.with_atomic_for_cas();
+    /// let mutations = vec![
+    ///     RawMutation::Put {
+    ///         key: b"account/1".to_vec().into(),
+    ///         value: b"90".to_vec(),
+    ///     },
+    ///     RawMutation::Put {
+    ///         key: b"account/2".to_vec().into(),
+    ///         value: b"110".to_vec(),
+    ///     },
+    /// ];
+    /// client.atomic_batch(mutations).await.unwrap();
+    /// # });
+    /// ```
+    pub async fn atomic_batch(
+        &self,
+        mutations: impl IntoIterator<Item = RawMutation>,
+    ) -> Result<()> {
+        self.atomic_batch_opt(mutations, DEFAULT_REGION_BACKOFF)
+            .await
+    }
+
+    /// Same as [`atomic_batch`](Client::atomic_batch) but with custom [`backoff`](crate::Backoff) strategy.
+    pub async fn atomic_batch_opt(
+        &self,
+        mutations: impl IntoIterator<Item = RawMutation>,
+        backoff: Backoff,
+    ) -> Result<()> {
+        debug!(self.logger, "invoking raw atomic_batch request");
+        self.assert_atomic()?;
+        let mut writes = BTreeMap::new();
+        for mutation in mutations {
+            match mutation {
+                RawMutation::Put { key, value } => writes.insert(key, Some(value)),
+                RawMutation::Delete { key } => writes.insert(key, None),
+            };
+        }
+
+        let keys: Vec<Key> = writes.keys().cloned().collect();
+        let mut regions = self.rpc.clone().group_keys_by_region(keys.into_iter());
+        while let Some((region, keys)) = regions.try_next().await? {
+            let puts = keys.iter().filter(|key| writes[*key].is_some()).count();
+            if puts != 0 && puts != keys.len() {
+                return Err(Error::MixedAtomicBatch {
+                    region_id: region.id(),
+                });
+            }
+        }
+
+        let mut pairs = Vec::new();
+        let mut deleted = Vec::new();
+        let mut put_keys = Vec::new();
+        let mut delete_keys = Vec::new();
+        if self.rpc.audit_log().is_some() {
+            for (key, value) in &writes {
+                match value {
+                    Some(_) => put_keys.push(key.clone()),
+                    None => delete_keys.push(key.clone()),
+                }
+            }
+        }
+        for (key, value) in writes {
+            match value {
+                Some(value) => pairs.push(KvPair(key, value)),
+                None => deleted.push(key),
+            }
+        }
+        let put = async {
+            if pairs.is_empty() {
+                return Ok(());
+            }
+            let request = new_raw_batch_put_request(pairs.into_iter(), self.cf.clone(), true);
+            let plan = crate::request::PlanBuilder::new(self.rpc.clone(), request)
+                .trace_fan_out(self.tracer.clone())
+                .retry_multi_region(backoff.clone())
+                .extract_error()
+                .plan();
+            plan.execute().await.map(|_| ())
+        };
+        let delete = async {
+            if deleted.is_empty() {
+                return Ok(());
+            }
+            let mut request = new_raw_batch_delete_request(deleted.into_iter(), self.cf.clone());
+            request.for_cas = true;
+            let plan = crate::request::PlanBuilder::new(self.rpc.clone(), request)
+                .trace_fan_out(self.tracer.clone())
+                .retry_multi_region(backoff.clone())
+                .extract_error()
+                .plan();
+            plan.execute().await.map(|_| ())
+        };
+        let result = futures::try_join!(put, delete);
+        if let Some(audit) = self.rpc.audit_log() {
+            let outcome = AuditOutcome::of_write(&result);
+            audit.record_writes(AuditOperation::Put, &put_keys, outcome);
+            audit.record_writes(AuditOperation::Delete, &delete_keys, outcome);
+        }
+        result?;
         Ok(())
     }
 
@@ -437,15 +946,112 @@ impl<PdC: PdClient> Client<PdC> {
     ) -> Result<()> {
         debug!(self.logger, "invoking raw delete_range request");
         self.assert_non_atomic()?;
-        let request = new_raw_delete_range_request(range.into(), self.cf.clone());
+        let range = range.into();
+        let (start, _) = range.clone().into_keys();
+        let request = new_raw_delete_range_request(range, self.cf.clone());
         let plan = crate::request::PlanBuilder::new(self.rpc.clone(), request)
+            .trace_fan_out(self.tracer.clone())
             .retry_multi_region(backoff)
             .extract_error()
             .plan();
-        plan.execute().await?;
+        let started = self.rpc.clock().now();
+        let result = plan.execute().await;
+        if let Some(audit) = self.rpc.audit_log() {
+            audit.record(
+                AuditOperation::DeleteRange,
+                &start,
+                AuditOutcome::of_write(&result),
+            );
+        }
+        // A range is tracked as its start key.
+        self.track_hot_keys([&start], started);
+        result?;
         Ok(())
     }
 
+    /// Delete all keys which start with `prefix`, returning how many were deleted.
+    ///
+    /// Keys are found by key-only scans and deleted in batches, so a failure can leave some of the
+    /// keys deleted. With `dry_run`, nothing is deleted, and the number of keys which would have
+    /// been deleted is returned. Keys written concurrently may or may not be deleted or counted.
+    ///
+    /// See [`BoundRange::prefix`] for the range which is deleted.
+    ///
+    /// # Examples
+    /// ```rust,no_run
+    /// # use tikv_client::{Config, RawClient};
+    /// # use futures::prelude::*;
+    /// # futures::executor::block_on(async {
+    /// # let client = RawClient::new(vec!["192.168.0.100"], None).await.unwrap();
+    /// let count = client.delete_prefix("tenant42/".to_owned(), true).await.unwrap();
+    /// println!("deleting {count} keys");
+    /// client.delete_prefix("tenant42/".to_owned(), false).await.unwrap();
+    /// # });
+    /// ```
+    pub async fn delete_prefix(&self, prefix: impl Into<Key>, dry_run: bool) -> Result<usize> {
+        debug!(self.logger, "invoking raw delete_prefix request"; "dry_run" => dry_run);
+        let range = BoundRange::prefix(prefix);
+        if dry_run {
+            return self.count(range).await;
+        }
+        let (mut start, end) = range.into_keys();
+        let mut count = 0;
+        loop {
+            let keys = self
+                .scan_keys((start, end.clone()), DELETE_PREFIX_BATCH_SIZE)
+                .await?;
+            count += keys.len();
+            let next_start = match keys.last() {
+                Some(last) if keys.len() as u32 == DELETE_PREFIX_BATCH_SIZE => {
+                    let mut next_start = last.clone();
+                    next_start.push_zero();
+                    Some(next_start)
+                }
+                _ => None,
+            };
+            if !keys.is_empty() {
+                self.batch_delete(keys).await?;
+            }
+            match next_start {
+                Some(next_start) => start = next_start,
+                None => return Ok(count),
+            }
+        }
+    }
+
+    /// Count the keys in `range`.
+    ///
+    /// Keys are counted by key-only scans, so values are not transferred. The count is not a
+    /// snapshot: keys written concurrently may or may not be counted.
+    ///
+    /// # Examples
+    /// ```rust,no_run
+    /// # use tikv_client::{Config, RawClient, IntoOwnedRange};
+    /// # use futures::prelude::*;
+    /// # futures::executor::block_on(async {
+    /// # let client = RawClient::new(vec!["192.168.0.100"], None).await.unwrap();
+    /// let count = client.count(("TiDB"..="TiKV").into_owned()).await.unwrap();
+    /// # });
+    /// ```
+    pub async fn count(&self, range: impl Into<BoundRange>) -> Result<usize> {
+        debug!(self.logger, "invoking raw count request");
+        let (mut start, end) = range.into().into_keys();
+        let mut count = 0;
+        loop {
+            let keys = self
+                .scan_keys((start, end.clone()), MAX_RAW_KV_SCAN_LIMIT)
+                .await?;
+            count += keys.len();
+            match keys.last() {
+                Some(last) if keys.len() as u32 == MAX_RAW_KV_SCAN_LIMIT => {
+                    start = last.clone();
+                    start.push_zero();
+                }
+                _ => return Ok(count),
+            }
+        }
+    }
+
     /// Create a new 'scan' request.
     ///
     /// Once resolved this request will result in a `Vec` of key-value pairs that lies in the specified range.
@@ -453,6 +1059,7 @@ impl<PdC: PdClient> Client<PdC> {
     /// If the number of eligible key-value pairs are greater than `limit`,
     /// only the first `limit` pairs are returned, ordered by the key.
     ///
+    /// To scan a range too large to hold in memory, use [`scan_stream`](Client::scan_stream).
     ///
     /// # Examples
     /// ```rust,no_run
@@ -469,57 +1076,231 @@ impl<PdC: PdClient> Client<PdC> {
         self.scan_opt(range, limit, DEFAULT_REGION_BACKOFF).await
     }
 
-    /// Same as [`scan`](Client::scan) but with custom [`backoff`](crate::Backoff) strategy.
-    pub async fn scan_opt(
+    /// Scan all of `range`, reading at most as fast as `pacing` allows.
+    ///
+    /// The range is read in batches, waiting between batches when a limit would be exceeded, so
+    /// that a full-range maintenance scan doesn't starve TiKV of resources. The stream ends after
+    /// the first error.
+    ///
+    /// # Examples
+    /// ```rust,no_run
+    /// # use tikv_client::{Config, RawClient, ScanPacing};
+    /// # use futures::prelude::*;
+    /// # futures::executor::block_on(async {
+    /// # let client = RawClient::new(vec!["192.168.0.100"], None).await.unwrap();
+    /// let pacing = ScanPacing::default().bytes_per_second(1024 * 1024);
+    /// let mut pairs = Box::pin(client.scan_paced(.., pacing));
+    /// while let Some(pair) = pairs.try_next().await.unwrap() {
+    ///     // Check `pair`...
+    /// }
+    /// # });
+    /// ```
+    pub fn scan_paced(
         &self,
         range: impl Into<BoundRange>,
-        limit: u32,
-        backoff: Backoff,
-    ) -> Result<Vec<KvPair>> {
-        debug!(self.logger, "invoking raw scan request");
-        self.scan_inner(range.into(), limit, false, backoff).await
+        pacing: ScanPacing,
+    ) -> impl Stream<Item = Result<KvPair>> + '_ {
+        debug!(self.logger, "invoking raw paced scan request");
+        let clock = self.rpc.clock();
+        paced_scan(
+            self,
+            range.into(),
+            pacing,
+            clock,
+            |client, range, limit| async move {
+                let pairs = client
+                    .scan_inner(range, limit, false, false, DEFAULT_REGION_BACKOFF)
+                    .await;
+                (client, pairs)
+            },
+        )
     }
 
-    /// Create a new 'scan' request that only returns the keys.
-    ///
-    /// Once resolved this request will result in a `Vec` of keys that lies in the specified range.
-    ///
-    /// If the number of eligible keys are greater than `limit`,
-    /// only the first `limit` pairs are returned, ordered by the key.
+    /// Scan all of `range` as a stream, reading at most `page_size` pairs at a time.
     ///
+    /// Unlike [`scan`](Client::scan), which returns all of its pairs at once, each page is read
+    /// from a single region when the stream is polled for more pairs, so scanning a large range
+    /// only holds a page in memory at a time. The stream ends after the first error.
     ///
     /// # Examples
     /// ```rust,no_run
-    /// # use tikv_client::{Key, Config, RawClient, IntoOwnedRange};
+    /// # use tikv_client::{Config, RawClient};
     /// # use futures::prelude::*;
     /// # futures::executor::block_on(async {
     /// # let client = RawClient::new(vec!["192.168.0.100"], None).await.unwrap();
-    /// let inclusive_range = "TiKV"..="TiDB";
-    /// let req = client.scan_keys(inclusive_range.into_owned(), 2);
-    /// let result: Vec<Key> = req.await.unwrap();
+    /// let mut pairs = Box::pin(client.scan_stream("user/".to_owned().., 1024));
+    /// while let Some(pair) = pairs.try_next().await.unwrap() {
+    ///     // Check `pair`...
+    /// }
     /// # });
     /// ```
-    pub async fn scan_keys(&self, range: impl Into<BoundRange>, limit: u32) -> Result<Vec<Key>> {
-        self.scan_keys_opt(range, limit, DEFAULT_REGION_BACKOFF)
-            .await
+    pub fn scan_stream(
+        &self,
+        range: impl Into<BoundRange>,
+        page_size: u32,
+    ) -> impl Stream<Item = Result<KvPair>> + '_ {
+        self.scan_stream_opt(range, page_size, DEFAULT_REGION_BACKOFF)
     }
 
-    /// Same as [`scan_keys`](Client::scan_keys) but with custom [`backoff`](crate::Backoff) strategy.
-    pub async fn scan_keys_opt(
+    /// Same as [`scan_stream`](Client::scan_stream) but with custom [`backoff`](crate::Backoff) strategy.
+    pub fn scan_stream_opt(
         &self,
         range: impl Into<BoundRange>,
-        limit: u32,
+        page_size: u32,
         backoff: Backoff,
-    ) -> Result<Vec<Key>> {
-        debug!(self.logger, "invoking raw scan_keys request");
+    ) -> impl Stream<Item = Result<KvPair>> + '_ {
+        debug!(self.logger, "invoking raw scan_stream request");
+        let (start, end) = range.into().into_keys();
+        let page_size = page_size.max(1);
+        stream::unfold(Some(start), move |start| {
+            let end = end.clone();
+            let backoff = backoff.clone();
+            async move {
+                let page = self.scan_page(start?, end, page_size, backoff).await;
+                match page {
+                    Ok((pairs, next)) => Some((Ok(pairs), next)),
+                    Err(e) => Some((Err(e), None)),
+                }
+            }
+        })
+        .map_ok(|pairs| stream::iter(pairs.into_iter().map(Ok)))
+        .try_flatten()
+    }
+
+    /// Create a new 'scan' request of the keys which start with `prefix`.
+    ///
+    /// See [`BoundRange::prefix`] for the range which is scanned.
+    ///
+    /// # Examples
+    /// ```rust,no_run
+    /// # use tikv_client::{KvPair, Config, RawClient};
+    /// # use futures::prelude::*;
+    /// # futures::executor::block_on(async {
+    /// # let client = RawClient::new(vec!["192.168.0.100"], None).await.unwrap();
+    /// let req = client.scan_prefix("user/".to_owned(), 10);
+    /// let result: Vec<KvPair> = req.await.unwrap();
+    /// # });
+    /// ```
+    pub async fn scan_prefix(&self, prefix: impl Into<Key>, limit: u32) -> Result<Vec<KvPair>> {
+        self.scan(BoundRange::prefix(prefix), limit).await
+    }
+
+    /// Same as [`scan`](Client::scan) but with custom [`backoff`](crate::Backoff) strategy.
+    pub async fn scan_opt(
+        &self,
+        range: impl Into<BoundRange>,
+        limit: u32,
+        backoff: Backoff,
+    ) -> Result<Vec<KvPair>> {
+        debug!(self.logger, "invoking raw scan request");
+        self.scan_inner(range.into(), limit, false, false, backoff)
+            .await
+    }
+
+    /// Create a new 'scan' request that only returns the keys.
+    ///
+    /// Once resolved this request will result in a `Vec` of keys that lies in the specified range.
+    ///
+    /// If the number of eligible keys are greater than `limit`,
+    /// only the first `limit` pairs are returned, ordered by the key.
+    ///
+    ///
+    /// # Examples
+    /// ```rust,no_run
+    /// # use tikv_client::{Key, Config, RawClient, IntoOwnedRange};
+    /// # use futures::prelude::*;
+    /// # futures::executor::block_on(async {
+    /// # let client = RawClient::new(vec!["192.168.0.100"], None).await.unwrap();
+    /// let inclusive_range = "TiKV"..="TiDB";
+    /// let req = client.scan_keys(inclusive_range.into_owned(), 2);
+    /// let result: Vec<Key> = req.await.unwrap();
+    /// # });
+    /// ```
+    pub async fn scan_keys(&self, range: impl Into<BoundRange>, limit: u32) -> Result<Vec<Key>> {
+        self.scan_keys_opt(range, limit, DEFAULT_REGION_BACKOFF)
+            .await
+    }
+
+    /// Same as [`scan_keys`](Client::scan_keys) but with custom [`backoff`](crate::Backoff) strategy.
+    pub async fn scan_keys_opt(
+        &self,
+        range: impl Into<BoundRange>,
+        limit: u32,
+        backoff: Backoff,
+    ) -> Result<Vec<Key>> {
+        debug!(self.logger, "invoking raw scan_keys request");
         Ok(self
-            .scan_inner(range, limit, true, backoff)
+            .scan_inner(range, limit, true, false, backoff)
             .await?
             .into_iter()
             .map(KvPair::into_key)
             .collect())
     }
 
+    /// Get the first key-value pair in `range`, if any.
+    ///
+    /// # Examples
+    /// ```rust,no_run
+    /// # use tikv_client::{KvPair, Config, RawClient};
+    /// # use futures::prelude::*;
+    /// # futures::executor::block_on(async {
+    /// # let client = RawClient::new(vec!["192.168.0.100"], None).await.unwrap();
+    /// let req = client.first("cursor/".to_owned()..);
+    /// let result: Option<KvPair> = req.await.unwrap();
+    /// # });
+    /// ```
+    pub async fn first(&self, range: impl Into<BoundRange>) -> Result<Option<KvPair>> {
+        debug!(self.logger, "invoking raw first request");
+        let pairs = self
+            .scan_inner(range, 1, false, false, DEFAULT_REGION_BACKOFF)
+            .await?;
+        Ok(pairs.into_iter().next())
+    }
+
+    /// Get the last key-value pair in `range`, if any.
+    ///
+    /// The regions covering `range` are scanned in reverse, one at a time, until a key is found.
+    ///
+    /// # Examples
+    /// ```rust,no_run
+    /// # use tikv_client::{KvPair, Config, RawClient};
+    /// # use futures::prelude::*;
+    /// # futures::executor::block_on(async {
+    /// # let client = RawClient::new(vec!["192.168.0.100"], None).await.unwrap();
+    /// let req = client.last("watermark/".to_owned().."watermark0".to_owned());
+    /// let result: Option<KvPair> = req.await.unwrap();
+    /// # });
+    /// ```
+    pub async fn last(&self, range: impl Into<BoundRange>) -> Result<Option<KvPair>> {
+        debug!(self.logger, "invoking raw last request");
+        let range = range.into();
+        let regions = self.rpc.clone().regions_for_range(range.clone()).await?;
+        let (start, end) = range.into_keys();
+        for region in regions.into_iter().rev() {
+            let region_start = max(start.clone(), region.start_key);
+            let region_end = match end.clone() {
+                Some(end) if !region.end_key.is_empty() => Some(min(end, region.end_key)),
+                Some(end) => Some(end),
+                None if region.end_key.is_empty() => None,
+                None => Some(region.end_key),
+            };
+            let pairs = self
+                .scan_inner(
+                    (region_start, region_end),
+                    1,
+                    false,
+                    true,
+                    DEFAULT_REGION_BACKOFF,
+                )
+                .await?;
+            let last = pairs.into_iter().next();
+            if last.is_some() {
+                return Ok(last);
+            }
+        }
+        Ok(None)
+    }
+
     /// Create a new 'batch scan' request.
     ///
     /// Once resolved this request will result in a set of scanners over the given keys.
@@ -560,8 +1341,13 @@ impl<PdC: PdClient> Client<PdC> {
         backoff: Backoff,
     ) -> Result<Vec<KvPair>> {
         debug!(self.logger, "invoking raw batch_scan request");
-        self.batch_scan_inner(ranges, each_limit, false, backoff)
-            .await
+        let pairs = self
+            .batch_scan_inner(ranges, each_limit, false, backoff)
+            .await?;
+        if let Some(audit) = self.rpc.audit_log() {
+            audit.record_scan(pairs.iter().map(KvPair::key));
+        }
+        Ok(pairs)
     }
 
     /// Create a new 'batch scan' request that only returns the keys.
@@ -604,12 +1390,16 @@ impl<PdC: PdClient> Client<PdC> {
         backoff: Backoff,
     ) -> Result<Vec<Key>> {
         debug!(self.logger, "invoking raw batch_scan_keys request");
-        Ok(self
+        let keys: Vec<Key> = self
             .batch_scan_inner(ranges, each_limit, true, backoff)
             .await?
             .into_iter()
             .map(KvPair::into_key)
-            .collect())
+            .collect();
+        if let Some(audit) = self.rpc.audit_log() {
+            audit.record_scan(&keys);
+        }
+        Ok(keys)
     }
 
     /// Create a new *atomic* 'compare and set' request.
@@ -644,18 +1434,113 @@ impl<PdC: PdClient> Client<PdC> {
     ) -> Result<(Option<Value>, bool)> {
         debug!(self.logger, "invoking raw compare_and_swap request");
         self.assert_atomic()?;
+        let key = key.into();
         let req = new_cas_request(
-            key.into(),
+            key.clone(),
             new_value.into(),
             previous_value.into(),
             self.cf.clone(),
         );
         let plan = crate::request::PlanBuilder::new(self.rpc.clone(), req)
+            .trace_fan_out(self.tracer.clone())
             .retry_multi_region(backoff)
             .merge(CollectSingle)
             .post_process_default()
             .plan();
-        plan.execute().await
+        let start = self.rpc.clock().now();
+        let result = plan.execute().await;
+        if let Some(audit) = self.rpc.audit_log() {
+            // A swap which was not made reads the value of the key.
+            let outcome = match &result {
+                Ok((_, true)) => AuditOutcome::Written,
+                Ok((Some(_), false)) => AuditOutcome::Found,
+                Ok((None, false)) => AuditOutcome::NotFound,
+                Err(_) => AuditOutcome::of_write(&result),
+            };
+            audit.record(AuditOperation::CompareAndSwap, &key, outcome);
+        }
+        self.track_hot_keys([&key], start);
+        result
+    }
+
+    /// Reset the time to live of `key` to `ttl`, without changing its value.
+    ///
+    /// The value of `key` is read and written back with the new TTL by a
+    /// [`compare_and_swap`](Client::compare_and_swap). If `key` is written concurrently, the swap
+    /// is retried with the value it finds, so a concurrent write is never undone. The client must
+    /// be in [`atomic mode`](Client::with_atomic_for_cas), and TiKV must be configured with
+    /// `storage.enable-ttl` for keys to expire.
+    ///
+    /// `ttl` is rounded up to whole seconds. A zero `ttl` makes `key` never expire.
+    ///
+    /// # Return Value
+    ///
+    /// Whether `key` exists, and so was touched.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use tikv_client::RawClient;
+    /// # use std::time::Duration;
+    /// # use futures::prelude::*;
+    /// # futures::executor::block_on(async {
+    /// let client = RawClient::new(vec!["192.168.0.100"], None)
+    ///     .await
+    ///     .unwrap()
+    ///     .with_atomic_for_cas();
+    /// let alive = client
+    ///     .touch("session/42".to_owned(), Duration::from_secs(600))
+    ///     .await
+    ///     .unwrap();
+    /// # });
+    /// ```
+    pub async fn touch(&self, key: impl Into<Key>, ttl: Duration) -> Result<bool> {
+        self.touch_opt(key, ttl, DEFAULT_REGION_BACKOFF).await
+    }
+
+    /// Same as [`touch`](Client::touch) but with custom [`backoff`](crate::Backoff) strategy.
+    pub async fn touch_opt(
+        &self,
+        key: impl Into<Key>,
+        ttl: Duration,
+        backoff: Backoff,
+    ) -> Result<bool> {
+        debug!(self.logger, "invoking raw touch request");
+        self.assert_atomic()?;
+        let key = key.into();
+        let result = self.touch_inner(key.clone(), ttl, backoff).await;
+        if let Some(audit) = self.rpc.audit_log() {
+            let outcome = match &result {
+                Ok(true) => AuditOutcome::Written,
+                Ok(false) => AuditOutcome::NotFound,
+                Err(_) => AuditOutcome::of_write(&result),
+            };
+            audit.record(AuditOperation::Touch, &key, outcome);
+        }
+        result
+    }
+
+    async fn touch_inner(&self, key: Key, ttl: Duration, backoff: Backoff) -> Result<bool> {
+        let ttl = ttl.as_secs() + u64::from(ttl.subsec_nanos() > 0);
+        let mut value = match self.get_opt(key.clone(), backoff.clone()).await? {
+            Some(value) => value,
+            None => return Ok(false),
+        };
+        loop {
+            let mut req = new_cas_request(key.clone(), value.clone(), Some(value), self.cf.clone());
+            req.ttl = ttl;
+            let plan = crate::request::PlanBuilder::new(self.rpc.clone(), req)
+                .trace_fan_out(self.tracer.clone())
+                .retry_multi_region(backoff.clone())
+                .merge(CollectSingle)
+                .post_process_default()
+                .plan();
+            match plan.execute().await? {
+                (_, true) => return Ok(true),
+                (Some(current), false) => value = current,
+                (None, false) => return Ok(false),
+            }
+        }
     }
 
     pub async fn coprocessor(
@@ -693,6 +1578,7 @@ impl<PdC: PdClient> Client<PdC> {
             request_builder,
         );
         let plan = crate::request::PlanBuilder::new(self.rpc.clone(), req)
+            .trace_fan_out(self.tracer.clone())
             .preserve_shard()
             .retry_multi_region(backoff)
             .post_process_default()
@@ -700,11 +1586,74 @@ impl<PdC: PdClient> Client<PdC> {
         plan.execute().await
     }
 
+    /// Create a [`BufferedWriter`] which batches puts, for high volume writes where each pair need
+    /// not be written immediately.
+    pub fn buffered_writer(&self) -> BufferedWriter<PdC> {
+        BufferedWriter::new(Client {
+            rpc: self.rpc.clone(),
+            cf: self.cf.clone(),
+            atomic: self.atomic,
+            tracer: self.tracer.clone(),
+            logger: self.logger.clone(),
+        })
+    }
+
+    /// Create a [`NamespacedRawClient`](crate::NamespacedRawClient) which only reads and writes
+    /// keys of the namespace `N`.
+    pub fn namespaced<N: Namespace>(&self) -> NamespacedClient<N, PdC> {
+        NamespacedClient::new(self.clone())
+    }
+
+    /// Create a [`PrefixedRawClient`](crate::PrefixedRawClient) which only reads and writes keys
+    /// starting with `prefix`, taking and returning keys without it.
+    pub fn with_prefix(&self, prefix: impl Into<Key>) -> PrefixedClient<PdC> {
+        PrefixedClient::new(self.clone(), prefix.into())
+    }
+
+    /// Fetch and cache the regions covering `range`, and connect to their stores.
+    ///
+    /// This is useful before a bulk job, to avoid querying PD while it runs. Returns the number of
+    /// regions covering the range.
+    pub async fn warm_region_cache(&self, range: impl Into<BoundRange>) -> Result<usize> {
+        self.rpc.clone().warm_region_cache(range.into()).await
+    }
+
+    /// Remove all cached regions which overlap `range`, so they are fetched from PD when next
+    /// used. Returns the number of regions removed.
+    ///
+    /// To invalidate the region containing a single key, use `key..=key`.
+    pub async fn invalidate_region_cache(&self, range: impl Into<BoundRange>) -> usize {
+        self.rpc.invalidate_region_cache_range(range.into()).await
+    }
+
+    /// Get statistics about the client's region cache.
+    pub async fn region_cache_stats(&self) -> RegionCacheStats {
+        self.rpc.region_cache_stats().await
+    }
+
+    /// Get the `top_n` hottest keys accessed by the client, hottest first: those whose sampled
+    /// accesses took longest.
+    ///
+    /// Keys are only tracked by clients created with
+    /// [`Config::with_hot_key_tracking`](crate::Config::with_hot_key_tracking); otherwise, the
+    /// list is empty.
+    pub fn hot_keys(&self, top_n: usize) -> Vec<HotKey> {
+        self.rpc
+            .hot_key_tracker()
+            .map_or_else(Vec::new, |tracker| tracker.hot_keys(top_n))
+    }
+
+    /// Get the regions covering `range`, in order, and where their leaders are.
+    pub async fn regions(&self, range: impl Into<BoundRange>) -> Result<Vec<RegionInfo>> {
+        self.rpc.clone().regions_for_range(range.into()).await
+    }
+
     async fn scan_inner(
         &self,
         range: impl Into<BoundRange>,
         limit: u32,
         key_only: bool,
+        reverse: bool,
         backoff: Backoff,
     ) -> Result<Vec<KvPair>> {
         if limit > MAX_RAW_KV_SCAN_LIMIT {
@@ -714,18 +1663,72 @@ impl<PdC: PdClient> Client<PdC> {
             });
         }
 
-        let request = new_raw_scan_request(range.into(), limit, key_only, self.cf.clone());
+        let request = new_raw_scan_request(range.into(), limit, key_only, reverse, self.cf.clone());
         let plan = crate::request::PlanBuilder::new(self.rpc.clone(), request)
+            .trace_fan_out(self.tracer.clone())
             .retry_multi_region(backoff)
             .merge(Collect)
             .plan();
+        let start = self.rpc.clock().now();
         let res = plan.execute().await;
         res.map(|mut s| {
+            // Each region is scanned separately, including the regions a region split into while
+            // it was being scanned, and their pairs are collected in ascending order of regions.
+            // The regions a changed region is scanned again on only cover its part of the range,
+            // but a key must never be returned twice, so duplicates are dropped too.
+            if reverse {
+                s.sort_by(|a, b| b.key().cmp(a.key()));
+            } else {
+                s.sort_by(|a, b| a.key().cmp(b.key()));
+            }
+            s.dedup_by(|a, b| a.key() == b.key());
             s.truncate(limit as usize);
+            if let Some(audit) = self.rpc.audit_log() {
+                audit.record_scan(s.iter().map(KvPair::key));
+            }
+            self.track_hot_keys(s.iter().map(KvPair::key), start);
             s
         })
     }
 
+    /// Read at most `limit` pairs from `start` to `end`, within the region of `start`, returning
+    /// them along with the key to read the next page from, if any.
+    ///
+    /// Each page looks up the region of its start afresh, so a scan whose regions split or merge
+    /// between pages carries on from the key after the last pair returned, on the new regions.
+    async fn scan_page(
+        &self,
+        start: Key,
+        end: Option<Key>,
+        limit: u32,
+        backoff: Backoff,
+    ) -> Result<(Vec<KvPair>, Option<Key>)> {
+        if end.as_ref().is_some_and(|end| &start >= end) {
+            return Ok((Vec::new(), None));
+        }
+        let region_end = self.rpc.region_for_key(&start).await?.end_key();
+        let (page_end, last_page) = match end {
+            Some(end) if region_end.is_empty() || end <= region_end => (Some(end), true),
+            _ if region_end.is_empty() => (None, true),
+            _ => (Some(region_end), false),
+        };
+        let range = match page_end.clone() {
+            Some(page_end) => BoundRange::from(start..page_end),
+            None => BoundRange::from(start..),
+        };
+        let pairs = self.scan_inner(range, limit, false, false, backoff).await?;
+        let next = match pairs.last() {
+            Some(last) if pairs.len() as u32 == limit => {
+                let mut next = last.key().clone();
+                next.push_zero();
+                Some(next)
+            }
+            _ if last_page => None,
+            _ => page_end,
+        };
+        Ok((pairs, next))
+    }
+
     async fn batch_scan_inner(
         &self,
         ranges: impl IntoIterator<Item = impl Into<BoundRange>>,
@@ -747,12 +1750,22 @@ impl<PdC: PdClient> Client<PdC> {
             self.cf.clone(),
         );
         let plan = crate::request::PlanBuilder::new(self.rpc.clone(), request)
+            .trace_fan_out(self.tracer.clone())
             .retry_multi_region(backoff)
             .merge(Collect)
             .plan();
         plan.execute().await
     }
 
+    /// Record the accesses of `keys` by a request which started at `start`, if hot keys are
+    /// tracked.
+    fn track_hot_keys<'a>(&self, keys: impl IntoIterator<Item = &'a Key>, start: Instant) {
+        if let Some(tracker) = self.rpc.hot_key_tracker() {
+            let latency = self.rpc.clock().now().saturating_duration_since(start);
+            tracker.record_accesses(keys, latency);
+        }
+    }
+
     fn assert_non_atomic(&self) -> Result<()> {
         if !self.atomic {
             Ok(())
@@ -773,13 +1786,22 @@ impl<PdC: PdClient> Client<PdC> {
 #[cfg(test)]
 mod tests {
     use std::any::Any;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
     use std::sync::Arc;
+    use std::time::Duration;
 
+    use futures::TryStreamExt;
+    use slog::Drain;
     use tikv_client_proto::kvrpcpb;
 
     use super::*;
     use crate::mock::MockKvClient;
     use crate::mock::MockPdClient;
+    use crate::simulation::Fault;
+    use crate::simulation::Simulation;
+    use crate::AuditEvent;
+    use crate::HotKeyTracking;
     use crate::Result;
 
     #[tokio::test]
@@ -811,6 +1833,7 @@ mod tests {
             rpc: pd_client,
             cf: Some(ColumnFamily::Default),
             atomic: false,
+            tracer: None,
             logger,
         };
         let resps = client
@@ -842,4 +1865,580 @@ mod tests {
         ]);
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_value_format_rejected() {
+        // The configuration is rejected before connecting to PD.
+        let config = Config::default().with_value_checksum();
+        let result = Client::new_with_config(vec!["127.0.0.1:1"], config, None).await;
+        assert!(matches!(result, Err(Error::StringError(_))));
+    }
+
+    #[tokio::test]
+    async fn test_scan_prefix() {
+        let sim = Simulation::new(1);
+        let client = sim.raw_client();
+        let keys = [
+            vec![1],
+            vec![1, 0xff],
+            vec![1, 0xff, 0xff, 0],
+            vec![2],
+            vec![0xff],
+            vec![0xff, 0xff, 1],
+        ];
+        client
+            .batch_put(keys.iter().map(|key| (key.clone(), key.clone())))
+            .await
+            .unwrap();
+
+        for (prefix, expected) in [
+            (vec![1, 0xff], &keys[1..3]),
+            (vec![1], &keys[0..3]),
+            (vec![0xff], &keys[4..]),
+            (vec![], &keys[..]),
+        ] {
+            let keys = client.scan_prefix(prefix, 10).await.unwrap();
+            let keys = keys.into_iter().map(|pair| Vec::from(pair.into_key()));
+            assert_eq!(keys.collect::<Vec<_>>(), expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_delete_prefix() {
+        let sim = Simulation::new(2);
+        sim.split(vec![1, 2, 0]);
+        let client = sim.raw_client();
+        let mut keys = (0..1500u16)
+            .map(|i| [&[1, 2][..], &i.to_be_bytes()].concat())
+            .collect::<Vec<_>>();
+        keys.extend([vec![1], vec![1, 3], vec![2]]);
+        client
+            .batch_put(keys.iter().map(|key| (key.clone(), key.clone())))
+            .await
+            .unwrap();
+
+        assert_eq!(client.delete_prefix(vec![1, 2], true).await.unwrap(), 1500);
+        assert_eq!(client.scan_keys(.., 10240).await.unwrap().len(), 1503);
+        assert_eq!(client.delete_prefix(vec![1, 2], false).await.unwrap(), 1500);
+        assert_eq!(
+            client.scan_keys(.., 10240).await.unwrap(),
+            vec![Key::from(vec![1]), vec![1, 3].into(), vec![2].into()]
+        );
+        assert_eq!(client.delete_prefix(vec![1, 2], false).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_count() {
+        let sim = Simulation::new(3);
+        sim.split(vec![0x20]);
+        let client = sim.raw_client();
+        let pairs = (0..30_000u32).map(|i| (i.to_be_bytes().to_vec(), vec![0]));
+        let pairs = pairs.collect::<Vec<_>>();
+        for batch in pairs.chunks(10_000) {
+            client.batch_put(batch.to_vec()).await.unwrap();
+        }
+
+        assert_eq!(client.count(..).await.unwrap(), 30_000);
+        let range = 100u32.to_be_bytes().to_vec()..=20_479u32.to_be_bytes().to_vec();
+        assert_eq!(client.count(range).await.unwrap(), 20_380);
+        assert_eq!(client.count(vec![1]..).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_scan_paced() {
+        let sim = Simulation::new(15);
+        sim.split(vec![0, 0, 2, 0]);
+        let client = sim.raw_client();
+        let pairs: Vec<KvPair> = (0..1000u32)
+            .map(|i| (i.to_be_bytes().to_vec(), vec![0]).into())
+            .collect();
+        client.batch_put(pairs.clone()).await.unwrap();
+
+        let pacing = ScanPacing::default().bytes_per_second(1 << 20);
+        let scanned = client.scan_paced(.., pacing.clone());
+        let scanned: Vec<KvPair> = scanned.try_collect().await.unwrap();
+        assert_eq!(scanned, pairs);
+        let scanned = client.scan_paced(pairs[100].key().clone().., pacing);
+        let scanned: Vec<KvPair> = scanned.try_collect().await.unwrap();
+        assert_eq!(scanned, pairs[100..]);
+    }
+
+    #[tokio::test]
+    async fn test_first_and_last() {
+        let sim = Simulation::new(4);
+        sim.split(vec![3]);
+        sim.split(vec![6]);
+        let client = sim.raw_client();
+        client
+            .batch_put([(vec![1], vec![1]), (vec![4], vec![4]), (vec![5], vec![5])])
+            .await
+            .unwrap();
+        let key = |pair: Option<KvPair>| pair.map(|pair| Vec::from(pair.into_key()));
+
+        assert_eq!(key(client.first(..).await.unwrap()), Some(vec![1]));
+        assert_eq!(key(client.last(..).await.unwrap()), Some(vec![5]));
+        assert_eq!(key(client.first(vec![2]..).await.unwrap()), Some(vec![4]));
+        assert_eq!(key(client.last(..vec![5]).await.unwrap()), Some(vec![4]));
+        assert_eq!(key(client.last(..=vec![5]).await.unwrap()), Some(vec![5]));
+        assert_eq!(key(client.last(vec![2]..vec![4]).await.unwrap()), None);
+        assert_eq!(key(client.first(vec![6]..).await.unwrap()), None);
+    }
+
+    #[tokio::test]
+    async fn test_fan_out_trace() {
+        let sim = Simulation::new(44);
+        let right = sim.regions()[0].id;
+        let left = sim.split(vec![5]).unwrap();
+        let tracer = FanOutTracer::new();
+        let client = sim.raw_client().with_fan_out_tracer(tracer.clone());
+        client
+            .batch_put([(vec![1], vec![1]), (vec![6], vec![6])])
+            .await
+            .unwrap();
+        assert_eq!(tracer.take().regions.len(), 2);
+
+        let mut busy = true;
+        sim.inject_faults(move |request| {
+            if request.region_id == right && std::mem::take(&mut busy) {
+                vec![Fault::ServerIsBusy { backoff_ms: 1 }]
+            } else {
+                Vec::new()
+            }
+        });
+        let pairs = client.batch_get([vec![1], vec![6]]).await.unwrap();
+        assert_eq!(pairs.len(), 2);
+        let trace = tracer.take();
+        let regions: Vec<_> = trace
+            .regions
+            .iter()
+            .map(|region| (region.region_id, region.requests, region.retries))
+            .collect();
+        // Regions are in order of their ids.
+        let mut expected = vec![(left, 1, 0), (right, 2, 1)];
+        expected.sort();
+        assert_eq!(regions, expected);
+        assert!(trace.regions.iter().all(|region| region.stores.len() == 1));
+        assert_eq!(trace.retries(), 1);
+        assert!(tracer.trace().regions.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_buffered_writer() {
+        let written = Arc::new(AtomicUsize::new(0));
+        let written_cloned = written.clone();
+        let pd_client = Arc::new(MockPdClient::new(MockKvClient::with_dispatch_hook(
+            move |req: &dyn Any| {
+                let req = req.downcast_ref::<kvrpcpb::RawBatchPutRequest>().unwrap();
+                if req.pairs.iter().any(|pair| pair.key == vec![100]) {
+                    return Err(Error::Unimplemented);
+                }
+                written_cloned.fetch_add(req.pairs.len(), Ordering::SeqCst);
+                Ok(Box::<kvrpcpb::RawBatchPutResponse>::default() as Box<dyn Any>)
+            },
+        )));
+        let client = Client {
+            rpc: pd_client,
+            cf: None,
+            atomic: false,
+            tracer: None,
+            logger: Logger::root(slog::Discard.fuse(), o!()),
+        };
+
+        // Writes are triggered by size, then by flush.
+        let mut writer = client
+            .buffered_writer()
+            .max_batch_pairs(3)
+            .flush_interval(Duration::from_secs(3600));
+        for i in 0..5u8 {
+            writer.put(vec![i], vec![i]).await.unwrap();
+        }
+        assert_eq!(written.load(Ordering::SeqCst), 3);
+        writer.flush().await.unwrap();
+        assert_eq!(written.load(Ordering::SeqCst), 5);
+        writer.flush().await.unwrap();
+        assert_eq!(written.load(Ordering::SeqCst), 5);
+
+        // Writes are triggered by time, and background errors are reported.
+        let mut writer = client
+            .buffered_writer()
+            .flush_interval(Duration::from_millis(10));
+        writer.put(vec![6], vec![6]).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(written.load(Ordering::SeqCst), 6);
+        writer.put(vec![100], vec![100]).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let err = writer.flush().await.unwrap_err();
+        assert!(matches!(err.root(), Error::Unimplemented));
+        assert_eq!(err.operation(), Some("raw_batch_put"));
+        assert_eq!(err.key(), Some(&[100][..]));
+        assert!(err.region_id().is_some());
+        assert_eq!(err.store_address(), Some("mock://tikv"));
+        assert_eq!(err.retry_attempts(), Some(0));
+        writer.flush().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_atomic_batch() {
+        let batches = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let batches_cloned = batches.clone();
+        let pd_client = Arc::new(MockPdClient::new(MockKvClient::with_dispatch_hook(
+            move |req: &dyn Any| {
+                let mut batches = batches_cloned.lock().unwrap();
+                if let Some(req) = req.downcast_ref::<kvrpcpb::RawBatchPutRequest>() {
+                    assert!(req.for_cas);
+                    let keys = req.pairs.iter().map(|pair| pair.key.clone()).collect();
+                    batches.push(("put", keys));
+                    Ok(Box::<kvrpcpb::RawBatchPutResponse>::default() as Box<dyn Any>)
+                } else {
+                    let req: &kvrpcpb::RawBatchDeleteRequest = req.downcast_ref().unwrap();
+                    assert!(req.for_cas);
+                    batches.push(("delete", req.keys.clone()));
+                    Ok(Box::<kvrpcpb::RawBatchDeleteResponse>::default() as Box<dyn Any>)
+                }
+            },
+        )));
+        let client = Client {
+            rpc: pd_client,
+            cf: None,
+            atomic: false,
+            tracer: None,
+            logger: Logger::root(slog::Discard.fuse(), o!()),
+        };
+        let put = |key: u8| RawMutation::Put {
+            key: vec![key].into(),
+            value: vec![key],
+        };
+        let delete = |key: u8| RawMutation::Delete {
+            key: vec![key].into(),
+        };
+
+        let err = client.atomic_batch(vec![put(1)]).await.unwrap_err();
+        assert!(matches!(err, Error::UnsupportedMode));
+
+        // Regions are [..10], [10..250], and [250..].
+        let client = client.with_atomic_for_cas();
+        let err = client
+            .atomic_batch(vec![put(1), put(11), delete(12)])
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::MixedAtomicBatch { region_id: 2 }));
+        assert!(batches.lock().unwrap().is_empty());
+
+        // The last write of a key wins.
+        let mutations = vec![delete(3), put(1), put(2), put(3), delete(11), delete(12)];
+        client.atomic_batch(mutations).await.unwrap();
+        let mut batches = batches.lock().unwrap().clone();
+        batches.sort();
+        assert_eq!(batches, vec![
+            ("delete", vec![vec![11], vec![12]]),
+            ("put", vec![vec![1], vec![2], vec![3]]),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_audit() {
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = events.clone();
+        let pd_client = MockPdClient::new(MockKvClient::with_dispatch_hook(
+            move |req: &dyn Any| {
+                if let Some(req) = req.downcast_ref::<kvrpcpb::RawGetRequest>() {
+                    let resp = kvrpcpb::RawGetResponse {
+                        not_found: req.key != b"found",
+                        value: b"value".to_vec(),
+                        ..Default::default()
+                    };
+                    Ok(Box::new(resp) as Box<dyn Any>)
+                } else if req.is::<kvrpcpb::RawPutRequest>() {
+                    Ok(Box::<kvrpcpb::RawPutResponse>::default() as Box<dyn Any>)
+                } else if req.is::<kvrpcpb::RawCasRequest>() {
+                    let resp = kvrpcpb::RawCasResponse {
+                        succeed: false,
+                        previous_value: b"other".to_vec(),
+                        ..Default::default()
+                    };
+                    Ok(Box::new(resp) as Box<dyn Any>)
+                } else if req.is::<kvrpcpb::RawDeleteRangeRequest>() {
+                    Err(Error::GrpcAPI(tonic::Status::deadline_exceeded("timed out")))
+                } else {
+                    let resp = kvrpcpb::RawDeleteResponse {
+                        error: "disk full".to_owned(),
+                        ..Default::default()
+                    };
+                    Ok(Box::new(resp) as Box<dyn Any>)
+                }
+            },
+        ))
+        .with_audit_sink(move |event: AuditEvent| recorded.lock().unwrap().push(event));
+        let client = Client {
+            rpc: Arc::new(pd_client),
+            cf: None,
+            atomic: false,
+            tracer: None,
+            logger: Logger::root(slog::Discard.fuse(), o!()),
+        };
+        client.get(b"found".to_vec()).await.unwrap();
+        client.get(b"missing".to_vec()).await.unwrap();
+        client.put(b"found".to_vec(), b"value".to_vec()).await.unwrap();
+        client.delete(b"found".to_vec()).await.unwrap_err();
+        client
+            .delete_range(b"a".to_vec()..b"b".to_vec())
+            .await
+            .unwrap_err();
+        let (_, swapped) = client
+            .with_atomic_for_cas()
+            .compare_and_swap(b"found".to_vec(), None, b"value".to_vec())
+            .await
+            .unwrap();
+        assert!(!swapped);
+
+        let events: Vec<(AuditOperation, Vec<u8>, AuditOutcome)> = events
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|event| (event.operation, event.key.clone().into(), event.outcome))
+            .collect();
+        assert_eq!(events, vec![
+            (AuditOperation::Get, b"found".to_vec(), AuditOutcome::Found),
+            (AuditOperation::Get, b"missing".to_vec(), AuditOutcome::NotFound),
+            (AuditOperation::Put, b"found".to_vec(), AuditOutcome::Written),
+            (AuditOperation::Delete, b"found".to_vec(), AuditOutcome::Failed),
+            (AuditOperation::DeleteRange, b"a".to_vec(), AuditOutcome::Undetermined),
+            (AuditOperation::CompareAndSwap, b"found".to_vec(), AuditOutcome::Found),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_hot_keys() {
+        let sim = Simulation::new(43);
+        sim.set_hot_key_tracking(HotKeyTracking::default().sample_interval(1));
+        let client = sim.raw_client();
+        client.put(vec![1], vec![1]).await.unwrap();
+        client.put(vec![2], vec![2]).await.unwrap();
+        client.scan(vec![1]..vec![3], 10).await.unwrap();
+        client.delete_range(vec![2]..vec![3]).await.unwrap();
+        let atomic = client.with_atomic_for_cas();
+        atomic
+            .compare_and_swap(vec![1], Some(vec![1]), vec![3])
+            .await
+            .unwrap();
+
+        let mut accesses: Vec<(Key, u64)> = sim
+            .hot_keys(5)
+            .into_iter()
+            .map(|hot_key| (hot_key.key, hot_key.sampled_accesses))
+            .collect();
+        accesses.sort();
+        // Key 1 is put, scanned and swapped; key 2 is put, scanned, and starts the deleted range.
+        assert_eq!(accesses, vec![(Key::from(vec![1]), 3), (Key::from(vec![2]), 3)]);
+    }
+
+    #[tokio::test]
+    async fn test_try_batch() {
+        // Reads and writes fail in the region [10, 250).
+        let pd_client = MockPdClient::new(MockKvClient::with_dispatch_hook(
+            move |req: &dyn Any| {
+                if let Some(req) = req.downcast_ref::<kvrpcpb::RawBatchGetRequest>() {
+                    let resp = if req.keys.contains(&vec![20]) {
+                        kvrpcpb::RawBatchGetResponse {
+                            region_error: Some(Default::default()),
+                            ..Default::default()
+                        }
+                    } else {
+                        let pairs = req.keys.iter().map(|key| kvrpcpb::KvPair {
+                            key: key.clone(),
+                            value: key.clone(),
+                            ..Default::default()
+                        });
+                        kvrpcpb::RawBatchGetResponse {
+                            pairs: pairs.collect(),
+                            ..Default::default()
+                        }
+                    };
+                    Ok(Box::new(resp) as Box<dyn Any>)
+                } else {
+                    let req = req.downcast_ref::<kvrpcpb::RawBatchPutRequest>().unwrap();
+                    let mut resp = kvrpcpb::RawBatchPutResponse::default();
+                    if req.pairs.iter().any(|pair| pair.key == vec![20]) {
+                        resp.error = "disk full".to_owned();
+                    }
+                    Ok(Box::new(resp) as Box<dyn Any>)
+                }
+            },
+        ));
+        let client = Client {
+            rpc: Arc::new(pd_client),
+            cf: None,
+            atomic: false,
+            tracer: None,
+            logger: Logger::root(slog::Discard.fuse(), o!()),
+        };
+
+        let keys = vec![vec![251], vec![20], vec![1], vec![21], vec![1]];
+        let backoff = Backoff::no_backoff();
+        let read = client.try_batch_get_opt(keys, backoff).await.unwrap();
+        let groups: Vec<(Vec<Key>, bool)> = read
+            .iter()
+            .map(|(keys, result)| (keys.clone(), result.is_ok()))
+            .collect();
+        assert_eq!(groups, vec![
+            (vec![vec![1].into()], true),
+            (vec![vec![20].into(), vec![21].into()], false),
+            (vec![vec![251].into()], true),
+        ]);
+        assert_eq!(read[0].1.as_ref().unwrap(), &vec![KvPair::new(vec![1], vec![1])]);
+
+        let pairs = vec![(vec![20], vec![0]), (vec![5], vec![0]), (vec![7], vec![0])];
+        let written = client.try_batch_put(pairs).await.unwrap();
+        let failed: Vec<KvPair> = written
+            .into_iter()
+            .filter(|(_, result)| result.is_err())
+            .flat_map(|(pairs, _)| pairs)
+            .collect();
+        assert_eq!(failed, vec![KvPair::new(vec![20], vec![0])]);
+    }
+
+    #[tokio::test]
+    async fn test_touch() {
+        let swaps = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let swaps_cloned = swaps.clone();
+        let pd_client = Arc::new(MockPdClient::new(MockKvClient::with_dispatch_hook(
+            move |req: &dyn Any| {
+                if let Some(req) = req.downcast_ref::<kvrpcpb::RawGetRequest>() {
+                    let resp = kvrpcpb::RawGetResponse {
+                        not_found: req.key != b"session",
+                        value: b"old".to_vec(),
+                        ..Default::default()
+                    };
+                    return Ok(Box::new(resp) as Box<dyn Any>);
+                }
+                let req: &kvrpcpb::RawCasRequest = req.downcast_ref().unwrap();
+                assert_eq!(req.value, req.previous_value);
+                let mut swaps = swaps_cloned.lock().unwrap();
+                swaps.push((req.previous_value.clone(), req.ttl));
+                // The value is first rewritten concurrently, and then the key deleted.
+                let resp = match swaps.len() {
+                    1 => kvrpcpb::RawCasResponse {
+                        previous_value: b"new".to_vec(),
+                        ..Default::default()
+                    },
+                    2 => kvrpcpb::RawCasResponse {
+                        succeed: true,
+                        ..Default::default()
+                    },
+                    _ => kvrpcpb::RawCasResponse {
+                        previous_not_exist: true,
+                        ..Default::default()
+                    },
+                };
+                Ok(Box::new(resp) as Box<dyn Any>)
+            },
+        )));
+        let client = Client {
+            rpc: pd_client,
+            cf: None,
+            atomic: false,
+            tracer: None,
+            logger: Logger::root(slog::Discard.fuse(), o!()),
+        };
+        let ttl = Duration::from_millis(1500);
+        let err = client.touch(b"session".to_vec(), ttl).await.unwrap_err();
+        assert!(matches!(err, Error::UnsupportedMode));
+
+        let client = client.with_atomic_for_cas();
+        assert!(!client.touch(b"missing".to_vec(), ttl).await.unwrap());
+        assert!(swaps.lock().unwrap().is_empty());
+        assert!(client.touch(b"session".to_vec(), ttl).await.unwrap());
+        assert!(!client.touch(b"session".to_vec(), ttl).await.unwrap());
+        assert_eq!(*swaps.lock().unwrap(), vec![
+            (b"old".to_vec(), 2),
+            (b"new".to_vec(), 2),
+            (b"old".to_vec(), 2),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_scan_stream() {
+        let sim = Simulation::new(34);
+        let key = |i: u32| Key::from(format!("k{i:02}"));
+        sim.split(key(5));
+        sim.split(key(12));
+        let client = sim.raw_client();
+        client
+            .batch_put((0..20).map(|i| (key(i), vec![i as u8])))
+            .await
+            .unwrap();
+
+        let scans = |sim: &Simulation| {
+            let requests = sim.requests().into_iter();
+            requests.filter(|req| req.label == "raw_scan").count()
+        };
+        let before = scans(&sim);
+        let pairs: Vec<KvPair> = client
+            .scan_stream(key(2)..key(17), 4)
+            .try_collect()
+            .await
+            .unwrap();
+        let keys: Vec<Key> = pairs.into_iter().map(KvPair::into_key).collect();
+        assert_eq!(keys, (2..17).map(key).collect::<Vec<_>>());
+        // Regions of 3, 7 and 5 keys of the range are read in pages of at most 4 keys.
+        assert_eq!(scans(&sim) - before, 5);
+
+        let pairs: Vec<KvPair> = client.scan_stream(.., 100).try_collect().await.unwrap();
+        assert_eq!(pairs.len(), 20);
+        let pairs: Vec<KvPair> = client
+            .scan_stream(key(8)..key(8), 4)
+            .try_collect()
+            .await
+            .unwrap();
+        assert!(pairs.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_scan_with_splits() {
+        let sim = Simulation::new(35);
+        let key = |i: u32| Key::from(format!("k{i:02}"));
+        let client = sim.raw_client();
+        client
+            .batch_put((0..30).map(|i| (key(i), vec![i as u8])))
+            .await
+            .unwrap();
+
+        // Split a region under each of the next scan requests, before the store handles it, so
+        // that the request fails and is retried on the new regions.
+        let mut splits = vec![27, 3, 21, 9, 15, 6, 24, 12, 18];
+        sim.inject_faults(move |request| match request.label {
+            "raw_scan" => splits
+                .pop()
+                .map_or_else(Vec::new, |i| vec![Fault::Split(key(i))]),
+            _ => Vec::new(),
+        });
+        let pairs = client.scan(.., 10).await.unwrap();
+        let keys: Vec<Key> = pairs.into_iter().map(KvPair::into_key).collect();
+        assert_eq!(keys, (0..10).map(key).collect::<Vec<_>>());
+        let scanned = client.scan_keys(key(5)..key(25), 30).await.unwrap();
+        assert_eq!(scanned, (5..25).map(key).collect::<Vec<_>>());
+        let last = client.last(..key(20)).await.unwrap();
+        assert_eq!(last.map(KvPair::into_key), Some(key(19)));
+
+        // A stream carries on from its last pair on the new regions, whether a region splits
+        // while a page is read or between pages.
+        let mut splits = vec![8, 2];
+        sim.inject_faults(move |request| match request.label {
+            "raw_scan" => splits
+                .pop()
+                .map_or_else(Vec::new, |i| vec![Fault::Split(key(i))]),
+            _ => Vec::new(),
+        });
+        let mut stream = Box::pin(client.scan_stream(.., 4));
+        let mut streamed = Vec::new();
+        while let Some(pair) = stream.try_next().await.unwrap() {
+            streamed.push(pair.into_key());
+            if streamed.len() == 13 {
+                sim.split(key(14));
+                sim.split(key(16));
+            }
+        }
+        assert_eq!(streamed, (0..30).map(key).collect::<Vec<_>>());
+        assert!(sim.regions().len() > 10);
+    }
 }
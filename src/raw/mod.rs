@@ -12,13 +12,28 @@
 use std::convert::TryFrom;
 use std::fmt;
 
+pub use self::buffered_writer::BufferedWriter;
 pub use self::client::Client;
+pub use self::namespaced::NamespacedClient;
+pub use self::prefixed::PrefixedClient;
 use crate::Error;
+use crate::Key;
+use crate::Value;
 
+mod buffered_writer;
 mod client;
 pub mod lowering;
+mod namespaced;
+mod prefixed;
 mod requests;
 
+/// A write of an [atomic batch](Client::atomic_batch).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RawMutation {
+    Put { key: Key, value: Value },
+    Delete { key: Key },
+}
+
 /// A [`ColumnFamily`](ColumnFamily) is an optional parameter for [`raw::Client`](Client) requests.
 ///
 /// TiKV uses RocksDB's `ColumnFamily` support. You can learn more about RocksDB's `ColumnFamily`s [on their wiki](https://github.com/facebook/rocksdb/wiki/Column-Families).
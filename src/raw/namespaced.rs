@@ -0,0 +1,177 @@
+// Copyright 2023 TiKV Project Authors. Licensed under Apache-2.0.
+
+use std::marker::PhantomData;
+
+use crate::pd::PdClient;
+use crate::pd::PdRpcClient;
+use crate::raw::Client;
+use crate::Key;
+use crate::KvPair;
+use crate::Namespace;
+use crate::Result;
+use crate::TypedKey;
+use crate::TypedRange;
+use crate::Value;
+
+/// A raw client confined to the keys of the [`Namespace`] `N`.
+///
+/// Keys are [`TypedKey`]s, and ranges [`TypedRange`]s, of the namespace, so every request, even a
+/// scan or deletion of a range, only reads or writes keys of the namespace.
+///
+/// Create one with [`RawClient::namespaced`](crate::RawClient::namespaced).
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// # use tikv_client::{Namespace, RawClient, TypedKey, TypedRange};
+/// # use futures::prelude::*;
+/// struct Sessions;
+///
+/// impl Namespace for Sessions {
+///     const PREFIX: &'static [u8] = b"sessions/";
+/// }
+///
+/// # futures::executor::block_on(async {
+/// let client = RawClient::new(vec!["192.168.0.100"], None).await.unwrap();
+/// let sessions = client.namespaced::<Sessions>();
+/// sessions
+///     .put(TypedKey::new(b"42".to_vec()), b"alice".to_vec())
+///     .await
+///     .unwrap();
+/// // Only deletes keys starting with `sessions/`.
+/// sessions.delete_range(TypedRange::all()).await.unwrap();
+/// # });
+/// ```
+pub struct NamespacedClient<N: Namespace, PdC: PdClient = PdRpcClient> {
+    client: Client<PdC>,
+    namespace: PhantomData<N>,
+}
+
+impl<N: Namespace, PdC: PdClient> Clone for NamespacedClient<N, PdC> {
+    fn clone(&self) -> Self {
+        NamespacedClient::new(self.client.clone())
+    }
+}
+
+impl<N: Namespace, PdC: PdClient> NamespacedClient<N, PdC> {
+    pub(crate) fn new(client: Client<PdC>) -> NamespacedClient<N, PdC> {
+        NamespacedClient {
+            client,
+            namespace: PhantomData,
+        }
+    }
+
+    /// Get the value of `key`. See [`RawClient::get`](crate::RawClient::get).
+    pub async fn get(&self, key: TypedKey<N>) -> Result<Option<Value>> {
+        self.client.get(key).await
+    }
+
+    /// Get the values of `keys`, omitting those which do not exist. See
+    /// [`RawClient::batch_get`](crate::RawClient::batch_get).
+    pub async fn batch_get(
+        &self,
+        keys: impl IntoIterator<Item = TypedKey<N>>,
+    ) -> Result<Vec<(TypedKey<N>, Value)>> {
+        let pairs = self.client.batch_get(keys).await?;
+        Ok(pairs.into_iter().map(typed_pair).collect())
+    }
+
+    /// Write `value` to `key`. See [`RawClient::put`](crate::RawClient::put).
+    pub async fn put(&self, key: TypedKey<N>, value: impl Into<Value>) -> Result<()> {
+        self.client.put(key, value).await
+    }
+
+    /// Write each of `pairs`. See [`RawClient::batch_put`](crate::RawClient::batch_put).
+    pub async fn batch_put(
+        &self,
+        pairs: impl IntoIterator<Item = (TypedKey<N>, impl Into<Value>)>,
+    ) -> Result<()> {
+        let pairs = pairs
+            .into_iter()
+            .map(|(key, value)| KvPair::new(key, value));
+        self.client.batch_put(pairs).await
+    }
+
+    /// Delete `key`. See [`RawClient::delete`](crate::RawClient::delete).
+    pub async fn delete(&self, key: TypedKey<N>) -> Result<()> {
+        self.client.delete(key).await
+    }
+
+    /// Delete each of `keys`. See [`RawClient::batch_delete`](crate::RawClient::batch_delete).
+    pub async fn batch_delete(&self, keys: impl IntoIterator<Item = TypedKey<N>>) -> Result<()> {
+        self.client.batch_delete(keys).await
+    }
+
+    /// Delete the keys in `range`. See [`RawClient::delete_range`](crate::RawClient::delete_range).
+    pub async fn delete_range(&self, range: TypedRange<N>) -> Result<()> {
+        self.client.delete_range(range).await
+    }
+
+    /// Read at most `limit` pairs of `range`, in order. See
+    /// [`RawClient::scan`](crate::RawClient::scan).
+    pub async fn scan(
+        &self,
+        range: TypedRange<N>,
+        limit: u32,
+    ) -> Result<Vec<(TypedKey<N>, Value)>> {
+        let pairs = self.client.scan(range, limit).await?;
+        Ok(pairs.into_iter().map(typed_pair).collect())
+    }
+
+    /// Read at most `limit` keys of `range`, in order. See
+    /// [`RawClient::scan_keys`](crate::RawClient::scan_keys).
+    pub async fn scan_keys(&self, range: TypedRange<N>, limit: u32) -> Result<Vec<TypedKey<N>>> {
+        let keys = self.client.scan_keys(range, limit).await?;
+        Ok(keys.into_iter().map(typed_key).collect())
+    }
+}
+
+fn typed_key<N: Namespace>(key: Key) -> TypedKey<N> {
+    TypedKey::from_key(key).expect("Unreachable: TiKV returned a key outside the namespace")
+}
+
+fn typed_pair<N: Namespace>(pair: KvPair) -> (TypedKey<N>, Value) {
+    let (key, value) = pair.into();
+    (typed_key(key), value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::Simulation;
+
+    struct Users;
+
+    impl Namespace for Users {
+        const PREFIX: &'static [u8] = b"users/";
+    }
+
+    #[tokio::test]
+    async fn test_namespaced_client() {
+        let sim = Simulation::new(32);
+        let client = sim.raw_client();
+        let outside = vec![b"users".to_vec(), b"users0".to_vec(), b"usersx".to_vec()];
+        for key in &outside {
+            client.put(key.clone(), b"other".to_vec()).await.unwrap();
+        }
+
+        let users = client.namespaced::<Users>();
+        let key = |suffix: &str| TypedKey::<Users>::new(suffix.to_owned());
+        users
+            .batch_put(vec![(key("a"), b"1".to_vec()), (key("b"), b"2".to_vec())])
+            .await
+            .unwrap();
+        assert_eq!(users.get(key("a")).await.unwrap(), Some(b"1".to_vec()));
+        let value = client.get(b"users/b".to_vec()).await.unwrap();
+        assert_eq!(value, Some(b"2".to_vec()));
+        let keys = users.scan_keys(TypedRange::all(), 10).await.unwrap();
+        assert_eq!(keys, vec![key("a"), key("b")]);
+        let range = TypedRange::new("b".to_owned()..);
+        let pairs = users.scan(range, 10).await.unwrap();
+        assert_eq!(pairs, vec![(key("b"), b"2".to_vec())]);
+
+        users.delete_range(TypedRange::all()).await.unwrap();
+        assert!(users.scan(TypedRange::all(), 10).await.unwrap().is_empty());
+        assert_eq!(client.batch_get(outside).await.unwrap().len(), 3);
+    }
+}
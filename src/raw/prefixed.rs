@@ -0,0 +1,273 @@
+// Copyright 2023 TiKV Project Authors. Licensed under Apache-2.0.
+
+use std::time::Duration;
+
+use tikv_client_common::internal_err;
+
+use crate::kv::prefixed_key;
+use crate::kv::prefixed_range;
+use crate::pd::PdClient;
+use crate::pd::PdRpcClient;
+use crate::raw::Client;
+use crate::raw::RawMutation;
+use crate::BoundRange;
+use crate::Key;
+use crate::KvPair;
+use crate::Result;
+use crate::Value;
+
+/// A raw client confined to the keys starting with a prefix chosen at runtime, such as that of a
+/// tenant sharing the cluster with others.
+///
+/// Keys passed to the client are the parts of keys after the prefix: they are prefixed before
+/// being written, and keys read have the prefix stripped. Ranges are clamped to the prefix, so
+/// every request, even a scan or deletion of the whole range `..`, only reads or writes keys
+/// starting with it. For prefixes known at compile time, a
+/// [`NamespacedRawClient`](crate::NamespacedRawClient) also keeps keys of different prefixes
+/// apart by type.
+///
+/// The client offers the reads, writes, scans and atomic operations of a raw client which take
+/// keys or ranges, but not its other operations, such as coprocessor requests. Keys are read in
+/// reverse with [`last`](PrefixedClient::last), as the raw client does not scan in reverse.
+///
+/// Create one with [`RawClient::with_prefix`](crate::RawClient::with_prefix).
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// # use tikv_client::RawClient;
+/// # use futures::prelude::*;
+/// # futures::executor::block_on(async {
+/// let client = RawClient::new(vec!["192.168.0.100"], None).await.unwrap();
+/// let tenant = client.with_prefix(b"tenant-42/".to_vec());
+/// // Writes `tenant-42/k1`.
+/// tenant.put(b"k1".to_vec(), b"v1".to_vec()).await.unwrap();
+/// // Only deletes keys starting with `tenant-42/`.
+/// tenant.delete_range(..).await.unwrap();
+/// # });
+/// ```
+#[derive(Clone)]
+pub struct PrefixedClient<PdC: PdClient = PdRpcClient> {
+    client: Client<PdC>,
+    prefix: Key,
+}
+
+impl<PdC: PdClient> PrefixedClient<PdC> {
+    pub(crate) fn new(client: Client<PdC>, prefix: Key) -> PrefixedClient<PdC> {
+        PrefixedClient { client, prefix }
+    }
+
+    /// The prefix of the keys the client reads and writes.
+    pub fn prefix(&self) -> &Key {
+        &self.prefix
+    }
+
+    /// Get the value of `key`. See [`RawClient::get`](crate::RawClient::get).
+    pub async fn get(&self, key: impl Into<Key>) -> Result<Option<Value>> {
+        self.client.get(self.key(key)).await
+    }
+
+    /// Get the values of `keys`, omitting those which do not exist. See
+    /// [`RawClient::batch_get`](crate::RawClient::batch_get).
+    pub async fn batch_get(
+        &self,
+        keys: impl IntoIterator<Item = impl Into<Key>>,
+    ) -> Result<Vec<KvPair>> {
+        let keys = keys.into_iter().map(|key| self.key(key));
+        let pairs = self.client.batch_get(keys).await?;
+        self.strip_pairs(pairs)
+    }
+
+    /// Write `value` to `key`. See [`RawClient::put`](crate::RawClient::put).
+    pub async fn put(&self, key: impl Into<Key>, value: impl Into<Value>) -> Result<()> {
+        self.client.put(self.key(key), value).await
+    }
+
+    /// Write each of `pairs`. See [`RawClient::batch_put`](crate::RawClient::batch_put).
+    pub async fn batch_put(
+        &self,
+        pairs: impl IntoIterator<Item = impl Into<KvPair>>,
+    ) -> Result<()> {
+        let pairs = pairs.into_iter().map(|pair| {
+            let (key, value) = <(Key, Value)>::from(pair.into());
+            KvPair::new(self.key(key), value)
+        });
+        self.client.batch_put(pairs).await
+    }
+
+    /// Delete `key`. See [`RawClient::delete`](crate::RawClient::delete).
+    pub async fn delete(&self, key: impl Into<Key>) -> Result<()> {
+        self.client.delete(self.key(key)).await
+    }
+
+    /// Delete each of `keys`. See [`RawClient::batch_delete`](crate::RawClient::batch_delete).
+    pub async fn batch_delete(&self, keys: impl IntoIterator<Item = impl Into<Key>>) -> Result<()> {
+        let keys = keys.into_iter().map(|key| self.key(key));
+        self.client.batch_delete(keys).await
+    }
+
+    /// Delete the keys in `range`. See [`RawClient::delete_range`](crate::RawClient::delete_range).
+    pub async fn delete_range(&self, range: impl Into<BoundRange>) -> Result<()> {
+        self.client.delete_range(self.range(range)).await
+    }
+
+    /// Apply `mutations` atomically. See
+    /// [`RawClient::atomic_batch`](crate::RawClient::atomic_batch).
+    pub async fn atomic_batch(
+        &self,
+        mutations: impl IntoIterator<Item = RawMutation>,
+    ) -> Result<()> {
+        let mutations = mutations.into_iter().map(|mutation| match mutation {
+            RawMutation::Put { key, value } => RawMutation::Put {
+                key: self.key(key),
+                value,
+            },
+            RawMutation::Delete { key } => RawMutation::Delete { key: self.key(key) },
+        });
+        self.client.atomic_batch(mutations).await
+    }
+
+    /// Write `new_value` to `key` if its value is `previous_value`. See
+    /// [`RawClient::compare_and_swap`](crate::RawClient::compare_and_swap).
+    pub async fn compare_and_swap(
+        &self,
+        key: impl Into<Key>,
+        previous_value: impl Into<Option<Value>>,
+        new_value: impl Into<Value>,
+    ) -> Result<(Option<Value>, bool)> {
+        self.client
+            .compare_and_swap(self.key(key), previous_value, new_value)
+            .await
+    }
+
+    /// Reset the time to live of `key` to `ttl`. See [`RawClient::touch`](crate::RawClient::touch).
+    pub async fn touch(&self, key: impl Into<Key>, ttl: Duration) -> Result<bool> {
+        self.client.touch(self.key(key), ttl).await
+    }
+
+    /// Read at most `limit` pairs of `range`, in order. See
+    /// [`RawClient::scan`](crate::RawClient::scan).
+    pub async fn scan(&self, range: impl Into<BoundRange>, limit: u32) -> Result<Vec<KvPair>> {
+        let pairs = self.client.scan(self.range(range), limit).await?;
+        self.strip_pairs(pairs)
+    }
+
+    /// Read at most `limit` keys of `range`, in order. See
+    /// [`RawClient::scan_keys`](crate::RawClient::scan_keys).
+    pub async fn scan_keys(&self, range: impl Into<BoundRange>, limit: u32) -> Result<Vec<Key>> {
+        let keys = self.client.scan_keys(self.range(range), limit).await?;
+        keys.into_iter().map(|key| self.strip(key)).collect()
+    }
+
+    /// Read the pairs of each of `ranges`. See
+    /// [`RawClient::batch_scan`](crate::RawClient::batch_scan).
+    pub async fn batch_scan(
+        &self,
+        ranges: impl IntoIterator<Item = impl Into<BoundRange>>,
+        each_limit: u32,
+    ) -> Result<Vec<KvPair>> {
+        let ranges = ranges.into_iter().map(|range| self.range(range));
+        let pairs = self.client.batch_scan(ranges, each_limit).await?;
+        self.strip_pairs(pairs)
+    }
+
+    /// Read the keys of each of `ranges`. See
+    /// [`RawClient::batch_scan_keys`](crate::RawClient::batch_scan_keys).
+    pub async fn batch_scan_keys(
+        &self,
+        ranges: impl IntoIterator<Item = impl Into<BoundRange>>,
+        each_limit: u32,
+    ) -> Result<Vec<Key>> {
+        let ranges = ranges.into_iter().map(|range| self.range(range));
+        let keys = self.client.batch_scan_keys(ranges, each_limit).await?;
+        keys.into_iter().map(|key| self.strip(key)).collect()
+    }
+
+    /// Read the first pair of `range`, if any. See [`RawClient::first`](crate::RawClient::first).
+    pub async fn first(&self, range: impl Into<BoundRange>) -> Result<Option<KvPair>> {
+        let pair = self.client.first(self.range(range)).await?;
+        pair.map(|pair| self.strip_pair(pair)).transpose()
+    }
+
+    /// Read the last pair of `range`, if any. See [`RawClient::last`](crate::RawClient::last).
+    pub async fn last(&self, range: impl Into<BoundRange>) -> Result<Option<KvPair>> {
+        let pair = self.client.last(self.range(range)).await?;
+        pair.map(|pair| self.strip_pair(pair)).transpose()
+    }
+
+    fn key(&self, suffix: impl Into<Key>) -> Key {
+        prefixed_key((&self.prefix).into(), suffix)
+    }
+
+    fn range(&self, suffixes: impl Into<BoundRange>) -> BoundRange {
+        prefixed_range((&self.prefix).into(), suffixes.into())
+    }
+
+    fn strip(&self, key: Key) -> Result<Key> {
+        let key = Vec::from(key);
+        match key.strip_prefix(<&[u8]>::from(&self.prefix)) {
+            Some(suffix) => Ok(suffix.to_vec().into()),
+            None => Err(internal_err!("TiKV returned a key outside the prefix")),
+        }
+    }
+
+    fn strip_pair(&self, pair: KvPair) -> Result<KvPair> {
+        let (key, value) = pair.into();
+        Ok(KvPair::new(self.strip(key)?, value))
+    }
+
+    fn strip_pairs(&self, pairs: Vec<KvPair>) -> Result<Vec<KvPair>> {
+        pairs.into_iter().map(|pair| self.strip_pair(pair)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::simulation::Simulation;
+    use crate::Key;
+    use crate::KvPair;
+
+    #[tokio::test]
+    async fn test_prefixed_client() {
+        let sim = Simulation::new(32);
+        let client = sim.raw_client();
+        let outside = vec![b"t1".to_vec(), b"t10".to_vec(), b"t2/a".to_vec()];
+        for key in &outside {
+            client.put(key.clone(), b"other".to_vec()).await.unwrap();
+        }
+
+        let tenant = client.with_prefix(b"t1/".to_vec());
+        tenant
+            .batch_put(vec![(b"a".to_vec(), b"1".to_vec()), (b"b".to_vec(), b"2".to_vec())])
+            .await
+            .unwrap();
+        assert_eq!(tenant.get(b"a".to_vec()).await.unwrap(), Some(b"1".to_vec()));
+        let value = client.get(b"t1/b".to_vec()).await.unwrap();
+        assert_eq!(value, Some(b"2".to_vec()));
+        let pairs = tenant.batch_get(vec![b"b".to_vec(), b"c".to_vec()]).await.unwrap();
+        assert_eq!(pairs, vec![KvPair::new(b"b".to_vec(), b"2".to_vec())]);
+
+        // Ranges are clamped to the prefix, and keys read are stripped of it.
+        let keys = tenant.scan_keys(.., 10).await.unwrap();
+        assert_eq!(keys, vec![Key::from(b"a".to_vec()), Key::from(b"b".to_vec())]);
+        let pairs = tenant.scan(b"b".to_vec().., 10).await.unwrap();
+        assert_eq!(pairs, vec![KvPair::new(b"b".to_vec(), b"2".to_vec())]);
+
+        let atomic = client.with_atomic_for_cas().with_prefix(b"t1/".to_vec());
+        let swapped = atomic
+            .compare_and_swap(b"a".to_vec(), Some(b"1".to_vec()), b"3".to_vec())
+            .await
+            .unwrap();
+        assert_eq!(swapped, (Some(b"1".to_vec()), true));
+        assert_eq!(client.get(b"t1/a".to_vec()).await.unwrap(), Some(b"3".to_vec()));
+        let last = tenant.last(..).await.unwrap();
+        assert_eq!(last, Some(KvPair::new(b"b".to_vec(), b"2".to_vec())));
+        let first = tenant.first(..).await.unwrap();
+        assert_eq!(first, Some(KvPair::new(b"a".to_vec(), b"3".to_vec())));
+        assert!(tenant.strip(Key::from(b"t2/a".to_vec())).is_err());
+
+        tenant.delete_range(..).await.unwrap();
+        assert!(tenant.scan(.., 10).await.unwrap().is_empty());
+        assert_eq!(client.batch_get(outside).await.unwrap().len(), 3);
+    }
+}
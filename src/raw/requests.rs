@@ -230,13 +230,20 @@ pub fn new_raw_scan_request(
     end_key: Vec<u8>,
     limit: u32,
     key_only: bool,
+    reverse: bool,
     cf: Option<ColumnFamily>,
 ) -> kvrpcpb::RawScanRequest {
     let mut req = kvrpcpb::RawScanRequest::default();
-    req.start_key = start_key;
-    req.end_key = end_key;
+    if !reverse {
+        req.start_key = start_key;
+        req.end_key = end_key;
+    } else {
+        req.start_key = end_key;
+        req.end_key = start_key;
+    }
     req.limit = limit;
     req.key_only = key_only;
+    req.reverse = reverse;
     req.maybe_set_cf(cf);
 
     req
@@ -246,7 +253,7 @@ impl KvRequest for kvrpcpb::RawScanRequest {
     type Response = kvrpcpb::RawScanResponse;
 }
 
-shardable_range!(kvrpcpb::RawScanRequest);
+shardable_range!(kvrpcpb::RawScanRequest, reverse);
 
 impl Merge<kvrpcpb::RawScanResponse> for Collect {
     type Out = Vec<KvPair>;
@@ -394,6 +401,14 @@ impl Request for RawCoprocessorRequest {
     fn set_context(&mut self, context: kvrpcpb::Context) {
         self.inner.set_context(context);
     }
+
+    fn set_resource_group_tag(&mut self, tag: Vec<u8>) {
+        self.inner.set_resource_group_tag(tag);
+    }
+
+    fn encoded_len(&self) -> usize {
+        self.inner.encoded_len()
+    }
 }
 
 impl KvRequest for RawCoprocessorRequest {
@@ -0,0 +1,299 @@
+// Copyright 2023 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! A mutex shared by all clients of a cluster.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde_derive::Deserialize;
+use serde_derive::Serialize;
+use slog::Logger;
+
+use super::finish;
+use super::physical_ms;
+use crate::pd::PdClient;
+use crate::pd::PdRpcClient;
+use crate::transaction::Transaction;
+use crate::Error;
+use crate::Key;
+use crate::Result;
+use crate::TransactionOptions;
+
+const DEFAULT_RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A mutex, stored under a key, which is held for a limited time.
+///
+/// Acquiring the lock grants a [`Lease`] which expires after the lock's TTL, unless it is
+/// [renewed](DistributedLock::renew). Once a lease has expired, the lock can be acquired by
+/// someone else, so a holder which stalls (e.g., in a long GC pause) can lose the lock without
+/// noticing. To guard against this, each lease carries a fencing token, which is greater than the
+/// token of every earlier lease. Pass the token along with writes to the resource the lock
+/// protects, and have the resource reject tokens lower than the greatest it has seen.
+///
+/// Expiry is measured in PD timestamps, so the clocks of the clients don't need to agree.
+///
+/// Create one with
+/// [`TransactionClient::distributed_lock`](crate::TransactionClient::distributed_lock).
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// # use tikv_client::{Config, TransactionClient};
+/// # use std::time::Duration;
+/// # use futures::prelude::*;
+/// # futures::executor::block_on(async {
+/// let client = TransactionClient::new(vec!["192.168.0.100"], None).await.unwrap();
+/// let lock = client.distributed_lock("locks/compaction".to_owned(), Duration::from_secs(10));
+/// let lease = lock.acquire().await.unwrap();
+/// // Do the work, passing `lease.token` to the resources it writes...
+/// let lease = lock.renew(&lease).await.unwrap();
+/// // ...and some more.
+/// lock.release(lease).await.unwrap();
+/// # });
+/// ```
+pub struct DistributedLock<PdC: PdClient = PdRpcClient> {
+    rpc: Arc<PdC>,
+    key: Key,
+    ttl: Duration,
+    retry_interval: Duration,
+    logger: Logger,
+}
+
+/// A grant of a [`DistributedLock`], until it expires.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Lease {
+    /// The fencing token of the lease, greater than those of all earlier leases of the lock.
+    pub token: u64,
+    /// When the lease expires, as the physical part (in milliseconds) of a PD timestamp.
+    pub expires_at: u64,
+}
+
+/// The value stored under the key of a lock.
+#[derive(Serialize, Deserialize)]
+struct LockRecord {
+    token: u64,
+    expires_at: u64,
+}
+
+impl<PdC: PdClient> DistributedLock<PdC> {
+    pub(crate) fn new(
+        rpc: Arc<PdC>,
+        key: Key,
+        ttl: Duration,
+        logger: Logger,
+    ) -> DistributedLock<PdC> {
+        DistributedLock {
+            rpc,
+            key,
+            ttl,
+            retry_interval: DEFAULT_RETRY_INTERVAL,
+            logger,
+        }
+    }
+
+    /// Set how long [`acquire`](DistributedLock::acquire) waits between attempts.
+    #[must_use]
+    pub fn retry_interval(mut self, retry_interval: Duration) -> Self {
+        self.retry_interval = retry_interval;
+        self
+    }
+
+    /// The key the lock is stored under.
+    pub fn key(&self) -> &Key {
+        &self.key
+    }
+
+    /// Try to acquire the lock, returning `None` if it is held by someone else.
+    ///
+    /// If this fails with an [undetermined](Error::UndeterminedError) error, the lock may have
+    /// been acquired; it will be free again once the TTL has passed.
+    pub async fn try_acquire(&self) -> Result<Option<Lease>> {
+        debug!(self.logger, "trying to acquire distributed lock");
+        let result = self.try_acquire_inner().await;
+        match result {
+            Err(e) if e.is_conflict() => Ok(None),
+            result => result,
+        }
+    }
+
+    /// Acquire the lock, waiting until it is free.
+    pub async fn acquire(&self) -> Result<Lease> {
+        loop {
+            if let Some(lease) = self.try_acquire().await? {
+                return Ok(lease);
+            }
+            self.rpc.clock().sleep(self.retry_interval).await;
+        }
+    }
+
+    /// Extend `lease` by the lock's TTL, returning the extended lease.
+    ///
+    /// Fails with [`Error::LockNotHeld`] if the lease has expired.
+    pub async fn renew(&self, lease: &Lease) -> Result<Lease> {
+        debug!(self.logger, "renewing distributed lock"; "token" => lease.token);
+        let mut txn = self.begin().await?;
+        let now = physical_ms(&txn.start_timestamp());
+        let result = async {
+            match txn.get_json::<LockRecord>(self.key.clone()).await? {
+                Some(record) if record.token == lease.token && record.expires_at > now => {}
+                _ => return Err(self.not_held()),
+            }
+            let lease = Lease {
+                token: lease.token,
+                expires_at: now + self.ttl.as_millis() as u64,
+            };
+            self.write(&mut txn, &lease).await?;
+            Ok(lease)
+        }
+        .await;
+        finish(txn, result).await
+    }
+
+    /// Release the lock, so that it can be acquired before `lease` expires.
+    ///
+    /// Fails with [`Error::LockNotHeld`] if the lock has since been acquired by someone else.
+    pub async fn release(&self, lease: Lease) -> Result<()> {
+        debug!(self.logger, "releasing distributed lock"; "token" => lease.token);
+        let mut txn = self.begin().await?;
+        let result = async {
+            match txn.get_json::<LockRecord>(self.key.clone()).await? {
+                Some(record) if record.token == lease.token => {}
+                _ => return Err(self.not_held()),
+            }
+            // Keep the token, so that the next lease's token is still greater.
+            let released = Lease {
+                token: lease.token,
+                expires_at: 0,
+            };
+            self.write(&mut txn, &released).await
+        }
+        .await;
+        finish(txn, result).await
+    }
+
+    async fn try_acquire_inner(&self) -> Result<Option<Lease>> {
+        let mut txn = self.begin().await?;
+        let now = physical_ms(&txn.start_timestamp());
+        let result = async {
+            let token = match txn.get_json::<LockRecord>(self.key.clone()).await? {
+                Some(record) if record.expires_at > now => return Ok(None),
+                Some(record) => record.token + 1,
+                None => 1,
+            };
+            // The lease is measured from the transaction's start, which is before it commits, so
+            // the holder never believes the lease lasts longer than it does.
+            let lease = Lease {
+                token,
+                expires_at: now + self.ttl.as_millis() as u64,
+            };
+            self.write(&mut txn, &lease).await?;
+            Ok(Some(lease))
+        }
+        .await;
+        finish(txn, result).await
+    }
+
+    async fn begin(&self) -> Result<Transaction<PdC>> {
+        let options = TransactionOptions::new_optimistic();
+        super::begin(&self.rpc, options, &self.logger).await
+    }
+
+    async fn write(&self, txn: &mut Transaction<PdC>, lease: &Lease) -> Result<()> {
+        let record = LockRecord {
+            token: lease.token,
+            expires_at: lease.expires_at,
+        };
+        txn.put_json(self.key.clone(), &record).await
+    }
+
+    fn not_held(&self) -> Error {
+        Error::LockNotHeld {
+            key: self.key.clone().into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::Fault;
+    use crate::simulation::Simulation;
+
+    #[tokio::test]
+    async fn test_distributed_lock() {
+        let sim = Simulation::new(9);
+        let logger = Logger::root(slog::Discard, o!());
+        let ttl = Duration::from_secs(10);
+        let lock = |key: &str| {
+            DistributedLock::new(sim.pd_client(), key.to_owned().into(), ttl, logger.clone())
+        };
+        let (a, b) = (lock("lock"), lock("lock"));
+
+        let first = a.try_acquire().await.unwrap().unwrap();
+        assert_eq!(first.token, 1);
+        assert_eq!(b.try_acquire().await.unwrap(), None);
+        // Other locks are independent.
+        assert!(lock("other").try_acquire().await.unwrap().is_some());
+
+        // Renewing extends the lease past the original TTL.
+        sim.advance_clock(Duration::from_secs(6));
+        let renewed = a.renew(&first).await.unwrap();
+        assert!(renewed.expires_at > first.expires_at);
+        sim.advance_clock(Duration::from_secs(6));
+        assert_eq!(b.try_acquire().await.unwrap(), None);
+
+        // Once the lease expires, the lock can be taken, with a greater token, and the old lease
+        // can be neither renewed nor released.
+        sim.advance_clock(Duration::from_secs(6));
+        let second = b.try_acquire().await.unwrap().unwrap();
+        assert_eq!(second.token, 2);
+        let e = a.renew(&renewed).await.unwrap_err();
+        assert!(matches!(e, Error::LockNotHeld { .. }), "{e:?}");
+        assert!(a.release(renewed).await.is_err());
+
+        b.release(second).await.unwrap();
+        assert_eq!(a.acquire().await.unwrap().token, 3);
+    }
+
+    #[tokio::test]
+    async fn test_distributed_lock_contention() {
+        let sim = Simulation::new(10);
+        let logger = Logger::root(slog::Discard, o!());
+        let ttl = Duration::from_secs(10);
+        let locks: Vec<_> = (0..8)
+            .map(|_| DistributedLock::new(sim.pd_client(), vec![0].into(), ttl, logger.clone()))
+            .collect();
+        let leases = futures::future::join_all(locks.iter().map(|lock| lock.try_acquire())).await;
+        let acquired: Vec<_> = leases
+            .into_iter()
+            .filter_map(|lease| lease.unwrap())
+            .collect();
+        assert_eq!(acquired.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_distributed_lock_read_error() {
+        let sim = Simulation::new(11);
+        let logger = Logger::root(slog::Discard, o!());
+        let lock = DistributedLock::new(
+            sim.pd_client(),
+            b"lock".to_vec().into(),
+            Duration::from_secs(10),
+            logger,
+        );
+        let lease = lock.try_acquire().await.unwrap().unwrap();
+
+        // Failed reads are returned, and their transactions rolled back rather than dropped while
+        // active, which would panic.
+        sim.inject_faults(|request| match request.label {
+            "kv_get" => vec![Fault::ServerIsBusy { backoff_ms: 1 }],
+            _ => Vec::new(),
+        });
+        assert!(lock.try_acquire().await.is_err());
+        assert!(lock.renew(&lease).await.is_err());
+        assert!(lock.release(lease.clone()).await.is_err());
+
+        sim.inject_faults(|_| Vec::new());
+        lock.release(lease).await.unwrap();
+    }
+}
@@ -0,0 +1,56 @@
+// Copyright 2023 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Common patterns built on the client, which are easy to get subtly wrong.
+
+pub mod lock;
+pub mod queue;
+pub mod sequence;
+pub mod sharding;
+
+use std::sync::Arc;
+
+use slog::Logger;
+
+pub use self::lock::DistributedLock;
+pub use self::lock::Lease;
+pub use self::queue::Message;
+pub use self::queue::Queue;
+pub use self::sequence::Sequence;
+pub use self::sharding::KeyHasher;
+pub use self::sharding::ShardedKey;
+use crate::pd::PdClient;
+use crate::transaction::Transaction;
+use crate::Result;
+use crate::Timestamp;
+use crate::TransactionOptions;
+
+/// Begin a transaction at the current timestamp.
+async fn begin<PdC: PdClient>(
+    rpc: &Arc<PdC>,
+    options: TransactionOptions,
+    logger: &Logger,
+) -> Result<Transaction<PdC>> {
+    let timestamp = rpc.clone().get_timestamp().await?;
+    let logger = logger.new(o!("child" => 1));
+    Ok(Transaction::new(timestamp, rpc.clone(), options, logger))
+}
+
+/// Commit `txn` if `result` is `Ok`, or else roll it back and return the error, so that the
+/// transaction is never dropped while still active.
+async fn finish<PdC: PdClient, T>(mut txn: Transaction<PdC>, result: Result<T>) -> Result<T> {
+    match result {
+        Ok(value) => {
+            txn.commit().await?;
+            Ok(value)
+        }
+        Err(e) => {
+            txn.rollback().await?;
+            Err(e)
+        }
+    }
+}
+
+/// The physical part of `timestamp`, in milliseconds, by which recipes measure time.
+fn physical_ms(timestamp: &Timestamp) -> u64 {
+    timestamp.physical as u64
+}
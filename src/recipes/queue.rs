@@ -0,0 +1,324 @@
+// Copyright 2023 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! A first-in, first-out queue shared by all clients of a cluster.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde_derive::Deserialize;
+use serde_derive::Serialize;
+use slog::Logger;
+
+use super::finish;
+use super::physical_ms;
+use crate::pd::PdClient;
+use crate::pd::PdRpcClient;
+use crate::transaction::Transaction;
+use crate::BoundRange;
+use crate::Error;
+use crate::Key;
+use crate::Result;
+use crate::TimestampExt;
+use crate::TransactionOptions;
+
+/// How many messages are read at a time when looking for a visible message.
+const SCAN_BATCH_SIZE: u32 = 64;
+/// How many times a pop is attempted when it conflicts with a concurrent one.
+const MAX_POP_ATTEMPTS: usize = 10;
+
+/// A queue of messages, stored under a prefix, with at-least-once delivery.
+///
+/// Popping a message hides it from other consumers for the queue's visibility timeout, rather
+/// than removing it. Once the consumer has handled the message, it [acknowledges](Queue::ack)
+/// it, which removes it from the queue. If the consumer fails before then, the message becomes
+/// visible again after the timeout, and is delivered to another consumer.
+///
+/// Messages are ordered by the timestamp at which they were pushed. Consumers popping at the same
+/// time contend for the first visible message; those which lose retry with the next one.
+///
+/// Create one with [`TransactionClient::queue`](crate::TransactionClient::queue).
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// # use tikv_client::{Config, TransactionClient};
+/// # use std::time::Duration;
+/// # use futures::prelude::*;
+/// # futures::executor::block_on(async {
+/// let client = TransactionClient::new(vec!["192.168.0.100"], None).await.unwrap();
+/// let queue = client.queue("queues/emails/".to_owned(), Duration::from_secs(30));
+/// queue.push(b"hello".to_vec()).await.unwrap();
+/// if let Some(message) = queue.pop().await.unwrap() {
+///     // Handle `message.payload`, then...
+///     queue.ack(&message).await.unwrap();
+/// }
+/// # });
+/// ```
+pub struct Queue<PdC: PdClient = PdRpcClient> {
+    rpc: Arc<PdC>,
+    prefix: Key,
+    visibility_timeout: Duration,
+    logger: Logger,
+}
+
+/// A message in a [`Queue`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Message {
+    /// The id of the message, unique within its queue.
+    pub id: u64,
+    pub payload: Vec<u8>,
+    /// How many times the message has been popped, including this time.
+    pub deliveries: u32,
+}
+
+/// The value stored under the key of a message.
+#[derive(Serialize, Deserialize)]
+struct MessageRecord {
+    payload: Vec<u8>,
+    invisible_until: u64,
+    deliveries: u32,
+}
+
+impl<PdC: PdClient> Queue<PdC> {
+    pub(crate) fn new(
+        rpc: Arc<PdC>,
+        prefix: Key,
+        visibility_timeout: Duration,
+        logger: Logger,
+    ) -> Queue<PdC> {
+        Queue {
+            rpc,
+            prefix,
+            visibility_timeout,
+            logger,
+        }
+    }
+
+    /// Add a message to the end of the queue, returning its id.
+    pub async fn push(&self, payload: impl Into<Vec<u8>>) -> Result<u64> {
+        debug!(self.logger, "pushing to queue");
+        let mut txn = self.begin().await?;
+        let id = txn.start_timestamp().version();
+        let record = MessageRecord {
+            payload: payload.into(),
+            invisible_until: 0,
+            deliveries: 0,
+        };
+        let result = txn.put_json(self.key(id), &record).await;
+        finish(txn, result.map(|()| id)).await
+    }
+
+    /// Get the first visible message, without popping it.
+    pub async fn peek(&self) -> Result<Option<Message>> {
+        debug!(self.logger, "peeking at queue");
+        let mut txn = self.begin().await?;
+        let first = self.first_visible(&mut txn).await;
+        txn.rollback().await?;
+        Ok(first?.map(|(id, record)| message(id, record)))
+    }
+
+    /// Pop the first visible message, hiding it for the visibility timeout.
+    ///
+    /// Returns `None` if there are no visible messages.
+    pub async fn pop(&self) -> Result<Option<Message>> {
+        debug!(self.logger, "popping from queue");
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            match self.pop_once().await {
+                Err(e) if e.is_conflict() && attempts < MAX_POP_ATTEMPTS => {
+                    debug!(self.logger, "queue pop conflicted, retrying"; "error" => %e);
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Remove a popped message from the queue.
+    ///
+    /// Returns `false` if the message was not removed, because it became visible again and was
+    /// popped by another consumer, or was already acknowledged.
+    pub async fn ack(&self, message: &Message) -> Result<bool> {
+        debug!(self.logger, "acknowledging queue message"; "id" => message.id);
+        let mut txn = self.begin().await?;
+        let key = self.key(message.id);
+        let result = async {
+            match txn.get_json::<MessageRecord>(key.clone()).await? {
+                Some(record) if record.deliveries == message.deliveries => {
+                    txn.delete(key).await?;
+                    Ok(true)
+                }
+                _ => Ok(false),
+            }
+        }
+        .await;
+        finish(txn, result).await
+    }
+
+    /// The number of messages in the queue, visible or not.
+    pub async fn len(&self) -> Result<usize> {
+        let mut txn = self.begin().await?;
+        let count = txn.count(BoundRange::prefix(self.prefix.clone())).await;
+        txn.rollback().await?;
+        count
+    }
+
+    async fn pop_once(&self) -> Result<Option<Message>> {
+        let mut txn = self.begin().await?;
+        let result = async {
+            let (id, mut record) = match self.first_visible(&mut txn).await? {
+                Some(first) => first,
+                None => return Ok(None),
+            };
+            let now = physical_ms(&txn.start_timestamp());
+            record.invisible_until = now + self.visibility_timeout.as_millis() as u64;
+            record.deliveries += 1;
+            txn.put_json(self.key(id), &record).await?;
+            Ok(Some(message(id, record)))
+        }
+        .await;
+        finish(txn, result).await
+    }
+
+    async fn first_visible(
+        &self,
+        txn: &mut Transaction<PdC>,
+    ) -> Result<Option<(u64, MessageRecord)>> {
+        let now = physical_ms(&txn.start_timestamp());
+        let (mut start, end) = BoundRange::prefix(self.prefix.clone()).into_keys();
+        loop {
+            let pairs: Vec<_> = txn
+                .scan((start, end.clone()), SCAN_BATCH_SIZE)
+                .await?
+                .collect();
+            for pair in &pairs {
+                let record = decode_record(pair.value())?;
+                if record.invisible_until <= now {
+                    return Ok(Some((self.id(pair.key()), record)));
+                }
+            }
+            match pairs.last() {
+                Some(last) if pairs.len() as u32 == SCAN_BATCH_SIZE => {
+                    start = last.key().clone();
+                    start.push_zero();
+                }
+                _ => return Ok(None),
+            }
+        }
+    }
+
+    async fn begin(&self) -> Result<Transaction<PdC>> {
+        let options = TransactionOptions::new_optimistic();
+        super::begin(&self.rpc, options, &self.logger).await
+    }
+
+    fn key(&self, id: u64) -> Key {
+        let mut key: Vec<u8> = self.prefix.clone().into();
+        key.extend_from_slice(&id.to_be_bytes());
+        key.into()
+    }
+
+    fn id(&self, key: &Key) -> u64 {
+        let key: &[u8] = key.into();
+        let mut id = [0; 8];
+        id.copy_from_slice(&key[key.len() - 8..]);
+        u64::from_be_bytes(id)
+    }
+}
+
+fn decode_record(value: &[u8]) -> Result<MessageRecord> {
+    serde_json::from_slice(value).map_err(|e| Error::ValueCodecError {
+        message: e.to_string(),
+    })
+}
+
+fn message(id: u64, record: MessageRecord) -> Message {
+    Message {
+        id,
+        payload: record.payload,
+        deliveries: record.deliveries,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::simulation::Fault;
+    use crate::simulation::Simulation;
+
+    #[tokio::test]
+    async fn test_queue() {
+        let sim = Simulation::new(12);
+        let logger = Logger::root(slog::Discard, o!());
+        let timeout = Duration::from_secs(30);
+        let queue = Queue::new(sim.pd_client(), b"q/".to_vec().into(), timeout, logger);
+        assert_eq!(queue.pop().await.unwrap(), None);
+        for payload in ["a", "b", "c"] {
+            queue.push(payload).await.unwrap();
+        }
+        assert_eq!(queue.len().await.unwrap(), 3);
+
+        let a = queue.peek().await.unwrap().unwrap();
+        assert_eq!((a.payload.as_slice(), a.deliveries), (&b"a"[..], 0));
+        let a = queue.pop().await.unwrap().unwrap();
+        assert_eq!((a.payload.as_slice(), a.deliveries), (&b"a"[..], 1));
+        let b = queue.pop().await.unwrap().unwrap();
+        assert_eq!(b.payload, b"b");
+        assert!(queue.ack(&b).await.unwrap());
+        assert!(!queue.ack(&b).await.unwrap());
+
+        // `a` was not acknowledged, so it is delivered again after the timeout.
+        sim.advance_clock(timeout);
+        let redelivered = queue.pop().await.unwrap().unwrap();
+        assert_eq!((redelivered.id, redelivered.deliveries), (a.id, 2));
+        assert!(!queue.ack(&a).await.unwrap());
+        assert!(queue.ack(&redelivered).await.unwrap());
+        assert_eq!(queue.pop().await.unwrap().unwrap().payload, b"c");
+        assert_eq!(queue.pop().await.unwrap(), None);
+        assert_eq!(queue.len().await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_queue_contention() {
+        let sim = Simulation::new(13);
+        let logger = Logger::root(slog::Discard, o!());
+        let timeout = Duration::from_secs(30);
+        let queue = Queue::new(sim.pd_client(), b"q/".to_vec().into(), timeout, logger);
+        for i in 0..10u8 {
+            queue.push(vec![i]).await.unwrap();
+        }
+
+        let popped = futures::future::join_all((0..10).map(|_| queue.pop())).await;
+        let mut payloads = HashSet::new();
+        for message in popped.into_iter().flat_map(|message| message.unwrap()) {
+            assert!(payloads.insert(message.payload), "popped twice");
+        }
+        assert!(!payloads.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_queue_read_error() {
+        let sim = Simulation::new(14);
+        let logger = Logger::root(slog::Discard, o!());
+        let timeout = Duration::from_secs(30);
+        let queue = Queue::new(sim.pd_client(), b"q/".to_vec().into(), timeout, logger);
+        queue.push("a").await.unwrap();
+        queue.push("b").await.unwrap();
+        let a = queue.pop().await.unwrap().unwrap();
+
+        // Failed reads are returned, and their transactions rolled back rather than dropped while
+        // active, which would panic.
+        sim.inject_faults(|request| match request.label {
+            "kv_get" | "kv_scan" => vec![Fault::ServerIsBusy { backoff_ms: 1 }],
+            _ => Vec::new(),
+        });
+        assert!(queue.pop().await.is_err());
+        assert!(queue.ack(&a).await.is_err());
+
+        sim.inject_faults(|_| Vec::new());
+        assert!(queue.ack(&a).await.unwrap());
+        assert_eq!(queue.pop().await.unwrap().unwrap().payload, b"b");
+    }
+}
@@ -0,0 +1,183 @@
+// Copyright 2023 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Unique, increasing ids shared by all clients of a cluster.
+
+use std::ops::Range;
+use std::sync::Arc;
+
+use slog::Logger;
+use tokio::sync::Mutex;
+
+use crate::pd::PdClient;
+use crate::pd::PdRpcClient;
+use crate::Error;
+use crate::Key;
+use crate::Result;
+use crate::TransactionOptions;
+
+const DEFAULT_BATCH_SIZE: u64 = 100;
+/// How many times an allocation is attempted when it conflicts with a concurrent one.
+const MAX_ALLOCATE_ATTEMPTS: usize = 10;
+
+/// A generator of ids, backed by a counter stored under a key.
+///
+/// Ids are allocated from the counter in batches, and handed out from the current batch without
+/// contacting TiKV. Ids are unique across all sequences using the same key, and increase within
+/// each sequence, but the sequences of different clients interleave, and ids left in a batch when
+/// a sequence is dropped are never used. The first id is 1.
+///
+/// The counter holds the next id to allocate, as 8 big-endian bytes.
+///
+/// Create one with [`TransactionClient::sequence`](crate::TransactionClient::sequence).
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// # use tikv_client::{Config, TransactionClient};
+/// # use futures::prelude::*;
+/// # futures::executor::block_on(async {
+/// let client = TransactionClient::new(vec!["192.168.0.100"], None).await.unwrap();
+/// let sequence = client.sequence("sequences/user_id".to_owned()).batch_size(1000);
+/// let id = sequence.next().await.unwrap();
+/// let ids = sequence.allocate(50).await.unwrap();
+/// # });
+/// ```
+pub struct Sequence<PdC: PdClient = PdRpcClient> {
+    rpc: Arc<PdC>,
+    key: Key,
+    batch_size: u64,
+    cached: Mutex<Range<u64>>,
+    logger: Logger,
+}
+
+impl<PdC: PdClient> Sequence<PdC> {
+    pub(crate) fn new(rpc: Arc<PdC>, key: Key, logger: Logger) -> Sequence<PdC> {
+        Sequence {
+            rpc,
+            key,
+            batch_size: DEFAULT_BATCH_SIZE,
+            cached: Mutex::new(0..0),
+            logger,
+        }
+    }
+
+    /// Set how many ids are allocated from the counter at a time.
+    #[must_use]
+    pub fn batch_size(mut self, batch_size: u64) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// The key the counter is stored under.
+    pub fn key(&self) -> &Key {
+        &self.key
+    }
+
+    /// Get the next id.
+    pub async fn next(&self) -> Result<u64> {
+        let mut cached = self.cached.lock().await;
+        if cached.is_empty() {
+            *cached = self.allocate(self.batch_size).await?;
+        }
+        let id = cached.start;
+        cached.start += 1;
+        Ok(id)
+    }
+
+    /// Allocate `count` consecutive ids from the counter, bypassing the current batch.
+    pub async fn allocate(&self, count: u64) -> Result<Range<u64>> {
+        debug!(self.logger, "allocating ids from sequence"; "count" => count);
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            match self.allocate_once(count).await {
+                Err(e) if e.is_conflict() && attempts < MAX_ALLOCATE_ATTEMPTS => {
+                    debug!(self.logger, "sequence allocation conflicted, retrying"; "error" => %e);
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn allocate_once(&self, count: u64) -> Result<Range<u64>> {
+        let options = TransactionOptions::new_pessimistic();
+        let mut txn = super::begin(&self.rpc, options, &self.logger).await?;
+        let result = async {
+            let start = match txn.get_for_update(self.key.clone()).await? {
+                Some(value) => decode_counter(&value)?,
+                None => 1,
+            };
+            let end = start.checked_add(count).ok_or_else(|| {
+                Error::StringError(format!("sequence {:?} is exhausted", self.key))
+            })?;
+            let value = end.to_be_bytes().to_vec();
+            txn.put(self.key.clone(), value).await?;
+            Ok(start..end)
+        }
+        .await;
+        match result {
+            Ok(ids) => {
+                txn.commit().await?;
+                Ok(ids)
+            }
+            Err(e) => {
+                txn.rollback().await?;
+                Err(e)
+            }
+        }
+    }
+}
+
+fn decode_counter(value: &[u8]) -> Result<u64> {
+    match <[u8; 8]>::try_from(value) {
+        Ok(bytes) => Ok(u64::from_be_bytes(bytes)),
+        Err(_) => Err(Error::ValueCodecError {
+            message: format!("sequence counter must be 8 bytes, got {}", value.len()),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::simulation::Simulation;
+
+    #[tokio::test]
+    async fn test_sequence() {
+        let sim = Simulation::new(11);
+        let logger = Logger::root(slog::Discard, o!());
+        let sequences: Vec<_> = (0..4)
+            .map(|_| Sequence::new(sim.pd_client(), vec![1].into(), logger.clone()).batch_size(7))
+            .collect();
+
+        let ids = futures::future::join_all(sequences.iter().map(|sequence| async move {
+            let mut ids = Vec::new();
+            for _ in 0..20 {
+                ids.push(sequence.next().await.unwrap());
+            }
+            ids
+        }))
+        .await;
+        let mut unique = HashSet::new();
+        for ids in ids {
+            assert!(ids.windows(2).all(|pair| pair[0] < pair[1]), "{ids:?}");
+            for id in ids {
+                assert!(unique.insert(id), "{id} was allocated twice");
+            }
+        }
+        assert_eq!(unique.len(), 80);
+        // 12 batches of 7 ids were allocated.
+        assert_eq!(sequences[0].allocate(3).await.unwrap(), 85..88);
+
+        let mut txn = sim.begin_optimistic().await.unwrap();
+        txn.put(vec![2], vec![0; 4]).await.unwrap();
+        txn.commit().await.unwrap();
+        let corrupt = Sequence::new(sim.pd_client(), vec![2].into(), logger);
+        assert!(matches!(
+            corrupt.next().await,
+            Err(Error::ValueCodecError { .. })
+        ));
+    }
+}
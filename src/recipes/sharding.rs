@@ -0,0 +1,363 @@
+// Copyright 2023 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Spreading keys which would otherwise be written in order, such as those starting with a
+//! timestamp or an id from a [`Sequence`](super::Sequence), across several ranges of the
+//! cluster.
+
+use std::fmt;
+use std::sync::Arc;
+
+use crate::kv::prefixed_key;
+use crate::kv::prefixed_range;
+use crate::pd::PdClient;
+use crate::raw::Client as RawClient;
+use crate::transaction::Transaction;
+use crate::BoundRange;
+use crate::Error;
+use crate::Key;
+use crate::KvPair;
+use crate::Result;
+
+/// A hash of keys, which picks the shards of a [`ShardedKey`].
+///
+/// The hash of a key must never change once keys have been written with it, or they could not be
+/// found again.
+pub trait KeyHasher: Send + Sync {
+    fn hash(&self, key: &[u8]) -> u64;
+}
+
+impl<F: Fn(&[u8]) -> u64 + Send + Sync> KeyHasher for F {
+    fn hash(&self, key: &[u8]) -> u64 {
+        self(key)
+    }
+}
+
+/// The 64-bit FNV-1a hash, which is the same on all platforms and in all versions of the client.
+struct Fnv1a;
+
+impl KeyHasher for Fnv1a {
+    fn hash(&self, key: &[u8]) -> u64 {
+        key.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+            (hash ^ u64::from(*byte)).wrapping_mul(0x0000_0100_0000_01b3)
+        })
+    }
+}
+
+/// Places a byte derived from the hash of a key between a base prefix and the key, so that keys
+/// written in increasing order are spread across `shards` ranges of the cluster rather than all
+/// landing in its last region.
+///
+/// The byte is the shard of the key, the key's hash modulo the number of shards. A key's shard
+/// can be computed from the key alone, so point reads and writes still touch a single key, but a
+/// range of keys is spread across all shards: [`scan`](ShardedKey::scan) and the other scans scan
+/// the range in each shard and merge the results in order of the keys. Scans only read keys
+/// starting with the base prefix, and skip those which are not the stored key of any key.
+///
+/// Keys are hashed with FNV-1a, unless another [`KeyHasher`] is set. The base prefix, the number
+/// of shards and the hasher must stay the same for as long as the keys are stored.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// # use tikv_client::RawClient;
+/// # use tikv_client::recipes::ShardedKey;
+/// # use futures::prelude::*;
+/// # futures::executor::block_on(async {
+/// let client = RawClient::new(vec!["192.168.0.100"], None).await.unwrap();
+/// let sharding = ShardedKey::new(b"events/".to_vec(), 16);
+/// for id in 0u64..1000 {
+///     let key = sharding.encode(id.to_be_bytes().to_vec());
+///     client.put(key, b"event".to_vec()).await.unwrap();
+/// }
+/// // The first 10 ids, read from all 16 shards.
+/// let events = sharding.scan(&client, .., 10).await.unwrap();
+/// # });
+/// ```
+#[derive(Clone)]
+pub struct ShardedKey {
+    prefix: Key,
+    shards: u8,
+    hasher: Arc<dyn KeyHasher>,
+}
+
+impl ShardedKey {
+    /// Spread the keys stored after `prefix` across `shards` shards. Zero shards are taken as one.
+    pub fn new(prefix: impl Into<Key>, shards: u8) -> ShardedKey {
+        ShardedKey {
+            prefix: prefix.into(),
+            shards: shards.max(1),
+            hasher: Arc::new(Fnv1a),
+        }
+    }
+
+    /// Pick the shards of keys with `hasher` rather than FNV-1a.
+    #[must_use]
+    pub fn hasher(mut self, hasher: impl KeyHasher + 'static) -> Self {
+        self.hasher = Arc::new(hasher);
+        self
+    }
+
+    /// The base prefix of the stored keys.
+    pub fn prefix(&self) -> &Key {
+        &self.prefix
+    }
+
+    /// The number of shards.
+    pub fn shards(&self) -> u8 {
+        self.shards
+    }
+
+    /// The shard of `key`.
+    pub fn shard(&self, key: &[u8]) -> u8 {
+        (self.hasher.hash(key) % u64::from(self.shards)) as u8
+    }
+
+    /// The stored key of `key`: the base prefix, then the shard of `key`, then `key`.
+    pub fn encode(&self, key: impl Into<Key>) -> Key {
+        let key = key.into();
+        let shard = self.shard((&key).into());
+        prefixed_key(&self.shard_prefix(shard), key)
+    }
+
+    /// The key whose stored key is `key`, or `None` if `key` is not the stored key of any key.
+    pub fn decode(&self, key: impl Into<Key>) -> Option<Key> {
+        let key = Vec::from(key.into());
+        let key = key.strip_prefix(<&[u8]>::from(&self.prefix))?;
+        let (shard, suffix) = key.split_first()?;
+        (*shard == self.shard(suffix)).then(|| suffix.to_vec().into())
+    }
+
+    /// The ranges of stored keys of the keys in `range`, one for each shard.
+    pub fn ranges(&self, range: impl Into<BoundRange>) -> Vec<BoundRange> {
+        let range = range.into();
+        (0..self.shards)
+            .map(|shard| prefixed_range(&self.shard_prefix(shard), range.clone()))
+            .collect()
+    }
+
+    /// Read at most `limit` pairs of the keys in `range`, in order of the keys, from a raw client.
+    /// The shards are scanned concurrently.
+    pub async fn scan<PdC: PdClient>(
+        &self,
+        client: &RawClient<PdC>,
+        range: impl Into<BoundRange>,
+        limit: u32,
+    ) -> Result<Vec<KvPair>> {
+        let scans = self.ranges(range).into_iter().map(|mut range| async move {
+            let mut pairs = Vec::new();
+            loop {
+                let page = client.scan(range.clone(), limit).await?;
+                let rest = remaining(&range, &page, limit, KvPair::key);
+                pairs.extend(page.into_iter().filter_map(|pair| self.decode_pair(pair)));
+                match rest {
+                    Some(rest) if pairs.len() < limit as usize => range = rest,
+                    _ => return Ok::<_, Error>(pairs),
+                }
+            }
+        });
+        let shards = futures::future::try_join_all(scans).await?;
+        Ok(merge(shards.into_iter().flatten(), limit, KvPair::key))
+    }
+
+    /// Read at most `limit` keys of `range`, in order, from a raw client. The shards are scanned
+    /// concurrently.
+    pub async fn scan_keys<PdC: PdClient>(
+        &self,
+        client: &RawClient<PdC>,
+        range: impl Into<BoundRange>,
+        limit: u32,
+    ) -> Result<Vec<Key>> {
+        let scans = self.ranges(range).into_iter().map(|mut range| async move {
+            let mut keys = Vec::new();
+            loop {
+                let page = client.scan_keys(range.clone(), limit).await?;
+                let rest = remaining(&range, &page, limit, |key| key);
+                keys.extend(page.into_iter().filter_map(|key| self.decode(key)));
+                match rest {
+                    Some(rest) if keys.len() < limit as usize => range = rest,
+                    _ => return Ok::<_, Error>(keys),
+                }
+            }
+        });
+        let shards = futures::future::try_join_all(scans).await?;
+        Ok(merge(shards.into_iter().flatten(), limit, |key| key))
+    }
+
+    /// Read at most `limit` pairs of the keys in `range`, in order of the keys, in a transaction.
+    /// The shards are scanned one after another.
+    pub async fn scan_in<PdC: PdClient>(
+        &self,
+        txn: &mut Transaction<PdC>,
+        range: impl Into<BoundRange>,
+        limit: u32,
+    ) -> Result<Vec<KvPair>> {
+        let mut pairs = Vec::new();
+        for mut range in self.ranges(range) {
+            let mut shard_pairs = 0;
+            loop {
+                let page: Vec<KvPair> = txn.scan(range.clone(), limit).await?.collect();
+                let rest = remaining(&range, &page, limit, KvPair::key);
+                let decoded = page.into_iter().filter_map(|pair| self.decode_pair(pair));
+                let len = pairs.len();
+                pairs.extend(decoded);
+                shard_pairs += pairs.len() - len;
+                match rest {
+                    Some(rest) if shard_pairs < limit as usize => range = rest,
+                    _ => break,
+                }
+            }
+        }
+        Ok(merge(pairs, limit, KvPair::key))
+    }
+
+    /// The prefix of the stored keys of the keys in `shard`.
+    fn shard_prefix(&self, shard: u8) -> Vec<u8> {
+        let mut prefix = Vec::from(self.prefix.clone());
+        prefix.push(shard);
+        prefix
+    }
+
+    fn decode_pair(&self, pair: KvPair) -> Option<KvPair> {
+        let (key, value) = pair.into();
+        Some(KvPair::new(self.decode(key)?, value))
+    }
+}
+
+impl fmt::Debug for ShardedKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ShardedKey")
+            .field("prefix", &self.prefix)
+            .field("shards", &self.shards)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The rest of `range` after `page`, a page of at most `limit` items read from its start, or
+/// `None` if the page ended the range.
+fn remaining<T>(
+    range: &BoundRange,
+    page: &[T],
+    limit: u32,
+    key: impl Fn(&T) -> &Key,
+) -> Option<BoundRange> {
+    if page.len() < limit as usize {
+        return None;
+    }
+    let mut next = key(page.last()?).clone();
+    next.push_zero();
+    range.split_at(next).1
+}
+
+/// Keep the first `limit` of the items read from each shard, in order of their keys. Each shard
+/// read at least the first `limit` of its items, or all of them, so these are the first of all.
+fn merge<T>(items: impl IntoIterator<Item = T>, limit: u32, key: impl Fn(&T) -> &Key) -> Vec<T> {
+    let mut items: Vec<T> = items.into_iter().collect();
+    items.sort_by(|a, b| key(a).cmp(key(b)));
+    items.truncate(limit as usize);
+    items
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::simulation::Simulation;
+
+    #[test]
+    fn test_encode() {
+        let sharding = ShardedKey::new(b"e/".to_vec(), 8);
+        let key = Key::from(b"event-1".to_vec());
+        let encoded = sharding.encode(key.clone());
+        assert_eq!(Vec::from(encoded.clone())[..2], b"e/"[..]);
+        assert_eq!(Vec::from(encoded.clone())[3..], b"event-1"[..]);
+        assert_eq!(sharding.decode(encoded), Some(key));
+        assert_eq!(sharding.decode(Vec::new()), None);
+        assert_eq!(sharding.decode(b"e/".to_vec()), None);
+        let wrong_shard = (sharding.shard(b"event-1") + 1) % 8;
+        let wrong = prefixed_key(&sharding.shard_prefix(wrong_shard), b"event-1".to_vec());
+        assert_eq!(sharding.decode(wrong), None);
+        let other_prefix = ShardedKey::new(b"f/".to_vec(), 8);
+        assert_eq!(other_prefix.decode(sharding.encode(b"event-1".to_vec())), None);
+
+        let by_length = ShardedKey::new(Vec::new(), 4).hasher(|key: &[u8]| key.len() as u64);
+        assert_eq!(by_length.shard(b"abcdef"), 2);
+        assert_eq!(ShardedKey::new(Vec::new(), 0).shards(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_scan() {
+        let sim = Simulation::new(21);
+        let client = sim.raw_client();
+        let sharding = ShardedKey::new(b"e/".to_vec(), 4);
+        let key = |id: u64| Key::from(id.to_be_bytes().to_vec());
+        for id in 0..40 {
+            client
+                .put(sharding.encode(key(id)), id.to_string())
+                .await
+                .unwrap();
+        }
+        // Consecutive keys are spread across shards.
+        let shards: HashSet<u8> = (0..40u64)
+            .map(|id| sharding.shard(&id.to_be_bytes()))
+            .collect();
+        assert!(shards.len() > 1);
+
+        let pairs = sharding.scan(&client, key(10)..key(20), 5).await.unwrap();
+        let expected: Vec<KvPair> = (10..15)
+            .map(|id| KvPair::new(key(id), id.to_string()))
+            .collect();
+        assert_eq!(pairs, expected);
+        let keys = sharding.scan_keys(&client, key(35).., 10).await.unwrap();
+        assert_eq!(keys, (35..40).map(key).collect::<Vec<_>>());
+
+        let mut txn = sim.begin_optimistic().await.unwrap();
+        for id in 200..205 {
+            txn.put(sharding.encode(key(id)), id.to_string())
+                .await
+                .unwrap();
+        }
+        txn.commit().await.unwrap();
+        let mut txn = sim.begin_optimistic().await.unwrap();
+        txn.put(sharding.encode(key(300)), "300").await.unwrap();
+        let pairs = sharding.scan_in(&mut txn, key(202).., 10).await.unwrap();
+        let keys: Vec<Key> = pairs.into_iter().map(Key::from).collect();
+        assert_eq!(keys, vec![key(202), key(203), key(204), key(300)]);
+        txn.rollback().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_scan_skips_foreign_keys() {
+        let sim = Simulation::new(22);
+        let client = sim.raw_client();
+        let sharding = ShardedKey::new(b"e/".to_vec(), 2);
+        let key = |id: u64| Key::from(id.to_be_bytes().to_vec());
+        for id in 1000..1010 {
+            client
+                .put(sharding.encode(key(id)), id.to_string())
+                .await
+                .unwrap();
+        }
+        // Each shard starts with more keys than the limit which are the stored keys of no key, as
+        // their shard is not that of their suffix.
+        for shard in 0..2 {
+            let foreign = (0u64..)
+                .filter(|id| sharding.shard(&id.to_be_bytes()) != shard)
+                .take(6);
+            for id in foreign {
+                let foreign = prefixed_key(&sharding.shard_prefix(shard), key(id));
+                client.put(foreign, b"foreign".to_vec()).await.unwrap();
+            }
+        }
+        client.put(b"e".to_vec(), b"outside".to_vec()).await.unwrap();
+        client.put(b"f".to_vec(), b"outside".to_vec()).await.unwrap();
+
+        let pairs = sharding.scan(&client, .., 4).await.unwrap();
+        let expected: Vec<KvPair> = (1000..1004)
+            .map(|id| KvPair::new(key(id), id.to_string()))
+            .collect();
+        assert_eq!(pairs, expected);
+        let keys = sharding.scan_keys(&client, .., 4).await.unwrap();
+        assert_eq!(keys, (1000..1004).map(key).collect::<Vec<_>>());
+    }
+}
@@ -24,6 +24,19 @@ pub struct RegionVerId {
     pub ver: u64,
 }
 
+/// A region covering part of a client's keys, and where its leader is.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RegionInfo {
+    pub id: RegionId,
+    pub start_key: Key,
+    /// The end of the region, exclusive. Empty if the region extends to the end of the keys.
+    pub end_key: Key,
+    /// The store of the region's leader, if it has one.
+    pub leader_store_id: Option<StoreId>,
+    /// The address of the store requests to the region are sent to.
+    pub store_address: String,
+}
+
 /// Information about a TiKV region and its leader.
 ///
 /// In TiKV all data is partitioned by range. Each partition is called a region.
@@ -3,6 +3,9 @@
 use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::ops::Bound;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 
 use tikv_client_common::Error;
@@ -49,12 +52,34 @@ impl RegionCacheMap {
             on_my_way_id: HashMap::new(),
         }
     }
+
+    fn remove(&mut self, ver_id: &RegionVerId) {
+        if let Some(region) = self.ver_id_to_region.remove(ver_id) {
+            self.id_to_ver_id.remove(&region.id());
+            self.key_to_ver_id.remove(&region.start_key());
+        }
+    }
+}
+
+/// A snapshot of the state of a client's region cache.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RegionCacheStats {
+    /// The number of regions in the cache.
+    pub regions: usize,
+    /// The number of stores in the cache.
+    pub stores: usize,
+    /// The number of region lookups answered from the cache.
+    pub hits: u64,
+    /// The number of region lookups which had to query PD.
+    pub misses: u64,
 }
 
 pub struct RegionCache<Client = RetryClient<Cluster>> {
     region_cache: RwLock<RegionCacheMap>,
     store_cache: RwLock<HashMap<StoreId, Store>>,
     inner_client: Arc<Client>,
+    hits: AtomicU64,
+    misses: AtomicU64,
 }
 
 impl<Client> RegionCache<Client> {
@@ -63,6 +88,8 @@ impl<Client> RegionCache<Client> {
             region_cache: RwLock::new(RegionCacheMap::new()),
             store_cache: RwLock::new(HashMap::new()),
             inner_client,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
         }
     }
 }
@@ -86,10 +113,12 @@ impl<C: RetryClientTrait> RegionCache<C> {
                 .unwrap();
 
             if region.contains(key) {
+                self.hits.fetch_add(1, Ordering::Relaxed);
                 return Ok(region.clone());
             }
         }
         drop(region_cache_guard);
+        self.misses.fetch_add(1, Ordering::Relaxed);
         self.read_through_region_by_key(key.clone()).await
     }
 
@@ -102,6 +131,7 @@ impl<C: RetryClientTrait> RegionCache<C> {
             let ver_id = region_cache_guard.id_to_ver_id.get(&id);
             if let Some(ver_id) = ver_id {
                 let region = region_cache_guard.ver_id_to_region.get(ver_id).unwrap();
+                self.hits.fetch_add(1, Ordering::Relaxed);
                 return Ok(region.clone());
             }
 
@@ -114,6 +144,7 @@ impl<C: RetryClientTrait> RegionCache<C> {
                 n.await;
                 continue;
             } else {
+                self.misses.fetch_add(1, Ordering::Relaxed);
                 return self.read_through_region_by_id(id).await;
             }
         }
@@ -196,9 +227,7 @@ impl<C: RetryClientTrait> RegionCache<C> {
         }
 
         for ver_id in to_be_removed {
-            let region_to_remove = cache.ver_id_to_region.remove(&ver_id).unwrap();
-            cache.key_to_ver_id.remove(&region_to_remove.start_key());
-            cache.id_to_ver_id.remove(&region_to_remove.id());
+            cache.remove(&ver_id);
         }
         cache
             .key_to_ver_id
@@ -222,14 +251,44 @@ impl<C: RetryClientTrait> RegionCache<C> {
     }
 
     pub async fn invalidate_region_cache(&self, ver_id: crate::region::RegionVerId) {
+        self.region_cache.write().await.remove(&ver_id);
+    }
+
+    /// Remove all cached regions which overlap the range `[start_key, end_key)`. An empty
+    /// `end_key` means the range is unbounded. Returns the number of regions removed.
+    pub async fn invalidate_range(&self, start_key: &Key, end_key: &Key) -> usize {
         let mut cache = self.region_cache.write().await;
-        let region_entry = cache.ver_id_to_region.get(&ver_id);
-        if let Some(region) = region_entry {
-            let id = region.id();
-            let start_key = region.start_key();
-            cache.ver_id_to_region.remove(&ver_id);
-            cache.id_to_ver_id.remove(&id);
-            cache.key_to_ver_id.remove(&start_key);
+        let mut to_be_removed = Vec::new();
+        if let Some((_, ver_id)) = cache.key_to_ver_id.range(..=start_key).next_back() {
+            let region = cache.ver_id_to_region.get(ver_id).unwrap();
+            if region.contains(start_key) {
+                to_be_removed.push(ver_id.clone());
+            }
+        }
+        let upper = if end_key.is_empty() {
+            Bound::Unbounded
+        } else {
+            Bound::Excluded(end_key)
+        };
+        to_be_removed.extend(
+            cache
+                .key_to_ver_id
+                .range((Bound::Excluded(start_key), upper))
+                .map(|(_, ver_id)| ver_id.clone()),
+        );
+
+        for ver_id in &to_be_removed {
+            cache.remove(ver_id);
+        }
+        to_be_removed.len()
+    }
+
+    pub async fn stats(&self) -> RegionCacheStats {
+        RegionCacheStats {
+            regions: self.region_cache.read().await.ver_id_to_region.len(),
+            stores: self.store_cache.read().await.len(),
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
         }
     }
 }
@@ -312,6 +371,15 @@ mod test {
         async fn update_safepoint(self: Arc<Self>, _safepoint: u64) -> Result<bool> {
             todo!()
         }
+
+        async fn update_service_safepoint(
+            self: Arc<Self>,
+            _service_id: String,
+            _ttl: i64,
+            _safepoint: u64,
+        ) -> Result<u64> {
+            todo!()
+        }
     }
 
     #[tokio::test]
@@ -480,6 +548,37 @@ mod test {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_invalidate_range_and_stats() -> Result<()> {
+        let retry_client = Arc::new(MockRetryClient::default());
+        let cache = RegionCache::new(retry_client.clone());
+
+        cache.add_region(region(1, vec![], vec![10])).await;
+        cache.add_region(region(2, vec![10], vec![20])).await;
+        cache.add_region(region(3, vec![30], vec![40])).await;
+        cache.add_region(region(4, vec![50], vec![])).await;
+
+        cache.get_region_by_key(&vec![5].into()).await?;
+        assert!(cache.get_region_by_key(&vec![25].into()).await.is_err());
+        let stats = cache.stats().await;
+        assert_eq!(stats.regions, 4);
+        assert_eq!(stats.hits, 1);
+        assert_eq!(stats.misses, 1);
+
+        assert_eq!(
+            cache.invalidate_range(&vec![15].into(), &vec![35].into()).await,
+            2
+        );
+        let mut expected_cache = BTreeMap::new();
+        expected_cache.insert(vec![].into(), region(1, vec![], vec![10]));
+        expected_cache.insert(vec![50].into(), region(4, vec![50], vec![]));
+        assert(&cache, &expected_cache).await;
+
+        assert_eq!(cache.invalidate_range(&vec![].into(), &vec![].into()).await, 2);
+        assert_eq!(cache.stats().await.regions, 0);
+        Ok(())
+    }
+
     // a helper function to assert the cache is in expected state
     async fn assert(
         cache: &RegionCache<MockRetryClient>,
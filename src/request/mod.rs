@@ -1,5 +1,11 @@
 // Copyright 2019 TiKV Project Authors. Licensed under Apache-2.0.
 
+use std::collections::HashSet;
+use std::sync::atomic::AtomicU32;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+
 use async_trait::async_trait;
 use derive_new::new;
 use tikv_client_store::HasKeyErrors;
@@ -25,11 +31,13 @@ pub use self::plan_builder::SingleKey;
 pub use self::shard::Batchable;
 pub use self::shard::HasNextBatch;
 pub use self::shard::NextBatch;
+pub use self::shard::ShardKey;
 pub use self::shard::Shardable;
 use crate::backoff::Backoff;
 use crate::backoff::DEFAULT_REGION_BACKOFF;
 use crate::backoff::OPTIMISTIC_BACKOFF;
 use crate::backoff::PESSIMISTIC_BACKOFF;
+use crate::region::RegionId;
 use crate::transaction::HasLocks;
 
 pub mod plan;
@@ -75,6 +83,46 @@ impl RetryOptions {
     }
 }
 
+/// Records the regions a plan touches and how often it retries.
+///
+/// A `RetryStats` is shared by all the plans built from a
+/// [`PlanBuilder`](PlanBuilder::record_retries), and by any clones of it.
+#[derive(Clone, Debug, Default)]
+pub struct RetryStats {
+    region_retries: Arc<AtomicU32>,
+    lock_retries: Arc<AtomicU32>,
+    regions: Arc<Mutex<HashSet<RegionId>>>,
+}
+
+impl RetryStats {
+    /// The number of times a request was retried because of a region error.
+    pub fn region_retries(&self) -> u32 {
+        self.region_retries.load(Ordering::Relaxed)
+    }
+
+    /// The number of times a request was retried after encountering locks.
+    pub fn lock_retries(&self) -> u32 {
+        self.lock_retries.load(Ordering::Relaxed)
+    }
+
+    /// The number of distinct regions requests were sent to.
+    pub fn region_count(&self) -> usize {
+        self.regions.lock().unwrap().len()
+    }
+
+    pub(crate) fn on_region_retry(&self) {
+        self.region_retries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn on_lock_retry(&self) {
+        self.lock_retries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn on_region(&self, region_id: RegionId) {
+        self.regions.lock().unwrap().insert(region_id);
+    }
+}
+
 #[cfg(test)]
 mod test {
     use std::any::Any;
@@ -139,6 +187,10 @@ mod test {
             fn set_context(&mut self, _: kvrpcpb::Context) {
                 unreachable!();
             }
+
+            fn encoded_len(&self) -> usize {
+                0
+            }
         }
 
         #[async_trait]
@@ -2,6 +2,8 @@
 
 use std::marker::PhantomData;
 use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
 
 use async_recursion::async_recursion;
 use async_trait::async_trait;
@@ -15,22 +17,33 @@ use tikv_client_store::HasRegionError;
 use tikv_client_store::HasRegionErrors;
 use tikv_client_store::KvClient;
 use tokio::sync::Semaphore;
-use tokio::time::sleep;
 
 use crate::backoff::Backoff;
+use crate::circuit_breaker::StoreHealth;
+use crate::clock::timeout_at;
+use crate::clock::Clock;
+use crate::fan_out::FanOutTracer;
 use crate::pd::PdClient;
+use crate::rate_limit::InFlightLimiter;
+use crate::rate_limit::RateLimiter;
 use crate::request::shard::HasNextBatch;
 use crate::request::KvRequest;
 use crate::request::NextBatch;
+use crate::request::RetryStats;
+use crate::request::ShardKey;
 use crate::request::Shardable;
+use crate::retry_observer::RetryEvent;
+use crate::retry_observer::RetryReason;
 use crate::stats::tikv_stats;
 use crate::store::RegionStore;
 use crate::transaction::resolve_locks;
 use crate::transaction::HasLocks;
+use crate::transaction::LockObserver;
 use crate::transaction::ResolveLocksContext;
 use crate::transaction::ResolveLocksOptions;
 use crate::util::iter::FlatMapOkIterExt;
 use crate::Error;
+use crate::ErrorContext;
 use crate::Result;
 
 /// A plan for how to execute a request. A user builds up a plan with various
@@ -49,6 +62,21 @@ pub trait Plan: Sized + Clone + Sync + Send + 'static {
 pub struct Dispatch<Req: KvRequest> {
     pub request: Req,
     pub kv_client: Option<Arc<dyn KvClient + Send + Sync>>,
+    /// If set, the request is not sent after this time, and is abandoned if it is still in
+    /// flight.
+    pub deadline: Option<Instant>,
+    /// If set, the request waits for the limiter before it is sent.
+    pub rate_limiter: Option<Arc<RateLimiter>>,
+    /// If set, the request fails fast if its store is unhealthy, and its outcome is recorded.
+    pub store_health: Option<Arc<StoreHealth>>,
+    /// If set, the request waits until fewer requests are in flight to its store than the limit.
+    pub in_flight_limiter: Option<Arc<InFlightLimiter>>,
+    /// The address of the store the request is sent to.
+    pub store_address: Option<String>,
+    /// If set, the tag set on the request's context once its region is known.
+    pub resource_group_tag: Option<Vec<u8>>,
+    /// The clock the deadline is measured by.
+    pub clock: Arc<dyn Clock>,
 }
 
 #[async_trait]
@@ -56,22 +84,73 @@ impl<Req: KvRequest> Plan for Dispatch<Req> {
     type Result = Req::Response;
 
     async fn execute(&self) -> Result<Self::Result> {
-        let stats = tikv_stats(self.request.label());
-        let result = self
+        let now = self.clock.now();
+        if self.deadline.is_some_and(|deadline| now >= deadline) {
+            return Err(Error::DeadlineExceeded);
+        }
+        let stats = tikv_stats(self.request.label(), self.store_address.as_deref());
+        let kv_client = self
             .kv_client
             .as_ref()
-            .expect("Unreachable: kv_client has not been initialised in Dispatch")
-            .dispatch(&self.request)
-            .await;
+            .expect("Unreachable: kv_client has not been initialised in Dispatch");
+        let store_health = match (&self.store_health, &self.store_address) {
+            (Some(health), Some(address)) => Some((health, address)),
+            _ => None,
+        };
+        let dispatch = async {
+            if let Some(limiter) = &self.rate_limiter {
+                limiter.acquire(self.request.encoded_len()).await;
+            }
+            let _in_flight = match (&self.in_flight_limiter, &self.store_address) {
+                (Some(limiter), Some(address)) => Some(limiter.acquire(address).await),
+                _ => None,
+            };
+            if let Some((health, address)) = store_health {
+                health.check(address)?;
+            }
+            let start = Instant::now();
+            let result = kv_client.dispatch(&self.request).await;
+            if let Some((health, address)) = store_health {
+                // Only errors from the store's connection say anything about its health.
+                match &result {
+                    Ok(_) => health.record(address, true, start.elapsed()),
+                    Err(Error::Grpc(_)) | Err(Error::GrpcAPI(_)) => {
+                        health.record(address, false, start.elapsed())
+                    }
+                    Err(_) => {}
+                }
+            }
+            result
+        };
+        let result = match self.deadline {
+            Some(deadline) => timeout_at(self.clock.as_ref(), deadline, dispatch)
+                .await
+                .unwrap_or(Err(Error::DeadlineExceeded)),
+            None => dispatch.await,
+        };
         let result = stats.done(result);
-        result.map(|r| {
-            *r.downcast()
-                .expect("Downcast failed: request and response type mismatch")
-        })
+        result
+            .map(|r| {
+                *r.downcast()
+                    .expect("Downcast failed: request and response type mismatch")
+            })
+            .map_err(|e| {
+                e.with_context(ErrorContext {
+                    operation: Some(self.request.label()),
+                    ..Default::default()
+                })
+            })
     }
 }
 
-const MULTI_REGION_CONCURRENCY: usize = 16;
+pub(crate) const MULTI_REGION_CONCURRENCY: usize = 16;
+
+/// Tell the client's retry observer, if it has one, about a retry.
+fn notify_retry(pd_client: &impl PdClient, event: RetryEvent) {
+    if let Some(observer) = pd_client.retry_observer() {
+        observer.on_retry(event);
+    }
+}
 
 pub struct RetryableMultiRegion<P: Plan, PdC: PdClient> {
     pub(super) inner: P,
@@ -82,12 +161,22 @@ pub struct RetryableMultiRegion<P: Plan, PdC: PdClient> {
     /// If true, return Ok and preserve all regions' results, even if some of them are Err.
     /// Otherwise, return the first Err if there is any.
     pub preserve_region_results: bool,
+
+    /// Where to record the regions touched and retries made, if anywhere.
+    pub stats: Option<RetryStats>,
+
+    /// Where to record the requests sent to each region, if anywhere.
+    pub tracer: Option<FanOutTracer>,
+
+    /// If set, retries are not backed off beyond this time.
+    pub deadline: Option<Instant>,
 }
 
 impl<P: Plan + Shardable, PdC: PdClient> RetryableMultiRegion<P, PdC>
 where P::Result: HasKeyErrors + HasRegionError
 {
     // A plan may involve multiple shards
+    #[allow(clippy::too_many_arguments)]
     #[async_recursion]
     async fn single_plan_handler(
         pd_client: Arc<PdC>,
@@ -95,21 +184,39 @@ where P::Result: HasKeyErrors + HasRegionError
         backoff: Backoff,
         permits: Arc<Semaphore>,
         preserve_region_results: bool,
+        stats: Option<RetryStats>,
+        tracer: Option<FanOutTracer>,
+        deadline: Option<Instant>,
     ) -> Result<<Self as Plan>::Result> {
         let shards = current_plan.shards(&pd_client).collect::<Vec<_>>().await;
         let mut handles = Vec::new();
         for shard in shards {
             let (shard, region_store) = shard?;
+            if let Some(stats) = &stats {
+                stats.on_region(region_store.region_with_leader.id());
+            }
+            let context = ErrorContext {
+                key: shard.shard_key(),
+                region_id: Some(region_store.region_with_leader.id()),
+                store_address: Some(region_store.address.clone()),
+                ..Default::default()
+            };
             let mut clone = current_plan.clone();
             clone.apply_shard(shard, &region_store)?;
-            let handle = tokio::spawn(Self::single_shard_handler(
-                pd_client.clone(),
-                clone,
-                region_store,
-                backoff.clone(),
-                permits.clone(),
-                preserve_region_results,
-            ));
+            let handle = tokio::spawn(
+                Self::single_shard_handler(
+                    pd_client.clone(),
+                    clone,
+                    region_store,
+                    backoff.clone(),
+                    permits.clone(),
+                    preserve_region_results,
+                    stats.clone(),
+                    tracer.clone(),
+                    deadline,
+                )
+                .map_err(move |e| e.with_context(context)),
+            );
             handles.push(handle);
         }
 
@@ -133,6 +240,7 @@ where P::Result: HasKeyErrors + HasRegionError
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     #[async_recursion]
     async fn single_shard_handler(
         pd_client: Arc<PdC>,
@@ -141,22 +249,76 @@ where P::Result: HasKeyErrors + HasRegionError
         mut backoff: Backoff,
         permits: Arc<Semaphore>,
         preserve_region_results: bool,
+        stats: Option<RetryStats>,
+        tracer: Option<FanOutTracer>,
+        deadline: Option<Instant>,
     ) -> Result<<Self as Plan>::Result> {
+        let retry_context = |e: Error, backoff: &Backoff| {
+            e.with_context(ErrorContext {
+                retry_attempts: Some(backoff.current_attempts()),
+                ..Default::default()
+            })
+        };
+
         // limit concurrent requests
         let permit = permits.acquire().await.unwrap();
-        let mut resp = plan.execute().await?;
+        let clock = pd_client.clock();
+        let start = clock.now();
+        let resp = plan.execute().await;
+        if let Some(tracer) = &tracer {
+            let region_id = region_store.region_with_leader.id();
+            let latency = clock.now().saturating_duration_since(start);
+            tracer.on_request(region_id, &region_store.address, latency);
+        }
+        let mut resp = resp.map_err(|e| retry_context(e, &backoff))?;
         drop(permit);
 
         if let Some(e) = resp.key_errors() {
             Ok(vec![Err(Error::MultipleKeyErrors(e))])
         } else if let Some(e) = resp.region_error() {
+            let mut event = RetryEvent {
+                reason: RetryReason::of_region_error(&e),
+                attempt: backoff.current_attempts() + 1,
+                delay: None,
+                will_retry: false,
+                region_id: Some(region_store.region_with_leader.id()),
+                store_address: Some(region_store.address.clone()),
+            };
             match backoff.next_delay_duration() {
                 Some(duration) => {
+                    // A busy store suggests how long to back off for, which is followed rather
+                    // than the client's own schedule, up to the longest delay of the schedule.
+                    let duration = match &e.server_is_busy {
+                        Some(busy) if busy.backoff_ms > 0 => {
+                            Duration::from_millis(busy.backoff_ms).min(backoff.max_delay())
+                        }
+                        _ => duration,
+                    };
+                    // Backing off past the deadline would only delay the request's failure.
+                    let duration = match deadline {
+                        Some(deadline) => {
+                            let left = deadline.saturating_duration_since(pd_client.clock().now());
+                            duration.min(left)
+                        }
+                        None => duration,
+                    };
+                    if let Some(stats) = &stats {
+                        stats.on_region_retry();
+                    }
+                    if let Some(tracer) = &tracer {
+                        tracer.on_retry(region_store.region_with_leader.id());
+                    }
                     let region_error_resolved =
-                        Self::handle_region_error(pd_client.clone(), e, region_store).await?;
+                        Self::handle_region_error(pd_client.clone(), e, region_store).await;
                     // don't sleep if we have resolved the region error
-                    if !region_error_resolved {
-                        sleep(duration).await;
+                    event.delay = match region_error_resolved {
+                        Ok(false) => Some(duration),
+                        Ok(true) | Err(_) => None,
+                    };
+                    event.will_retry = region_error_resolved.is_ok();
+                    notify_retry(pd_client.as_ref(), event);
+                    if !region_error_resolved? {
+                        pd_client.clock().sleep(duration).await;
                     }
                     Self::single_plan_handler(
                         pd_client,
@@ -164,10 +326,16 @@ where P::Result: HasKeyErrors + HasRegionError
                         backoff,
                         permits,
                         preserve_region_results,
+                        stats,
+                        tracer,
+                        deadline,
                     )
                     .await
                 }
-                None => Err(Error::RegionError(Box::new(e))),
+                None => {
+                    notify_retry(pd_client.as_ref(), event);
+                    Err(retry_context(Error::RegionError(Box::new(e)), &backoff))
+                }
             }
         } else {
             Ok(vec![Ok(resp)])
@@ -217,10 +385,9 @@ where P::Result: HasKeyErrors + HasRegionError
         } else if e.stale_command.is_some() || e.region_not_found.is_some() {
             pd_client.invalidate_region_cache(ver_id).await;
             Ok(false)
-        } else if e.server_is_busy.is_some()
-            || e.raft_entry_too_large.is_some()
-            || e.max_timestamp_not_synced.is_some()
-        {
+        } else if e.server_is_busy.is_some() {
+            Ok(false)
+        } else if e.raft_entry_too_large.is_some() || e.max_timestamp_not_synced.is_some() {
             Err(Error::RegionError(Box::new(e)))
         } else {
             // TODO: pass the logger around
@@ -278,6 +445,9 @@ impl<P: Plan, PdC: PdClient> Clone for RetryableMultiRegion<P, PdC> {
             pd_client: self.pd_client.clone(),
             backoff: self.backoff.clone(),
             preserve_region_results: self.preserve_region_results,
+            stats: self.stats.clone(),
+            tracer: self.tracer.clone(),
+            deadline: self.deadline,
         }
     }
 }
@@ -299,6 +469,9 @@ where P::Result: HasKeyErrors + HasRegionError
             self.backoff.clone(),
             concurrency_permits.clone(),
             self.preserve_region_results,
+            self.stats.clone(),
+            self.tracer.clone(),
+            self.deadline,
         )
         .await
     }
@@ -345,7 +518,11 @@ macro_rules! collect_first {
             type Out = $type_;
 
             fn merge(&self, mut input: Vec<Result<$type_>>) -> Result<Self::Out> {
-                assert!(input.len() == 1);
+                if input.len() != 1 {
+                    return Err($crate::Error::InconsistentResults {
+                        message: format!("expected 1 response, got {}", input.len()),
+                    });
+                }
                 input.pop().unwrap()
             }
         }
@@ -400,6 +577,10 @@ pub struct ResolveLock<P: Plan, PdC: PdClient> {
     pub inner: P,
     pub pd_client: Arc<PdC>,
     pub backoff: Backoff,
+    pub stats: Option<RetryStats>,
+    pub observer: Option<Arc<dyn LockObserver>>,
+    /// If set, locks are neither resolved nor waited for after this time.
+    pub deadline: Option<Instant>,
 }
 
 impl<P: Plan, PdC: PdClient> Clone for ResolveLock<P, PdC> {
@@ -408,6 +589,9 @@ impl<P: Plan, PdC: PdClient> Clone for ResolveLock<P, PdC> {
             inner: self.inner.clone(),
             pd_client: self.pd_client.clone(),
             backoff: self.backoff.clone(),
+            stats: self.stats.clone(),
+            observer: self.observer.clone(),
+            deadline: self.deadline,
         }
     }
 }
@@ -421,24 +605,58 @@ where P::Result: HasLocks
     async fn execute(&self) -> Result<Self::Result> {
         let mut result = self.inner.execute().await?;
         let mut clone = self.clone();
+        let mut attempt = 0;
         loop {
             let locks = result.take_locks();
             if locks.is_empty() {
                 return Ok(result);
             }
+            let now = self.pd_client.clock().now();
+            if self.deadline.is_some_and(|deadline| now >= deadline) {
+                return Err(Error::DeadlineExceeded);
+            }
+            attempt += 1;
+            let mut event = RetryEvent {
+                reason: RetryReason::Lock,
+                attempt,
+                delay: None,
+                will_retry: false,
+                region_id: None,
+                store_address: None,
+            };
 
             if self.backoff.is_none() {
+                notify_retry(self.pd_client.as_ref(), event);
                 return Err(Error::ResolveLockError);
             }
 
+            if let Some(stats) = &self.stats {
+                stats.on_lock_retry();
+            }
             let pd_client = self.pd_client.clone();
-            if resolve_locks(locks, pd_client.clone()).await? {
+            let observer = self.observer.as_deref();
+            if resolve_locks(locks, pd_client.clone(), observer, self.deadline).await? {
+                event.will_retry = true;
+                notify_retry(self.pd_client.as_ref(), event);
                 result = self.inner.execute().await?;
             } else {
-                match clone.backoff.next_delay_duration() {
+                let delay = clone.backoff.next_delay_duration();
+                event.delay = delay;
+                event.will_retry = delay.is_some();
+                notify_retry(self.pd_client.as_ref(), event);
+                match delay {
                     None => return Err(Error::ResolveLockError),
                     Some(delay_duration) => {
-                        sleep(delay_duration).await;
+                        // Waiting for the lock past the deadline would only delay the request's
+                        // failure.
+                        let delay_duration = match self.deadline {
+                            Some(deadline) => {
+                                let left = deadline.saturating_duration_since(now);
+                                delay_duration.min(left)
+                            }
+                            None => delay_duration,
+                        };
+                        self.pd_client.clock().sleep(delay_duration).await;
                         result = clone.inner.execute().await?;
                     }
                 }
@@ -533,9 +751,10 @@ where P::Result: HasLocks + HasNextBatch + HasKeyErrors + HasRegionError
 
             // Propagate errors to `retry_multi_region` for retry.
             if let Some(e) = scan_lock_resp.key_errors() {
+                let errors: Vec<_> = e.iter().map(ToString::to_string).collect();
                 info!(
                     self.logger,
-                    "CleanupLocks::execute, inner key errors:{:?}", e
+                    "CleanupLocks::execute, inner key errors:{:?}", errors
                 );
                 result.key_error = Some(e);
                 return Ok(result);
@@ -578,16 +797,20 @@ where P::Result: HasLocks + HasNextBatch + HasKeyErrors + HasRegionError
             );
 
             let lock_size = locks.len();
-            match lock_resolver
+            let res = lock_resolver
                 .cleanup_locks(self.store.clone().unwrap(), locks, self.pd_client.clone())
                 .await
-            {
+                .map_err(|e| match e.root() {
+                    Error::ExtractedErrors(_) => e.into_root(),
+                    _ => e,
+                });
+            match res {
                 Ok(()) => {
                     result.resolved_locks += lock_size;
                 }
                 Err(Error::ExtractedErrors(mut errors)) => {
                     // Propagate errors to `retry_multi_region` for retry.
-                    if let Error::RegionError(e) = errors.pop().unwrap() {
+                    if let Error::RegionError(e) = errors.pop().unwrap().into_root() {
                         result.region_error = Some(*e);
                     } else {
                         result.key_error = Some(errors);
@@ -753,10 +976,16 @@ mod test {
                 inner: ErrPlan,
                 backoff: Backoff::no_backoff(),
                 pd_client: Arc::new(MockPdClient::default()),
+                stats: None,
+                observer: None,
+                deadline: None,
             },
             pd_client: Arc::new(MockPdClient::default()),
             backoff: Backoff::no_backoff(),
             preserve_region_results: false,
+            stats: None,
+            tracer: None,
+            deadline: None,
         };
         assert!(plan.execute().await.is_err())
     }
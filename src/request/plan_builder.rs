@@ -2,6 +2,7 @@
 
 use std::marker::PhantomData;
 use std::sync::Arc;
+use std::time::Instant;
 
 use tikv_client_store::HasKeyErrors;
 use tikv_client_store::HasRegionError;
@@ -9,6 +10,7 @@ use tikv_client_store::HasRegionErrors;
 
 use super::plan::PreserveShard;
 use crate::backoff::Backoff;
+use crate::fan_out::FanOutTracer;
 use crate::pd::PdClient;
 use crate::request::plan::CleanupLocks;
 use crate::request::shard::HasNextBatch;
@@ -23,10 +25,12 @@ use crate::request::Plan;
 use crate::request::Process;
 use crate::request::ProcessResponse;
 use crate::request::ResolveLock;
+use crate::request::RetryStats;
 use crate::request::RetryableMultiRegion;
 use crate::request::Shardable;
 use crate::store::RegionStore;
 use crate::transaction::HasLocks;
+use crate::transaction::LockObserver;
 use crate::transaction::ResolveLocksContext;
 use crate::transaction::ResolveLocksOptions;
 use crate::Result;
@@ -35,6 +39,10 @@ use crate::Result;
 pub struct PlanBuilder<PdC: PdClient, P: Plan, Ph: PlanBuilderPhase> {
     pd_client: Arc<PdC>,
     plan: P,
+    stats: Option<RetryStats>,
+    observer: Option<Arc<dyn LockObserver>>,
+    tracer: Option<FanOutTracer>,
+    deadline: Option<Instant>,
     phantom: PhantomData<Ph>,
 }
 
@@ -48,15 +56,46 @@ impl PlanBuilderPhase for Targetted {}
 
 impl<PdC: PdClient, Req: KvRequest> PlanBuilder<PdC, Dispatch<Req>, NoTarget> {
     pub fn new(pd_client: Arc<PdC>, request: Req) -> Self {
+        let rate_limiter = pd_client.rate_limiter();
+        let store_health = pd_client.store_health();
+        let in_flight_limiter = pd_client.in_flight_limiter();
+        let clock = pd_client.clock();
         PlanBuilder {
             pd_client,
             plan: Dispatch {
                 request,
                 kv_client: None,
+                deadline: None,
+                rate_limiter,
+                store_health,
+                in_flight_limiter,
+                store_address: None,
+                resource_group_tag: None,
+                clock,
             },
+            stats: None,
+            observer: None,
+            tracer: None,
+            deadline: None,
             phantom: PhantomData,
         }
     }
+
+    /// Fail the request if it would be sent after `deadline`, and abandon it if it is still in
+    /// flight at `deadline`. Retries, and resolving the locks the request meets, are subject to
+    /// the same deadline, so call this before adding retrying or lock resolving plans.
+    pub fn deadline(mut self, deadline: Option<Instant>) -> Self {
+        self.plan.deadline = deadline;
+        self.deadline = deadline;
+        self
+    }
+
+    /// Tag the request's context with `tag`, if there is one, so that TiKV attributes the
+    /// resources the request uses to it.
+    pub fn resource_group_tag(mut self, tag: Option<Vec<u8>>) -> Self {
+        self.plan.resource_group_tag = tag;
+        self
+    }
 }
 
 impl<PdC: PdClient, P: Plan> PlanBuilder<PdC, P, Targetted> {
@@ -68,6 +107,27 @@ impl<PdC: PdClient, P: Plan> PlanBuilder<PdC, P, Targetted> {
 }
 
 impl<PdC: PdClient, P: Plan, Ph: PlanBuilderPhase> PlanBuilder<PdC, P, Ph> {
+    /// Record the regions touched and the retries made by plans added after this call into
+    /// `stats`.
+    pub fn record_retries(mut self, stats: RetryStats) -> Self {
+        self.stats = Some(stats);
+        self
+    }
+
+    /// Report locks encountered by lock resolving plans added after this call to `observer`, if
+    /// there is one.
+    pub fn observe_locks(mut self, observer: Option<Arc<dyn LockObserver>>) -> Self {
+        self.observer = observer;
+        self
+    }
+
+    /// Record the regions the requests of multi-region plans added after this call are sent to
+    /// into `tracer`, if there is one.
+    pub fn trace_fan_out(mut self, tracer: Option<FanOutTracer>) -> Self {
+        self.tracer = tracer;
+        self
+    }
+
     /// If there is a lock error, then resolve the lock and retry the request.
     pub fn resolve_lock(self, backoff: Backoff) -> PlanBuilder<PdC, ResolveLock<P, PdC>, Ph>
     where P::Result: HasLocks {
@@ -77,7 +137,14 @@ impl<PdC: PdClient, P: Plan, Ph: PlanBuilderPhase> PlanBuilder<PdC, P, Ph> {
                 inner: self.plan,
                 backoff,
                 pd_client: self.pd_client,
+                stats: self.stats.clone(),
+                observer: self.observer.clone(),
+                deadline: self.deadline,
             },
+            stats: self.stats,
+            observer: self.observer,
+            tracer: self.tracer,
+            deadline: self.deadline,
             phantom: PhantomData,
         }
     }
@@ -104,6 +171,10 @@ impl<PdC: PdClient, P: Plan, Ph: PlanBuilderPhase> PlanBuilder<PdC, P, Ph> {
                 backoff,
                 pd_client: self.pd_client,
             },
+            stats: self.stats,
+            observer: self.observer,
+            tracer: self.tracer,
+            deadline: self.deadline,
             phantom: PhantomData,
         }
     }
@@ -122,6 +193,10 @@ impl<PdC: PdClient, P: Plan, Ph: PlanBuilderPhase> PlanBuilder<PdC, P, Ph> {
                 merge,
                 phantom: PhantomData,
             },
+            stats: self.stats,
+            observer: self.observer,
+            tracer: self.tracer,
+            deadline: self.deadline,
             phantom: PhantomData,
         }
     }
@@ -140,6 +215,10 @@ impl<PdC: PdClient, P: Plan, Ph: PlanBuilderPhase> PlanBuilder<PdC, P, Ph> {
                 inner: self.plan,
                 processor: DefaultProcessor,
             },
+            stats: self.stats,
+            observer: self.observer,
+            tracer: self.tracer,
+            deadline: self.deadline,
             phantom: PhantomData,
         }
     }
@@ -177,7 +256,14 @@ where P::Result: HasKeyErrors + HasRegionError
                 pd_client: self.pd_client,
                 backoff,
                 preserve_region_results,
+                stats: self.stats.clone(),
+                tracer: self.tracer.clone(),
+                deadline: self.deadline,
             },
+            stats: self.stats,
+            observer: self.observer,
+            tracer: self.tracer,
+            deadline: self.deadline,
             phantom: PhantomData,
         }
     }
@@ -191,7 +277,7 @@ impl<PdC: PdClient, R: KvRequest + SingleKey> PlanBuilder<PdC, Dispatch<R>, NoTa
         let key = self.plan.request.key();
         // TODO: retry when region error occurred
         let store = self.pd_client.clone().store_for_key(key.into()).await?;
-        set_single_region_store(self.plan, store, self.pd_client)
+        set_single_region_store(self.plan, store, self.pd_client, self.stats, self.observer)
     }
 }
 
@@ -201,7 +287,7 @@ impl<PdC: PdClient, R: KvRequest> PlanBuilder<PdC, Dispatch<R>, NoTarget> {
         self,
         store: RegionStore,
     ) -> Result<PlanBuilder<PdC, Dispatch<R>, Targetted>> {
-        set_single_region_store(self.plan, store, self.pd_client)
+        set_single_region_store(self.plan, store, self.pd_client, self.stats, self.observer)
     }
 }
 
@@ -215,6 +301,10 @@ where P::Result: HasKeyErrors
                 inner: self.plan,
                 shard: None,
             },
+            stats: self.stats,
+            observer: self.observer,
+            tracer: self.tracer,
+            deadline: self.deadline,
             phantom: PhantomData,
         }
     }
@@ -227,6 +317,10 @@ where P::Result: HasKeyErrors + HasRegionErrors
         PlanBuilder {
             pd_client: self.pd_client,
             plan: ExtractError { inner: self.plan },
+            stats: self.stats,
+            observer: self.observer,
+            tracer: self.tracer,
+            deadline: self.deadline,
             phantom: self.phantom,
         }
     }
@@ -236,13 +330,27 @@ fn set_single_region_store<PdC: PdClient, R: KvRequest>(
     mut plan: Dispatch<R>,
     store: RegionStore,
     pd_client: Arc<PdC>,
+    stats: Option<RetryStats>,
+    observer: Option<Arc<dyn LockObserver>>,
 ) -> Result<PlanBuilder<PdC, Dispatch<R>, Targetted>> {
     plan.request
         .set_context(store.region_with_leader.context()?);
+    if let Some(tag) = &plan.resource_group_tag {
+        plan.request.set_resource_group_tag(tag.clone());
+    }
+    if let Some(stats) = &stats {
+        stats.on_region(store.region_with_leader.id());
+    }
     plan.kv_client = Some(store.client);
+    plan.store_address = Some(store.address);
+    let deadline = plan.deadline;
     Ok(PlanBuilder {
         plan,
         pd_client,
+        stats,
+        observer,
+        tracer: None,
+        deadline,
         phantom: PhantomData,
     })
 }
@@ -3,6 +3,7 @@
 use std::sync::Arc;
 
 use futures::stream::BoxStream;
+use tikv_client_proto::kvrpcpb;
 
 use super::plan::PreserveShard;
 use crate::pd::PdClient;
@@ -32,7 +33,7 @@ macro_rules! impl_inner_shardable {
 }
 
 pub trait Shardable {
-    type Shard: Clone + Send + Sync;
+    type Shard: Clone + Send + Sync + ShardKey;
 
     fn shards(
         &self,
@@ -42,6 +43,48 @@ pub trait Shardable {
     fn apply_shard(&mut self, shard: Self::Shard, store: &RegionStore) -> Result<()>;
 }
 
+/// Describes a shard in errors.
+pub trait ShardKey {
+    /// The first key of the shard, if it has keys.
+    fn shard_key(&self) -> Option<Vec<u8>>;
+}
+
+impl ShardKey for () {
+    fn shard_key(&self) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+impl ShardKey for Vec<Vec<u8>> {
+    fn shard_key(&self) -> Option<Vec<u8>> {
+        self.first().cloned()
+    }
+}
+
+impl ShardKey for (Vec<u8>, Vec<u8>) {
+    fn shard_key(&self) -> Option<Vec<u8>> {
+        Some(self.0.clone())
+    }
+}
+
+impl ShardKey for Vec<kvrpcpb::Mutation> {
+    fn shard_key(&self) -> Option<Vec<u8>> {
+        self.first().map(|mutation| mutation.key.clone())
+    }
+}
+
+impl ShardKey for Vec<kvrpcpb::KvPair> {
+    fn shard_key(&self) -> Option<Vec<u8>> {
+        self.first().map(|pair| pair.key.clone())
+    }
+}
+
+impl ShardKey for Vec<kvrpcpb::KeyRange> {
+    fn shard_key(&self) -> Option<Vec<u8>> {
+        self.first().map(|range| range.start_key.clone())
+    }
+}
+
 pub trait Batchable {
     type Item;
 
@@ -92,7 +135,12 @@ impl<Req: KvRequest + Shardable> Shardable for Dispatch<Req> {
 
     fn apply_shard(&mut self, shard: Self::Shard, store: &RegionStore) -> Result<()> {
         self.kv_client = Some(store.client.clone());
-        self.request.apply_shard(shard, store)
+        self.store_address = Some(store.address.clone());
+        self.request.apply_shard(shard, store)?;
+        if let Some(tag) = &self.resource_group_tag {
+            self.request.set_resource_group_tag(tag.clone());
+        }
+        Ok(())
     }
 }
 
@@ -202,9 +250,19 @@ macro_rules! shardable_keys {
     };
 }
 
+/// Implements [`Shardable`] for a request over the range from its `start_key` to its `end_key`.
+///
+/// If a field is named, the request is a reverse scan when that field is true, and its range is
+/// from its `end_key` to its `start_key`.
 #[macro_export]
 macro_rules! shardable_range {
-    ($type_: ty) => {
+    (@reverse $self_:ident) => {
+        false
+    };
+    (@reverse $self_:ident $reverse:ident) => {
+        $self_.$reverse
+    };
+    ($type_: ty $(, $reverse:ident)?) => {
         impl Shardable for $type_ {
             type Shard = (Vec<u8>, Vec<u8>);
 
@@ -212,8 +270,11 @@ macro_rules! shardable_range {
                 &self,
                 pd_client: &Arc<impl $crate::pd::PdClient>,
             ) -> BoxStream<'static, $crate::Result<(Self::Shard, $crate::store::RegionStore)>> {
-                let start_key = self.start_key.clone().into();
-                let end_key = self.end_key.clone().into();
+                let mut start_key = self.start_key.clone().into();
+                let mut end_key = self.end_key.clone().into();
+                if shardable_range!(@reverse self $($reverse)?) {
+                    std::mem::swap(&mut start_key, &mut end_key);
+                }
                 $crate::store::store_stream_for_range((start_key, end_key), pd_client.clone())
             }
 
@@ -224,8 +285,14 @@ macro_rules! shardable_range {
             ) -> $crate::Result<()> {
                 self.context = Some(store.region_with_leader.context()?);
 
-                self.start_key = shard.0.into();
-                self.end_key = shard.1.into();
+                let (start_key, end_key) = shard;
+                if shardable_range!(@reverse self $($reverse)?) {
+                    self.start_key = end_key.into();
+                    self.end_key = start_key.into();
+                } else {
+                    self.start_key = start_key.into();
+                    self.end_key = end_key.into();
+                }
                 Ok(())
             }
         }
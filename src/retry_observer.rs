@@ -0,0 +1,93 @@
+// Copyright 2023 TiKV Project Authors. Licensed under Apache-2.0.
+
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tikv_client_proto::errorpb;
+
+use crate::region::RegionId;
+
+/// Why a request is retried.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RetryReason {
+    /// The store is not the leader of the region.
+    NotLeader,
+    /// The region has been split or merged since the client cached it.
+    EpochNotMatch,
+    /// The store does not have the region.
+    RegionNotFound,
+    /// The store is too busy to serve the request.
+    ServerIsBusy,
+    /// Another region error.
+    RegionError,
+    /// The request found locks of other transactions.
+    Lock,
+    /// A request to PD failed.
+    Pd,
+}
+
+impl RetryReason {
+    pub(crate) fn of_region_error(e: &errorpb::Error) -> RetryReason {
+        if e.not_leader.is_some() {
+            RetryReason::NotLeader
+        } else if e.epoch_not_match.is_some() {
+            RetryReason::EpochNotMatch
+        } else if e.region_not_found.is_some() {
+            RetryReason::RegionNotFound
+        } else if e.server_is_busy.is_some() {
+            RetryReason::ServerIsBusy
+        } else {
+            RetryReason::RegionError
+        }
+    }
+}
+
+/// A decision to retry a failed request, or to give up on it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RetryEvent {
+    pub reason: RetryReason,
+    /// The number of times the request has failed, including this time.
+    pub attempt: u32,
+    /// How long the client sleeps before retrying, or `None` if it does not sleep.
+    pub delay: Option<Duration>,
+    /// Whether the client retries the request, rather than giving up and returning an error.
+    pub will_retry: bool,
+    /// The region the request was sent to, if it was sent to TiKV.
+    pub region_id: Option<RegionId>,
+    /// The address of the store the request was sent to, if it was sent to TiKV.
+    pub store_address: Option<String>,
+}
+
+/// Observes the retries and backoffs of a client's requests.
+///
+/// Useful for exporting retry storms to a metrics system. Register an observer using
+/// [`Config::with_retry_observer`](crate::Config::with_retry_observer). The observer is called on
+/// the task executing the request, so it should not block.
+pub trait RetryObserver: Send + Sync {
+    fn on_retry(&self, event: RetryEvent);
+}
+
+impl<F: Fn(RetryEvent) + Send + Sync> RetryObserver for F {
+    fn on_retry(&self, event: RetryEvent) {
+        self(event)
+    }
+}
+
+/// A shared `RetryObserver` which can be stored in a `Config`.
+#[derive(Clone)]
+pub(crate) struct RetryObserverHandle(pub Arc<dyn RetryObserver>);
+
+impl fmt::Debug for RetryObserverHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("RetryObserver")
+    }
+}
+
+impl PartialEq for RetryObserverHandle {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::as_ptr(&self.0) as *const () == Arc::as_ptr(&other.0) as *const ()
+    }
+}
+
+impl Eq for RetryObserverHandle {}
@@ -0,0 +1,242 @@
+// Copyright 2023 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Routing operations across several TiKV clusters.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use futures::future::try_join_all;
+use slog::Logger;
+
+use crate::Config;
+use crate::RawClient;
+use crate::Result;
+use crate::TransactionClient;
+
+type RouteFn = Box<dyn Fn(&[u8]) -> Option<String> + Send + Sync>;
+
+/// Holds clients for several TiKV clusters, and picks the cluster to use for each operation.
+///
+/// Clusters are named. An operation can be routed to a cluster by name, or by a shard key chosen
+/// by the caller (e.g., a tenant id). By default, shard keys are spread over the clusters using
+/// rendezvous hashing, so adding a cluster only moves the shard keys which are routed to the new
+/// cluster. Use [`route_with`](ClusterRouter::route_with) to route shard keys explicitly.
+///
+/// All clusters share the process's runtime and metrics.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// # use tikv_client::{ClusterRouter, Config, TransactionClient};
+/// # use futures::prelude::*;
+/// # futures::executor::block_on(async {
+/// let router = ClusterRouter::<TransactionClient>::connect(
+///     vec![
+///         ("east", vec!["192.168.0.100"]),
+///         ("west", vec!["192.168.1.100"]),
+///     ],
+///     Config::default(),
+///     None,
+/// )
+/// .await
+/// .unwrap();
+/// let client = router.route("tenant-42").unwrap();
+/// let mut txn = client.begin_optimistic().await.unwrap();
+/// // ...
+/// # });
+/// ```
+pub struct ClusterRouter<C> {
+    clusters: BTreeMap<String, C>,
+    route_fn: Option<RouteFn>,
+}
+
+impl<C> ClusterRouter<C> {
+    /// Create a router without any clusters.
+    pub fn new() -> ClusterRouter<C> {
+        ClusterRouter {
+            clusters: BTreeMap::new(),
+            route_fn: None,
+        }
+    }
+
+    /// Add a cluster, replacing any existing cluster with the same name.
+    #[must_use]
+    pub fn add_cluster(mut self, name: impl Into<String>, client: C) -> Self {
+        self.clusters.insert(name.into(), client);
+        self
+    }
+
+    /// Route shard keys using `route_fn`, which returns the name of the cluster to use for a
+    /// shard key.
+    #[must_use]
+    pub fn route_with(
+        mut self,
+        route_fn: impl Fn(&[u8]) -> Option<String> + Send + Sync + 'static,
+    ) -> Self {
+        self.route_fn = Some(Box::new(route_fn));
+        self
+    }
+
+    /// The client for the cluster called `name`.
+    pub fn cluster(&self, name: &str) -> Option<&C> {
+        self.clusters.get(name)
+    }
+
+    /// The client for the cluster which `shard_key` is routed to.
+    ///
+    /// Returns `None` if there are no clusters, or if the custom routing function names a
+    /// cluster which does not exist.
+    pub fn route(&self, shard_key: impl AsRef<[u8]>) -> Option<&C> {
+        self.route_name(shard_key.as_ref())
+            .and_then(|name| self.clusters.get(&name))
+    }
+
+    /// The names of all clusters, in order.
+    pub fn cluster_names(&self) -> impl Iterator<Item = &str> {
+        self.clusters.keys().map(String::as_str)
+    }
+
+    fn route_name(&self, shard_key: &[u8]) -> Option<String> {
+        if let Some(route_fn) = &self.route_fn {
+            return route_fn(shard_key);
+        }
+        self.clusters
+            .keys()
+            .max_by_key(|name| rendezvous_score(name.as_bytes(), shard_key))
+            .cloned()
+    }
+}
+
+impl<C> Default for ClusterRouter<C> {
+    fn default() -> Self {
+        ClusterRouter::new()
+    }
+}
+
+impl<C> fmt::Debug for ClusterRouter<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ClusterRouter")
+            .field("clusters", &self.clusters.keys().collect::<Vec<_>>())
+            .field("custom_routing", &self.route_fn.is_some())
+            .finish()
+    }
+}
+
+impl ClusterRouter<TransactionClient> {
+    /// Connect transactional clients to each of the named clusters, using the same
+    /// configuration.
+    pub async fn connect<N: Into<String>, S: Into<String>>(
+        clusters: impl IntoIterator<Item = (N, Vec<S>)>,
+        config: Config,
+        logger: Option<Logger>,
+    ) -> Result<ClusterRouter<TransactionClient>> {
+        let clients = try_join_all(clusters.into_iter().map(|(name, pd_endpoints)| {
+            let config = config.clone();
+            let logger = logger.clone();
+            async move {
+                let client =
+                    TransactionClient::new_with_config(pd_endpoints, config, logger).await?;
+                Ok::<_, crate::Error>((name.into(), client))
+            }
+        }))
+        .await?;
+        Ok(clients
+            .into_iter()
+            .fold(ClusterRouter::new(), |router, (name, client)| {
+                router.add_cluster(name, client)
+            }))
+    }
+}
+
+impl ClusterRouter<RawClient> {
+    /// Connect raw clients to each of the named clusters, using the same configuration.
+    pub async fn connect<N: Into<String>, S: Into<String>>(
+        clusters: impl IntoIterator<Item = (N, Vec<S>)>,
+        config: Config,
+        logger: Option<Logger>,
+    ) -> Result<ClusterRouter<RawClient>> {
+        let clients = try_join_all(clusters.into_iter().map(|(name, pd_endpoints)| {
+            let config = config.clone();
+            let logger = logger.clone();
+            async move {
+                let client = RawClient::new_with_config(pd_endpoints, config, logger).await?;
+                Ok::<_, crate::Error>((name.into(), client))
+            }
+        }))
+        .await?;
+        Ok(clients
+            .into_iter()
+            .fold(ClusterRouter::new(), |router, (name, client)| {
+                router.add_cluster(name, client)
+            }))
+    }
+}
+
+// FNV-1a of the cluster name followed by the shard key, with a final mixing step because FNV
+// alone scores clusters with similar names too similarly. It must be stable across processes and
+// releases, so that every client routes a shard key to the same cluster.
+fn rendezvous_score(name: &[u8], shard_key: &[u8]) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x100000001b3;
+    let mut hash = name
+        .iter()
+        .chain(&[0xff])
+        .chain(shard_key)
+        .fold(OFFSET_BASIS, |hash, byte| {
+            (hash ^ u64::from(*byte)).wrapping_mul(PRIME)
+        });
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51afd7ed558ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ceb9fe1a85ec53);
+    hash ^ (hash >> 33)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_route() {
+        let router = ClusterRouter::new()
+            .add_cluster("a", 1)
+            .add_cluster("b", 2)
+            .add_cluster("c", 3);
+        assert_eq!(router.cluster("b"), Some(&2));
+        assert_eq!(router.cluster("d"), None);
+        assert_eq!(router.cluster_names().collect::<Vec<_>>(), vec!["a", "b", "c"]);
+
+        let keys = (0..100).map(|i| format!("tenant-{i}")).collect::<Vec<_>>();
+        let routed = keys
+            .iter()
+            .map(|key| *router.route(key).unwrap())
+            .collect::<Vec<_>>();
+        for cluster in 1..=3 {
+            assert!(routed.contains(&cluster));
+        }
+
+        // Adding a cluster only moves keys to the new cluster.
+        let router = router.add_cluster("d", 4);
+        for (key, before) in keys.iter().zip(routed) {
+            let after = *router.route(key).unwrap();
+            assert!(after == before || after == 4);
+        }
+
+        assert!(ClusterRouter::<u32>::new().route("tenant-1").is_none());
+    }
+
+    #[test]
+    fn test_route_with() {
+        let router = ClusterRouter::new()
+            .add_cluster("a", 1)
+            .add_cluster("b", 2)
+            .route_with(|key| match key.first() {
+                Some(b'x') => Some("a".to_owned()),
+                Some(b'y') => Some("b".to_owned()),
+                _ => Some("z".to_owned()),
+            });
+        assert_eq!(router.route("x1"), Some(&1));
+        assert_eq!(router.route("y1"), Some(&2));
+        assert_eq!(router.route("z1"), None);
+    }
+}
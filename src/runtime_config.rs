@@ -0,0 +1,167 @@
+// Copyright 2023 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Settings of a live client which can be changed without reconnecting.
+
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+
+use slog::Drain;
+use slog::Level;
+use slog::Logger;
+use slog::OwnedKVList;
+use slog::Record;
+
+use crate::RateLimit;
+
+/// Changes to the settings of a live client, applied by `update_config` of a
+/// [`RawClient`](crate::RawClient) or [`TransactionClient`](crate::TransactionClient).
+///
+/// Settings which are not set are left as they are. Changes apply to the requests sent after the
+/// update; requests in flight are not affected.
+///
+/// # Examples
+/// ```rust
+/// # use tikv_client::{ConfigUpdate, RateLimit};
+/// # use std::time::Duration;
+/// let update = ConfigUpdate::default()
+///     .timeout(Duration::from_secs(5))
+///     .rate_limit(RateLimit::default().requests_per_second(1000))
+///     .log_level(slog::Level::Warning);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ConfigUpdate {
+    pub timeout: Option<Duration>,
+    pub rate_limit: Option<RateLimit>,
+    pub log_level: Option<Level>,
+}
+
+impl ConfigUpdate {
+    /// Set the timeout of requests to TiKV, as set by
+    /// [`Config::with_timeout`](crate::Config::with_timeout).
+    #[must_use]
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Replace the limit on the requests sent to TiKV, as set by
+    /// [`Config::with_rate_limit`](crate::Config::with_rate_limit). A limit which doesn't limit
+    /// anything, e.g., `RateLimit::default()`, removes the limit.
+    #[must_use]
+    pub fn rate_limit(mut self, rate_limit: RateLimit) -> Self {
+        self.rate_limit = Some(rate_limit);
+        self
+    }
+
+    /// Drop the client's log messages which are less severe than `level`.
+    ///
+    /// A client logs at `Info` and above by default. A logger passed to the client may filter
+    /// messages further.
+    #[must_use]
+    pub fn log_level(mut self, level: Level) -> Self {
+        self.log_level = Some(level);
+        self
+    }
+}
+
+/// The least severe level of the messages a client logs, which can be changed.
+#[derive(Clone, Debug)]
+pub(crate) struct LogLevel(Arc<AtomicUsize>);
+
+impl LogLevel {
+    pub(crate) fn new(level: Level) -> LogLevel {
+        LogLevel(Arc::new(AtomicUsize::new(level.as_usize())))
+    }
+
+    pub(crate) fn set(&self, level: Level) {
+        self.0.store(level.as_usize(), Ordering::Relaxed);
+    }
+
+    fn get(&self) -> Level {
+        Level::from_usize(self.0.load(Ordering::Relaxed)).unwrap_or(Level::Trace)
+    }
+
+    /// `logger`, dropping the messages less severe than this level.
+    pub(crate) fn filter(&self, logger: Logger) -> Logger {
+        let drain = LevelFilter {
+            inner: logger,
+            level: self.clone(),
+        };
+        Logger::root(drain, o!())
+    }
+}
+
+struct LevelFilter {
+    inner: Logger,
+    level: LogLevel,
+}
+
+impl Drain for LevelFilter {
+    type Ok = ();
+    type Err = slog::Never;
+
+    fn log(&self, record: &Record, values: &OwnedKVList) -> Result<(), slog::Never> {
+        if record.level().is_at_least(self.level.get()) {
+            Drain::log(&self.inner, record, values)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// The logger of a client created with `logger`, and its level.
+///
+/// Without a logger, the client logs to stdout.
+pub(crate) fn client_logger(logger: Option<Logger>) -> (Logger, LogLevel) {
+    let (logger, level) = match logger {
+        Some(logger) => (logger, Level::Trace),
+        None => {
+            let plain = slog_term::PlainSyncDecorator::new(std::io::stdout());
+            let drain = slog_term::FullFormat::new(plain).build().fuse();
+            (Logger::root(drain, o!()), Level::Info)
+        }
+    };
+    let level = LogLevel::new(level);
+    (level.filter(logger), level)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    #[test]
+    fn test_log_level() {
+        let logged = Arc::new(Mutex::new(Vec::new()));
+        let recorded = logged.clone();
+        let record = move |record: &Record, _: &OwnedKVList| {
+            recorded.lock().unwrap().push(record.msg().to_string());
+            Ok::<(), slog::Never>(())
+        };
+        let level = LogLevel::new(Level::Info);
+        let logger = level.filter(Logger::root(FnDrain(record), o!()));
+
+        debug!(logger, "hidden");
+        info!(logger, "shown");
+        level.set(Level::Debug);
+        debug!(logger, "debug");
+        level.set(Level::Error);
+        warn!(logger, "warning");
+        assert_eq!(*logged.lock().unwrap(), vec!["shown", "debug"]);
+    }
+
+    struct FnDrain<F>(F);
+
+    impl<F> Drain for FnDrain<F>
+    where F: Fn(&Record, &OwnedKVList) -> Result<(), slog::Never> {
+        type Ok = ();
+        type Err = slog::Never;
+
+        fn log(&self, record: &Record, values: &OwnedKVList) -> Result<(), slog::Never> {
+            (self.0)(record, values)
+        }
+    }
+}
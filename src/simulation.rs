@@ -0,0 +1,2186 @@
+// Copyright 2023 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! A deterministic, in-process simulation of a TiKV cluster, for testing code which uses the
+//! client without running PD or TiKV.
+//!
+//! A [`Simulation`] stands in for PD and a few TiKV stores. Its stores serve raw and transactional
+//! requests from an in-memory, multi-version store, and check each request's region, epoch, and
+//! leader like TiKV does, so the client's routing, retry, and two-phase commit logic run
+//! unchanged against it. Faults are injected per request with
+//! [`inject_faults`](Simulation::inject_faults): regions can be split and leaders transferred
+//! under a running request, and requests or responses can be dropped.
+//!
+//! The simulation is deterministic: given the same seed and the same sequence of operations, it
+//! receives the same requests and makes the same decisions. This holds when the client runs on a
+//! current-thread runtime (as `#[tokio::test]` does by default) and backoffs have no jitter (as the
+//! default backoffs do). Pausing tokio's clock with `tokio::time::pause` makes backoffs take no
+//! time.
+//!
+//! Async commit and one-phase commit are not simulated: transactions using async commit fall back
+//! to two-phase commit, and one-phase commit fails.
+//!
+//! This module requires the `simulation` feature.
+//!
+//! # Examples
+//!
+//! ```rust
+//! # use tikv_client::simulation::{Fault, Simulation};
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() {
+//! let sim = Simulation::new(42);
+//! // Split the cluster's only region under the first commit request.
+//! let mut split = false;
+//! sim.inject_faults(move |request| {
+//!     if request.label == "kv_commit" && !split {
+//!         split = true;
+//!         vec![Fault::Split(b"m".to_vec().into())]
+//!     } else {
+//!         Vec::new()
+//!     }
+//! });
+//!
+//! let mut txn = sim.begin_optimistic().await.unwrap();
+//! txn.put(b"a".to_vec(), b"1".to_vec()).await.unwrap();
+//! txn.put(b"z".to_vec(), b"2".to_vec()).await.unwrap();
+//! txn.commit().await.unwrap();
+//! assert_eq!(sim.regions().len(), 2);
+//! # }
+//! ```
+
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::ops::Bound;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use rand::rngs::StdRng;
+use rand::Rng;
+use rand::SeedableRng;
+use slog::Drain;
+use slog::Logger;
+use tikv_client_common::internal_err;
+use tikv_client_proto::errorpb;
+use tikv_client_proto::kvrpcpb;
+use tikv_client_proto::metapb;
+use tikv_client_store::KvClient;
+use tikv_client_store::Request;
+
+use crate::clock::Clock;
+use crate::clock::SystemClock;
+#[cfg(any(test, feature = "fault-injection"))]
+use crate::fault_injection::FaultInjector;
+use crate::hot_keys::HotKeyTracker;
+use crate::pd::PdClient;
+use crate::pd::RetryClientTrait;
+use crate::raw::Client as RawClient;
+use crate::region::RegionId;
+use crate::region::RegionVerId;
+use crate::region::RegionWithLeader;
+use crate::region::StoreId;
+use crate::region_cache::RegionCache;
+use crate::region_cache::RegionCacheStats;
+use crate::retry_observer::RetryObserver;
+use crate::spawner::Spawner;
+use crate::store::RegionStore;
+use crate::transaction::Transaction;
+use crate::transaction::TransactionOptions;
+use crate::BoundRange;
+use crate::Error;
+use crate::HotKey;
+use crate::HotKeyTracking;
+use crate::Key;
+use crate::Result;
+use crate::Timestamp;
+use crate::TimestampExt;
+
+const STORE_COUNT: u64 = 3;
+
+/// A simulated cluster of PD and TiKV stores.
+///
+/// A new simulation has three stores and a single region, led by the first store, covering all
+/// keys. Clients created from the same simulation share a region cache, like clients of one
+/// process would.
+pub struct Simulation {
+    state: Arc<Mutex<State>>,
+    pd: Arc<SimulatedPdClient>,
+    logger: Logger,
+}
+
+/// A request received by a simulated store.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SimulatedRequest {
+    /// The position of the request among all requests received by the simulation, starting
+    /// from 0.
+    pub sequence: u64,
+    /// The kind of request, e.g., `"kv_prewrite"`.
+    pub label: &'static str,
+    /// The store the request was sent to.
+    pub store_id: StoreId,
+    /// The region the request was sent to, according to the client.
+    pub region_id: RegionId,
+}
+
+/// A fault to inject when a simulated store receives a request.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Fault {
+    /// Lose the request before the store handles it.
+    DropRequest,
+    /// Lose the response after the store has handled the request.
+    DropResponse,
+    /// Split the region containing the key at the key, before the store handles the request.
+    Split(Key),
+    /// Move a region's leader to a store, before the store handles the request.
+    TransferLeader {
+        region_id: RegionId,
+        store_id: StoreId,
+    },
+    /// Reject the request with a `ServerIsBusy` region error suggesting a backoff, rather than
+    /// handling it.
+    ServerIsBusy { backoff_ms: u64 },
+}
+
+/// A region of a [`Simulation`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SimulatedRegion {
+    pub id: RegionId,
+    pub start_key: Key,
+    /// Empty if the region is unbounded.
+    pub end_key: Key,
+    pub leader_store_id: StoreId,
+}
+
+type FaultHook = Box<dyn FnMut(&SimulatedRequest) -> Vec<Fault> + Send>;
+
+impl Simulation {
+    /// Create a simulation whose random decisions, e.g., which messages to drop, are derived from
+    /// `seed`.
+    pub fn new(seed: u64) -> Simulation {
+        let state = Arc::new(Mutex::new(State::new(seed)));
+        let pd = Arc::new(SimulatedPdClient {
+            state: state.clone(),
+            region_cache: RegionCache::new(Arc::new(SimulatedPd {
+                state: state.clone(),
+            })),
+        });
+        Simulation {
+            state,
+            pd,
+            logger: Logger::root(slog::Discard.fuse(), o!()),
+        }
+    }
+
+    /// The PD client the simulation's clients use, for sending requests with the
+    /// [`request`](crate::request) API.
+    pub fn pd_client(&self) -> Arc<SimulatedPdClient> {
+        self.pd.clone()
+    }
+
+    /// Create a raw client of the simulated cluster.
+    pub fn raw_client(&self) -> RawClient<SimulatedPdClient> {
+        RawClient::new_with_pd_client(self.pd.clone(), self.logger.clone())
+    }
+
+    /// Begin an optimistic transaction on the simulated cluster.
+    pub async fn begin_optimistic(&self) -> Result<Transaction<SimulatedPdClient>> {
+        self.begin_with_options(TransactionOptions::new_optimistic())
+            .await
+    }
+
+    /// Begin a pessimistic transaction on the simulated cluster.
+    pub async fn begin_pessimistic(&self) -> Result<Transaction<SimulatedPdClient>> {
+        self.begin_with_options(TransactionOptions::new_pessimistic())
+            .await
+    }
+
+    /// Begin a transaction with custom options on the simulated cluster.
+    pub async fn begin_with_options(
+        &self,
+        options: TransactionOptions,
+    ) -> Result<Transaction<SimulatedPdClient>> {
+        let timestamp = self.pd.clone().get_timestamp().await?;
+        let logger = self.logger.new(o!("child" => 1));
+        Ok(Transaction::new(timestamp, self.pd.clone(), options, logger))
+    }
+
+    /// Call `hook` for every request the simulated stores receive, injecting the faults it
+    /// returns. Replaces any previous hook.
+    pub fn inject_faults<F>(&self, hook: F)
+    where F: FnMut(&SimulatedRequest) -> Vec<Fault> + Send + 'static {
+        self.state.lock().unwrap().hook = Some(Box::new(hook));
+    }
+
+    /// Call `observer` whenever a request of the simulation's clients is retried, or given up on.
+    /// Replaces any previous observer.
+    pub fn set_retry_observer(&self, observer: impl RetryObserver + 'static) {
+        self.state.lock().unwrap().retry_observer = Some(Arc::new(observer));
+    }
+
+    /// Use `clock` for the heartbeats, backoffs and deadlines of the simulation's clients. It does
+    /// not affect the timestamps of the simulation, which [`advance_clock`](Simulation::advance_clock)
+    /// advances.
+    pub fn set_clock(&self, clock: Arc<dyn Clock>) {
+        self.state.lock().unwrap().clock = Some(clock);
+    }
+
+    /// Run the background tasks of the simulation's clients with `spawner`.
+    pub fn set_spawner(&self, spawner: impl Spawner + 'static) {
+        self.state.lock().unwrap().spawner = Some(Arc::new(spawner));
+    }
+
+    /// Track the hot keys of the simulation's clients. Replaces any previous tracking, and its
+    /// statistics.
+    pub fn set_hot_key_tracking(&self, tracking: HotKeyTracking) {
+        self.state.lock().unwrap().hot_key_tracker = Some(Arc::new(HotKeyTracker::new(tracking)));
+    }
+
+    /// The `top_n` hottest keys of the simulation's clients, hottest first, if they are
+    /// [tracked](Simulation::set_hot_key_tracking).
+    pub fn hot_keys(&self, top_n: usize) -> Vec<HotKey> {
+        self.pd
+            .hot_key_tracker()
+            .map_or_else(Vec::new, |tracker| tracker.hot_keys(top_n))
+    }
+
+    /// Inject the faults of `injector` into the requests of the simulation's clients, before they
+    /// reach the simulated stores. Replaces any previous injector. Requires the `fault-injection`
+    /// feature.
+    #[cfg(any(test, feature = "fault-injection"))]
+    pub fn set_fault_injection(&self, injector: FaultInjector) {
+        self.state.lock().unwrap().fault_injector = Some(injector);
+    }
+
+    /// Drop each request, and each response, with the given probabilities.
+    pub fn set_drop_rates(&self, request_rate: f64, response_rate: f64) {
+        let mut state = self.state.lock().unwrap();
+        state.drop_request_rate = request_rate;
+        state.drop_response_rate = response_rate;
+    }
+
+    /// Split the region containing `key` at `key`, returning the id of the new region, which
+    /// covers the keys before `key`. Returns `None` if a region already starts at `key`.
+    pub fn split(&self, key: impl Into<Key>) -> Option<RegionId> {
+        let key: Vec<u8> = key.into().into();
+        self.state.lock().unwrap().split(&key)
+    }
+
+    /// Move the leader of a region to another store.
+    pub fn transfer_leader(&self, region_id: RegionId, store_id: StoreId) -> Result<()> {
+        self.state
+            .lock()
+            .unwrap()
+            .transfer_leader(region_id, store_id)
+    }
+
+    /// The simulation's regions, in order of their keys.
+    pub fn regions(&self) -> Vec<SimulatedRegion> {
+        self.state
+            .lock()
+            .unwrap()
+            .regions
+            .values()
+            .map(|region| SimulatedRegion {
+                id: region.region.id,
+                start_key: region.region.start_key.clone().into(),
+                end_key: region.region.end_key.clone().into(),
+                leader_store_id: region.leader.store_id,
+            })
+            .collect()
+    }
+
+    /// Advance the simulated clock, which timestamps and lock TTLs are based on.
+    pub fn advance_clock(&self, duration: Duration) {
+        let mut state = self.state.lock().unwrap();
+        state.physical_ms += duration.as_millis() as i64;
+        state.logical = 0;
+    }
+
+    /// Every request the simulated stores have received, in order.
+    pub fn requests(&self) -> Vec<SimulatedRequest> {
+        self.state.lock().unwrap().history.clone()
+    }
+
+    /// The simulated PD, as read by a region cache.
+    #[cfg(feature = "mock-server")]
+    pub(crate) fn pd(&self) -> Arc<SimulatedPd> {
+        Arc::new(SimulatedPd {
+            state: self.state.clone(),
+        })
+    }
+
+    /// A client of the store with id `store_id`.
+    #[cfg(feature = "mock-server")]
+    pub(crate) fn kv_client(&self, store_id: StoreId) -> SimulatedKvClient {
+        SimulatedKvClient {
+            state: self.state.clone(),
+            store_id,
+        }
+    }
+
+    /// Allocate `count` timestamps with the same physical part, and return the last, like PD's
+    /// TSO does.
+    #[cfg(feature = "mock-server")]
+    pub(crate) fn allocate_timestamps(&self, count: u32) -> Timestamp {
+        let mut state = self.state.lock().unwrap();
+        if state.logical + count as i64 >= 1 << 18 {
+            state.physical_ms += 1;
+            state.logical = 0;
+        }
+        state.logical += count as i64;
+        Timestamp {
+            physical: state.physical_ms,
+            logical: state.logical,
+            ..Default::default()
+        }
+    }
+}
+
+/// The PD client of a [`Simulation`].
+pub struct SimulatedPdClient {
+    state: Arc<Mutex<State>>,
+    region_cache: RegionCache<SimulatedPd>,
+}
+
+/// A client of one store of a [`Simulation`].
+#[derive(Clone)]
+pub struct SimulatedKvClient {
+    state: Arc<Mutex<State>>,
+    store_id: StoreId,
+}
+
+// Serves the region cache's reads from PD.
+pub(crate) struct SimulatedPd {
+    state: Arc<Mutex<State>>,
+}
+
+#[async_trait]
+impl PdClient for SimulatedPdClient {
+    type KvClient = SimulatedKvClient;
+
+    async fn map_region_to_store(self: Arc<Self>, region: RegionWithLeader) -> Result<RegionStore> {
+        let store_id = region.get_store_id()?;
+        let store = self.region_cache.get_store_by_id(store_id).await?;
+        let client: Arc<dyn KvClient + Send + Sync> = Arc::new(SimulatedKvClient {
+            state: self.state.clone(),
+            store_id,
+        });
+        #[cfg(any(test, feature = "fault-injection"))]
+        let client = {
+            let injector = self.state.lock().unwrap().fault_injector.clone();
+            match injector {
+                Some(injector) => injector.wrap(client, region.id(), &store.address, self.clock()),
+                None => client,
+            }
+        };
+        Ok(RegionStore::new(region, client, store.address))
+    }
+
+    async fn region_for_key(&self, key: &Key) -> Result<RegionWithLeader> {
+        self.region_cache.get_region_by_key(key).await
+    }
+
+    async fn region_for_id(&self, id: RegionId) -> Result<RegionWithLeader> {
+        self.region_cache.get_region_by_id(id).await
+    }
+
+    async fn get_timestamp(self: Arc<Self>) -> Result<Timestamp> {
+        Ok(self.state.lock().unwrap().next_timestamp())
+    }
+
+    async fn update_safepoint(self: Arc<Self>, safepoint: u64) -> Result<bool> {
+        Ok(self.state.lock().unwrap().update_safepoint(safepoint))
+    }
+
+    async fn update_service_safepoint(
+        self: Arc<Self>,
+        service_id: String,
+        ttl: i64,
+        safepoint: u64,
+    ) -> Result<u64> {
+        let mut state = self.state.lock().unwrap();
+        Ok(state.update_service_safepoint(service_id, ttl, safepoint))
+    }
+
+    async fn update_leader(&self, ver_id: RegionVerId, leader: metapb::Peer) -> Result<()> {
+        self.region_cache.update_leader(ver_id, leader).await
+    }
+
+    async fn invalidate_region_cache(&self, ver_id: RegionVerId) {
+        self.region_cache.invalidate_region_cache(ver_id).await
+    }
+
+    async fn invalidate_region_cache_range(&self, range: BoundRange) -> usize {
+        let (start_key, end_key) = range.into_keys();
+        self.region_cache
+            .invalidate_range(&start_key, &end_key.unwrap_or_default())
+            .await
+    }
+
+    async fn region_cache_stats(&self) -> RegionCacheStats {
+        self.region_cache.stats().await
+    }
+
+    fn retry_observer(&self) -> Option<Arc<dyn RetryObserver>> {
+        self.state.lock().unwrap().retry_observer.clone()
+    }
+
+    fn spawner(&self) -> Option<Arc<dyn Spawner>> {
+        self.state.lock().unwrap().spawner.clone()
+    }
+
+    fn clock(&self) -> Arc<dyn Clock> {
+        let clock = self.state.lock().unwrap().clock.clone();
+        clock.unwrap_or_else(|| Arc::new(SystemClock))
+    }
+
+    fn hot_key_tracker(&self) -> Option<Arc<HotKeyTracker>> {
+        self.state.lock().unwrap().hot_key_tracker.clone()
+    }
+}
+
+#[async_trait]
+impl RetryClientTrait for SimulatedPd {
+    async fn get_region(self: Arc<Self>, key: Vec<u8>) -> Result<RegionWithLeader> {
+        let state = self.state.lock().unwrap();
+        Ok(state.region_for_key(&key).to_region_with_leader())
+    }
+
+    async fn get_region_by_id(self: Arc<Self>, region_id: RegionId) -> Result<RegionWithLeader> {
+        let state = self.state.lock().unwrap();
+        state
+            .region_for_id(region_id)
+            .map(SimRegion::to_region_with_leader)
+            .ok_or(Error::RegionNotFoundInResponse { region_id })
+    }
+
+    async fn get_store(self: Arc<Self>, id: StoreId) -> Result<metapb::Store> {
+        if id == 0 || id > STORE_COUNT {
+            return Err(internal_err!("store {} does not exist", id));
+        }
+        Ok(metapb::Store {
+            id,
+            address: store_address(id),
+            ..Default::default()
+        })
+    }
+
+    async fn get_all_stores(self: Arc<Self>) -> Result<Vec<metapb::Store>> {
+        let mut stores = Vec::new();
+        for id in 1..=STORE_COUNT {
+            stores.push(self.clone().get_store(id).await?);
+        }
+        Ok(stores)
+    }
+
+    async fn get_timestamp(self: Arc<Self>) -> Result<Timestamp> {
+        Ok(self.state.lock().unwrap().next_timestamp())
+    }
+
+    async fn update_safepoint(self: Arc<Self>, safepoint: u64) -> Result<bool> {
+        Ok(self.state.lock().unwrap().update_safepoint(safepoint))
+    }
+
+    async fn update_service_safepoint(
+        self: Arc<Self>,
+        service_id: String,
+        ttl: i64,
+        safepoint: u64,
+    ) -> Result<u64> {
+        let mut state = self.state.lock().unwrap();
+        Ok(state.update_service_safepoint(service_id, ttl, safepoint))
+    }
+}
+
+#[async_trait]
+impl KvClient for SimulatedKvClient {
+    async fn dispatch(&self, req: &dyn Request) -> Result<Box<dyn Any>> {
+        // Let other tasks run first, so that concurrent requests interleave.
+        tokio::task::yield_now().await;
+        self.state.lock().unwrap().receive(self.store_id, req)
+    }
+}
+
+fn store_address(id: StoreId) -> String {
+    format!("simulation://store-{id}")
+}
+
+fn dropped() -> Error {
+    Error::GrpcAPI(tonic::Status::unavailable(
+        "message dropped by the simulation",
+    ))
+}
+
+struct SimRegion {
+    region: metapb::Region,
+    leader: metapb::Peer,
+}
+
+impl SimRegion {
+    fn to_region_with_leader(&self) -> RegionWithLeader {
+        RegionWithLeader::new(self.region.clone(), Some(self.leader.clone()))
+    }
+}
+
+struct Lock {
+    primary: Vec<u8>,
+    start_ts: u64,
+    ttl: u64,
+    kind: LockKind,
+    for_update_ts: u64,
+    txn_size: u64,
+}
+
+enum LockKind {
+    Put(Vec<u8>),
+    Delete,
+    Lock,
+    Pessimistic,
+}
+
+#[derive(Clone)]
+struct Write {
+    start_ts: u64,
+    kind: WriteKind,
+}
+
+#[derive(Clone)]
+enum WriteKind {
+    Put(Vec<u8>),
+    Delete,
+    Lock,
+    Rollback,
+}
+
+type RegionResult<T> = std::result::Result<T, errorpb::Error>;
+
+struct State {
+    rng: StdRng,
+    physical_ms: i64,
+    logical: i64,
+    next_id: u64,
+    // Keyed by start key.
+    regions: BTreeMap<Vec<u8>, SimRegion>,
+    raw: HashMap<String, BTreeMap<Vec<u8>, Vec<u8>>>,
+    locks: BTreeMap<Vec<u8>, Lock>,
+    // For each key, its writes keyed by commit timestamp. Rollbacks are keyed by start timestamp.
+    writes: BTreeMap<Vec<u8>, BTreeMap<u64, Write>>,
+    gc_safepoint: u64,
+    // The safepoint of each service, and when it expires, in physical milliseconds. Like PD, it
+    // starts out with the GC worker's, at zero.
+    service_safepoints: HashMap<String, (u64, i64)>,
+    drop_request_rate: f64,
+    drop_response_rate: f64,
+    hook: Option<FaultHook>,
+    history: Vec<SimulatedRequest>,
+    retry_observer: Option<Arc<dyn RetryObserver>>,
+    spawner: Option<Arc<dyn Spawner>>,
+    clock: Option<Arc<dyn Clock>>,
+    hot_key_tracker: Option<Arc<HotKeyTracker>>,
+    #[cfg(any(test, feature = "fault-injection"))]
+    fault_injector: Option<FaultInjector>,
+}
+
+impl State {
+    fn new(seed: u64) -> State {
+        let mut state = State {
+            rng: StdRng::seed_from_u64(seed),
+            physical_ms: 1,
+            logical: 0,
+            next_id: 1,
+            regions: BTreeMap::new(),
+            raw: HashMap::new(),
+            locks: BTreeMap::new(),
+            writes: BTreeMap::new(),
+            gc_safepoint: 0,
+            service_safepoints: HashMap::from([("gc_worker".to_owned(), (0, i64::MAX))]),
+            drop_request_rate: 0.0,
+            drop_response_rate: 0.0,
+            hook: None,
+            history: Vec::new(),
+            retry_observer: None,
+            spawner: None,
+            clock: None,
+            hot_key_tracker: None,
+            #[cfg(any(test, feature = "fault-injection"))]
+            fault_injector: None,
+        };
+        let region = state.new_region(Vec::new(), Vec::new(), 1, 1);
+        state.regions.insert(Vec::new(), region);
+        state
+    }
+
+    fn alloc_id(&mut self) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    fn new_region(
+        &mut self,
+        start_key: Vec<u8>,
+        end_key: Vec<u8>,
+        version: u64,
+        leader_store_id: StoreId,
+    ) -> SimRegion {
+        let id = self.alloc_id();
+        let peers: Vec<metapb::Peer> = (1..=STORE_COUNT)
+            .map(|store_id| metapb::Peer {
+                id: self.alloc_id(),
+                store_id,
+                ..Default::default()
+            })
+            .collect();
+        let leader = peers[(leader_store_id - 1) as usize].clone();
+        SimRegion {
+            region: metapb::Region {
+                id,
+                start_key,
+                end_key,
+                region_epoch: Some(metapb::RegionEpoch {
+                    conf_ver: 1,
+                    version,
+                }),
+                peers,
+                ..Default::default()
+            },
+            leader,
+        }
+    }
+
+    fn next_timestamp(&mut self) -> Timestamp {
+        self.logical += 1;
+        if self.logical >= 1 << 18 {
+            self.physical_ms += 1;
+            self.logical = 0;
+        }
+        Timestamp {
+            physical: self.physical_ms,
+            logical: self.logical,
+            ..Default::default()
+        }
+    }
+
+    /// Advance the GC safepoint, like PD, which never moves it back.
+    fn update_safepoint(&mut self, safepoint: u64) -> bool {
+        self.gc_safepoint = self.gc_safepoint.max(safepoint);
+        self.gc_safepoint == safepoint
+    }
+
+    /// Set or release the safepoint of a service, like PD, which doesn't set a safepoint below the
+    /// minimum. Returns the minimum.
+    fn update_service_safepoint(&mut self, service_id: String, ttl: i64, safepoint: u64) -> u64 {
+        let now = self.physical_ms;
+        self.service_safepoints.retain(|_, (_, expires_ms)| *expires_ms > now);
+        if ttl <= 0 {
+            self.service_safepoints.remove(&service_id);
+        } else if safepoint >= self.min_service_safepoint() {
+            let expires_ms = now.saturating_add(ttl.saturating_mul(1000));
+            self.service_safepoints.insert(service_id, (safepoint, expires_ms));
+        }
+        self.min_service_safepoint()
+    }
+
+    fn min_service_safepoint(&self) -> u64 {
+        self.service_safepoints
+            .values()
+            .map(|(safepoint, _)| *safepoint)
+            .min()
+            .unwrap_or(self.gc_safepoint)
+    }
+
+    fn region_for_key(&self, key: &[u8]) -> &SimRegion {
+        self.regions
+            .range::<[u8], _>((Bound::Unbounded, Bound::Included(key)))
+            .next_back()
+            .map(|(_, region)| region)
+            .expect("regions cover all keys")
+    }
+
+    fn region_for_id(&self, id: RegionId) -> Option<&SimRegion> {
+        self.regions.values().find(|region| region.region.id == id)
+    }
+
+    fn split(&mut self, key: &[u8]) -> Option<RegionId> {
+        let start_key = self.region_for_key(key).region.start_key.clone();
+        if start_key == key {
+            return None;
+        }
+        // Like TiKV, the original region keeps the keys after the split key.
+        let mut right = self.regions.remove(&start_key).unwrap();
+        let epoch = right.region.region_epoch.as_mut().unwrap();
+        epoch.version += 1;
+        let version = epoch.version;
+        let left = self.new_region(
+            start_key.clone(),
+            key.to_vec(),
+            version,
+            right.leader.store_id,
+        );
+        let left_id = left.region.id;
+        right.region.start_key = key.to_vec();
+        self.regions.insert(start_key, left);
+        self.regions.insert(key.to_vec(), right);
+        Some(left_id)
+    }
+
+    fn transfer_leader(&mut self, region_id: RegionId, store_id: StoreId) -> Result<()> {
+        let region = self
+            .regions
+            .values_mut()
+            .find(|region| region.region.id == region_id)
+            .ok_or(Error::RegionNotFoundInResponse { region_id })?;
+        region.leader = region
+            .region
+            .peers
+            .iter()
+            .find(|peer| peer.store_id == store_id)
+            .cloned()
+            .ok_or_else(|| {
+                internal_err!("store {} has no peer of region {}", store_id, region_id)
+            })?;
+        Ok(())
+    }
+
+    fn receive(&mut self, store_id: StoreId, req: &dyn Request) -> Result<Box<dyn Any>> {
+        let request = SimulatedRequest {
+            sequence: self.history.len() as u64,
+            label: req.label(),
+            store_id,
+            region_id: context_of(req.as_any()).map_or(0, |context| context.region_id),
+        };
+        self.history.push(request.clone());
+
+        let mut faults = match &mut self.hook {
+            Some(hook) => hook(&request),
+            None => Vec::new(),
+        };
+        if self.rng.gen::<f64>() < self.drop_request_rate {
+            faults.push(Fault::DropRequest);
+        }
+        if self.rng.gen::<f64>() < self.drop_response_rate {
+            faults.push(Fault::DropResponse);
+        }
+        let mut drop_request = false;
+        let mut drop_response = false;
+        let mut rejection = None;
+        for fault in faults {
+            match fault {
+                Fault::DropRequest => drop_request = true,
+                Fault::DropResponse => drop_response = true,
+                Fault::Split(key) => {
+                    let key: Vec<u8> = key.into();
+                    self.split(&key);
+                }
+                Fault::TransferLeader {
+                    region_id,
+                    store_id,
+                } => self.transfer_leader(region_id, store_id)?,
+                Fault::ServerIsBusy { backoff_ms } => {
+                    rejection = Some(errorpb::Error {
+                        message: "server is busy".to_owned(),
+                        server_is_busy: Some(errorpb::ServerIsBusy {
+                            reason: "injected".to_owned(),
+                            backoff_ms,
+                        }),
+                        ..Default::default()
+                    })
+                }
+            }
+        }
+
+        if drop_request {
+            return Err(dropped());
+        }
+        let response = self.serve(store_id, req, rejection)?;
+        if drop_response {
+            return Err(dropped());
+        }
+        Ok(response)
+    }
+
+    // Find the region a request is for, failing like TiKV if it is not led by this store or the
+    // request's epoch is stale.
+    fn check_region(
+        &self,
+        store_id: StoreId,
+        context: Option<&kvrpcpb::Context>,
+    ) -> RegionResult<metapb::Region> {
+        let context = context.ok_or_else(|| errorpb::Error {
+            message: "request has no context".to_owned(),
+            ..Default::default()
+        })?;
+        let region = self
+            .region_for_id(context.region_id)
+            .ok_or_else(|| errorpb::Error {
+                message: "region not found".to_owned(),
+                region_not_found: Some(errorpb::RegionNotFound {
+                    region_id: context.region_id,
+                }),
+                ..Default::default()
+            })?;
+        if region.leader.store_id != store_id {
+            return Err(errorpb::Error {
+                message: "not leader".to_owned(),
+                not_leader: Some(errorpb::NotLeader {
+                    region_id: region.region.id,
+                    leader: Some(region.leader.clone()),
+                }),
+                ..Default::default()
+            });
+        }
+        if context.region_epoch != region.region.region_epoch {
+            return Err(errorpb::Error {
+                message: "epoch not match".to_owned(),
+                epoch_not_match: Some(errorpb::EpochNotMatch {
+                    current_regions: vec![region.region.clone()],
+                }),
+                ..Default::default()
+            });
+        }
+        Ok(region.region.clone())
+    }
+
+    // Keys in `[start, end)`, clamped to `region`. An empty `end` means unbounded.
+    fn clamp(region: &metapb::Region, start: &[u8], end: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        let start = start.max(region.start_key.as_slice()).to_vec();
+        let end = match (end.is_empty(), region.end_key.is_empty()) {
+            (true, _) => region.end_key.clone(),
+            (false, true) => end.to_vec(),
+            (false, false) => end.min(region.end_key.as_slice()).to_vec(),
+        };
+        (start, end)
+    }
+
+    fn range_bounds(start: &[u8], end: &[u8]) -> (Bound<Vec<u8>>, Bound<Vec<u8>>) {
+        let end = if end.is_empty() {
+            Bound::Unbounded
+        } else {
+            Bound::Excluded(end.to_vec())
+        };
+        (Bound::Included(start.to_vec()), end)
+    }
+
+    // ---- MVCC ----
+
+    fn read(&self, key: &[u8], ts: u64) -> Option<Vec<u8>> {
+        let writes = self.writes.get(key)?;
+        for write in writes.range(..=ts).rev().map(|(_, write)| write) {
+            match &write.kind {
+                WriteKind::Put(value) => return Some(value.clone()),
+                WriteKind::Delete => return None,
+                WriteKind::Lock | WriteKind::Rollback => {}
+            }
+        }
+        None
+    }
+
+    fn check_lock(&self, key: &[u8], ts: u64) -> std::result::Result<(), kvrpcpb::KeyError> {
+        match self.locks.get(key) {
+            Some(lock)
+                if lock.start_ts <= ts
+                    && !matches!(lock.kind, LockKind::Lock | LockKind::Pessimistic) =>
+            {
+                Err(locked_error(key, lock))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // The commit timestamp and write of the transaction started at `start_ts`, if it wrote `key`.
+    fn find_write(&self, key: &[u8], start_ts: u64) -> Option<(u64, &Write)> {
+        self.writes.get(key).and_then(|writes| {
+            writes
+                .iter()
+                .find(|(_, write)| write.start_ts == start_ts)
+                .map(|(commit_ts, write)| (*commit_ts, write))
+        })
+    }
+
+    fn is_rolled_back(&self, key: &[u8], start_ts: u64) -> bool {
+        matches!(
+            self.find_write(key, start_ts),
+            Some((_, write)) if matches!(write.kind, WriteKind::Rollback)
+        )
+    }
+
+    fn newer_write(&self, key: &[u8], ts: u64) -> Option<(u64, &Write)> {
+        self.writes.get(key).and_then(|writes| {
+            writes
+                .range(ts + 1..)
+                .rev()
+                .find(|(_, write)| matches!(write.kind, WriteKind::Put(_) | WriteKind::Delete))
+                .map(|(commit_ts, write)| (*commit_ts, write))
+        })
+    }
+
+    fn commit_key(
+        &mut self,
+        key: &[u8],
+        start_ts: u64,
+        commit_ts: u64,
+    ) -> std::result::Result<(), kvrpcpb::KeyError> {
+        if let Some(lock) = self.locks.get(key) {
+            if lock.start_ts == start_ts {
+                let lock = self.locks.remove(key).unwrap();
+                let kind = match lock.kind {
+                    LockKind::Put(value) => WriteKind::Put(value),
+                    LockKind::Delete => WriteKind::Delete,
+                    LockKind::Lock | LockKind::Pessimistic => WriteKind::Lock,
+                };
+                self.writes
+                    .entry(key.to_vec())
+                    .or_default()
+                    .insert(commit_ts, Write { start_ts, kind });
+                return Ok(());
+            }
+        }
+        match self.find_write(key, start_ts) {
+            Some((_, write)) if !matches!(write.kind, WriteKind::Rollback) => Ok(()),
+            _ => Err(kvrpcpb::KeyError {
+                txn_not_found: Some(kvrpcpb::TxnNotFound {
+                    start_ts,
+                    primary_key: key.to_vec(),
+                }),
+                ..Default::default()
+            }),
+        }
+    }
+
+    fn rollback_key(
+        &mut self,
+        key: &[u8],
+        start_ts: u64,
+    ) -> std::result::Result<(), kvrpcpb::KeyError> {
+        if matches!(self.locks.get(key), Some(lock) if lock.start_ts == start_ts) {
+            self.locks.remove(key);
+        } else if let Some((commit_ts, write)) = self.find_write(key, start_ts) {
+            if matches!(write.kind, WriteKind::Rollback) {
+                return Ok(());
+            }
+            return Err(kvrpcpb::KeyError {
+                abort: format!("transaction {start_ts} is already committed at {commit_ts}"),
+                ..Default::default()
+            });
+        }
+        // Record the rollback, so that a late prewrite of the transaction fails.
+        self.writes.entry(key.to_vec()).or_default().insert(
+            start_ts,
+            Write {
+                start_ts,
+                kind: WriteKind::Rollback,
+            },
+        );
+        Ok(())
+    }
+
+    fn is_expired(lock: &Lock, current_ts: u64) -> bool {
+        let physical = |version: u64| Timestamp::from_version(version).physical as u64;
+        physical(current_ts) >= physical(lock.start_ts) + lock.ttl
+    }
+
+    // ---- Request handlers ----
+
+    fn kv_get(
+        &mut self,
+        req: &kvrpcpb::GetRequest,
+        region: &metapb::Region,
+    ) -> RegionResult<kvrpcpb::GetResponse> {
+        check_keys(region, [&req.key])?;
+        let mut resp = kvrpcpb::GetResponse::default();
+        if let Err(e) = self.check_lock(&req.key, req.version) {
+            resp.error = Some(e);
+            return Ok(resp);
+        }
+        match self.read(&req.key, req.version) {
+            Some(value) => resp.value = value,
+            None => resp.not_found = true,
+        }
+        Ok(resp)
+    }
+
+    fn kv_mvcc_get_by_key(
+        &mut self,
+        req: &kvrpcpb::MvccGetByKeyRequest,
+        region: &metapb::Region,
+    ) -> RegionResult<kvrpcpb::MvccGetByKeyResponse> {
+        check_keys(region, [&req.key])?;
+        // Like TiKV, list the newest writes first.
+        let writes = self.writes.get(&req.key).into_iter().flatten().rev();
+        let writes = writes
+            .map(|(commit_ts, write)| {
+                let (op, short_value) = match &write.kind {
+                    WriteKind::Put(value) => (kvrpcpb::Op::Put, value.clone()),
+                    WriteKind::Delete => (kvrpcpb::Op::Del, Vec::new()),
+                    WriteKind::Lock => (kvrpcpb::Op::Lock, Vec::new()),
+                    WriteKind::Rollback => (kvrpcpb::Op::Rollback, Vec::new()),
+                };
+                kvrpcpb::MvccWrite {
+                    r#type: op.into(),
+                    start_ts: write.start_ts,
+                    commit_ts: *commit_ts,
+                    short_value,
+                    ..Default::default()
+                }
+            })
+            .collect();
+        Ok(kvrpcpb::MvccGetByKeyResponse {
+            info: Some(kvrpcpb::MvccInfo {
+                writes,
+                ..Default::default()
+            }),
+            ..Default::default()
+        })
+    }
+
+    fn kv_batch_get(
+        &mut self,
+        req: &kvrpcpb::BatchGetRequest,
+        region: &metapb::Region,
+    ) -> RegionResult<kvrpcpb::BatchGetResponse> {
+        check_keys(region, &req.keys)?;
+        let mut resp = kvrpcpb::BatchGetResponse::default();
+        for key in &req.keys {
+            if let Err(e) = self.check_lock(key, req.version) {
+                resp.pairs.push(kvrpcpb::KvPair {
+                    error: Some(e),
+                    key: key.clone(),
+                    ..Default::default()
+                });
+            } else if let Some(value) = self.read(key, req.version) {
+                resp.pairs.push(kvrpcpb::KvPair {
+                    key: key.clone(),
+                    value,
+                    ..Default::default()
+                });
+            }
+        }
+        Ok(resp)
+    }
+
+    fn kv_scan(
+        &mut self,
+        req: &kvrpcpb::ScanRequest,
+        region: &metapb::Region,
+    ) -> RegionResult<kvrpcpb::ScanResponse> {
+        // A reverse scan's start key is its upper bound.
+        let (lower, upper) = if req.reverse {
+            (&req.end_key, &req.start_key)
+        } else {
+            (&req.start_key, &req.end_key)
+        };
+        let (start, end) = Self::clamp(region, lower, upper);
+        let bounds = Self::range_bounds(&start, &end);
+        let mut keys: Vec<&Vec<u8>> = self
+            .writes
+            .range(bounds.clone())
+            .map(|(key, _)| key)
+            .chain(self.locks.range(bounds).map(|(key, _)| key))
+            .collect();
+        keys.sort();
+        keys.dedup();
+        if req.reverse {
+            keys.reverse();
+        }
+
+        let mut resp = kvrpcpb::ScanResponse::default();
+        for key in keys {
+            if req.limit > 0 && resp.pairs.len() >= req.limit as usize {
+                break;
+            }
+            if let Err(e) = self.check_lock(key, req.version) {
+                resp.pairs.push(kvrpcpb::KvPair {
+                    error: Some(e),
+                    key: key.clone(),
+                    ..Default::default()
+                });
+            } else if let Some(value) = self.read(key, req.version) {
+                resp.pairs.push(kvrpcpb::KvPair {
+                    key: key.clone(),
+                    value: if req.key_only { Vec::new() } else { value },
+                    ..Default::default()
+                });
+            }
+        }
+        Ok(resp)
+    }
+
+    fn kv_prewrite(
+        &mut self,
+        req: &kvrpcpb::PrewriteRequest,
+        region: &metapb::Region,
+    ) -> RegionResult<kvrpcpb::PrewriteResponse> {
+        check_keys(region, req.mutations.iter().map(|m| &m.key))?;
+        let start_ts = req.start_version;
+        let mut errors = Vec::new();
+        let mut locks = Vec::new();
+        for (i, mutation) in req.mutations.iter().enumerate() {
+            let key = &mutation.key;
+            let is_pessimistic = req.is_pessimistic_lock.get(i).copied().unwrap_or(false);
+            match self.locks.get(key) {
+                Some(lock) if lock.start_ts != start_ts => {
+                    errors.push(locked_error(key, lock));
+                    continue;
+                }
+                Some(lock) if !matches!(lock.kind, LockKind::Pessimistic) => continue,
+                Some(_) => {}
+                None if is_pessimistic => {
+                    errors.push(kvrpcpb::KeyError {
+                        abort: format!("pessimistic lock of transaction {start_ts} not found"),
+                        ..Default::default()
+                    });
+                    continue;
+                }
+                None => {
+                    if let Some((commit_ts, write)) = self.newer_write(key, start_ts) {
+                        errors.push(conflict_error(
+                            key,
+                            &req.primary_lock,
+                            start_ts,
+                            commit_ts,
+                            write,
+                        ));
+                        continue;
+                    }
+                    if self.is_rolled_back(key, start_ts) {
+                        errors.push(kvrpcpb::KeyError {
+                            abort: format!("transaction {start_ts} is already rolled back"),
+                            ..Default::default()
+                        });
+                        continue;
+                    }
+                }
+            }
+            let kind = match mutation.op() {
+                kvrpcpb::Op::Put => LockKind::Put(mutation.value.clone()),
+                kvrpcpb::Op::Del => LockKind::Delete,
+                kvrpcpb::Op::Lock => LockKind::Lock,
+                kvrpcpb::Op::Insert | kvrpcpb::Op::CheckNotExists => {
+                    if self.read(key, u64::MAX).is_some() {
+                        errors.push(kvrpcpb::KeyError {
+                            already_exist: Some(kvrpcpb::AlreadyExist { key: key.clone() }),
+                            ..Default::default()
+                        });
+                        continue;
+                    }
+                    if mutation.op() == kvrpcpb::Op::CheckNotExists {
+                        continue;
+                    }
+                    LockKind::Put(mutation.value.clone())
+                }
+                op => {
+                    errors.push(kvrpcpb::KeyError {
+                        abort: format!("unexpected mutation {op:?} in prewrite"),
+                        ..Default::default()
+                    });
+                    continue;
+                }
+            };
+            locks.push((
+                key.clone(),
+                Lock {
+                    primary: req.primary_lock.clone(),
+                    start_ts,
+                    ttl: req.lock_ttl,
+                    kind,
+                    for_update_ts: req.for_update_ts,
+                    txn_size: req.txn_size,
+                },
+            ));
+        }
+        // Nothing is written unless every mutation can be.
+        if errors.is_empty() {
+            self.locks.extend(locks);
+        }
+        Ok(kvrpcpb::PrewriteResponse {
+            errors,
+            ..Default::default()
+        })
+    }
+
+    fn kv_commit(
+        &mut self,
+        req: &kvrpcpb::CommitRequest,
+        region: &metapb::Region,
+    ) -> RegionResult<kvrpcpb::CommitResponse> {
+        check_keys(region, &req.keys)?;
+        let mut resp = kvrpcpb::CommitResponse::default();
+        for key in &req.keys {
+            if let Err(e) = self.commit_key(key, req.start_version, req.commit_version) {
+                resp.error = Some(e);
+                return Ok(resp);
+            }
+        }
+        resp.commit_version = req.commit_version;
+        Ok(resp)
+    }
+
+    fn kv_batch_rollback(
+        &mut self,
+        req: &kvrpcpb::BatchRollbackRequest,
+        region: &metapb::Region,
+    ) -> RegionResult<kvrpcpb::BatchRollbackResponse> {
+        check_keys(region, &req.keys)?;
+        let mut resp = kvrpcpb::BatchRollbackResponse::default();
+        for key in &req.keys {
+            if let Err(e) = self.rollback_key(key, req.start_version) {
+                resp.error = Some(e);
+                return Ok(resp);
+            }
+        }
+        Ok(resp)
+    }
+
+    fn kv_cleanup(
+        &mut self,
+        req: &kvrpcpb::CleanupRequest,
+        region: &metapb::Region,
+    ) -> RegionResult<kvrpcpb::CleanupResponse> {
+        check_keys(region, [&req.key])?;
+        let mut resp = kvrpcpb::CleanupResponse::default();
+        if let Some(lock) = self.locks.get(&req.key) {
+            if lock.start_ts == req.start_version
+                && req.current_ts != 0
+                && !Self::is_expired(lock, req.current_ts)
+            {
+                resp.error = Some(locked_error(&req.key, lock));
+                return Ok(resp);
+            }
+        }
+        if let Some((commit_ts, write)) = self.find_write(&req.key, req.start_version) {
+            if !matches!(write.kind, WriteKind::Rollback) {
+                resp.commit_version = commit_ts;
+                return Ok(resp);
+            }
+        }
+        if let Err(e) = self.rollback_key(&req.key, req.start_version) {
+            resp.error = Some(e);
+        }
+        Ok(resp)
+    }
+
+    fn kv_check_txn_status(
+        &mut self,
+        req: &kvrpcpb::CheckTxnStatusRequest,
+        region: &metapb::Region,
+    ) -> RegionResult<kvrpcpb::CheckTxnStatusResponse> {
+        check_keys(region, [&req.primary_key])?;
+        let key = &req.primary_key;
+        let mut resp = kvrpcpb::CheckTxnStatusResponse::default();
+        if let Some(lock) = self
+            .locks
+            .get(key)
+            .filter(|lock| lock.start_ts == req.lock_ts)
+        {
+            if !Self::is_expired(lock, req.current_ts) {
+                resp.lock_ttl = lock.ttl;
+                resp.lock_info = Some(lock_info(key, lock));
+                return Ok(resp);
+            }
+            if matches!(lock.kind, LockKind::Pessimistic) {
+                self.locks.remove(key);
+                resp.action = kvrpcpb::Action::TtlExpirePessimisticRollback.into();
+            } else {
+                self.rollback_key(key, req.lock_ts).ok();
+                resp.action = kvrpcpb::Action::TtlExpireRollback.into();
+            }
+            return Ok(resp);
+        }
+        match self.find_write(key, req.lock_ts) {
+            Some((_, write)) if matches!(write.kind, WriteKind::Rollback) => {}
+            Some((commit_ts, _)) => resp.commit_version = commit_ts,
+            None if req.rollback_if_not_exist => {
+                self.rollback_key(key, req.lock_ts).ok();
+                resp.action = kvrpcpb::Action::LockNotExistRollback.into();
+            }
+            None => {
+                resp.error = Some(kvrpcpb::KeyError {
+                    txn_not_found: Some(kvrpcpb::TxnNotFound {
+                        start_ts: req.lock_ts,
+                        primary_key: key.clone(),
+                    }),
+                    ..Default::default()
+                });
+            }
+        }
+        Ok(resp)
+    }
+
+    fn kv_resolve_lock(
+        &mut self,
+        req: &kvrpcpb::ResolveLockRequest,
+        region: &metapb::Region,
+    ) -> RegionResult<kvrpcpb::ResolveLockResponse> {
+        check_keys(region, &req.keys)?;
+        let txns: Vec<(u64, u64)> = if req.txn_infos.is_empty() {
+            vec![(req.start_version, req.commit_version)]
+        } else {
+            req.txn_infos
+                .iter()
+                .map(|info| (info.txn, info.status))
+                .collect()
+        };
+        let mut resp = kvrpcpb::ResolveLockResponse::default();
+        for (start_ts, commit_ts) in txns {
+            let keys: Vec<Vec<u8>> = if req.keys.is_empty() {
+                let (start, end) = Self::clamp(region, &[], &[]);
+                self.locks
+                    .range(Self::range_bounds(&start, &end))
+                    .filter(|(_, lock)| lock.start_ts == start_ts)
+                    .map(|(key, _)| key.clone())
+                    .collect()
+            } else {
+                req.keys.clone()
+            };
+            for key in keys {
+                let result = if commit_ts > 0 {
+                    self.commit_key(&key, start_ts, commit_ts)
+                } else {
+                    self.rollback_key(&key, start_ts)
+                };
+                if let Err(e) = result {
+                    resp.error = Some(e);
+                    return Ok(resp);
+                }
+            }
+        }
+        Ok(resp)
+    }
+
+    fn kv_txn_heart_beat(
+        &mut self,
+        req: &kvrpcpb::TxnHeartBeatRequest,
+        region: &metapb::Region,
+    ) -> RegionResult<kvrpcpb::TxnHeartBeatResponse> {
+        check_keys(region, [&req.primary_lock])?;
+        let mut resp = kvrpcpb::TxnHeartBeatResponse::default();
+        match self.locks.get_mut(&req.primary_lock) {
+            Some(lock) if lock.start_ts == req.start_version => {
+                lock.ttl = lock.ttl.max(req.advise_lock_ttl);
+                resp.lock_ttl = lock.ttl;
+            }
+            _ => {
+                resp.error = Some(kvrpcpb::KeyError {
+                    txn_not_found: Some(kvrpcpb::TxnNotFound {
+                        start_ts: req.start_version,
+                        primary_key: req.primary_lock.clone(),
+                    }),
+                    ..Default::default()
+                });
+            }
+        }
+        Ok(resp)
+    }
+
+    fn kv_scan_lock(
+        &mut self,
+        req: &kvrpcpb::ScanLockRequest,
+        region: &metapb::Region,
+    ) -> RegionResult<kvrpcpb::ScanLockResponse> {
+        let (start, end) = Self::clamp(region, &req.start_key, &req.end_key);
+        let locks = self
+            .locks
+            .range(Self::range_bounds(&start, &end))
+            .filter(|(_, lock)| lock.start_ts <= req.max_version)
+            .take(if req.limit == 0 {
+                usize::MAX
+            } else {
+                req.limit as usize
+            })
+            .map(|(key, lock)| lock_info(key, lock))
+            .collect();
+        Ok(kvrpcpb::ScanLockResponse {
+            locks,
+            ..Default::default()
+        })
+    }
+
+    fn kv_pessimistic_lock(
+        &mut self,
+        req: &kvrpcpb::PessimisticLockRequest,
+        region: &metapb::Region,
+    ) -> RegionResult<kvrpcpb::PessimisticLockResponse> {
+        check_keys(region, req.mutations.iter().map(|m| &m.key))?;
+        let start_ts = req.start_version;
+        let mut resp = kvrpcpb::PessimisticLockResponse::default();
+        for mutation in &req.mutations {
+            let key = &mutation.key;
+            match self.locks.get(key) {
+                Some(lock) if lock.start_ts != start_ts => {
+                    resp.errors.push(locked_error(key, lock));
+                }
+                Some(_) => {}
+                None => {
+                    if let Some((commit_ts, write)) = self.newer_write(key, req.for_update_ts) {
+                        resp.errors.push(conflict_error(
+                            key,
+                            &req.primary_lock,
+                            start_ts,
+                            commit_ts,
+                            write,
+                        ));
+                    } else if self.is_rolled_back(key, start_ts) {
+                        resp.errors.push(kvrpcpb::KeyError {
+                            abort: format!("transaction {start_ts} is already rolled back"),
+                            ..Default::default()
+                        });
+                    }
+                }
+            }
+        }
+        if !resp.errors.is_empty() {
+            return Ok(resp);
+        }
+
+        for mutation in &req.mutations {
+            let key = &mutation.key;
+            if !self.locks.contains_key(key) {
+                self.locks.insert(
+                    key.clone(),
+                    Lock {
+                        primary: req.primary_lock.clone(),
+                        start_ts,
+                        ttl: req.lock_ttl,
+                        kind: LockKind::Pessimistic,
+                        for_update_ts: req.for_update_ts,
+                        txn_size: 0,
+                    },
+                );
+            }
+            if req.return_values || req.check_existence {
+                let value = self.read(key, req.for_update_ts);
+                resp.not_founds.push(value.is_none());
+                if req.return_values {
+                    resp.values.push(value.unwrap_or_default());
+                }
+            }
+        }
+        Ok(resp)
+    }
+
+    fn kv_pessimistic_rollback(
+        &mut self,
+        req: &kvrpcpb::PessimisticRollbackRequest,
+        region: &metapb::Region,
+    ) -> RegionResult<kvrpcpb::PessimisticRollbackResponse> {
+        check_keys(region, &req.keys)?;
+        for key in &req.keys {
+            if matches!(
+                self.locks.get(key),
+                Some(lock) if lock.start_ts == req.start_version
+                    && matches!(lock.kind, LockKind::Pessimistic)
+                    && lock.for_update_ts <= req.for_update_ts
+            ) {
+                self.locks.remove(key);
+            }
+        }
+        Ok(kvrpcpb::PessimisticRollbackResponse::default())
+    }
+
+    fn raw_get(
+        &mut self,
+        req: &kvrpcpb::RawGetRequest,
+        region: &metapb::Region,
+    ) -> RegionResult<kvrpcpb::RawGetResponse> {
+        check_keys(region, [&req.key])?;
+        let mut resp = kvrpcpb::RawGetResponse::default();
+        match self.raw_cf(&req.cf).get(&req.key) {
+            Some(value) => resp.value = value.clone(),
+            None => resp.not_found = true,
+        }
+        Ok(resp)
+    }
+
+    fn raw_batch_get(
+        &mut self,
+        req: &kvrpcpb::RawBatchGetRequest,
+        region: &metapb::Region,
+    ) -> RegionResult<kvrpcpb::RawBatchGetResponse> {
+        check_keys(region, &req.keys)?;
+        let cf = self.raw_cf(&req.cf);
+        let pairs = req
+            .keys
+            .iter()
+            .filter_map(|key| {
+                cf.get(key).map(|value| kvrpcpb::KvPair {
+                    key: key.clone(),
+                    value: value.clone(),
+                    ..Default::default()
+                })
+            })
+            .collect();
+        Ok(kvrpcpb::RawBatchGetResponse {
+            pairs,
+            ..Default::default()
+        })
+    }
+
+    fn raw_put(
+        &mut self,
+        req: &kvrpcpb::RawPutRequest,
+        region: &metapb::Region,
+    ) -> RegionResult<kvrpcpb::RawPutResponse> {
+        check_keys(region, [&req.key])?;
+        self.raw_cf(&req.cf)
+            .insert(req.key.clone(), req.value.clone());
+        Ok(kvrpcpb::RawPutResponse::default())
+    }
+
+    fn raw_batch_put(
+        &mut self,
+        req: &kvrpcpb::RawBatchPutRequest,
+        region: &metapb::Region,
+    ) -> RegionResult<kvrpcpb::RawBatchPutResponse> {
+        check_keys(region, req.pairs.iter().map(|pair| &pair.key))?;
+        let cf = self.raw_cf(&req.cf);
+        for pair in &req.pairs {
+            cf.insert(pair.key.clone(), pair.value.clone());
+        }
+        Ok(kvrpcpb::RawBatchPutResponse::default())
+    }
+
+    fn raw_delete(
+        &mut self,
+        req: &kvrpcpb::RawDeleteRequest,
+        region: &metapb::Region,
+    ) -> RegionResult<kvrpcpb::RawDeleteResponse> {
+        check_keys(region, [&req.key])?;
+        self.raw_cf(&req.cf).remove(&req.key);
+        Ok(kvrpcpb::RawDeleteResponse::default())
+    }
+
+    fn raw_batch_delete(
+        &mut self,
+        req: &kvrpcpb::RawBatchDeleteRequest,
+        region: &metapb::Region,
+    ) -> RegionResult<kvrpcpb::RawBatchDeleteResponse> {
+        check_keys(region, &req.keys)?;
+        let cf = self.raw_cf(&req.cf);
+        for key in &req.keys {
+            cf.remove(key);
+        }
+        Ok(kvrpcpb::RawBatchDeleteResponse::default())
+    }
+
+    fn raw_delete_range(
+        &mut self,
+        req: &kvrpcpb::RawDeleteRangeRequest,
+        region: &metapb::Region,
+    ) -> RegionResult<kvrpcpb::RawDeleteRangeResponse> {
+        let (start, end) = Self::clamp(region, &req.start_key, &req.end_key);
+        let cf = self.raw_cf(&req.cf);
+        let keys: Vec<Vec<u8>> = cf
+            .range(Self::range_bounds(&start, &end))
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in keys {
+            cf.remove(&key);
+        }
+        Ok(kvrpcpb::RawDeleteRangeResponse::default())
+    }
+
+    fn raw_scan(
+        &mut self,
+        req: &kvrpcpb::RawScanRequest,
+        region: &metapb::Region,
+    ) -> RegionResult<kvrpcpb::RawScanResponse> {
+        // A reverse scan's start key is its upper bound.
+        let (lower, upper) = if req.reverse {
+            (&req.end_key, &req.start_key)
+        } else {
+            (&req.start_key, &req.end_key)
+        };
+        let (start, end) = Self::clamp(region, lower, upper);
+        let limit = if req.limit == 0 {
+            usize::MAX
+        } else {
+            req.limit as usize
+        };
+        let to_pair = |(key, value): (&Vec<u8>, &Vec<u8>)| kvrpcpb::KvPair {
+            key: key.clone(),
+            value: if req.key_only {
+                Vec::new()
+            } else {
+                value.clone()
+            },
+            ..Default::default()
+        };
+        let range = self.raw_cf(&req.cf).range(Self::range_bounds(&start, &end));
+        let kvs = if req.reverse {
+            range.rev().take(limit).map(to_pair).collect()
+        } else {
+            range.take(limit).map(to_pair).collect()
+        };
+        Ok(kvrpcpb::RawScanResponse {
+            kvs,
+            ..Default::default()
+        })
+    }
+
+    fn raw_compare_and_swap(
+        &mut self,
+        req: &kvrpcpb::RawCasRequest,
+        region: &metapb::Region,
+    ) -> RegionResult<kvrpcpb::RawCasResponse> {
+        check_keys(region, [&req.key])?;
+        let cf = self.raw_cf(&req.cf);
+        let previous = cf.get(&req.key).cloned();
+        let succeed = match &previous {
+            Some(value) => !req.previous_not_exist && value == &req.previous_value,
+            None => req.previous_not_exist,
+        };
+        if succeed {
+            cf.insert(req.key.clone(), req.value.clone());
+        }
+        Ok(kvrpcpb::RawCasResponse {
+            succeed,
+            previous_not_exist: previous.is_none(),
+            previous_value: previous.unwrap_or_default(),
+            ..Default::default()
+        })
+    }
+
+    fn raw_cf(&mut self, cf: &str) -> &mut BTreeMap<Vec<u8>, Vec<u8>> {
+        self.raw.entry(cf.to_owned()).or_default()
+    }
+}
+
+// Generates `State::serve`, which checks a request's region and passes it to its handler, and
+// `context_of`, for every kind of request the simulation supports.
+macro_rules! simulated_requests {
+    ($($request:ident => $response:ident: $handler:ident,)*) => {
+        impl State {
+            // Handle `req`, or reject it with `rejection` if there is one.
+            fn serve(
+                &mut self,
+                store_id: StoreId,
+                req: &dyn Request,
+                rejection: Option<errorpb::Error>,
+            ) -> Result<Box<dyn Any>> {
+                let req = req.as_any();
+                $(
+                    if let Some(req) = req.downcast_ref::<kvrpcpb::$request>() {
+                        let region = match rejection {
+                            Some(e) => Err(e),
+                            None => self.check_region(store_id, req.context.as_ref()),
+                        };
+                        let resp = region
+                            .and_then(|region| self.$handler(req, &region))
+                            .unwrap_or_else(|e| kvrpcpb::$response {
+                                region_error: Some(e),
+                                ..Default::default()
+                            });
+                        return Ok(Box::new(resp) as Box<dyn Any>);
+                    }
+                )*
+                Err(Error::GrpcAPI(tonic::Status::unimplemented(
+                    "request is not supported by the simulation",
+                )))
+            }
+        }
+
+        fn context_of(req: &dyn Any) -> Option<&kvrpcpb::Context> {
+            $(
+                if let Some(req) = req.downcast_ref::<kvrpcpb::$request>() {
+                    return req.context.as_ref();
+                }
+            )*
+            None
+        }
+    };
+}
+
+simulated_requests! {
+    GetRequest => GetResponse: kv_get,
+    BatchGetRequest => BatchGetResponse: kv_batch_get,
+    ScanRequest => ScanResponse: kv_scan,
+    PrewriteRequest => PrewriteResponse: kv_prewrite,
+    CommitRequest => CommitResponse: kv_commit,
+    BatchRollbackRequest => BatchRollbackResponse: kv_batch_rollback,
+    CleanupRequest => CleanupResponse: kv_cleanup,
+    CheckTxnStatusRequest => CheckTxnStatusResponse: kv_check_txn_status,
+    ResolveLockRequest => ResolveLockResponse: kv_resolve_lock,
+    TxnHeartBeatRequest => TxnHeartBeatResponse: kv_txn_heart_beat,
+    ScanLockRequest => ScanLockResponse: kv_scan_lock,
+    MvccGetByKeyRequest => MvccGetByKeyResponse: kv_mvcc_get_by_key,
+    PessimisticLockRequest => PessimisticLockResponse: kv_pessimistic_lock,
+    PessimisticRollbackRequest => PessimisticRollbackResponse: kv_pessimistic_rollback,
+    RawGetRequest => RawGetResponse: raw_get,
+    RawBatchGetRequest => RawBatchGetResponse: raw_batch_get,
+    RawPutRequest => RawPutResponse: raw_put,
+    RawBatchPutRequest => RawBatchPutResponse: raw_batch_put,
+    RawDeleteRequest => RawDeleteResponse: raw_delete,
+    RawBatchDeleteRequest => RawBatchDeleteResponse: raw_batch_delete,
+    RawDeleteRangeRequest => RawDeleteRangeResponse: raw_delete_range,
+    RawScanRequest => RawScanResponse: raw_scan,
+    RawCasRequest => RawCasResponse: raw_compare_and_swap,
+}
+
+fn check_keys<'a>(
+    region: &metapb::Region,
+    keys: impl IntoIterator<Item = &'a Vec<u8>>,
+) -> RegionResult<()> {
+    for key in keys {
+        let in_region =
+            key >= &region.start_key && (region.end_key.is_empty() || key < &region.end_key);
+        if !in_region {
+            return Err(errorpb::Error {
+                message: "key not in region".to_owned(),
+                key_not_in_region: Some(errorpb::KeyNotInRegion {
+                    key: key.clone(),
+                    region_id: region.id,
+                    start_key: region.start_key.clone(),
+                    end_key: region.end_key.clone(),
+                }),
+                ..Default::default()
+            });
+        }
+    }
+    Ok(())
+}
+
+fn lock_info(key: &[u8], lock: &Lock) -> kvrpcpb::LockInfo {
+    let lock_type = match lock.kind {
+        LockKind::Put(_) => kvrpcpb::Op::Put,
+        LockKind::Delete => kvrpcpb::Op::Del,
+        LockKind::Lock => kvrpcpb::Op::Lock,
+        LockKind::Pessimistic => kvrpcpb::Op::PessimisticLock,
+    };
+    kvrpcpb::LockInfo {
+        primary_lock: lock.primary.clone(),
+        lock_version: lock.start_ts,
+        key: key.to_vec(),
+        lock_ttl: lock.ttl,
+        txn_size: lock.txn_size,
+        lock_type: lock_type.into(),
+        lock_for_update_ts: lock.for_update_ts,
+        ..Default::default()
+    }
+}
+
+fn locked_error(key: &[u8], lock: &Lock) -> kvrpcpb::KeyError {
+    kvrpcpb::KeyError {
+        locked: Some(lock_info(key, lock)),
+        ..Default::default()
+    }
+}
+
+fn conflict_error(
+    key: &[u8],
+    primary: &[u8],
+    start_ts: u64,
+    commit_ts: u64,
+    write: &Write,
+) -> kvrpcpb::KeyError {
+    kvrpcpb::KeyError {
+        conflict: Some(kvrpcpb::WriteConflict {
+            start_ts,
+            conflict_ts: write.start_ts,
+            key: key.to_vec(),
+            primary: primary.to_vec(),
+            conflict_commit_ts: commit_ts,
+        }),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::CheckLevel;
+    use crate::transaction::HeartbeatOption;
+    use crate::ErrorCode;
+    use crate::KvPair;
+    use crate::RetryEvent;
+    use crate::RetryReason;
+
+    fn options() -> TransactionOptions {
+        TransactionOptions::new_optimistic()
+            .drop_check(CheckLevel::None)
+            .heartbeat_option(HeartbeatOption::NoHeartbeat)
+    }
+
+    async fn get(sim: &Simulation, key: &[u8]) -> Option<Vec<u8>> {
+        let mut snapshot = sim.begin_with_options(options().read_only()).await.unwrap();
+        snapshot.get(key.to_vec()).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_raw_with_splits_and_leader_transfers() {
+        let sim = Simulation::new(1);
+        let client = sim.raw_client();
+        client
+            .batch_put((0..20u8).map(|i| (vec![i], vec![i])))
+            .await
+            .unwrap();
+
+        // Change the topology under the next requests, so that the client's cached regions and
+        // leaders are stale.
+        let mut sequence = sim.requests().len() as u64;
+        sim.inject_faults(move |request| {
+            sequence += 1;
+            match sequence {
+                2 => vec![Fault::Split(vec![10].into())],
+                3 => vec![Fault::TransferLeader {
+                    region_id: request.region_id,
+                    store_id: 2,
+                }],
+                4 => vec![Fault::Split(vec![5].into())],
+                _ => Vec::new(),
+            }
+        });
+        for i in 0..20u8 {
+            assert_eq!(client.get(vec![i]).await.unwrap(), Some(vec![i]));
+        }
+        let pairs = client.scan(vec![3]..vec![15], 100).await.unwrap();
+        assert_eq!(pairs.len(), 12);
+        client.delete_range(vec![8]..vec![12]).await.unwrap();
+        assert_eq!(client.scan(.., 100).await.unwrap().len(), 16);
+
+        let regions = sim.regions();
+        assert_eq!(regions.len(), 3);
+        assert_eq!(regions[1].start_key, vec![5].into());
+        assert!(regions.iter().any(|region| region.leader_store_id == 2));
+    }
+
+    #[tokio::test]
+    async fn test_scan_with_splits() {
+        let sim = Simulation::new(30);
+        let client = sim.raw_client();
+        client
+            .batch_put((0..20u8).map(|i| (vec![i], vec![i])))
+            .await
+            .unwrap();
+        let mut txn = sim.begin_with_options(options()).await.unwrap();
+        for i in 0..20u8 {
+            txn.put(vec![100 + i], vec![i]).await.unwrap();
+        }
+        txn.commit().await.unwrap();
+
+        // Split the region under the next scans, so that each is retried on the new regions.
+        let mut splits = vec![117, 105, 110, 18, 13, 2, 5];
+        sim.inject_faults(move |request| {
+            if !request.label.ends_with("scan") {
+                return Vec::new();
+            }
+            splits
+                .pop()
+                .map_or_else(Vec::new, |key| vec![Fault::Split(vec![key].into())])
+        });
+        let keys = |pairs: Vec<KvPair>| -> Vec<u8> {
+            pairs
+                .into_iter()
+                .map(|pair| Vec::from(pair.into_key())[0])
+                .collect()
+        };
+        let pairs = client.scan(.., 8).await.unwrap();
+        assert_eq!(keys(pairs), (0..8).collect::<Vec<_>>());
+        let last = client.last(..vec![20]).await.unwrap();
+        assert_eq!(keys(last.into_iter().collect()), vec![19]);
+
+        let mut snapshot = sim.begin_with_options(options().read_only()).await.unwrap();
+        let pairs = snapshot.scan(vec![100].., 30).await.unwrap();
+        assert_eq!(keys(pairs.collect()), (100..120).collect::<Vec<_>>());
+        let pairs = snapshot.scan_reverse(vec![100].., 5).await.unwrap();
+        assert_eq!(keys(pairs.collect()), (115..120).rev().collect::<Vec<_>>());
+        assert!(sim.regions().len() > 4);
+    }
+
+    #[tokio::test]
+    async fn test_batch_get_values() {
+        let sim = Simulation::new(31);
+        sim.split(vec![5]);
+        let mut txn = sim.begin_with_options(options()).await.unwrap();
+        for i in 0..10u8 {
+            txn.put(vec![i], vec![i]).await.unwrap();
+        }
+        txn.commit().await.unwrap();
+
+        let mut snapshot = sim.begin_with_options(options().read_only()).await.unwrap();
+        let keys = vec![vec![7], vec![1], vec![20], vec![7], vec![1]];
+        let values = snapshot.batch_get_values(keys).await.unwrap();
+        assert_eq!(values, vec![
+            Some(vec![7]),
+            Some(vec![1]),
+            None,
+            Some(vec![7]),
+            Some(vec![1]),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_retry_observer() {
+        let sim = Simulation::new(24);
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let observed = events.clone();
+        sim.set_retry_observer(move |event| observed.lock().unwrap().push(event));
+        let client = sim.raw_client();
+        client.put(vec![1], vec![1]).await.unwrap();
+        assert!(events.lock().unwrap().is_empty());
+
+        sim.transfer_leader(1, 2).unwrap();
+        assert_eq!(client.get(vec![1]).await.unwrap(), Some(vec![1]));
+        sim.split(vec![5]);
+        assert_eq!(client.get(vec![1]).await.unwrap(), Some(vec![1]));
+        let events = events.lock().unwrap();
+        // A new leader is tried at once, while an epoch mismatch is backed off.
+        let retry = |reason, delay, store_id| RetryEvent {
+            reason,
+            attempt: 1,
+            delay,
+            will_retry: true,
+            region_id: Some(1),
+            store_address: Some(store_address(store_id)),
+        };
+        let expected = vec![
+            retry(RetryReason::NotLeader, None, 1),
+            retry(RetryReason::EpochNotMatch, Some(Duration::from_millis(2)), 2),
+        ];
+        assert_eq!(*events, expected);
+    }
+
+    #[tokio::test]
+    async fn test_server_is_busy() {
+        let sim = Simulation::new(29);
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let observed = events.clone();
+        sim.set_retry_observer(move |event| observed.lock().unwrap().push(event));
+        let client = sim.raw_client();
+        client.put(vec![1], vec![1]).await.unwrap();
+
+        let mut sequence = 0;
+        sim.inject_faults(move |_| {
+            sequence += 1;
+            match sequence {
+                1 => vec![Fault::ServerIsBusy { backoff_ms: 50 }],
+                2 => vec![Fault::ServerIsBusy { backoff_ms: 0 }],
+                3 => vec![Fault::ServerIsBusy { backoff_ms: 60_000 }],
+                _ => Vec::new(),
+            }
+        });
+        assert_eq!(client.get(vec![1]).await.unwrap(), Some(vec![1]));
+        let delays: Vec<_> = events.lock().unwrap().iter().map(|e| e.delay).collect();
+        // The store's suggestion is followed up to the backoff's longest delay, and the client's
+        // own backoff used without one.
+        assert_eq!(delays, vec![
+            Some(Duration::from_millis(50)),
+            Some(Duration::from_millis(4)),
+            Some(Duration::from_millis(500)),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_transaction_with_faults() {
+        let sim = Simulation::new(2);
+        sim.inject_faults(|request| match request.label {
+            "kv_prewrite" if request.sequence == 0 => vec![Fault::Split(b"k".to_vec().into())],
+            "kv_commit" if request.sequence < 4 => vec![Fault::TransferLeader {
+                region_id: request.region_id,
+                store_id: 3,
+            }],
+            _ => Vec::new(),
+        });
+        let mut txn = sim.begin_with_options(options()).await.unwrap();
+        txn.put(b"a".to_vec(), b"1".to_vec()).await.unwrap();
+        txn.put(b"z".to_vec(), b"2".to_vec()).await.unwrap();
+        txn.commit().await.unwrap();
+
+        let count = |label| {
+            sim.requests()
+                .iter()
+                .filter(|request| request.label == label)
+                .count()
+        };
+        assert!(count("kv_prewrite") > 2);
+        assert!(count("kv_commit") > 1);
+        assert_eq!(get(&sim, b"a").await, Some(b"1".to_vec()));
+        assert_eq!(get(&sim, b"z").await, Some(b"2".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn test_write_conflict() {
+        let sim = Simulation::new(3);
+        let mut first = sim.begin_with_options(options()).await.unwrap();
+        let mut second = sim.begin_with_options(options()).await.unwrap();
+        first.put(b"k".to_vec(), b"1".to_vec()).await.unwrap();
+        second.put(b"k".to_vec(), b"2".to_vec()).await.unwrap();
+        first.commit().await.unwrap();
+        let e = second.commit().await.unwrap_err();
+        assert!(e.is_conflict(), "{e:?}");
+        assert_eq!(get(&sim, b"k").await, Some(b"1".to_vec()));
+
+        let mut pessimistic = sim.begin_pessimistic().await.unwrap();
+        pessimistic.put(b"k".to_vec(), b"3".to_vec()).await.unwrap();
+        pessimistic.commit().await.unwrap();
+        assert_eq!(get(&sim, b"k").await, Some(b"3".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn test_leader_transfers_during_primary_commit() {
+        let sim = Simulation::new(25);
+        // Move the leader away from each of the first `transfers` commit requests, which is more
+        // than the region backoff allows for.
+        let inject = |transfers: usize| {
+            let mut commits = 0;
+            sim.inject_faults(move |request| match request.label {
+                "kv_commit" if commits < transfers => {
+                    commits += 1;
+                    vec![Fault::TransferLeader {
+                        region_id: request.region_id,
+                        store_id: request.store_id % STORE_COUNT + 1,
+                    }]
+                }
+                _ => Vec::new(),
+            });
+        };
+        let count = |label| {
+            sim.requests()
+                .iter()
+                .filter(|request| request.label == label)
+                .count()
+        };
+
+        inject(11);
+        let mut txn = sim.begin_with_options(options()).await.unwrap();
+        txn.put(b"a".to_vec(), b"1".to_vec()).await.unwrap();
+        txn.commit().await.unwrap();
+        // The retry learns of the last transfer, and follows the leader.
+        assert_eq!(count("kv_commit"), 13);
+        assert_eq!(get(&sim, b"a").await, Some(b"1".to_vec()));
+
+        // If the retry fails too, the primary shows the transaction was not committed, so the
+        // error is not undetermined, and the transaction can be rolled back.
+        inject(usize::MAX);
+        let mut txn = sim.begin_with_options(options()).await.unwrap();
+        txn.put(b"a".to_vec(), b"2".to_vec()).await.unwrap();
+        let e = txn.commit().await.unwrap_err();
+        assert!(matches!(e.root(), Error::RegionError(_)), "{e:?}");
+        assert!(count("kv_check_txn_status") > 0);
+        txn.rollback().await.unwrap();
+        sim.inject_faults(|_| Vec::new());
+        assert_eq!(get(&sim, b"a").await, Some(b"1".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn test_lost_commit_messages() {
+        let sim = Simulation::new(4);
+
+        // The primary is committed, but the client doesn't know.
+        sim.inject_faults(|request| match request.label {
+            "kv_commit" => vec![Fault::DropResponse],
+            _ => Vec::new(),
+        });
+        let mut txn = sim.begin_with_options(options()).await.unwrap();
+        txn.put(b"a".to_vec(), b"1".to_vec()).await.unwrap();
+        let e = txn.commit().await.unwrap_err();
+        assert!(matches!(e, Error::UndeterminedError { .. }), "{e:?}");
+        sim.inject_faults(|_| Vec::new());
+        assert_eq!(get(&sim, b"a").await, Some(b"1".to_vec()));
+
+        // The primary is never committed, so its lock is rolled back once it expires.
+        sim.inject_faults(|request| match request.label {
+            "kv_commit" => vec![Fault::DropRequest],
+            _ => Vec::new(),
+        });
+        let mut txn = sim.begin_with_options(options()).await.unwrap();
+        txn.put(b"a".to_vec(), b"2".to_vec()).await.unwrap();
+        assert!(txn.commit().await.is_err());
+        sim.inject_faults(|_| Vec::new());
+        sim.advance_clock(Duration::from_secs(60));
+        assert_eq!(get(&sim, b"a").await, Some(b"1".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn test_commit_again() {
+        let sim = Simulation::new(27);
+        // Apply `fault` to the first request labelled `label`.
+        let inject = |label: &'static str, fault: Fault| {
+            let mut fault = Some(fault);
+            sim.inject_faults(move |request| match request.label {
+                l if l == label => fault.take().into_iter().collect(),
+                _ => Vec::new(),
+            });
+        };
+        let count = |label| {
+            sim.requests()
+                .iter()
+                .filter(|request| request.label == label)
+                .count()
+        };
+        let begin = |value: &'static [u8]| {
+            let sim = &sim;
+            async move {
+                let mut txn = sim.begin_with_options(options()).await.unwrap();
+                txn.put(b"a".to_vec(), value.to_vec()).await.unwrap();
+                txn.put(b"b".to_vec(), value.to_vec()).await.unwrap();
+                txn
+            }
+        };
+
+        // A failed prewrite is simply sent again.
+        inject("kv_prewrite", Fault::DropRequest);
+        let mut txn = begin(b"1").await;
+        assert!(txn.commit().await.is_err());
+        txn.commit().await.unwrap().unwrap();
+        assert_eq!(get(&sim, b"b").await, Some(b"1".to_vec()));
+
+        // The primary was committed: the primary says so, and nothing is prewritten again.
+        inject("kv_commit", Fault::DropResponse);
+        let mut txn = begin(b"2").await;
+        let e = txn.commit().await.unwrap_err();
+        assert!(matches!(e, Error::UndeterminedError { .. }), "{e:?}");
+        let prewrites = count("kv_prewrite");
+        let commit_ts = txn.commit().await.unwrap().unwrap();
+        assert_eq!(count("kv_prewrite"), prewrites);
+        assert!(count("kv_check_txn_status") > 0);
+        assert!(txn.commit().await.is_err());
+        assert_eq!(get(&sim, b"b").await, Some(b"2".to_vec()));
+
+        // The primary was not committed: its commit is sent again, at a timestamp newer than any
+        // taken since the failed attempt.
+        inject("kv_commit", Fault::DropRequest);
+        let mut txn = begin(b"3").await;
+        let e = txn.commit().await.unwrap_err();
+        assert!(matches!(e, Error::UndeterminedError { .. }), "{e:?}");
+        let prewrites = count("kv_prewrite");
+        let reader = sim.begin_optimistic().await.unwrap();
+        let resumed_ts = txn.commit().await.unwrap().unwrap();
+        assert!(resumed_ts.version() > commit_ts.version());
+        assert!(resumed_ts.version() > reader.start_timestamp().version());
+        assert_eq!(count("kv_prewrite"), prewrites);
+        assert_eq!(get(&sim, b"b").await, Some(b"3".to_vec()));
+
+        // The transaction was rolled back by another client in the meantime.
+        inject("kv_commit", Fault::DropRequest);
+        let mut txn = begin(b"4").await;
+        assert!(txn.commit().await.is_err());
+        sim.advance_clock(Duration::from_secs(60));
+        assert_eq!(get(&sim, b"a").await, Some(b"3".to_vec()));
+        let e = txn.commit().await.unwrap_err();
+        assert_eq!(e.code(), ErrorCode::TransactionNotFound, "{e:?}");
+        assert_eq!(get(&sim, b"b").await, Some(b"3".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn test_deterministic() {
+        async fn run(seed: u64) -> (Vec<SimulatedRequest>, Vec<bool>) {
+            let sim = Simulation::new(seed);
+            sim.set_drop_rates(0.2, 0.2);
+            sim.split(vec![100]);
+            let client = sim.raw_client();
+            let mut outcomes = Vec::new();
+            for i in 0..50u8 {
+                outcomes.push(client.put(vec![i * 5], vec![i]).await.is_ok());
+                outcomes.push(client.batch_get(vec![vec![i], vec![200]]).await.is_ok());
+            }
+            (sim.requests(), outcomes)
+        }
+
+        let (requests, outcomes) = run(5).await;
+        assert_eq!(requests.len(), 150);
+        assert!(outcomes.contains(&true) && outcomes.contains(&false));
+        assert_eq!(run(5).await, (requests, outcomes.clone()));
+        assert_ne!(run(6).await.1, outcomes);
+    }
+}
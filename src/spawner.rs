@@ -0,0 +1,82 @@
+// Copyright 2023 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Where the client runs its background tasks.
+
+use std::fmt;
+use std::future::Future;
+use std::sync::Arc;
+
+pub(crate) use tikv_client_common::spawn_with;
+pub use tikv_client_common::Spawner;
+
+use crate::pd::PdClient;
+
+/// A shared `Spawner` which can be stored in a `Config`.
+#[derive(Clone)]
+pub(crate) struct SpawnerHandle(pub Arc<dyn Spawner>);
+
+impl fmt::Debug for SpawnerHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Spawner")
+    }
+}
+
+impl PartialEq for SpawnerHandle {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::as_ptr(&self.0) as *const () == Arc::as_ptr(&other.0) as *const ()
+    }
+}
+
+impl Eq for SpawnerHandle {}
+
+/// Run `task` in the background, with the spawner of `pd_client` if it has one.
+pub(crate) fn spawn(pd_client: &impl PdClient, task: impl Future<Output = ()> + Send + 'static) {
+    spawn_with(pd_client.spawner().as_deref(), task)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+
+    use futures::future::BoxFuture;
+
+    use super::*;
+    use crate::simulation::Simulation;
+
+    struct CountingSpawner {
+        handle: tokio::runtime::Handle,
+        spawned: Arc<AtomicUsize>,
+    }
+
+    impl Spawner for CountingSpawner {
+        fn spawn(&self, task: BoxFuture<'static, ()>) {
+            self.spawned.fetch_add(1, Ordering::SeqCst);
+            Spawner::spawn(&self.handle, task);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_spawner() {
+        let sim = Simulation::new(28);
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let spawned = Arc::new(AtomicUsize::new(0));
+        sim.set_spawner(CountingSpawner {
+            handle: runtime.handle().clone(),
+            spawned: spawned.clone(),
+        });
+
+        let mut txn = sim.begin_optimistic().await.unwrap();
+        txn.put(b"a".to_vec(), b"1".to_vec()).await.unwrap();
+        txn.put(b"b".to_vec(), b"1".to_vec()).await.unwrap();
+        txn.commit().await.unwrap();
+        // The heartbeat, and the commit of the secondary key.
+        assert_eq!(spawned.load(Ordering::SeqCst), 2);
+
+        let mut writer = sim.raw_client().buffered_writer();
+        writer.put(b"c".to_vec(), b"1".to_vec()).await.unwrap();
+        writer.flush().await.unwrap();
+        assert_eq!(spawned.load(Ordering::SeqCst), 3);
+        runtime.shutdown_background();
+    }
+}
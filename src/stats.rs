@@ -1,5 +1,7 @@
 // Copyright 2018 TiKV Project Authors. Licensed under Apache-2.0.
 
+use std::collections::HashSet;
+use std::sync::Mutex;
 use std::time::Duration;
 use std::time::Instant;
 
@@ -10,6 +12,7 @@ use prometheus::Histogram;
 use prometheus::HistogramVec;
 use prometheus::IntCounterVec;
 
+use crate::Error;
 use crate::Result;
 
 pub struct RequestStats {
@@ -18,6 +21,9 @@ pub struct RequestStats {
     duration: &'static HistogramVec,
     failed_duration: &'static HistogramVec,
     failed_counter: &'static IntCounterVec,
+    /// For TiKV requests, the address of the store the request is sent to, and the counter of
+    /// requests by store and outcome.
+    outcome_counter: Option<(String, &'static IntCounterVec)>,
 }
 
 impl RequestStats {
@@ -35,6 +41,7 @@ impl RequestStats {
             duration,
             failed_duration,
             failed_counter,
+            outcome_counter: None,
         }
     }
 
@@ -49,18 +56,28 @@ impl RequestStats {
                 .observe(duration_to_sec(self.start.elapsed()));
             self.failed_counter.with_label_values(&[self.cmd]).inc();
         }
+        if let Some((store, counter)) = &self.outcome_counter {
+            counter
+                .with_label_values(&[self.cmd, store, outcome(&r)])
+                .inc();
+        }
         r
     }
 }
 
-pub fn tikv_stats(cmd: &'static str) -> RequestStats {
-    RequestStats::new(
+/// Stats of a request to TiKV, raw or transactional, sent to the store at `store_address`, if
+/// known.
+pub fn tikv_stats(cmd: &'static str, store_address: Option<&str>) -> RequestStats {
+    let mut stats = RequestStats::new(
         cmd,
         &TIKV_REQUEST_DURATION_HISTOGRAM_VEC,
         &TIKV_REQUEST_COUNTER_VEC,
         &TIKV_FAILED_REQUEST_DURATION_HISTOGRAM_VEC,
         &TIKV_FAILED_REQUEST_COUNTER_VEC,
-    )
+    );
+    let store = store_address.unwrap_or("unknown").to_owned();
+    stats.outcome_counter = Some((store, &TIKV_REQUEST_OUTCOME_COUNTER_VEC));
+    stats
 }
 
 pub fn pd_stats(cmd: &'static str) -> RequestStats {
@@ -73,6 +90,65 @@ pub fn pd_stats(cmd: &'static str) -> RequestStats {
     )
 }
 
+/// The outcome label of a request. A request is `"ok"` if a response was received, even if the
+/// response carries a region or key error.
+fn outcome<R>(r: &Result<R>) -> &'static str {
+    match r.as_ref().map_err(Error::root) {
+        Ok(_) => "ok",
+        Err(Error::DeadlineExceeded) => "deadline_exceeded",
+        Err(Error::StoreUnavailable { .. }) => "store_unavailable",
+        Err(Error::Grpc(_)) | Err(Error::GrpcAPI(_)) => "grpc_error",
+        Err(_) => "error",
+    }
+}
+
+/// Observe the time spent in a phase of committing a transaction: `"prewrite"`,
+/// `"get_commit_ts"`, `"commit_primary"`, or `"commit_secondary"`.
+pub fn observe_commit_phase(phase: &'static str, duration: Duration) {
+    TXN_COMMIT_PHASE_DURATION_HISTOGRAM_VEC
+        .with_label_values(&[phase])
+        .observe(duration_to_sec(duration));
+}
+
+/// The most distinct transaction labels recorded in metrics, to bound their cardinality.
+const MAX_TRANSACTION_LABELS: usize = 64;
+
+/// Observe the time a transaction labelled `label` took from beginning until it reached
+/// `outcome`: `"committed"`, `"rolled_back"`, or `"failed"`.
+///
+/// Once `MAX_TRANSACTION_LABELS` labels have been seen, new labels are recorded as `"other"`.
+pub fn observe_transaction(label: &str, outcome: &'static str, duration: Duration) {
+    let label = {
+        let mut labels = TXN_LABELS.lock().unwrap();
+        if labels.contains(label) {
+            label
+        } else if labels.len() < MAX_TRANSACTION_LABELS {
+            labels.insert(label.to_owned());
+            label
+        } else {
+            "other"
+        }
+    };
+    TXN_DURATION_HISTOGRAM_VEC
+        .with_label_values(&[label, outcome])
+        .observe(duration_to_sec(duration));
+}
+
+/// Count a retry of a request to PD.
+pub fn pd_retry(cmd: &'static str) {
+    PD_REQUEST_RETRY_COUNTER_VEC.with_label_values(&[cmd]).inc();
+}
+
+/// Count a request to PD which ran out of its budget, and observe how long its attempts took.
+pub fn pd_budget_exceeded(cmd: &'static str, duration: Duration) {
+    PD_BUDGET_EXCEEDED_COUNTER_VEC
+        .with_label_values(&[cmd])
+        .inc();
+    PD_FAILED_REQUEST_DURATION_HISTOGRAM_VEC
+        .with_label_values(&[cmd])
+        .observe(duration_to_sec(duration));
+}
+
 #[allow(dead_code)]
 pub fn observe_tso_batch(batch_size: usize) {
     PD_TSO_BATCH_SIZE_HISTOGRAM.observe(batch_size as f64);
@@ -103,6 +179,12 @@ lazy_static::lazy_static! {
         &["type"]
     )
     .unwrap();
+    static ref TIKV_REQUEST_OUTCOME_COUNTER_VEC: IntCounterVec = register_int_counter_vec!(
+        "tikv_request_outcome_total",
+        "Total number of requests sent to TiKV, by store and outcome",
+        &["type", "store", "outcome"]
+    )
+    .unwrap();
     static ref PD_REQUEST_DURATION_HISTOGRAM_VEC: HistogramVec = register_histogram_vec!(
         "pd_request_duration_seconds",
         "Bucketed histogram of PD requests duration",
@@ -127,6 +209,31 @@ lazy_static::lazy_static! {
         &["type"]
     )
     .unwrap();
+    static ref PD_REQUEST_RETRY_COUNTER_VEC: IntCounterVec = register_int_counter_vec!(
+        "pd_request_retry_total",
+        "Total number of retries of requests to PD",
+        &["type"]
+    )
+    .unwrap();
+    static ref PD_BUDGET_EXCEEDED_COUNTER_VEC: IntCounterVec = register_int_counter_vec!(
+        "pd_request_budget_exceeded_total",
+        "Total number of requests to PD which did not complete within their retry budget",
+        &["type"]
+    )
+    .unwrap();
+    static ref TXN_COMMIT_PHASE_DURATION_HISTOGRAM_VEC: HistogramVec = register_histogram_vec!(
+        "tikv_txn_commit_phase_duration_seconds",
+        "Bucketed histogram of the duration of each phase of transaction commits",
+        &["phase"]
+    )
+    .unwrap();
+    static ref TXN_LABELS: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+    static ref TXN_DURATION_HISTOGRAM_VEC: HistogramVec = register_histogram_vec!(
+        "tikv_txn_duration_seconds",
+        "Bucketed histogram of the duration of transactions, by label and outcome",
+        &["label", "outcome"]
+    )
+    .unwrap();
     static ref PD_TSO_BATCH_SIZE_HISTOGRAM: Histogram = register_histogram!(
         "pd_tso_batch_size",
         "Bucketed histogram of TSO request batch size"
@@ -141,3 +248,29 @@ fn duration_to_sec(d: Duration) -> f64 {
     // In most cases, we can't have so large Duration, so here just panic if overflow now.
     d.as_secs() as f64 + (nanos / 1_000_000_000.0)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::Simulation;
+
+    #[tokio::test]
+    async fn test_request_outcomes() {
+        let sim = Simulation::new(35);
+        let store = format!("simulation://store-{}", sim.regions()[0].leader_store_id);
+        let count = |cmd: &str| {
+            TIKV_REQUEST_OUTCOME_COUNTER_VEC
+                .with_label_values(&[cmd, &store, "ok"])
+                .get()
+        };
+        let (raw_before, txn_before) = (count("raw_get"), count("kv_get"));
+
+        // Raw and transactional requests are labelled alike.
+        sim.raw_client().get(b"key".to_vec()).await.unwrap();
+        let mut txn = sim.begin_optimistic().await.unwrap();
+        txn.get(b"key".to_vec()).await.unwrap();
+        txn.rollback().await.unwrap();
+        assert!(count("raw_get") > raw_before);
+        assert!(count("kv_get") > txn_before);
+    }
+}
@@ -1,7 +1,5 @@
 // Copyright 2019 TiKV Project Authors. Licensed under Apache-2.0.
 
-use std::cmp::max;
-use std::cmp::min;
 use std::sync::Arc;
 
 use async_trait::async_trait;
@@ -23,6 +21,7 @@ use crate::Result;
 pub struct RegionStore {
     pub region_with_leader: RegionWithLeader,
     pub client: Arc<dyn KvClient + Send + Sync>,
+    pub address: String,
 }
 
 #[async_trait]
@@ -34,7 +33,7 @@ pub trait KvConnectStore: KvConnect {
     ) -> Result<RegionStore> {
         log::info!("connect to tikv endpoint: {:?}", &address);
         let client = self.connect(address.as_str()).await?;
-        Ok(RegionStore::new(region, Arc::new(client)))
+        Ok(RegionStore::new(region, Arc::new(client), address))
     }
 }
 
@@ -73,31 +72,19 @@ pub fn store_stream_for_range<PdC: PdClient>(
         BoundRange::from(range.clone())
     };
     pd_client
-        .stores_for_range(bnd_range)
+        .stores_for_range(bnd_range.clone())
         .map_ok(move |store| {
-            let region_range = store.region_with_leader.range();
-            let result_range = range_intersection(
-                region_range,
-                (range.0.clone().into(), range.1.clone().into()),
-            );
-            ((result_range.0.into(), result_range.1.into()), store)
+            // The range used for request is the part of `range` in the region.
+            let region_range = BoundRange::from(store.region_with_leader.range());
+            let (start, end) = match region_range.intersect(&bnd_range) {
+                Some(range) => range.into_keys(),
+                None => (range.0.clone().into(), Some(range.0.clone().into())),
+            };
+            ((start.into(), end.unwrap_or_default().into()), store)
         })
         .boxed()
 }
 
-/// The range used for request should be the intersection of `region_range` and `range`.
-fn range_intersection(region_range: (Key, Key), range: (Key, Key)) -> (Key, Key) {
-    let (lower, upper) = region_range;
-    let up = if upper.is_empty() {
-        range.1
-    } else if range.1.is_empty() {
-        upper
-    } else {
-        min(upper, range.1)
-    };
-    (max(lower, range.0), up)
-}
-
 pub fn store_stream_for_ranges<PdC: PdClient>(
     ranges: Vec<kvrpcpb::KeyRange>,
     pd_client: Arc<PdC>,
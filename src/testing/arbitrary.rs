@@ -0,0 +1,143 @@
+// Copyright 2023 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Implementations of [`quickcheck::Arbitrary`] for the client's types, e.g., to fuzz a storage
+//! layer built on the client against a [`Simulation`](crate::simulation::Simulation).
+//!
+//! They generate the same values as the `strategy` module of the `proptest` feature: keys are
+//! never empty, since TiKV does not accept writes of the empty key, and are at most
+//! [`MAX_KEY_LEN`](super::MAX_KEY_LEN) bytes, so that generated keys often share prefixes. Ranges
+//! never start after they end.
+//!
+//! # Examples
+//!
+//! ```rust
+//! # use quickcheck::quickcheck;
+//! # use tikv_client::testing::Mutation;
+//! fn keys_are_not_empty(mutations: Vec<Mutation>) -> bool {
+//!     mutations.iter().all(|mutation| !mutation.key().is_empty())
+//! }
+//! quickcheck(keys_are_not_empty as fn(Vec<Mutation>) -> bool);
+//! ```
+
+use std::ops::Bound;
+
+use quickcheck::Arbitrary;
+use quickcheck::Gen;
+
+use super::Mutation;
+use super::MAX_KEY_LEN;
+use super::MAX_VALUE_LEN;
+use crate::BoundRange;
+use crate::Key;
+use crate::KvPair;
+use crate::Value;
+
+fn bytes(g: &mut Gen, min_len: usize, max_len: usize) -> Vec<u8> {
+    let len = min_len + usize::arbitrary(g) % (max_len - min_len + 1);
+    (0..len).map(|_| u8::arbitrary(g)).collect()
+}
+
+fn value(g: &mut Gen) -> Value {
+    bytes(g, 0, MAX_VALUE_LEN)
+}
+
+impl Arbitrary for Key {
+    fn arbitrary(g: &mut Gen) -> Key {
+        bytes(g, 1, MAX_KEY_LEN).into()
+    }
+
+    fn shrink(&self) -> Box<dyn Iterator<Item = Key>> {
+        let keys = Vec::from(self.clone()).shrink();
+        let keys = keys.filter(|key| !key.is_empty());
+        Box::new(keys.map(Key::from))
+    }
+}
+
+impl Arbitrary for KvPair {
+    fn arbitrary(g: &mut Gen) -> KvPair {
+        KvPair::new(Key::arbitrary(g), value(g))
+    }
+
+    fn shrink(&self) -> Box<dyn Iterator<Item = KvPair>> {
+        let pairs = (self.0.clone(), self.1.clone()).shrink();
+        Box::new(pairs.map(KvPair::from))
+    }
+}
+
+impl Arbitrary for BoundRange {
+    fn arbitrary(g: &mut Gen) -> BoundRange {
+        let mut keys = [Key::arbitrary(g), Key::arbitrary(g)];
+        keys.sort();
+        let [start, end] = keys;
+        let start = if bool::arbitrary(g) {
+            Bound::Included(start)
+        } else {
+            Bound::Excluded(start)
+        };
+        let end = match g.choose(&[0, 1, 2]) {
+            Some(0) => Bound::Included(end),
+            Some(1) => Bound::Excluded(end),
+            _ => Bound::Unbounded,
+        };
+        BoundRange::from((start, end))
+    }
+}
+
+impl Arbitrary for Mutation {
+    fn arbitrary(g: &mut Gen) -> Mutation {
+        let key = Key::arbitrary(g);
+        match g.choose(&[0, 1, 2, 3]) {
+            Some(0) => Mutation::Put {
+                key,
+                value: value(g),
+            },
+            Some(1) => Mutation::Insert {
+                key,
+                value: value(g),
+            },
+            Some(2) => Mutation::Delete { key },
+            _ => Mutation::Lock { key },
+        }
+    }
+
+    fn shrink(&self) -> Box<dyn Iterator<Item = Mutation>> {
+        let mutation = self.clone();
+        Box::new(self.key().shrink().map(move |key| match &mutation {
+            Mutation::Put { value, .. } => Mutation::Put {
+                key,
+                value: value.clone(),
+            },
+            Mutation::Insert { value, .. } => Mutation::Insert {
+                key,
+                value: value.clone(),
+            },
+            Mutation::Delete { .. } => Mutation::Delete { key },
+            Mutation::Lock { .. } => Mutation::Lock { key },
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::RangeBounds;
+
+    use quickcheck::quickcheck;
+
+    use super::*;
+
+    #[test]
+    fn test_arbitrary() {
+        fn valid(pair: KvPair, range: BoundRange) -> bool {
+            let start = match range.start_bound() {
+                Bound::Included(start) | Bound::Excluded(start) => start,
+                Bound::Unbounded => return false,
+            };
+            let ordered = match range.end_bound() {
+                Bound::Included(end) | Bound::Excluded(end) => start <= end,
+                Bound::Unbounded => true,
+            };
+            ordered && (1..=MAX_KEY_LEN).contains(&pair.key().len())
+        }
+        quickcheck(valid as fn(KvPair, BoundRange) -> bool);
+    }
+}
@@ -0,0 +1,630 @@
+// Copyright 2023 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Recording the transactions of concurrent clients and checking them for consistency.
+//!
+//! A [`History`] records when each transaction started and finished, what it read and wrote, and
+//! whether it committed. [`History::check`] then searches for an order of the transactions which
+//! explains every read, respects real time (a transaction which finished before another started
+//! comes first), and satisfies the isolation level given by [`Consistency`]. This is the approach
+//! of checkers like Knossos and Porcupine, applied to a key-value store. Use it to test that an
+//! application's transactions stay consistent under contention and faults, e.g., against a
+//! [`Simulation`](crate::simulation::Simulation) which drops requests.
+//!
+//! A transaction whose outcome is unknown, e.g., because its commit failed with
+//! [`Error::UndeterminedError`], may or may not have taken effect, at any time after it started.
+//! Aborted transactions are ignored, including their reads.
+//!
+//! The search is exponential in the number of concurrent transactions in the worst case, so keep
+//! histories small: tens of clients and hundreds of transactions. Keys the history doesn't write
+//! are expected to be absent, so use a fresh cluster or key prefix.
+//!
+//! # Examples
+//!
+//! ```rust
+//! # use tikv_client::testing::history::{CheckResult, Consistency, History, Outcome};
+//! let history = History::new();
+//! let mut first = history.begin();
+//! let mut second = history.begin();
+//! first.read(b"x".to_vec(), None);
+//! second.read(b"x".to_vec(), None);
+//! first.write(b"x".to_vec(), b"1".to_vec());
+//! second.write(b"x".to_vec(), b"2".to_vec());
+//! first.finish(Outcome::Committed);
+//! second.finish(Outcome::Committed);
+//!
+//! // Both transactions overwrote the value they read: a lost update.
+//! assert!(matches!(
+//!     history.check(Consistency::SnapshotIsolation),
+//!     CheckResult::Inconsistent(_)
+//! ));
+//! ```
+
+use std::collections::BTreeMap;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use crate::Error;
+use crate::Key;
+use crate::Result;
+use crate::Value;
+
+// How many states the search visits before giving up.
+const MAX_STATES: usize = 1_000_000;
+
+/// A record of the transactions of concurrent clients.
+///
+/// Cloning a `History` gives another handle to the same record.
+#[derive(Clone, Default)]
+pub struct History {
+    inner: Arc<Mutex<Recorded>>,
+}
+
+#[derive(Default)]
+struct Recorded {
+    // A logical clock, ticked by each transaction's start and finish.
+    clock: u64,
+    transactions: Vec<RecordedTransaction>,
+}
+
+struct RecordedTransaction {
+    started: u64,
+    finished: Option<u64>,
+    operations: Vec<Operation>,
+    outcome: Outcome,
+}
+
+/// An operation of a recorded transaction.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Operation {
+    /// Reading `value` from `key`, or finding it absent.
+    Read { key: Key, value: Option<Value> },
+    /// Writing `value` to `key`, or deleting it.
+    Write { key: Key, value: Option<Value> },
+}
+
+/// How a recorded transaction finished.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// The transaction committed.
+    Committed,
+    /// The transaction did not take effect.
+    Aborted,
+    /// The transaction may or may not have taken effect.
+    Unknown,
+}
+
+impl Outcome {
+    /// The outcome of a transaction whose commit returned `result`.
+    pub fn of<T>(result: &Result<T>) -> Outcome {
+        match result {
+            Ok(_) => Outcome::Committed,
+            Err(e) if matches!(e.root(), Error::UndeterminedError { .. }) => {
+                Outcome::Unknown
+            }
+            Err(_) => Outcome::Aborted,
+        }
+    }
+}
+
+/// The isolation level a history is checked against.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Consistency {
+    /// Each transaction reads a snapshot taken when it started, and fails to commit if another
+    /// transaction committed a write to one of its keys since. This is what TiKV's transactions
+    /// guarantee; it allows write skew.
+    SnapshotIsolation,
+    /// Each transaction takes effect at a single point in time, as if the transactions ran one at a
+    /// time.
+    Serializable,
+}
+
+/// The result of [`History::check`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CheckResult {
+    /// An order of the transactions explains the history.
+    Consistent,
+    /// No order of the transactions explains the history, for the given reason.
+    Inconsistent(String),
+    /// The history is too large or too concurrent to check.
+    Inconclusive,
+}
+
+/// Records a transaction of a [`History`]. Create one with [`History::begin`].
+///
+/// A transaction which is never [finished](Recorder::finish) has an unknown outcome.
+pub struct Recorder {
+    history: History,
+    index: usize,
+}
+
+impl History {
+    pub fn new() -> History {
+        History::default()
+    }
+
+    /// Record that a transaction starts.
+    ///
+    /// Call this before beginning the transaction, so that its start timestamp is taken after
+    /// every transaction the history saw finish.
+    pub fn begin(&self) -> Recorder {
+        let mut recorded = self.inner.lock().unwrap();
+        recorded.clock += 1;
+        let started = recorded.clock;
+        recorded.transactions.push(RecordedTransaction {
+            started,
+            finished: None,
+            operations: Vec::new(),
+            outcome: Outcome::Unknown,
+        });
+        Recorder {
+            history: self.clone(),
+            index: recorded.transactions.len() - 1,
+        }
+    }
+
+    /// The number of recorded transactions.
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().transactions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Check whether an order of the recorded transactions satisfies `consistency`.
+    pub fn check(&self, consistency: Consistency) -> CheckResult {
+        let recorded = self.inner.lock().unwrap();
+        let mut candidates = Vec::new();
+        for (index, transaction) in recorded.transactions.iter().enumerate() {
+            if transaction.outcome == Outcome::Aborted {
+                continue;
+            }
+            match Candidate::new(index, transaction) {
+                Ok(candidate) => candidates.push(candidate),
+                Err(reason) => return CheckResult::Inconsistent(reason),
+            }
+        }
+        Checker::new(candidates, consistency).check()
+    }
+}
+
+impl Recorder {
+    /// Record that the transaction read `value` from `key`, or found it absent.
+    pub fn read(&mut self, key: impl Into<Key>, value: Option<Value>) {
+        self.push(Operation::Read {
+            key: key.into(),
+            value,
+        });
+    }
+
+    /// Record that the transaction wrote `value` to `key`.
+    pub fn write(&mut self, key: impl Into<Key>, value: impl Into<Value>) {
+        self.push(Operation::Write {
+            key: key.into(),
+            value: Some(value.into()),
+        });
+    }
+
+    /// Record that the transaction deleted `key`.
+    pub fn delete(&mut self, key: impl Into<Key>) {
+        self.push(Operation::Write {
+            key: key.into(),
+            value: None,
+        });
+    }
+
+    /// Record that the transaction finished with `outcome`.
+    pub fn finish(self, outcome: Outcome) {
+        let mut recorded = self.history.inner.lock().unwrap();
+        recorded.clock += 1;
+        let finished = recorded.clock;
+        let transaction = &mut recorded.transactions[self.index];
+        transaction.finished = Some(finished);
+        transaction.outcome = outcome;
+    }
+
+    fn push(&mut self, operation: Operation) {
+        let mut recorded = self.history.inner.lock().unwrap();
+        recorded.transactions[self.index].operations.push(operation);
+    }
+}
+
+// A transaction which may appear in an order of the history. Each transaction reads its snapshot
+// and commits its writes at two points in the order, which are the same point when checking
+// serializability.
+struct Candidate {
+    index: usize,
+    started: u64,
+    // The points must come before any transaction which started after these times.
+    read_deadline: u64,
+    commit_deadline: u64,
+    // Whether the transaction must commit.
+    required: bool,
+    // The reads of keys the transaction hadn't written itself.
+    reads: Vec<(Key, Option<Value>)>,
+    writes: BTreeMap<Key, Option<Value>>,
+}
+
+impl Candidate {
+    fn new(
+        index: usize,
+        transaction: &RecordedTransaction,
+    ) -> std::result::Result<Candidate, String> {
+        let mut reads = Vec::new();
+        let mut writes = BTreeMap::new();
+        for operation in &transaction.operations {
+            match operation {
+                Operation::Read { key, value } => match writes.get(key) {
+                    Some(written) if written != value => {
+                        return Err(format!(
+                            "transaction {index} read {value:?} from key {key:?} after writing \
+                             {written:?}"
+                        ));
+                    }
+                    Some(_) => {}
+                    None => reads.push((key.clone(), value.clone())),
+                },
+                Operation::Write { key, value } => {
+                    writes.insert(key.clone(), value.clone());
+                }
+            }
+        }
+        let finished = transaction.finished.unwrap_or(u64::MAX);
+        let required = transaction.outcome == Outcome::Committed;
+        Ok(Candidate {
+            index,
+            started: transaction.started,
+            read_deadline: finished,
+            commit_deadline: if required { finished } else { u64::MAX },
+            required,
+            reads,
+            writes,
+        })
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct State {
+    data: BTreeMap<Key, Value>,
+    read: Vec<bool>,
+    committed: Vec<bool>,
+    // Transactions which read before another transaction committed a write to one of their keys,
+    // and so can't commit under snapshot isolation.
+    doomed: Vec<bool>,
+    // The latest start of a transaction whose point is in the order so far.
+    frontier: u64,
+}
+
+struct Checker {
+    candidates: Vec<Candidate>,
+    consistency: Consistency,
+    visited: HashSet<State>,
+    exhausted: bool,
+    // The read which failed after the longest order, with that order's length.
+    deepest_failure: Option<(usize, String)>,
+}
+
+impl Checker {
+    fn new(candidates: Vec<Candidate>, consistency: Consistency) -> Checker {
+        Checker {
+            candidates,
+            consistency,
+            visited: HashSet::new(),
+            exhausted: false,
+            deepest_failure: None,
+        }
+    }
+
+    fn check(mut self) -> CheckResult {
+        let len = self.candidates.len();
+        let state = State {
+            data: BTreeMap::new(),
+            read: vec![false; len],
+            committed: vec![false; len],
+            doomed: vec![false; len],
+            frontier: 0,
+        };
+        if self.search(state, 0) {
+            CheckResult::Consistent
+        } else if self.exhausted {
+            CheckResult::Inconclusive
+        } else {
+            CheckResult::Inconsistent(match self.deepest_failure {
+                Some((_, reason)) => reason,
+                None => format!(
+                    "no order of the transactions satisfies {:?}: concurrent transactions \
+                     committed writes to the same keys",
+                    self.consistency
+                ),
+            })
+        }
+    }
+
+    fn search(&mut self, state: State, depth: usize) -> bool {
+        let candidates = &self.candidates;
+        if (0..candidates.len()).all(|i| state.committed[i] || !candidates[i].required) {
+            return true;
+        }
+        if (0..candidates.len()).any(|i| state.doomed[i] && candidates[i].required) {
+            return false;
+        }
+        if self.visited.len() >= MAX_STATES {
+            self.exhausted = true;
+            return false;
+        }
+        if !self.visited.insert(state.clone()) {
+            return false;
+        }
+
+        // The next point must come before the earliest deadline of a point which must still be
+        // ordered.
+        let deadline = (0..candidates.len())
+            .filter(|&i| candidates[i].required)
+            .flat_map(|i| {
+                let candidate = &candidates[i];
+                let read = (!state.read[i]).then_some(candidate.read_deadline);
+                let commit = (!state.committed[i]).then_some(candidate.commit_deadline);
+                read.into_iter().chain(commit)
+            })
+            .min()
+            .unwrap_or(u64::MAX);
+
+        let snapshot_isolation = self.consistency == Consistency::SnapshotIsolation;
+        for i in 0..self.candidates.len() {
+            let candidate = &self.candidates[i];
+            let (started, read_deadline, commit_deadline) = (
+                candidate.started,
+                candidate.read_deadline,
+                candidate.commit_deadline,
+            );
+            if started > deadline || state.committed[i] || state.doomed[i] {
+                continue;
+            }
+            let mut next = state.clone();
+            next.frontier = next.frontier.max(started);
+            if !state.read[i] {
+                if snapshot_isolation && read_deadline < state.frontier {
+                    continue;
+                }
+                if let Err(reason) = self.check_reads(i, &state.data) {
+                    let deepest = self.deepest_failure.as_ref();
+                    if deepest.is_none_or(|(failed_depth, _)| *failed_depth < depth) {
+                        self.deepest_failure = Some((depth, reason));
+                    }
+                    continue;
+                }
+                next.read[i] = true;
+                if snapshot_isolation {
+                    if self.search(next, depth + 1) {
+                        return true;
+                    }
+                    continue;
+                }
+            }
+            if commit_deadline < state.frontier {
+                continue;
+            }
+            self.commit(i, &mut next);
+            if self.search(next, depth + 1) {
+                return true;
+            }
+        }
+        false
+    }
+
+    fn check_reads(
+        &self,
+        i: usize,
+        data: &BTreeMap<Key, Value>,
+    ) -> std::result::Result<(), String> {
+        let candidate = &self.candidates[i];
+        for (key, value) in &candidate.reads {
+            let expected = data.get(key);
+            if value.as_ref() != expected {
+                return Err(format!(
+                    "no order of the transactions explains transaction {} reading {value:?} \
+                     from key {key:?}; at best, it would have read {expected:?}",
+                    candidate.index
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn commit(&self, i: usize, state: &mut State) {
+        let candidate = &self.candidates[i];
+        for (key, value) in &candidate.writes {
+            match value {
+                Some(value) => state.data.insert(key.clone(), value.clone()),
+                None => state.data.remove(key),
+            };
+        }
+        state.committed[i] = true;
+        let written = &candidate.writes;
+        for (j, other) in self.candidates.iter().enumerate() {
+            let concurrent = state.read[j] && !state.committed[j];
+            if concurrent && other.writes.keys().any(|key| written.contains_key(key)) {
+                state.doomed[j] = true;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures::future::join_all;
+
+    use super::*;
+    use crate::simulation::Simulation;
+    use crate::transaction::CheckLevel;
+    use crate::transaction::HeartbeatOption;
+    use crate::TransactionOptions;
+
+    fn record(history: &History, operations: Vec<Operation>, outcome: Outcome) {
+        let mut recorder = history.begin();
+        for operation in operations {
+            recorder.push(operation);
+        }
+        recorder.finish(outcome);
+    }
+
+    fn read(key: &str, value: Option<&str>) -> Operation {
+        Operation::Read {
+            key: key.to_owned().into(),
+            value: value.map(|value| value.as_bytes().to_vec()),
+        }
+    }
+
+    fn write(key: &str, value: &str) -> Operation {
+        Operation::Write {
+            key: key.to_owned().into(),
+            value: Some(value.as_bytes().to_vec()),
+        }
+    }
+
+    #[test]
+    fn test_sequential_history() {
+        let history = History::new();
+        record(&history, vec![read("x", None), write("x", "1")], Outcome::Committed);
+        record(&history, vec![write("x", "2")], Outcome::Aborted);
+        record(&history, vec![read("x", Some("1"))], Outcome::Committed);
+        assert_eq!(
+            history.check(Consistency::Serializable),
+            CheckResult::Consistent
+        );
+
+        // A transaction which started after another committed must see its writes.
+        record(&history, vec![read("x", None)], Outcome::Committed);
+        assert!(matches!(
+            history.check(Consistency::SnapshotIsolation),
+            CheckResult::Inconsistent(_)
+        ));
+
+        // A transaction must see its own writes.
+        let history = History::new();
+        record(&history, vec![write("x", "1"), read("x", None)], Outcome::Unknown);
+        assert!(matches!(
+            history.check(Consistency::SnapshotIsolation),
+            CheckResult::Inconsistent(_)
+        ));
+    }
+
+    #[test]
+    fn test_concurrent_history() {
+        // Two concurrent increments which both committed lost an update.
+        let history = History::new();
+        let mut first = history.begin();
+        let mut second = history.begin();
+        for recorder in [&mut first, &mut second] {
+            recorder.push(read("x", None));
+            recorder.push(write("x", "1"));
+        }
+        first.finish(Outcome::Committed);
+        second.finish(Outcome::Unknown);
+        assert_eq!(
+            history.check(Consistency::SnapshotIsolation),
+            CheckResult::Consistent
+        );
+        record(&history, vec![read("x", Some("1"))], Outcome::Committed);
+        assert_eq!(
+            history.check(Consistency::SnapshotIsolation),
+            CheckResult::Consistent
+        );
+        let mut recorded = history.inner.lock().unwrap();
+        recorded.transactions[1].outcome = Outcome::Committed;
+        drop(recorded);
+        assert!(matches!(
+            history.check(Consistency::SnapshotIsolation),
+            CheckResult::Inconsistent(_)
+        ));
+
+        // Write skew is allowed by snapshot isolation, but isn't serializable.
+        let history = History::new();
+        let mut first = history.begin();
+        let mut second = history.begin();
+        for recorder in [&mut first, &mut second] {
+            recorder.push(read("x", None));
+            recorder.push(read("y", None));
+        }
+        first.push(write("x", "1"));
+        second.push(write("y", "1"));
+        first.finish(Outcome::Committed);
+        second.finish(Outcome::Committed);
+        assert_eq!(
+            history.check(Consistency::SnapshotIsolation),
+            CheckResult::Consistent
+        );
+        assert!(matches!(
+            history.check(Consistency::Serializable),
+            CheckResult::Inconsistent(_)
+        ));
+    }
+
+    // Concurrent clients increment counters in transactions while requests and responses are
+    // dropped.
+    #[tokio::test]
+    async fn test_simulated_history() {
+        let sim = Simulation::new(7);
+        sim.set_drop_rates(0.05, 0.05);
+        let history = History::new();
+        let options = TransactionOptions::new_optimistic()
+            .drop_check(CheckLevel::None)
+            .heartbeat_option(HeartbeatOption::NoHeartbeat);
+
+        let clients = (0..4u8).map(|client| {
+            let sim = &sim;
+            let history = &history;
+            let options = options.clone();
+            async move {
+                for i in 0..6u8 {
+                    let key = if (client + i) % 3 == 0 { "x" } else { "y" };
+                    let mut recorder = history.begin();
+                    let mut txn = match sim.begin_with_options(options.clone()).await {
+                        Ok(txn) => txn,
+                        Err(_) => {
+                            recorder.finish(Outcome::Aborted);
+                            continue;
+                        }
+                    };
+                    let value = match txn.get(key.to_owned()).await {
+                        Ok(value) => value,
+                        Err(_) => {
+                            let _ = txn.rollback().await;
+                            recorder.finish(Outcome::Aborted);
+                            continue;
+                        }
+                    };
+                    recorder.read(key.to_owned(), value.clone());
+                    let count = value.map_or(0, |value| value[0]);
+                    txn.put(key.to_owned(), vec![count + 1]).await.unwrap();
+                    recorder.write(key.to_owned(), vec![count + 1]);
+                    let result = txn.commit().await;
+                    if result.is_err() {
+                        let _ = txn.rollback().await;
+                    }
+                    recorder.finish(Outcome::of(&result));
+                }
+            }
+        });
+        join_all(clients).await;
+
+        sim.set_drop_rates(0.0, 0.0);
+        sim.advance_clock(Duration::from_secs(60));
+        let mut final_read = history.begin();
+        let mut snapshot = sim.begin_with_options(options).await.unwrap();
+        for key in ["x", "y"] {
+            let value = snapshot.get(key.to_owned()).await.unwrap();
+            final_read.read(key.to_owned(), value);
+        }
+        final_read.finish(Outcome::Committed);
+
+        assert_eq!(history.len(), 25);
+        assert_eq!(
+            history.check(Consistency::SnapshotIsolation),
+            CheckResult::Consistent
+        );
+    }
+}
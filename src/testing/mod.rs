@@ -0,0 +1,95 @@
+// Copyright 2023 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Tools for testing applications which use the client.
+
+#[cfg(feature = "quickcheck")]
+pub mod arbitrary;
+pub mod history;
+#[cfg(feature = "proptest")]
+pub mod strategy;
+
+use crate::pd::PdClient;
+use crate::Key;
+use crate::Result;
+use crate::Transaction;
+use crate::Value;
+
+/// The length of the longest key generated by the `proptest` and `quickcheck` features.
+pub const MAX_KEY_LEN: usize = 8;
+/// The length of the longest value generated by the `proptest` and `quickcheck` features.
+pub const MAX_VALUE_LEN: usize = 64;
+
+/// A write of a transaction, e.g., one of a sequence generated to fuzz a storage layer with the
+/// `proptest` or `quickcheck` features.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Mutation {
+    /// [`Transaction::put`].
+    Put { key: Key, value: Value },
+    /// [`Transaction::insert`].
+    Insert { key: Key, value: Value },
+    /// [`Transaction::delete`].
+    Delete { key: Key },
+    /// [`Transaction::lock_keys`] of the single key.
+    Lock { key: Key },
+}
+
+impl Mutation {
+    /// The key written.
+    pub fn key(&self) -> &Key {
+        match self {
+            Mutation::Put { key, .. }
+            | Mutation::Insert { key, .. }
+            | Mutation::Delete { key }
+            | Mutation::Lock { key } => key,
+        }
+    }
+
+    /// Write the mutation to `txn`.
+    pub async fn apply<PdC: PdClient>(self, txn: &mut Transaction<PdC>) -> Result<()> {
+        match self {
+            Mutation::Put { key, value } => txn.put(key, value).await,
+            Mutation::Insert { key, value } => txn.insert(key, value).await,
+            Mutation::Delete { key } => txn.delete(key).await,
+            Mutation::Lock { key } => txn.lock_keys(vec![key]).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::Simulation;
+
+    #[tokio::test]
+    async fn test_apply_mutations() {
+        let sim = Simulation::new(33);
+        let key = |key: &str| Key::from(key.to_owned());
+        let mut txn = sim.begin_optimistic().await.unwrap();
+        txn.put(key("b"), b"0".to_vec()).await.unwrap();
+        let mutations = vec![
+            Mutation::Put {
+                key: key("a"),
+                value: b"1".to_vec(),
+            },
+            Mutation::Insert {
+                key: key("c"),
+                value: b"3".to_vec(),
+            },
+            Mutation::Delete { key: key("b") },
+            Mutation::Lock { key: key("d") },
+        ];
+        for mutation in mutations {
+            mutation.apply(&mut txn).await.unwrap();
+        }
+        txn.commit().await.unwrap();
+
+        let mut txn = sim.begin_optimistic().await.unwrap();
+        let values = txn
+            .batch_get_values(vec![key("a"), key("b"), key("c"), key("d")])
+            .await
+            .unwrap();
+        let expected = vec![Some(b"1".to_vec()), None, Some(b"3".to_vec()), None];
+        assert_eq!(values, expected);
+        txn.rollback().await.unwrap();
+    }
+}
@@ -0,0 +1,113 @@
+// Copyright 2023 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! [`proptest`] strategies generating the client's types, e.g., to fuzz a storage layer built on
+//! the client against a [`Simulation`](crate::simulation::Simulation).
+//!
+//! Keys are never empty, since TiKV does not accept writes of the empty key, and are short, so
+//! that generated keys often share prefixes and ranges often hold several of them.
+//!
+//! # Examples
+//!
+//! ```rust
+//! # use proptest::prelude::*;
+//! # use tikv_client::testing::strategy;
+//! proptest! {
+//!     fn test_writes(mutations in strategy::mutations(0..32)) {
+//!         for mutation in mutations {
+//!             prop_assert!(!mutation.key().is_empty());
+//!         }
+//!     }
+//! }
+//! # test_writes();
+//! ```
+
+use std::ops::Bound;
+
+use proptest::collection::vec;
+use proptest::collection::SizeRange;
+use proptest::prelude::*;
+
+use super::Mutation;
+use super::MAX_KEY_LEN;
+use super::MAX_VALUE_LEN;
+use crate::BoundRange;
+use crate::Key;
+use crate::KvPair;
+use crate::Value;
+
+/// Keys of 1 to [`MAX_KEY_LEN`](super::MAX_KEY_LEN) bytes.
+pub fn key() -> impl Strategy<Value = Key> {
+    vec(any::<u8>(), 1..=MAX_KEY_LEN).prop_map(Key::from)
+}
+
+/// Values of up to [`MAX_VALUE_LEN`](super::MAX_VALUE_LEN) bytes, including empty ones.
+pub fn value() -> impl Strategy<Value = Value> {
+    vec(any::<u8>(), 0..=MAX_VALUE_LEN)
+}
+
+/// Pairs of a [`key`] and a [`value`].
+pub fn kv_pair() -> impl Strategy<Value = KvPair> {
+    (key(), value()).prop_map(|(key, value)| KvPair::new(key, value))
+}
+
+/// Ranges whose start is not after their end, with bounds of either kind, and an end which may be
+/// unbounded.
+pub fn bound_range() -> impl Strategy<Value = BoundRange> {
+    let bound = |key: Key, included: bool| {
+        if included {
+            Bound::Included(key)
+        } else {
+            Bound::Excluded(key)
+        }
+    };
+    (key(), key(), any::<(bool, bool)>(), any::<bool>()).prop_map(
+        move |(start, end, (start_included, end_included), unbounded)| {
+            let (start, end) = if start <= end {
+                (start, end)
+            } else {
+                (end, start)
+            };
+            let end = if unbounded {
+                Bound::Unbounded
+            } else {
+                bound(end, end_included)
+            };
+            BoundRange::from((bound(start, start_included), end))
+        },
+    )
+}
+
+/// A put, insert, delete, or lock of a [`key`].
+pub fn mutation() -> impl Strategy<Value = Mutation> {
+    prop_oneof![
+        (key(), value()).prop_map(|(key, value)| Mutation::Put { key, value }),
+        (key(), value()).prop_map(|(key, value)| Mutation::Insert { key, value }),
+        key().prop_map(|key| Mutation::Delete { key }),
+        key().prop_map(|key| Mutation::Lock { key }),
+    ]
+}
+
+/// Sequences of [`mutation`]s, with lengths in `len`.
+pub fn mutations(len: impl Into<SizeRange>) -> impl Strategy<Value = Vec<Mutation>> {
+    vec(mutation(), len)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::RangeBounds;
+
+    use super::*;
+
+    proptest! {
+        #[test]
+        fn test_bound_range(range in bound_range()) {
+            let start = match range.start_bound() {
+                Bound::Included(start) | Bound::Excluded(start) => start.clone(),
+                Bound::Unbounded => unreachable!(),
+            };
+            if let Bound::Included(end) | Bound::Excluded(end) = range.end_bound() {
+                prop_assert!(&start <= end);
+            }
+        }
+    }
+}
@@ -5,9 +5,16 @@
 //! The higher bits of the version are the physical part of the timestamp.
 
 use std::convert::TryInto;
+use std::fmt;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
 
+use async_trait::async_trait;
 pub use tikv_client_proto::pdpb::Timestamp;
 
+use crate::Result;
+
 const PHYSICAL_SHIFT_BITS: i64 = 18;
 const LOGICAL_MASK: i64 = (1 << PHYSICAL_SHIFT_BITS) - 1;
 
@@ -49,3 +56,69 @@ impl TimestampExt for Timestamp {
         }
     }
 }
+
+/// A source of timestamps for transactions, in place of PD's timestamp oracle (TSO).
+///
+/// Clients get timestamps from PD unless they are given a provider with
+/// [`Config::with_timestamp_provider`](crate::Config::with_timestamp_provider): a
+/// [`LogicalClock`] in tests, or, in advanced deployments, a client of a local or hybrid TSO
+/// service. The timestamps of every client of a cluster must be unique and increase strictly, or
+/// transactions lose their isolation.
+#[async_trait]
+pub trait TimestampProvider: Send + Sync + 'static {
+    /// Return a timestamp greater than any returned before.
+    async fn get_timestamp(&self) -> Result<Timestamp>;
+}
+
+/// Counts up from a starting version, one version per timestamp.
+///
+/// Only suitable when the provider's clients are the only clients of the cluster, e.g., in tests.
+///
+/// # Examples
+/// ```rust
+/// # use tikv_client::{LogicalClock, TimestampExt, TimestampProvider};
+/// # futures::executor::block_on(async {
+/// let clock = LogicalClock::new(100);
+/// assert_eq!(clock.get_timestamp().await.unwrap().version(), 100);
+/// assert_eq!(clock.get_timestamp().await.unwrap().version(), 101);
+/// # });
+/// ```
+#[derive(Debug, Default)]
+pub struct LogicalClock {
+    next: AtomicU64,
+}
+
+impl LogicalClock {
+    /// A clock whose first timestamp has version `start`.
+    pub fn new(start: u64) -> LogicalClock {
+        LogicalClock {
+            next: AtomicU64::new(start),
+        }
+    }
+}
+
+#[async_trait]
+impl TimestampProvider for LogicalClock {
+    async fn get_timestamp(&self) -> Result<Timestamp> {
+        let version = self.next.fetch_add(1, Ordering::SeqCst);
+        Ok(Timestamp::from_version(version))
+    }
+}
+
+/// A shared `TimestampProvider` which can be stored in a `Config`.
+#[derive(Clone)]
+pub(crate) struct TimestampProviderHandle(pub Arc<dyn TimestampProvider>);
+
+impl fmt::Debug for TimestampProviderHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("TimestampProvider")
+    }
+}
+
+impl PartialEq for TimestampProviderHandle {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::as_ptr(&self.0) as *const () == Arc::as_ptr(&other.0) as *const ()
+    }
+}
+
+impl Eq for TimestampProviderHandle {}
@@ -0,0 +1,254 @@
+// Copyright 2023 TiKV Project Authors. Licensed under Apache-2.0.
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use futures::future;
+use serde_derive::Deserialize;
+use serde_derive::Serialize;
+
+use crate::pd::PdClient;
+use crate::spawner;
+use crate::Result;
+use crate::Timestamp;
+use crate::TimestampExt;
+
+/// Prefetching of the start timestamps of transactions, so that a burst of transactions doesn't
+/// wait on PD's timestamp oracle.
+///
+/// A [`TransactionClient`](crate::TransactionClient) keeps a pool of up to `pool_size`
+/// timestamps, which `begin_optimistic`, `begin_pessimistic` and `begin_with_options` take from.
+/// Once the pool is half empty, it is refilled in the background; a transaction begun while the
+/// pool is empty gets its timestamp from PD. Timestamps are dropped from the pool once they are
+/// older than `max_age`.
+///
+/// A pooled timestamp was issued before the transaction began, so the transaction may not see
+/// writes other clients committed in the meantime, by at most `max_age`. Only use prefetching
+/// when that is acceptable. Writes committed by the client's own transactions are always seen:
+/// each commit discards the pooled timestamps at or below its commit timestamp. Commit timestamps, and those of
+/// [`current_timestamp`](crate::TransactionClient::current_timestamp), always come from PD.
+///
+/// # Examples
+/// ```rust
+/// # use tikv_client::{Config, TimestampPrefetch};
+/// # use std::time::Duration;
+/// let config = Config::default().with_timestamp_prefetch(
+///     TimestampPrefetch::default()
+///         .pool_size(256)
+///         .max_age(Duration::from_millis(100)),
+/// );
+/// ```
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+#[serde(rename_all = "kebab-case")]
+pub struct TimestampPrefetch {
+    pub pool_size: usize,
+    pub max_age: Duration,
+}
+
+impl Default for TimestampPrefetch {
+    fn default() -> Self {
+        TimestampPrefetch {
+            pool_size: 64,
+            max_age: Duration::from_millis(500),
+        }
+    }
+}
+
+impl TimestampPrefetch {
+    /// Set how many timestamps are prefetched. A size of zero is taken as one.
+    #[must_use]
+    pub fn pool_size(mut self, pool_size: usize) -> Self {
+        self.pool_size = pool_size.max(1);
+        self
+    }
+
+    /// Set how long a prefetched timestamp may be used for.
+    #[must_use]
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = max_age;
+        self
+    }
+}
+
+/// A pool of timestamps fetched ahead of time from PD.
+pub(crate) struct TimestampPool {
+    config: TimestampPrefetch,
+    state: Mutex<PoolState>,
+}
+
+#[derive(Default)]
+struct PoolState {
+    /// The prefetched timestamps, oldest first, and when they were fetched.
+    timestamps: VecDeque<(Timestamp, Instant)>,
+    refilling: bool,
+    /// The version of the latest commit of the client's transactions. Timestamps at or below it
+    /// would not see the commit's writes, so are discarded.
+    committed: u64,
+    /// Incremented whenever the pool is discarded, so that refills begun before are discarded
+    /// too.
+    generation: u64,
+}
+
+impl TimestampPool {
+    pub(crate) fn new(config: TimestampPrefetch) -> TimestampPool {
+        TimestampPool {
+            config,
+            state: Mutex::new(PoolState::default()),
+        }
+    }
+
+    /// Take a timestamp from the pool, or from PD if the pool is empty, and start refilling the
+    /// pool if it is running low.
+    pub(crate) async fn get_timestamp<PdC: PdClient>(
+        self: &Arc<Self>,
+        pd: &Arc<PdC>,
+    ) -> Result<Timestamp> {
+        let pooled = {
+            let mut state = self.state.lock().unwrap();
+            let now = pd.clock().now();
+            let committed = state.committed;
+            while state.timestamps.front().is_some_and(|(timestamp, fetched)| {
+                now.duration_since(*fetched) > self.config.max_age
+                    || timestamp.version() <= committed
+            }) {
+                state.timestamps.pop_front();
+            }
+            let pooled = state.timestamps.pop_front().map(|(timestamp, _)| timestamp);
+            let pool_size = self.config.pool_size.max(1);
+            if !state.refilling && state.timestamps.len() <= pool_size / 2 {
+                state.refilling = true;
+                let count = pool_size - state.timestamps.len();
+                let generation = state.generation;
+                spawner::spawn(
+                    pd.as_ref(),
+                    self.clone().refill(pd.clone(), count, generation),
+                );
+            }
+            pooled
+        };
+        match pooled {
+            Some(timestamp) => Ok(timestamp),
+            None => pd.clone().get_timestamp().await,
+        }
+    }
+
+    /// Record that a transaction of the client may have committed at `commit_ts`, so that
+    /// transactions begun afterwards see its writes. If the commit timestamp is not known, the
+    /// whole pool is discarded.
+    pub(crate) fn observe_commit(&self, commit_ts: Option<&Timestamp>) {
+        let mut state = self.state.lock().unwrap();
+        match commit_ts {
+            Some(commit_ts) => {
+                state.committed = state.committed.max(commit_ts.version());
+                let committed = state.committed;
+                while state
+                    .timestamps
+                    .front()
+                    .is_some_and(|(timestamp, _)| timestamp.version() <= committed)
+                {
+                    state.timestamps.pop_front();
+                }
+            }
+            None => {
+                state.timestamps.clear();
+                state.generation += 1;
+            }
+        }
+    }
+
+    /// Fetch `count` timestamps, and add them to the pool unless it was discarded since
+    /// `generation`. PD's timestamp oracle coalesces the concurrent requests into a few round
+    /// trips.
+    async fn refill<PdC: PdClient>(self: Arc<Self>, pd: Arc<PdC>, count: usize, generation: u64) {
+        let fetched = future::try_join_all((0..count).map(|_| pd.clone().get_timestamp())).await;
+        let mut state = self.state.lock().unwrap();
+        state.refilling = false;
+        if state.generation != generation {
+            return;
+        }
+        if let Ok(mut timestamps) = fetched {
+            timestamps.sort_by_key(|timestamp| (timestamp.physical, timestamp.logical));
+            let now = pd.clock().now();
+            state
+                .timestamps
+                .extend(timestamps.into_iter().map(|timestamp| (timestamp, now)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::Simulation;
+    use crate::MockClock;
+
+    async fn refilled(pool: &TimestampPool) {
+        while pool.state.lock().unwrap().refilling {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_timestamp_pool() {
+        let sim = Simulation::new(38);
+        let clock = Arc::new(MockClock::new());
+        sim.set_clock(clock.clone());
+        let pd = sim.pd_client();
+        let config = TimestampPrefetch::default().pool_size(4);
+        let pool = Arc::new(TimestampPool::new(config));
+
+        // The pool starts empty, so the first timestamp comes from PD.
+        pool.get_timestamp(&pd).await.unwrap();
+        refilled(&pool).await;
+        let latest = pd.clone().get_timestamp().await.unwrap().version();
+        let mut pooled = Vec::new();
+        for _ in 0..3 {
+            pooled.push(pool.get_timestamp(&pd).await.unwrap().version());
+        }
+        assert!(pooled.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(pooled.iter().all(|version| *version < latest));
+
+        // Expired timestamps are not used.
+        let config = TimestampPrefetch::default()
+            .pool_size(4)
+            .max_age(Duration::from_millis(1));
+        let pool = Arc::new(TimestampPool::new(config));
+        pool.get_timestamp(&pd).await.unwrap();
+        refilled(&pool).await;
+        let latest = pd.clone().get_timestamp().await.unwrap().version();
+        clock.advance(Duration::from_millis(5));
+        assert!(pool.get_timestamp(&pd).await.unwrap().version() > latest);
+    }
+
+    #[tokio::test]
+    async fn test_timestamp_pool_after_commit() {
+        let sim = Simulation::new(39);
+        let pd = sim.pd_client();
+        let config = TimestampPrefetch::default().pool_size(8);
+        let pool = Arc::new(TimestampPool::new(config));
+        pool.get_timestamp(&pd).await.unwrap();
+        refilled(&pool).await;
+
+        // A transaction begun after a commit sees its writes, although the pool holds timestamps
+        // from before the commit.
+        let mut txn = sim
+            .begin_optimistic()
+            .await
+            .unwrap()
+            .with_timestamp_pool(Some(pool.clone()));
+        txn.put(b"k".to_vec(), b"v".to_vec()).await.unwrap();
+        let commit_ts = txn.commit().await.unwrap().unwrap();
+        let start_ts = pool.get_timestamp(&pd).await.unwrap();
+        assert!(start_ts.version() > commit_ts.version());
+
+        // If the commit timestamp is not known, nothing pooled is used.
+        refilled(&pool).await;
+        let latest = pd.clone().get_timestamp().await.unwrap().version();
+        pool.observe_commit(None);
+        assert!(pool.get_timestamp(&pd).await.unwrap().version() > latest);
+    }
+}
@@ -2,22 +2,66 @@
 
 use std::collections::btree_map::Entry;
 use std::collections::BTreeMap;
-use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fmt;
 use std::future::Future;
+use std::sync::Arc;
 
 use tikv_client_proto::kvrpcpb;
 
+use crate::transaction::BufferedMutation;
+use crate::transaction::MergedScanner;
+use crate::transaction::MutationKind;
 use crate::BoundRange;
+use crate::Error;
 use crate::Key;
 use crate::KvPair;
 use crate::Result;
 use crate::Value;
 
+/// Observes the number of bytes a transaction's buffer holds: the keys and values it will write,
+/// and the values it has read.
+///
+/// Useful for enforcing a memory budget shared by many concurrent transactions, e.g., by adding
+/// each change to a global gauge. Register an observer using
+/// [`TransactionOptions::buffer_observer`](crate::TransactionOptions::buffer_observer). The
+/// observer is called with the previous and current size of the buffer whenever it changes,
+/// including when the transaction is dropped and its buffer freed, on the task using the
+/// transaction, so it should not block.
+pub trait BufferObserver: Send + Sync {
+    fn on_resize(&self, previous: usize, current: usize);
+}
+
+impl<F: Fn(usize, usize) + Send + Sync> BufferObserver for F {
+    fn on_resize(&self, previous: usize, current: usize) {
+        self(previous, current)
+    }
+}
+
+/// A shared `BufferObserver` which can be stored in `TransactionOptions`.
+#[derive(Clone)]
+pub(crate) struct BufferObserverHandle(pub Arc<dyn BufferObserver>);
+
+impl fmt::Debug for BufferObserverHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("BufferObserver")
+    }
+}
+
+impl PartialEq for BufferObserverHandle {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::as_ptr(&self.0) as *const () == Arc::as_ptr(&other.0) as *const ()
+    }
+}
+
 /// A caching layer which buffers reads and writes in a transaction.
 pub struct Buffer {
     primary_key: Option<Key>,
     entry_map: BTreeMap<Key, BufferEntry>,
     is_pessimistic: bool,
+    /// The number of key and value bytes held by `entry_map`.
+    size: usize,
+    observer: Option<Arc<dyn BufferObserver>>,
 }
 
 impl Buffer {
@@ -26,9 +70,24 @@ impl Buffer {
             primary_key: None,
             entry_map: BTreeMap::new(),
             is_pessimistic,
+            size: 0,
+            observer: None,
         }
     }
 
+    /// Report each change of the buffer's [`size`](Buffer::size) to `observer`, starting with its
+    /// current size, if it is not empty.
+    pub fn with_observer(mut self, observer: Option<Arc<dyn BufferObserver>>) -> Buffer {
+        self.observer = observer;
+        self.report(0);
+        self
+    }
+
+    /// The number of key and value bytes held by the buffer, including values it has only read.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
     /// Get the primary key of the buffer.
     pub fn get_primary_key(&self) -> Option<Key> {
         self.primary_key.clone()
@@ -39,6 +98,11 @@ impl Buffer {
         self.primary_key.get_or_insert_with(|| key.clone());
     }
 
+    /// Replace the primary key. `key` must be one of the buffer's mutations.
+    pub fn set_primary_key(&mut self, key: Key) {
+        self.primary_key = Some(key);
+    }
+
     /// Get a value from the buffer.
     /// If the returned value is None, it means the key doesn't exist in buffer yet.
     pub fn get(&self, key: &Key) -> Option<Value> {
@@ -66,7 +130,7 @@ impl Buffer {
     }
 
     /// Get multiple values from the buffer. If any are not present, run `f` to
-    /// get the missing values.
+    /// get the missing values. Duplicate keys are only looked up, and returned, once.
     ///
     /// only used for snapshot read (i.e. not for `batch_get_for_update`)
     pub async fn batch_get_or_else<F, Fut>(
@@ -78,36 +142,40 @@ impl Buffer {
         F: FnOnce(Box<dyn Iterator<Item = Key> + Send>) -> Fut,
         Fut: Future<Output = Result<Vec<KvPair>>>,
     {
-        let (cached_results, undetermined_keys) = {
-            // Partition the keys into those we have buffered and those we have to
-            // get from the store.
-            let (undetermined_keys, cached_results): (Vec<_>, Vec<_>) = keys
-                .map(|key| {
-                    let value = self
-                        .entry_map
-                        .get(&key)
-                        .map(BufferEntry::get_value)
-                        .unwrap_or(MutationValue::Undetermined);
-                    (key, value)
-                })
-                .partition(|(_, v)| *v == MutationValue::Undetermined);
-
-            let cached_results = cached_results
-                .into_iter()
-                .filter_map(|(k, v)| v.unwrap().map(|v| KvPair(k, v)));
-
-            let undetermined_keys = undetermined_keys.into_iter().map(|(k, _)| k);
-            (cached_results, undetermined_keys)
-        };
+        // Partition the keys into those we have buffered and those we have to get from the store.
+        let mut cached_results = Vec::new();
+        let mut undetermined_keys = Vec::new();
+        let mut seen = HashSet::new();
+        for key in keys {
+            if !seen.insert(key.clone()) {
+                continue;
+            }
+            match self.entry_map.get(&key).map(BufferEntry::get_value) {
+                Some(MutationValue::Determined(Some(value))) => {
+                    cached_results.push(KvPair(key, value))
+                }
+                Some(MutationValue::Determined(None)) => {}
+                Some(MutationValue::Undetermined) | None => undetermined_keys.push(key),
+            }
+        }
 
-        let fetched_results = f(Box::new(undetermined_keys)).await?;
+        let requested_keys: HashSet<Key> = undetermined_keys.iter().cloned().collect();
+        let fetched_results = f(Box::new(undetermined_keys.into_iter())).await?;
+        if let Some(kvpair) = fetched_results
+            .iter()
+            .find(|kvpair| !requested_keys.contains(&kvpair.0))
+        {
+            return Err(Error::InconsistentResults {
+                message: format!("batch_get returned unrequested key {:?}", kvpair.0),
+            });
+        }
         for kvpair in &fetched_results {
             let key = kvpair.0.clone();
             let value = Some(kvpair.1.clone());
             self.update_cache(key, value);
         }
 
-        let results = cached_results.chain(fetched_results);
+        let results = cached_results.into_iter().chain(fetched_results);
         Ok(results)
     }
 
@@ -124,74 +192,66 @@ impl Buffer {
         F: FnOnce(BoundRange, u32) -> Fut,
         Fut: Future<Output = Result<Vec<KvPair>>>,
     {
-        // read from local buffer
-        let mutation_range = self.entry_map.range(range.clone());
-
-        // fetch from TiKV
-        // fetch more entries because some of them may be deleted.
-        let redundant_limit = limit
-            + mutation_range
-                .clone()
-                .filter(|(_, m)| matches!(m, BufferEntry::Del))
-                .count() as u32;
-
-        let mut results = f(range, redundant_limit)
-            .await?
-            .into_iter()
-            .map(|pair| pair.into())
-            .collect::<HashMap<Key, Value>>();
-
-        // override using local data
-        for (k, m) in mutation_range {
-            match m {
-                BufferEntry::Put(v) => {
-                    results.insert(k.clone(), v.clone());
-                }
-                BufferEntry::Del => {
-                    results.remove(k);
-                }
-                _ => {}
-            }
-        }
+        let scanner = self.scanner(&range);
+        let fetched = f(range.clone(), scanner.fetch_limit(&range, limit)).await?;
 
         // update local buffer
         if update_cache {
-            for (k, v) in &results {
-                self.update_cache(k.clone(), Some(v.clone()));
+            for pair in &fetched {
+                if !scanner.is_written(pair.key()) {
+                    self.update_cache(pair.key().clone(), Some(pair.value().clone()));
+                }
             }
         }
 
-        let mut res = results
-            .into_iter()
-            .map(|(k, v)| KvPair::new(k, v))
-            .collect::<Vec<_>>();
-
-        // TODO: use `BTreeMap` instead of `HashMap` to avoid sorting.
-        if reverse {
-            res.sort_unstable_by(|a, b| b.key().cmp(a.key()));
-        } else {
-            res.sort_unstable_by(|a, b| a.key().cmp(b.key()));
-        }
+        Ok(scanner.merge(&range, fetched, limit, reverse).into_iter())
+    }
 
-        Ok(res.into_iter().take(limit as usize))
+    /// How many entries `scan_and_fetch` fetches from TiKV to return `limit` entries of `range`:
+    /// more than `limit`, because some of them may be deleted.
+    pub fn scan_fetch_limit(&self, range: &BoundRange, limit: u32) -> u32 {
+        self.scanner(range).fetch_limit(range, limit)
+    }
+
+    /// The values written to keys in `range`, to merge with the entries fetched from TiKV.
+    fn scanner(&self, range: &BoundRange) -> MergedScanner {
+        let mut scanner = MergedScanner::default();
+        for (key, entry) in self.entry_map.range(range.clone()) {
+            match entry {
+                BufferEntry::Put(value) | BufferEntry::Insert(value) => {
+                    scanner.put(key.clone(), value.clone())
+                }
+                BufferEntry::Del => scanner.delete(key.clone()),
+                _ => {}
+            }
+        }
+        scanner
     }
 
     /// Lock the given key if necessary.
     pub fn lock(&mut self, key: Key) {
+        let before = self.entry_size(&key);
         self.primary_key.get_or_insert_with(|| key.clone());
         let value = self
             .entry_map
-            .entry(key)
+            .entry(key.clone())
             // Mutated keys don't need a lock.
             .or_insert(BufferEntry::Locked(None));
         // But values which we have only read, but not written, do.
         if let BufferEntry::Cached(v) = value {
             *value = BufferEntry::Locked(Some(v.take()))
         }
+        self.resized(&key, before);
+    }
+
+    /// Whether `key` is locked without being written.
+    pub fn is_locked(&self, key: &Key) -> bool {
+        matches!(self.entry_map.get(key), Some(BufferEntry::Locked(_)))
     }
 
     /// Unlock the given key if locked.
     pub fn unlock(&mut self, key: &Key) {
+        let before = self.entry_size(key);
         if let Some(value) = self.entry_map.get_mut(key) {
             if let BufferEntry::Locked(v) = value {
                 if let Some(v) = v {
@@ -201,10 +261,39 @@ impl Buffer {
                 }
             }
         }
+        self.resized(key, before);
+    }
+
+    /// Discard the mutation of `key`, so that reads of it are no longer served by the buffer.
+    /// Returns whether there was a mutation.
+    ///
+    /// In a pessimistic transaction, the key stays locked, because the lock is held in TiKV.
+    pub fn unset(&mut self, key: &Key) -> bool {
+        if matches!(self.entry_map.get(key), None | Some(BufferEntry::Cached(_))) {
+            return false;
+        }
+        let before = self.entry_size(key);
+        if self.is_pessimistic {
+            let entry = BufferEntry::Locked(None);
+            self.entry_map.insert(key.clone(), entry);
+            self.resized(key, before);
+            return true;
+        }
+        self.entry_map.remove(key);
+        self.resized(key, before);
+        if self.primary_key.as_ref() == Some(key) {
+            let primary_key = self
+                .mutations()
+                .find(|(_, kind)| *kind != MutationKind::CheckNotExists)
+                .map(|(key, _)| key.clone());
+            self.primary_key = primary_key;
+        }
+        true
     }
 
     /// Put a value into the buffer (does not write through).
     pub fn put(&mut self, key: Key, value: Value) {
+        let before = self.entry_size(&key);
         let mut entry = self.entry_map.entry(key.clone());
         match entry {
             Entry::Occupied(ref mut o)
@@ -213,23 +302,27 @@ impl Buffer {
             {
                 o.insert(BufferEntry::Insert(value));
             }
-            _ => self.insert_entry(key, BufferEntry::Put(value)),
+            _ => self.insert_entry(key.clone(), BufferEntry::Put(value)),
         }
+        self.resized(&key, before);
     }
 
     /// Mark a value as Insert mutation into the buffer (does not write through).
     pub fn insert(&mut self, key: Key, value: Value) {
+        let before = self.entry_size(&key);
         let mut entry = self.entry_map.entry(key.clone());
         match entry {
             Entry::Occupied(ref mut o) if matches!(o.get(), BufferEntry::Del) => {
                 o.insert(BufferEntry::Put(value));
             }
-            _ => self.insert_entry(key, BufferEntry::Insert(value)),
+            _ => self.insert_entry(key.clone(), BufferEntry::Insert(value)),
         }
+        self.resized(&key, before);
     }
 
     /// Mark a value as deleted.
     pub fn delete(&mut self, key: Key) {
+        let before = self.entry_size(&key);
         let is_pessimistic = self.is_pessimistic;
         let mut entry = self.entry_map.entry(key.clone());
 
@@ -241,8 +334,9 @@ impl Buffer {
             {
                 o.insert(BufferEntry::CheckNotExist);
             }
-            _ => self.insert_entry(key, BufferEntry::Del),
+            _ => self.insert_entry(key.clone(), BufferEntry::Del),
         }
+        self.resized(&key, before);
     }
 
     /// Converts the buffered mutations to the proto buffer version
@@ -253,6 +347,79 @@ impl Buffer {
             .collect()
     }
 
+    /// Converts the buffered mutations to a serializable form, for
+    /// [`TransactionState`](crate::transaction::TransactionState).
+    pub fn export_mutations(&self) -> Vec<BufferedMutation> {
+        self.entry_map
+            .iter()
+            .filter_map(|(key, entry)| {
+                let key = Vec::from(key.clone());
+                Some(match entry {
+                    BufferEntry::Cached(_) => return None,
+                    BufferEntry::Put(value) => BufferedMutation::Put {
+                        key,
+                        value: value.clone(),
+                    },
+                    BufferEntry::Insert(value) => BufferedMutation::Insert {
+                        key,
+                        value: value.clone(),
+                    },
+                    BufferEntry::Del => BufferedMutation::Delete { key },
+                    BufferEntry::Locked(_) => BufferedMutation::Lock { key },
+                    BufferEntry::CheckNotExist => BufferedMutation::CheckNotExists { key },
+                })
+            })
+            .collect()
+    }
+
+    /// Create a buffer holding mutations exported by [`export_mutations`](Buffer::export_mutations).
+    pub fn import_mutations(
+        is_pessimistic: bool,
+        primary_key: Option<Key>,
+        mutations: Vec<BufferedMutation>,
+    ) -> Buffer {
+        let entry_map: BTreeMap<Key, BufferEntry> = mutations
+            .into_iter()
+            .map(|mutation| match mutation {
+                BufferedMutation::Put { key, value } => (key.into(), BufferEntry::Put(value)),
+                BufferedMutation::Insert { key, value } => {
+                    (key.into(), BufferEntry::Insert(value))
+                }
+                BufferedMutation::Delete { key } => (key.into(), BufferEntry::Del),
+                BufferedMutation::Lock { key } => (key.into(), BufferEntry::Locked(None)),
+                BufferedMutation::CheckNotExists { key } => {
+                    (key.into(), BufferEntry::CheckNotExist)
+                }
+            })
+            .collect();
+        let size = entry_map
+            .iter()
+            .map(|(key, entry)| key.len() + entry.value_len())
+            .sum();
+        Buffer {
+            primary_key,
+            entry_map,
+            is_pessimistic,
+            size,
+            observer: None,
+        }
+    }
+
+    /// The buffered mutations, ordered by key. Cached reads are not mutations.
+    pub fn mutations(&self) -> impl Iterator<Item = (&Key, MutationKind)> {
+        self.entry_map.iter().filter_map(|(key, entry)| {
+            let kind = match entry {
+                BufferEntry::Cached(_) => return None,
+                BufferEntry::Put(_) => MutationKind::Put,
+                BufferEntry::Insert(_) => MutationKind::Insert,
+                BufferEntry::Del => MutationKind::Delete,
+                BufferEntry::Locked(_) => MutationKind::Lock,
+                BufferEntry::CheckNotExist => MutationKind::CheckNotExists,
+            };
+            Some((key, kind))
+        })
+    }
+
     pub fn get_write_size(&self) -> usize {
         self.entry_map
             .iter()
@@ -272,12 +439,17 @@ impl Buffer {
     }
 
     fn update_cache(&mut self, key: Key, value: Option<Value>) {
+        let before = self.entry_size(&key);
         match self.entry_map.get(&key) {
             Some(BufferEntry::Locked(None)) => {
-                self.entry_map.insert(key, BufferEntry::Locked(Some(value)));
+                self.entry_map
+                    .insert(key.clone(), BufferEntry::Locked(Some(value)));
+                self.resized(&key, before);
             }
             None => {
-                self.entry_map.insert(key, BufferEntry::Cached(value));
+                self.entry_map
+                    .insert(key.clone(), BufferEntry::Cached(value));
+                self.resized(&key, before);
             }
             Some(BufferEntry::Cached(v)) | Some(BufferEntry::Locked(Some(v))) => {
                 assert!(&value == v);
@@ -300,6 +472,37 @@ impl Buffer {
         }
         self.entry_map.insert(key, entry);
     }
+
+    /// The number of key and value bytes held by the entry of `key`.
+    fn entry_size(&self, key: &Key) -> usize {
+        match self.entry_map.get(key) {
+            Some(entry) => key.len() + entry.value_len(),
+            None => 0,
+        }
+    }
+
+    /// Account for a change of the entry of `key`, which held `before` bytes.
+    fn resized(&mut self, key: &Key, before: usize) {
+        let previous = self.size;
+        self.size = self.size - before + self.entry_size(key);
+        self.report(previous);
+    }
+
+    fn report(&self, previous: usize) {
+        if let Some(observer) = &self.observer {
+            if previous != self.size {
+                observer.on_resize(previous, self.size);
+            }
+        }
+    }
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        let previous = self.size;
+        self.size = 0;
+        self.report(previous);
+    }
 }
 
 // The state of a key-value pair in the buffer.
@@ -364,6 +567,16 @@ impl BufferEntry {
         Some(pb)
     }
 
+    fn value_len(&self) -> usize {
+        match self {
+            BufferEntry::Cached(Some(value))
+            | BufferEntry::Locked(Some(Some(value)))
+            | BufferEntry::Put(value)
+            | BufferEntry::Insert(value) => value.len(),
+            _ => 0,
+        }
+    }
+
     fn get_value(&self) -> MutationValue {
         match self {
             BufferEntry::Cached(value) => MutationValue::Determined(value.clone()),
@@ -386,15 +599,6 @@ enum MutationValue {
     Undetermined,
 }
 
-impl MutationValue {
-    fn unwrap(self) -> Option<Value> {
-        match self {
-            MutationValue::Determined(v) => v,
-            MutationValue::Undetermined => unreachable!(),
-        }
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use futures::executor::block_on;
@@ -403,6 +607,39 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn size() {
+        let key = |k: u8| Key::from(vec![k]);
+        let sizes = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let observed = sizes.clone();
+        let observer = move |previous: usize, current: usize| {
+            observed.lock().unwrap().push((previous, current));
+        };
+        let observer: Arc<dyn BufferObserver> = Arc::new(observer);
+
+        let mut buffer = Buffer::new(true).with_observer(Some(observer.clone()));
+        buffer.put(key(1), vec![1; 10]);
+        buffer.put(key(1), vec![1; 4]);
+        buffer.update_cache(key(2), Some(vec![2; 3]));
+        // Locking a read value keeps it.
+        buffer.lock(key(2));
+        buffer.delete(key(3));
+        // The key stays locked.
+        assert!(buffer.unset(&key(1)));
+        assert_eq!(buffer.size(), 6);
+        let mutations = buffer.export_mutations();
+        drop(buffer);
+        let expected = vec![(0, 11), (11, 5), (5, 9), (9, 10), (10, 6), (6, 0)];
+        assert_eq!(*sizes.lock().unwrap(), expected);
+
+        sizes.lock().unwrap().clear();
+        let buffer = Buffer::import_mutations(true, None, mutations).with_observer(Some(observer));
+        // Only the keys are exported, not the values read.
+        assert_eq!(buffer.size(), 3);
+        drop(buffer);
+        assert_eq!(*sizes.lock().unwrap(), vec![(0, 3), (3, 0)]);
+    }
+
     #[test]
     fn set_and_get_from_buffer() {
         let mut buffer = Buffer::new(false);
@@ -502,7 +739,59 @@ mod tests {
         ]);
     }
 
+    #[test]
+    fn duplicate_keys_are_fetched_once() {
+        let key = |k: u8| Key::from(vec![k]);
+        let mut buffer = Buffer::new(false);
+        buffer.put(key(1), vec![1]);
+        let keys = vec![key(2), key(1), key(3), key(2), key(1)];
+        let result = block_on(buffer.batch_get_or_else(keys.into_iter(), |requested| {
+            assert_eq!(requested.collect::<Vec<_>>(), vec![key(2), key(3)]);
+            ready(Ok(vec![(key(2), vec![2]).into()]))
+        }));
+        assert_eq!(result.unwrap().collect::<Vec<_>>(), vec![
+            KvPair(key(1), vec![1]),
+            KvPair(key(2), vec![2])
+        ]);
+    }
+
+    #[test]
+    fn unrequested_keys_are_rejected() {
+        let mut buffer = Buffer::new(false);
+        buffer.put(b"key1".to_vec().into(), b"value1".to_vec());
+        let result = block_on(buffer.batch_get_or_else(
+            vec![b"key1".to_vec().into(), b"key2".to_vec().into()].into_iter(),
+            move |_| ready(Ok(vec![(b"key1".to_vec(), b"stale".to_vec()).into()])),
+        ));
+        assert!(matches!(result, Err(Error::InconsistentResults { .. })));
+    }
+
     // Check that multiple writes to the same key combine in the correct way.
+    #[test]
+    fn unset() {
+        let key = |k: u8| Key::from(vec![k]);
+        let mut buffer = Buffer::new(false);
+        buffer.put(key(1), vec![1]);
+        buffer.delete(key(2));
+        buffer.update_cache(key(3), Some(vec![3]));
+        assert!(buffer.unset(&key(1)));
+        assert!(!buffer.unset(&key(1)));
+        assert!(!buffer.unset(&key(3)));
+        // The primary key moves to a key which is still mutated.
+        assert_eq!(buffer.get_primary_key(), Some(key(2)));
+        let value = buffer.get_from_mutations(&key(1));
+        assert_eq!(value, MutationValue::Undetermined);
+        assert!(buffer.unset(&key(2)));
+        assert_eq!(buffer.get_primary_key(), None);
+
+        let mut buffer = Buffer::new(true);
+        buffer.lock(key(1));
+        buffer.put(key(1), vec![1]);
+        assert!(buffer.unset(&key(1)));
+        let mutations: Vec<_> = buffer.mutations().collect();
+        assert_eq!(mutations, vec![(&key(1), MutationKind::Lock)]);
+    }
+
     #[test]
     fn state_machine() {
         let mut buffer = Buffer::new(false);
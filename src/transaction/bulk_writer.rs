@@ -0,0 +1,304 @@
+// Copyright 2023 TiKV Project Authors. Licensed under Apache-2.0.
+
+use std::pin::Pin;
+use std::sync::Arc;
+
+use futures::prelude::*;
+use slog::Logger;
+
+use crate::pd::PdClient;
+use crate::pd::PdRpcClient;
+use crate::transaction::CheckLevel;
+use crate::transaction::Transaction;
+use crate::transaction::TransactionOptions;
+use crate::value_format::ValueFormat;
+use crate::KvPair;
+use crate::Result;
+use crate::Timestamp;
+
+const DEFAULT_MAX_CHUNK_PAIRS: usize = 4096;
+const DEFAULT_MAX_CHUNK_BYTES: usize = 16 * 1024 * 1024;
+const DEFAULT_CONCURRENCY: usize = 4;
+
+/// Writes a stream of key-value pairs in many small transactions.
+///
+/// Pairs are grouped into chunks in the order they arrive, and each chunk is written and
+/// committed by its own optimistic transaction. Several chunks are committed concurrently. Each
+/// chunk is atomic, but the write as a whole is not: if a chunk fails, the chunks before and after
+/// it may still be committed. This makes `BulkWriter` suitable for loading data which doesn't
+/// need to become visible all at once, without the size limits and lock contention of a single
+/// huge transaction.
+///
+/// Create one with [`TransactionClient::bulk_writer`](crate::TransactionClient::bulk_writer).
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// # use tikv_client::{Config, KvPair, TransactionClient};
+/// # use futures::prelude::*;
+/// # futures::executor::block_on(async {
+/// let client = TransactionClient::new(vec!["192.168.0.100"], None).await.unwrap();
+/// let pairs = (0..100_000u32).map(|i| KvPair::new(i.to_be_bytes().to_vec(), b"v".to_vec()));
+/// let writer = client.bulk_writer().max_chunk_pairs(1000).concurrency(8);
+/// let mut results = Box::pin(writer.write(stream::iter(pairs)));
+/// while let Some(chunk) = results.next().await {
+///     if let Err(e) = &chunk.result {
+///         println!("chunk {} of {} pairs failed: {}", chunk.index, chunk.len, e);
+///     }
+/// }
+/// # });
+/// ```
+pub struct BulkWriter<PdC: PdClient = PdRpcClient> {
+    rpc: Arc<PdC>,
+    options: TransactionOptions,
+    max_chunk_pairs: usize,
+    max_chunk_bytes: usize,
+    concurrency: usize,
+    value_format: Option<Arc<ValueFormat>>,
+    logger: Logger,
+}
+
+/// The outcome of writing one chunk of a [`BulkWriter`]'s input.
+#[derive(Debug)]
+pub struct BulkWriteChunk {
+    /// The position of the chunk in the input, starting from 0.
+    pub index: usize,
+    /// The number of pairs in the chunk.
+    pub len: usize,
+    /// The total size of the chunk's keys and values.
+    pub bytes: usize,
+    /// The commit timestamp of the chunk's transaction, or the error which failed it.
+    pub result: Result<Timestamp>,
+    /// The chunk's pairs if it failed, so that they can be retried; otherwise empty.
+    pub failed_pairs: Vec<KvPair>,
+}
+
+impl<PdC: PdClient> BulkWriter<PdC> {
+    pub(crate) fn new(rpc: Arc<PdC>, logger: Logger) -> BulkWriter<PdC> {
+        BulkWriter {
+            rpc,
+            options: TransactionOptions::new_optimistic().drop_check(CheckLevel::Warn),
+            max_chunk_pairs: DEFAULT_MAX_CHUNK_PAIRS,
+            max_chunk_bytes: DEFAULT_MAX_CHUNK_BYTES,
+            concurrency: DEFAULT_CONCURRENCY,
+            value_format: None,
+            logger,
+        }
+    }
+
+    /// Encode the values written with `format`.
+    pub(crate) fn with_value_format(mut self, format: Option<Arc<ValueFormat>>) -> Self {
+        self.value_format = format;
+        self
+    }
+
+    /// Set the options of the transactions which write each chunk.
+    ///
+    /// The default is optimistic transactions.
+    #[must_use]
+    pub fn transaction_options(mut self, options: TransactionOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Set the maximum number of pairs in a chunk.
+    ///
+    /// The default is 4096.
+    #[must_use]
+    pub fn max_chunk_pairs(mut self, max_chunk_pairs: usize) -> Self {
+        self.max_chunk_pairs = max_chunk_pairs.max(1);
+        self
+    }
+
+    /// Set the maximum total size of the keys and values in a chunk. A single pair larger than
+    /// this is written in a chunk of its own.
+    ///
+    /// The default is 16 MiB.
+    #[must_use]
+    pub fn max_chunk_bytes(mut self, max_chunk_bytes: usize) -> Self {
+        self.max_chunk_bytes = max_chunk_bytes;
+        self
+    }
+
+    /// Set how many chunks are committed at once.
+    ///
+    /// The default is 4.
+    #[must_use]
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Write `pairs`, returning the outcome of each chunk as it finishes.
+    ///
+    /// Chunks may finish out of order. Nothing is written unless the returned stream is polled.
+    pub fn write<'a>(
+        &'a self,
+        pairs: impl Stream<Item = KvPair> + Send + 'a,
+    ) -> impl Stream<Item = BulkWriteChunk> + 'a {
+        chunks(pairs, self.max_chunk_pairs, self.max_chunk_bytes)
+            .enumerate()
+            .map(move |(index, chunk)| self.write_chunk(index, chunk))
+            .buffer_unordered(self.concurrency)
+    }
+
+    async fn write_chunk(&self, index: usize, pairs: Vec<KvPair>) -> BulkWriteChunk {
+        debug!(self.logger, "writing bulk chunk"; "index" => index, "len" => pairs.len());
+        let len = pairs.len();
+        let bytes = pairs.iter().map(pair_size).sum();
+        let result = self.commit_chunk(&pairs).await;
+        if let Err(e) = &result {
+            warn!(self.logger, "bulk chunk failed"; "index" => index, "error" => %e);
+        }
+        let failed_pairs = if result.is_err() { pairs } else { Vec::new() };
+        BulkWriteChunk {
+            index,
+            len,
+            bytes,
+            result,
+            failed_pairs,
+        }
+    }
+
+    async fn commit_chunk(&self, pairs: &[KvPair]) -> Result<Timestamp> {
+        let timestamp = self.rpc.clone().get_timestamp().await?;
+        let logger = self.logger.new(o!("child" => 1));
+        let mut txn = Transaction::new(timestamp, self.rpc.clone(), self.options.clone(), logger)
+            .with_value_format(self.value_format.clone());
+        let result = async {
+            for pair in pairs {
+                txn.put(pair.key().clone(), pair.value().clone()).await?;
+            }
+            txn.commit().await
+        }
+        .await;
+        match result {
+            Ok(commit_ts) => Ok(commit_ts.unwrap_or_default()),
+            Err(e) => {
+                // The transaction may already have been rolled back, in which case this fails.
+                let _ = txn.rollback().await;
+                Err(e)
+            }
+        }
+    }
+}
+
+fn pair_size(pair: &KvPair) -> usize {
+    pair.key().len() + pair.value().len()
+}
+
+// Groups `pairs` into chunks of at most `max_pairs` pairs and, unless a single pair is larger,
+// `max_bytes` bytes.
+fn chunks<'a>(
+    pairs: impl Stream<Item = KvPair> + Send + 'a,
+    max_pairs: usize,
+    max_bytes: usize,
+) -> impl Stream<Item = Vec<KvPair>> + Send + 'a {
+    type Pairs<'a> = Pin<Box<dyn Stream<Item = KvPair> + Send + 'a>>;
+    let pairs: Pairs<'a> = Box::pin(pairs);
+    stream::unfold(
+        (pairs, None::<KvPair>),
+        move |(mut pairs, carried)| async move {
+            let mut chunk = Vec::new();
+            let mut bytes = 0;
+            let mut next = match carried {
+                Some(pair) => Some(pair),
+                None => pairs.next().await,
+            };
+            while let Some(pair) = next {
+                let size = pair_size(&pair);
+                if !chunk.is_empty() && (chunk.len() >= max_pairs || bytes + size > max_bytes) {
+                    return Some((chunk, (pairs, Some(pair))));
+                }
+                bytes += size;
+                chunk.push(pair);
+                next = pairs.next().await;
+            }
+            if chunk.is_empty() {
+                None
+            } else {
+                Some((chunk, (pairs, None)))
+            }
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::any::Any;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+
+    use slog::Drain;
+    use tikv_client_proto::kvrpcpb;
+
+    use super::*;
+    use crate::mock::MockKvClient;
+    use crate::mock::MockPdClient;
+    use crate::transaction::HeartbeatOption;
+    use crate::Error;
+
+    #[tokio::test]
+    async fn test_chunks() {
+        let pairs = (0..10u8).map(|i| KvPair::new(vec![i], vec![0; i as usize]));
+        let chunks = chunks(stream::iter(pairs), 3, 12)
+            .map(|chunk| {
+                chunk
+                    .iter()
+                    .map(|pair| pair.value().len())
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(
+            chunks,
+            vec![vec![0, 1, 2], vec![3, 4], vec![5], vec![6], vec![7], vec![8], vec![9]]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_bulk_writer() {
+        let prewritten = Arc::new(AtomicUsize::new(0));
+        let prewritten_cloned = prewritten.clone();
+        let pd_client = Arc::new(MockPdClient::new(MockKvClient::with_dispatch_hook(
+            move |req: &dyn Any| {
+                if let Some(req) = req.downcast_ref::<kvrpcpb::PrewriteRequest>() {
+                    if req.mutations.iter().any(|m| m.key == vec![13]) {
+                        return Err(Error::Unimplemented);
+                    }
+                    prewritten_cloned.fetch_add(req.mutations.len(), Ordering::SeqCst);
+                    Ok(Box::<kvrpcpb::PrewriteResponse>::default() as Box<dyn Any>)
+                } else if req.is::<kvrpcpb::CommitRequest>() {
+                    Ok(Box::<kvrpcpb::CommitResponse>::default() as Box<dyn Any>)
+                } else {
+                    Ok(Box::<kvrpcpb::BatchRollbackResponse>::default() as Box<dyn Any>)
+                }
+            },
+        )));
+        let logger = Logger::root(slog::Discard.fuse(), o!());
+        let writer = BulkWriter::new(pd_client, logger)
+            .transaction_options(
+                TransactionOptions::new_optimistic()
+                    .drop_check(CheckLevel::None)
+                    .heartbeat_option(HeartbeatOption::NoHeartbeat),
+            )
+            .max_chunk_pairs(5)
+            .concurrency(2);
+        let pairs = (0..23u8).map(|i| KvPair::new(vec![i], vec![i]));
+        let mut results = writer.write(stream::iter(pairs)).collect::<Vec<_>>().await;
+        results.sort_by_key(|chunk| chunk.index);
+
+        assert_eq!(results.len(), 5);
+        assert_eq!(
+            results.iter().map(|chunk| chunk.len).collect::<Vec<_>>(),
+            vec![5, 5, 5, 5, 3]
+        );
+        assert!(results.iter().all(|chunk| chunk.bytes == chunk.len * 2));
+        for chunk in &results {
+            assert_eq!(chunk.result.is_err(), chunk.index == 2);
+        }
+        assert_eq!(results[2].failed_pairs.len(), 5);
+        assert!(results[0].failed_pairs.is_empty());
+        assert_eq!(prewritten.load(Ordering::SeqCst), 18);
+    }
+}
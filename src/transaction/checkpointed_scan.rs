@@ -0,0 +1,294 @@
+// Copyright 2023 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Scans which report their progress, so that they can be resumed.
+
+use std::collections::VecDeque;
+use std::fmt;
+
+use serde_derive::Deserialize;
+use serde_derive::Serialize;
+
+use crate::pd::PdClient;
+use crate::pd::PdRpcClient;
+use crate::transaction::Transaction;
+use crate::BoundRange;
+use crate::Key;
+use crate::KvPair;
+use crate::Result;
+
+const DEFAULT_CHECKPOINT_EVERY: u32 = 1000;
+const DEFAULT_BATCH_SIZE: u32 = 256;
+
+type OnCheckpoint = Box<dyn FnMut(&ScanCheckpoint) -> Result<()> + Send>;
+
+/// Where a [`CheckpointedScan`] had got to.
+///
+/// It is serializable, so that it can be saved anywhere, and passed to
+/// [`TransactionClient::resume_checkpointed_scan`](crate::TransactionClient::resume_checkpointed_scan)
+/// to carry on from where the scan stopped.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ScanCheckpoint {
+    /// The version of the snapshot being scanned.
+    pub version: u64,
+    /// The start of the range, inclusive.
+    pub start: Vec<u8>,
+    /// The end of the range, exclusive, or `None` if the range is unbounded.
+    pub end: Option<Vec<u8>>,
+    /// The last key processed, or `None` if no key has been processed.
+    pub last_key: Option<Vec<u8>>,
+}
+
+impl ScanCheckpoint {
+    pub(crate) fn new(version: u64, range: BoundRange) -> ScanCheckpoint {
+        let (start, end) = range.into_keys();
+        ScanCheckpoint {
+            version,
+            start: start.into(),
+            end: end.map(Into::into),
+            last_key: None,
+        }
+    }
+
+    /// Where the rest of the range starts.
+    fn next_key(&self) -> Key {
+        match &self.last_key {
+            Some(key) => {
+                let mut key = Key::from(key.clone());
+                key.push_zero();
+                key
+            }
+            None => self.start.clone().into(),
+        }
+    }
+}
+
+/// A scan of a range, as of a snapshot, which regularly hands a [`ScanCheckpoint`] to a callback,
+/// so that it can be saved and the scan resumed after a restart.
+///
+/// A pair returned by [`next`](CheckpointedScan::next) counts as processed once `next` is called
+/// again, or [`save_checkpoint`](CheckpointedScan::save_checkpoint) is called. A checkpoint is
+/// handed to the callback after every [`checkpoint_every`](CheckpointedScan::checkpoint_every)
+/// processed pairs, and once the range has been scanned. If the callback fails, so does the call
+/// of `next`.
+///
+/// Created by
+/// [`TransactionClient::checkpointed_scan`](crate::TransactionClient::checkpointed_scan).
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// # use tikv_client::{ScanCheckpoint, TransactionClient};
+/// # futures::executor::block_on(async {
+/// let client = TransactionClient::new(vec!["192.168.0.100"], None).await.unwrap();
+/// let save = |checkpoint: &ScanCheckpoint| {
+///     std::fs::write("scan.json", serde_json::to_vec(checkpoint).unwrap())?;
+///     Ok(())
+/// };
+/// let mut scan = match std::fs::read("scan.json") {
+///     Ok(saved) => {
+///         let checkpoint = serde_json::from_slice(&saved).unwrap();
+///         client.resume_checkpointed_scan(checkpoint, save)
+///     }
+///     Err(_) => client
+///         .checkpointed_scan("a".to_owned().."z".to_owned(), save)
+///         .await
+///         .unwrap(),
+/// };
+/// while let Some(pair) = scan.next().await.unwrap() {
+///     // Process the pair...
+/// }
+/// # });
+/// ```
+pub struct CheckpointedScan<PdC: PdClient = PdRpcClient> {
+    snapshot: Transaction<PdC>,
+    checkpoint: ScanCheckpoint,
+    on_checkpoint: OnCheckpoint,
+    checkpoint_every: u32,
+    batch_size: u32,
+    /// How many pairs have been processed since the last checkpoint.
+    unsaved: u32,
+    /// The key of the pair last returned, which is processed once the next pair is asked for.
+    returned: Option<Key>,
+    /// Pairs read from TiKV but not yet returned.
+    pending: VecDeque<KvPair>,
+    /// Where the next batch is read from, or `None` once the range has been read.
+    scan_from: Option<Key>,
+}
+
+impl<PdC: PdClient> CheckpointedScan<PdC> {
+    pub(crate) fn new(
+        snapshot: Transaction<PdC>,
+        checkpoint: ScanCheckpoint,
+        on_checkpoint: impl FnMut(&ScanCheckpoint) -> Result<()> + Send + 'static,
+    ) -> CheckpointedScan<PdC> {
+        CheckpointedScan {
+            snapshot,
+            scan_from: Some(checkpoint.next_key()),
+            checkpoint,
+            on_checkpoint: Box::new(on_checkpoint),
+            checkpoint_every: DEFAULT_CHECKPOINT_EVERY,
+            batch_size: DEFAULT_BATCH_SIZE,
+            unsaved: 0,
+            returned: None,
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Set how many pairs are processed between checkpoints. The default is 1000.
+    #[must_use]
+    pub fn checkpoint_every(mut self, pairs: u32) -> Self {
+        self.checkpoint_every = pairs.max(1);
+        self
+    }
+
+    /// Set how many pairs are read from TiKV at a time. The default is 256.
+    #[must_use]
+    pub fn batch_size(mut self, batch_size: u32) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// The checkpoint of the pairs processed so far, which may not have been saved yet.
+    pub fn checkpoint(&self) -> &ScanCheckpoint {
+        &self.checkpoint
+    }
+
+    /// Return the next pair of the range, or `None` once the range has been scanned.
+    pub async fn next(&mut self) -> Result<Option<KvPair>> {
+        self.mark_processed();
+        if self.unsaved >= self.checkpoint_every {
+            self.save()?;
+        }
+        match self.next_pair().await? {
+            Some(pair) => {
+                self.returned = Some(pair.key().clone());
+                Ok(Some(pair))
+            }
+            None => {
+                if self.unsaved > 0 {
+                    self.save()?;
+                }
+                Ok(None)
+            }
+        }
+    }
+
+    /// Count the pair last returned as processed, and hand the checkpoint to the callback now,
+    /// e.g., before stopping the scan early.
+    pub fn save_checkpoint(&mut self) -> Result<()> {
+        self.mark_processed();
+        self.save()
+    }
+
+    fn mark_processed(&mut self) {
+        if let Some(key) = self.returned.take() {
+            self.checkpoint.last_key = Some(key.into());
+            self.unsaved += 1;
+        }
+    }
+
+    fn save(&mut self) -> Result<()> {
+        self.unsaved = 0;
+        (self.on_checkpoint)(&self.checkpoint)
+    }
+
+    async fn next_pair(&mut self) -> Result<Option<KvPair>> {
+        if self.pending.is_empty() {
+            let start = match self.scan_from.take() {
+                Some(start) => start,
+                None => return Ok(None),
+            };
+            let end = self.checkpoint.end.clone().map(Key::from);
+            let limit = self.batch_size;
+            self.pending = self.snapshot.scan((start, end), limit).await?.collect();
+            if self.pending.len() as u32 == limit {
+                let mut next = self.pending.back().unwrap().key().clone();
+                next.push_zero();
+                self.scan_from = Some(next);
+            }
+        }
+        Ok(self.pending.pop_front())
+    }
+}
+
+impl<PdC: PdClient> fmt::Debug for CheckpointedScan<PdC> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CheckpointedScan")
+            .field("checkpoint", &self.checkpoint)
+            .field("checkpoint_every", &self.checkpoint_every)
+            .field("batch_size", &self.batch_size)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::Mutex;
+
+    use slog::Logger;
+
+    use super::*;
+    use crate::simulation::Simulation;
+    use crate::Timestamp;
+    use crate::TimestampExt;
+    use crate::TransactionOptions;
+
+    #[tokio::test]
+    async fn test_checkpointed_scan() {
+        let sim = Simulation::new(36);
+        sim.split(vec![50]);
+        let mut txn = sim.begin_optimistic().await.unwrap();
+        for key in 0..100u8 {
+            txn.put(vec![key], vec![key]).await.unwrap();
+        }
+        txn.commit().await.unwrap();
+
+        let version = sim.pd_client().get_timestamp().await.unwrap().version();
+        let saved = Arc::new(Mutex::new(Vec::new()));
+        let scan = |checkpoint: ScanCheckpoint| {
+            let options = TransactionOptions::new_optimistic().read_only();
+            let logger = Logger::root(slog::Discard, o!());
+            let timestamp = Timestamp::from_version(checkpoint.version);
+            let snapshot = Transaction::new(timestamp, sim.pd_client(), options, logger);
+            let saved = saved.clone();
+            let save = move |checkpoint: &ScanCheckpoint| {
+                saved.lock().unwrap().push(checkpoint.clone());
+                Ok(())
+            };
+            CheckpointedScan::new(snapshot, checkpoint, save)
+                .checkpoint_every(10)
+                .batch_size(16)
+        };
+        let last_saved = || {
+            let saved = saved.lock().unwrap();
+            saved
+                .last()
+                .and_then(|checkpoint| checkpoint.last_key.clone())
+        };
+
+        // Stop after processing 25 pairs, having saved a checkpoint after 20.
+        let mut first = scan(ScanCheckpoint::new(version, (vec![10]..vec![90]).into()));
+        for key in 10..35u8 {
+            let pair = first.next().await.unwrap().unwrap();
+            assert_eq!(pair.key(), &Key::from(vec![key]));
+        }
+        assert_eq!(last_saved(), Some(vec![29]));
+        assert_eq!(first.checkpoint().last_key, Some(vec![33]));
+
+        // Writes after the snapshot are not seen when resuming.
+        let mut txn = sim.begin_optimistic().await.unwrap();
+        txn.delete(vec![60]).await.unwrap();
+        txn.commit().await.unwrap();
+        let checkpoint = saved.lock().unwrap().last().unwrap().clone();
+        let mut rest = scan(checkpoint);
+        let mut keys = Vec::new();
+        while let Some(pair) = rest.next().await.unwrap() {
+            keys.push(pair.key().clone());
+        }
+        let expected: Vec<Key> = (30..90u8).map(|key| vec![key].into()).collect();
+        assert_eq!(keys, expected);
+        // The end of the scan is saved.
+        assert_eq!(last_saved(), Some(vec![89]));
+    }
+}
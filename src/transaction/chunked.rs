@@ -0,0 +1,226 @@
+// Copyright 2023 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Values too large for a single TiKV entry, stored in chunks under derived keys.
+
+use crate::pd::PdClient;
+use crate::transaction::Transaction;
+use crate::Error;
+use crate::Key;
+use crate::Result;
+use crate::Value;
+
+/// The size of each chunk of a chunked value, well below TiKV's default limit on the size of an
+/// entry (`txn-entry-size-limit`, 6 MiB).
+pub const VALUE_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Marks a manifest, which is stored under the key of a chunked value.
+const MANIFEST_MAGIC: &[u8; 8] = b"tikv:chk";
+/// The magic, the number of chunks (u32) and the length of the value (u64).
+const MANIFEST_LEN: usize = MANIFEST_MAGIC.len() + 4 + 8;
+
+/// Where a value's chunks are, as recorded by its manifest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Manifest {
+    chunks: u32,
+    len: u64,
+}
+
+impl Manifest {
+    fn encode(&self) -> Value {
+        let mut value = MANIFEST_MAGIC.to_vec();
+        value.extend_from_slice(&self.chunks.to_be_bytes());
+        value.extend_from_slice(&self.len.to_be_bytes());
+        value
+    }
+
+    /// Returns `None` if `value` is not a manifest.
+    fn decode(value: &[u8]) -> Option<Manifest> {
+        if value.len() != MANIFEST_LEN || !value.starts_with(MANIFEST_MAGIC) {
+            return None;
+        }
+        let (chunks, len) = value[MANIFEST_MAGIC.len()..].split_at(4);
+        Some(Manifest {
+            chunks: u32::from_be_bytes(chunks.try_into().unwrap()),
+            len: u64::from_be_bytes(len.try_into().unwrap()),
+        })
+    }
+}
+
+/// The key of chunk `index` of the value of `key`: `key`, then `#`, then the index as 4 big-endian
+/// bytes.
+fn chunk_key(key: &Key, index: u32) -> Key {
+    let mut chunk_key: Vec<u8> = key.clone().into();
+    chunk_key.push(b'#');
+    chunk_key.extend_from_slice(&index.to_be_bytes());
+    chunk_key.into()
+}
+
+impl<PdC: PdClient> Transaction<PdC> {
+    /// Get a value written by [`put_chunked`](Transaction::put_chunked), reassembling it from its
+    /// chunks.
+    ///
+    /// Values written by [`put`](Transaction::put) are returned as they are.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use tikv_client::{Config, TransactionClient};
+    /// # use futures::prelude::*;
+    /// # futures::executor::block_on(async {
+    /// # let client = TransactionClient::new(vec!["192.168.0.100", "192.168.0.101"], None).await.unwrap();
+    /// let mut txn = client.begin_optimistic().await.unwrap();
+    /// let blob = txn.get_chunked("blobs/1".to_owned()).await.unwrap();
+    /// # });
+    /// ```
+    pub async fn get_chunked(&mut self, key: impl Into<Key>) -> Result<Option<Value>> {
+        let key = key.into();
+        let value = match self.get(key.clone()).await? {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        let manifest = match Manifest::decode(&value) {
+            Some(manifest) => manifest,
+            None => return Ok(Some(value)),
+        };
+        let chunk_keys: Vec<Key> = (0..manifest.chunks)
+            .map(|index| chunk_key(&key, index))
+            .collect();
+        let mut chunks: Vec<_> = self.batch_get(chunk_keys.clone()).await?.collect();
+        chunks.sort_unstable_by(|a, b| a.key().cmp(b.key()));
+        if chunks.len() != chunk_keys.len() {
+            return Err(corrupt(&key, "chunks are missing"));
+        }
+        let mut value = Vec::with_capacity(manifest.len as usize);
+        for chunk in chunks {
+            value.extend_from_slice(chunk.value());
+        }
+        if value.len() as u64 != manifest.len {
+            return Err(corrupt(&key, "chunks do not match the manifest"));
+        }
+        Ok(Some(value))
+    }
+
+    /// Set the value of `key`, splitting it into chunks if it is too large for a single entry.
+    ///
+    /// A value of at most [`VALUE_CHUNK_SIZE`] bytes is stored under `key`, as by
+    /// [`put`](Transaction::put). A larger value is split into chunks of that size, stored under
+    /// the keys `key#0`, `key#1`, ... (the index is encoded as 4 big-endian bytes), and a manifest
+    /// recording the chunks is stored under `key`. Read the value with
+    /// [`get_chunked`](Transaction::get_chunked), and delete it with
+    /// [`delete_chunked`](Transaction::delete_chunked), so that the chunks are handled too. The
+    /// chunk keys must not be used for anything else.
+    ///
+    /// The chunks of a value previously stored under `key` are deleted.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use tikv_client::{Config, TransactionClient};
+    /// # use futures::prelude::*;
+    /// # futures::executor::block_on(async {
+    /// # let client = TransactionClient::new(vec!["192.168.0.100", "192.168.0.101"], None).await.unwrap();
+    /// let mut txn = client.begin_optimistic().await.unwrap();
+    /// txn.put_chunked("blobs/1".to_owned(), vec![0; 30 * 1024 * 1024])
+    ///     .await
+    ///     .unwrap();
+    /// txn.commit().await.unwrap();
+    /// # });
+    /// ```
+    pub async fn put_chunked(
+        &mut self,
+        key: impl Into<Key>,
+        value: impl Into<Value>,
+    ) -> Result<()> {
+        let key = key.into();
+        let value = value.into();
+        let old_chunks = self.chunks(&key).await?;
+        // A small value which looks like a manifest is chunked, so that it isn't mistaken for one.
+        let chunks = if value.len() <= VALUE_CHUNK_SIZE && Manifest::decode(&value).is_none() {
+            self.put(key.clone(), value).await?;
+            0
+        } else {
+            let manifest = Manifest {
+                chunks: value.chunks(VALUE_CHUNK_SIZE).count() as u32,
+                len: value.len() as u64,
+            };
+            for (index, chunk) in value.chunks(VALUE_CHUNK_SIZE).enumerate() {
+                self.put(chunk_key(&key, index as u32), chunk.to_vec())
+                    .await?;
+            }
+            self.put(key.clone(), manifest.encode()).await?;
+            manifest.chunks
+        };
+        for index in chunks..old_chunks {
+            self.delete(chunk_key(&key, index)).await?;
+        }
+        Ok(())
+    }
+
+    /// Delete a value written by [`put_chunked`](Transaction::put_chunked), including its chunks.
+    pub async fn delete_chunked(&mut self, key: impl Into<Key>) -> Result<()> {
+        let key = key.into();
+        for index in 0..self.chunks(&key).await? {
+            self.delete(chunk_key(&key, index)).await?;
+        }
+        self.delete(key).await
+    }
+
+    /// The number of chunks of the value of `key`.
+    async fn chunks(&mut self, key: &Key) -> Result<u32> {
+        let value = self.get(key.clone()).await?;
+        let manifest = value.as_deref().and_then(Manifest::decode);
+        Ok(manifest.map_or(0, |manifest| manifest.chunks))
+    }
+}
+
+fn corrupt(key: &Key, message: &str) -> Error {
+    Error::ValueCodecError {
+        message: format!("chunked value of {key:?} is corrupt: {message}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::Simulation;
+
+    #[tokio::test]
+    async fn test_chunked() {
+        let sim = Simulation::new(20);
+        sim.split(vec![1, b'#', 0, 0, 0, 2]);
+        let large: Vec<u8> = (0..VALUE_CHUNK_SIZE * 3 + 10).map(|i| i as u8).collect();
+        let mut txn = sim.begin_optimistic().await.unwrap();
+        txn.put_chunked(vec![1], large.clone()).await.unwrap();
+        txn.put_chunked(vec![2], vec![2]).await.unwrap();
+        txn.commit().await.unwrap();
+
+        let mut txn = sim.begin_optimistic().await.unwrap();
+        assert_eq!(txn.get_chunked(vec![1]).await.unwrap(), Some(large));
+        assert_eq!(txn.get_chunked(vec![2]).await.unwrap(), Some(vec![2]));
+        assert_eq!(txn.get_chunked(vec![3]).await.unwrap(), None);
+        assert_eq!(txn.count(..).await.unwrap(), 6);
+        // Overwriting a value with a smaller one deletes the chunks it no longer needs.
+        let smaller = vec![1; VALUE_CHUNK_SIZE + 1];
+        txn.put_chunked(vec![1], smaller.clone()).await.unwrap();
+        txn.commit().await.unwrap();
+
+        let mut txn = sim.begin_optimistic().await.unwrap();
+        assert_eq!(txn.get_chunked(vec![1]).await.unwrap(), Some(smaller));
+        assert_eq!(txn.count(..).await.unwrap(), 4);
+        txn.delete(chunk_key(&vec![1].into(), 1)).await.unwrap();
+        let e = txn.get_chunked(vec![1]).await.unwrap_err();
+        assert!(matches!(e, Error::ValueCodecError { .. }), "{e:?}");
+        txn.delete_chunked(vec![1]).await.unwrap();
+        txn.delete_chunked(vec![2]).await.unwrap();
+        assert_eq!(txn.count(..).await.unwrap(), 0);
+        txn.commit().await.unwrap();
+    }
+
+    #[test]
+    fn test_manifest() {
+        let manifest = Manifest { chunks: 3, len: 42 };
+        assert_eq!(Manifest::decode(&manifest.encode()), Some(manifest));
+        assert_eq!(Manifest::decode(MANIFEST_MAGIC), None);
+        assert_eq!(Manifest::decode(&[0; MANIFEST_LEN]), None);
+    }
+}
@@ -1,26 +1,57 @@
 // Copyright 2019 TiKV Project Authors. Licensed under Apache-2.0.
 
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 
-use slog::Drain;
+use futures::Stream;
 use slog::Logger;
 use tikv_client_proto::pdpb::Timestamp;
 
 use crate::backoff::DEFAULT_REGION_BACKOFF;
 use crate::config::Config;
+use crate::keyspace::Keyspaces;
 use crate::pd::PdClient;
 use crate::pd::PdRpcClient;
+use crate::pd::SingleNodePdClient;
+use crate::presplit;
+use crate::recipes::DistributedLock;
+use crate::recipes::Queue;
+use crate::recipes::Sequence;
+use crate::region::RegionInfo;
+use crate::region_cache::RegionCacheStats;
 use crate::request::plan::CleanupLocksResult;
 use crate::request::Plan;
+use crate::runtime_config::client_logger;
 use crate::timestamp::TimestampExt;
+use crate::timestamp_pool::TimestampPool;
+use crate::transaction::export;
+use crate::transaction::lock::get_txn_status;
 use crate::transaction::lock::ResolveLocksOptions;
+use crate::transaction::shutdown::TransactionRegistry;
+use crate::transaction::watch;
+use crate::transaction::BulkWriter;
+use crate::transaction::CheckpointedScan;
+use crate::transaction::CloseReport;
+use crate::transaction::ExportOptions;
+use crate::transaction::ExportedFile;
+use crate::transaction::KeyChange;
+use crate::transaction::Participant;
+use crate::transaction::ReadCache;
 use crate::transaction::ResolveLocksContext;
+use crate::transaction::ScanCheckpoint;
 use crate::transaction::Snapshot;
 use crate::transaction::Transaction;
 use crate::transaction::TransactionOptions;
+use crate::transaction::TransactionState;
+use crate::transaction::TxnStatus;
 use crate::transaction_lowering::new_scan_lock_request;
+use crate::value_format::ValueFormat;
 use crate::Backoff;
 use crate::BoundRange;
+use crate::ConfigUpdate;
+use crate::HotKey;
+use crate::Key;
 use crate::Result;
 
 // FIXME: cargo-culted value
@@ -42,15 +73,23 @@ const SCAN_LOCK_BATCH_SIZE: u32 = 1024;
 ///
 /// The returned results of transactional requests are [`Future`](std::future::Future)s that must be
 /// awaited to execute.
-pub struct Client {
-    pd: Arc<PdRpcClient>,
+pub struct Client<PdC: PdClient = PdRpcClient> {
+    pd: Arc<PdC>,
+    read_cache: Option<Arc<ReadCache>>,
+    value_format: Option<Arc<ValueFormat>>,
+    timestamp_pool: Option<Arc<TimestampPool>>,
+    transactions: Arc<TransactionRegistry>,
     logger: Logger,
 }
 
-impl Clone for Client {
+impl<PdC: PdClient> Clone for Client<PdC> {
     fn clone(&self) -> Self {
         Self {
             pd: self.pd.clone(),
+            read_cache: self.read_cache.clone(),
+            value_format: self.value_format.clone(),
+            timestamp_pool: self.timestamp_pool.clone(),
+            transactions: self.transactions.clone(),
             logger: self.logger.clone(),
         }
     }
@@ -109,22 +148,193 @@ impl Client {
         config: Config,
         optional_logger: Option<Logger>,
     ) -> Result<Client> {
-        let logger = optional_logger.unwrap_or_else(|| {
-            let plain = slog_term::PlainSyncDecorator::new(std::io::stdout());
-            Logger::root(
-                slog_term::FullFormat::new(plain)
-                    .build()
-                    .filter_level(slog::Level::Info)
-                    .fuse(),
-                o!(),
-            )
-        });
+        let (logger, log_level) = client_logger(optional_logger);
         debug!(logger, "creating new transactional client");
         let pd_endpoints: Vec<String> = pd_endpoints.into_iter().map(Into::into).collect();
-        let pd = Arc::new(PdRpcClient::connect(&pd_endpoints, config, true, logger.clone()).await?);
-        Ok(Client { pd, logger })
+        let read_cache = config
+            .read_cache_capacity
+            .map(|capacity| Arc::new(ReadCache::new(capacity)));
+        let value_format = ValueFormat::new(&config).map(Arc::new);
+        let timestamp_pool = config
+            .timestamp_prefetch
+            .clone()
+            .map(|prefetch| Arc::new(TimestampPool::new(prefetch)));
+        let pd = PdRpcClient::connect(&pd_endpoints, config, true, logger.clone()).await?;
+        let pd = Arc::new(pd.with_log_level(log_level));
+        Ok(Client {
+            pd,
+            read_cache,
+            value_format,
+            timestamp_pool,
+            transactions: Default::default(),
+            logger,
+        })
+    }
+
+    /// Manage the cluster's keyspaces.
+    pub fn keyspaces(&self) -> Keyspaces {
+        Keyspaces::new(self.pd.clone())
+    }
+
+    /// Change settings of the client without reconnecting. Settings which `update` leaves unset
+    /// are kept.
+    ///
+    /// The change applies to the requests sent afterwards, including those of clones of the
+    /// client.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use tikv_client::{ConfigUpdate, TransactionClient};
+    /// # use futures::prelude::*;
+    /// # use std::time::Duration;
+    /// # futures::executor::block_on(async {
+    /// let client = TransactionClient::new(vec!["192.168.0.100"], None).await.unwrap();
+    /// client.update_config(
+    ///     ConfigUpdate::default()
+    ///         .timeout(Duration::from_secs(5))
+    ///         .log_level(slog::Level::Debug),
+    /// );
+    /// # });
+    /// ```
+    pub fn update_config(&self, update: ConfigUpdate) {
+        self.pd.update_config(&update);
+    }
+
+    /// Split `range` into `target_shard_count` regions with evenly spaced boundaries, and scatter
+    /// the new regions across stores. Returns the keys the range was split at.
+    ///
+    /// Use this before loading a lot of data into an empty range, so that the load is spread over
+    /// the cluster from the start. Boundaries are interpolated between the bounds of the range,
+    /// which suits evenly distributed keys; otherwise use
+    /// [`presplit_range_by_sample`](Client::presplit_range_by_sample).
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use tikv_client::{Config, TransactionClient};
+    /// # use futures::prelude::*;
+    /// # futures::executor::block_on(async {
+    /// let client = TransactionClient::new(vec!["192.168.0.100"], None).await.unwrap();
+    /// let split_keys = client
+    ///     .presplit_range("user0".to_owned().."user9".to_owned(), 16)
+    ///     .await
+    ///     .unwrap();
+    /// # });
+    /// ```
+    pub async fn presplit_range(
+        &self,
+        range: impl Into<BoundRange>,
+        target_shard_count: usize,
+    ) -> Result<Vec<Key>> {
+        debug!(self.logger, "invoking transactional presplit_range request");
+        let range = range.into();
+        let split_keys = presplit::even_split_keys(range.clone(), target_shard_count);
+        presplit::split_and_scatter(&self.pd, range, split_keys).await
+    }
+
+    /// Split `range` into `target_shard_count` regions holding roughly the same number of keys,
+    /// and scatter the new regions across stores. Returns the keys the range was split at.
+    ///
+    /// Boundaries are chosen from the first `sample_limit` keys in the range, as of the current
+    /// timestamp.
+    pub async fn presplit_range_by_sample(
+        &self,
+        range: impl Into<BoundRange>,
+        target_shard_count: usize,
+        sample_limit: u32,
+    ) -> Result<Vec<Key>> {
+        debug!(
+            self.logger,
+            "invoking transactional presplit_range_by_sample request"
+        );
+        let range = range.into();
+        let timestamp = self.current_timestamp().await?;
+        let mut snapshot = self.snapshot(timestamp, TransactionOptions::new_optimistic());
+        let samples = snapshot
+            .scan_keys(range.clone(), sample_limit)
+            .await?
+            .collect();
+        let split_keys = presplit::sampled_split_keys(samples, target_shard_count);
+        presplit::split_and_scatter(&self.pd, range, split_keys).await
+    }
+}
+
+impl Client<SingleNodePdClient> {
+    /// Create a transactional [`Client`] which talks to a single TiKV instance at `address`,
+    /// without PD.
+    ///
+    /// This is a development mode, for running examples and tests against one TiKV instance, e.g.,
+    /// a mock, rather than a full cluster. The instance must serve a single region, with id 1,
+    /// which covers all keys. Timestamps are issued by the client from the system clock, so only
+    /// one client may write at a time. See [`SingleNodePdClient`] for the limitations of the mode.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use tikv_client::TransactionClient;
+    /// # use futures::prelude::*;
+    /// # futures::executor::block_on(async {
+    /// let client = TransactionClient::connect_single_node("127.0.0.1:20160")
+    ///     .await
+    ///     .unwrap();
+    /// let mut txn = client.begin_optimistic().await.unwrap();
+    /// txn.put("key".to_owned(), "value".to_owned()).await.unwrap();
+    /// txn.commit().await.unwrap();
+    /// # });
+    /// ```
+    pub async fn connect_single_node(address: impl Into<String>) -> Result<Self> {
+        Self::connect_single_node_with_config(address, Config::default()).await
     }
 
+    /// Create a transactional [`Client`] which talks to a single TiKV instance at `address`,
+    /// without PD, configured by `config`.
+    ///
+    /// See [`connect_single_node`](Client::connect_single_node). Settings which only concern PD,
+    /// such as the PD timeout and retry budget, are ignored. A
+    /// [`TimestampProvider`](crate::TimestampProvider) in `config` issues the timestamps in place
+    /// of the system clock.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use tikv_client::{Config, LogicalClock, TransactionClient};
+    /// # use futures::prelude::*;
+    /// # futures::executor::block_on(async {
+    /// let client = TransactionClient::connect_single_node_with_config(
+    ///     "127.0.0.1:20160",
+    ///     Config::default().with_timestamp_provider(LogicalClock::new(1)),
+    /// )
+    /// .await
+    /// .unwrap();
+    /// # });
+    /// ```
+    pub async fn connect_single_node_with_config(
+        address: impl Into<String>,
+        config: Config,
+    ) -> Result<Self> {
+        let logger = Logger::root(slog::Discard, o!());
+        let read_cache = config
+            .read_cache_capacity
+            .map(|capacity| Arc::new(ReadCache::new(capacity)));
+        let value_format = ValueFormat::new(&config).map(Arc::new);
+        let timestamp_pool = config
+            .timestamp_prefetch
+            .clone()
+            .map(|prefetch| Arc::new(TimestampPool::new(prefetch)));
+        let pd = SingleNodePdClient::connect(address.into(), &config).await?;
+        Ok(Client {
+            pd: Arc::new(pd),
+            read_cache,
+            value_format,
+            timestamp_pool,
+            transactions: Default::default(),
+            logger,
+        })
+    }
+}
+
+impl<PdC: PdClient> Client<PdC> {
     /// Creates a new optimistic [`Transaction`].
     ///
     /// Use the transaction to issue requests like [`get`](Transaction::get) or
@@ -147,9 +357,9 @@ impl Client {
     /// transaction.commit().await.unwrap();
     /// # });
     /// ```
-    pub async fn begin_optimistic(&self) -> Result<Transaction> {
+    pub async fn begin_optimistic(&self) -> Result<Transaction<PdC>> {
         debug!(self.logger, "creating new optimistic transaction");
-        let timestamp = self.current_timestamp().await?;
+        let timestamp = self.start_timestamp().await?;
         Ok(self.new_transaction(timestamp, TransactionOptions::new_optimistic()))
     }
 
@@ -172,9 +382,9 @@ impl Client {
     /// transaction.commit().await.unwrap();
     /// # });
     /// ```
-    pub async fn begin_pessimistic(&self) -> Result<Transaction> {
+    pub async fn begin_pessimistic(&self) -> Result<Transaction<PdC>> {
         debug!(self.logger, "creating new pessimistic transaction");
-        let timestamp = self.current_timestamp().await?;
+        let timestamp = self.start_timestamp().await?;
         Ok(self.new_transaction(timestamp, TransactionOptions::new_pessimistic()))
     }
 
@@ -197,14 +407,20 @@ impl Client {
     /// transaction.commit().await.unwrap();
     /// # });
     /// ```
-    pub async fn begin_with_options(&self, options: TransactionOptions) -> Result<Transaction> {
+    pub async fn begin_with_options(
+        &self,
+        options: TransactionOptions,
+    ) -> Result<Transaction<PdC>> {
         debug!(self.logger, "creating new customized transaction");
-        let timestamp = self.current_timestamp().await?;
+        let timestamp = self.start_timestamp().await?;
         Ok(self.new_transaction(timestamp, options))
     }
 
     /// Create a new [`Snapshot`](Snapshot) at the given [`Timestamp`](Timestamp).
-    pub fn snapshot(&self, timestamp: Timestamp, options: TransactionOptions) -> Snapshot {
+    ///
+    /// Reads of the snapshot fail with [`Error::ClientClosed`](crate::Error::ClientClosed) once the
+    /// client is closed.
+    pub fn snapshot(&self, timestamp: Timestamp, options: TransactionOptions) -> Snapshot<PdC> {
         debug!(self.logger, "creating new snapshot");
         let logger = self.logger.new(o!("child" => 1));
         Snapshot::new(self.new_transaction(timestamp, options.read_only()), logger)
@@ -228,6 +444,117 @@ impl Client {
         self.pd.clone().get_timestamp().await
     }
 
+    /// Close the client, e.g., before the service using it restarts.
+    ///
+    /// The client stops beginning transactions, and waits up to `timeout` for the commits in flight
+    /// to finish. Optimistic transactions which have not started committing are then rolled back:
+    /// they fail any further operation. The heartbeats of the client's transactions and its store
+    /// health probes are stopped, and its connections to TiKV stores are dropped, which ends their
+    /// batching tasks once the requests in flight finish. The connection to PD, and its timestamp
+    /// task, live until the client and its clones are dropped.
+    ///
+    /// Pessimistic transactions are left to be rolled back by their owners; once their heartbeats
+    /// stop, their locks expire. Other operations of the client's transactions and snapshots fail
+    /// with [`Error::ClientClosed`](crate::Error::ClientClosed). Clones of the client are closed
+    /// too.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use tikv_client::{Config, TransactionClient};
+    /// # use futures::prelude::*;
+    /// # use std::time::Duration;
+    /// # futures::executor::block_on(async {
+    /// let client = TransactionClient::new(vec!["192.168.0.100"], None).await.unwrap();
+    /// // ... Run transactions.
+    /// let report = client.close(Duration::from_secs(5)).await;
+    /// assert_eq!(report.unfinished_commits, 0);
+    /// # });
+    /// ```
+    pub async fn close(&self, timeout: Duration) -> CloseReport {
+        debug!(self.logger, "closing transactional client");
+        let report = self.transactions.close(timeout).await;
+        self.pd.close().await;
+        report
+    }
+
+    /// The start timestamp of a new transaction, from the timestamp pool if there is one.
+    async fn start_timestamp(&self) -> Result<Timestamp> {
+        self.transactions.check_open()?;
+        match &self.timestamp_pool {
+            Some(pool) => pool.get_timestamp(&self.pd).await,
+            None => self.current_timestamp().await,
+        }
+    }
+
+    /// Fetch and cache the regions covering `range`, and connect to their stores.
+    ///
+    /// This is useful before a bulk job, to avoid querying PD while it runs. Returns the number of
+    /// regions covering the range.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use tikv_client::{Config, TransactionClient};
+    /// # use futures::prelude::*;
+    /// # futures::executor::block_on(async {
+    /// let client = TransactionClient::new(vec!["192.168.0.100"], None)
+    ///     .await
+    ///     .unwrap();
+    /// let regions = client.warm_region_cache("a".to_owned().."z".to_owned()).await.unwrap();
+    /// # });
+    /// ```
+    pub async fn warm_region_cache(&self, range: impl Into<BoundRange>) -> Result<usize> {
+        self.pd.clone().warm_region_cache(range.into()).await
+    }
+
+    /// Remove all cached regions which overlap `range`, so they are fetched from PD when next
+    /// used. Returns the number of regions removed.
+    ///
+    /// To invalidate the region containing a single key, use `key..=key`.
+    pub async fn invalidate_region_cache(&self, range: impl Into<BoundRange>) -> usize {
+        self.pd.invalidate_region_cache_range(range.into()).await
+    }
+
+    /// Get statistics about the client's region cache.
+    pub async fn region_cache_stats(&self) -> RegionCacheStats {
+        self.pd.region_cache_stats().await
+    }
+
+    /// Get the `top_n` hottest keys accessed by the client's transactions, hottest first: those
+    /// which conflicted most, then those whose sampled accesses took longest.
+    ///
+    /// Keys are only tracked by clients created with
+    /// [`Config::with_hot_key_tracking`](crate::Config::with_hot_key_tracking); otherwise, the
+    /// list is empty.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use tikv_client::{Config, HotKeyTracking, TransactionClient};
+    /// # use futures::prelude::*;
+    /// # futures::executor::block_on(async {
+    /// let config = Config::default().with_hot_key_tracking(HotKeyTracking::default());
+    /// let client = TransactionClient::new_with_config(vec!["192.168.0.100"], config, None)
+    ///     .await
+    ///     .unwrap();
+    /// // ... Run transactions.
+    /// for hot_key in client.hot_keys(10) {
+    ///     println!("{:?}: {} conflicts", hot_key.key, hot_key.conflicts);
+    /// }
+    /// # });
+    /// ```
+    pub fn hot_keys(&self, top_n: usize) -> Vec<HotKey> {
+        self.pd
+            .hot_key_tracker()
+            .map_or_else(Vec::new, |tracker| tracker.hot_keys(top_n))
+    }
+
+    /// Get the regions covering `range`, in order, and where their leaders are.
+    pub async fn regions(&self, range: impl Into<BoundRange>) -> Result<Vec<RegionInfo>> {
+        self.pd.clone().regions_for_range(range.into()).await
+    }
+
     /// Request garbage collection (GC) of the TiKV cluster.
     ///
     /// GC deletes MVCC records whose timestamp is lower than the given `safepoint`. We must guarantee
@@ -263,6 +590,43 @@ impl Client {
         Ok(res)
     }
 
+    /// Find out whether a transaction committed, and at which timestamp, from its primary key and
+    /// start timestamp, e.g., those of an [`UndeterminedError`](crate::Error::UndeterminedError)
+    /// returned by a previous process.
+    ///
+    /// If the transaction's primary lock has expired, or was never written, the transaction is
+    /// rolled back, so that its fate is settled. A transaction using async commit is settled by
+    /// checking its secondary keys instead. If the lock is still live, the status is
+    /// [`TxnStatus::Pending`], and should be checked again later.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use tikv_client::{Config, Error, Timestamp, TimestampExt, TransactionClient, TxnStatus};
+    /// # use futures::prelude::*;
+    /// # futures::executor::block_on(async {
+    /// let client = TransactionClient::new(vec!["192.168.0.100"], None).await.unwrap();
+    /// let mut txn = client.begin_optimistic().await.unwrap();
+    /// txn.put("key".to_owned(), "value".to_owned()).await.unwrap();
+    /// if let Err(Error::UndeterminedError { primary_key, start_ts, .. }) = txn.commit().await {
+    ///     let start_ts = Timestamp::from_version(start_ts);
+    ///     match client.get_txn_status(primary_key, start_ts).await.unwrap() {
+    ///         TxnStatus::Committed { commit_ts } => println!("committed at {commit_ts:?}"),
+    ///         TxnStatus::RolledBack => println!("rolled back"),
+    ///         TxnStatus::Pending => println!("not settled yet"),
+    ///     }
+    /// }
+    /// # });
+    /// ```
+    pub async fn get_txn_status(
+        &self,
+        primary_key: impl Into<Key>,
+        start_ts: Timestamp,
+    ) -> Result<TxnStatus> {
+        debug!(self.logger, "invoking get_txn_status");
+        get_txn_status(self.pd.clone(), primary_key.into(), start_ts.version()).await
+    }
+
     pub async fn cleanup_locks(
         &self,
         range: impl Into<BoundRange>,
@@ -300,8 +664,173 @@ impl Client {
         plan.execute().await
     }
 
-    fn new_transaction(&self, timestamp: Timestamp, options: TransactionOptions) -> Transaction {
+    /// Recreate a transaction from a state exported by
+    /// [`Transaction::export_state`](crate::Transaction::export_state), possibly by another client.
+    ///
+    /// `options` must be optimistic or pessimistic to match the exported transaction, otherwise
+    /// [`Error::InvalidTransactionType`](crate::Error::InvalidTransactionType) is returned.
+    pub fn import_transaction(
+        &self,
+        state: TransactionState,
+        options: TransactionOptions,
+    ) -> Result<Transaction<PdC>> {
+        self.transactions.check_open()?;
+        let logger = self.logger.new(o!("child" => 1));
+        let txn = Transaction::from_state(state, self.pd.clone(), options, logger)?;
+        Ok(txn
+            .with_value_format(self.value_format.clone())
+            .with_registry(Some(self.transactions.clone()))
+            .with_timestamp_pool(self.timestamp_pool.clone()))
+    }
+
+    /// Create a [`BulkWriter`] for loading many pairs in chunked transactions.
+    pub fn bulk_writer(&self) -> BulkWriter<PdC> {
+        let logger = self.logger.new(o!("child" => 1));
+        BulkWriter::new(self.pd.clone(), logger).with_value_format(self.value_format.clone())
+    }
+
+    /// Create a [`Participant`] in a transaction with the given start timestamp, for taking part
+    /// in a two-phase commit driven by an external coordinator.
+    pub fn participant(&self, start_ts: Timestamp) -> Participant<PdC> {
+        let logger = self.logger.new(o!("child" => 1));
+        Participant::new(start_ts, self.pd.clone(), logger)
+            .with_value_format(self.value_format.clone())
+    }
+
+    /// Create a [`DistributedLock`] stored under `key`, whose leases last for `ttl` unless they
+    /// are renewed.
+    pub fn distributed_lock(&self, key: impl Into<Key>, ttl: Duration) -> DistributedLock<PdC> {
+        let logger = self.logger.new(o!("child" => 1));
+        DistributedLock::new(self.pd.clone(), key.into(), ttl, logger)
+    }
+
+    /// Create a [`Sequence`] of ids, allocated from a counter stored under `key`.
+    pub fn sequence(&self, key: impl Into<Key>) -> Sequence<PdC> {
+        let logger = self.logger.new(o!("child" => 1));
+        Sequence::new(self.pd.clone(), key.into(), logger)
+    }
+
+    /// Create a [`Queue`] of messages stored under `prefix`, which are hidden for
+    /// `visibility_timeout` when popped.
+    pub fn queue(&self, prefix: impl Into<Key>, visibility_timeout: Duration) -> Queue<PdC> {
+        let logger = self.logger.new(o!("child" => 1));
+        Queue::new(self.pd.clone(), prefix.into(), visibility_timeout, logger)
+    }
+
+    /// Watch `key` for changes, by polling it every `poll_interval`.
+    ///
+    /// The stream yields the key's value when it is first polled, and again whenever a write to
+    /// the key is committed, even if the value is unchanged. Writes committed between two polls are
+    /// seen as one change. Each poll reads the key and the list of its versions, which is
+    /// expensive, so watch few keys, and not too often.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use tikv_client::{Config, TransactionClient};
+    /// # use std::time::Duration;
+    /// # use futures::prelude::*;
+    /// # futures::executor::block_on(async {
+    /// let client = TransactionClient::new(vec!["192.168.0.100"], None).await.unwrap();
+    /// let changes = client.watch("config".to_owned(), Duration::from_secs(1));
+    /// futures::pin_mut!(changes);
+    /// while let Some(change) = changes.next().await {
+    ///     println!("config is now {:?}", change.unwrap().value);
+    /// }
+    /// # });
+    /// ```
+    pub fn watch(
+        &self,
+        key: impl Into<Key>,
+        poll_interval: Duration,
+    ) -> impl Stream<Item = Result<KeyChange>> {
+        let logger = self.logger.new(o!("child" => 1));
+        let format = self.value_format.clone();
+        watch::watch(self.pd.clone(), key.into(), poll_interval, format, logger)
+    }
+
+    /// Export `range` to files in `dir`, as of a snapshot taken when the export starts.
+    ///
+    /// Pairs are written in key order, to files named `00000000.ndjson`, `00000001.ndjson`, ...
+    /// (`.kv` for the binary format), each closed once it reaches
+    /// [`max_file_bytes`](ExportOptions::max_file_bytes). The stream yields each file once it is
+    /// complete. Progress is saved in `dir/checkpoint.json`: if the export is interrupted, calling
+    /// `export` again with the same range, directory and format resumes it at the same snapshot.
+    ///
+    /// While the export runs, a service safepoint keeps GC from removing the snapshot's data. It
+    /// runs out ten minutes after an interrupted export stops reading; if GC has passed the
+    /// snapshot by the time the export is resumed, resuming fails with
+    /// [`Error::SnapshotTooOld`](crate::Error::SnapshotTooOld).
+    ///
+    /// Write the pairs back with [`BulkWriter::import`].
+    ///
+    /// Files are written with blocking IO.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use tikv_client::{Config, ExportOptions, TransactionClient};
+    /// # use futures::prelude::*;
+    /// # futures::executor::block_on(async {
+    /// let client = TransactionClient::new(vec!["192.168.0.100"], None).await.unwrap();
+    /// let files = client.export("a".to_owned().."z".to_owned(), "/backup", ExportOptions::default());
+    /// let files: Vec<_> = files.try_collect().await.unwrap();
+    /// # });
+    /// ```
+    pub fn export(
+        &self,
+        range: impl Into<BoundRange>,
+        dir: impl Into<PathBuf>,
+        options: ExportOptions,
+    ) -> impl Stream<Item = Result<ExportedFile>> {
+        let logger = self.logger.new(o!("child" => 1));
+        let format = self.value_format.clone();
+        let (range, dir) = (range.into(), dir.into());
+        export::export(self.pd.clone(), range, dir, options, format, logger)
+    }
+
+    /// Scan `range`, as of a snapshot taken now, handing the scan's progress to `on_checkpoint`
+    /// regularly so that it can be saved, and the scan resumed with
+    /// [`resume_checkpointed_scan`](Client::resume_checkpointed_scan).
+    ///
+    /// See [`CheckpointedScan`] for when checkpoints are taken.
+    pub async fn checkpointed_scan(
+        &self,
+        range: impl Into<BoundRange>,
+        on_checkpoint: impl FnMut(&ScanCheckpoint) -> Result<()> + Send + 'static,
+    ) -> Result<CheckpointedScan<PdC>> {
+        debug!(self.logger, "creating new checkpointed scan");
+        let timestamp = self.current_timestamp().await?;
+        let checkpoint = ScanCheckpoint::new(timestamp.version(), range.into());
+        Ok(self.resume_checkpointed_scan(checkpoint, on_checkpoint))
+    }
+
+    /// Carry on the scan `checkpoint` was taken of, after the last key it processed, at the same
+    /// snapshot.
+    ///
+    /// The snapshot must not have been garbage collected.
+    pub fn resume_checkpointed_scan(
+        &self,
+        checkpoint: ScanCheckpoint,
+        on_checkpoint: impl FnMut(&ScanCheckpoint) -> Result<()> + Send + 'static,
+    ) -> CheckpointedScan<PdC> {
+        debug!(self.logger, "resuming checkpointed scan");
+        let timestamp = Timestamp::from_version(checkpoint.version);
+        let options = TransactionOptions::new_optimistic().read_only();
+        let snapshot = self.new_transaction(timestamp, options);
+        CheckpointedScan::new(snapshot, checkpoint, on_checkpoint)
+    }
+
+    fn new_transaction(
+        &self,
+        timestamp: Timestamp,
+        options: TransactionOptions,
+    ) -> Transaction<PdC> {
         let logger = self.logger.new(o!("child" => 1));
         Transaction::new(timestamp, self.pd.clone(), options, logger)
+            .with_read_cache(self.read_cache.clone())
+            .with_value_format(self.value_format.clone())
+            .with_registry(Some(self.transactions.clone()))
+            .with_timestamp_pool(self.timestamp_pool.clone())
     }
 }
@@ -0,0 +1,581 @@
+// Copyright 2023 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Exporting a range, as of a snapshot, to files.
+
+use std::collections::VecDeque;
+use std::fs;
+use std::fs::File;
+use std::io::BufWriter;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+
+use futures::prelude::*;
+use serde_derive::Deserialize;
+use serde_derive::Serialize;
+use slog::Logger;
+
+use crate::pd::PdClient;
+use crate::transaction::Transaction;
+use crate::value_format::ValueFormat;
+use crate::BoundRange;
+use crate::Error;
+use crate::Key;
+use crate::KvPair;
+use crate::Result;
+use crate::Timestamp;
+use crate::TimestampExt;
+use crate::TransactionOptions;
+
+/// The name of the file recording the progress of an export, in the export's directory.
+pub(crate) const CHECKPOINT_FILE: &str = "checkpoint.json";
+/// The first bytes of a file in the [binary](ExportFormat::Binary) format.
+pub(crate) const BINARY_MAGIC: &[u8; 8] = b"TIKVKV01";
+
+const DEFAULT_MAX_FILE_BYTES: u64 = 64 * 1024 * 1024;
+const DEFAULT_BATCH_SIZE: u32 = 1024;
+// How long the service safepoint protecting an export's snapshot lasts unless it is renewed. It
+// is renewed while the export reads, so it only runs out once the export is abandoned.
+const SAFEPOINT_TTL: Duration = Duration::from_secs(600);
+const SAFEPOINT_RENEW_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The format of the files written by an export.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ExportFormat {
+    /// One JSON object per line, `{"key":"…","value":"…"}`, with keys and values in hexadecimal.
+    Ndjson,
+    /// The 8 bytes `TIKVKV01`, then, for each pair, the length of the key as 4 big-endian bytes,
+    /// the key, the length of the value as 4 big-endian bytes and the value.
+    Binary,
+}
+
+impl ExportFormat {
+    pub(crate) fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Ndjson => "ndjson",
+            ExportFormat::Binary => "kv",
+        }
+    }
+}
+
+/// How [`TransactionClient::export`](crate::TransactionClient::export) writes files.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExportOptions {
+    pub format: ExportFormat,
+    pub max_file_bytes: u64,
+    pub batch_size: u32,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        ExportOptions {
+            format: ExportFormat::Ndjson,
+            max_file_bytes: DEFAULT_MAX_FILE_BYTES,
+            batch_size: DEFAULT_BATCH_SIZE,
+        }
+    }
+}
+
+impl ExportOptions {
+    /// Set the format of the files. The default is [`ExportFormat::Ndjson`].
+    #[must_use]
+    pub fn format(mut self, format: ExportFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Set the size at which a file is closed and the next one started. A file exceeds it by at
+    /// most one pair. The default is 64 MiB.
+    #[must_use]
+    pub fn max_file_bytes(mut self, max_file_bytes: u64) -> Self {
+        self.max_file_bytes = max_file_bytes.max(1);
+        self
+    }
+
+    /// Set how many pairs are read from TiKV at a time. The default is 1024.
+    #[must_use]
+    pub fn batch_size(mut self, batch_size: u32) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+}
+
+/// A file completed by an export.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExportedFile {
+    pub path: PathBuf,
+    /// The number of pairs in the file.
+    pub pairs: u64,
+    /// The size of the file.
+    pub bytes: u64,
+}
+
+/// The progress of an export, saved after each file is completed.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) struct Checkpoint {
+    /// The version of the snapshot being exported.
+    pub version: u64,
+    pub format: ExportFormat,
+    pub start: Vec<u8>,
+    pub end: Option<Vec<u8>>,
+    /// Where the rest of the range starts.
+    pub next_key: Vec<u8>,
+    /// The names of the completed files, in order.
+    pub files: Vec<String>,
+    pub done: bool,
+}
+
+impl Checkpoint {
+    pub(crate) fn load(dir: &Path) -> Result<Option<Checkpoint>> {
+        let path = dir.join(CHECKPOINT_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let bytes = fs::read(path)?;
+        let checkpoint = serde_json::from_slice(&bytes)
+            .map_err(|e| Error::StringError(format!("export checkpoint is not readable: {e}")))?;
+        Ok(Some(checkpoint))
+    }
+
+    /// Replace the saved checkpoint, atomically.
+    fn save(&self, dir: &Path) -> Result<()> {
+        save_json(&dir.join(CHECKPOINT_FILE), self)
+    }
+}
+
+/// Write `value` to `path` as JSON, replacing the file atomically.
+pub(crate) fn save_json(path: &Path, value: &impl serde::Serialize) -> Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    fs::write(&tmp, serde_json::to_vec(value).unwrap())?;
+    fs::rename(tmp, path)?;
+    Ok(())
+}
+
+/// Export `range`, as of a snapshot, to files in `dir`, yielding each file once it is complete.
+///
+/// If `dir` holds a checkpoint of an unfinished export of the same range, the export resumes from
+/// it, at the same snapshot. Until the export finishes, a service safepoint keeps GC from removing
+/// the snapshot's data; if GC passed the snapshot while the export was stopped, resuming fails
+/// with [`Error::SnapshotTooOld`].
+pub(crate) fn export<PdC: PdClient>(
+    rpc: Arc<PdC>,
+    range: BoundRange,
+    dir: PathBuf,
+    options: ExportOptions,
+    value_format: Option<Arc<ValueFormat>>,
+    logger: Logger,
+) -> impl Stream<Item = Result<ExportedFile>> {
+    let exporter = Exporter::start(rpc, range, dir, options, value_format, logger);
+    stream::once(exporter)
+        .map_ok(|exporter| {
+            stream::try_unfold(exporter, |mut exporter| async move {
+                let file = exporter.next_file().await?;
+                Ok(file.map(|file| (file, exporter)))
+            })
+        })
+        .try_flatten()
+}
+
+struct Exporter<PdC: PdClient> {
+    rpc: Arc<PdC>,
+    snapshot: Transaction<PdC>,
+    dir: PathBuf,
+    options: ExportOptions,
+    checkpoint: Checkpoint,
+    /// Pairs read from TiKV but not yet written.
+    pending: VecDeque<KvPair>,
+    /// Where the next batch is read from, or `None` once the range has been read.
+    scan_from: Option<Key>,
+    /// When the service safepoint was last renewed, or `None` once it has been released.
+    safepoint_renewed: Option<Instant>,
+    logger: Logger,
+}
+
+impl<PdC: PdClient> Exporter<PdC> {
+    async fn start(
+        rpc: Arc<PdC>,
+        range: BoundRange,
+        dir: PathBuf,
+        options: ExportOptions,
+        value_format: Option<Arc<ValueFormat>>,
+        logger: Logger,
+    ) -> Result<Exporter<PdC>> {
+        let (start, end) = range.into_keys();
+        let start: Vec<u8> = start.into();
+        let end: Option<Vec<u8>> = end.map(Into::into);
+        let checkpoint = match Checkpoint::load(&dir)? {
+            Some(checkpoint) => {
+                if checkpoint.start != start
+                    || checkpoint.end != end
+                    || checkpoint.format != options.format
+                {
+                    return Err(Error::StringError(format!(
+                        "{} holds a different export",
+                        dir.display()
+                    )));
+                }
+                debug!(logger, "resuming export"; "files" => checkpoint.files.len());
+                checkpoint
+            }
+            None => {
+                fs::create_dir_all(&dir)?;
+                let timestamp = rpc.clone().get_timestamp().await?;
+                let checkpoint = Checkpoint {
+                    version: timestamp.version(),
+                    format: options.format,
+                    next_key: start.clone(),
+                    start,
+                    end,
+                    files: Vec::new(),
+                    done: false,
+                };
+                checkpoint.save(&dir)?;
+                checkpoint
+            }
+        };
+        let timestamp = Timestamp::from_version(checkpoint.version);
+        let snapshot_options = TransactionOptions::new_optimistic().read_only();
+        let snapshot = Transaction::new(timestamp, rpc.clone(), snapshot_options, logger.clone())
+            .with_value_format(value_format);
+        let scan_from = (!checkpoint.done).then(|| checkpoint.next_key.clone().into());
+        let mut exporter = Exporter {
+            rpc,
+            snapshot,
+            dir,
+            options,
+            checkpoint,
+            pending: VecDeque::new(),
+            scan_from,
+            safepoint_renewed: None,
+            logger,
+        };
+        if !exporter.checkpoint.done {
+            exporter.renew_safepoint().await?;
+        }
+        Ok(exporter)
+    }
+
+    /// The service protecting the snapshot from GC. Each export reads at its own timestamp, so
+    /// the version identifies the export.
+    fn service_id(&self) -> String {
+        format!("tikv-client-export-{}", self.checkpoint.version)
+    }
+
+    /// Register, or renew, the service safepoint at the snapshot's version.
+    async fn renew_safepoint(&mut self) -> Result<()> {
+        let version = self.checkpoint.version;
+        let ttl = SAFEPOINT_TTL.as_secs() as i64;
+        let min_safepoint = self
+            .rpc
+            .clone()
+            .update_service_safepoint(self.service_id(), ttl, version)
+            .await?;
+        if min_safepoint > version {
+            return Err(Error::SnapshotTooOld {
+                version,
+                safepoint: min_safepoint,
+            });
+        }
+        self.safepoint_renewed = Some(self.rpc.clock().now());
+        Ok(())
+    }
+
+    /// Release the service safepoint once the range has been read. If this fails, the safepoint
+    /// still runs out after its TTL.
+    async fn release_safepoint(&mut self) {
+        if self.safepoint_renewed.take().is_none() {
+            return;
+        }
+        let version = self.checkpoint.version;
+        let res = self
+            .rpc
+            .clone()
+            .update_service_safepoint(self.service_id(), 0, version)
+            .await;
+        if let Err(e) = res {
+            warn!(self.logger, "failed to release the export's safepoint"; "error" => %e);
+        }
+    }
+
+    /// Write the next file, or return `None` if the range has been exported.
+    async fn next_file(&mut self) -> Result<Option<ExportedFile>> {
+        let name = format!(
+            "{:08}.{}",
+            self.checkpoint.files.len(),
+            self.options.format.extension()
+        );
+        let path = self.dir.join(&name);
+        let tmp = self.dir.join(format!("{name}.tmp"));
+        let mut writer = FileWriter::create(&tmp, self.options.format)?;
+        let mut next_key = None;
+        while writer.bytes < self.options.max_file_bytes {
+            let pair = match self.next_pair().await? {
+                Some(pair) => pair,
+                None => break,
+            };
+            writer.write(&pair)?;
+            let mut key = pair.into_key();
+            key.push_zero();
+            next_key = Some(key);
+        }
+        let next_key = match next_key {
+            Some(next_key) => next_key,
+            None => {
+                fs::remove_file(tmp)?;
+                if !self.checkpoint.done {
+                    self.checkpoint.done = true;
+                    self.checkpoint.save(&self.dir)?;
+                }
+                self.release_safepoint().await;
+                return Ok(None);
+            }
+        };
+        let file = ExportedFile {
+            path: path.clone(),
+            pairs: writer.pairs,
+            bytes: writer.finish()?,
+        };
+        fs::rename(tmp, path)?;
+        self.checkpoint.files.push(name);
+        self.checkpoint.next_key = next_key.into();
+        self.checkpoint.done = self.pending.is_empty() && self.scan_from.is_none();
+        self.checkpoint.save(&self.dir)?;
+        if self.checkpoint.done {
+            self.release_safepoint().await;
+        }
+        debug!(self.logger, "exported file"; "pairs" => file.pairs, "bytes" => file.bytes);
+        Ok(Some(file))
+    }
+
+    async fn next_pair(&mut self) -> Result<Option<KvPair>> {
+        if self.pending.is_empty() {
+            let start = match self.scan_from.take() {
+                Some(start) => start,
+                None => return Ok(None),
+            };
+            let renewed = self.safepoint_renewed.expect("the range is read under a safepoint");
+            let now = self.rpc.clock().now();
+            if now.saturating_duration_since(renewed) >= SAFEPOINT_RENEW_INTERVAL {
+                self.renew_safepoint().await?;
+            }
+            let end = self.checkpoint.end.clone().map(Key::from);
+            let limit = self.options.batch_size;
+            self.pending = self.snapshot.scan((start, end), limit).await?.collect();
+            if self.pending.len() as u32 == limit {
+                let mut next = self.pending.back().unwrap().key().clone();
+                next.push_zero();
+                self.scan_from = Some(next);
+            }
+        }
+        Ok(self.pending.pop_front())
+    }
+}
+
+struct FileWriter {
+    writer: BufWriter<File>,
+    format: ExportFormat,
+    pairs: u64,
+    bytes: u64,
+}
+
+impl FileWriter {
+    fn create(path: &Path, format: ExportFormat) -> Result<FileWriter> {
+        let mut writer = FileWriter {
+            writer: BufWriter::new(File::create(path)?),
+            format,
+            pairs: 0,
+            bytes: 0,
+        };
+        if format == ExportFormat::Binary {
+            writer.write_bytes(BINARY_MAGIC)?;
+        }
+        Ok(writer)
+    }
+
+    fn write(&mut self, pair: &KvPair) -> Result<()> {
+        let key: &[u8] = pair.key().into();
+        match self.format {
+            ExportFormat::Ndjson => {
+                let record = NdjsonRecord {
+                    key: to_hex(key),
+                    value: to_hex(pair.value()),
+                };
+                let mut line = serde_json::to_vec(&record).unwrap();
+                line.push(b'\n');
+                self.write_bytes(&line)?;
+            }
+            ExportFormat::Binary => {
+                for bytes in [key, pair.value()] {
+                    self.write_bytes(&(bytes.len() as u32).to_be_bytes())?;
+                    self.write_bytes(bytes)?;
+                }
+            }
+        }
+        self.pairs += 1;
+        Ok(())
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<()> {
+        self.writer.write_all(bytes)?;
+        self.bytes += bytes.len() as u64;
+        Ok(())
+    }
+
+    /// Flush the file to disk, returning its size.
+    fn finish(self) -> Result<u64> {
+        let file = self.writer.into_inner().map_err(|e| e.into_error())?;
+        file.sync_all()?;
+        Ok(self.bytes)
+    }
+}
+
+/// A line of a file in the [ndjson](ExportFormat::Ndjson) format.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct NdjsonRecord {
+    pub key: String,
+    pub value: String,
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::Simulation;
+    use crate::MockClock;
+
+    #[tokio::test]
+    async fn test_export() {
+        let sim = Simulation::new(22);
+        sim.split(vec![50]);
+        let mut txn = sim.begin_optimistic().await.unwrap();
+        for key in 0..100u8 {
+            txn.put(vec![key], vec![key; 10]).await.unwrap();
+        }
+        txn.commit().await.unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let logger = Logger::root(slog::Discard, o!());
+        let options = ExportOptions::default()
+            .format(ExportFormat::Binary)
+            .max_file_bytes(250)
+            .batch_size(16);
+        let range = BoundRange::from(vec![10]..vec![90]);
+        let run = || {
+            let path = dir.path().to_owned();
+            let (options, logger) = (options.clone(), logger.clone());
+            export(sim.pd_client(), range.clone(), path, options, None, logger)
+        };
+
+        // Stop after the first file, and write to the range: resuming exports the same snapshot.
+        let first = run().boxed().next().await.unwrap().unwrap();
+        assert_eq!((first.pairs, first.bytes), (13, 8 + 13 * 19));
+        let mut txn = sim.begin_optimistic().await.unwrap();
+        txn.delete(vec![80]).await.unwrap();
+        txn.commit().await.unwrap();
+        let rest: Vec<_> = run().try_collect().await.unwrap();
+        assert_eq!(rest.len(), 6);
+        assert_eq!(rest.iter().map(|file| file.pairs).sum::<u64>(), 80 - 13);
+
+        let mut exported = Vec::new();
+        for file in std::iter::once(&first).chain(&rest) {
+            let bytes = fs::read(&file.path).unwrap();
+            assert_eq!(bytes.len() as u64, file.bytes);
+            let mut records = &bytes[BINARY_MAGIC.len()..];
+            while !records.is_empty() {
+                let key = records[4];
+                exported.push(key);
+                records = &records[4 + 1 + 4 + 10..];
+            }
+        }
+        assert_eq!(exported, (10..90).collect::<Vec<u8>>());
+        // A finished export yields nothing more.
+        assert_eq!(run().try_collect::<Vec<_>>().await.unwrap(), vec![]);
+        let checkpoint = Checkpoint::load(dir.path()).unwrap().unwrap();
+        assert!(checkpoint.done);
+        assert_eq!(checkpoint.files.len(), 7);
+
+        let ndjson = ExportOptions::default();
+        let path = dir.path().to_owned();
+        let other = export(sim.pd_client(), range, path, ndjson, None, logger);
+        assert!(other.boxed().next().await.unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_export_safepoint() {
+        let sim = Simulation::new(23);
+        let clock = Arc::new(MockClock::new());
+        sim.set_clock(clock.clone());
+        let advance = |duration| {
+            clock.advance(duration);
+            sim.advance_clock(duration);
+        };
+        let pd = sim.pd_client();
+        // Like TiDB's GC worker: advance GC to now, or as far as the services' safepoints allow.
+        let gc = || {
+            let pd = pd.clone();
+            async move {
+                let now = pd.clone().get_timestamp().await.unwrap().version();
+                let service_id = "gc_worker".to_owned();
+                let min = pd
+                    .clone()
+                    .update_service_safepoint(service_id, i64::MAX, now)
+                    .await
+                    .unwrap();
+                pd.update_safepoint(min).await.unwrap();
+                min
+            }
+        };
+        let mut txn = sim.begin_optimistic().await.unwrap();
+        for key in 0..10u8 {
+            txn.put(vec![key], vec![key]).await.unwrap();
+        }
+        txn.commit().await.unwrap();
+
+        let logger = Logger::root(slog::Discard, o!());
+        let options = ExportOptions::default().max_file_bytes(1).batch_size(2);
+        let run = |dir: &Path| {
+            let (options, logger) = (options.clone(), logger.clone());
+            export(pd.clone(), BoundRange::from(..), dir.to_owned(), options, None, logger)
+        };
+
+        // A finished export releases its safepoint.
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(run(dir.path()).try_collect::<Vec<_>>().await.unwrap().len(), 10);
+        let version = Checkpoint::load(dir.path()).unwrap().unwrap().version;
+        assert!(gc().await > version);
+
+        // A running export holds GC back at its snapshot, for as long as it keeps reading.
+        let dir = tempfile::tempdir().unwrap();
+        let mut files = run(dir.path()).boxed();
+        files.next().await.unwrap().unwrap();
+        let version = Checkpoint::load(dir.path()).unwrap().unwrap().version;
+        assert_eq!(gc().await, version);
+        advance(SAFEPOINT_TTL - Duration::from_secs(1));
+        files.next().await.unwrap().unwrap();
+        files.next().await.unwrap().unwrap();
+        advance(Duration::from_secs(2));
+        assert_eq!(gc().await, version);
+
+        // Once a stopped export's safepoint runs out, GC passes the snapshot, and resuming fails.
+        drop(files);
+        advance(SAFEPOINT_TTL);
+        let safepoint = gc().await;
+        assert!(safepoint > version);
+        let e = run(dir.path()).boxed().next().await.unwrap().unwrap_err();
+        match e {
+            Error::SnapshotTooOld {
+                version: snapshot,
+                safepoint: gc_safepoint,
+            } => assert_eq!((snapshot, gc_safepoint), (version, safepoint)),
+            e => panic!("unexpected error: {e:?}"),
+        }
+    }
+}
@@ -0,0 +1,333 @@
+// Copyright 2023 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Importing the files written by an export.
+
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+use futures::prelude::*;
+use serde_derive::Deserialize;
+use serde_derive::Serialize;
+
+use crate::pd::PdClient;
+use crate::transaction::export::save_json;
+use crate::transaction::export::Checkpoint;
+use crate::transaction::export::NdjsonRecord;
+use crate::transaction::export::BINARY_MAGIC;
+use crate::transaction::BulkWriter;
+use crate::transaction::ExportFormat;
+use crate::Error;
+use crate::KvPair;
+use crate::Result;
+
+/// The name of the file recording the progress of an import, in the export's directory.
+const IMPORT_CHECKPOINT_FILE: &str = "import-checkpoint.json";
+
+/// The progress of an import, reported after each file is imported.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ImportProgress {
+    /// The file just imported.
+    pub path: PathBuf,
+    /// The number of pairs in the file.
+    pub pairs: u64,
+    /// The number of files imported so far, including those imported before the import was
+    /// resumed.
+    pub files_imported: usize,
+    /// The number of files in the export.
+    pub files: usize,
+}
+
+/// The files of an export which have been imported.
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+struct ImportCheckpoint {
+    /// The version of the exported snapshot, so that the checkpoint of an earlier export in the
+    /// same directory is ignored.
+    version: u64,
+    files: Vec<String>,
+}
+
+struct Importer {
+    dir: PathBuf,
+    format: ExportFormat,
+    files: Vec<String>,
+    checkpoint: ImportCheckpoint,
+}
+
+impl<PdC: PdClient> BulkWriter<PdC> {
+    /// Write back the pairs exported to `dir` by
+    /// [`TransactionClient::export`](crate::TransactionClient::export), yielding the progress
+    /// after each file.
+    ///
+    /// The export must be complete. Files are imported in order, each in the chunks of this
+    /// writer; if a chunk fails, the import stops with its error. Progress is saved in
+    /// `dir/import-checkpoint.json`, and calling `import` again resumes from the first file which
+    /// was not completely imported. Writing a pair again is harmless, so a file which was partly
+    /// imported is simply imported again.
+    ///
+    /// Files are read with blocking IO.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use tikv_client::{Config, TransactionClient};
+    /// # use futures::prelude::*;
+    /// # futures::executor::block_on(async {
+    /// let client = TransactionClient::new(vec!["192.168.0.100"], None).await.unwrap();
+    /// let writer = client.bulk_writer().concurrency(8);
+    /// let mut progress = Box::pin(writer.import("/backup"));
+    /// while let Some(progress) = progress.try_next().await.unwrap() {
+    ///     println!("imported {} of {} files", progress.files_imported, progress.files);
+    /// }
+    /// # });
+    /// ```
+    pub fn import(
+        &self,
+        dir: impl Into<PathBuf>,
+    ) -> impl Stream<Item = Result<ImportProgress>> + '_ {
+        let importer = Importer::start(dir.into());
+        stream::once(future::ready(importer))
+            .map_ok(move |importer| {
+                stream::try_unfold(importer, move |mut importer| async move {
+                    let progress = importer.next_file(self).await?;
+                    Ok(progress.map(|progress| (progress, importer)))
+                })
+            })
+            .try_flatten()
+    }
+}
+
+impl Importer {
+    fn start(dir: PathBuf) -> Result<Importer> {
+        let export = match Checkpoint::load(&dir)? {
+            Some(export) => export,
+            None => {
+                return Err(Error::StringError(format!(
+                    "{} holds no export",
+                    dir.display()
+                )));
+            }
+        };
+        if !export.done {
+            return Err(Error::StringError(format!(
+                "the export in {} is not complete",
+                dir.display()
+            )));
+        }
+        let path = dir.join(IMPORT_CHECKPOINT_FILE);
+        let checkpoint = if path.exists() {
+            let checkpoint: ImportCheckpoint =
+                serde_json::from_slice(&fs::read(&path)?).map_err(|e| {
+                    Error::StringError(format!("import checkpoint is not readable: {e}"))
+                })?;
+            checkpoint
+        } else {
+            ImportCheckpoint::default()
+        };
+        let checkpoint = if checkpoint.version == export.version {
+            checkpoint
+        } else {
+            ImportCheckpoint {
+                version: export.version,
+                files: Vec::new(),
+            }
+        };
+        Ok(Importer {
+            dir,
+            format: export.format,
+            files: export.files,
+            checkpoint,
+        })
+    }
+
+    /// Import the next file, or return `None` if every file has been imported.
+    async fn next_file<PdC: PdClient>(
+        &mut self,
+        writer: &BulkWriter<PdC>,
+    ) -> Result<Option<ImportProgress>> {
+        let name = match self.files.get(self.checkpoint.files.len()) {
+            Some(name) => name.clone(),
+            None => return Ok(None),
+        };
+        let path = self.dir.join(&name);
+        let pairs = read_pairs(&path, self.format)?;
+        let len = pairs.len() as u64;
+        let mut chunks: Vec<_> = writer.write(stream::iter(pairs)).collect().await;
+        chunks.sort_unstable_by_key(|chunk| chunk.index);
+        if let Some(chunk) = chunks.into_iter().find(|chunk| chunk.result.is_err()) {
+            return Err(chunk.result.unwrap_err());
+        }
+        self.checkpoint.files.push(name);
+        save_json(&self.dir.join(IMPORT_CHECKPOINT_FILE), &self.checkpoint)?;
+        Ok(Some(ImportProgress {
+            path,
+            pairs: len,
+            files_imported: self.checkpoint.files.len(),
+            files: self.files.len(),
+        }))
+    }
+}
+
+/// The pairs in an exported file.
+fn read_pairs(path: &Path, format: ExportFormat) -> Result<Vec<KvPair>> {
+    let bytes = fs::read(path)?;
+    let corrupt = |message: &str| {
+        Error::StringError(format!(
+            "exported file {} is corrupt: {message}",
+            path.display()
+        ))
+    };
+    let mut pairs = Vec::new();
+    match format {
+        ExportFormat::Ndjson => {
+            for line in bytes.split(|byte| *byte == b'\n') {
+                if line.is_empty() {
+                    continue;
+                }
+                let record: NdjsonRecord =
+                    serde_json::from_slice(line).map_err(|e| corrupt(&e.to_string()))?;
+                let key = from_hex(&record.key).ok_or_else(|| corrupt("a key is not hex"))?;
+                let value = from_hex(&record.value).ok_or_else(|| corrupt("a value is not hex"))?;
+                pairs.push(KvPair::new(key, value));
+            }
+        }
+        ExportFormat::Binary => {
+            let mut records = match bytes.strip_prefix(BINARY_MAGIC) {
+                Some(records) => records,
+                None => return Err(corrupt("the header is missing")),
+            };
+            while !records.is_empty() {
+                let key = take_field(&mut records).ok_or_else(|| corrupt("a key is truncated"))?;
+                let value =
+                    take_field(&mut records).ok_or_else(|| corrupt("a value is truncated"))?;
+                pairs.push(KvPair::new(key.to_vec(), value.to_vec()));
+            }
+        }
+    }
+    Ok(pairs)
+}
+
+/// Take a field, prefixed by its length as 4 big-endian bytes, from the start of `records`.
+fn take_field<'a>(records: &mut &'a [u8]) -> Option<&'a [u8]> {
+    if records.len() < 4 {
+        return None;
+    }
+    let (len, rest) = records.split_at(4);
+    let len = u32::from_be_bytes(len.try_into().unwrap()) as usize;
+    if rest.len() < len {
+        return None;
+    }
+    let (field, rest) = rest.split_at(len);
+    *records = rest;
+    Some(field)
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 == 1 {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use slog::Logger;
+
+    use super::*;
+    use crate::simulation::Simulation;
+    use crate::transaction::export::export;
+    use crate::transaction::ExportOptions;
+    use crate::BoundRange;
+
+    #[tokio::test]
+    async fn test_import() {
+        let sim = Simulation::new(23);
+        sim.split(vec![50]);
+        let mut txn = sim.begin_optimistic().await.unwrap();
+        for key in 0..100u8 {
+            txn.put(vec![key], vec![key; key as usize % 7])
+                .await
+                .unwrap();
+        }
+        txn.commit().await.unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let logger = Logger::root(slog::Discard, o!());
+        let writer = BulkWriter::new(sim.pd_client(), logger.clone()).max_chunk_pairs(8);
+        let e = Box::pin(writer.import(dir.path())).try_next().await;
+        let e = e.unwrap_err();
+        assert!(matches!(e, Error::StringError(_)), "{e:?}");
+
+        let options = ExportOptions::default().max_file_bytes(1000);
+        let path = dir.path().to_owned();
+        let files = export(
+            sim.pd_client(),
+            BoundRange::from(..),
+            path,
+            options,
+            None,
+            logger.clone(),
+        );
+        let files: Vec<_> = files.try_collect().await.unwrap();
+        assert_eq!(files.len(), 3);
+        let mut txn = sim.begin_optimistic().await.unwrap();
+        for key in 0..100u8 {
+            txn.delete(vec![key]).await.unwrap();
+        }
+        txn.commit().await.unwrap();
+
+        // Stop after the first file: resuming imports the rest.
+        let first = Box::pin(writer.import(dir.path())).next().await;
+        let first = first.unwrap().unwrap();
+        assert_eq!((first.files_imported, first.files), (1, 3));
+        assert_eq!(first.pairs, files[0].pairs);
+        let rest: Vec<_> = writer.import(dir.path()).try_collect().await.unwrap();
+        let imported: Vec<_> = rest
+            .iter()
+            .map(|progress| progress.files_imported)
+            .collect();
+        assert_eq!(imported, vec![2, 3]);
+        let last = Box::pin(writer.import(dir.path())).try_next().await;
+        assert!(last.unwrap().is_none());
+
+        let mut txn = sim.begin_optimistic().await.unwrap();
+        let pairs: Vec<_> = txn.scan(.., 1000).await.unwrap().collect();
+        assert_eq!(pairs.len(), 100);
+        for pair in pairs {
+            let key: &[u8] = pair.key().into();
+            assert_eq!(pair.value(), &vec![key[0]; key[0] as usize % 7]);
+        }
+        txn.rollback().await.unwrap();
+    }
+
+    #[test]
+    fn test_read_pairs() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file");
+        fs::write(
+            &path,
+            b"{\"key\":\"0a\",\"value\":\"\"}\n{\"key\":\"ff00\",\"value\":\"01\"}\n",
+        )
+        .unwrap();
+        let pairs = read_pairs(&path, ExportFormat::Ndjson).unwrap();
+        let expected = vec![
+            KvPair::new(vec![10], vec![]),
+            KvPair::new(vec![255, 0], vec![1]),
+        ];
+        assert_eq!(pairs, expected);
+        fs::write(&path, b"{\"key\":\"0\",\"value\":\"\"}\n").unwrap();
+        assert!(read_pairs(&path, ExportFormat::Ndjson).is_err());
+
+        let mut bytes = BINARY_MAGIC.to_vec();
+        bytes.extend_from_slice(&[0, 0, 0, 1, 10, 0, 0, 0, 0]);
+        fs::write(&path, &bytes).unwrap();
+        let pairs = read_pairs(&path, ExportFormat::Binary).unwrap();
+        assert_eq!(pairs, vec![KvPair::new(vec![10], vec![])]);
+        fs::write(&path, &bytes[..bytes.len() - 1]).unwrap();
+        assert!(read_pairs(&path, ExportFormat::Binary).is_err());
+        assert!(read_pairs(&path, ExportFormat::Ndjson).is_err());
+    }
+}
@@ -1,8 +1,12 @@
 // Copyright 2020 TiKV Project Authors. Licensed under Apache-2.0.
 
+use std::cmp;
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::fmt;
+use std::iter;
 use std::sync::Arc;
+use std::time::Instant;
 
 use fail::fail_point;
 use log::debug;
@@ -29,10 +33,63 @@ use crate::transaction::requests::SecondaryLocksStatus;
 use crate::transaction::requests::TransactionStatus;
 use crate::transaction::requests::TransactionStatusKind;
 use crate::Error;
+use crate::Key;
 use crate::Result;
 
 const RESOLVE_LOCK_RETRY_LIMIT: usize = 10;
 
+/// An event reported to a [`LockObserver`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LockEvent {
+    /// A request found `key` locked by a live transaction, and must wait for the lock to be
+    /// released or to expire before it can succeed.
+    Blocked {
+        key: Key,
+        /// The start timestamp of the transaction holding the lock.
+        lock_ts: u64,
+        primary_lock: Key,
+    },
+    /// A request found an expired lock on `key` and resolved it.
+    Resolved {
+        key: Key,
+        /// The start timestamp of the transaction which held the lock.
+        lock_ts: u64,
+        /// The commit timestamp of that transaction, or `None` if it was rolled back.
+        commit_ts: Option<u64>,
+    },
+}
+
+/// Observes requests blocking on, and resolving, the locks of other transactions.
+///
+/// Useful for diagnosing contention. Register an observer using
+/// [`TransactionOptions::lock_observer`](crate::TransactionOptions::lock_observer). The observer
+/// is called on the task executing the request, so it should not block.
+pub trait LockObserver: Send + Sync {
+    fn on_lock_event(&self, event: LockEvent);
+}
+
+impl<F: Fn(LockEvent) + Send + Sync> LockObserver for F {
+    fn on_lock_event(&self, event: LockEvent) {
+        self(event)
+    }
+}
+
+/// A shared `LockObserver` which can be stored in `TransactionOptions`.
+#[derive(Clone)]
+pub(crate) struct LockObserverHandle(pub Arc<dyn LockObserver>);
+
+impl fmt::Debug for LockObserverHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("LockObserver")
+    }
+}
+
+impl PartialEq for LockObserverHandle {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::as_ptr(&self.0) as *const () == Arc::as_ptr(&other.0) as *const ()
+    }
+}
+
 /// _Resolves_ the given locks. Returns whether all the given locks are resolved.
 ///
 /// If a key has a lock, the latest status of the key is unknown. We need to "resolve" the lock,
@@ -40,9 +97,14 @@ const RESOLVE_LOCK_RETRY_LIMIT: usize = 10;
 /// the key. We first use `CleanupRequest` to let the status of the primary lock converge and get
 /// its status (committed or rolled back). Then, we use the status of its primary lock to determine
 /// the status of the other keys in the same transaction.
+///
+/// If `observer` is given, it is told about each lock which is resolved and each which is still
+/// live. If `deadline` is given, requests are neither sent nor waited for after it.
 pub async fn resolve_locks(
     locks: Vec<kvrpcpb::LockInfo>,
     pd_client: Arc<impl PdClient>,
+    observer: Option<&dyn LockObserver>,
+    deadline: Option<Instant>,
 ) -> Result<bool> {
     debug!("resolving locks");
     let ts = pd_client.clone().get_timestamp().await?;
@@ -52,9 +114,25 @@ pub async fn resolve_locks(
             >= lock.lock_ttl as i64;
         if !expired {
             has_live_locks = true;
+            if let Some(observer) = observer {
+                observer.on_lock_event(LockEvent::Blocked {
+                    key: lock.key.clone().into(),
+                    lock_ts: lock.lock_version,
+                    primary_lock: lock.primary_lock.clone().into(),
+                });
+            }
         }
         expired
     });
+    let report_resolved = |lock: &kvrpcpb::LockInfo, commit_version: u64| {
+        if let Some(observer) = observer {
+            observer.on_lock_event(LockEvent::Resolved {
+                key: lock.key.clone().into(),
+                lock_ts: lock.lock_version,
+                commit_ts: (commit_version != 0).then_some(commit_version),
+            });
+        }
+    };
 
     // records the commit version of each primary lock (representing the status of the transaction)
     let mut commit_versions: HashMap<u64, u64> = HashMap::new();
@@ -70,14 +148,17 @@ pub async fn resolve_locks(
             .map(|regions| regions.contains(&region_ver_id))
             .unwrap_or(false)
         {
+            report_resolved(&lock, commit_versions[&lock.lock_version]);
             continue;
         }
 
         let commit_version = match commit_versions.get(&lock.lock_version) {
             Some(&commit_version) => commit_version,
             None => {
-                let request = requests::new_cleanup_request(lock.primary_lock, lock.lock_version);
+                let request =
+                    requests::new_cleanup_request(lock.primary_lock.clone(), lock.lock_version);
                 let plan = crate::request::PlanBuilder::new(pd_client.clone(), request)
+                    .deadline(deadline)
                     .resolve_lock(OPTIMISTIC_BACKOFF)
                     .retry_multi_region(DEFAULT_REGION_BACKOFF)
                     .merge(CollectSingle)
@@ -94,8 +175,10 @@ pub async fn resolve_locks(
             lock.lock_version,
             commit_version,
             pd_client.clone(),
+            deadline,
         )
         .await?;
+        report_resolved(&lock, commit_version);
         clean_regions
             .entry(lock.lock_version)
             .or_insert_with(HashSet::new)
@@ -104,11 +187,121 @@ pub async fn resolve_locks(
     Ok(!has_live_locks)
 }
 
+/// The fate of a transaction, as found by
+/// [`TransactionClient::get_txn_status`](crate::TransactionClient::get_txn_status).
+#[derive(Clone, Debug, PartialEq)]
+pub enum TxnStatus {
+    Committed {
+        commit_ts: Timestamp,
+    },
+    RolledBack,
+    /// The transaction's primary is locked by a live transaction. Check again later.
+    Pending,
+}
+
+/// Find out whether the transaction with primary key `primary_key` and start timestamp
+/// `start_ts` committed, rolling it back if its primary lock has expired or was never written.
+///
+/// TiKV does not roll back the expired primary lock of a transaction using async commit, which
+/// committed if all its secondaries were prewritten. The secondaries are checked, and the
+/// transaction's locks resolved, as lock resolution does.
+pub(crate) async fn get_txn_status(
+    pd_client: Arc<impl PdClient>,
+    primary_key: Key,
+    start_ts: u64,
+) -> Result<TxnStatus> {
+    let primary_key: Vec<u8> = primary_key.into();
+    let current = pd_client.clone().get_timestamp().await?;
+    let mut status =
+        check_txn_status(pd_client.clone(), &primary_key, start_ts, current.clone(), false).await?;
+    status.check_ttl(current.clone());
+    let lock = match status.kind {
+        TransactionStatusKind::Committed(commit_ts) => {
+            return Ok(TxnStatus::Committed { commit_ts });
+        }
+        TransactionStatusKind::RolledBack => return Ok(TxnStatus::RolledBack),
+        TransactionStatusKind::Locked(_, lock) if lock.use_async_commit && status.is_expired => {
+            lock
+        }
+        TransactionStatusKind::Locked(..) => return Ok(TxnStatus::Pending),
+    };
+
+    let req = new_check_secondary_locks_request(lock.secondaries.clone(), start_ts);
+    let plan = crate::request::PlanBuilder::new(pd_client.clone(), req)
+        .retry_multi_region(DEFAULT_REGION_BACKOFF)
+        .extract_error()
+        .merge(Collect)
+        .plan();
+    let secondaries = plan.execute().await?;
+    let commit_version = if secondaries.fallback_2pc {
+        // A secondary was prewritten without async commit, so the primary alone decides.
+        let status = check_txn_status(pd_client, &primary_key, start_ts, current, true).await?;
+        return Ok(match status.kind {
+            TransactionStatusKind::Committed(commit_ts) => TxnStatus::Committed { commit_ts },
+            TransactionStatusKind::RolledBack => TxnStatus::RolledBack,
+            TransactionStatusKind::Locked(..) => TxnStatus::Pending,
+        });
+    } else if let Some(commit_ts) = secondaries.commit_ts {
+        commit_ts.version()
+    } else if secondaries.locked == lock.secondaries.len() {
+        cmp::max(lock.min_commit_ts, secondaries.min_commit_ts)
+    } else {
+        // A secondary which is neither locked nor committed was rolled back.
+        0
+    };
+
+    let mut clean_regions = HashSet::new();
+    for key in iter::once(primary_key).chain(lock.secondaries) {
+        let region = pd_client.region_for_key(&key.clone().into()).await?.ver_id();
+        if !clean_regions.contains(&region) {
+            let region =
+                resolve_lock_with_retry(&key, start_ts, commit_version, pd_client.clone(), None)
+                    .await?;
+            clean_regions.insert(region);
+        }
+    }
+    Ok(match commit_version {
+        0 => TxnStatus::RolledBack,
+        commit_version => TxnStatus::Committed {
+            commit_ts: Timestamp::from_version(commit_version),
+        },
+    })
+}
+
+/// Check the status of the transaction with primary key `primary_key` and start timestamp
+/// `start_ts`, rolling it back if its primary lock has expired by `current` or was never written.
+async fn check_txn_status(
+    pd_client: Arc<impl PdClient>,
+    primary_key: &[u8],
+    start_ts: u64,
+    current: Timestamp,
+    force_sync_commit: bool,
+) -> Result<TransactionStatus> {
+    // A zero `caller_start_ts` keeps TiKV from pushing the lock's min_commit_ts.
+    let req = new_check_txn_status_request(
+        primary_key.to_vec(),
+        start_ts,
+        0,
+        current.version(),
+        true,
+        force_sync_commit,
+        false,
+    );
+    let plan = crate::request::PlanBuilder::new(pd_client, req)
+        .retry_multi_region(DEFAULT_REGION_BACKOFF)
+        .merge(CollectSingle)
+        .extract_error()
+        .post_process_default()
+        .plan();
+    plan.execute().await
+}
+
 async fn resolve_lock_with_retry(
     #[allow(clippy::ptr_arg)] key: &Vec<u8>,
     start_version: u64,
     commit_version: u64,
     pd_client: Arc<impl PdClient>,
+    deadline: Option<Instant>,
 ) -> Result<RegionVerId> {
     debug!("resolving locks with retry");
     // FIXME: Add backoff
@@ -120,19 +313,24 @@ async fn resolve_lock_with_retry(
         let request = requests::new_resolve_lock_request(start_version, commit_version);
         // The only place where single-region is used
         let plan = crate::request::PlanBuilder::new(pd_client.clone(), request)
+            .deadline(deadline)
             .single_region_with_store(store)
             .await?
             .resolve_lock(Backoff::no_backoff())
             .extract_error()
             .plan();
-        match plan.execute().await {
+        let result = plan.execute().await.map_err(|e| match e.root() {
+            Error::ExtractedErrors(_) => e.into_root(),
+            _ => e,
+        });
+        match result {
             Ok(_) => {
                 return Ok(ver_id);
             }
             // Retry on region error
             Err(Error::ExtractedErrors(mut errors)) => {
                 // ResolveLockResponse can have at most 1 error
-                match errors.pop() {
+                match errors.pop().map(Error::into_root) {
                     e @ Some(Error::RegionError(_)) => {
                         error = e;
                         continue;
@@ -432,6 +630,11 @@ mod tests {
     use super::*;
     use crate::mock::MockKvClient;
     use crate::mock::MockPdClient;
+    use crate::simulation::Fault;
+    use crate::simulation::Simulation;
+    use crate::transaction::CheckLevel;
+    use crate::transaction::HeartbeatOption;
+    use crate::TransactionOptions;
 
     #[tokio::test]
     async fn test_resolve_lock_with_retry() {
@@ -453,7 +656,7 @@ mod tests {
 
         let key = vec![1];
         let region1 = MockPdClient::region1();
-        let resolved_region = resolve_lock_with_retry(&key, 1, 2, client.clone())
+        let resolved_region = resolve_lock_with_retry(&key, 1, 2, client.clone(), None)
             .await
             .unwrap();
         assert_eq!(region1.ver_id(), resolved_region);
@@ -461,8 +664,221 @@ mod tests {
         // Test resolve lock over retry limit
         fail::cfg("region-error", "10*return").unwrap();
         let key = vec![100];
-        resolve_lock_with_retry(&key, 3, 4, client)
+        resolve_lock_with_retry(&key, 3, 4, client, None)
             .await
             .expect_err("should return error");
     }
+
+    #[tokio::test]
+    async fn test_resolve_locks_observer() {
+        let client = Arc::new(MockPdClient::new(MockKvClient::with_dispatch_hook(
+            |req: &dyn Any| {
+                if req.downcast_ref::<kvrpcpb::CleanupRequest>().is_some() {
+                    let resp = kvrpcpb::CleanupResponse {
+                        commit_version: 5,
+                        ..Default::default()
+                    };
+                    Ok(Box::new(resp) as Box<dyn Any>)
+                } else {
+                    Ok(Box::<kvrpcpb::ResolveLockResponse>::default() as Box<dyn Any>)
+                }
+            },
+        )));
+        let expired = kvrpcpb::LockInfo {
+            key: vec![1],
+            primary_lock: vec![1],
+            lock_version: 1,
+            lock_ttl: 0,
+            ..Default::default()
+        };
+        let live = kvrpcpb::LockInfo {
+            key: vec![2],
+            primary_lock: vec![3],
+            lock_version: 2,
+            lock_ttl: 1000,
+            ..Default::default()
+        };
+
+        let events = std::sync::Mutex::new(Vec::new());
+        let observer = |event| events.lock().unwrap().push(event);
+        let resolved = resolve_locks(vec![expired, live], client, Some(&observer), None)
+            .await
+            .unwrap();
+        assert!(!resolved);
+        assert_eq!(*events.lock().unwrap(), vec![
+            LockEvent::Resolved {
+                key: vec![1].into(),
+                lock_ts: 1,
+                commit_ts: Some(5),
+            },
+            LockEvent::Blocked {
+                key: vec![2].into(),
+                lock_ts: 2,
+                primary_lock: vec![3].into(),
+            },
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_get_txn_status() {
+        let sim = Simulation::new(26);
+        let options = TransactionOptions::new_optimistic()
+            .drop_check(CheckLevel::None)
+            .heartbeat_option(HeartbeatOption::NoHeartbeat);
+        let undetermined_commit = |fault: Fault| {
+            sim.inject_faults(move |request| match request.label {
+                "kv_commit" => vec![fault.clone()],
+                _ => Vec::new(),
+            });
+            async {
+                let mut txn = sim.begin_with_options(options.clone()).await.unwrap();
+                txn.put(vec![1], vec![1]).await.unwrap();
+                let e = txn.commit().await.unwrap_err();
+                sim.inject_faults(|_| Vec::new());
+                match e {
+                    Error::UndeterminedError {
+                        primary_key,
+                        start_ts,
+                        ..
+                    } => (primary_key, start_ts),
+                    e => panic!("{e:?}"),
+                }
+            }
+        };
+
+        // The commit took effect, but its response was lost.
+        let (primary_key, start_ts) = undetermined_commit(Fault::DropResponse).await;
+        let status = get_txn_status(sim.pd_client(), primary_key.into(), start_ts).await;
+        match status.unwrap() {
+            TxnStatus::Committed { commit_ts } => assert!(commit_ts.version() > start_ts),
+            status => panic!("{status:?}"),
+        }
+
+        // The commit was lost: the transaction is pending until its lock expires.
+        let (primary_key, start_ts) = undetermined_commit(Fault::DropRequest).await;
+        let primary_key = Key::from(primary_key);
+        let status = get_txn_status(sim.pd_client(), primary_key.clone(), start_ts).await;
+        assert_eq!(status.unwrap(), TxnStatus::Pending);
+        sim.advance_clock(std::time::Duration::from_secs(60));
+        for _ in 0..2 {
+            let status = get_txn_status(sim.pd_client(), primary_key.clone(), start_ts).await;
+            assert_eq!(status.unwrap(), TxnStatus::RolledBack);
+        }
+
+        // A transaction which left no trace is rolled back.
+        let status = get_txn_status(sim.pd_client(), vec![2].into(), start_ts + 1).await;
+        assert_eq!(status.unwrap(), TxnStatus::RolledBack);
+    }
+
+    #[tokio::test]
+    async fn test_get_txn_status_async_commit() {
+        // The status of a transaction whose primary lock, on [1], is an expired async-commit lock
+        // with secondaries [2] and [12], in two regions. Also returns the commit versions the
+        // transaction's locks were resolved with, once per region.
+        async fn txn_status(
+            check_secondaries: fn(
+                &kvrpcpb::CheckSecondaryLocksRequest,
+            ) -> kvrpcpb::CheckSecondaryLocksResponse,
+        ) -> (TxnStatus, Vec<u64>) {
+            let resolved = Arc::new(std::sync::Mutex::new(Vec::new()));
+            let resolved_by_hook = resolved.clone();
+            let hook = move |req: &dyn Any| {
+                if let Some(req) = req.downcast_ref::<kvrpcpb::CheckTxnStatusRequest>() {
+                    let resp = if req.force_sync_commit {
+                        kvrpcpb::CheckTxnStatusResponse {
+                            commit_version: 30,
+                            ..Default::default()
+                        }
+                    } else {
+                        kvrpcpb::CheckTxnStatusResponse {
+                            lock_info: Some(kvrpcpb::LockInfo {
+                                primary_lock: vec![1],
+                                key: vec![1],
+                                lock_version: 10,
+                                use_async_commit: true,
+                                secondaries: vec![vec![2], vec![12]],
+                                min_commit_ts: 15,
+                                ..Default::default()
+                            }),
+                            ..Default::default()
+                        }
+                    };
+                    Ok(Box::new(resp) as Box<dyn Any>)
+                } else if let Some(req) = req.downcast_ref::<kvrpcpb::CheckSecondaryLocksRequest>()
+                {
+                    Ok(Box::new(check_secondaries(req)) as Box<dyn Any>)
+                } else if let Some(req) = req.downcast_ref::<kvrpcpb::ResolveLockRequest>() {
+                    resolved_by_hook.lock().unwrap().push(req.commit_version);
+                    Ok(Box::<kvrpcpb::ResolveLockResponse>::default() as Box<dyn Any>)
+                } else {
+                    unreachable!()
+                }
+            };
+            let client = Arc::new(MockPdClient::new(MockKvClient::with_dispatch_hook(hook)));
+            let status = get_txn_status(client, vec![1].into(), 10).await.unwrap();
+            let resolved = resolved.lock().unwrap().clone();
+            (status, resolved)
+        }
+
+        fn secondary_lock(key: &[u8], use_async_commit: bool) -> kvrpcpb::LockInfo {
+            kvrpcpb::LockInfo {
+                key: key.to_vec(),
+                lock_version: 10,
+                use_async_commit,
+                min_commit_ts: 20,
+                ..Default::default()
+            }
+        }
+
+        // Every secondary was prewritten, so the transaction committed.
+        let (status, resolved) = txn_status(|req| kvrpcpb::CheckSecondaryLocksResponse {
+            locks: req.keys.iter().map(|key| secondary_lock(key, true)).collect(),
+            ..Default::default()
+        })
+        .await;
+        assert_eq!(status, TxnStatus::Committed {
+            commit_ts: Timestamp::from_version(20)
+        });
+        assert_eq!(resolved, vec![20, 20]);
+
+        // A secondary was committed already.
+        let (status, resolved) = txn_status(|req| match req.keys[0].as_slice() {
+            [12] => kvrpcpb::CheckSecondaryLocksResponse {
+                commit_ts: 18,
+                ..Default::default()
+            },
+            key => kvrpcpb::CheckSecondaryLocksResponse {
+                locks: vec![secondary_lock(key, true)],
+                ..Default::default()
+            },
+        })
+        .await;
+        assert_eq!(status, TxnStatus::Committed {
+            commit_ts: Timestamp::from_version(18)
+        });
+        assert_eq!(resolved, vec![18, 18]);
+
+        // A secondary was never prewritten, and is rolled back by the check.
+        let (status, resolved) = txn_status(|req| match req.keys[0].as_slice() {
+            [12] => kvrpcpb::CheckSecondaryLocksResponse::default(),
+            key => kvrpcpb::CheckSecondaryLocksResponse {
+                locks: vec![secondary_lock(key, true)],
+                ..Default::default()
+            },
+        })
+        .await;
+        assert_eq!(status, TxnStatus::RolledBack);
+        assert_eq!(resolved, vec![0, 0]);
+
+        // A secondary fell back to two-phase commit, so the primary decides.
+        let (status, resolved) = txn_status(|req| kvrpcpb::CheckSecondaryLocksResponse {
+            locks: req.keys.iter().map(|key| secondary_lock(key, false)).collect(),
+            ..Default::default()
+        })
+        .await;
+        assert_eq!(status, TxnStatus::Committed {
+            commit_ts: Timestamp::from_version(30)
+        });
+        assert!(resolved.is_empty());
+    }
 }
@@ -26,6 +26,10 @@ pub fn new_get_request(key: Key, timestamp: Timestamp) -> kvrpcpb::GetRequest {
     requests::new_get_request(key.into(), timestamp.version())
 }
 
+pub fn new_mvcc_get_by_key_request(key: Key) -> kvrpcpb::MvccGetByKeyRequest {
+    requests::new_mvcc_get_by_key_request(key.into())
+}
+
 pub fn new_batch_get_request(
     keys: impl Iterator<Item = Key>,
     timestamp: Timestamp,
@@ -0,0 +1,179 @@
+// Copyright 2023 TiKV Project Authors. Licensed under Apache-2.0.
+
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+use crate::BoundRange;
+use crate::Key;
+use crate::KvPair;
+use crate::Value;
+
+/// Merges writes which have not been committed with the pairs a scan reads from a snapshot, so
+/// that the scan reads its own writes.
+///
+/// This is how a [`Transaction`](crate::Transaction) scans, in either direction, over its
+/// buffered writes. Wrappers which buffer or cache writes of their own can use it to scan over
+/// them the same way:
+///
+/// 1. record writes with [`put`](MergedScanner::put) and [`delete`](MergedScanner::delete),
+/// 2. read [`fetch_limit`](MergedScanner::fetch_limit) pairs of the range from the snapshot, which
+///    is more than the limit of the scan, since some of the pairs read may have been deleted,
+/// 3. [`merge`](MergedScanner::merge) the pairs read with the writes.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// # use tikv_client::{BoundRange, MergedScanner, TransactionClient, TransactionOptions};
+/// # use futures::prelude::*;
+/// # futures::executor::block_on(async {
+/// let client = TransactionClient::new(vec!["192.168.0.100"], None).await.unwrap();
+/// let timestamp = client.current_timestamp().await.unwrap();
+/// let mut snapshot = client.snapshot(timestamp, TransactionOptions::new_optimistic());
+/// let mut writes = MergedScanner::default();
+/// writes.put(b"k1".to_vec(), b"v1".to_vec());
+/// writes.delete(b"k2".to_vec());
+///
+/// let range: BoundRange = (b"k".to_vec()..b"l".to_vec()).into();
+/// let fetch_limit = writes.fetch_limit(&range, 10);
+/// let read = snapshot.scan_reverse(range.clone(), fetch_limit).await.unwrap();
+/// let pairs = writes.merge(&range, read.collect(), 10, true);
+/// # });
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MergedScanner {
+    /// The value written to each key, or `None` if it was deleted.
+    writes: BTreeMap<Key, Option<Value>>,
+}
+
+impl MergedScanner {
+    /// Record that `value` was written to `key`, replacing any previous write of `key`.
+    pub fn put(&mut self, key: impl Into<Key>, value: impl Into<Value>) {
+        self.writes.insert(key.into(), Some(value.into()));
+    }
+
+    /// Record that `key` was deleted, replacing any previous write of `key`.
+    pub fn delete(&mut self, key: impl Into<Key>) {
+        self.writes.insert(key.into(), None);
+    }
+
+    /// Whether `key` was written, i.e., put or deleted.
+    pub fn is_written(&self, key: &Key) -> bool {
+        self.writes.contains_key(key)
+    }
+
+    /// How many pairs of `range` to read from the snapshot, so that merging them returns the
+    /// first `limit` pairs of the range.
+    pub fn fetch_limit(&self, range: &BoundRange, limit: u32) -> u32 {
+        let deleted = self
+            .writes
+            .range(range.clone())
+            .filter(|(_, value)| value.is_none())
+            .count();
+        limit.saturating_add(deleted as u32)
+    }
+
+    /// Merge `pairs`, read from the snapshot in `range`, with the writes in `range`, returning at
+    /// most `limit` pairs, in descending order of keys if `reverse`, and ascending order
+    /// otherwise.
+    ///
+    /// `pairs` may be in any order, but must be the first pairs of the range in the order of the
+    /// scan, and at least [`fetch_limit`](MergedScanner::fetch_limit) of them unless the range
+    /// has fewer pairs.
+    pub fn merge(
+        &self,
+        range: &BoundRange,
+        mut pairs: Vec<KvPair>,
+        limit: u32,
+        reverse: bool,
+    ) -> Vec<KvPair> {
+        let order = |a: &Key, b: &Key| if reverse { b.cmp(a) } else { a.cmp(b) };
+        pairs.sort_by(|a, b| order(a.key(), b.key()));
+        let mut writes: Vec<_> = self.writes.range(range.clone()).collect();
+        if reverse {
+            writes.reverse();
+        }
+
+        let mut pairs = pairs.into_iter().peekable();
+        let mut writes = writes.into_iter().peekable();
+        let mut merged = Vec::new();
+        while merged.len() < limit as usize {
+            let next = match (pairs.peek(), writes.peek()) {
+                (None, None) => break,
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some(pair), Some((key, _))) => order(pair.key(), key),
+            };
+            if next == Ordering::Less {
+                merged.extend(pairs.next());
+                continue;
+            }
+            // The key was written, so what was read of it, if anything, is overridden.
+            if next == Ordering::Equal {
+                pairs.next();
+            }
+            if let Some((key, Some(value))) = writes.next() {
+                merged.push(KvPair::new(key.clone(), value.clone()));
+            }
+        }
+        merged
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::collection::btree_map;
+    use proptest::prelude::*;
+
+    use super::*;
+
+    fn key() -> impl Strategy<Value = Key> {
+        (0..16u8).prop_map(|key| vec![key].into())
+    }
+
+    proptest! {
+        #[test]
+        fn test_merged_scanner(
+            snapshot in btree_map(key(), any::<u8>(), 0..16),
+            writes in btree_map(key(), any::<Option<u8>>(), 0..16),
+            start in 0..16u8,
+            len in 0..17u8,
+            limit in 0..20u32,
+            reverse in any::<bool>(),
+        ) {
+            let range: BoundRange = match start + len {
+                end if end >= 16 => (vec![start]..).into(),
+                end => (vec![start]..vec![end]).into(),
+            };
+            let mut scanner = MergedScanner::default();
+            let mut expected = snapshot.clone();
+            for (key, value) in writes {
+                match value {
+                    Some(value) => {
+                        scanner.put(key.clone(), vec![value]);
+                        expected.insert(key, value);
+                    }
+                    None => {
+                        scanner.delete(key.clone());
+                        expected.remove(&key);
+                    }
+                }
+            }
+            let scan = |map: &BTreeMap<Key, u8>, limit: u32| -> Vec<KvPair> {
+                let pairs = map.range(range.clone());
+                let pairs: Vec<_> = if reverse {
+                    pairs.rev().take(limit as usize).collect()
+                } else {
+                    pairs.take(limit as usize).collect()
+                };
+                pairs
+                    .into_iter()
+                    .map(|(key, value)| KvPair::new(key.clone(), vec![*value]))
+                    .collect()
+            };
+
+            let read = scan(&snapshot, scanner.fetch_limit(&range, limit));
+            let merged = scanner.merge(&range, read, limit, reverse);
+            prop_assert_eq!(merged, scan(&expected, limit));
+        }
+    }
+}
@@ -8,25 +8,62 @@
 //!
 //! **Warning:** It is not advisable to use both raw and transactional functionality in the same keyspace.
 
+pub use buffer::BufferObserver;
+pub(crate) use buffer::BufferObserverHandle;
+pub use bulk_writer::BulkWriteChunk;
+pub use bulk_writer::BulkWriter;
+pub use checkpointed_scan::CheckpointedScan;
+pub use checkpointed_scan::ScanCheckpoint;
+pub use chunked::VALUE_CHUNK_SIZE;
 pub use client::Client;
+pub use export::ExportFormat;
+pub use export::ExportOptions;
+pub use export::ExportedFile;
+pub use import::ImportProgress;
 pub(crate) use lock::resolve_locks;
 pub(crate) use lock::HasLocks;
+pub(crate) use lock::LockObserverHandle;
+pub use merge::MergedScanner;
+pub(crate) use read_cache::ReadCache;
+pub use participant::Participant;
+pub use shutdown::CloseReport;
 pub use snapshot::Snapshot;
+pub use state::BufferedMutation;
+pub use state::MutationKind;
+pub use state::TransactionState;
 pub use transaction::CheckLevel;
+pub use transaction::CommitStats;
 #[doc(hidden)]
 pub use transaction::HeartbeatOption;
+pub use transaction::PrimaryKeyStrategy;
+pub use transaction::ScanForUpdate;
 pub use transaction::Transaction;
 pub use transaction::TransactionOptions;
+pub use watch::KeyChange;
 
 mod buffer;
+mod bulk_writer;
+mod checkpointed_scan;
+mod chunked;
 mod client;
+mod export;
+mod import;
 pub mod lowering;
 #[macro_use]
 mod requests;
 mod lock;
+mod merge;
+mod participant;
+mod read_cache;
+pub use lock::LockEvent;
+pub use lock::LockObserver;
 pub use lock::LockResolver;
 pub use lock::ResolveLocksContext;
 pub use lock::ResolveLocksOptions;
+pub use lock::TxnStatus;
+mod shutdown;
 mod snapshot;
+mod state;
 #[allow(clippy::module_inception)]
 mod transaction;
+mod watch;
@@ -0,0 +1,262 @@
+// Copyright 2023 TiKV Project Authors. Licensed under Apache-2.0.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use slog::Logger;
+
+use crate::pd::PdClient;
+use crate::pd::PdRpcClient;
+use crate::request::CollectError;
+use crate::request::Plan;
+use crate::request::PlanBuilder;
+use crate::request::RetryOptions;
+use crate::transaction::lowering::*;
+use crate::transaction::transaction::MAX_TTL;
+use crate::transaction::BufferedMutation;
+use crate::value_format::ValueFormat;
+use crate::Key;
+use crate::Result;
+use crate::Timestamp;
+
+/// One participant's part of a transaction whose two-phase commit is driven by an external
+/// coordinator.
+///
+/// A [`Transaction`](crate::Transaction) runs Percolator two-phase commit itself. A
+/// `Participant` instead exposes the steps of the protocol, so that a coordinator can run a
+/// single transaction across several clients (e.g., in different services):
+///
+/// 1. the coordinator picks a start timestamp and a primary key, and creates a participant with
+///    that timestamp on each client,
+/// 2. each participant [prewrites](Participant::prewrite) its mutations, naming the same primary
+///    key,
+/// 3. once every prewrite succeeded, the coordinator picks a commit timestamp and
+///    [commits](Participant::commit_keys) the primary key; the transaction is committed once
+///    this succeeds,
+/// 4. participants commit their remaining keys with the same commit timestamp.
+///
+/// If any prewrite fails, participants [roll back](Participant::rollback_keys) their keys
+/// instead. The coordinator is responsible for making sure its participants finish before their
+/// locks expire; expired locks may be rolled back by other transactions.
+///
+/// Create a participant with
+/// [`TransactionClient::participant`](crate::TransactionClient::participant).
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// # use tikv_client::{BufferedMutation, Config, TransactionClient};
+/// # use futures::prelude::*;
+/// # futures::executor::block_on(async {
+/// let client = TransactionClient::new(vec!["192.168.0.100"], None).await.unwrap();
+/// let start_ts = client.current_timestamp().await.unwrap();
+/// let participant = client.participant(start_ts);
+/// participant
+///     .prewrite(
+///         b"primary".to_vec(),
+///         vec![BufferedMutation::Put {
+///             key: b"primary".to_vec(),
+///             value: b"value".to_vec(),
+///         }],
+///     )
+///     .await
+///     .unwrap();
+/// let commit_ts = client.current_timestamp().await.unwrap();
+/// participant
+///     .commit_keys(vec![b"primary".to_vec()], commit_ts)
+///     .await
+///     .unwrap();
+/// # });
+/// ```
+pub struct Participant<PdC: PdClient = PdRpcClient> {
+    start_ts: Timestamp,
+    rpc: Arc<PdC>,
+    retry_options: RetryOptions,
+    lock_ttl: Duration,
+    value_format: Option<Arc<ValueFormat>>,
+    logger: Logger,
+}
+
+impl<PdC: PdClient> Participant<PdC> {
+    pub(crate) fn new(start_ts: Timestamp, rpc: Arc<PdC>, logger: Logger) -> Participant<PdC> {
+        Participant {
+            start_ts,
+            rpc,
+            retry_options: RetryOptions::default_optimistic(),
+            lock_ttl: Duration::from_millis(MAX_TTL),
+            value_format: None,
+            logger,
+        }
+    }
+
+    /// Encode the values of prewritten mutations with `format`.
+    pub(crate) fn with_value_format(mut self, format: Option<Arc<ValueFormat>>) -> Self {
+        self.value_format = format;
+        self
+    }
+
+    /// Set the retry options used by this participant's requests.
+    #[must_use]
+    pub fn retry_options(mut self, retry_options: RetryOptions) -> Self {
+        self.retry_options = retry_options;
+        self
+    }
+
+    /// Set the TTL of locks written by [`prewrite`](Participant::prewrite).
+    ///
+    /// The default is 20 seconds.
+    #[must_use]
+    pub fn lock_ttl(mut self, lock_ttl: Duration) -> Self {
+        self.lock_ttl = lock_ttl;
+        self
+    }
+
+    /// The start timestamp of the transaction.
+    pub fn start_ts(&self) -> &Timestamp {
+        &self.start_ts
+    }
+
+    /// Lock `mutations` and write them as uncommitted data, pointing at `primary`.
+    ///
+    /// Every participant of a transaction must use the same primary key, and exactly one of them
+    /// must prewrite it.
+    pub async fn prewrite(
+        &self,
+        primary: impl Into<Key>,
+        mutations: impl IntoIterator<Item = BufferedMutation>,
+    ) -> Result<()> {
+        debug!(self.logger, "invoking participant prewrite request");
+        let mut mutations = mutations
+            .into_iter()
+            .map(BufferedMutation::into_proto)
+            .collect::<Vec<_>>();
+        if mutations.is_empty() {
+            return Ok(());
+        }
+        if let Some(format) = &self.value_format {
+            format.encode_mutations(&mut mutations)?;
+        }
+        let request = new_prewrite_request(
+            mutations,
+            primary.into(),
+            self.start_ts.clone(),
+            self.lock_ttl.as_millis() as u64,
+        );
+        let plan = PlanBuilder::new(self.rpc.clone(), request)
+            .resolve_lock(self.retry_options.lock_backoff.clone())
+            .retry_multi_region(self.retry_options.region_backoff.clone())
+            .merge(CollectError)
+            .extract_error()
+            .plan();
+        plan.execute().await?;
+        Ok(())
+    }
+
+    /// Commit prewritten `keys` at `commit_ts`.
+    ///
+    /// The primary key must be committed before any other key.
+    pub async fn commit_keys(
+        &self,
+        keys: impl IntoIterator<Item = impl Into<Key>>,
+        commit_ts: Timestamp,
+    ) -> Result<()> {
+        debug!(self.logger, "invoking participant commit request");
+        let keys = keys.into_iter().map(Into::into).collect::<Vec<Key>>();
+        if keys.is_empty() {
+            return Ok(());
+        }
+        let request = new_commit_request(keys.into_iter(), self.start_ts.clone(), commit_ts);
+        let plan = PlanBuilder::new(self.rpc.clone(), request)
+            .resolve_lock(self.retry_options.lock_backoff.clone())
+            .retry_multi_region(self.retry_options.region_backoff.clone())
+            .extract_error()
+            .plan();
+        plan.execute().await?;
+        Ok(())
+    }
+
+    /// Roll back `keys`, removing any locks and data prewritten for them.
+    ///
+    /// Rolling back a key which was never prewritten prevents it from being prewritten later.
+    pub async fn rollback_keys(&self, keys: impl IntoIterator<Item = impl Into<Key>>) -> Result<()> {
+        debug!(self.logger, "invoking participant rollback request");
+        let keys = keys.into_iter().map(Into::into).collect::<Vec<Key>>();
+        if keys.is_empty() {
+            return Ok(());
+        }
+        let request = new_batch_rollback_request(keys.into_iter(), self.start_ts.clone());
+        let plan = PlanBuilder::new(self.rpc.clone(), request)
+            .resolve_lock(self.retry_options.lock_backoff.clone())
+            .retry_multi_region(self.retry_options.region_backoff.clone())
+            .extract_error()
+            .plan();
+        plan.execute().await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::any::Any;
+    use std::sync::Arc;
+
+    use slog::Drain;
+    use slog::Logger;
+    use tikv_client_proto::kvrpcpb;
+
+    use super::*;
+    use crate::mock::MockKvClient;
+    use crate::mock::MockPdClient;
+    use crate::TimestampExt;
+
+    #[tokio::test]
+    async fn test_participant() {
+        let pd_client = Arc::new(MockPdClient::new(MockKvClient::with_dispatch_hook(
+            |req: &dyn Any| {
+                if let Some(req) = req.downcast_ref::<kvrpcpb::PrewriteRequest>() {
+                    assert_eq!(req.start_version, 10);
+                    assert_eq!(req.primary_lock, b"primary".to_vec());
+                    assert_eq!(req.lock_ttl, 5000);
+                    assert_eq!(req.mutations.len(), 2);
+                    Ok(Box::<kvrpcpb::PrewriteResponse>::default() as Box<dyn Any>)
+                } else if let Some(req) = req.downcast_ref::<kvrpcpb::CommitRequest>() {
+                    assert_eq!(req.start_version, 10);
+                    assert_eq!(req.commit_version, 11);
+                    Ok(Box::<kvrpcpb::CommitResponse>::default() as Box<dyn Any>)
+                } else if let Some(req) = req.downcast_ref::<kvrpcpb::BatchRollbackRequest>() {
+                    assert_eq!(req.start_version, 10);
+                    Ok(Box::<kvrpcpb::BatchRollbackResponse>::default() as Box<dyn Any>)
+                } else {
+                    unreachable!()
+                }
+            },
+        )));
+        let logger = Logger::root(slog::Discard.fuse(), o!());
+        let participant = Participant::new(Timestamp::from_version(10), pd_client, logger)
+            .lock_ttl(Duration::from_secs(5));
+
+        participant
+            .prewrite(
+                b"primary".to_vec(),
+                vec![
+                    BufferedMutation::Put {
+                        key: b"primary".to_vec(),
+                        value: b"value".to_vec(),
+                    },
+                    BufferedMutation::Delete {
+                        key: b"secondary".to_vec(),
+                    },
+                ],
+            )
+            .await
+            .unwrap();
+        participant
+            .commit_keys(vec![b"primary".to_vec()], Timestamp::from_version(11))
+            .await
+            .unwrap();
+        participant
+            .rollback_keys(vec![b"secondary".to_vec()])
+            .await
+            .unwrap();
+    }
+}
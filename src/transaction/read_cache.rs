@@ -0,0 +1,119 @@
+// Copyright 2023 TiKV Project Authors. Licensed under Apache-2.0.
+
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::Key;
+use crate::Value;
+
+/// A bounded cache of the values read by the transactions of a client, keyed by the key and the
+/// timestamp it was read at.
+///
+/// A key's value at a timestamp never changes, so entries don't go stale. They are still dropped
+/// when a transaction of the client writes the key, so that the cache never holds a value the
+/// client knows to be overwritten. Once the cache is full, the least recently used entry is
+/// evicted.
+pub(crate) struct ReadCache {
+    capacity: usize,
+    state: Mutex<ReadCacheState>,
+}
+
+#[derive(Default)]
+struct ReadCacheState {
+    /// The cached values of each key, by the version they were read at, and when they were last
+    /// used.
+    entries: HashMap<Key, HashMap<u64, (Option<Value>, u64)>>,
+    /// The entries, ordered by when they were last used.
+    recency: BTreeMap<u64, (Key, u64)>,
+    next_use: u64,
+}
+
+impl ReadCache {
+    pub(crate) fn new(capacity: usize) -> ReadCache {
+        ReadCache {
+            capacity: capacity.max(1),
+            state: Mutex::new(ReadCacheState::default()),
+        }
+    }
+
+    /// The value of `key` at `version`, or `None` if it is not cached.
+    pub(crate) fn get(&self, key: &Key, version: u64) -> Option<Option<Value>> {
+        let mut state = self.state.lock().unwrap();
+        let next_use = state.next_use;
+        let (value, last_use) = state.entries.get_mut(key)?.get_mut(&version)?;
+        let value = value.clone();
+        let previous_use = std::mem::replace(last_use, next_use);
+        state.next_use += 1;
+        let entry = state.recency.remove(&previous_use);
+        state.recency.insert(next_use, entry.unwrap());
+        Some(value)
+    }
+
+    pub(crate) fn insert(&self, key: Key, version: u64, value: Option<Value>) {
+        let mut guard = self.state.lock().unwrap();
+        let state = &mut *guard;
+        let next_use = state.next_use;
+        state.next_use += 1;
+        let versions = state.entries.entry(key.clone()).or_default();
+        if let Some((_, previous_use)) = versions.insert(version, (value, next_use)) {
+            state.recency.remove(&previous_use);
+        }
+        state.recency.insert(next_use, (key, version));
+        while state.recency.len() > self.capacity {
+            let (_, (key, version)) = state.recency.pop_first().unwrap();
+            state.remove(&key, version);
+        }
+    }
+
+    /// Drop the cached values of `key`, at every version.
+    pub(crate) fn invalidate(&self, key: &Key) {
+        let mut guard = self.state.lock().unwrap();
+        let state = &mut *guard;
+        if let Some(versions) = state.entries.remove(key) {
+            for (_, last_use) in versions.into_values() {
+                state.recency.remove(&last_use);
+            }
+        }
+    }
+}
+
+impl ReadCacheState {
+    fn remove(&mut self, key: &Key, version: u64) {
+        if let Some(versions) = self.entries.get_mut(key) {
+            versions.remove(&version);
+            if versions.is_empty() {
+                self.entries.remove(key);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_cache() {
+        let cache = ReadCache::new(3);
+        let key = |k: u8| Key::from(vec![k]);
+        cache.insert(key(1), 10, Some(vec![1]));
+        cache.insert(key(1), 20, None);
+        cache.insert(key(2), 10, Some(vec![2]));
+        assert_eq!(cache.get(&key(1), 10), Some(Some(vec![1])));
+        assert_eq!(cache.get(&key(1), 20), Some(None));
+        assert_eq!(cache.get(&key(1), 30), None);
+
+        // The least recently used entry is evicted.
+        cache.insert(key(3), 10, Some(vec![3]));
+        assert_eq!(cache.get(&key(2), 10), None);
+        assert_eq!(cache.get(&key(1), 10), Some(Some(vec![1])));
+
+        cache.invalidate(&key(1));
+        assert_eq!(cache.get(&key(1), 10), None);
+        assert_eq!(cache.get(&key(1), 20), None);
+        assert_eq!(cache.get(&key(3), 10), Some(Some(vec![3])));
+        let state = cache.state.lock().unwrap();
+        assert_eq!((state.entries.len(), state.recency.len()), (1, 1));
+    }
+}
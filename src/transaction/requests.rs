@@ -38,6 +38,9 @@ use crate::store::RegionStore;
 use crate::timestamp::TimestampExt;
 use crate::transaction::HasLocks;
 use crate::util::iter::FlatMapOkIterExt;
+use crate::Error;
+use crate::ErrorContext;
+use crate::Key;
 use crate::KvPair;
 use crate::Result;
 use crate::Value;
@@ -115,6 +118,32 @@ impl Process<kvrpcpb::GetResponse> for DefaultProcessor {
     }
 }
 
+pub fn new_mvcc_get_by_key_request(key: Vec<u8>) -> kvrpcpb::MvccGetByKeyRequest {
+    let mut req = kvrpcpb::MvccGetByKeyRequest::default();
+    req.key = key;
+    req
+}
+
+impl KvRequest for kvrpcpb::MvccGetByKeyRequest {
+    type Response = kvrpcpb::MvccGetByKeyResponse;
+}
+
+shardable_key!(kvrpcpb::MvccGetByKeyRequest);
+collect_first!(kvrpcpb::MvccGetByKeyResponse);
+impl SingleKey for kvrpcpb::MvccGetByKeyRequest {
+    fn key(&self) -> &Vec<u8> {
+        &self.key
+    }
+}
+
+impl Process<kvrpcpb::MvccGetByKeyResponse> for DefaultProcessor {
+    type Out = kvrpcpb::MvccInfo;
+
+    fn process(&self, input: Result<kvrpcpb::MvccGetByKeyResponse>) -> Result<Self::Out> {
+        Ok(input?.info.unwrap_or_default())
+    }
+}
+
 pub fn new_batch_get_request(keys: Vec<Vec<u8>>, timestamp: u64) -> kvrpcpb::BatchGetRequest {
     let mut req = kvrpcpb::BatchGetRequest::default();
     req.keys = keys;
@@ -128,14 +157,44 @@ impl KvRequest for kvrpcpb::BatchGetRequest {
 
 shardable_keys!(kvrpcpb::BatchGetRequest);
 
+/// The pairs found by a batch get, and the errors TiKV returned for the keys it could not read.
+pub struct BatchGetPairs {
+    pub pairs: Vec<KvPair>,
+    pub key_errors: Vec<(Key, Error)>,
+}
+
+impl BatchGetPairs {
+    /// The pairs found, or, if some keys could not be read, an error naming each of them.
+    pub fn into_pairs(self) -> Result<Vec<KvPair>> {
+        if self.key_errors.is_empty() {
+            return Ok(self.pairs);
+        }
+        let errors = self.key_errors.into_iter().map(|(key, e)| {
+            e.with_context(ErrorContext {
+                operation: Some("kv_batch_get"),
+                key: Some(key.into()),
+                ..Default::default()
+            })
+        });
+        Err(Error::MultipleKeyErrors(errors.collect()))
+    }
+}
+
 impl Merge<kvrpcpb::BatchGetResponse> for Collect {
-    type Out = Vec<KvPair>;
+    type Out = BatchGetPairs;
 
     fn merge(&self, input: Vec<Result<kvrpcpb::BatchGetResponse>>) -> Result<Self::Out> {
-        input
-            .into_iter()
-            .flat_map_ok(|resp| resp.pairs.into_iter().map(Into::into))
-            .collect()
+        let mut pairs = Vec::new();
+        let mut key_errors = Vec::new();
+        for resp in input {
+            for mut pair in resp?.pairs {
+                match pair.error.take() {
+                    Some(e) => key_errors.push((pair.key.into(), Error::KeyError(Box::new(e)))),
+                    None => pairs.push(pair.into()),
+                }
+            }
+        }
+        Ok(BatchGetPairs { pairs, key_errors })
     }
 }
 
@@ -166,7 +225,7 @@ impl KvRequest for kvrpcpb::ScanRequest {
     type Response = kvrpcpb::ScanResponse;
 }
 
-shardable_range!(kvrpcpb::ScanRequest);
+shardable_range!(kvrpcpb::ScanRequest, reverse);
 
 impl Merge<kvrpcpb::ScanResponse> for Collect {
     type Out = Vec<KvPair>;
@@ -781,6 +840,7 @@ impl Merge<kvrpcpb::CheckSecondaryLocksResponse> for Collect {
             commit_ts: None,
             min_commit_ts: 0,
             fallback_2pc: false,
+            locked: 0,
         };
         for resp in input {
             let resp = resp?;
@@ -790,6 +850,7 @@ impl Merge<kvrpcpb::CheckSecondaryLocksResponse> for Collect {
                     return Ok(out);
                 }
                 out.min_commit_ts = cmp::max(out.min_commit_ts, lock.min_commit_ts);
+                out.locked += 1;
             }
             out.commit_ts = match (
                 out.commit_ts.take(),
@@ -812,6 +873,8 @@ pub struct SecondaryLocksStatus {
     pub commit_ts: Option<Timestamp>,
     pub min_commit_ts: u64,
     pub fallback_2pc: bool,
+    // The number of the keys checked which are still locked.
+    pub locked: usize,
 }
 
 pair_locks!(kvrpcpb::BatchGetResponse);
@@ -826,6 +889,8 @@ error_locks!(kvrpcpb::CheckSecondaryLocksResponse);
 
 impl HasLocks for kvrpcpb::CleanupResponse {}
 
+impl HasLocks for kvrpcpb::MvccGetByKeyResponse {}
+
 impl HasLocks for kvrpcpb::ScanLockResponse {
     fn take_locks(&mut self) -> Vec<LockInfo> {
         std::mem::take(&mut self.locks)
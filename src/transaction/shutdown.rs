@@ -0,0 +1,156 @@
+// Copyright 2023 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Closing a transactional client.
+
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::Weak;
+use std::time::Duration;
+
+use tokio::sync::Notify;
+use tokio::sync::RwLock;
+use tokio_util::sync::CancellationToken;
+
+use crate::transaction::transaction::TransactionStatus;
+use crate::Error;
+use crate::Result;
+
+/// What came of closing a [`TransactionClient`](crate::TransactionClient).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CloseReport {
+    /// The optimistic transactions which had not started committing, and were rolled back.
+    pub rolled_back: usize,
+    /// The commits still in flight once the timeout passed. The client no longer keeps their
+    /// locks alive, so other clients resolve them once their TTLs expire.
+    pub unfinished_commits: usize,
+}
+
+/// The transactions of a client, tracked so that the client can be closed.
+#[derive(Default)]
+pub(crate) struct TransactionRegistry {
+    closed: AtomicBool,
+    /// Cancelled once the client has closed, stopping the heartbeats of its transactions.
+    stopped: CancellationToken,
+    /// The statuses of the client's optimistic transactions.
+    optimistic: Mutex<Vec<Weak<RwLock<TransactionStatus>>>>,
+    /// How many commits are in flight.
+    committing: AtomicUsize,
+    commit_finished: Notify,
+}
+
+impl TransactionRegistry {
+    /// Fail if the client has been closed.
+    pub(crate) fn check_open(&self) -> Result<()> {
+        if self.closed.load(Ordering::SeqCst) {
+            return Err(Error::ClientClosed);
+        }
+        Ok(())
+    }
+
+    /// Track the optimistic transaction with `status`, so that it is rolled back if it is still
+    /// active when the client closes.
+    pub(crate) fn register_optimistic(&self, status: &Arc<RwLock<TransactionStatus>>) {
+        let mut optimistic = self.optimistic.lock().unwrap();
+        optimistic.retain(|status| status.strong_count() > 0);
+        optimistic.push(Arc::downgrade(status));
+    }
+
+    /// Count a commit as in flight until the returned guard is dropped.
+    pub(crate) fn start_commit(self: &Arc<Self>) -> CommitGuard {
+        self.committing.fetch_add(1, Ordering::SeqCst);
+        CommitGuard(self.clone())
+    }
+
+    /// A token which is cancelled once the client has closed.
+    pub(crate) fn stopped(&self) -> CancellationToken {
+        self.stopped.clone()
+    }
+
+    /// Refuse new transactions, wait up to `timeout` for the commits in flight, roll back the
+    /// optimistic transactions which are still active, and stop heartbeats.
+    pub(crate) async fn close(&self, timeout: Duration) -> CloseReport {
+        self.closed.store(true, Ordering::SeqCst);
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            let finished = self.commit_finished.notified();
+            if self.committing.load(Ordering::SeqCst) == 0 {
+                break;
+            }
+            if tokio::time::timeout_at(deadline, finished).await.is_err() {
+                break;
+            }
+        }
+
+        let statuses: Vec<_> = self
+            .optimistic
+            .lock()
+            .unwrap()
+            .drain(..)
+            .filter_map(|status| status.upgrade())
+            .collect();
+        let mut rolled_back = 0;
+        for status in statuses {
+            let mut status = status.write().await;
+            // Nothing has been written to TiKV before a transaction starts committing.
+            if *status == TransactionStatus::Active {
+                *status = TransactionStatus::Rolledback;
+                rolled_back += 1;
+            }
+        }
+        self.stopped.cancel();
+        CloseReport {
+            rolled_back,
+            unfinished_commits: self.committing.load(Ordering::SeqCst),
+        }
+    }
+}
+
+/// Counts a commit as in flight while it is alive.
+pub(crate) struct CommitGuard(Arc<TransactionRegistry>);
+
+impl Drop for CommitGuard {
+    fn drop(&mut self) {
+        self.0.committing.fetch_sub(1, Ordering::SeqCst);
+        self.0.commit_finished.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_close() {
+        let registry = Arc::new(TransactionRegistry::default());
+        let active = Arc::new(RwLock::new(TransactionStatus::Active));
+        let committed = Arc::new(RwLock::new(TransactionStatus::Committed));
+        registry.register_optimistic(&active);
+        registry.register_optimistic(&committed);
+        assert!(registry.check_open().is_ok());
+
+        // Closing waits for the commit in flight.
+        let commit = registry.start_commit();
+        let finish_commit = async {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            drop(commit);
+        };
+        let (report, ()) = tokio::join!(registry.close(Duration::from_secs(10)), finish_commit);
+        assert_eq!(report, CloseReport {
+            rolled_back: 1,
+            unfinished_commits: 0,
+        });
+        assert!(*active.read().await == TransactionStatus::Rolledback);
+        assert!(*committed.read().await == TransactionStatus::Committed);
+        assert!(matches!(registry.check_open(), Err(Error::ClientClosed)));
+        assert!(registry.stopped().is_cancelled());
+
+        // Commits are waited for no longer than the timeout.
+        let registry = Arc::new(TransactionRegistry::default());
+        let _commit = registry.start_commit();
+        let report = registry.close(Duration::from_millis(10)).await;
+        assert_eq!(report.unfinished_commits, 1);
+    }
+}
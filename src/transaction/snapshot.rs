@@ -1,12 +1,17 @@
 // Copyright 2019 TiKV Project Authors. Licensed under Apache-2.0.
 
 use derive_new::new;
+use futures::Stream;
 use slog::Logger;
+use tokio_util::sync::CancellationToken;
 
+use crate::pd::PdClient;
+use crate::pd::PdRpcClient;
 use crate::BoundRange;
 use crate::Key;
 use crate::KvPair;
 use crate::Result;
+use crate::ScanPacing;
 use crate::Transaction;
 use crate::Value;
 
@@ -18,12 +23,12 @@ use crate::Value;
 ///
 /// See the [Transaction](struct@crate::Transaction) docs for more information on the methods.
 #[derive(new)]
-pub struct Snapshot {
-    transaction: Transaction,
+pub struct Snapshot<PdC: PdClient = PdRpcClient> {
+    transaction: Transaction<PdC>,
     logger: Logger,
 }
 
-impl Snapshot {
+impl<PdC: PdClient> Snapshot<PdC> {
     /// Get the value associated with the given key.
     pub async fn get(&mut self, key: impl Into<Key>) -> Result<Option<Value>> {
         debug!(self.logger, "invoking get request on snapshot");
@@ -37,12 +42,58 @@ impl Snapshot {
     }
 
     /// Get the values associated with the given keys.
+    ///
+    /// The result has one entry for each distinct key, in the order in which the keys first appear
+    /// in `keys`. The entry of a key holds its value, `None` if the key does not exist, or the error
+    /// TiKV returned when reading it, so one unreadable key does not hide the values of the others.
+    ///
+    /// The request as a whole fails if it could not be completed, for example if a region could not
+    /// be reached, or if TiKV returned an error without saying which key it is for.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use tikv_client::{Config, TransactionClient, TransactionOptions};
+    /// # futures::executor::block_on(async {
+    /// # let client = TransactionClient::new(vec!["192.168.0.100"], None).await.unwrap();
+    /// # let timestamp = client.current_timestamp().await.unwrap();
+    /// let mut snapshot = client.snapshot(timestamp, TransactionOptions::new_optimistic());
+    /// let keys = vec!["TiKV".to_owned(), "TiDB".to_owned()];
+    /// for (key, entry) in snapshot.batch_get(keys).await.unwrap() {
+    ///     match entry {
+    ///         Ok(Some(value)) => println!("{:?} = {:?}", key, value),
+    ///         Ok(None) => println!("{:?} does not exist", key),
+    ///         Err(e) => println!("{:?} could not be read: {}", key, e),
+    ///     }
+    /// }
+    /// # });
+    /// ```
     pub async fn batch_get(
         &mut self,
         keys: impl IntoIterator<Item = impl Into<Key>>,
-    ) -> Result<impl Iterator<Item = KvPair>> {
+    ) -> Result<Vec<(Key, Result<Option<Value>>)>> {
         debug!(self.logger, "invoking batch_get request on snapshot");
-        self.transaction.batch_get(keys).await
+        self.transaction.batch_get_entries(keys).await
+    }
+
+    /// Get the values of `keys`, in the order of the keys.
+    pub async fn batch_get_values(
+        &mut self,
+        keys: impl IntoIterator<Item = impl Into<Key>>,
+    ) -> Result<Vec<Option<Value>>> {
+        debug!(self.logger, "invoking batch_get_values request on snapshot");
+        self.transaction.batch_get_values(keys).await
+    }
+
+    /// Get the values of `keys`, and scan each of `ranges`, in one round of requests.
+    pub async fn multi_get(
+        &mut self,
+        keys: impl IntoIterator<Item = impl Into<Key>>,
+        ranges: impl IntoIterator<Item = impl Into<BoundRange>>,
+        each_limit: u32,
+    ) -> Result<(Vec<KvPair>, Vec<Vec<KvPair>>)> {
+        debug!(self.logger, "invoking multi_get request on snapshot");
+        self.transaction.multi_get(keys, ranges, each_limit).await
     }
 
     /// Scan a range, return at most `limit` key-value pairs that lying in the range.
@@ -55,6 +106,32 @@ impl Snapshot {
         self.transaction.scan(range, limit).await
     }
 
+    /// Scan the keys which start with `prefix`, return at most `limit` key-value pairs.
+    pub async fn scan_prefix(
+        &mut self,
+        prefix: impl Into<Key>,
+        limit: u32,
+    ) -> Result<impl Iterator<Item = KvPair>> {
+        debug!(self.logger, "invoking scan_prefix request on snapshot");
+        self.transaction.scan_prefix(prefix, limit).await
+    }
+
+    /// Count the keys in `range`, without transferring their values.
+    pub async fn count(&mut self, range: impl Into<BoundRange>) -> Result<usize> {
+        debug!(self.logger, "invoking count request on snapshot");
+        self.transaction.count(range).await
+    }
+
+    /// Scan all of `range`, reading at most as fast as `pacing` allows.
+    pub fn scan_paced(
+        &mut self,
+        range: impl Into<BoundRange>,
+        pacing: ScanPacing,
+    ) -> impl Stream<Item = Result<KvPair>> + '_ {
+        debug!(self.logger, "invoking paced scan request on snapshot");
+        self.transaction.scan_paced(range, pacing)
+    }
+
     /// Scan a range, return at most `limit` keys that lying in the range.
     pub async fn scan_keys(
         &mut self,
@@ -87,4 +164,23 @@ impl Snapshot {
         );
         self.transaction.scan_keys_reverse(range, limit).await
     }
+
+    /// Get the first key-value pair in `range`, if any.
+    pub async fn first(&mut self, range: impl Into<BoundRange>) -> Result<Option<KvPair>> {
+        debug!(self.logger, "invoking first request on snapshot");
+        self.transaction.first(range).await
+    }
+
+    /// Get the last key-value pair in `range`, if any.
+    pub async fn last(&mut self, range: impl Into<BoundRange>) -> Result<Option<KvPair>> {
+        debug!(self.logger, "invoking last request on snapshot");
+        self.transaction.last(range).await
+    }
+
+    /// Abort reads on this snapshot when `token` is cancelled.
+    ///
+    /// See [`Transaction::set_cancellation_token`] for details.
+    pub fn set_cancellation_token(&mut self, token: CancellationToken) {
+        self.transaction.set_cancellation_token(token);
+    }
 }
@@ -0,0 +1,78 @@
+// Copyright 2023 TiKV Project Authors. Licensed under Apache-2.0.
+
+use serde_derive::Deserialize;
+use serde_derive::Serialize;
+use tikv_client_proto::kvrpcpb;
+
+use crate::Value;
+
+/// The state of an active transaction, exported so that it can be finished by another client,
+/// possibly in another process.
+///
+/// Created by [`Transaction::export_state`](crate::Transaction::export_state), and turned back
+/// into a transaction by
+/// [`TransactionClient::import_transaction`](crate::TransactionClient::import_transaction).
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct TransactionState {
+    pub start_ts: u64,
+    /// The transaction's `for_update_ts` if it is pessimistic, `None` if it is optimistic.
+    pub for_update_ts: Option<u64>,
+    pub primary_key: Option<Vec<u8>>,
+    pub mutations: Vec<BufferedMutation>,
+}
+
+/// A write or lock buffered by a transaction which has not been committed.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BufferedMutation {
+    Put { key: Vec<u8>, value: Value },
+    Insert { key: Vec<u8>, value: Value },
+    Delete { key: Vec<u8> },
+    /// The key is locked; in a pessimistic transaction, the lock is already held in TiKV.
+    Lock { key: Vec<u8> },
+    CheckNotExists { key: Vec<u8> },
+}
+
+/// The kind of a mutation buffered by a transaction, as listed by
+/// [`Transaction::pending_mutations`](crate::Transaction::pending_mutations).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MutationKind {
+    Put,
+    /// A put which fails if the key already exists.
+    Insert,
+    Delete,
+    Lock,
+    CheckNotExists,
+}
+
+impl BufferedMutation {
+    pub(crate) fn into_proto(self) -> kvrpcpb::Mutation {
+        let mut pb = kvrpcpb::Mutation::default();
+        match self {
+            BufferedMutation::Put { key, value } => {
+                pb.op = kvrpcpb::Op::Put.into();
+                pb.key = key;
+                pb.value = value;
+            }
+            BufferedMutation::Insert { key, value } => {
+                pb.op = kvrpcpb::Op::Insert.into();
+                pb.key = key;
+                pb.value = value;
+            }
+            BufferedMutation::Delete { key } => {
+                pb.op = kvrpcpb::Op::Del.into();
+                pb.key = key;
+            }
+            BufferedMutation::Lock { key } => {
+                pb.op = kvrpcpb::Op::Lock.into();
+                pb.key = key;
+            }
+            BufferedMutation::CheckNotExists { key } => {
+                pb.op = kvrpcpb::Op::CheckNotExists.into();
+                pb.key = key;
+            }
+        }
+        pb
+    }
+}
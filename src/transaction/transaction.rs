@@ -1,5 +1,7 @@
 // Copyright 2019 TiKV Project Authors. Licensed under Apache-2.0.
 
+use std::collections::HashMap;
+use std::collections::HashSet;
 use std::iter;
 use std::sync::Arc;
 use std::time::Instant;
@@ -7,16 +9,23 @@ use std::time::Instant;
 use derive_new::new;
 use fail::fail_point;
 use futures::prelude::*;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 use slog::Logger;
 use tikv_client_proto::kvrpcpb;
 use tikv_client_proto::pdpb::Timestamp;
 use tokio::sync::RwLock;
 use tokio::time::Duration;
+use tokio_util::sync::CancellationToken;
 
+use crate::audit::AuditOperation;
+use crate::audit::AuditOutcome;
 use crate::backoff::Backoff;
 use crate::backoff::DEFAULT_REGION_BACKOFF;
 use crate::pd::PdClient;
 use crate::pd::PdRpcClient;
+use crate::rate_limit::paced_scan;
+use crate::region::RegionWithLeader;
 use crate::request::Collect;
 use crate::request::CollectError;
 use crate::request::CollectSingle;
@@ -24,15 +33,41 @@ use crate::request::CollectWithShard;
 use crate::request::Plan;
 use crate::request::PlanBuilder;
 use crate::request::RetryOptions;
+use crate::request::RetryStats;
+use crate::spawner::spawn;
+use crate::stats::observe_commit_phase;
+use crate::stats::observe_transaction;
 use crate::timestamp::TimestampExt;
+use crate::timestamp_pool::TimestampPool;
 use crate::transaction::buffer::Buffer;
 use crate::transaction::lowering::*;
+use crate::transaction::requests::new_check_txn_status_request;
+use crate::transaction::requests::BatchGetPairs;
+use crate::transaction::requests::TransactionStatusKind;
+use crate::transaction::resolve_locks;
+use crate::transaction::shutdown::TransactionRegistry;
+use crate::transaction::BufferObserver;
+use crate::transaction::BufferObserverHandle;
+use crate::transaction::BufferedMutation;
+use crate::transaction::LockObserver;
+use crate::transaction::LockObserverHandle;
+use crate::transaction::MutationKind;
+use crate::transaction::ReadCache;
+use crate::transaction::TransactionState;
+use crate::value_format::decode_pairs;
+use crate::value_format::decode_value;
+use crate::value_format::ValueFormat;
 use crate::BoundRange;
+use crate::ConflictKind;
 use crate::Error;
+use crate::FanOutTracer;
+use crate::JsonCodec;
 use crate::Key;
 use crate::KvPair;
 use crate::Result;
+use crate::ScanPacing;
 use crate::Value;
+use crate::ValueCodec;
 
 /// An undo-able set of actions on the dataset.
 ///
@@ -82,9 +117,22 @@ pub struct Transaction<PdC: PdClient = PdRpcClient> {
     options: TransactionOptions,
     is_heartbeat_started: bool,
     start_instant: Instant,
+    commit_stats: Option<CommitStats>,
+    // The commit timestamp sent for the primary by an earlier call to `commit` which failed.
+    primary_commit_ts: Option<Timestamp>,
+    commit_hooks: Vec<CommitHook>,
+    rollback_hooks: Vec<RollbackHook>,
+    cancellation_token: Option<CancellationToken>,
+    read_cache: Option<Arc<ReadCache>>,
+    value_format: Option<Arc<ValueFormat>>,
+    registry: Option<Arc<TransactionRegistry>>,
+    timestamp_pool: Option<Arc<TimestampPool>>,
     logger: Logger,
 }
 
+type CommitHook = Box<dyn FnOnce(Option<Timestamp>) + Send + Sync>;
+type RollbackHook = Box<dyn FnOnce() + Send + Sync>;
+
 impl<PdC: PdClient> Transaction<PdC> {
     pub(crate) fn new(
         timestamp: Timestamp,
@@ -97,18 +145,92 @@ impl<PdC: PdClient> Transaction<PdC> {
         } else {
             TransactionStatus::Active
         };
+        let start_instant = rpc.clock().now();
+        let logger = match &options.label {
+            Some(label) => logger.new(o!("label" => label.clone())),
+            None => logger,
+        };
         Transaction {
             status: Arc::new(RwLock::new(status)),
             timestamp,
-            buffer: Buffer::new(options.is_pessimistic()),
+            buffer: Buffer::new(options.is_pessimistic())
+                .with_observer(options.buffer_size_observer()),
             rpc,
             options,
             is_heartbeat_started: false,
-            start_instant: std::time::Instant::now(),
+            start_instant,
+            commit_stats: None,
+            primary_commit_ts: None,
+            commit_hooks: Vec::new(),
+            rollback_hooks: Vec::new(),
+            cancellation_token: None,
+            read_cache: None,
+            value_format: None,
+            registry: None,
+            timestamp_pool: None,
             logger,
         }
     }
 
+    /// Read values through `cache`, shared with other transactions of the client.
+    pub(crate) fn with_read_cache(mut self, cache: Option<Arc<ReadCache>>) -> Self {
+        self.read_cache = cache;
+        self
+    }
+
+    /// Encode the values the transaction writes, and decode the values it reads, with `format`.
+    pub(crate) fn with_value_format(mut self, format: Option<Arc<ValueFormat>>) -> Self {
+        self.value_format = format;
+        self
+    }
+
+    /// Track the transaction in `registry`, so that closing the client stops it.
+    pub(crate) fn with_registry(mut self, registry: Option<Arc<TransactionRegistry>>) -> Self {
+        if let Some(registry) = &registry {
+            if !self.options.read_only && !self.is_pessimistic() {
+                registry.register_optimistic(&self.status);
+            }
+        }
+        self.registry = registry;
+        self
+    }
+
+    /// Report the transaction's commit to `pool`, the client's pool of start timestamps, so that
+    /// transactions begun after the commit see its writes.
+    pub(crate) fn with_timestamp_pool(mut self, pool: Option<Arc<TimestampPool>>) -> Self {
+        self.timestamp_pool = pool;
+        self
+    }
+
+    /// Recreate a transaction from the state exported by [`export_state`](Transaction::export_state).
+    pub(crate) fn from_state(
+        state: TransactionState,
+        rpc: Arc<PdC>,
+        mut options: TransactionOptions,
+        logger: Logger,
+    ) -> Result<Transaction<PdC>> {
+        match (state.for_update_ts, &options.kind) {
+            (Some(for_update_ts), TransactionKind::Pessimistic(_)) if !options.read_only => {
+                options.kind = TransactionKind::Pessimistic(Timestamp::from_version(for_update_ts));
+            }
+            (None, TransactionKind::Optimistic) if !options.read_only => {}
+            _ => return Err(Error::InvalidTransactionType),
+        }
+        let mut txn = Transaction::new(
+            Timestamp::from_version(state.start_ts),
+            rpc,
+            options,
+            logger,
+        );
+        txn.buffer = Buffer::import_mutations(
+            txn.is_pessimistic(),
+            state.primary_key.map(Into::into),
+            state.mutations,
+        )
+        .with_observer(txn.options.buffer_size_observer());
+        Ok(txn)
+    }
+
     /// Create a new 'get' request
     ///
     /// Once resolved this request will result in the fetching of the value associated with the
@@ -134,19 +256,88 @@ impl<PdC: PdClient> Transaction<PdC> {
         let rpc = self.rpc.clone();
         let key = key.into();
         let retry_options = self.options.retry_options.clone();
+        let lock_observer = self.options.observer();
+        let deadline = self.deadline();
+        let resource_group_tag = self.options.resource_group_tag();
+        let fan_out_tracer = self.options.fan_out_tracer.clone();
+        let cancellation_token = self.cancellation_token.clone();
+        let read_cache = self.read_cache.clone();
+        let value_format = self.value_format.clone();
 
-        self.buffer
-            .get_or_else(key, |key| async move {
-                let request = new_get_request(key, timestamp);
+        let start = self.rpc.clock().now();
+        let result = self
+            .buffer
+            .get_or_else(key.clone(), |key| async move {
+                let version = timestamp.version();
+                if let Some(value) = read_cache.as_ref().and_then(|c| c.get(&key, version)) {
+                    return Ok(value);
+                }
+                let request = new_get_request(key.clone(), timestamp);
                 let plan = PlanBuilder::new(rpc, request)
+                    .resource_group_tag(resource_group_tag)
+                    .trace_fan_out(fan_out_tracer)
+                    .deadline(deadline)
+                    .observe_locks(lock_observer)
                     .resolve_lock(retry_options.lock_backoff)
                     .retry_multi_region(DEFAULT_REGION_BACKOFF)
                     .merge(CollectSingle)
                     .post_process_default()
                     .plan();
-                plan.execute().await
+                let value = cancellable(cancellation_token.as_ref(), plan.execute()).await?;
+                let format = value_format.as_deref();
+                let value = value.map(|v| decode_value(format, &key, v)).transpose()?;
+                if let Some(cache) = read_cache {
+                    cache.insert(key, version, value.clone());
+                }
+                Ok(value)
             })
-            .await
+            .await;
+        if let Some(audit) = self.rpc.audit_log() {
+            let outcome = match &result {
+                Ok(Some(_)) => AuditOutcome::Found,
+                Ok(None) => AuditOutcome::NotFound,
+                Err(_) => AuditOutcome::Failed,
+            };
+            audit.record(AuditOperation::Get, &key, outcome);
+        }
+        self.track_hot_keys([&key], start, &result);
+        result
+    }
+
+    /// Create a new 'get' request for a value stored as JSON, and deserialize it.
+    ///
+    /// See [`get`](Transaction::get) and [`put_json`](Transaction::put_json).
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use tikv_client::{Config, TransactionClient};
+    /// # use futures::prelude::*;
+    /// # futures::executor::block_on(async {
+    /// # let client = TransactionClient::new(vec!["192.168.0.100", "192.168.0.101"], None).await.unwrap();
+    /// let mut txn = client.begin_optimistic().await.unwrap();
+    /// let ids: Option<Vec<u64>> = txn.get_json("ids".to_owned()).await.unwrap();
+    /// # });
+    /// ```
+    pub async fn get_json<T: Serialize + DeserializeOwned>(
+        &mut self,
+        key: impl Into<Key>,
+    ) -> Result<Option<T>> {
+        self.get_with_codec(key, &JsonCodec).await
+    }
+
+    /// Create a new 'get' request, and decode the value using `codec`.
+    ///
+    /// See [`get`](Transaction::get) and [`ValueCodec`].
+    pub async fn get_with_codec<T>(
+        &mut self,
+        key: impl Into<Key>,
+        codec: &impl ValueCodec<T>,
+    ) -> Result<Option<T>> {
+        self.get(key)
+            .await?
+            .map(|value| codec.decode(value))
+            .transpose()
     }
 
     /// Create a `get for update` request.
@@ -233,7 +424,11 @@ impl<PdC: PdClient> Transaction<PdC> {
     /// given keys.
     ///
     /// Non-existent entries will not appear in the result. The order of the keys is not retained in
-    /// the result.
+    /// the result, and duplicate keys are only fetched, and returned, once. Use
+    /// [`batch_get_values`](Transaction::batch_get_values) for results in the order of the keys.
+    ///
+    /// If TiKV could not read some of the keys, the request fails with an
+    /// [`Error::MultipleKeyErrors`] holding the error of each of them, with the key in its context.
     ///
     /// # Examples
     ///
@@ -249,7 +444,6 @@ impl<PdC: PdClient> Transaction<PdC> {
     ///     .batch_get(keys)
     ///     .await
     ///     .unwrap()
-    ///     .map(|pair| (pair.0, pair.1))
     ///     .collect();
     /// // Finish the transaction...
     /// txn.commit().await.unwrap();
@@ -260,24 +454,272 @@ impl<PdC: PdClient> Transaction<PdC> {
         keys: impl IntoIterator<Item = impl Into<Key>>,
     ) -> Result<impl Iterator<Item = KvPair>> {
         debug!(self.logger, "invoking transactional batch_get request");
+        let keys: Vec<Key> = keys.into_iter().map(Into::into).collect();
+        let start = self.rpc.clock().now();
+        let result = self
+            .batch_get_inner(keys.clone())
+            .await
+            .and_then(BatchGetPairs::into_pairs);
+        if let Some(audit) = self.rpc.audit_log() {
+            audit.record_reads(AuditOperation::BatchGet, &keys, &result);
+        }
+        self.track_hot_keys(&keys, start, &result);
+        result.map(Vec::into_iter)
+    }
+
+    /// Get the value of each of `keys`, reporting the keys which could not be read separately.
+    ///
+    /// Returns one entry for each distinct key, in the order in which the keys first appear. The
+    /// entry of a key holds its value, `None` if it does not exist, or the error TiKV returned
+    /// for it. The whole read fails if it could not be completed, or if TiKV returned an error
+    /// without saying which key it is for.
+    pub(crate) async fn batch_get_entries(
+        &mut self,
+        keys: impl IntoIterator<Item = impl Into<Key>>,
+    ) -> Result<Vec<(Key, Result<Option<Value>>)>> {
+        let mut seen = HashSet::new();
+        let keys: Vec<Key> = keys
+            .into_iter()
+            .map(Into::into)
+            .filter(|key| seen.insert(key.clone()))
+            .collect();
+        let entries = self.batch_get_inner(keys.clone()).await.map(|read| {
+            let pairs = read.pairs.into_iter().map(|pair| (pair.0, pair.1));
+            let mut values: HashMap<Key, Value> = pairs.collect();
+            let mut key_errors: HashMap<Key, Error> = read.key_errors.into_iter().collect();
+            keys.iter()
+                .map(|key| {
+                    let entry = match key_errors.remove(key) {
+                        Some(e) => Err(e),
+                        None => Ok(values.remove(key)),
+                    };
+                    (key.clone(), entry)
+                })
+                .collect::<Vec<_>>()
+        });
+        if let Some(audit) = self.rpc.audit_log() {
+            match &entries {
+                Ok(entries) => {
+                    for (key, entry) in entries {
+                        let outcome = match entry {
+                            Ok(Some(_)) => AuditOutcome::Found,
+                            Ok(None) => AuditOutcome::NotFound,
+                            Err(_) => AuditOutcome::Failed,
+                        };
+                        audit.record(AuditOperation::BatchGet, key, outcome);
+                    }
+                }
+                Err(_) => {
+                    for key in &keys {
+                        audit.record(AuditOperation::BatchGet, key, AuditOutcome::Failed);
+                    }
+                }
+            }
+        }
+        entries
+    }
+
+    /// Read `keys`, returning the pairs found and the errors TiKV returned for individual keys.
+    async fn batch_get_inner(&mut self, keys: Vec<Key>) -> Result<BatchGetPairs> {
         self.check_allow_operation().await?;
         let timestamp = self.timestamp.clone();
         let rpc = self.rpc.clone();
         let retry_options = self.options.retry_options.clone();
+        let lock_observer = self.options.observer();
+        let deadline = self.deadline();
+        let resource_group_tag = self.options.resource_group_tag();
+        let fan_out_tracer = self.options.fan_out_tracer.clone();
+        let cancellation_token = self.cancellation_token.clone();
+        let read_cache = self.read_cache.clone();
+        let value_format = self.value_format.clone();
 
-        self.buffer
-            .batch_get_or_else(keys.into_iter().map(|k| k.into()), move |keys| async move {
-                let request = new_batch_get_request(keys, timestamp);
+        let mut key_errors = Vec::new();
+        let errors = &mut key_errors;
+        let pairs = self
+            .buffer
+            .batch_get_or_else(keys.into_iter(), move |keys| async move {
+                let version = timestamp.version();
+                let mut cached = Vec::new();
+                let mut uncached = Vec::new();
+                for key in keys {
+                    match read_cache.as_ref().and_then(|c| c.get(&key, version)) {
+                        Some(Some(value)) => cached.push(KvPair(key, value)),
+                        Some(None) => {}
+                        None => uncached.push(key),
+                    }
+                }
+                if uncached.is_empty() {
+                    return Ok(cached);
+                }
+                let request = new_batch_get_request(uncached.clone().into_iter(), timestamp);
                 let plan = PlanBuilder::new(rpc, request)
+                    .resource_group_tag(resource_group_tag)
+                    .trace_fan_out(fan_out_tracer)
+                    .deadline(deadline)
+                    .observe_locks(lock_observer)
                     .resolve_lock(retry_options.lock_backoff)
                     .retry_multi_region(retry_options.region_backoff)
                     .merge(Collect)
                     .plan();
-                plan.execute()
-                    .await
-                    .map(|r| r.into_iter().map(Into::into).collect())
+                let read = cancellable(cancellation_token.as_ref(), plan.execute()).await?;
+                let fetched = decode_pairs(value_format.as_deref(), read.pairs)?;
+                if let Some(cache) = read_cache {
+                    let mut values: HashMap<&Key, &Value> =
+                        fetched.iter().map(|pair| (&pair.0, &pair.1)).collect();
+                    let failed: HashSet<&Key> =
+                        read.key_errors.iter().map(|(key, _)| key).collect();
+                    // A key which could not be read must not be cached as missing.
+                    for key in uncached.into_iter().filter(|key| !failed.contains(key)) {
+                        let value = values.remove(&key).cloned();
+                        cache.insert(key, version, value);
+                    }
+                }
+                errors.extend(read.key_errors);
+                cached.extend(fetched);
+                Ok(cached)
             })
-            .await
+            .await?
+            .collect();
+        Ok(BatchGetPairs { pairs, key_errors })
+    }
+
+    /// Get the values of `keys`, in the order of the keys.
+    ///
+    /// Similar to [`batch_get`](Transaction::batch_get), but the `i`th value returned is the value
+    /// of the `i`th key, or `None` if the key does not exist. Duplicate keys are only fetched once,
+    /// and their value is returned at each of their positions.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use tikv_client::{Config, TransactionClient};
+    /// # use futures::prelude::*;
+    /// # futures::executor::block_on(async {
+    /// # let client = TransactionClient::new(vec!["192.168.0.100", "192.168.0.101"], None).await.unwrap();
+    /// let mut txn = client.begin_optimistic().await.unwrap();
+    /// let keys = vec!["TiKV".to_owned(), "TiDB".to_owned(), "TiKV".to_owned()];
+    /// let values = txn.batch_get_values(keys).await.unwrap();
+    /// assert_eq!(values[0], values[2]);
+    /// // Finish the transaction...
+    /// txn.commit().await.unwrap();
+    /// # });
+    /// ```
+    pub async fn batch_get_values(
+        &mut self,
+        keys: impl IntoIterator<Item = impl Into<Key>>,
+    ) -> Result<Vec<Option<Value>>> {
+        debug!(
+            self.logger,
+            "invoking transactional batch_get_values request"
+        );
+        let keys: Vec<Key> = keys.into_iter().map(Into::into).collect();
+        let values: HashMap<Key, Value> = self.batch_get(keys.clone()).await?.collect();
+        Ok(keys.iter().map(|key| values.get(key).cloned()).collect())
+    }
+
+    /// Get the values of `keys`, and scan each of `ranges`, in one round of requests.
+    ///
+    /// The key and range requests are grouped by region and sent concurrently, rather than one
+    /// after another, so a page built from both point reads and range reads costs a single round
+    /// trip. All reads are at the transaction's start timestamp, and see its buffered writes.
+    ///
+    /// Returns the pairs of the keys which exist, as [`batch_get`](Transaction::batch_get) does,
+    /// and, for each range, at most `each_limit` pairs in it, ordered by key.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use tikv_client::{BoundRange, Config, TransactionClient};
+    /// # use futures::prelude::*;
+    /// # futures::executor::block_on(async {
+    /// # let client = TransactionClient::new(vec!["192.168.0.100", "192.168.0.101"], None).await.unwrap();
+    /// let mut txn = client.begin_optimistic().await.unwrap();
+    /// let keys = vec!["user/1".to_owned(), "user/1/profile".to_owned()];
+    /// let ranges = vec![BoundRange::prefix("user/1/posts/".to_owned())];
+    /// let (user, posts) = txn.multi_get(keys, ranges, 20).await.unwrap();
+    /// // Finish the transaction...
+    /// txn.commit().await.unwrap();
+    /// # });
+    /// ```
+    pub async fn multi_get(
+        &mut self,
+        keys: impl IntoIterator<Item = impl Into<Key>>,
+        ranges: impl IntoIterator<Item = impl Into<BoundRange>>,
+        each_limit: u32,
+    ) -> Result<(Vec<KvPair>, Vec<Vec<KvPair>>)> {
+        debug!(self.logger, "invoking transactional multi_get request");
+        self.check_allow_operation().await?;
+        let keys: Vec<Key> = keys.into_iter().map(Into::into).collect();
+        let ranges: Vec<BoundRange> = ranges.into_iter().map(Into::into).collect();
+        let retry_options = self.options.retry_options.clone();
+        let deadline = self.deadline();
+
+        // Fetch everything first, then combine it with the buffer as the single-request reads do.
+        let get = {
+            let request = new_batch_get_request(keys.clone().into_iter(), self.timestamp.clone());
+            let plan = PlanBuilder::new(self.rpc.clone(), request)
+                .resource_group_tag(self.options.resource_group_tag())
+                .trace_fan_out(self.options.fan_out_tracer.clone())
+                .deadline(deadline)
+                .observe_locks(self.options.observer())
+                .resolve_lock(retry_options.lock_backoff.clone())
+                .retry_multi_region(retry_options.region_backoff.clone())
+                .merge(Collect)
+                .plan();
+            let empty = keys.is_empty();
+            async move {
+                if empty {
+                    return Ok(BatchGetPairs {
+                        pairs: Vec::new(),
+                        key_errors: Vec::new(),
+                    });
+                }
+                plan.execute().await
+            }
+        };
+        let scans = ranges.iter().map(|range| {
+            let limit = self.buffer.scan_fetch_limit(range, each_limit);
+            let request =
+                new_scan_request(range.clone(), self.timestamp.clone(), limit, false, false);
+            let plan = PlanBuilder::new(self.rpc.clone(), request)
+                .resource_group_tag(self.options.resource_group_tag())
+                .trace_fan_out(self.options.fan_out_tracer.clone())
+                .deadline(deadline)
+                .observe_locks(self.options.observer())
+                .resolve_lock(retry_options.lock_backoff.clone())
+                .retry_multi_region(retry_options.region_backoff.clone())
+                .merge(Collect)
+                .plan();
+            async move { plan.execute().await }
+        });
+        let scans = future::try_join_all(scans.collect::<Vec<_>>());
+        let fetch = future::try_join(get, scans);
+        let (fetched, scanned) = cancellable(self.cancellation_token.as_ref(), fetch).await?;
+
+        let format = self.value_format.clone();
+        let fetched = decode_pairs(format.as_deref(), fetched.into_pairs()?)?.into_iter();
+        let values = self
+            .buffer
+            .batch_get_or_else(keys.into_iter(), |requested| {
+                let requested: HashSet<Key> = requested.collect();
+                let fetched = fetched.filter(|pair| requested.contains(pair.key()));
+                future::ready(Ok(fetched.collect()))
+            })
+            .await?
+            .collect();
+        let mut range_values = Vec::with_capacity(ranges.len());
+        for (range, scanned) in ranges.into_iter().zip(scanned) {
+            let scanned = scanned.into_iter().map(KvPair::from).collect();
+            let scanned = decode_pairs(format.as_deref(), scanned)?;
+            let pairs = self
+                .buffer
+                .scan_and_fetch(range, each_limit, true, false, |_, _| {
+                    future::ready(Ok(scanned))
+                })
+                .await?;
+            range_values.push(pairs.collect());
+        }
+        Ok((values, range_values))
     }
 
     /// Create a new 'batch get for update' request.
@@ -325,6 +767,82 @@ impl<PdC: PdClient> Transaction<PdC> {
         }
     }
 
+    /// Lock the keys in `range` and read their latest values, like
+    /// [`batch_get_for_update`](Transaction::batch_get_for_update) does for the keys it is given.
+    ///
+    /// Returns at most `limit` pairs, ordered by key. With
+    /// [`TransactionOptions::skip_locked`], keys which other transactions have locked are left
+    /// out and reported in [`ScanForUpdate::skipped`], and the scan goes on past them until
+    /// `limit` keys are locked or the range ends.
+    ///
+    /// Only valid for pessimistic transactions; otherwise
+    /// [`Error::InvalidTransactionType`](crate::Error::InvalidTransactionType) is returned.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use tikv_client::{Config, TransactionClient, TransactionOptions};
+    /// # use futures::prelude::*;
+    /// # futures::executor::block_on(async {
+    /// # let client = TransactionClient::new(vec!["192.168.0.100", "192.168.0.101"], None).await.unwrap();
+    /// let options = TransactionOptions::new_pessimistic().skip_locked();
+    /// let mut txn = client.begin_with_options(options).await.unwrap();
+    /// // Claim up to 10 jobs which no other consumer holds.
+    /// let jobs = txn
+    ///     .scan_for_update("job/".to_owned().."job0".to_owned(), 10)
+    ///     .await
+    ///     .unwrap();
+    /// for job in jobs.pairs {
+    ///     // Process the job...
+    ///     txn.delete(job.into_key()).await.unwrap();
+    /// }
+    /// txn.commit().await.unwrap();
+    /// # });
+    /// ```
+    pub async fn scan_for_update(
+        &mut self,
+        range: impl Into<BoundRange>,
+        limit: u32,
+    ) -> Result<ScanForUpdate> {
+        debug!(
+            self.logger,
+            "invoking transactional scan_for_update request"
+        );
+        self.check_allow_operation().await?;
+        if !self.is_pessimistic() {
+            return Err(Error::InvalidTransactionType);
+        }
+        let (mut start, end) = range.into().into_keys();
+        let mut result = ScanForUpdate::default();
+        while (result.pairs.len() as u32) < limit {
+            let batch_size = limit - result.pairs.len() as u32;
+            let keys: Vec<Key> = self
+                .scan_inner((start.clone(), end.clone()), batch_size, true, false)
+                .await?
+                .map(KvPair::into_key)
+                .collect();
+            let exhausted = (keys.len() as u32) < batch_size;
+            if let Some(last) = keys.last() {
+                start = last.clone();
+                start.push_zero();
+            }
+            // Keys deleted since the transaction began are not returned.
+            let (mut locked, mut skipped) = if self.options.skip_locked {
+                self.lock_skipping_locked(keys).await?
+            } else {
+                (self.pessimistic_lock(keys, true).await?, Vec::new())
+            };
+            locked.sort_by(|a, b| a.key().cmp(b.key()));
+            skipped.sort();
+            result.pairs.extend(locked);
+            result.skipped.extend(skipped);
+            if exhausted {
+                break;
+            }
+        }
+        Ok(result)
+    }
+
     /// Create a new 'scan' request.
     ///
     /// Once resolved this request will result in a `Vec` of all key-value pairs that lie in the
@@ -362,6 +880,35 @@ impl<PdC: PdClient> Transaction<PdC> {
         self.scan_inner(range, limit, false, false).await
     }
 
+    /// Create a new 'scan' request of the keys which start with `prefix`.
+    ///
+    /// See [`BoundRange::prefix`] for the range which is scanned.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use tikv_client::{KvPair, Config, TransactionClient};
+    /// # use futures::prelude::*;
+    /// # futures::executor::block_on(async {
+    /// # let client = TransactionClient::new(vec!["192.168.0.100", "192.168.0.101"], None).await.unwrap();
+    /// let mut txn = client.begin_optimistic().await.unwrap();
+    /// let result: Vec<KvPair> = txn
+    ///     .scan_prefix("user/".to_owned(), 10)
+    ///     .await
+    ///     .unwrap()
+    ///     .collect();
+    /// // Finish the transaction...
+    /// txn.commit().await.unwrap();
+    /// # });
+    /// ```
+    pub async fn scan_prefix(
+        &mut self,
+        prefix: impl Into<Key>,
+        limit: u32,
+    ) -> Result<impl Iterator<Item = KvPair>> {
+        self.scan(BoundRange::prefix(prefix), limit).await
+    }
+
     /// Create a new 'scan' request that only returns the keys.
     ///
     /// Once resolved this request will result in a `Vec` of keys that lies in the specified range.
@@ -401,6 +948,87 @@ impl<PdC: PdClient> Transaction<PdC> {
             .map(KvPair::into_key))
     }
 
+    /// Count the keys in `range`, including this transaction's buffered writes.
+    ///
+    /// Keys are counted by key-only scans, so values are not transferred.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use tikv_client::{Config, TransactionClient};
+    /// # use futures::prelude::*;
+    /// # futures::executor::block_on(async {
+    /// # let client = TransactionClient::new(vec!["192.168.0.100", "192.168.0.101"], None).await.unwrap();
+    /// let mut txn = client.begin_optimistic().await.unwrap();
+    /// let count = txn.count(b"foo".to_vec()..b"bar".to_vec()).await.unwrap();
+    /// // Finish the transaction...
+    /// txn.commit().await.unwrap();
+    /// # });
+    /// ```
+    pub async fn count(&mut self, range: impl Into<BoundRange>) -> Result<usize> {
+        debug!(self.logger, "invoking transactional count request");
+        let (mut start, end) = range.into().into_keys();
+        let mut count = 0;
+        loop {
+            let keys: Vec<Key> = self
+                .scan_inner((start, end.clone()), COUNT_SCAN_BATCH_SIZE, true, false)
+                .await?
+                .map(KvPair::into_key)
+                .collect();
+            count += keys.len();
+            match keys.last() {
+                Some(last) if keys.len() as u32 == COUNT_SCAN_BATCH_SIZE => {
+                    start = last.clone();
+                    start.push_zero();
+                }
+                _ => return Ok(count),
+            }
+        }
+    }
+
+    /// Scan all of `range`, reading at most as fast as `pacing` allows.
+    ///
+    /// The range is read in batches, waiting between batches when a limit would be exceeded, so
+    /// that a full-range scan doesn't starve TiKV of resources. The stream ends after the first
+    /// error.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use tikv_client::{Config, ScanPacing, TransactionClient};
+    /// # use futures::prelude::*;
+    /// # futures::executor::block_on(async {
+    /// # let client = TransactionClient::new(vec!["192.168.0.100", "192.168.0.101"], None).await.unwrap();
+    /// let mut txn = client.begin_optimistic().await.unwrap();
+    /// let pacing = ScanPacing::default().keys_per_second(1000);
+    /// let mut pairs = Box::pin(txn.scan_paced(.., pacing));
+    /// while let Some(pair) = pairs.try_next().await.unwrap() {
+    ///     // Check `pair`...
+    /// }
+    /// drop(pairs);
+    /// // Finish the transaction...
+    /// txn.commit().await.unwrap();
+    /// # });
+    /// ```
+    pub fn scan_paced(
+        &mut self,
+        range: impl Into<BoundRange>,
+        pacing: ScanPacing,
+    ) -> impl Stream<Item = Result<KvPair>> + '_ {
+        debug!(self.logger, "invoking transactional paced scan request");
+        let clock = self.rpc.clock();
+        paced_scan(
+            self,
+            range.into(),
+            pacing,
+            clock,
+            |txn, range, limit| async move {
+                let pairs = txn.scan_inner(range, limit, false, false).await;
+                (txn, pairs.map(Iterator::collect))
+            },
+        )
+    }
+
     /// Create a 'scan_reverse' request.
     ///
     /// Similar to [`scan`](Transaction::scan), but scans in the reverse direction.
@@ -431,6 +1059,36 @@ impl<PdC: PdClient> Transaction<PdC> {
             .map(KvPair::into_key))
     }
 
+    /// Get the first key-value pair in `range`, if any, including this transaction's buffered
+    /// writes.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use tikv_client::{KvPair, Config, TransactionClient};
+    /// # use futures::prelude::*;
+    /// # futures::executor::block_on(async {
+    /// # let client = TransactionClient::new(vec!["192.168.0.100", "192.168.0.101"], None).await.unwrap();
+    /// let mut txn = client.begin_optimistic().await.unwrap();
+    /// let result: Option<KvPair> = txn.first("cursor/".to_owned()..).await.unwrap();
+    /// // Finish the transaction...
+    /// txn.commit().await.unwrap();
+    /// # });
+    /// ```
+    pub async fn first(&mut self, range: impl Into<BoundRange>) -> Result<Option<KvPair>> {
+        debug!(self.logger, "invoking transactional first request");
+        Ok(self.scan_inner(range, 1, false, false).await?.next())
+    }
+
+    /// Get the last key-value pair in `range`, if any, including this transaction's buffered
+    /// writes.
+    ///
+    /// Similar to [`first`](Transaction::first), but from the end of the range.
+    pub async fn last(&mut self, range: impl Into<BoundRange>) -> Result<Option<KvPair>> {
+        debug!(self.logger, "invoking transactional last request");
+        Ok(self.scan_inner(range, 1, false, true).await?.next())
+    }
+
     /// Sets the value associated with the given key.
     ///
     /// # Examples
@@ -459,27 +1117,67 @@ impl<PdC: PdClient> Transaction<PdC> {
         Ok(())
     }
 
-    /// Inserts the value associated with the given key.
+    /// Serialize `value` as JSON and set it as the value of `key`.
     ///
-    /// Similar to [`put'], but it has an additional constraint that the key should not exist
-    /// before this operation.
+    /// See [`put`](Transaction::put) and [`get_json`](Transaction::get_json).
     ///
     /// # Examples
     ///
     /// ```rust,no_run
-    /// # use tikv_client::{Key, Value, Config, TransactionClient};
+    /// # use tikv_client::{Config, TransactionClient};
     /// # use futures::prelude::*;
     /// # futures::executor::block_on(async {
     /// # let client = TransactionClient::new(vec!["192.168.0.100", "192.168.0.101"], None).await.unwrap();
     /// let mut txn = client.begin_optimistic().await.unwrap();
-    /// let key = "foo".to_owned();
-    /// let val = "FOO".to_owned();
-    /// txn.insert(key, val);
+    /// txn.put_json("ids".to_owned(), &vec![1u64, 2, 3]).await.unwrap();
     /// txn.commit().await.unwrap();
     /// # });
     /// ```
-    pub async fn insert(&mut self, key: impl Into<Key>, value: impl Into<Value>) -> Result<()> {
-        debug!(self.logger, "invoking transactional insert request");
+    pub async fn put_json<T: Serialize + ?Sized>(
+        &mut self,
+        key: impl Into<Key>,
+        value: &T,
+    ) -> Result<()> {
+        let value = serde_json::to_vec(value).map_err(|e| Error::ValueCodecError {
+            message: e.to_string(),
+        })?;
+        self.put(key, value).await
+    }
+
+    /// Encode `value` using `codec` and set it as the value of `key`.
+    ///
+    /// See [`put`](Transaction::put) and [`ValueCodec`].
+    pub async fn put_with_codec<T>(
+        &mut self,
+        key: impl Into<Key>,
+        value: &T,
+        codec: &impl ValueCodec<T>,
+    ) -> Result<()> {
+        let value = codec.encode(value)?;
+        self.put(key, value).await
+    }
+
+    /// Inserts the value associated with the given key.
+    ///
+    /// Similar to [`put'], but it has an additional constraint that the key should not exist
+    /// before this operation.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use tikv_client::{Key, Value, Config, TransactionClient};
+    /// # use futures::prelude::*;
+    /// # futures::executor::block_on(async {
+    /// # let client = TransactionClient::new(vec!["192.168.0.100", "192.168.0.101"], None).await.unwrap();
+    /// let mut txn = client.begin_optimistic().await.unwrap();
+    /// let key = "foo".to_owned();
+    /// let val = "FOO".to_owned();
+    /// txn.insert(key, val);
+    /// txn.commit().await.unwrap();
+    /// # });
+    /// ```
+    pub async fn insert(&mut self, key: impl Into<Key>, value: impl Into<Value>) -> Result<()> {
+        debug!(self.logger, "invoking transactional insert request");
         self.check_allow_operation().await?;
         let key = key.into();
         if self.buffer.get(&key).is_some() {
@@ -525,6 +1223,100 @@ impl<PdC: PdClient> Transaction<PdC> {
         Ok(())
     }
 
+    /// Discard the buffered put, insert, delete or lock of `key`.
+    ///
+    /// The transaction will no longer write or lock the key, and reads of it see its value at the
+    /// transaction's start timestamp again. In a pessimistic transaction, the key's lock in TiKV is
+    /// released, unless the key is the transaction's primary key, which stays locked (but is not
+    /// written) until the transaction ends.
+    ///
+    /// Returns whether the key had a buffered mutation.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use tikv_client::{Config, TransactionClient};
+    /// # use futures::prelude::*;
+    /// # futures::executor::block_on(async {
+    /// # let client = TransactionClient::new(vec!["192.168.0.100", "192.168.0.101"], None).await.unwrap();
+    /// let mut txn = client.begin_optimistic().await.unwrap();
+    /// txn.put("key".to_owned(), "value".to_owned()).await.unwrap();
+    /// txn.unset("key".to_owned()).await.unwrap();
+    /// // Nothing is written.
+    /// txn.commit().await.unwrap();
+    /// # });
+    /// ```
+    pub async fn unset(&mut self, key: impl Into<Key>) -> Result<bool> {
+        debug!(self.logger, "invoking transactional unset request");
+        self.check_allow_operation().await?;
+        let key = key.into();
+        if !self.buffer.unset(&key) {
+            return Ok(false);
+        }
+        if let TransactionKind::Pessimistic(for_update_ts) = self.options.kind.clone() {
+            if self.buffer.get_primary_key().as_ref() != Some(&key) {
+                let start_ts = self.timestamp.clone();
+                self.pessimistic_lock_rollback(iter::once(key), start_ts, for_update_ts)
+                    .await?;
+            }
+        }
+        Ok(true)
+    }
+
+    /// Release the pessimistic locks this transaction holds on `keys`, when it will not write them
+    /// after all.
+    ///
+    /// Only keys which are locked but not written are released: keys with a buffered put, insert
+    /// or delete stay locked, as does the transaction's primary key, which holds the transaction's
+    /// status until it ends. Use [`unset`](Transaction::unset) to discard the write of a key and
+    /// release its lock. Keys which this transaction has not locked are ignored.
+    ///
+    /// Only valid for pessimistic transactions; otherwise
+    /// [`Error::InvalidTransactionType`](crate::Error::InvalidTransactionType) is returned.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use tikv_client::{Config, TransactionClient};
+    /// # use futures::prelude::*;
+    /// # futures::executor::block_on(async {
+    /// # let client = TransactionClient::new(vec!["192.168.0.100"], None).await.unwrap();
+    /// let mut txn = client.begin_pessimistic().await.unwrap();
+    /// txn.lock_keys(vec!["a".to_owned(), "b".to_owned(), "c".to_owned()])
+    ///     .await
+    ///     .unwrap();
+    /// // Only "a" turns out to need writing.
+    /// txn.rollback_pessimistic_locks(vec!["b".to_owned(), "c".to_owned()])
+    ///     .await
+    ///     .unwrap();
+    /// txn.put("a".to_owned(), "value".to_owned()).await.unwrap();
+    /// txn.commit().await.unwrap();
+    /// # });
+    /// ```
+    pub async fn rollback_pessimistic_locks(
+        &mut self,
+        keys: impl IntoIterator<Item = impl Into<Key>>,
+    ) -> Result<()> {
+        debug!(
+            self.logger,
+            "invoking transactional rollback_pessimistic_locks request"
+        );
+        self.check_allow_operation().await?;
+        let for_update_ts = match self.options.kind.clone() {
+            TransactionKind::Pessimistic(for_update_ts) => for_update_ts,
+            TransactionKind::Optimistic => return Err(Error::InvalidTransactionType),
+        };
+        let primary_key = self.buffer.get_primary_key();
+        let keys: Vec<Key> = keys
+            .into_iter()
+            .map(Into::into)
+            .filter(|key| self.buffer.is_locked(key) && primary_key.as_ref() != Some(key))
+            .collect();
+        let start_ts = self.timestamp.clone();
+        self.pessimistic_lock_rollback(keys.into_iter(), start_ts, for_update_ts)
+            .await
+    }
+
     /// Lock the given keys without mutating their values.
     ///
     /// In optimistic mode, write conflicts are not checked until commit.
@@ -571,6 +1363,11 @@ impl<PdC: PdClient> Transaction<PdC> {
     /// Commits the actions of the transaction. On success, we return the commit timestamp (or
     /// `None` if there was nothing to commit).
     ///
+    /// If committing fails, e.g., with a transient network error, `commit` can be called again.
+    /// The transaction keeps its start timestamp, and if the failed call got as far as committing
+    /// the primary key, the next call first asks the primary whether that commit took effect
+    /// instead of prewriting again.
+    ///
     /// # Examples
     ///
     /// ```rust,no_run
@@ -585,7 +1382,16 @@ impl<PdC: PdClient> Transaction<PdC> {
     /// ```
     pub async fn commit(&mut self) -> Result<Option<Timestamp>> {
         debug!(self.logger, "commiting transaction");
-        {
+        // Unless an earlier attempt may have passed the commit point, nothing can have been
+        // committed, so roll back the locks the transaction holds.
+        if self.primary_commit_ts.is_none() && self.check_cancelled().is_err() {
+            debug!(self.logger, "commit canceled, rolling back");
+            self.rollback().await?;
+            return Err(Error::OperationCanceled);
+        }
+        // The commit is counted as in flight under the same lock as it starts, so that closing the
+        // client either rolls the transaction back or waits for the commit.
+        let _committing = {
             let mut status = self.status.write().await;
             if !matches!(
                 *status,
@@ -593,18 +1399,51 @@ impl<PdC: PdClient> Transaction<PdC> {
             ) {
                 return Err(Error::OperationAfterCommitError);
             }
+            self.check_deadline()?;
             *status = TransactionStatus::StartedCommit;
-        }
+            self.registry
+                .as_ref()
+                .map(|registry| registry.start_commit())
+        };
 
+        self.choose_primary_key().await?;
         let primary_key = self.buffer.get_primary_key();
-        let mutations = self.buffer.to_proto_mutations();
+        let mut mutations = self.buffer.to_proto_mutations();
         if mutations.is_empty() {
             assert!(primary_key.is_none());
+            self.observe_outcome("committed");
+            self.run_commit_hooks(None);
             return Ok(None);
         }
 
+        if let Some(format) = &self.value_format {
+            format.encode_mutations(&mut mutations)?;
+        }
+
         self.start_auto_heartbeat().await;
 
+        let audit = self.rpc.audit_log();
+        let audited: Vec<(AuditOperation, Key)> = match &audit {
+            Some(_) => self
+                .buffer
+                .mutations()
+                .filter_map(|(key, kind)| match kind {
+                    MutationKind::Put | MutationKind::Insert => Some((AuditOperation::Put, key)),
+                    MutationKind::Delete => Some((AuditOperation::Delete, key)),
+                    MutationKind::Lock | MutationKind::CheckNotExists => None,
+                })
+                .map(|(operation, key)| (operation, key.clone()))
+                .collect(),
+            None => Vec::new(),
+        };
+
+        let written: Vec<Key> = match self.rpc.hot_key_tracker() {
+            Some(_) => self.buffer.mutations().map(|(key, _)| key.clone()).collect(),
+            None => Vec::new(),
+        };
+
+        let start = self.rpc.clock().now();
+        let mut stats = CommitStats::default();
         let res = Committer::new(
             primary_key,
             mutations,
@@ -614,17 +1453,148 @@ impl<PdC: PdClient> Transaction<PdC> {
             self.buffer.get_write_size() as u64,
             self.start_instant,
             self.logger.new(o!("child" => 1)),
+            self.cancellation_token.clone(),
         )
-        .commit()
-        .await;
+        .commit(&mut stats, &mut self.primary_commit_ts)
+        .await
+        .map_err(|e| e.with_conflict_kind(ConflictKind::Optimistic));
+        self.commit_stats = Some(stats);
+        self.invalidate_read_cache();
+        if let Some(pool) = &self.timestamp_pool {
+            match &res {
+                Ok(Some(commit_ts)) => pool.observe_commit(Some(commit_ts)),
+                // The committer has already rolled back the transaction.
+                Err(e) if is_rolled_back(e) => {}
+                // The transaction may have committed, perhaps at the timestamp sent for the
+                // primary.
+                _ => pool.observe_commit(self.primary_commit_ts.as_ref()),
+            }
+        }
+        if let Some(audit) = audit {
+            let outcome = AuditOutcome::of_write(&res);
+            for (operation, key) in &audited {
+                audit.record_writes(*operation, [key], outcome);
+            }
+        }
+        self.track_hot_keys(&written, start, &res);
 
-        if res.is_ok() {
-            let mut status = self.status.write().await;
-            *status = TransactionStatus::Committed;
+        match &res {
+            Ok(commit_ts) => {
+                *self.status.write().await = TransactionStatus::Committed;
+                self.observe_outcome("committed");
+                self.run_commit_hooks(commit_ts.clone());
+            }
+            // The committer has already rolled back the transaction.
+            Err(e) if is_rolled_back(e) => {
+                *self.status.write().await = TransactionStatus::Rolledback;
+                self.observe_outcome("rolled_back");
+                self.run_rollback_hooks();
+            }
+            Err(_) => self.observe_outcome("failed"),
         }
         res
     }
 
+    /// Commit the transaction, first adding the mutations `extra` computes from its pending writes.
+    ///
+    /// `extra` is called with the mutations the transaction would commit, ordered by key, just
+    /// before they are prewritten. The mutations it returns are applied as if by
+    /// [`put`](Transaction::put), [`insert`](Transaction::insert), [`delete`](Transaction::delete)
+    /// and [`lock_keys`](Transaction::lock_keys), and commit atomically with the rest of the
+    /// transaction. This suits writing an outbox event which describes the transaction's changes.
+    ///
+    /// If an earlier call to `commit` or `commit_with` failed part way, `extra` is not called: the
+    /// mutations the earlier call added are still part of the transaction.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use tikv_client::{BufferedMutation, TimestampExt, TransactionClient};
+    /// # futures::executor::block_on(async {
+    /// # let client = TransactionClient::new(vec!["192.168.0.100"], None).await.unwrap();
+    /// let mut txn = client.begin_optimistic().await.unwrap();
+    /// txn.put("user/1".to_owned(), "Alice".to_owned()).await.unwrap();
+    /// let event_key = format!("outbox/{}", txn.start_timestamp().version());
+    /// txn.commit_with(|pending| {
+    ///     let event = format!("{} keys changed", pending.len());
+    ///     vec![BufferedMutation::Put {
+    ///         key: event_key.into_bytes(),
+    ///         value: event.into_bytes(),
+    ///     }]
+    /// })
+    /// .await
+    /// .unwrap();
+    /// # });
+    /// ```
+    pub async fn commit_with(
+        &mut self,
+        extra: impl FnOnce(&[BufferedMutation]) -> Vec<BufferedMutation>,
+    ) -> Result<Option<Timestamp>> {
+        if *self.status.read().await == TransactionStatus::Active {
+            debug!(self.logger, "adding final mutations before commit");
+            let pending = self.buffer.export_mutations();
+            for mutation in extra(&pending) {
+                match mutation {
+                    BufferedMutation::Put { key, value } => self.put(key, value).await?,
+                    BufferedMutation::Insert { key, value } => self.insert(key, value).await?,
+                    BufferedMutation::Delete { key } => self.delete(key).await?,
+                    BufferedMutation::Lock { key } => self.lock_keys(iter::once(key)).await?,
+                    // An insert which is then deleted only checks that the key does not exist.
+                    BufferedMutation::CheckNotExists { key } => {
+                        self.insert(key.clone(), Value::new()).await?;
+                        self.delete(key).await?;
+                    }
+                }
+            }
+        }
+        self.commit().await
+    }
+
+    /// Export the transaction's state, so that it can be committed or rolled back by another
+    /// client using [`TransactionClient::import_transaction`](crate::TransactionClient::import_transaction).
+    ///
+    /// The state includes the transaction's timestamps and buffered mutations, but not values it
+    /// has read. Once exported, this transaction can no longer be used. Locks held by a
+    /// pessimistic transaction are no longer kept alive by this client, so the importing client
+    /// should commit or roll back the transaction promptly.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use tikv_client::{Config, TransactionClient, TransactionOptions};
+    /// # use futures::prelude::*;
+    /// # futures::executor::block_on(async {
+    /// # let client = TransactionClient::new(vec!["192.168.0.100"], None).await.unwrap();
+    /// let mut txn = client.begin_optimistic().await.unwrap();
+    /// txn.put("foo".to_owned(), "bar".to_owned()).await.unwrap();
+    /// let state = txn.export_state().await.unwrap();
+    ///
+    /// // Possibly in another process...
+    /// let mut txn = client
+    ///     .import_transaction(state, TransactionOptions::new_optimistic())
+    ///     .unwrap();
+    /// txn.commit().await.unwrap();
+    /// # });
+    /// ```
+    pub async fn export_state(self) -> Result<TransactionState> {
+        {
+            let mut status = self.status.write().await;
+            if *status != TransactionStatus::Active {
+                return Err(Error::OperationAfterCommitError);
+            }
+            *status = TransactionStatus::Dropped;
+        }
+        Ok(TransactionState {
+            start_ts: self.timestamp.version(),
+            for_update_ts: match &self.options.kind {
+                TransactionKind::Optimistic => None,
+                TransactionKind::Pessimistic(for_update_ts) => Some(for_update_ts.version()),
+            },
+            primary_key: self.buffer.get_primary_key().map(Into::into),
+            mutations: self.buffer.export_mutations(),
+        })
+    }
+
     /// Rollback the transaction.
     ///
     /// If it succeeds, all mutations made by this transaction will be discarded.
@@ -643,7 +1613,7 @@ impl<PdC: PdClient> Transaction<PdC> {
     /// ```
     pub async fn rollback(&mut self) -> Result<()> {
         debug!(self.logger, "rolling back transaction");
-        {
+        let prewritten = {
             let status = self.status.read().await;
             if !matches!(
                 *status,
@@ -653,7 +1623,10 @@ impl<PdC: PdClient> Transaction<PdC> {
             ) {
                 return Err(Error::OperationAfterCommitError);
             }
-        }
+            // A failed commit, or an earlier attempt to roll back after one, may have prewritten
+            // keys.
+            *status != TransactionStatus::Active
+        };
 
         {
             let mut status = self.status.write().await;
@@ -662,7 +1635,7 @@ impl<PdC: PdClient> Transaction<PdC> {
 
         let primary_key = self.buffer.get_primary_key();
         let mutations = self.buffer.to_proto_mutations();
-        let res = Committer::new(
+        let mut committer = Committer::new(
             primary_key,
             mutations,
             self.timestamp.clone(),
@@ -671,22 +1644,154 @@ impl<PdC: PdClient> Transaction<PdC> {
             self.buffer.get_write_size() as u64,
             self.start_instant,
             self.logger.new(o!("child" => 1)),
-        )
-        .rollback()
-        .await;
+            None,
+        );
+        committer.prewritten = prewritten;
+        let res = committer.rollback().await;
 
         if res.is_ok() {
-            let mut status = self.status.write().await;
-            *status = TransactionStatus::Rolledback;
+            *self.status.write().await = TransactionStatus::Rolledback;
+            self.observe_outcome("rolled_back");
+            self.run_rollback_hooks();
         }
         res
     }
 
+    /// Register a callback to be run once the transaction has successfully committed.
+    ///
+    /// The callback receives the commit timestamp, or `None` if there was nothing to commit.
+    /// Callbacks are run in the order they were registered, before `commit` returns. They are not
+    /// run if the commit fails.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use tikv_client::{Config, Timestamp, TransactionClient};
+    /// # use futures::prelude::*;
+    /// # futures::executor::block_on(async {
+    /// # let client = TransactionClient::new(vec!["192.168.0.100"], None).await.unwrap();
+    /// let mut txn = client.begin_optimistic().await.unwrap();
+    /// txn.on_committed(|commit_ts| println!("committed at {:?}", commit_ts));
+    /// // ... Do some actions.
+    /// txn.commit().await.unwrap();
+    /// # });
+    /// ```
+    pub fn on_committed(&mut self, hook: impl FnOnce(Option<Timestamp>) + Send + Sync + 'static) {
+        self.commit_hooks.push(Box::new(hook));
+    }
+
+    /// Register a callback to be run once the transaction has successfully rolled back.
+    ///
+    /// Callbacks are run in the order they were registered, before `rollback` returns. They are
+    /// not run if the rollback fails, nor if the transaction is dropped without being rolled back.
+    pub fn on_rolled_back(&mut self, hook: impl FnOnce() + Send + Sync + 'static) {
+        self.rollback_hooks.push(Box::new(hook));
+    }
+
+    fn run_commit_hooks(&mut self, commit_ts: Option<Timestamp>) {
+        for hook in self.commit_hooks.drain(..) {
+            hook(commit_ts.clone());
+        }
+    }
+
+    /// Drop the values of the keys the transaction writes from the read cache.
+    fn invalidate_read_cache(&self) {
+        if let Some(cache) = &self.read_cache {
+            for mutation in self.buffer.to_proto_mutations() {
+                if matches!(
+                    mutation.op(),
+                    kvrpcpb::Op::Put | kvrpcpb::Op::Del | kvrpcpb::Op::Insert
+                ) {
+                    cache.invalidate(&mutation.key.into());
+                }
+            }
+        }
+    }
+
+    fn run_rollback_hooks(&mut self) {
+        for hook in self.rollback_hooks.drain(..) {
+            hook();
+        }
+    }
+
+    /// Abort operations on this transaction when `token` is cancelled.
+    ///
+    /// Once the token is cancelled, reads which are in flight fail with
+    /// `Error::OperationCanceled`, as does any further operation. If the transaction is being
+    /// committed and has not yet reached its commit point, the commit is abandoned, the
+    /// transaction is rolled back, and `commit` returns `Error::OperationCanceled`. A commit which
+    /// has passed its commit point runs to completion. Rolling back is never canceled.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use tikv_client::{CancellationToken, Config, TransactionClient};
+    /// # use futures::prelude::*;
+    /// # futures::executor::block_on(async {
+    /// # let client = TransactionClient::new(vec!["192.168.0.100"], None).await.unwrap();
+    /// let token = CancellationToken::new();
+    /// let mut txn = client.begin_optimistic().await.unwrap();
+    /// txn.set_cancellation_token(token.child_token());
+    /// // ... Do some actions, and on shutdown elsewhere:
+    /// token.cancel();
+    /// # });
+    /// ```
+    pub fn set_cancellation_token(&mut self, token: CancellationToken) {
+        self.cancellation_token = Some(token);
+    }
+
     /// Get the start timestamp of this transaction.
     pub fn start_timestamp(&self) -> Timestamp {
         self.timestamp.clone()
     }
 
+    /// The [label](TransactionOptions::label) of the transaction, if it has one.
+    pub fn label(&self) -> Option<&str> {
+        self.options.label.as_deref()
+    }
+
+    /// Whether the transaction was created [read-only](TransactionOptions::read_only).
+    pub fn is_read_only(&self) -> bool {
+        self.options.read_only
+    }
+
+    /// The mutations the transaction has buffered, and would commit, ordered by key.
+    ///
+    /// Locks taken by [`lock_keys`](Transaction::lock_keys) and the `*_for_update` reads are
+    /// mutations too; values the transaction has only read are not.
+    pub fn pending_mutations(&self) -> impl Iterator<Item = (&Key, MutationKind)> {
+        self.buffer.mutations()
+    }
+
+    /// The number of key and value bytes the transaction would write when it commits.
+    pub fn write_size(&self) -> usize {
+        self.buffer.get_write_size()
+    }
+
+    /// The number of key and value bytes the transaction holds in memory: those it would write,
+    /// and those of values it has read. See [`BufferObserver`].
+    pub fn buffer_size(&self) -> usize {
+        self.buffer.size()
+    }
+
+    /// The number of [pending mutations](Transaction::pending_mutations).
+    pub fn len(&self) -> usize {
+        self.buffer.mutations().count()
+    }
+
+    /// Whether the transaction has no [pending mutations](Transaction::pending_mutations).
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Get statistics about the last attempt to commit this transaction.
+    ///
+    /// Returns `None` if the transaction has not tried to commit, or if there was nothing to
+    /// commit. Statistics are recorded whether or not the commit succeeded.
+    pub fn commit_stats(&self) -> Option<&CommitStats> {
+        self.commit_stats.as_ref()
+    }
+
     /// Send a heart beat message to keep the transaction alive on the server and update its TTL.
     ///
     /// Returns the TTL set on the transaction's locks by TiKV.
@@ -701,9 +1806,10 @@ impl<PdC: PdClient> Transaction<PdC> {
         let request = new_heart_beat_request(
             self.timestamp.clone(),
             primary_key,
-            self.start_instant.elapsed().as_millis() as u64 + MAX_TTL,
+            elapsed_since(self.rpc.as_ref(), self.start_instant).as_millis() as u64 + MAX_TTL,
         );
         let plan = PlanBuilder::new(self.rpc.clone(), request)
+            .observe_locks(self.options.observer())
             .resolve_lock(self.options.retry_options.lock_backoff.clone())
             .retry_multi_region(self.options.retry_options.region_backoff.clone())
             .merge(CollectSingle)
@@ -723,8 +1829,15 @@ impl<PdC: PdClient> Transaction<PdC> {
         let timestamp = self.timestamp.clone();
         let rpc = self.rpc.clone();
         let retry_options = self.options.retry_options.clone();
+        let lock_observer = self.options.observer();
+        let deadline = self.deadline();
+        let resource_group_tag = self.options.resource_group_tag();
+        let fan_out_tracer = self.options.fan_out_tracer.clone();
+        let cancellation_token = self.cancellation_token.clone();
+        let value_format = self.value_format.clone().filter(|_| !key_only);
 
-        self.buffer
+        let pairs: Vec<KvPair> = self
+            .buffer
             .scan_and_fetch(
                 range.into(),
                 limit,
@@ -734,16 +1847,25 @@ impl<PdC: PdClient> Transaction<PdC> {
                     let request =
                         new_scan_request(new_range, timestamp, new_limit, key_only, reverse);
                     let plan = PlanBuilder::new(rpc, request)
+                        .resource_group_tag(resource_group_tag)
+                        .trace_fan_out(fan_out_tracer)
+                        .deadline(deadline)
+                        .observe_locks(lock_observer)
                         .resolve_lock(retry_options.lock_backoff)
                         .retry_multi_region(retry_options.region_backoff)
                         .merge(Collect)
                         .plan();
-                    plan.execute()
-                        .await
-                        .map(|r| r.into_iter().map(Into::into).collect())
+                    let pairs = cancellable(cancellation_token.as_ref(), plan.execute()).await?;
+                    let pairs = pairs.into_iter().map(Into::into).collect();
+                    decode_pairs(value_format.as_deref(), pairs)
                 },
             )
-            .await
+            .await?
+            .collect();
+        if let Some(audit) = self.rpc.audit_log() {
+            audit.record_scan(pairs.iter().map(KvPair::key));
+        }
+        Ok(pairs.into_iter())
     }
 
     /// Pessimistically lock the keys, and optionally retrieve corresponding values.
@@ -759,6 +1881,20 @@ impl<PdC: PdClient> Transaction<PdC> {
         &mut self,
         keys: impl IntoIterator<Item = impl PessimisticLock>,
         need_value: bool,
+    ) -> Result<Vec<KvPair>> {
+        self.pessimistic_lock_with(keys, need_value, None, false).await
+    }
+
+    /// Like [`pessimistic_lock`](Transaction::pessimistic_lock), locking the keys at
+    /// `for_update_ts` if it is given rather than at a new timestamp. If `no_wait`, fails at once
+    /// when a key is locked by another transaction, with an error which reports the lock, rather
+    /// than resolving the lock or waiting for it to be released.
+    async fn pessimistic_lock_with(
+        &mut self,
+        keys: impl IntoIterator<Item = impl PessimisticLock>,
+        need_value: bool,
+        for_update_ts: Option<Timestamp>,
+        no_wait: bool,
     ) -> Result<Vec<KvPair>> {
         debug!(self.logger, "acquiring pessimistic lock");
         assert!(
@@ -778,9 +1914,12 @@ impl<PdC: PdClient> Transaction<PdC> {
             .buffer
             .get_primary_key()
             .unwrap_or_else(|| first_key.clone());
-        let for_update_ts = self.rpc.clone().get_timestamp().await?;
+        let for_update_ts = match for_update_ts {
+            Some(for_update_ts) => for_update_ts,
+            None => self.rpc.clone().get_timestamp().await?,
+        };
         self.options.push_for_update_ts(for_update_ts.clone());
-        let request = new_pessimistic_lock_request(
+        let mut request = new_pessimistic_lock_request(
             keys.clone().into_iter(),
             primary_lock,
             self.timestamp.clone(),
@@ -788,13 +1927,44 @@ impl<PdC: PdClient> Transaction<PdC> {
             for_update_ts.clone(),
             need_value,
         );
-        let plan = PlanBuilder::new(self.rpc.clone(), request)
-            .resolve_lock(self.options.retry_options.lock_backoff.clone())
-            .preserve_shard()
-            .retry_multi_region_preserve_results(self.options.retry_options.region_backoff.clone())
-            .merge(CollectWithShard)
-            .plan();
-        let pairs = plan.execute().await;
+        let start = self.rpc.clock().now();
+        let pairs = if no_wait {
+            // Without the lock resolution, the locks met are left in the key errors.
+            request.wait_timeout = -1;
+            PlanBuilder::new(self.rpc.clone(), request)
+                .resource_group_tag(self.options.resource_group_tag())
+                .trace_fan_out(self.options.fan_out_tracer.clone())
+                .deadline(self.deadline())
+                .preserve_shard()
+                .retry_multi_region_preserve_results(
+                    self.options.retry_options.region_backoff.clone(),
+                )
+                .merge(CollectWithShard)
+                .plan()
+                .execute()
+                .await
+        } else {
+            PlanBuilder::new(self.rpc.clone(), request)
+                .resource_group_tag(self.options.resource_group_tag())
+                .trace_fan_out(self.options.fan_out_tracer.clone())
+                .deadline(self.deadline())
+                .observe_locks(self.options.observer())
+                .resolve_lock(self.options.retry_options.lock_backoff.clone())
+                .preserve_shard()
+                .retry_multi_region_preserve_results(
+                    self.options.retry_options.region_backoff.clone(),
+                )
+                .merge(CollectWithShard)
+                .plan()
+                .execute()
+                .await
+        }
+        .map_err(|e| e.with_conflict_kind(ConflictKind::Pessimistic));
+        let locked: Vec<Key> = match self.rpc.hot_key_tracker() {
+            Some(_) => keys.iter().map(|key| key.clone().key()).collect(),
+            None => Vec::new(),
+        };
+        self.track_hot_keys(&locked, start, &pairs);
 
         if let Err(err) = pairs {
             match err {
@@ -819,8 +1989,59 @@ impl<PdC: PdClient> Transaction<PdC> {
                 self.buffer.lock(key.key());
             }
 
-            pairs
+            match &self.value_format {
+                Some(format) if need_value => decode_pairs(Some(format), pairs?),
+                _ => pairs,
+            }
+        }
+    }
+
+    /// Lock `keys` and read their values for update, skipping the keys which other transactions
+    /// have locked. Returns the pairs read and the keys skipped.
+    ///
+    /// The keys are locked at a single `for_update_ts`. TiKV fails a request as a whole when one
+    /// of its keys is locked, so the request is sent again without the keys found locked. A lock
+    /// is resolved the first time it is met, in case it has expired, and its key is only skipped
+    /// if it is still locked when the request is sent again.
+    async fn lock_skipping_locked(
+        &mut self,
+        mut keys: Vec<Key>,
+    ) -> Result<(Vec<KvPair>, Vec<Key>)> {
+        let mut skipped = Vec::new();
+        if keys.is_empty() {
+            return Ok((Vec::new(), skipped));
         }
+        let for_update_ts = self.rpc.clone().get_timestamp().await?;
+        let mut resolved = HashSet::new();
+        while !keys.is_empty() {
+            let err = match self
+                .pessimistic_lock_with(keys.clone(), true, Some(for_update_ts.clone()), true)
+                .await
+            {
+                Ok(pairs) => return Ok((pairs, skipped)),
+                Err(e) => e,
+            };
+            let locks = held_locks(&err);
+            if locks.is_empty() {
+                return Err(err);
+            }
+            let mut unresolved = Vec::new();
+            for lock in locks {
+                let key = Key::from(lock.key.clone());
+                if resolved.insert(key.clone()) {
+                    unresolved.push(lock);
+                } else {
+                    keys.retain(|k| *k != key);
+                    skipped.push(key);
+                }
+            }
+            if !unresolved.is_empty() {
+                let observer = self.options.observer();
+                let deadline = self.deadline();
+                resolve_locks(unresolved, self.rpc.clone(), observer.as_deref(), deadline).await?;
+            }
+        }
+        Ok((Vec::new(), skipped))
     }
 
     /// Rollback pessimistic lock
@@ -843,6 +2064,9 @@ impl<PdC: PdClient> Transaction<PdC> {
             for_update_ts,
         );
         let plan = PlanBuilder::new(self.rpc.clone(), req)
+            .resource_group_tag(self.options.resource_group_tag())
+            .trace_fan_out(self.options.fan_out_tracer.clone())
+            .observe_locks(self.options.observer())
             .resolve_lock(self.options.retry_options.lock_backoff.clone())
             .retry_multi_region(self.options.retry_options.region_backoff.clone())
             .extract_error()
@@ -857,9 +2081,15 @@ impl<PdC: PdClient> Transaction<PdC> {
 
     /// Checks if the transaction can perform arbitrary operations.
     async fn check_allow_operation(&self) -> Result<()> {
+        if let Some(registry) = &self.registry {
+            registry.check_open()?;
+        }
         let status = self.status.read().await;
         match *status {
-            TransactionStatus::ReadOnly | TransactionStatus::Active => Ok(()),
+            TransactionStatus::ReadOnly | TransactionStatus::Active => {
+                self.check_deadline()?;
+                self.check_cancelled()
+            }
             TransactionStatus::Committed
             | TransactionStatus::Rolledback
             | TransactionStatus::StartedCommit
@@ -868,14 +2098,104 @@ impl<PdC: PdClient> Transaction<PdC> {
         }
     }
 
-    fn is_pessimistic(&self) -> bool {
-        matches!(self.options.kind, TransactionKind::Pessimistic(_))
+    fn deadline(&self) -> Option<Instant> {
+        self.options.deadline_for(self.start_instant)
     }
 
-    async fn start_auto_heartbeat(&mut self) {
-        debug!(self.logger, "starting auto_heartbeat");
-        if !self.options.heartbeat_option.is_auto_heartbeat() || self.is_heartbeat_started {
-            return;
+    fn check_deadline(&self) -> Result<()> {
+        match self.deadline() {
+            Some(deadline) if self.rpc.clock().now() >= deadline => Err(Error::DeadlineExceeded),
+            _ => Ok(()),
+        }
+    }
+
+    fn check_cancelled(&self) -> Result<()> {
+        match &self.cancellation_token {
+            Some(token) if token.is_cancelled() => Err(Error::OperationCanceled),
+            _ => Ok(()),
+        }
+    }
+
+    /// Set the primary key of an optimistic transaction as its options'
+    /// [`PrimaryKeyStrategy`] describes. Pessimistic transactions keep the first key they locked.
+    async fn choose_primary_key(&mut self) -> Result<()> {
+        if self.is_pessimistic() || self.buffer.get_primary_key().is_none() {
+            return Ok(());
+        }
+        let primary_key = match &self.options.primary_key_strategy {
+            PrimaryKeyStrategy::FirstWritten => return Ok(()),
+            PrimaryKeyStrategy::Smallest => self.primary_key_candidates().next(),
+            PrimaryKeyStrategy::Explicit(key) => {
+                let key = key.clone();
+                self.buffer.lock(key.clone());
+                self.primary_key_candidates()
+                    .find(|candidate| *candidate == key)
+            }
+            PrimaryKeyStrategy::MostColocated => {
+                let keys: Vec<Key> = self.primary_key_candidates().collect();
+                let groups: Vec<(RegionWithLeader, Vec<Key>)> = self
+                    .rpc
+                    .clone()
+                    .group_keys_by_region(keys.into_iter())
+                    .try_collect()
+                    .await?;
+                let mut most_colocated: Option<Vec<Key>> = None;
+                for (_, keys) in groups {
+                    if most_colocated
+                        .as_ref()
+                        .is_none_or(|most| keys.len() > most.len())
+                    {
+                        most_colocated = Some(keys);
+                    }
+                }
+                most_colocated.and_then(|keys| keys.into_iter().next())
+            }
+        };
+        if let Some(primary_key) = primary_key {
+            self.buffer.set_primary_key(primary_key);
+        }
+        Ok(())
+    }
+
+    /// The keys which could be the primary key: those written or locked, in order.
+    fn primary_key_candidates(&self) -> impl Iterator<Item = Key> + '_ {
+        self.buffer
+            .mutations()
+            .filter(|(_, kind)| *kind != MutationKind::CheckNotExists)
+            .map(|(key, _)| key.clone())
+    }
+
+    fn is_pessimistic(&self) -> bool {
+        matches!(self.options.kind, TransactionKind::Pessimistic(_))
+    }
+
+    /// Record the accesses of `keys` by a request which started at `start`, and the write
+    /// conflict its `result` reports, if hot keys are tracked.
+    fn track_hot_keys<'a, T>(
+        &self,
+        keys: impl IntoIterator<Item = &'a Key>,
+        start: Instant,
+        result: &Result<T>,
+    ) {
+        if let Some(tracker) = self.rpc.hot_key_tracker() {
+            tracker.record_accesses(keys, elapsed_since(self.rpc.as_ref(), start));
+            if let Err(e) = result {
+                tracker.record_error(e);
+            }
+        }
+    }
+
+    /// Record how long the transaction took to reach `outcome`, under its label.
+    fn observe_outcome(&self, outcome: &'static str) {
+        let label = self.options.label.as_deref().unwrap_or_default();
+        let duration = elapsed_since(self.rpc.as_ref(), self.start_instant);
+        observe_transaction(label, outcome, duration);
+    }
+
+    async fn start_auto_heartbeat(&mut self) {
+        debug!(self.logger, "starting auto_heartbeat");
+        if !self.options.heartbeat_option.is_auto_heartbeat() || self.is_heartbeat_started {
+            return;
         }
         self.is_heartbeat_started = true;
 
@@ -892,10 +2212,19 @@ impl<PdC: PdClient> Transaction<PdC> {
             HeartbeatOption::FixedTime(heartbeat_interval) => heartbeat_interval,
         };
         let start_instant = self.start_instant;
+        let clock = rpc.clock();
+        let stopped = match &self.registry {
+            Some(registry) => registry.stopped(),
+            None => CancellationToken::new(),
+        };
 
         let heartbeat_task = async move {
             loop {
-                tokio::time::sleep(heartbeat_interval).await;
+                clock.sleep(heartbeat_interval).await;
+                // A closed client no longer keeps its transactions alive.
+                if stopped.is_cancelled() {
+                    break;
+                }
                 {
                     let status = status.read().await;
                     if matches!(
@@ -910,7 +2239,7 @@ impl<PdC: PdClient> Transaction<PdC> {
                 let request = new_heart_beat_request(
                     start_ts.clone(),
                     primary_key.clone(),
-                    start_instant.elapsed().as_millis() as u64 + MAX_TTL,
+                    elapsed_since(rpc.as_ref(), start_instant).as_millis() as u64 + MAX_TTL,
                 );
                 let plan = PlanBuilder::new(rpc.clone(), request)
                     .retry_multi_region(region_backoff.clone())
@@ -921,7 +2250,7 @@ impl<PdC: PdClient> Transaction<PdC> {
             Ok::<(), Error>(())
         };
 
-        tokio::spawn(async {
+        spawn(self.rpc.as_ref(), async {
             if let Err(err) = heartbeat_task.await {
                 log::error!("Error: While sending heartbeat. {}", err);
             }
@@ -953,8 +2282,83 @@ impl<PdC: PdClient> Drop for Transaction<PdC> {
     }
 }
 
+// The time since `start_instant`, by the clock of `rpc`.
+fn elapsed_since<PdC: PdClient>(rpc: &PdC, start_instant: Instant) -> Duration {
+    rpc.clock().now().saturating_duration_since(start_instant)
+}
+
+/// The locks of other transactions which `e` reports keys couldn't be locked for.
+fn held_locks(e: &Error) -> Vec<kvrpcpb::LockInfo> {
+    match e.root() {
+        Error::KeyError(error) => error.locked.clone().into_iter().collect(),
+        Error::MultipleKeyErrors(errors) | Error::ExtractedErrors(errors) => {
+            errors.iter().flat_map(held_locks).collect()
+        }
+        Error::PessimisticLockError { inner, .. } => held_locks(inner),
+        _ => Vec::new(),
+    }
+}
+
+/// Whether `e` reports that the request was sent to a peer which is not the leader of its region.
+fn is_not_leader(e: &Error) -> bool {
+    match e.root() {
+        Error::RegionError(e) => e.not_leader.is_some(),
+        _ => false,
+    }
+}
+
+/// Whether `e`, returned by a commit, reports that the committer rolled the transaction back.
+fn is_rolled_back(e: &Error) -> bool {
+    matches!(
+        e.root(),
+        Error::OperationCanceled | Error::CommitTsTooLarge { .. }
+    )
+}
+
+/// Whether `e` reports that a commit timestamp would exceed `max_commit_ts`.
+fn is_commit_ts_too_large(e: &Error) -> bool {
+    match e.root() {
+        Error::KeyError(e) => e.commit_ts_too_large.is_some(),
+        Error::MultipleKeyErrors(errors) | Error::ExtractedErrors(errors) => {
+            errors.iter().any(is_commit_ts_too_large)
+        }
+        _ => false,
+    }
+}
+
+/// Whether `e` leaves it unknown if the failed request took effect. A gRPC status reports that
+/// TiKV refused the request, unless its code shows the call may have been cut off after sending.
+fn is_undetermined(e: &Error) -> bool {
+    match e.root() {
+        Error::Grpc(_) | Error::DeadlineExceeded => true,
+        Error::GrpcAPI(status) => matches!(
+            status.code(),
+            tonic::Code::Unavailable
+                | tonic::Code::DeadlineExceeded
+                | tonic::Code::Cancelled
+                | tonic::Code::Unknown
+        ),
+        _ => false,
+    }
+}
+
+/// Run `future` to completion, unless `token` is cancelled first.
+async fn cancellable<T>(
+    token: Option<&CancellationToken>,
+    future: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    match token {
+        Some(token) => tokio::select! {
+            biased;
+            _ = token.cancelled() => Err(Error::OperationCanceled),
+            result = future => result,
+        },
+        None => future.await,
+    }
+}
+
 /// The default max TTL of a lock in milliseconds. Also called `ManagedLockTTL` in TiDB.
-const MAX_TTL: u64 = 20000;
+pub(crate) const MAX_TTL: u64 = 20000;
 /// The default TTL of a lock in milliseconds.
 const DEFAULT_LOCK_TTL: u64 = 3000;
 /// The default heartbeat interval
@@ -963,6 +2367,12 @@ const DEFAULT_HEARTBEAT_INTERVAL: Duration = Duration::from_millis(MAX_TTL / 2);
 /// each request below 16KB.
 pub const TXN_COMMIT_BATCH_SIZE: u64 = 16 * 1024;
 const TTL_FACTOR: f64 = 6000.0;
+/// Transactions with more keys than this use 2PC rather than async commit.
+const ASYNC_COMMIT_KEYS_LIMIT: usize = 256;
+/// Transactions whose secondary keys total more bytes than this use 2PC rather than async commit.
+const ASYNC_COMMIT_TOTAL_KEY_SIZE_LIMIT: usize = 4096;
+/// The number of keys fetched by each scan of [`Transaction::count`].
+const COUNT_SCAN_BATCH_SIZE: u32 = 10240;
 
 /// Optimistic or pessimistic transaction.
 #[derive(Clone, PartialEq, Debug)]
@@ -991,6 +2401,26 @@ pub struct TransactionOptions {
     check_level: CheckLevel,
     #[doc(hidden)]
     heartbeat_option: HeartbeatOption,
+    /// Where to report locks encountered by the transaction's requests.
+    lock_observer: Option<LockObserverHandle>,
+    /// Where to report changes of the size of the transaction's buffer.
+    buffer_observer: Option<BufferObserverHandle>,
+    /// Operations fail after this time.
+    deadline: Option<Instant>,
+    /// Operations fail once this much time has passed since the transaction began.
+    timeout: Option<Duration>,
+    /// The transaction must not commit after this timestamp.
+    max_commit_ts: Option<Timestamp>,
+    /// How the primary key is chosen among the keys the transaction writes.
+    primary_key_strategy: PrimaryKeyStrategy,
+    /// Keys whose secondary locks are released first when committing, hottest first.
+    commit_priority: Vec<Key>,
+    /// The name of the logical operation the transaction performs.
+    label: Option<String>,
+    /// Whether reads for update skip keys locked by other transactions.
+    skip_locked: bool,
+    /// Where to record the regions the transaction's requests are sent to.
+    fan_out_tracer: Option<FanOutTracer>,
 }
 
 #[derive(Clone, PartialEq, Eq, Debug)]
@@ -1016,6 +2446,16 @@ impl TransactionOptions {
             retry_options: RetryOptions::default_optimistic(),
             check_level: CheckLevel::Panic,
             heartbeat_option: HeartbeatOption::FixedTime(DEFAULT_HEARTBEAT_INTERVAL),
+            lock_observer: None,
+            buffer_observer: None,
+            deadline: None,
+            timeout: None,
+            max_commit_ts: None,
+            primary_key_strategy: PrimaryKeyStrategy::FirstWritten,
+            commit_priority: Vec::new(),
+            label: None,
+            skip_locked: false,
+            fan_out_tracer: None,
         }
     }
 
@@ -1029,6 +2469,16 @@ impl TransactionOptions {
             retry_options: RetryOptions::default_pessimistic(),
             check_level: CheckLevel::Panic,
             heartbeat_option: HeartbeatOption::FixedTime(DEFAULT_HEARTBEAT_INTERVAL),
+            lock_observer: None,
+            buffer_observer: None,
+            deadline: None,
+            timeout: None,
+            max_commit_ts: None,
+            primary_key_strategy: PrimaryKeyStrategy::FirstWritten,
+            commit_priority: Vec::new(),
+            label: None,
+            skip_locked: false,
+            fan_out_tracer: None,
         }
     }
 
@@ -1081,6 +2531,149 @@ impl TransactionOptions {
         self
     }
 
+    /// Report each time the transaction blocks on, or resolves, another transaction's lock to
+    /// `observer`.
+    #[must_use]
+    pub fn lock_observer(mut self, observer: impl LockObserver + 'static) -> TransactionOptions {
+        self.lock_observer = Some(LockObserverHandle(Arc::new(observer)));
+        self
+    }
+
+    /// Report each change of the number of bytes the transaction holds in its buffer to
+    /// `observer`.
+    #[must_use]
+    pub fn buffer_observer(
+        mut self,
+        observer: impl BufferObserver + 'static,
+    ) -> TransactionOptions {
+        self.buffer_observer = Some(BufferObserverHandle(Arc::new(observer)));
+        self
+    }
+
+    /// Set a deadline for the whole transaction.
+    ///
+    /// Once the deadline has passed, any further operation on the transaction fails with
+    /// `Error::DeadlineExceeded` and `commit` will refuse to start. Requests which are in flight
+    /// at the deadline are abandoned. If that happens while committing the primary key, `commit`
+    /// returns an `Error::UndeterminedError`. Rolling back is not subject to the deadline.
+    #[must_use]
+    pub fn deadline(mut self, deadline: Instant) -> TransactionOptions {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Set a deadline for the whole transaction, relative to when the transaction begins.
+    ///
+    /// See [`deadline`](TransactionOptions::deadline) for details. If both are set, the earlier
+    /// of the two applies.
+    #[must_use]
+    pub fn timeout(mut self, timeout: Duration) -> TransactionOptions {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Never commit the transaction with a commit timestamp later than `max_commit_ts`.
+    ///
+    /// Use this to make sure a transaction commits before a fence, such as a schema change. If
+    /// the transaction cannot commit in time, it is rolled back and `commit` returns
+    /// `Error::CommitTsTooLarge`. Async commit falls back to 2PC if TiKV cannot honour the limit.
+    #[must_use]
+    pub fn max_commit_ts(mut self, max_commit_ts: Timestamp) -> TransactionOptions {
+        self.max_commit_ts = Some(max_commit_ts);
+        self
+    }
+
+    /// Choose the primary key of the transaction as `strategy` describes.
+    ///
+    /// The strategy applies to optimistic transactions, whose primary key is chosen when they
+    /// commit. A pessimistic transaction always uses the first key it locks, since that key's lock
+    /// is already held as the primary lock.
+    #[must_use]
+    pub fn primary_key_strategy(mut self, strategy: PrimaryKeyStrategy) -> TransactionOptions {
+        self.primary_key_strategy = strategy;
+        self
+    }
+
+    /// Release the locks on `keys` first when committing the secondary keys, hottest first.
+    ///
+    /// Secondary keys are committed region by region, and by default all regions are committed
+    /// together. With priority keys, the regions holding them are committed first, in the order
+    /// of the hottest key each holds, so that readers blocked on those keys are released before
+    /// the rest of the transaction's keys are committed.
+    #[must_use]
+    pub fn commit_priority(
+        mut self,
+        keys: impl IntoIterator<Item = impl Into<Key>>,
+    ) -> TransactionOptions {
+        self.commit_priority = keys.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Label the transaction with the name of the logical operation it performs, such as
+    /// `"checkout"` or `"reindex"`.
+    ///
+    /// The label is attached to the transaction's log messages, to its duration in the
+    /// `tikv_txn_duration_seconds` metric, and to the context of its requests as the SQL digest of
+    /// their resource group tag, so that TiKV can attribute their cost to the operation.
+    ///
+    /// Labels should come from a small, fixed set of names. Only the first 64 distinct labels of a
+    /// process are used in the metric; transactions with other labels are counted as `"other"`.
+    #[must_use]
+    pub fn label(mut self, label: impl Into<String>) -> TransactionOptions {
+        self.label = Some(label.into());
+        self
+    }
+
+    /// Make the scans for update of a pessimistic transaction skip the keys which other
+    /// transactions have locked, rather than wait for their locks to be released.
+    ///
+    /// This applies to [`scan_for_update`](Transaction::scan_for_update), which reports the keys
+    /// it skipped in [`ScanForUpdate::skipped`]. It suits the consumers of a job queue, each of
+    /// which claims the jobs no other consumer holds. Other reads for update, writes and
+    /// [`lock_keys`](Transaction::lock_keys) still wait for locks.
+    #[must_use]
+    pub fn skip_locked(mut self) -> TransactionOptions {
+        self.skip_locked = true;
+        self
+    }
+
+    /// Record which regions and stores the transaction's reads, locks and commit are sent to,
+    /// with the latency and retries of each region, into `tracer`.
+    ///
+    /// [`take`](FanOutTracer::take) the trace after an operation to see which region held it up.
+    /// Heartbeats are not traced.
+    #[must_use]
+    pub fn trace_fan_out(mut self, tracer: FanOutTracer) -> TransactionOptions {
+        self.fan_out_tracer = Some(tracer);
+        self
+    }
+
+    /// The label, encoded as the `sql_digest` of a `ResourceGroupTag` message, as TiKV's
+    /// resource metering expects.
+    fn resource_group_tag(&self) -> Option<Vec<u8>> {
+        self.label.as_ref().map(|label| {
+            let mut tag = Vec::new();
+            prost::encoding::bytes::encode(1, &label.as_bytes().to_vec(), &mut tag);
+            tag
+        })
+    }
+
+    fn deadline_for(&self, start_instant: Instant) -> Option<Instant> {
+        let timeout_deadline = self.timeout.map(|timeout| start_instant + timeout);
+        match (self.deadline, timeout_deadline) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    fn observer(&self) -> Option<Arc<dyn LockObserver>> {
+        self.lock_observer.as_ref().map(|handle| handle.0.clone())
+    }
+
+    fn buffer_size_observer(&self) -> Option<Arc<dyn BufferObserver>> {
+        self.buffer_observer.as_ref().map(|handle| handle.0.clone())
+    }
+
     fn push_for_update_ts(&mut self, for_update_ts: Timestamp) {
         match &mut self.kind {
             TransactionKind::Optimistic => unreachable!(),
@@ -1108,6 +2701,26 @@ impl TransactionOptions {
     }
 }
 
+/// Determines which key of a transaction is its primary key.
+///
+/// The primary key's lock records the state of the whole transaction: the transaction commits
+/// when its primary key commits, and other transactions which meet its locks check the primary
+/// key to resolve them. The default is the first key written.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum PrimaryKeyStrategy {
+    /// The first key written or locked.
+    FirstWritten,
+    /// The smallest key written or locked.
+    Smallest,
+    /// The given key. It is locked if the transaction does not write it, unless the transaction
+    /// writes no keys at all. If the transaction only checks that the key does not exist, the
+    /// first key written is used instead.
+    Explicit(Key),
+    /// The smallest key in the region which holds the most keys written or locked, so that the
+    /// primary key's region is the one most of the transaction's locks depend on.
+    MostColocated,
+}
+
 /// Determines what happens when a transaction is dropped without being rolled back or committed.
 ///
 /// The default is to panic.
@@ -1130,6 +2743,43 @@ impl HeartbeatOption {
     }
 }
 
+/// Statistics about committing a transaction, see [`Transaction::commit_stats`].
+///
+/// Secondary keys are committed in the background after `commit` returns, so the work done for
+/// them is not included; the time spent committing them is only recorded in the
+/// `tikv_txn_commit_phase_duration_seconds` histogram.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CommitStats {
+    /// The number of keys mutated by the transaction.
+    pub keys: usize,
+    /// The total size of the keys and values written, in bytes.
+    pub write_bytes: u64,
+    /// The number of distinct regions requests were sent to.
+    pub regions: usize,
+    /// The number of times a request was retried because of a region error.
+    pub region_retries: u32,
+    /// The number of times a request was retried after encountering locks.
+    pub lock_retries: u32,
+    /// Time spent prewriting (or committing, if one-phase commit was used).
+    pub prewrite_duration: Duration,
+    /// Time spent getting the commit timestamp from PD.
+    pub get_commit_ts_duration: Duration,
+    /// Time spent committing the primary key, once the commit timestamp was known.
+    pub commit_primary_duration: Duration,
+    /// Total time spent in `commit`.
+    pub commit_duration: Duration,
+}
+
+/// The keys locked by [`Transaction::scan_for_update`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ScanForUpdate {
+    /// The pairs locked and read, ordered by key.
+    pub pairs: Vec<KvPair>,
+    /// The keys left unlocked because other transactions hold their locks, ordered by key. Keys
+    /// are only skipped with [`TransactionOptions::skip_locked`].
+    pub skipped: Vec<Key>,
+}
+
 /// A struct wrapping the details of two-phase commit protocol (2PC).
 ///
 /// The two phases are `prewrite` and `commit`.
@@ -1150,13 +2800,67 @@ struct Committer<PdC: PdClient = PdRpcClient> {
     write_size: u64,
     start_instant: Instant,
     logger: Logger,
+    cancellation_token: Option<CancellationToken>,
+    #[new(default)]
+    retry_stats: RetryStats,
+    // Whether keys may have been prewritten, so that rolling back must also remove their locks.
+    #[new(default)]
+    prewritten: bool,
 }
 
 impl<PdC: PdClient> Committer<PdC> {
-    async fn commit(mut self) -> Result<Option<Timestamp>> {
+    /// Commit the transaction. `primary_commit_ts` is the commit timestamp sent for the primary
+    /// by an earlier attempt, if any, and is set once one is sent by this attempt.
+    async fn commit(
+        self,
+        stats: &mut CommitStats,
+        primary_commit_ts: &mut Option<Timestamp>,
+    ) -> Result<Option<Timestamp>> {
+        let retry_stats = self.retry_stats.clone();
+        stats.keys = self.mutations.len();
+        stats.write_bytes = self.write_size;
+
+        let commit_start = Instant::now();
+        let res = self.commit_inner(stats, primary_commit_ts).await;
+        stats.commit_duration = commit_start.elapsed();
+        stats.regions = retry_stats.region_count();
+        stats.region_retries = retry_stats.region_retries();
+        stats.lock_retries = retry_stats.lock_retries();
+        res
+    }
+
+    async fn commit_inner(
+        mut self,
+        stats: &mut CommitStats,
+        primary_commit_ts: &mut Option<Timestamp>,
+    ) -> Result<Option<Timestamp>> {
         debug!(self.logger, "committing");
+        self.prewritten = true;
 
-        let min_commit_ts = self.prewrite().await?;
+        if primary_commit_ts.is_some() {
+            // Every key was prewritten by the earlier attempt, which may also have committed the
+            // primary.
+            debug!(self.logger, "resuming the commit of the primary");
+            let commit_primary_start = Instant::now();
+            let res = self.resume_primary(primary_commit_ts).await;
+            stats.commit_primary_duration = commit_primary_start.elapsed();
+            observe_commit_phase("commit_primary", stats.commit_primary_duration);
+            return self.finish(res).await;
+        }
+
+        let prewrite_start = Instant::now();
+        let cancellation_token = self.cancellation_token.clone();
+        let min_commit_ts = cancellable(cancellation_token.as_ref(), self.prewrite()).await;
+        stats.prewrite_duration = prewrite_start.elapsed();
+        observe_commit_phase("prewrite", stats.prewrite_duration);
+        if let Err(Error::OperationCanceled) = min_commit_ts.as_ref().map_err(Error::root) {
+            // Nothing can have been committed yet, so it is safe to roll back. Any prewrites
+            // still in flight will be resolved by lock resolution once their locks expire.
+            debug!(self.logger, "commit canceled, rolling back");
+            self.rollback().await?;
+            return Err(Error::OperationCanceled);
+        }
+        let min_commit_ts = min_commit_ts?;
 
         fail_point!("after-prewrite", |_| {
             Err(Error::StringError(
@@ -1169,33 +2873,111 @@ impl<PdC: PdClient> Committer<PdC> {
             return Ok(min_commit_ts);
         }
 
-        let commit_ts = if self.options.async_commit {
-            // FIXME: min_commit_ts == 0 => fallback to normal 2PC
-            min_commit_ts.unwrap()
-        } else {
-            match self.commit_primary().await {
-                Ok(commit_ts) => commit_ts,
-                Err(e) => {
-                    return if self.undetermined {
-                        Err(Error::UndeterminedError(Box::new(e)))
-                    } else {
-                        Err(e)
-                    };
-                }
+        // If async commit was not possible, prewrite will set `async_commit` to false.
+        if self.options.async_commit {
+            return self.finish(Ok(min_commit_ts.unwrap())).await;
+        }
+        let res = self.commit_primary(stats, primary_commit_ts).await;
+        self.finish(res).await
+    }
+
+    /// Finish committing once the primary is committed with `res`, by committing the secondaries
+    /// in the background.
+    async fn finish(self, res: Result<Timestamp>) -> Result<Option<Timestamp>> {
+        let commit_ts = match res {
+            Ok(commit_ts) => commit_ts,
+            Err(e) if matches!(e.root(), Error::CommitTsTooLarge { .. }) => {
+                debug!(self.logger, "commit ts too large, rolling back");
+                self.rollback().await?;
+                return Err(e);
             }
-        };
-        tokio::spawn(self.commit_secondary(commit_ts.clone()).map(|res| {
-            if let Err(e) = res {
-                log::warn!("Failed to commit secondary keys: {}", e);
+            Err(e) => {
+                return if self.undetermined {
+                    Err(Error::UndeterminedError {
+                        source: Box::new(e),
+                        primary_key: self.primary_key.clone().unwrap().into(),
+                        start_ts: self.start_version.version(),
+                    })
+                } else {
+                    Err(e)
+                };
             }
-        }));
+        };
+        let rpc = self.rpc.clone();
+        let commit_secondary_start = Instant::now();
+        spawn(
+            rpc.as_ref(),
+            self.commit_secondary(commit_ts.clone()).map(move |res| {
+                observe_commit_phase("commit_secondary", commit_secondary_start.elapsed());
+                if let Err(e) = res {
+                    log::warn!("Failed to commit secondary keys: {}", e);
+                }
+            }),
+        );
         Ok(Some(commit_ts))
     }
 
     async fn prewrite(&mut self) -> Result<Option<Timestamp>> {
         debug!(self.logger, "prewriting");
+        if self.options.async_commit && !self.can_use_async_commit() {
+            debug!(self.logger, "too many keys for async commit, using 2PC");
+            self.options.async_commit = false;
+        }
+        let response = loop {
+            match self.prewrite_once().await {
+                Err(e) if self.options.async_commit && is_commit_ts_too_large(&e) => {
+                    debug!(self.logger, "commit ts too large for async commit, using 2PC");
+                    self.options.async_commit = false;
+                }
+                response => break response?,
+            }
+        };
+
+        if self.options.try_one_pc && response.len() == 1 {
+            if response[0].one_pc_commit_ts == 0 {
+                return Err(Error::OnePcFailure);
+            }
+
+            return Ok(Timestamp::try_from_version(response[0].one_pc_commit_ts));
+        }
+
+        self.options.try_one_pc = false;
+
+        // TiKV returns a zero `min_commit_ts` if it could not use async commit, e.g., because
+        // `max_commit_ts` would be exceeded. The locks are then normal 2PC locks.
+        if self.options.async_commit && response.iter().any(|r| r.min_commit_ts == 0) {
+            debug!(self.logger, "TiKV could not use async commit, using 2PC");
+            self.options.async_commit = false;
+        }
+
+        let min_commit_ts = response
+            .iter()
+            .map(|r| {
+                assert_eq!(r.one_pc_commit_ts, 0);
+                r.min_commit_ts
+            })
+            .max()
+            .map(Timestamp::from_version);
+
+        Ok(min_commit_ts)
+    }
+
+    fn can_use_async_commit(&self) -> bool {
+        if self.mutations.len() > ASYNC_COMMIT_KEYS_LIMIT {
+            return false;
+        }
+        let secondary_key_size: usize = self
+            .mutations
+            .iter()
+            .filter(|m| self.primary_key.as_ref().unwrap() != m.key.as_ref())
+            .map(|m| m.key.len())
+            .sum();
+        secondary_key_size <= ASYNC_COMMIT_TOTAL_KEY_SIZE_LIMIT
+    }
+
+    async fn prewrite_once(&mut self) -> Result<Vec<kvrpcpb::PrewriteResponse>> {
         let primary_lock = self.primary_key.clone().unwrap();
-        let elapsed = self.start_instant.elapsed().as_millis() as u64;
+        let elapsed = elapsed_since(self.rpc.as_ref(), self.start_instant).as_millis() as u64;
         let lock_ttl = self.calc_txn_lock_ttl();
         let mut request = match &self.options.kind {
             TransactionKind::Optimistic => new_prewrite_request(
@@ -1221,49 +3003,90 @@ impl<PdC: PdClient> Committer<PdC> {
             .filter(|m| self.primary_key.as_ref().unwrap() != m.key.as_ref())
             .map(|m| m.key.clone())
             .collect();
-        // FIXME set max_commit_ts and min_commit_ts
+        if let Some(max_commit_ts) = &self.options.max_commit_ts {
+            request.max_commit_ts = max_commit_ts.version();
+        }
+        // FIXME set min_commit_ts
 
         let plan = PlanBuilder::new(self.rpc.clone(), request)
+            .resource_group_tag(self.options.resource_group_tag())
+            .trace_fan_out(self.options.fan_out_tracer.clone())
+            .deadline(self.options.deadline_for(self.start_instant))
+            .record_retries(self.retry_stats.clone())
+            .observe_locks(self.options.observer())
             .resolve_lock(self.options.retry_options.lock_backoff.clone())
             .retry_multi_region(self.options.retry_options.region_backoff.clone())
             .merge(CollectError)
             .extract_error()
             .plan();
-        let response = plan.execute().await?;
+        plan.execute().await
+    }
 
-        if self.options.try_one_pc && response.len() == 1 {
-            if response[0].one_pc_commit_ts == 0 {
-                return Err(Error::OnePcFailure);
-            }
+    /// Commits the primary key and returns the commit version, which is recorded in
+    /// `primary_commit_ts` before it is sent.
+    async fn commit_primary(
+        &mut self,
+        stats: &mut CommitStats,
+        primary_commit_ts: &mut Option<Timestamp>,
+    ) -> Result<Timestamp> {
+        debug!(self.logger, "committing primary");
+        let get_commit_ts_start = Instant::now();
+        let commit_version = self.commit_ts().await;
+        stats.get_commit_ts_duration = get_commit_ts_start.elapsed();
+        observe_commit_phase("get_commit_ts", stats.get_commit_ts_duration);
+        let commit_version = commit_version?;
+        *primary_commit_ts = Some(commit_version.clone());
+        let commit_primary_start = Instant::now();
+        let res = self.commit_primary_at(commit_version).await;
+        stats.commit_primary_duration = commit_primary_start.elapsed();
+        observe_commit_phase("commit_primary", stats.commit_primary_duration);
+        res
+    }
 
-            return Ok(Timestamp::try_from_version(response[0].one_pc_commit_ts));
+    /// A fresh timestamp to commit the primary at, which must not exceed `max_commit_ts`.
+    async fn commit_ts(&self) -> Result<Timestamp> {
+        let commit_version = self.rpc.clone().get_timestamp().await?;
+        if let Some(max_commit_ts) = &self.options.max_commit_ts {
+            if commit_version.version() > max_commit_ts.version() {
+                return Err(Error::CommitTsTooLarge {
+                    commit_ts: commit_version.version(),
+                    max_commit_ts: max_commit_ts.version(),
+                });
+            }
         }
+        Ok(commit_version)
+    }
 
-        self.options.try_one_pc = false;
-
-        let min_commit_ts = response
-            .iter()
-            .map(|r| {
-                assert_eq!(r.one_pc_commit_ts, 0);
-                r.min_commit_ts
-            })
-            .max()
-            .map(Timestamp::from_version);
-
-        Ok(min_commit_ts)
+    /// Commits the primary key at `commit_version`, following the region's leader if it moves.
+    async fn commit_primary_at(&mut self, commit_version: Timestamp) -> Result<Timestamp> {
+        match self.send_primary_commit(&commit_version).await {
+            Err(e) if is_not_leader(&e) => {
+                // The region's leader kept moving until the region backoff ran out. Follow it
+                // once more, with a fresh backoff, and if that fails too, find out from the
+                // primary whether the commit took effect.
+                debug!(self.logger, "primary commit hit leader changes, retrying");
+                match self.send_primary_commit(&commit_version).await {
+                    Ok(()) => Ok(commit_version),
+                    Err(e) => self.check_primary_committed(e).await,
+                }
+            }
+            result => result.map(|()| commit_version),
+        }
     }
 
-    /// Commits the primary key and returns the commit version
-    async fn commit_primary(&mut self) -> Result<Timestamp> {
-        debug!(self.logger, "committing primary");
+    async fn send_primary_commit(&mut self, commit_version: &Timestamp) -> Result<()> {
         let primary_key = self.primary_key.clone().into_iter();
-        let commit_version = self.rpc.clone().get_timestamp().await?;
         let req = new_commit_request(
             primary_key,
             self.start_version.clone(),
             commit_version.clone(),
         );
         let plan = PlanBuilder::new(self.rpc.clone(), req)
+            .resource_group_tag(self.options.resource_group_tag())
+            .trace_fan_out(self.options.fan_out_tracer.clone())
+            .deadline(self.options.deadline_for(self.start_instant))
+            .record_retries(self.retry_stats.clone())
+            .observe_locks(self.options.observer())
             .resolve_lock(self.options.retry_options.lock_backoff.clone())
             .retry_multi_region(self.options.retry_options.region_backoff.clone())
             .extract_error()
@@ -1271,26 +3094,105 @@ impl<PdC: PdClient> Committer<PdC> {
         plan.execute()
             .inspect_err(|e| {
                 // We don't know whether the transaction is committed or not if we fail to receive
-                // the response. Then, we mark the transaction as undetermined and propagate the
-                // error to the user.
-                if let Error::Grpc(_) = e {
+                // the response (or give up waiting for it). Then, we mark the transaction as
+                // undetermined and propagate the error to the user.
+                if is_undetermined(e) {
                     self.undetermined = true;
                 }
             })
             .await?;
+        Ok(())
+    }
 
-        Ok(commit_version)
+    /// Resume committing the primary, which an earlier attempt tried to commit at
+    /// `primary_commit_ts`.
+    async fn resume_primary(
+        &mut self,
+        primary_commit_ts: &mut Option<Timestamp>,
+    ) -> Result<Timestamp> {
+        // Until the primary answers, the earlier attempt may or may not have taken effect.
+        self.undetermined = true;
+        match self.primary_status().await? {
+            TransactionStatusKind::Committed(commit_ts) => {
+                self.undetermined = false;
+                Ok(commit_ts)
+            }
+            TransactionStatusKind::RolledBack => {
+                self.undetermined = false;
+                Err(Error::KeyError(Box::new(kvrpcpb::KeyError {
+                    txn_not_found: Some(kvrpcpb::TxnNotFound {
+                        start_ts: self.start_version.version(),
+                        primary_key: self.primary_key.clone().unwrap().into(),
+                    }),
+                    ..Default::default()
+                })))
+            }
+            TransactionStatusKind::Locked(..) => {
+                // The earlier commit did not take effect, and readers may since have pushed the
+                // lock's min_commit_ts past its timestamp, so commit at a fresh one.
+                self.undetermined = false;
+                let commit_version = self.commit_ts().await?;
+                *primary_commit_ts = Some(commit_version.clone());
+                self.commit_primary_at(commit_version).await
+            }
+        }
+    }
+
+    /// Check the status of the transaction at its primary, after committing the primary failed
+    /// with `e`. Returns the commit timestamp if the transaction is committed, or else `e`.
+    async fn check_primary_committed(&mut self, e: Error) -> Result<Timestamp> {
+        let status = match self.primary_status().await {
+            Ok(status) => status,
+            Err(check_err) => {
+                debug!(self.logger, "checking the primary failed"; "error" => %check_err);
+                return Err(e);
+            }
+        };
+        match status {
+            TransactionStatusKind::Committed(commit_ts) => {
+                self.undetermined = false;
+                Ok(commit_ts)
+            }
+            TransactionStatusKind::RolledBack => {
+                self.undetermined = false;
+                Err(e)
+            }
+            // A commit request which failed in transit may still commit the lock.
+            TransactionStatusKind::Locked(..) => Err(e),
+        }
+    }
+
+    /// The status of the transaction, as recorded at its primary.
+    async fn primary_status(&self) -> Result<TransactionStatusKind> {
+        let primary_key = self.primary_key.clone().unwrap();
+        // Neither roll back nor push the lock: zero timestamps make TiKV only report the status.
+        let req = new_check_txn_status_request(
+            primary_key.into(),
+            self.start_version.version(),
+            0,
+            0,
+            false,
+            false,
+            false,
+        );
+        let plan = PlanBuilder::new(self.rpc.clone(), req)
+            .retry_multi_region(self.options.retry_options.region_backoff.clone())
+            .merge(CollectSingle)
+            .extract_error()
+            .post_process_default()
+            .plan();
+        Ok(plan.execute().await?.kind)
     }
 
-    async fn commit_secondary(self, commit_version: Timestamp) -> Result<()> {
+    async fn commit_secondary(mut self, commit_version: Timestamp) -> Result<()> {
         debug!(self.logger, "committing secondary");
         let mutations_len = self.mutations.len();
         let primary_only = mutations_len == 1;
         #[cfg(not(feature = "integration-tests"))]
-        let mutations = self.mutations.into_iter();
+        let mutations = std::mem::take(&mut self.mutations).into_iter();
 
         #[cfg(feature = "integration-tests")]
-        let mutations = self.mutations.into_iter().take({
+        let mutations = std::mem::take(&mut self.mutations).into_iter().take({
             // Truncate mutation to a new length as `percent/100`.
             // Return error when truncate to zero.
             let logger = self.logger.clone();
@@ -1318,25 +3220,67 @@ impl<PdC: PdClient> Committer<PdC> {
             fp()?
         });
 
-        let req = if self.options.async_commit {
-            let keys = mutations.map(|m| m.key.into());
-            new_commit_request(keys, self.start_version, commit_version)
+        let keys: Vec<Key> = if self.options.async_commit {
+            mutations.map(|m| m.key.into()).collect()
         } else if primary_only {
             return Ok(());
         } else {
-            let primary_key = self.primary_key.unwrap();
-            let keys = mutations
+            let primary_key = self.primary_key.as_ref().unwrap();
+            mutations
                 .map(|m| m.key.into())
-                .filter(|key| &primary_key != key);
-            new_commit_request(keys, self.start_version, commit_version)
+                .filter(|key| primary_key != key)
+                .collect()
         };
-        let plan = PlanBuilder::new(self.rpc, req)
-            .resolve_lock(self.options.retry_options.lock_backoff)
-            .retry_multi_region(self.options.retry_options.region_backoff)
-            .extract_error()
-            .plan();
-        plan.execute().await?;
-        Ok(())
+        if self.options.commit_priority.is_empty() {
+            return self.commit_keys(keys, commit_version).await;
+        }
+
+        let priority: HashMap<&Key, usize> = self
+            .options
+            .commit_priority
+            .iter()
+            .enumerate()
+            .map(|(rank, key)| (key, rank))
+            .collect();
+        let groups: Vec<(RegionWithLeader, Vec<Key>)> = self
+            .rpc
+            .clone()
+            .group_keys_by_region(keys.into_iter())
+            .try_collect()
+            .await?;
+        let mut hot = Vec::new();
+        let mut cold = Vec::new();
+        for (_, keys) in groups {
+            match keys.iter().filter_map(|key| priority.get(key)).min() {
+                Some(rank) => hot.push((*rank, keys)),
+                None => cold.extend(keys),
+            }
+        }
+        hot.sort_by_key(|(rank, _)| *rank);
+        // The requests of the hot regions are sent in the order of their hottest keys.
+        let hot = hot
+            .into_iter()
+            .map(|(_, keys)| self.commit_keys(keys, commit_version.clone()));
+        future::try_join_all(hot).await?;
+        self.commit_keys(cold, commit_version).await
+    }
+
+    /// Commit `keys`, which are not the primary key unless the transaction uses async commit.
+    async fn commit_keys(&self, keys: Vec<Key>, commit_version: Timestamp) -> Result<()> {
+        if keys.is_empty() {
+            return Ok(());
+        }
+        let req = new_commit_request(keys.into_iter(), self.start_version.clone(), commit_version);
+        let plan = PlanBuilder::new(self.rpc.clone(), req)
+            .resource_group_tag(self.options.resource_group_tag())
+            .trace_fan_out(self.options.fan_out_tracer.clone())
+            .observe_locks(self.options.observer())
+            .resolve_lock(self.options.retry_options.lock_backoff.clone())
+            .retry_multi_region(self.options.retry_options.region_backoff.clone())
+            .extract_error()
+            .plan();
+        plan.execute().await?;
+        Ok(())
     }
 
     async fn rollback(self) -> Result<()> {
@@ -1344,30 +3288,47 @@ impl<PdC: PdClient> Committer<PdC> {
         if self.options.kind == TransactionKind::Optimistic && self.mutations.is_empty() {
             return Ok(());
         }
-        let keys = self
+        let keys: Vec<Key> = self
             .mutations
             .into_iter()
-            .map(|mutation| mutation.key.into());
-        match self.options.kind {
-            TransactionKind::Optimistic => {
-                let req = new_batch_rollback_request(keys, self.start_version);
-                let plan = PlanBuilder::new(self.rpc, req)
-                    .resolve_lock(self.options.retry_options.lock_backoff)
-                    .retry_multi_region(self.options.retry_options.region_backoff)
-                    .extract_error()
-                    .plan();
-                plan.execute().await?;
-            }
-            TransactionKind::Pessimistic(for_update_ts) => {
-                let req = new_pessimistic_rollback_request(keys, self.start_version, for_update_ts);
-                let plan = PlanBuilder::new(self.rpc, req)
-                    .resolve_lock(self.options.retry_options.lock_backoff)
-                    .retry_multi_region(self.options.retry_options.region_backoff)
-                    .extract_error()
-                    .plan();
-                plan.execute().await?;
+            .map(|mutation| mutation.key.into())
+            .collect();
+        let lock_observer = self.options.observer();
+        let resource_group_tag = self.options.resource_group_tag();
+        let fan_out_tracer = self.options.fan_out_tracer.clone();
+        // Every key of a pessimistic transaction is locked before it is buffered, so this
+        // releases all of its pessimistic locks, including those of keys which are never
+        // prewritten.
+        if let TransactionKind::Pessimistic(for_update_ts) = self.options.kind.clone() {
+            let req = new_pessimistic_rollback_request(
+                keys.clone().into_iter(),
+                self.start_version.clone(),
+                for_update_ts,
+            );
+            let plan = PlanBuilder::new(self.rpc.clone(), req)
+                .resource_group_tag(resource_group_tag.clone())
+                .trace_fan_out(fan_out_tracer.clone())
+                .observe_locks(lock_observer.clone())
+                .resolve_lock(self.options.retry_options.lock_backoff.clone())
+                .retry_multi_region(self.options.retry_options.region_backoff.clone())
+                .extract_error()
+                .plan();
+            plan.execute().await?;
+            if !self.prewritten {
+                return Ok(());
             }
         }
+        // A pessimistic rollback leaves the locks of prewritten keys in place.
+        let req = new_batch_rollback_request(keys.into_iter(), self.start_version);
+        let plan = PlanBuilder::new(self.rpc, req)
+            .resource_group_tag(resource_group_tag)
+            .trace_fan_out(fan_out_tracer)
+            .observe_locks(lock_observer)
+            .resolve_lock(self.options.retry_options.lock_backoff)
+            .retry_multi_region(self.options.retry_options.region_backoff)
+            .extract_error()
+            .plan();
+        plan.execute().await?;
         Ok(())
     }
 
@@ -1383,7 +3344,7 @@ impl<PdC: PdClient> Committer<PdC> {
 }
 
 #[derive(PartialEq, Eq)]
-enum TransactionStatus {
+pub(crate) enum TransactionStatus {
     /// The transaction is read-only [`Snapshot`](super::Snapshot), no need to commit or rollback or panic on drop.
     ReadOnly,
     /// The transaction have not been committed or rolled back.
@@ -1403,23 +3364,55 @@ enum TransactionStatus {
 #[cfg(test)]
 mod tests {
     use std::any::Any;
+    use std::collections::HashSet;
     use std::io;
     use std::sync::atomic::AtomicUsize;
     use std::sync::atomic::Ordering;
     use std::sync::Arc;
+    use std::sync::Mutex;
     use std::time::Duration;
 
     use fail::FailScenario;
+    use futures::future;
     use slog::Drain;
     use slog::Logger;
+    use tikv_client_proto::errorpb;
     use tikv_client_proto::kvrpcpb;
     use tikv_client_proto::pdpb::Timestamp;
 
+    use crate::backoff::Backoff;
+    use crate::backoff::DEFAULT_REGION_BACKOFF;
     use crate::mock::MockKvClient;
     use crate::mock::MockPdClient;
+    use crate::pd::PdClient;
+    use crate::request::Plan;
+    use crate::request::PlanBuilder;
+    use crate::simulation::Simulation;
+    use crate::transaction::transaction::MAX_TTL;
     use crate::transaction::HeartbeatOption;
+    use crate::transaction::ReadCache;
+    use crate::value_format::ValueFormat;
+    use crate::BoundRange;
+    use crate::BufferedMutation;
+    use crate::CheckLevel;
+    use crate::Compression;
+    use crate::ConflictKind;
+    use crate::Config;
+    use crate::Error;
+    use crate::ErrorCode;
+    use crate::HotKeyTracking;
+    use crate::Key;
+    use crate::KvPair;
+    use crate::MockClock;
+    use crate::MutationKind;
+    use crate::PrimaryKeyStrategy;
+    use crate::RetryOptions;
+    use crate::Snapshot;
+    use crate::TimestampExt;
     use crate::Transaction;
     use crate::TransactionOptions;
+    use crate::TransactionState;
+    use crate::WriteConflict;
 
     #[tokio::test]
     async fn test_optimistic_heartbeat() -> Result<(), io::Error> {
@@ -1514,4 +3507,1208 @@ mod tests {
         heartbeat_txn_handle.await.unwrap();
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_heartbeat_with_mock_clock() {
+        let clock = Arc::new(MockClock::new());
+        let ttls = Arc::new(Mutex::new(Vec::new()));
+        let ttls_cloned = ttls.clone();
+        let pd_client = Arc::new(
+            MockPdClient::new(MockKvClient::with_dispatch_hook(move |req: &dyn Any| {
+                if let Some(req) = req.downcast_ref::<kvrpcpb::TxnHeartBeatRequest>() {
+                    ttls_cloned.lock().unwrap().push(req.advise_lock_ttl);
+                    Ok(Box::<kvrpcpb::TxnHeartBeatResponse>::default() as Box<dyn Any>)
+                } else {
+                    Ok(Box::<kvrpcpb::PessimisticLockResponse>::default() as Box<dyn Any>)
+                }
+            }))
+            .with_clock(clock.clone()),
+        );
+        let mut txn = Transaction::new(
+            Timestamp::default(),
+            pd_client,
+            TransactionOptions::new_pessimistic()
+                .drop_check(CheckLevel::None)
+                .heartbeat_option(HeartbeatOption::FixedTime(Duration::from_secs(10))),
+            Logger::root(slog::Discard, o!()),
+        );
+        txn.put(vec![1], vec![1]).await.unwrap();
+
+        // Each heartbeat extends the primary lock's TTL by the time since the transaction began.
+        for elapsed in [10_000, 20_000] {
+            while clock.sleepers() == 0 {
+                tokio::task::yield_now().await;
+            }
+            assert!(ttls.lock().unwrap().len() < 2);
+            clock.advance(Duration::from_secs(10));
+            while clock.sleepers() == 0 {
+                tokio::task::yield_now().await;
+            }
+            assert_eq!(ttls.lock().unwrap().last(), Some(&(elapsed + MAX_TTL)));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_commit_stats() {
+        let logger = Logger::root(slog::Discard, o!());
+        let pd_client = Arc::new(MockPdClient::new(MockKvClient::with_dispatch_hook(
+            move |req: &dyn Any| {
+                if req.downcast_ref::<kvrpcpb::PrewriteRequest>().is_some() {
+                    Ok(Box::<kvrpcpb::PrewriteResponse>::default() as Box<dyn Any>)
+                } else {
+                    Ok(Box::<kvrpcpb::CommitResponse>::default() as Box<dyn Any>)
+                }
+            },
+        )));
+        let mut txn = Transaction::new(
+            Timestamp::default(),
+            pd_client,
+            TransactionOptions::new_optimistic().heartbeat_option(HeartbeatOption::NoHeartbeat),
+            logger,
+        );
+        assert!(txn.commit_stats().is_none());
+        txn.put(vec![1], vec![1, 2]).await.unwrap();
+        txn.put(vec![20], vec![3]).await.unwrap();
+        txn.commit().await.unwrap();
+
+        let stats = txn.commit_stats().unwrap();
+        assert_eq!(stats.keys, 2);
+        assert!(stats.write_bytes > 0);
+        assert_eq!(stats.regions, 2);
+        assert_eq!(stats.region_retries, 0);
+        assert_eq!(stats.lock_retries, 0);
+        assert!(stats.commit_duration >= stats.prewrite_duration);
+        let phases =
+            stats.prewrite_duration + stats.get_commit_ts_duration + stats.commit_primary_duration;
+        assert!(stats.commit_duration >= phases);
+    }
+
+    #[tokio::test]
+    async fn test_commit_and_rollback_hooks() {
+        let logger = Logger::root(slog::Discard, o!());
+        let pd_client = Arc::new(MockPdClient::new(MockKvClient::with_dispatch_hook(
+            move |req: &dyn Any| {
+                if req.downcast_ref::<kvrpcpb::PrewriteRequest>().is_some() {
+                    Ok(Box::<kvrpcpb::PrewriteResponse>::default() as Box<dyn Any>)
+                } else if req.downcast_ref::<kvrpcpb::BatchRollbackRequest>().is_some() {
+                    Ok(Box::<kvrpcpb::BatchRollbackResponse>::default() as Box<dyn Any>)
+                } else {
+                    Ok(Box::<kvrpcpb::CommitResponse>::default() as Box<dyn Any>)
+                }
+            },
+        )));
+        let options =
+            TransactionOptions::new_optimistic().heartbeat_option(HeartbeatOption::NoHeartbeat);
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        let mut txn = Transaction::new(
+            Timestamp::default(),
+            pd_client.clone(),
+            options.clone(),
+            logger.clone(),
+        );
+        let committed = calls.clone();
+        txn.on_committed(move |commit_ts| {
+            assert!(commit_ts.is_some());
+            committed.fetch_add(1, Ordering::SeqCst);
+        });
+        txn.on_rolled_back(|| panic!("rolled back a committed transaction"));
+        txn.put(vec![1], vec![1]).await.unwrap();
+        txn.commit().await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        let mut txn = Transaction::new(Timestamp::default(), pd_client, options, logger);
+        let rolled_back = calls.clone();
+        txn.on_rolled_back(move || {
+            rolled_back.fetch_add(10, Ordering::SeqCst);
+        });
+        txn.on_committed(|_| panic!("committed a rolled back transaction"));
+        txn.put(vec![1], vec![1]).await.unwrap();
+        txn.rollback().await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 11);
+    }
+
+    #[tokio::test]
+    async fn test_deadline() {
+        let logger = Logger::root(slog::Discard, o!());
+        let pd_client = Arc::new(MockPdClient::new(MockKvClient::with_dispatch_hook(
+            move |req: &dyn Any| {
+                if req.downcast_ref::<kvrpcpb::BatchRollbackRequest>().is_some() {
+                    Ok(Box::<kvrpcpb::BatchRollbackResponse>::default() as Box<dyn Any>)
+                } else {
+                    panic!("unexpected request after the deadline")
+                }
+            },
+        )));
+        let mut txn = Transaction::new(
+            Timestamp::default(),
+            pd_client,
+            TransactionOptions::new_optimistic()
+                .heartbeat_option(HeartbeatOption::NoHeartbeat)
+                .timeout(Duration::from_secs(3600))
+                .deadline(std::time::Instant::now()),
+            logger,
+        );
+        assert!(matches!(
+            txn.get(vec![1]).await,
+            Err(crate::Error::DeadlineExceeded)
+        ));
+        assert!(matches!(
+            txn.put(vec![1], vec![1]).await,
+            Err(crate::Error::DeadlineExceeded)
+        ));
+        txn.lock_keys(vec![vec![1]]).await.unwrap_err();
+        assert!(matches!(
+            txn.commit().await,
+            Err(crate::Error::DeadlineExceeded)
+        ));
+        txn.rollback().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_deadline_with_lock() {
+        // The key stays locked by a live transaction, and the lock backoff would wait for it far
+        // longer than the deadline allows.
+        let pd_client = Arc::new(MockPdClient::new(MockKvClient::with_dispatch_hook(
+            move |req: &dyn Any| {
+                if req.downcast_ref::<kvrpcpb::GetRequest>().is_some() {
+                    let resp = kvrpcpb::GetResponse {
+                        error: Some(kvrpcpb::KeyError {
+                            locked: Some(kvrpcpb::LockInfo {
+                                key: vec![1],
+                                primary_lock: vec![1],
+                                lock_ttl: 3_600_000,
+                                ..Default::default()
+                            }),
+                            ..Default::default()
+                        }),
+                        ..Default::default()
+                    };
+                    Ok(Box::new(resp) as Box<dyn Any>)
+                } else {
+                    Ok(Box::<kvrpcpb::BatchRollbackResponse>::default() as Box<dyn Any>)
+                }
+            },
+        )));
+        let lock_backoff = Backoff::no_jitter_backoff(60_000, 60_000, 10);
+        let start = std::time::Instant::now();
+        let mut txn = Transaction::new(
+            Timestamp::default(),
+            pd_client,
+            TransactionOptions::new_optimistic()
+                .heartbeat_option(HeartbeatOption::NoHeartbeat)
+                .retry_options(RetryOptions::new(DEFAULT_REGION_BACKOFF, lock_backoff))
+                .deadline(start + Duration::from_millis(100)),
+            Logger::root(slog::Discard, o!()),
+        );
+        let e = txn.get(vec![1]).await.unwrap_err();
+        assert!(matches!(e.root(), Error::DeadlineExceeded), "{e:?}");
+        assert!(start.elapsed() < Duration::from_secs(10));
+        txn.rollback().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_timeout_with_mock_clock() {
+        let clock = Arc::new(MockClock::new());
+        let pd_client = Arc::new(
+            MockPdClient::new(MockKvClient::with_dispatch_hook(move |req: &dyn Any| {
+                if req.downcast_ref::<kvrpcpb::GetRequest>().is_some() {
+                    Ok(Box::<kvrpcpb::GetResponse>::default() as Box<dyn Any>)
+                } else {
+                    Ok(Box::<kvrpcpb::BatchRollbackResponse>::default() as Box<dyn Any>)
+                }
+            }))
+            .with_clock(clock.clone()),
+        );
+        let mut txn = Transaction::new(
+            Timestamp::default(),
+            pd_client,
+            TransactionOptions::new_optimistic()
+                .heartbeat_option(HeartbeatOption::NoHeartbeat)
+                .timeout(Duration::from_secs(10)),
+            Logger::root(slog::Discard, o!()),
+        );
+        txn.get(vec![1]).await.unwrap();
+        clock.advance(Duration::from_secs(10));
+        assert!(matches!(
+            txn.get(vec![2]).await,
+            Err(crate::Error::DeadlineExceeded)
+        ));
+        txn.rollback().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_cancellation() {
+        let logger = Logger::root(slog::Discard, o!());
+        let pd_client = Arc::new(MockPdClient::new(MockKvClient::with_dispatch_hook(
+            move |req: &dyn Any| {
+                if req.downcast_ref::<kvrpcpb::BatchRollbackRequest>().is_some() {
+                    Ok(Box::<kvrpcpb::BatchRollbackResponse>::default() as Box<dyn Any>)
+                } else {
+                    panic!("unexpected request after cancellation")
+                }
+            },
+        )));
+        let token = tokio_util::sync::CancellationToken::new();
+        let mut txn = Transaction::new(
+            Timestamp::default(),
+            pd_client,
+            TransactionOptions::new_optimistic().heartbeat_option(HeartbeatOption::NoHeartbeat),
+            logger,
+        );
+        txn.set_cancellation_token(token.child_token());
+        txn.put(vec![1], vec![1]).await.unwrap();
+        token.cancel();
+        assert!(matches!(
+            txn.get(vec![2]).await,
+            Err(crate::Error::OperationCanceled)
+        ));
+        assert!(matches!(
+            txn.commit().await,
+            Err(crate::Error::OperationCanceled)
+        ));
+
+        let in_flight = super::cancellable(Some(&token), future::pending::<crate::Result<()>>());
+        assert!(matches!(
+            in_flight.await,
+            Err(crate::Error::OperationCanceled)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_cancellation_rolls_back() {
+        // Whether it is canceled before commit starts or while prewriting, the commit rolls the
+        // transaction back, so it can be dropped without rolling it back by hand.
+        for in_flight in [false, true] {
+            let token = tokio_util::sync::CancellationToken::new();
+            let token_cloned = token.clone();
+            let rollbacks = Arc::new(AtomicUsize::new(0));
+            let rollbacks_cloned = rollbacks.clone();
+            let pd_client = Arc::new(MockPdClient::new(MockKvClient::with_dispatch_hook(
+                move |req: &dyn Any| {
+                    if req.downcast_ref::<kvrpcpb::PrewriteRequest>().is_some() {
+                        assert!(in_flight, "prewrite after cancellation");
+                        // The prewrite is canceled while it backs off from a busy store.
+                        token_cloned.cancel();
+                        let resp = kvrpcpb::PrewriteResponse {
+                            region_error: Some(errorpb::Error {
+                                server_is_busy: Some(errorpb::ServerIsBusy {
+                                    backoff_ms: 60_000,
+                                    ..Default::default()
+                                }),
+                                ..Default::default()
+                            }),
+                            ..Default::default()
+                        };
+                        Ok(Box::new(resp) as Box<dyn Any>)
+                    } else if req.downcast_ref::<kvrpcpb::BatchRollbackRequest>().is_some() {
+                        rollbacks_cloned.fetch_add(1, Ordering::SeqCst);
+                        Ok(Box::<kvrpcpb::BatchRollbackResponse>::default() as Box<dyn Any>)
+                    } else {
+                        panic!("unexpected request")
+                    }
+                },
+            )));
+            let mut txn = Transaction::new(
+                Timestamp::default(),
+                pd_client,
+                TransactionOptions::new_optimistic()
+                    .heartbeat_option(HeartbeatOption::NoHeartbeat),
+                Logger::root(slog::Discard, o!()),
+            );
+            txn.set_cancellation_token(token.child_token());
+            txn.put(vec![1], vec![1]).await.unwrap();
+            if !in_flight {
+                token.cancel();
+            }
+            let e = txn.commit().await.unwrap_err();
+            assert!(matches!(e, Error::OperationCanceled), "{e:?}");
+            assert_eq!(rollbacks.load(Ordering::SeqCst), 1);
+            assert!(matches!(
+                txn.rollback().await,
+                Err(Error::OperationAfterCommitError)
+            ));
+            // With the default check level, dropping an active transaction panics.
+            drop(txn);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_async_commit_fallback() {
+        // Both ways TiKV can refuse async commit should result in a 2PC commit.
+        for commit_ts_too_large in [false, true] {
+            let prewrites = Arc::new(AtomicUsize::new(0));
+            let commits = Arc::new(AtomicUsize::new(0));
+            let prewrites_cloned = prewrites.clone();
+            let commits_cloned = commits.clone();
+            let pd_client = Arc::new(MockPdClient::new(MockKvClient::with_dispatch_hook(
+                move |req: &dyn Any| {
+                    if let Some(req) = req.downcast_ref::<kvrpcpb::PrewriteRequest>() {
+                        let attempt = prewrites_cloned.fetch_add(1, Ordering::SeqCst);
+                        let mut resp = kvrpcpb::PrewriteResponse::default();
+                        if commit_ts_too_large && attempt == 0 {
+                            assert!(req.use_async_commit);
+                            resp.errors = vec![kvrpcpb::KeyError {
+                                commit_ts_too_large: Some(Default::default()),
+                                ..Default::default()
+                            }];
+                        } else if commit_ts_too_large {
+                            assert!(!req.use_async_commit);
+                        }
+                        Ok(Box::new(resp) as Box<dyn Any>)
+                    } else {
+                        commits_cloned.fetch_add(1, Ordering::SeqCst);
+                        Ok(Box::<kvrpcpb::CommitResponse>::default() as Box<dyn Any>)
+                    }
+                },
+            )));
+            let mut txn = Transaction::new(
+                Timestamp::default(),
+                pd_client,
+                TransactionOptions::new_optimistic()
+                    .use_async_commit()
+                    .heartbeat_option(HeartbeatOption::NoHeartbeat),
+                Logger::root(slog::Discard, o!()),
+            );
+            txn.put(vec![1], vec![1]).await.unwrap();
+            txn.commit().await.unwrap();
+            let expected_prewrites = if commit_ts_too_large { 2 } else { 1 };
+            assert_eq!(prewrites.load(Ordering::SeqCst), expected_prewrites);
+            assert_eq!(commits.load(Ordering::SeqCst), 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_max_commit_ts() {
+        let pd_client = Arc::new(MockPdClient::new(MockKvClient::with_dispatch_hook(
+            move |req: &dyn Any| {
+                if let Some(req) = req.downcast_ref::<kvrpcpb::PrewriteRequest>() {
+                    assert_eq!(req.max_commit_ts, 42);
+                    Ok(Box::<kvrpcpb::PrewriteResponse>::default() as Box<dyn Any>)
+                } else {
+                    Ok(Box::<kvrpcpb::CommitResponse>::default() as Box<dyn Any>)
+                }
+            },
+        )));
+        let mut txn = Transaction::new(
+            Timestamp::default(),
+            pd_client,
+            TransactionOptions::new_optimistic()
+                .max_commit_ts(Timestamp::from_version(42))
+                .heartbeat_option(HeartbeatOption::NoHeartbeat),
+            Logger::root(slog::Discard, o!()),
+        );
+        txn.put(vec![1], vec![1]).await.unwrap();
+        txn.commit().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_max_commit_ts_exceeded() {
+        let sim = Simulation::new(46);
+        let count = |label| {
+            sim.requests()
+                .iter()
+                .filter(|request| request.label == label)
+                .count()
+        };
+        // The fence has passed by the time the transaction commits.
+        let fence = sim.pd_client().get_timestamp().await.unwrap();
+        let options = TransactionOptions::new_optimistic()
+            .max_commit_ts(fence.clone())
+            .heartbeat_option(HeartbeatOption::NoHeartbeat);
+        let mut txn = sim.begin_with_options(options).await.unwrap();
+        txn.put(vec![1], vec![1]).await.unwrap();
+        txn.put(vec![2], vec![2]).await.unwrap();
+        let e = txn.commit().await.unwrap_err();
+        match &e {
+            Error::CommitTsTooLarge {
+                commit_ts,
+                max_commit_ts,
+            } => {
+                assert!(*commit_ts > fence.version());
+                assert_eq!(*max_commit_ts, fence.version());
+            }
+            e => panic!("unexpected error: {e:?}"),
+        }
+        assert_eq!(e.code(), ErrorCode::CommitTsTooLarge);
+        assert!(!e.is_retryable());
+        // Nothing was committed, and the prewritten locks were rolled back rather than left for
+        // other transactions to resolve.
+        assert_eq!(count("kv_commit"), 0);
+        assert!(count("kv_batch_rollback") > 0);
+        let mut txn = sim.begin_optimistic().await.unwrap();
+        assert_eq!(txn.get(vec![1]).await.unwrap(), None);
+        txn.put(vec![2], vec![3]).await.unwrap();
+        txn.commit().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_max_commit_ts_refused() {
+        // TiKV refuses the limit for async commit, and then for 2PC too: the commit fails rather
+        // than committing past the limit.
+        let prewrites = Arc::new(AtomicUsize::new(0));
+        let prewrites_cloned = prewrites.clone();
+        let pd_client = Arc::new(MockPdClient::new(MockKvClient::with_dispatch_hook(
+            move |req: &dyn Any| {
+                if req.downcast_ref::<kvrpcpb::PrewriteRequest>().is_some() {
+                    prewrites_cloned.fetch_add(1, Ordering::SeqCst);
+                    let resp = kvrpcpb::PrewriteResponse {
+                        errors: vec![kvrpcpb::KeyError {
+                            commit_ts_too_large: Some(Default::default()),
+                            ..Default::default()
+                        }],
+                        ..Default::default()
+                    };
+                    Ok(Box::new(resp) as Box<dyn Any>)
+                } else if req.downcast_ref::<kvrpcpb::BatchRollbackRequest>().is_some() {
+                    Ok(Box::<kvrpcpb::BatchRollbackResponse>::default() as Box<dyn Any>)
+                } else {
+                    panic!("unexpected request")
+                }
+            },
+        )));
+        let mut txn = Transaction::new(
+            Timestamp::default(),
+            pd_client,
+            TransactionOptions::new_optimistic()
+                .use_async_commit()
+                .max_commit_ts(Timestamp::from_version(42))
+                .drop_check(CheckLevel::None)
+                .heartbeat_option(HeartbeatOption::NoHeartbeat),
+            Logger::root(slog::Discard, o!()),
+        );
+        txn.put(vec![1], vec![1]).await.unwrap();
+        let e = txn.commit().await.unwrap_err();
+        assert_eq!(e.code(), ErrorCode::CommitTsTooLarge, "{e:?}");
+        assert_eq!(prewrites.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_primary_key_strategy() {
+        let strategies = [
+            (PrimaryKeyStrategy::FirstWritten, vec![20]),
+            (PrimaryKeyStrategy::Smallest, vec![5]),
+            (PrimaryKeyStrategy::Explicit(vec![7].into()), vec![7]),
+            (PrimaryKeyStrategy::MostColocated, vec![11]),
+        ];
+        for (strategy, expected) in strategies {
+            let primary_locks = Arc::new(Mutex::new(HashSet::new()));
+            let primary_locks_cloned = primary_locks.clone();
+            let pd_client = Arc::new(MockPdClient::new(MockKvClient::with_dispatch_hook(
+                move |req: &dyn Any| {
+                    if let Some(req) = req.downcast_ref::<kvrpcpb::PrewriteRequest>() {
+                        let primary_lock = req.primary_lock.clone();
+                        primary_locks_cloned.lock().unwrap().insert(primary_lock);
+                        Ok(Box::<kvrpcpb::PrewriteResponse>::default() as Box<dyn Any>)
+                    } else {
+                        Ok(Box::<kvrpcpb::CommitResponse>::default() as Box<dyn Any>)
+                    }
+                },
+            )));
+            let mut txn = Transaction::new(
+                Timestamp::default(),
+                pd_client,
+                TransactionOptions::new_optimistic()
+                    .primary_key_strategy(strategy)
+                    .heartbeat_option(HeartbeatOption::NoHeartbeat),
+                Logger::root(slog::Discard, o!()),
+            );
+            // Regions of the mock: [..10], [10..250], [250..].
+            for key in [20, 5, 11, 12] {
+                txn.put(vec![key], vec![key]).await.unwrap();
+            }
+            txn.commit().await.unwrap();
+            let primary_locks = primary_locks.lock().unwrap();
+            assert_eq!(*primary_locks, HashSet::from([expected]));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_commit_priority() {
+        let commits = Arc::new(Mutex::new(Vec::new()));
+        let commits_cloned = commits.clone();
+        let pd_client = Arc::new(MockPdClient::new(MockKvClient::with_dispatch_hook(
+            move |req: &dyn Any| {
+                if let Some(req) = req.downcast_ref::<kvrpcpb::CommitRequest>() {
+                    commits_cloned.lock().unwrap().push(req.keys.clone());
+                    Ok(Box::<kvrpcpb::CommitResponse>::default() as Box<dyn Any>)
+                } else {
+                    Ok(Box::<kvrpcpb::PrewriteResponse>::default() as Box<dyn Any>)
+                }
+            },
+        )));
+        let mut txn = Transaction::new(
+            Timestamp::default(),
+            pd_client,
+            TransactionOptions::new_optimistic()
+                .commit_priority(vec![vec![30], vec![251]])
+                .heartbeat_option(HeartbeatOption::NoHeartbeat),
+            Logger::root(slog::Discard, o!()),
+        );
+        // Regions of the mock: [..10], [10..250], [250..].
+        for key in [1, 5, 20, 30, 251] {
+            txn.put(vec![key], vec![key]).await.unwrap();
+        }
+        txn.commit().await.unwrap();
+        // The secondary keys are committed in the background.
+        let committed = async {
+            while commits.lock().unwrap().len() < 4 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(10), committed)
+            .await
+            .unwrap();
+        assert_eq!(*commits.lock().unwrap(), vec![
+            vec![vec![1]],
+            vec![vec![20], vec![30]],
+            vec![vec![251]],
+            vec![vec![5]],
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_label() {
+        let tags = Arc::new(Mutex::new(Vec::new()));
+        let tags_cloned = tags.clone();
+        let pd_client = Arc::new(MockPdClient::new(MockKvClient::with_dispatch_hook(
+            move |req: &dyn Any| {
+                let mut tags = tags_cloned.lock().unwrap();
+                if let Some(req) = req.downcast_ref::<kvrpcpb::GetRequest>() {
+                    tags.push(req.context.clone().unwrap().resource_group_tag);
+                    Ok(Box::<kvrpcpb::GetResponse>::default() as Box<dyn Any>)
+                } else if let Some(req) = req.downcast_ref::<kvrpcpb::PrewriteRequest>() {
+                    tags.push(req.context.clone().unwrap().resource_group_tag);
+                    Ok(Box::<kvrpcpb::PrewriteResponse>::default() as Box<dyn Any>)
+                } else if let Some(req) = req.downcast_ref::<kvrpcpb::CommitRequest>() {
+                    tags.push(req.context.clone().unwrap().resource_group_tag);
+                    Ok(Box::<kvrpcpb::CommitResponse>::default() as Box<dyn Any>)
+                } else {
+                    unreachable!()
+                }
+            },
+        )));
+        let mut txn = Transaction::new(
+            Timestamp::default(),
+            pd_client,
+            TransactionOptions::new_optimistic()
+                .label("checkout")
+                .heartbeat_option(HeartbeatOption::NoHeartbeat),
+            Logger::root(slog::Discard, o!()),
+        );
+        assert_eq!(txn.label(), Some("checkout"));
+        txn.get(vec![1]).await.unwrap();
+        txn.put(vec![1], vec![1]).await.unwrap();
+        txn.commit().await.unwrap();
+        // The label is the first field, `sql_digest`, of an encoded `ResourceGroupTag`.
+        let mut tag = vec![0x0a, 8];
+        tag.extend_from_slice(b"checkout");
+        assert_eq!(*tags.lock().unwrap(), vec![tag; 3]);
+    }
+
+    #[tokio::test]
+    async fn test_commit_primary_grpc_error() {
+        // A call cut off in transit may have committed the primary; a refused one did not.
+        let cases = [
+            (tonic::Status::unavailable("connection reset"), true),
+            (tonic::Status::deadline_exceeded("timed out"), true),
+            (tonic::Status::invalid_argument("bad request"), false),
+            (tonic::Status::permission_denied("denied"), false),
+        ];
+        for (status, undetermined) in cases {
+            let code = status.code();
+            let pd_client = Arc::new(MockPdClient::new(MockKvClient::with_dispatch_hook(
+                move |req: &dyn Any| {
+                    if req.downcast_ref::<kvrpcpb::PrewriteRequest>().is_some() {
+                        Ok(Box::<kvrpcpb::PrewriteResponse>::default() as Box<dyn Any>)
+                    } else if req.downcast_ref::<kvrpcpb::CommitRequest>().is_some() {
+                        Err(Error::GrpcAPI(tonic::Status::new(code, "commit failed")))
+                    } else {
+                        panic!("unexpected request")
+                    }
+                },
+            )));
+            let mut txn = Transaction::new(
+                Timestamp::default(),
+                pd_client,
+                TransactionOptions::new_optimistic()
+                    .heartbeat_option(HeartbeatOption::NoHeartbeat),
+                Logger::root(slog::Discard, o!()),
+            );
+            txn.put(vec![1], vec![1]).await.unwrap();
+            match txn.commit().await.unwrap_err() {
+                Error::UndeterminedError {
+                    source,
+                    primary_key,
+                    ..
+                } => {
+                    assert!(undetermined, "{status:?}");
+                    assert!(
+                        matches!(source.root(), Error::GrpcAPI(_)),
+                        "{source:?}"
+                    );
+                    assert_eq!(primary_key, vec![1]);
+                }
+                e => {
+                    assert!(!undetermined, "{status:?}");
+                    assert!(matches!(e.root(), Error::GrpcAPI(_)), "{e:?}");
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_json_values() {
+        let pd_client = Arc::new(MockPdClient::new(MockKvClient::with_dispatch_hook(
+            |_: &dyn Any| unreachable!("buffered values should not be read from TiKV"),
+        )));
+        let mut txn = Transaction::new(
+            Timestamp::default(),
+            pd_client,
+            TransactionOptions::new_optimistic()
+                .drop_check(CheckLevel::None)
+                .heartbeat_option(HeartbeatOption::NoHeartbeat),
+            Logger::root(slog::Discard, o!()),
+        );
+        txn.put_json(vec![1], &vec![1u64, 2, 3]).await.unwrap();
+        assert_eq!(txn.get(vec![1]).await.unwrap(), Some(b"[1,2,3]".to_vec()));
+        assert_eq!(
+            txn.get_json::<Vec<u64>>(vec![1]).await.unwrap(),
+            Some(vec![1, 2, 3])
+        );
+
+        txn.put(vec![2], b"not json".to_vec()).await.unwrap();
+        assert!(matches!(
+            txn.get_json::<Vec<u64>>(vec![2]).await,
+            Err(Error::ValueCodecError { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_export_and_import_state() {
+        let prewritten = Arc::new(AtomicUsize::new(0));
+        let prewritten_cloned = prewritten.clone();
+        let pd_client = Arc::new(MockPdClient::new(MockKvClient::with_dispatch_hook(
+            move |req: &dyn Any| {
+                if let Some(req) = req.downcast_ref::<kvrpcpb::PrewriteRequest>() {
+                    assert_eq!(req.start_version, 7);
+                    assert_eq!(req.primary_lock, vec![1]);
+                    prewritten_cloned.fetch_add(req.mutations.len(), Ordering::SeqCst);
+                    Ok(Box::<kvrpcpb::PrewriteResponse>::default() as Box<dyn Any>)
+                } else {
+                    Ok(Box::<kvrpcpb::CommitResponse>::default() as Box<dyn Any>)
+                }
+            },
+        )));
+        let options = TransactionOptions::new_optimistic()
+            .drop_check(CheckLevel::None)
+            .heartbeat_option(HeartbeatOption::NoHeartbeat);
+        let mut txn = Transaction::new(
+            Timestamp::from_version(7),
+            pd_client.clone(),
+            options.clone(),
+            Logger::root(slog::Discard, o!()),
+        );
+        txn.put(vec![1], vec![1]).await.unwrap();
+        txn.delete(vec![2]).await.unwrap();
+        let state = txn.export_state().await.unwrap();
+        assert_eq!(state.start_ts, 7);
+        assert_eq!(state.for_update_ts, None);
+        assert_eq!(state.primary_key, Some(vec![1]));
+
+        let json = serde_json::to_string(&state).unwrap();
+        let state: TransactionState = serde_json::from_str(&json).unwrap();
+        assert!(matches!(
+            Transaction::from_state(
+                state.clone(),
+                pd_client.clone(),
+                TransactionOptions::new_pessimistic(),
+                Logger::root(slog::Discard, o!()),
+            ),
+            Err(Error::InvalidTransactionType)
+        ));
+
+        let mut txn = Transaction::from_state(
+            state,
+            pd_client,
+            options,
+            Logger::root(slog::Discard, o!()),
+        )
+        .unwrap();
+        assert_eq!(txn.get(vec![1]).await.unwrap(), Some(vec![1]));
+        txn.commit().await.unwrap();
+        assert_eq!(prewritten.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_count() {
+        let sim = Simulation::new(5);
+        sim.split(vec![0x20]);
+        let mut txn = sim.begin_optimistic().await.unwrap();
+        for i in 0..12_000u32 {
+            txn.put(i.to_be_bytes().to_vec(), vec![0]).await.unwrap();
+        }
+        txn.commit().await.unwrap();
+
+        let mut txn = sim.begin_optimistic().await.unwrap();
+        assert_eq!(txn.count(..).await.unwrap(), 12_000);
+        // Buffered writes are counted.
+        txn.delete(5u32.to_be_bytes().to_vec()).await.unwrap();
+        txn.put(vec![0xff], vec![0]).await.unwrap();
+        let range = 1u32.to_be_bytes().to_vec()..;
+        assert_eq!(txn.count(range).await.unwrap(), 11_999);
+        txn.rollback().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_read_cache() {
+        let sim = Simulation::new(16);
+        let mut txn = sim.begin_optimistic().await.unwrap();
+        txn.put(vec![1], vec![1]).await.unwrap();
+        txn.put(vec![2], vec![2]).await.unwrap();
+        txn.commit().await.unwrap();
+
+        let cache = Arc::new(ReadCache::new(10));
+        let timestamp = sim.pd_client().get_timestamp().await.unwrap();
+        let snapshot = || {
+            let options = TransactionOptions::new_optimistic().read_only();
+            let logger = Logger::root(slog::Discard, o!());
+            Transaction::new(timestamp.clone(), sim.pd_client(), options, logger)
+                .with_read_cache(Some(cache.clone()))
+        };
+        let reads = || {
+            let requests = sim.requests().into_iter();
+            requests
+                .filter(|request| matches!(request.label, "kv_get" | "kv_batch_get"))
+                .count()
+        };
+        let keys = [vec![1], vec![2], vec![3]];
+
+        let mut first = snapshot();
+        assert_eq!(first.get(vec![1]).await.unwrap(), Some(vec![1]));
+        assert_eq!(first.batch_get(keys.clone()).await.unwrap().count(), 2);
+        let before = reads();
+        // Another snapshot at the same timestamp reads through the cache.
+        let mut second = snapshot();
+        assert_eq!(second.get(vec![3]).await.unwrap(), None);
+        assert_eq!(second.batch_get(keys).await.unwrap().count(), 2);
+        assert_eq!(reads(), before);
+
+        // Writing a key drops its cached values.
+        let txn = sim.begin_optimistic().await.unwrap();
+        let mut txn = txn.with_read_cache(Some(cache.clone()));
+        txn.put(vec![1], vec![10]).await.unwrap();
+        txn.commit().await.unwrap();
+        assert_eq!(cache.get(&vec![1].into(), timestamp.version()), None);
+        assert!(cache.get(&vec![2].into(), timestamp.version()).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_batch_get_key_errors() {
+        let pd_client = Arc::new(MockPdClient::new(MockKvClient::with_dispatch_hook(
+            |req: &dyn Any| {
+                let req = req.downcast_ref::<kvrpcpb::BatchGetRequest>().unwrap();
+                // Key 1 exists, key 2 cannot be read, and key 3 does not exist.
+                let pairs = req.keys.iter().filter(|key| key[0] != 3).map(|key| {
+                    let mut pair = kvrpcpb::KvPair {
+                        key: key.clone(),
+                        ..Default::default()
+                    };
+                    if key[0] == 2 {
+                        pair.error = Some(kvrpcpb::KeyError {
+                            abort: "data is corrupted".to_owned(),
+                            ..Default::default()
+                        });
+                    } else {
+                        pair.value = key.clone();
+                    }
+                    pair
+                });
+                let resp = kvrpcpb::BatchGetResponse {
+                    pairs: pairs.collect(),
+                    ..Default::default()
+                };
+                Ok(Box::new(resp) as Box<dyn Any>)
+            },
+        )));
+        let snapshot = || {
+            let options = TransactionOptions::new_optimistic().read_only();
+            let logger = Logger::root(slog::Discard, o!());
+            Transaction::new(Timestamp::default(), pd_client.clone(), options, logger)
+        };
+
+        // The unreadable key is not reported as a key with an empty value.
+        let err = match snapshot().batch_get(vec![vec![1], vec![2], vec![3]]).await {
+            Ok(_) => panic!("the read of key 2 failed"),
+            Err(e) => e,
+        };
+        match err {
+            Error::MultipleKeyErrors(errors) => {
+                assert_eq!(errors.len(), 1);
+                assert_eq!(errors[0].context().unwrap().operation, Some("kv_batch_get"));
+            }
+            e => panic!("unexpected error: {e:?}"),
+        }
+
+        let mut snapshot = Snapshot::new(snapshot(), Logger::root(slog::Discard, o!()));
+        let keys = vec![vec![3], vec![2], vec![1], vec![3]];
+        let entries = snapshot.batch_get(keys).await.unwrap();
+        let keys: Vec<Vec<u8>> = entries.iter().map(|(key, _)| key.clone().into()).collect();
+        assert_eq!(keys, vec![vec![3], vec![2], vec![1]]);
+        assert_eq!(entries[0].1.as_ref().unwrap(), &None);
+        assert!(matches!(entries[1].1, Err(Error::KeyError(_))));
+        assert_eq!(entries[2].1.as_ref().unwrap(), &Some(vec![1]));
+    }
+
+    #[tokio::test]
+    async fn test_commit_with() {
+        let sim = Simulation::new(37);
+        let mut txn = sim.begin_optimistic().await.unwrap();
+        txn.put(vec![1], vec![10]).await.unwrap();
+        txn.delete(vec![2]).await.unwrap();
+        txn.commit_with(|pending| {
+            let keys: Vec<u8> = pending
+                .iter()
+                .map(|mutation| match mutation {
+                    BufferedMutation::Put { key, .. } | BufferedMutation::Delete { key } => key[0],
+                    _ => unreachable!(),
+                })
+                .collect();
+            vec![BufferedMutation::Put {
+                key: vec![100],
+                value: keys,
+            }]
+        })
+        .await
+        .unwrap();
+
+        let mut txn = sim.begin_optimistic().await.unwrap();
+        assert_eq!(txn.get(vec![1]).await.unwrap(), Some(vec![10]));
+        assert_eq!(txn.get(vec![100]).await.unwrap(), Some(vec![1, 2]));
+        txn.rollback().await.unwrap();
+
+        // The final mutations are checked like any other.
+        let mut txn = sim.begin_optimistic().await.unwrap();
+        txn.put(vec![3], vec![30]).await.unwrap();
+        let extra = |_: &[BufferedMutation]| {
+            vec![BufferedMutation::Insert {
+                key: vec![100],
+                value: vec![],
+            }]
+        };
+        assert!(txn.commit_with(extra).await.is_err());
+        let mut txn = sim.begin_optimistic().await.unwrap();
+        assert_eq!(txn.get(vec![3]).await.unwrap(), None);
+        txn.rollback().await.unwrap();
+    }
+
+    // The keys locked in `sim`, in order.
+    async fn locked_keys(sim: &Simulation) -> Vec<Vec<u8>> {
+        let pd = sim.pd_client();
+        let version = pd.clone().get_timestamp().await.unwrap();
+        let req = crate::transaction_lowering::new_scan_lock_request((..).into(), &version, 0);
+        let locks: Vec<kvrpcpb::LockInfo> = PlanBuilder::new(pd, req)
+            .retry_multi_region(DEFAULT_REGION_BACKOFF)
+            .merge(crate::request::Collect)
+            .plan()
+            .execute()
+            .await
+            .unwrap();
+        locks.into_iter().map(|lock| lock.key).collect()
+    }
+
+    #[tokio::test]
+    async fn test_rollback_pessimistic_locks() {
+        let sim = Simulation::new(39);
+        let mut txn = sim.begin_pessimistic().await.unwrap();
+        txn.lock_keys(vec![vec![1], vec![2], vec![3]])
+            .await
+            .unwrap();
+        txn.put(vec![4], vec![40]).await.unwrap();
+        assert_eq!(
+            locked_keys(&sim).await,
+            vec![vec![1], vec![2], vec![3], vec![4]]
+        );
+
+        // The primary key and written keys stay locked.
+        txn.rollback_pessimistic_locks(vec![vec![1], vec![2], vec![4], vec![5]])
+            .await
+            .unwrap();
+        assert_eq!(locked_keys(&sim).await, vec![vec![1], vec![3], vec![4]]);
+
+        // Rolling back releases every lock, including those of keys which are only locked.
+        txn.rollback().await.unwrap();
+        assert!(locked_keys(&sim).await.is_empty());
+
+        let mut txn = sim.begin_optimistic().await.unwrap();
+        assert!(matches!(
+            txn.rollback_pessimistic_locks(vec![vec![1]]).await,
+            Err(Error::InvalidTransactionType)
+        ));
+        txn.rollback().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_skip_locked() {
+        let sim = Simulation::new(40);
+        let mut txn = sim.begin_optimistic().await.unwrap();
+        for job in 1..=4 {
+            txn.put(vec![job], vec![job * 10]).await.unwrap();
+        }
+        txn.commit().await.unwrap();
+        let skip_locked = TransactionOptions::new_pessimistic().skip_locked();
+
+        let mut first = sim.begin_pessimistic().await.unwrap();
+        first
+            .batch_get_for_update(vec![vec![2], vec![3]])
+            .await
+            .unwrap();
+
+        let mut second = sim.begin_with_options(skip_locked).await.unwrap();
+        let claimed = second.scan_for_update(.., 2).await.unwrap();
+        assert_eq!(claimed, ScanForUpdate {
+            pairs: vec![
+                KvPair::new(vec![1], vec![10]),
+                KvPair::new(vec![4], vec![40]),
+            ],
+            skipped: vec![vec![2].into(), vec![3].into()],
+        });
+        first.rollback().await.unwrap();
+        second.rollback().await.unwrap();
+
+        // Without skipping locked keys, the scan locks every key.
+        let mut txn = sim.begin_pessimistic().await.unwrap();
+        let claimed = txn.scan_for_update(vec![2].., 10).await.unwrap();
+        assert_eq!(claimed.pairs.len(), 3);
+        assert!(claimed.skipped.is_empty());
+        txn.rollback().await.unwrap();
+
+        let mut txn = sim.begin_optimistic().await.unwrap();
+        assert!(matches!(
+            txn.scan_for_update(.., 1).await,
+            Err(Error::InvalidTransactionType)
+        ));
+        txn.rollback().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_write_conflict_details() {
+        let sim = Simulation::new(41);
+        let mut first = sim.begin_optimistic().await.unwrap();
+        let mut second = sim.begin_optimistic().await.unwrap();
+        first.put(vec![1], vec![1]).await.unwrap();
+        second.put(vec![1], vec![2]).await.unwrap();
+        let commit_ts = first.commit().await.unwrap().unwrap();
+
+        let e = second.commit().await.unwrap_err();
+        assert!(e.is_conflict() && e.is_retryable(), "{e:?}");
+        let conflict = e.write_conflict().unwrap();
+        assert_eq!(conflict, WriteConflict {
+            key: vec![1],
+            primary: vec![1],
+            start_ts: second.start_timestamp().version(),
+            conflict_start_ts: first.start_timestamp().version(),
+            conflict_commit_ts: commit_ts.version(),
+            kind: Some(ConflictKind::Optimistic),
+        });
+        second.rollback().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_hot_keys() {
+        let sim = Simulation::new(42);
+        sim.set_hot_key_tracking(HotKeyTracking::default().sample_interval(1));
+        let mut first = sim.begin_optimistic().await.unwrap();
+        let mut second = sim.begin_optimistic().await.unwrap();
+        first.get(vec![2]).await.unwrap();
+        first.put(vec![1], vec![1]).await.unwrap();
+        second.put(vec![1], vec![2]).await.unwrap();
+        first.commit().await.unwrap();
+        second.commit().await.unwrap_err();
+        second.rollback().await.unwrap();
+
+        let hot_keys = sim.hot_keys(5);
+        let keys: Vec<Key> = hot_keys.iter().map(|hot_key| hot_key.key.clone()).collect();
+        assert_eq!(keys, vec![Key::from(vec![1]), Key::from(vec![2])]);
+        assert_eq!(hot_keys[0].conflicts, 1);
+        assert_eq!(hot_keys[0].sampled_accesses, 2);
+        assert_eq!((hot_keys[1].conflicts, hot_keys[1].sampled_accesses), (0, 1));
+    }
+
+    #[tokio::test]
+    async fn test_multi_get() {
+        let sim = Simulation::new(17);
+        sim.split(vec![5]);
+        let mut txn = sim.begin_optimistic().await.unwrap();
+        for key in 0..10 {
+            txn.put(vec![key], vec![key]).await.unwrap();
+        }
+        txn.commit().await.unwrap();
+
+        let mut txn = sim.begin_optimistic().await.unwrap();
+        txn.delete(vec![1]).await.unwrap();
+        txn.delete(vec![7]).await.unwrap();
+        txn.put(vec![20], vec![20]).await.unwrap();
+        let keys = [vec![1], vec![4], vec![20], vec![30]];
+        let ranges = [vec![0]..vec![3], vec![6]..vec![30]];
+        let (values, ranges) = txn.multi_get(keys, ranges, 3).await.unwrap();
+        let mut values: Vec<Vec<u8>> = values.into_iter().map(|pair| pair.1).collect();
+        values.sort();
+        assert_eq!(values, vec![vec![4], vec![20]]);
+        let keys: Vec<Vec<Vec<u8>>> = ranges
+            .into_iter()
+            .map(|pairs| pairs.into_iter().map(|pair| pair.0.into()).collect())
+            .collect();
+        let expected = [vec![vec![0], vec![2]], vec![vec![6], vec![8], vec![9]]];
+        assert_eq!(keys, expected);
+
+        let no_ranges: [BoundRange; 0] = [];
+        let (values, ranges) = txn.multi_get([vec![2]], no_ranges, 3).await.unwrap();
+        assert_eq!((values.len(), ranges.len()), (1, 0));
+        txn.rollback().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_introspection() {
+        let sim = Simulation::new(18);
+        let mut txn = sim.begin_pessimistic().await.unwrap();
+        assert!(!txn.is_read_only());
+        assert!(txn.is_empty());
+        txn.put(vec![3], vec![0; 10]).await.unwrap();
+        txn.insert(vec![2], vec![0; 5]).await.unwrap();
+        txn.delete(vec![1]).await.unwrap();
+        txn.get_for_update(vec![4]).await.unwrap();
+        // Plain reads are not mutations.
+        txn.get(vec![5]).await.unwrap();
+
+        let mutations: Vec<_> = txn
+            .pending_mutations()
+            .map(|(key, kind)| (Vec::from(key.clone()), kind))
+            .collect();
+        let expected = [
+            (vec![1], MutationKind::Delete),
+            (vec![2], MutationKind::Insert),
+            (vec![3], MutationKind::Put),
+            (vec![4], MutationKind::Lock),
+        ];
+        assert_eq!(mutations, expected);
+        assert_eq!(txn.len(), 4);
+        assert_eq!(txn.write_size(), 3 + 15);
+        txn.rollback().await.unwrap();
+
+        let options = TransactionOptions::new_optimistic().read_only();
+        let snapshot = sim.begin_with_options(options).await.unwrap();
+        assert!(snapshot.is_read_only());
+    }
+
+    #[tokio::test]
+    async fn test_unset() {
+        let sim = Simulation::new(19);
+        let mut txn = sim.begin_optimistic().await.unwrap();
+        txn.put(vec![1], vec![1]).await.unwrap();
+        txn.put(vec![2], vec![2]).await.unwrap();
+        txn.commit().await.unwrap();
+
+        let mut txn = sim.begin_optimistic().await.unwrap();
+        txn.put(vec![1], vec![10]).await.unwrap();
+        txn.delete(vec![2]).await.unwrap();
+        assert!(txn.unset(vec![1]).await.unwrap());
+        assert!(!txn.unset(vec![3]).await.unwrap());
+        assert_eq!(txn.get(vec![1]).await.unwrap(), Some(vec![1]));
+        txn.commit().await.unwrap();
+
+        let mut txn = sim.begin_pessimistic().await.unwrap();
+        txn.put(vec![1], vec![10]).await.unwrap();
+        txn.put(vec![3], vec![3]).await.unwrap();
+        assert!(txn.unset(vec![1]).await.unwrap());
+        assert!(txn.unset(vec![3]).await.unwrap());
+        // The primary key stays locked, but the lock on the other key is released.
+        let mut other = sim.begin_pessimistic().await.unwrap();
+        other.put(vec![3], vec![30]).await.unwrap();
+        other.commit().await.unwrap();
+        txn.commit().await.unwrap();
+
+        let mut snapshot = sim.begin_optimistic().await.unwrap();
+        let keys = [vec![1], vec![2], vec![3]];
+        let pairs = snapshot.batch_get(keys).await.unwrap();
+        let mut pairs: Vec<(Vec<u8>, Vec<u8>)> =
+            pairs.map(|pair| (pair.0.into(), pair.1)).collect();
+        pairs.sort();
+        assert_eq!(pairs, vec![(vec![1], vec![1]), (vec![3], vec![30])]);
+        snapshot.rollback().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_first_and_last() {
+        let sim = Simulation::new(6);
+        sim.split(vec![3]);
+        sim.split(vec![6]);
+        let mut txn = sim.begin_optimistic().await.unwrap();
+        for key in [1, 4, 5, 7] {
+            txn.put(vec![key], vec![key]).await.unwrap();
+        }
+        txn.commit().await.unwrap();
+
+        let key = |pair: Option<KvPair>| pair.map(|pair| Vec::from(pair.into_key()));
+        let mut txn = sim.begin_optimistic().await.unwrap();
+        assert_eq!(key(txn.first(..).await.unwrap()), Some(vec![1]));
+        assert_eq!(key(txn.last(..).await.unwrap()), Some(vec![7]));
+        assert_eq!(key(txn.last(..vec![7]).await.unwrap()), Some(vec![5]));
+        assert_eq!(key(txn.first(vec![2]..vec![4]).await.unwrap()), None);
+        // Buffered writes are seen.
+        txn.delete(vec![7]).await.unwrap();
+        txn.put(vec![0], vec![0]).await.unwrap();
+        assert_eq!(key(txn.first(..).await.unwrap()), Some(vec![0]));
+        assert_eq!(key(txn.last(..).await.unwrap()), Some(vec![5]));
+        txn.rollback().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_value_format() {
+        let sim = Simulation::new(21);
+        let config = Config::default().with_compression(Compression::default());
+        let format = ValueFormat::new(&config).map(Arc::new);
+        let large = vec![7; 4096];
+        let txn = sim.begin_optimistic().await.unwrap();
+        let mut txn = txn.with_value_format(format.clone());
+        txn.put(vec![1], large.clone()).await.unwrap();
+        txn.put(vec![2], vec![2]).await.unwrap();
+        txn.commit().await.unwrap();
+
+        let txn = sim.begin_pessimistic().await.unwrap();
+        let mut txn = txn.with_value_format(format);
+        assert_eq!(txn.get(vec![1]).await.unwrap(), Some(large.clone()));
+        let keys = vec![vec![1], vec![2]];
+        let pairs = txn.batch_get(keys).await.unwrap();
+        let mut values: Vec<_> = pairs.map(KvPair::into_value).collect();
+        values.sort();
+        assert_eq!(values, vec![vec![2], large.clone()]);
+        let scanned: Vec<_> = txn.scan(vec![2].., 10).await.unwrap().collect();
+        assert_eq!(scanned, vec![KvPair::new(vec![2], vec![2])]);
+        assert_eq!(txn.scan_keys(.., 10).await.unwrap().count(), 2);
+        assert_eq!(txn.get_for_update(vec![2]).await.unwrap(), Some(vec![2]));
+        txn.rollback().await.unwrap();
+
+        // Values are stored behind a header, and compressed once they are large enough.
+        let mut txn = sim.begin_optimistic().await.unwrap();
+        let stored = txn.get(vec![1]).await.unwrap().unwrap();
+        assert!(stored.len() < large.len() / 10, "{}", stored.len());
+        assert_eq!(txn.get(vec![2]).await.unwrap(), Some(vec![0, 2]));
+        txn.rollback().await.unwrap();
+
+        // Values which don't match their checksums fail reads.
+        let format = ValueFormat::new(&Config::default().with_value_checksum()).map(Arc::new);
+        let txn = sim.begin_optimistic().await.unwrap();
+        let mut txn = txn.with_value_format(format.clone());
+        assert!(matches!(
+            txn.get(vec![1]).await,
+            Err(Error::ChecksumMismatch { .. })
+        ));
+        txn.put(vec![3], vec![3]).await.unwrap();
+        txn.commit().await.unwrap();
+        let txn = sim.begin_optimistic().await.unwrap();
+        let mut txn = txn.with_value_format(format);
+        assert_eq!(txn.get(vec![3]).await.unwrap(), Some(vec![3]));
+        txn.rollback().await.unwrap();
+    }
 }
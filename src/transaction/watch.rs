@@ -0,0 +1,160 @@
+// Copyright 2023 TiKV Project Authors. Licensed under Apache-2.0.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::prelude::*;
+use slog::Logger;
+use tikv_client_proto::kvrpcpb;
+
+use crate::backoff::DEFAULT_REGION_BACKOFF;
+use crate::pd::PdClient;
+use crate::request::CollectSingle;
+use crate::request::Plan;
+use crate::request::PlanBuilder;
+use crate::timestamp::TimestampExt;
+use crate::transaction::lowering::new_mvcc_get_by_key_request;
+use crate::transaction::Transaction;
+use crate::transaction::TransactionOptions;
+use crate::value_format::ValueFormat;
+use crate::Key;
+use crate::Result;
+use crate::Value;
+
+/// A change to the value of a key, seen by
+/// [`TransactionClient::watch`](crate::TransactionClient::watch).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyChange {
+    /// The new value, or `None` if the key was deleted or has never been written.
+    pub value: Option<Value>,
+    /// The commit timestamp of the write, or 0 if the key has never been written.
+    pub commit_version: u64,
+}
+
+struct WatchState<PdC: PdClient> {
+    rpc: Arc<PdC>,
+    key: Key,
+    poll_interval: Duration,
+    value_format: Option<Arc<ValueFormat>>,
+    logger: Logger,
+    polled: bool,
+    commit_version: Option<u64>,
+}
+
+/// Poll `key` every `poll_interval`, yielding its value when it is first polled and whenever it
+/// is written after that.
+pub(crate) fn watch<PdC: PdClient>(
+    rpc: Arc<PdC>,
+    key: Key,
+    poll_interval: Duration,
+    value_format: Option<Arc<ValueFormat>>,
+    logger: Logger,
+) -> impl Stream<Item = Result<KeyChange>> {
+    let state = WatchState {
+        rpc,
+        key,
+        poll_interval,
+        value_format,
+        logger,
+        polled: false,
+        commit_version: None,
+    };
+    stream::unfold(state, |mut state| async move {
+        loop {
+            if state.polled {
+                state.rpc.clock().sleep(state.poll_interval).await;
+            }
+            state.polled = true;
+            match poll(&state).await {
+                Ok(change) if state.commit_version == Some(change.commit_version) => {}
+                Ok(change) => {
+                    state.commit_version = Some(change.commit_version);
+                    return Some((Ok(change), state));
+                }
+                Err(e) => return Some((Err(e), state)),
+            }
+        }
+    })
+}
+
+async fn poll<PdC: PdClient>(state: &WatchState<PdC>) -> Result<KeyChange> {
+    let timestamp = state.rpc.clone().get_timestamp().await?;
+    let version = timestamp.version();
+    // Read the value first: the read resolves any locks on the key, so that every write committed
+    // before the timestamp is then listed by the MVCC request.
+    let options = TransactionOptions::new_optimistic().read_only();
+    let logger = state.logger.new(o!("child" => 1));
+    let mut snapshot = Transaction::new(timestamp, state.rpc.clone(), options, logger)
+        .with_value_format(state.value_format.clone());
+    let value = snapshot.get(state.key.clone()).await?;
+
+    let request = new_mvcc_get_by_key_request(state.key.clone());
+    let plan = PlanBuilder::new(state.rpc.clone(), request)
+        .retry_multi_region(DEFAULT_REGION_BACKOFF)
+        .merge(CollectSingle)
+        .post_process_default()
+        .plan();
+    let info = plan.execute().await?;
+    let commit_version = info
+        .writes
+        .iter()
+        .filter(|write| matches!(write.r#type(), kvrpcpb::Op::Put | kvrpcpb::Op::Del))
+        .map(|write| write.commit_ts)
+        .filter(|commit_ts| *commit_ts <= version)
+        .max()
+        .unwrap_or(0);
+    Ok(KeyChange {
+        value,
+        commit_version,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::pin_mut;
+
+    use super::*;
+    use crate::simulation::Simulation;
+
+    #[tokio::test]
+    async fn test_watch() {
+        let sim = Simulation::new(14);
+        let logger = Logger::root(slog::Discard, o!());
+        let interval = Duration::from_millis(5);
+        let changes = watch(sim.pd_client(), vec![1].into(), interval, None, logger);
+        pin_mut!(changes);
+        let sim = &sim;
+        let write = |value: Option<Vec<u8>>| async move {
+            let mut txn = sim.begin_optimistic().await.unwrap();
+            match value {
+                Some(value) => txn.put(vec![1], value).await.unwrap(),
+                None => txn.delete(vec![1]).await.unwrap(),
+            }
+            txn.commit().await.unwrap().unwrap().version()
+        };
+
+        let change = changes.next().await.unwrap().unwrap();
+        assert_eq!((change.value, change.commit_version), (None, 0));
+        let commit_version = write(Some(vec![1])).await;
+        let change = changes.next().await.unwrap().unwrap();
+        assert_eq!(change.value, Some(vec![1]));
+        assert_eq!(change.commit_version, commit_version);
+
+        // Writing other keys or locking the key isn't a change, but writing the same value is.
+        let mut txn = sim.begin_optimistic().await.unwrap();
+        txn.put(vec![2], vec![2]).await.unwrap();
+        txn.lock_keys(vec![vec![1]]).await.unwrap();
+        txn.commit().await.unwrap();
+        let unchanged = tokio::time::timeout(interval * 10, changes.next()).await;
+        assert!(unchanged.is_err());
+        let commit_version = write(Some(vec![1])).await;
+        let change = changes.next().await.unwrap().unwrap();
+        assert_eq!(change.value, Some(vec![1]));
+        assert_eq!(change.commit_version, commit_version);
+
+        let commit_version = write(None).await;
+        let change = changes.next().await.unwrap().unwrap();
+        assert_eq!(change.value, None);
+        assert_eq!(change.commit_version, commit_version);
+    }
+}
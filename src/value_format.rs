@@ -0,0 +1,513 @@
+// Copyright 2023 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Client-side encoding of the values a client stores in TiKV.
+
+#[cfg(feature = "aes-gcm")]
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use serde_derive::Deserialize;
+use serde_derive::Serialize;
+use tikv_client_proto::kvrpcpb;
+
+use crate::Config;
+use crate::Error;
+use crate::Key;
+use crate::KvPair;
+use crate::Result;
+use crate::Value;
+
+/// Values smaller than this are not compressed by default.
+const DEFAULT_COMPRESSION_THRESHOLD: usize = 1024;
+#[cfg(feature = "zstd")]
+const ZSTD_LEVEL: i32 = 3;
+
+/// The length of the checksum appended to values.
+const CHECKSUM_LEN: usize = 4;
+
+/// The header byte of a value stored as it is.
+const HEADER_UNCOMPRESSED: u8 = 0;
+/// The header byte of a value compressed with LZ4, prefixed by its uncompressed length.
+const HEADER_LZ4: u8 = 1;
+/// The header byte of a value compressed with zstd.
+const HEADER_ZSTD: u8 = 2;
+
+/// Compression of the values written by a client's transactions.
+///
+/// Values of at least `threshold` bytes are compressed before they are sent to TiKV, and
+/// decompressed when they are read. Every value written by the client, compressed or not, is
+/// prefixed by a header byte recording how it was stored, so all clients reading or writing the
+/// same keys must have compression enabled.
+///
+/// # Examples
+/// ```rust
+/// # use tikv_client::{Compression, CompressionAlgorithm, Config};
+/// let config = Config::default().with_compression(
+///     Compression::default()
+///         .algorithm(CompressionAlgorithm::Lz4)
+///         .threshold(4096),
+/// );
+/// ```
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+#[serde(rename_all = "kebab-case")]
+pub struct Compression {
+    pub algorithm: CompressionAlgorithm,
+    pub threshold: usize,
+}
+
+/// The algorithm values are compressed with.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum CompressionAlgorithm {
+    /// LZ4, which is fast and compresses moderately.
+    Lz4,
+    /// Zstandard, which compresses better than LZ4 but is slower. Requires the `zstd` feature.
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression {
+            algorithm: CompressionAlgorithm::Lz4,
+            threshold: DEFAULT_COMPRESSION_THRESHOLD,
+        }
+    }
+}
+
+impl Compression {
+    /// Set the algorithm values are compressed with. The default is LZ4.
+    #[must_use]
+    pub fn algorithm(mut self, algorithm: CompressionAlgorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    /// Set the size, in bytes, from which values are compressed. The default is 1 KiB.
+    #[must_use]
+    pub fn threshold(mut self, threshold: usize) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    fn encode(&self, value: &[u8]) -> Result<Value> {
+        if value.len() < self.threshold {
+            let mut encoded = Vec::with_capacity(value.len() + 1);
+            encoded.push(HEADER_UNCOMPRESSED);
+            encoded.extend_from_slice(value);
+            return Ok(encoded);
+        }
+        let mut compressed = match self.algorithm {
+            CompressionAlgorithm::Lz4 => {
+                let mut compressed = vec![HEADER_LZ4];
+                compressed.extend(lz4_flex::compress_prepend_size(value));
+                compressed
+            }
+            #[cfg(feature = "zstd")]
+            CompressionAlgorithm::Zstd => {
+                let mut compressed = vec![HEADER_ZSTD];
+                compressed.extend(zstd::bulk::compress(value, ZSTD_LEVEL).map_err(codec_error)?);
+                compressed
+            }
+        };
+        // Store values which don't compress as they are, to save decompressing them.
+        if compressed.len() > value.len() {
+            compressed.clear();
+            compressed.push(HEADER_UNCOMPRESSED);
+            compressed.extend_from_slice(value);
+        }
+        Ok(compressed)
+    }
+}
+
+fn decompress(value: Value) -> Result<Value> {
+    match value.first() {
+        Some(&HEADER_UNCOMPRESSED) => {
+            let mut value = value;
+            value.remove(0);
+            Ok(value)
+        }
+        Some(&HEADER_LZ4) => lz4_flex::decompress_size_prepended(&value[1..]).map_err(codec_error),
+        #[cfg(feature = "zstd")]
+        Some(&HEADER_ZSTD) => zstd::stream::decode_all(&value[1..]).map_err(codec_error),
+        #[cfg(not(feature = "zstd"))]
+        Some(&HEADER_ZSTD) => Err(Error::ValueCodecError {
+            message: "value is compressed with zstd, which requires the `zstd` feature".to_owned(),
+        }),
+        Some(header) => Err(Error::ValueCodecError {
+            message: format!("value has an unknown compression header {header}"),
+        }),
+        None => Err(Error::ValueCodecError {
+            message: "value has no compression header".to_owned(),
+        }),
+    }
+}
+
+fn codec_error(e: impl std::fmt::Display) -> Error {
+    Error::ValueCodecError {
+        message: e.to_string(),
+    }
+}
+
+/// Encrypts the values written by a client's transactions, and decrypts the values they read.
+///
+/// Values are encrypted after they are compressed, just before they are sent to TiKV, so TiKV and
+/// anything between it and the client only see ciphertext. Keys are not encrypted. The key a
+/// value is stored under is passed along, so that a cipher can bind the value to it, and reject
+/// values copied to another key.
+///
+/// Set a client's cipher with [`Config::with_value_cipher`]. See [`AesGcmCipher`] for an
+/// implementation, which requires the `aes-gcm` feature.
+pub trait ValueCipher: Send + Sync {
+    /// Encrypt `value`, stored under `key`.
+    fn encrypt(&self, key: &Key, value: &[u8]) -> Result<Value>;
+
+    /// Decrypt `value`, stored under `key`.
+    ///
+    /// Return [`Error::ValueCodecError`] if `value` can't be decrypted.
+    fn decrypt(&self, key: &Key, value: &[u8]) -> Result<Value>;
+}
+
+/// A shared `ValueCipher` which can be stored in a `Config`.
+#[derive(Clone)]
+pub(crate) struct ValueCipherHandle(pub Arc<dyn ValueCipher>);
+
+impl fmt::Debug for ValueCipherHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ValueCipher")
+    }
+}
+
+impl PartialEq for ValueCipherHandle {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::as_ptr(&self.0) as *const () == Arc::as_ptr(&other.0) as *const ()
+    }
+}
+
+impl Eq for ValueCipherHandle {}
+
+/// A [`ValueCipher`] using AES-256-GCM, with support for rotating keys.
+///
+/// Each value is encrypted with a random nonce, and authenticated along with the key it is stored
+/// under. The id of the encryption key is stored with the value, so after rotating to a new key,
+/// values encrypted with the previous keys can still be read, as long as those keys are added with
+/// [`previous_key`](AesGcmCipher::previous_key).
+///
+/// # Examples
+/// ```rust,ignore
+/// # use tikv_client::{AesGcmCipher, Config};
+/// let cipher = AesGcmCipher::new(2, [2; 32]).previous_key(1, [1; 32]);
+/// let config = Config::default().with_value_cipher(cipher);
+/// ```
+#[cfg(feature = "aes-gcm")]
+pub struct AesGcmCipher {
+    key_id: u32,
+    keys: HashMap<u32, aes_gcm::Aes256Gcm>,
+}
+
+#[cfg(feature = "aes-gcm")]
+impl AesGcmCipher {
+    /// The length of the nonce stored with each value.
+    const NONCE_LEN: usize = 12;
+
+    /// Encrypt values with `key`, identified by `key_id`.
+    pub fn new(key_id: u32, key: [u8; 32]) -> AesGcmCipher {
+        AesGcmCipher {
+            key_id,
+            keys: HashMap::new(),
+        }
+        .previous_key(key_id, key)
+    }
+
+    /// Decrypt values encrypted with `key`, identified by `key_id`, such as a key which has been
+    /// rotated out.
+    #[must_use]
+    pub fn previous_key(mut self, key_id: u32, key: [u8; 32]) -> Self {
+        use aes_gcm::KeyInit;
+
+        self.keys
+            .entry(key_id)
+            .or_insert_with(|| aes_gcm::Aes256Gcm::new(&key.into()));
+        self
+    }
+}
+
+#[cfg(feature = "aes-gcm")]
+impl ValueCipher for AesGcmCipher {
+    fn encrypt(&self, key: &Key, value: &[u8]) -> Result<Value> {
+        use aes_gcm::aead::Aead;
+        use aes_gcm::aead::AeadCore;
+        use aes_gcm::aead::OsRng;
+        use aes_gcm::aead::Payload;
+
+        let nonce = aes_gcm::Aes256Gcm::generate_nonce(&mut OsRng);
+        let payload = Payload {
+            msg: value,
+            aad: key.into(),
+        };
+        let ciphertext = self.keys[&self.key_id]
+            .encrypt(&nonce, payload)
+            .map_err(codec_error)?;
+        let mut encrypted = self.key_id.to_be_bytes().to_vec();
+        encrypted.extend_from_slice(&nonce);
+        encrypted.extend(ciphertext);
+        Ok(encrypted)
+    }
+
+    fn decrypt(&self, key: &Key, value: &[u8]) -> Result<Value> {
+        use aes_gcm::aead::Aead;
+        use aes_gcm::aead::Payload;
+
+        if value.len() < 4 + Self::NONCE_LEN {
+            return Err(Error::ValueCodecError {
+                message: "encrypted value is truncated".to_owned(),
+            });
+        }
+        let (key_id, value) = value.split_at(4);
+        let (nonce, ciphertext) = value.split_at(Self::NONCE_LEN);
+        let key_id = u32::from_be_bytes(key_id.try_into().unwrap());
+        let cipher = self
+            .keys
+            .get(&key_id)
+            .ok_or_else(|| Error::ValueCodecError {
+                message: format!("value is encrypted with unknown key {key_id}"),
+            })?;
+        let payload = Payload {
+            msg: ciphertext,
+            aad: key.into(),
+        };
+        cipher
+            .decrypt(nonce.into(), payload)
+            .map_err(|_| Error::ValueCodecError {
+                message: "value could not be decrypted".to_owned(),
+            })
+    }
+}
+
+/// How a client encodes the values it writes, and decodes the values it reads, as configured by
+/// its [`Config`].
+///
+/// Values are compressed, then encrypted, then checksummed.
+pub(crate) struct ValueFormat {
+    compression: Option<Compression>,
+    cipher: Option<Arc<dyn ValueCipher>>,
+    checksum: bool,
+}
+
+impl ValueFormat {
+    /// Returns `None` if values are stored as they are.
+    pub(crate) fn new(config: &Config) -> Option<ValueFormat> {
+        let compression = config.compression.clone();
+        let cipher = config.value_cipher.clone().map(|handle| handle.0);
+        let checksum = config.value_checksum;
+        if compression.is_none() && cipher.is_none() && !checksum {
+            return None;
+        }
+        Some(ValueFormat {
+            compression,
+            cipher,
+            checksum,
+        })
+    }
+
+    pub(crate) fn encode(&self, key: &Key, value: &[u8]) -> Result<Value> {
+        let value = match &self.compression {
+            Some(compression) => compression.encode(value)?,
+            None => value.to_vec(),
+        };
+        let mut value = match &self.cipher {
+            Some(cipher) => cipher.encrypt(key, &value)?,
+            None => value,
+        };
+        if self.checksum {
+            let checksum = crc32fast::hash(&value);
+            value.extend_from_slice(&checksum.to_be_bytes());
+        }
+        Ok(value)
+    }
+
+    pub(crate) fn decode(&self, key: &Key, value: Value) -> Result<Value> {
+        let value = if self.checksum {
+            verify_checksum(value)?
+        } else {
+            value
+        };
+        let value = match &self.cipher {
+            Some(cipher) => cipher.decrypt(key, &value)?,
+            None => value,
+        };
+        match &self.compression {
+            Some(_) => decompress(value),
+            None => Ok(value),
+        }
+    }
+
+    /// Encode the values of the `Put` and `Insert` mutations.
+    pub(crate) fn encode_mutations(&self, mutations: &mut [kvrpcpb::Mutation]) -> Result<()> {
+        for mutation in mutations {
+            if matches!(mutation.op(), kvrpcpb::Op::Put | kvrpcpb::Op::Insert) {
+                mutation.value = self.encode((&mutation.key).into(), &mutation.value)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Strip the CRC32 checksum from the end of `value`, after checking it.
+fn verify_checksum(mut value: Value) -> Result<Value> {
+    if value.len() < CHECKSUM_LEN {
+        return Err(Error::ValueCodecError {
+            message: "value is too short to have a checksum".to_owned(),
+        });
+    }
+    let checksum = value.split_off(value.len() - CHECKSUM_LEN);
+    let expected = u32::from_be_bytes(checksum.try_into().unwrap());
+    let actual = crc32fast::hash(&value);
+    if actual != expected {
+        return Err(Error::ChecksumMismatch { expected, actual });
+    }
+    Ok(value)
+}
+
+/// Decode the value of `key` if the client has a value format.
+pub(crate) fn decode_value(format: Option<&ValueFormat>, key: &Key, value: Value) -> Result<Value> {
+    match format {
+        Some(format) => format.decode(key, value),
+        None => Ok(value),
+    }
+}
+
+/// Decode the values of `pairs` if the client has a value format.
+pub(crate) fn decode_pairs(
+    format: Option<&ValueFormat>,
+    pairs: Vec<KvPair>,
+) -> Result<Vec<KvPair>> {
+    match format {
+        Some(format) => pairs
+            .into_iter()
+            .map(|KvPair(key, value)| {
+                let value = format.decode(&key, value)?;
+                Ok(KvPair(key, value))
+            })
+            .collect(),
+        None => Ok(pairs),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reverses values, and binds them to their keys by appending the key.
+    struct ReverseCipher;
+
+    impl ValueCipher for ReverseCipher {
+        fn encrypt(&self, key: &Key, value: &[u8]) -> Result<Value> {
+            let mut encrypted: Value = value.iter().rev().copied().collect();
+            encrypted.extend_from_slice(key.into());
+            Ok(encrypted)
+        }
+
+        fn decrypt(&self, key: &Key, value: &[u8]) -> Result<Value> {
+            let key: &[u8] = key.into();
+            match value.strip_suffix(key) {
+                Some(value) => Ok(value.iter().rev().copied().collect()),
+                None => Err(Error::ValueCodecError {
+                    message: "wrong key".to_owned(),
+                }),
+            }
+        }
+    }
+
+    #[test]
+    fn test_compression() {
+        let config = Config::default().with_compression(Compression::default().threshold(16));
+        let format = ValueFormat::new(&config).unwrap();
+        let key = Key::from(vec![1]);
+        let small = b"small".to_vec();
+        let large = vec![7; 4096];
+        let scrambled = |i: u32| (i.wrapping_mul(2654435761) >> 24) as u8;
+        let random: Vec<u8> = (0..64).map(scrambled).collect();
+        for value in [Vec::new(), small, large.clone(), random] {
+            let encoded = format.encode(&key, &value).unwrap();
+            assert_eq!(format.decode(&key, encoded).unwrap(), value);
+        }
+        let encoded = format.encode(&key, &large).unwrap();
+        assert_eq!(encoded[0], HEADER_LZ4);
+        assert!(encoded.len() < 100);
+        assert_eq!(format.encode(&key, b"small").unwrap(), b"\0small");
+
+        for corrupt in [vec![], vec![9, 1], vec![HEADER_LZ4, 1]] {
+            let e = format.decode(&key, corrupt).unwrap_err();
+            assert!(matches!(e, Error::ValueCodecError { .. }), "{e:?}");
+        }
+        assert!(ValueFormat::new(&Config::default()).is_none());
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn test_zstd() {
+        let compression = Compression::default().algorithm(CompressionAlgorithm::Zstd);
+        let format = ValueFormat::new(&Config::default().with_compression(compression)).unwrap();
+        let key = Key::from(vec![1]);
+        let value = vec![7; 4096];
+        let encoded = format.encode(&key, &value).unwrap();
+        assert_eq!(encoded[0], HEADER_ZSTD);
+        assert_eq!(format.decode(&key, encoded).unwrap(), value);
+    }
+
+    #[test]
+    fn test_cipher() {
+        let (key, other_key) = (Key::from(vec![1]), Key::from(vec![2]));
+        let config = Config::default().with_value_cipher(ReverseCipher);
+        let format = ValueFormat::new(&config).unwrap();
+        assert_eq!(format.encode(&key, &[3, 4]).unwrap(), vec![4, 3, 1]);
+        assert_eq!(format.decode(&key, vec![4, 3, 1]).unwrap(), vec![3, 4]);
+        assert!(format.decode(&other_key, vec![4, 3, 1]).is_err());
+
+        // Values are compressed before they are encrypted.
+        let config = config.with_compression(Compression::default().threshold(16));
+        let format = ValueFormat::new(&config).unwrap();
+        let value = vec![7; 4096];
+        let encoded = format.encode(&key, &value).unwrap();
+        assert_eq!(encoded[encoded.len() - 2], HEADER_LZ4);
+        assert_eq!(format.decode(&key, encoded).unwrap(), value);
+    }
+
+    #[test]
+    fn test_checksum() {
+        let key = Key::from(vec![1]);
+        let format = ValueFormat::new(&Config::default().with_value_checksum()).unwrap();
+        let mut encoded = format.encode(&key, b"value").unwrap();
+        assert_eq!(encoded.len(), 5 + CHECKSUM_LEN);
+        assert_eq!(format.decode(&key, encoded.clone()).unwrap(), b"value");
+
+        encoded[0] ^= 1;
+        let e = format.decode(&key, encoded).unwrap_err();
+        assert!(matches!(e, Error::ChecksumMismatch { .. }), "{e:?}");
+        let e = format.decode(&key, vec![1, 2]).unwrap_err();
+        assert!(matches!(e, Error::ValueCodecError { .. }), "{e:?}");
+    }
+
+    #[cfg(feature = "aes-gcm")]
+    #[test]
+    fn test_aes_gcm_cipher() {
+        let (key, other_key) = (Key::from(vec![1]), Key::from(vec![2]));
+        let old = AesGcmCipher::new(1, [1; 32]);
+        let encrypted = old.encrypt(&key, b"value").unwrap();
+        assert_ne!(old.encrypt(&key, b"value").unwrap(), encrypted);
+        assert_eq!(old.decrypt(&key, &encrypted).unwrap(), b"value");
+        assert!(old.decrypt(&other_key, &encrypted).is_err());
+
+        // After rotating keys, values encrypted with the previous key can still be decrypted.
+        let new = AesGcmCipher::new(2, [2; 32]).previous_key(1, [1; 32]);
+        assert_eq!(new.decrypt(&key, &encrypted).unwrap(), b"value");
+        let encrypted = new.encrypt(&key, b"value").unwrap();
+        assert_eq!(encrypted[..4], 2u32.to_be_bytes());
+        let e = old.decrypt(&key, &encrypted).unwrap_err();
+        assert!(matches!(e, Error::ValueCodecError { .. }), "{e:?}");
+        assert!(new.decrypt(&key, &encrypted[..10]).is_err());
+    }
+}
@@ -126,16 +126,13 @@ async fn txn_crud() -> Result<()> {
         // TODO needed because pessimistic does not check locks (#235)
         TransactionOptions::new_optimistic(),
     );
-    let batch_get_res: HashMap<Key, Value> = snapshot
+    let values: Vec<Option<Value>> = snapshot
         .batch_get(vec!["foo".to_owned(), "bar".to_owned()])
         .await?
-        .map(|pair| (pair.0, pair.1))
-        .collect();
-    assert_eq!(
-        batch_get_res.get(&Key::from("foo".to_owned())),
-        Some(Value::from("foo".to_owned())).as_ref()
-    );
-    assert_eq!(batch_get_res.get(&Key::from("bar".to_owned())), None);
+        .into_iter()
+        .map(|(_, value)| value)
+        .collect::<Result<_>>()?;
+    assert_eq!(values, vec![Some(Value::from("foo".to_owned())), None]);
     Ok(())
 }
 
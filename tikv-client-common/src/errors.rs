@@ -1,9 +1,17 @@
 // Copyright 2018 TiKV Project Authors. Licensed under Apache-2.0.
 
+use std::fmt;
 use std::result;
 
 use thiserror::Error;
 
+use crate::redaction::redact_key_error;
+use crate::redaction::redact_region_error;
+use crate::redaction::redaction;
+use crate::redaction::Redacted;
+use crate::redaction::RedactedMessage;
+use crate::redaction::Redaction;
+
 /// An error originating from the TiKV client or dependencies.
 #[derive(Debug, Error)]
 #[allow(clippy::large_enum_variant)]
@@ -23,17 +31,63 @@ pub enum Error {
     /// It's not allowed to perform operations in a transaction after it has been committed or rolled back.
     #[error("Cannot read or write data after any attempt to commit or roll back the transaction")]
     OperationAfterCommitError,
+    /// The transaction's deadline passed before the operation could complete.
+    #[error("Transaction deadline exceeded")]
+    DeadlineExceeded,
+    /// The client has been closed, so it can't begin transactions.
+    #[error("The client has been closed")]
+    ClientClosed,
+    /// A request to PD did not complete, retries included, within the budget for PD requests.
+    #[error("PD request {} did not complete within {:?}", operation, budget)]
+    PdTimeout {
+        operation: &'static str,
+        budget: std::time::Duration,
+    },
+    /// Committing would have placed the transaction after its `max_commit_ts`.
+    #[error("Commit timestamp {} exceeds the transaction's max_commit_ts {}", commit_ts, max_commit_ts)]
+    CommitTsTooLarge { commit_ts: u64, max_commit_ts: u64 },
+    /// GC has passed the version of a snapshot, so the data it would read may have been removed.
+    #[error("Snapshot at version {} is older than the GC safepoint {}", version, safepoint)]
+    SnapshotTooOld { version: u64, safepoint: u64 },
+    /// The operation was canceled using the transaction's cancellation token.
+    #[error("The operation was canceled")]
+    OperationCanceled,
+    /// A value could not be encoded or decoded by a value codec.
+    #[error("Failed to encode or decode value: {}", message)]
+    ValueCodecError { message: String },
+    /// A value read from TiKV does not match the checksum it was written with.
+    #[error("Value checksum mismatch: expected {:#010x}, got {:#010x}", expected, actual)]
+    ChecksumMismatch { expected: u32, actual: u32 },
+    /// A key could not be parsed from its hexadecimal or escaped form.
+    #[error("Failed to parse key: {}", message)]
+    InvalidKeyEncoding { message: String },
     /// We tried to use 1pc for a transaction, but it didn't work. Probably should have used 2pc.
     #[error("1PC transaction could not be committed.")]
     OnePcFailure,
     /// An operation requires a primary key, but the transaction was empty.
     #[error("transaction has no primary key")]
     NoPrimaryKey,
+    /// The lease on a distributed lock was lost, e.g., because it expired and the lock was
+    /// acquired by someone else.
+    #[error("Lock {} is not held by this lease", Redacted(.key))]
+    LockNotHeld { key: Vec<u8> },
     /// For raw client, operation is not supported in atomic/non-atomic mode.
     #[error(
         "The operation is not supported in current mode, please consider using RawClient with or without atomic mode"
     )]
     UnsupportedMode,
+    /// An atomic batch of raw writes both puts and deletes keys of a region, which TiKV can't
+    /// apply atomically.
+    #[error("An atomic batch can't both put and delete keys of region {region_id}")]
+    MixedAtomicBatch { region_id: u64 },
+    /// A PD node belongs to a different cluster than the one the client connected to, e.g.,
+    /// because a PD endpoint resolves to the wrong address.
+    #[error("PD cluster id mismatch: expected {}, got {}", expected, actual)]
+    ClusterMismatch { expected: u64, actual: u64 },
+    /// Results from TiKV did not match the request, e.g., a response for a single key held
+    /// several results. This indicates a bug in the client or TiKV.
+    #[error("Inconsistent results: {}", message)]
+    InconsistentResults { message: String },
     #[error("There is no current_regions in the EpochNotMatch error")]
     NoCurrentRegions,
     #[error("The specified entry is not found in the region cache")]
@@ -57,19 +111,33 @@ pub enum Error {
     #[error("A futures oneshot channel was canceled. {0}")]
     Canceled(#[from] futures::channel::oneshot::Canceled),
     /// Errors caused by changes of region information
-    #[error("Region error: {0:?}")]
+    #[error("Region error: {:?}", redact_region_error(.0))]
     RegionError(Box<tikv_client_proto::errorpb::Error>),
-    /// Whether the transaction is committed or not is undetermined
-    #[error("Whether the transaction is committed or not is undetermined")]
-    UndeterminedError(Box<Error>),
-    /// Wraps `tikv_client_proto::kvrpcpb::KeyError`
-    #[error("{0:?}")]
+    /// Whether the transaction is committed or not is undetermined, e.g., because the response
+    /// to committing its primary key was lost.
+    ///
+    /// The transaction may have been committed, so it must not simply be retried. Its fate can be
+    /// found later from its primary key and start timestamp, once its lock has been resolved.
+    #[error(
+        "Whether the transaction is committed or not is undetermined (start_ts {}, primary key {}): {}",
+        start_ts,
+        Redacted(.primary_key),
+        source
+    )]
+    UndeterminedError {
+        source: Box<Error>,
+        primary_key: Vec<u8>,
+        start_ts: u64,
+    },
+    /// Wraps `tikv_client_proto::kvrpcpb::KeyError`. The details of a write conflict are also
+    /// available from [`Error::write_conflict`].
+    #[error("{:?}", redact_key_error(.0))]
     KeyError(Box<tikv_client_proto::kvrpcpb::KeyError>),
     /// Multiple errors generated from the ExtractError plan.
-    #[error("Multiple errors: {0:?}")]
+    #[error("Multiple errors: {}", ErrorList(.0))]
     ExtractedErrors(Vec<Error>),
     /// Multiple key errors
-    #[error("Multiple key errors: {0:?}")]
+    #[error("Multiple key errors: {}", ErrorList(.0))]
     MultipleKeyErrors(Vec<Error>),
     /// Invalid ColumnFamily
     #[error("Unsupported column family {}", _0)]
@@ -78,7 +146,7 @@ pub enum Error {
     #[error("Failed to join tokio tasks")]
     JoinError(#[from] tokio::task::JoinError),
     /// No region is found for the given key.
-    #[error("Region is not found for key: {:?}", key)]
+    #[error("Region is not found for key: {}", Redacted(.key))]
     RegionForKeyNotFound { key: Vec<u8> },
     /// No region is found for the given id. note: distinguish it with the RegionNotFound error in errorpb.
     #[error("Region {} is not found in the response", region_id)]
@@ -86,23 +154,459 @@ pub enum Error {
     /// No leader is found for the given id.
     #[error("Leader of region {} is not found", region_id)]
     LeaderNotFound { region_id: u64 },
+    /// The store has failed repeatedly, and is avoided until its circuit breaker's cooldown ends.
+    #[error("Store {} is unavailable after repeated failures", address)]
+    StoreUnavailable { address: String },
     /// Scan limit exceeds the maximum
     #[error("Limit {} exceeds max scan limit {}", limit, max_limit)]
     MaxScanLimitExceeded { limit: u32, max_limit: u32 },
     #[error("Invalid Semver string: {0:?}")]
     InvalidSemver(#[from] semver::Error),
     /// A string error returned by TiKV server
-    #[error("Kv error. {}", message)]
+    #[error("Kv error. {}", RedactedMessage(.message))]
     KvError { message: String },
     #[error("{}", message)]
     InternalError { message: String },
     #[error("{0}")]
     StringError(String),
-    #[error("PessimisticLock error: {:?}", inner)]
+    #[error("PessimisticLock error: {:?}", RedactedDebug(.inner))]
     PessimisticLockError {
         inner: Box<Error>,
         success_keys: Vec<Vec<u8>>,
     },
+    /// An error annotated with where it happened. Errors of requests to TiKV are annotated, so
+    /// match on [`Error::root`], the underlying error, rather than on the error itself.
+    #[error("{source} ({context})")]
+    WithContext {
+        source: Box<Error>,
+        context: Box<ErrorContext>,
+    },
+}
+
+impl Error {
+    /// Annotate the error with `context`. Details the error is already annotated with are kept.
+    pub fn with_context(self, mut context: ErrorContext) -> Error {
+        if redaction() == Redaction::Redact {
+            context.key = None;
+        }
+        match self {
+            Error::WithContext {
+                source,
+                context: mut existing,
+            } => {
+                existing.merge(context);
+                Error::WithContext {
+                    source,
+                    context: existing,
+                }
+            }
+            e => Error::WithContext {
+                source: Box::new(e),
+                context: Box::new(context),
+            },
+        }
+    }
+
+    /// The error without the context it is annotated with; match on this rather than on the
+    /// error itself.
+    pub fn root(&self) -> &Error {
+        match self {
+            Error::WithContext { source, .. } => source,
+            e => e,
+        }
+    }
+
+    /// The error without the context it is annotated with, dropping the context.
+    pub fn into_root(self) -> Error {
+        match self {
+            Error::WithContext { source, .. } => *source,
+            e => e,
+        }
+    }
+
+    /// Where the error happened, if known.
+    pub fn context(&self) -> Option<&ErrorContext> {
+        match self {
+            Error::WithContext { context, .. } => Some(context),
+            _ => None,
+        }
+    }
+
+    /// The name of the request which failed, e.g., `kv_prewrite`.
+    pub fn operation(&self) -> Option<&str> {
+        self.context().and_then(|context| context.operation)
+    }
+
+    /// A key of the request which failed, unless content is [redacted](Redaction::Redact).
+    pub fn key(&self) -> Option<&[u8]> {
+        self.context().and_then(|context| context.key.as_deref())
+    }
+
+    /// The id of the region the failed request was sent to.
+    pub fn region_id(&self) -> Option<u64> {
+        self.context().and_then(|context| context.region_id)
+    }
+
+    /// The address of the TiKV store the failed request was sent to.
+    pub fn store_address(&self) -> Option<&str> {
+        self.context()
+            .and_then(|context| context.store_address.as_deref())
+    }
+
+    /// How many times the failed request was retried.
+    pub fn retry_attempts(&self) -> Option<u32> {
+        self.context().and_then(|context| context.retry_attempts)
+    }
+
+    /// Annotate the write conflicts the error reports, if any, as detected by an operation of
+    /// `kind`.
+    pub fn with_conflict_kind(self, kind: ConflictKind) -> Error {
+        if self.write_conflict().is_none() {
+            return self;
+        }
+        self.with_context(ErrorContext {
+            conflict_kind: Some(kind),
+            ..Default::default()
+        })
+    }
+
+    /// The details of the first write conflict the error reports, if any.
+    pub fn write_conflict(&self) -> Option<WriteConflict> {
+        self.find_write_conflict(None)
+    }
+
+    fn find_write_conflict(&self, kind: Option<ConflictKind>) -> Option<WriteConflict> {
+        match self {
+            Error::KeyError(e) => e.conflict.as_ref().map(|conflict| WriteConflict {
+                key: conflict.key.clone(),
+                primary: conflict.primary.clone(),
+                start_ts: conflict.start_ts,
+                conflict_start_ts: conflict.conflict_ts,
+                conflict_commit_ts: conflict.conflict_commit_ts,
+                kind,
+            }),
+            Error::ExtractedErrors(errors) | Error::MultipleKeyErrors(errors) => errors
+                .iter()
+                .find_map(|e| e.find_write_conflict(kind)),
+            Error::PessimisticLockError { inner, .. } => inner.find_write_conflict(kind),
+            Error::WithContext { source, context } => {
+                source.find_write_conflict(context.conflict_kind.or(kind))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Error {
+    /// Classify the error, for deciding how to handle it without matching on messages.
+    ///
+    /// Errors which wrap other errors are classified by the wrapped error; errors which wrap
+    /// several errors are classified by the first of them.
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Error::Unimplemented => ErrorCode::Unimplemented,
+            Error::DuplicateKeyInsertion => ErrorCode::DuplicateKey,
+            Error::ResolveLockError => ErrorCode::KeyLocked,
+            Error::InvalidTransactionType
+            | Error::OperationAfterCommitError
+            | Error::NoPrimaryKey
+            | Error::UnsupportedMode
+            | Error::MixedAtomicBatch { .. }
+            | Error::ColumnFamilyError(_)
+            | Error::MaxScanLimitExceeded { .. }
+            | Error::SnapshotTooOld { .. }
+            | Error::InvalidSemver(_)
+            | Error::Url(_) => ErrorCode::InvalidUsage,
+            Error::DeadlineExceeded | Error::PdTimeout { .. } => ErrorCode::DeadlineExceeded,
+            Error::CommitTsTooLarge { .. } => ErrorCode::CommitTsTooLarge,
+            Error::LockNotHeld { .. } => ErrorCode::LockNotHeld,
+            Error::OperationCanceled
+            | Error::ClientClosed
+            | Error::Canceled(_)
+            | Error::Channel(_) => ErrorCode::Canceled,
+            Error::ValueCodecError { .. }
+            | Error::ChecksumMismatch { .. }
+            | Error::InvalidKeyEncoding { .. } => ErrorCode::Codec,
+            Error::ClusterMismatch { .. } => ErrorCode::ClusterMismatch,
+            Error::Io(_) => ErrorCode::Io,
+            Error::Grpc(_)
+            | Error::LeaderNotFound { .. }
+            | Error::RegionForKeyNotFound { .. }
+            | Error::RegionNotFoundInResponse { .. }
+            | Error::NoCurrentRegions
+            | Error::StoreUnavailable { .. }
+            | Error::EntryNotFoundInRegionCache => ErrorCode::Unavailable,
+            Error::GrpcAPI(status) => match status.code() {
+                tonic::Code::Unavailable => ErrorCode::Unavailable,
+                tonic::Code::DeadlineExceeded => ErrorCode::DeadlineExceeded,
+                tonic::Code::Cancelled => ErrorCode::Canceled,
+                tonic::Code::ResourceExhausted => ErrorCode::ServerBusy,
+                _ => ErrorCode::Rpc,
+            },
+            Error::RegionError(e) if e.server_is_busy.is_some() => ErrorCode::ServerBusy,
+            Error::RegionError(_) => ErrorCode::Region,
+            Error::UndeterminedError { .. } => ErrorCode::Undetermined,
+            Error::KeyError(e) => key_error_code(e),
+            Error::ExtractedErrors(errors) | Error::MultipleKeyErrors(errors) => errors
+                .first()
+                .map_or(ErrorCode::Internal, |error| error.code()),
+            Error::PessimisticLockError { inner, .. } => inner.code(),
+            Error::WithContext { source, .. } => source.code(),
+            Error::OnePcFailure
+            | Error::InconsistentResults { .. }
+            | Error::JoinError(_)
+            | Error::KvError { .. }
+            | Error::InternalError { .. }
+            | Error::StringError(_) => ErrorCode::Internal,
+        }
+    }
+
+    /// Whether the operation may succeed if it is retried, e.g., in a new transaction.
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self.code(),
+            ErrorCode::WriteConflict
+                | ErrorCode::Deadlock
+                | ErrorCode::KeyLocked
+                | ErrorCode::CommitTsExpired
+                | ErrorCode::Region
+                | ErrorCode::ServerBusy
+                | ErrorCode::Unavailable
+                | ErrorCode::DeadlineExceeded
+        )
+    }
+
+    /// Whether the error was caused by a concurrent transaction.
+    pub fn is_conflict(&self) -> bool {
+        matches!(
+            self.code(),
+            ErrorCode::WriteConflict | ErrorCode::Deadlock | ErrorCode::KeyLocked
+        )
+    }
+
+    /// Whether the cluster could not be reached or is overloaded.
+    pub fn is_cluster_unavailable(&self) -> bool {
+        matches!(self.code(), ErrorCode::Unavailable | ErrorCode::ServerBusy)
+    }
+}
+
+fn key_error_code(e: &tikv_client_proto::kvrpcpb::KeyError) -> ErrorCode {
+    if e.conflict.is_some() || !e.retryable.is_empty() {
+        ErrorCode::WriteConflict
+    } else if e.deadlock.is_some() {
+        ErrorCode::Deadlock
+    } else if e.locked.is_some() {
+        ErrorCode::KeyLocked
+    } else if e.already_exist.is_some() {
+        ErrorCode::DuplicateKey
+    } else if e.commit_ts_expired.is_some() {
+        ErrorCode::CommitTsExpired
+    } else if e.commit_ts_too_large.is_some() {
+        ErrorCode::CommitTsTooLarge
+    } else if e.txn_not_found.is_some() {
+        ErrorCode::TransactionNotFound
+    } else if e.assertion_failed.is_some() {
+        ErrorCode::AssertionFailed
+    } else {
+        ErrorCode::Aborted
+    }
+}
+
+/// A classification of [`Error`]s, returned by [`Error::code`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorCode {
+    /// The feature is not implemented.
+    Unimplemented,
+    /// The client was used incorrectly, e.g., with an invalid argument.
+    InvalidUsage,
+    /// A key written by another transaction was committed after this transaction started.
+    WriteConflict,
+    /// Pessimistic transactions are waiting for each other's locks.
+    Deadlock,
+    /// A key is locked by another transaction.
+    KeyLocked,
+    /// A key to be inserted already exists.
+    DuplicateKey,
+    /// The commit timestamp is earlier than the transaction's minimum commit timestamp.
+    CommitTsExpired,
+    /// The commit timestamp would exceed the transaction's `max_commit_ts`.
+    CommitTsTooLarge,
+    /// The transaction was not found, e.g., because it was already rolled back.
+    TransactionNotFound,
+    /// An assertion of a mutation failed.
+    AssertionFailed,
+    /// A distributed lock is no longer held, e.g., because its lease expired.
+    LockNotHeld,
+    /// TiKV aborted the transaction for another reason.
+    Aborted,
+    /// Whether the transaction was committed is unknown.
+    Undetermined,
+    /// A region error which the client could not resolve by retrying.
+    Region,
+    /// TiKV is too busy to handle the request.
+    ServerBusy,
+    /// The cluster, or a part of it, could not be reached.
+    Unavailable,
+    /// A PD node belongs to a different cluster.
+    ClusterMismatch,
+    /// The operation did not finish before its deadline.
+    DeadlineExceeded,
+    /// The operation was canceled.
+    Canceled,
+    /// A gRPC request failed for another reason.
+    Rpc,
+    /// A key or value could not be encoded or decoded.
+    Codec,
+    /// An IO error.
+    Io,
+    /// An error inside the client or TiKV.
+    Internal,
+}
+
+/// Details of a write conflict, returned by [`Error::write_conflict`].
+///
+/// A key was written by another transaction, which committed after this transaction read or
+/// locked the key. The transaction may succeed if it is retried. A key which conflicts often is
+/// hot; spreading its writes over several keys may help.
+#[derive(Clone, PartialEq, Eq)]
+pub struct WriteConflict {
+    /// The key both transactions wrote.
+    pub key: Vec<u8>,
+    /// The primary key of this transaction.
+    pub primary: Vec<u8>,
+    /// The start timestamp of this transaction.
+    pub start_ts: u64,
+    /// The start timestamp of the conflicting transaction.
+    pub conflict_start_ts: u64,
+    /// The commit timestamp of the conflicting transaction.
+    pub conflict_commit_ts: u64,
+    /// When the conflict was detected, if known.
+    pub kind: Option<ConflictKind>,
+}
+
+impl fmt::Debug for WriteConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WriteConflict")
+            .field("key", &format_args!("{}", Redacted(&self.key)))
+            .field("primary", &format_args!("{}", Redacted(&self.primary)))
+            .field("start_ts", &self.start_ts)
+            .field("conflict_start_ts", &self.conflict_start_ts)
+            .field("conflict_commit_ts", &self.conflict_commit_ts)
+            .field("kind", &self.kind)
+            .finish()
+    }
+}
+
+impl fmt::Display for WriteConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Write conflict on key {}", Redacted(&self.key))?;
+        if let Some(kind) = self.kind {
+            write!(f, " ({kind})")?;
+        }
+        write!(
+            f,
+            ": transaction {} committed at {}, after transaction {} started",
+            self.conflict_start_ts, self.conflict_commit_ts, self.start_ts,
+        )
+    }
+}
+
+/// When a [`WriteConflict`] was detected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ConflictKind {
+    /// When prewriting the key to commit the transaction, because the key was written after the
+    /// transaction's start timestamp.
+    Optimistic,
+    /// When locking the key in a pessimistic transaction, because the key was written after the
+    /// lock's `for_update_ts`.
+    Pessimistic,
+}
+
+impl fmt::Display for ConflictKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConflictKind::Optimistic => f.write_str("optimistic"),
+            ConflictKind::Pessimistic => f.write_str("pessimistic"),
+        }
+    }
+}
+
+/// Shows an error as its `Debug` representation, unless content is redacted, in which case its
+/// `Display` representation, which redacts keys, is used instead.
+struct RedactedDebug<'a>(&'a Error);
+
+impl fmt::Debug for RedactedDebug<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match redaction() {
+            Redaction::Off => fmt::Debug::fmt(self.0, f),
+            _ => fmt::Display::fmt(self.0, f),
+        }
+    }
+}
+
+struct ErrorList<'a>(&'a [Error]);
+
+impl fmt::Display for ErrorList<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.0.iter().map(RedactedDebug))
+            .finish()
+    }
+}
+
+/// Details of where an error happened.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ErrorContext {
+    pub operation: Option<&'static str>,
+    pub key: Option<Vec<u8>>,
+    pub region_id: Option<u64>,
+    pub store_address: Option<String>,
+    pub retry_attempts: Option<u32>,
+    pub conflict_kind: Option<ConflictKind>,
+}
+
+impl ErrorContext {
+    // Fills in the details which are not already known from `other`.
+    fn merge(&mut self, other: ErrorContext) {
+        self.operation = self.operation.or(other.operation);
+        self.key = self.key.take().or(other.key);
+        self.region_id = self.region_id.or(other.region_id);
+        self.store_address = self.store_address.take().or(other.store_address);
+        self.retry_attempts = self.retry_attempts.or(other.retry_attempts);
+        self.conflict_kind = self.conflict_kind.or(other.conflict_kind);
+    }
+}
+
+impl fmt::Display for ErrorContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut sep = "";
+        if let Some(operation) = self.operation {
+            write!(f, "operation: {operation}")?;
+            sep = ", ";
+        }
+        if let Some(key) = &self.key {
+            write!(f, "{sep}key: {}", Redacted(key))?;
+            sep = ", ";
+        }
+        if let Some(region_id) = self.region_id {
+            write!(f, "{sep}region: {region_id}")?;
+            sep = ", ";
+        }
+        if let Some(store_address) = &self.store_address {
+            write!(f, "{sep}store: {store_address}")?;
+            sep = ", ";
+        }
+        if let Some(retry_attempts) = self.retry_attempts {
+            write!(f, "{sep}retries: {retry_attempts}")?;
+            sep = ", ";
+        }
+        if let Some(conflict_kind) = self.conflict_kind {
+            write!(f, "{sep}conflict: {conflict_kind}")?;
+        }
+        Ok(())
+    }
 }
 
 impl From<tikv_client_proto::errorpb::Error> for Error {
@@ -131,3 +635,142 @@ macro_rules! internal_err {
         internal_err!(format!($f, $($arg),+))
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::redaction::set_redaction;
+
+    #[test]
+    fn test_error_context() {
+        let e = Error::Unimplemented;
+        assert!(e.context().is_none());
+        assert!(e.operation().is_none());
+
+        let e = e
+            .with_context(ErrorContext {
+                operation: Some("kv_get"),
+                key: Some(vec![0xab, 0x01]),
+                ..Default::default()
+            })
+            .with_context(ErrorContext {
+                operation: Some("ignored"),
+                region_id: Some(2),
+                store_address: Some("127.0.0.1:20160".to_owned()),
+                retry_attempts: Some(3),
+                ..Default::default()
+            });
+        assert!(matches!(e.root(), Error::Unimplemented));
+        assert!(e.context().is_some());
+        assert_eq!(e.operation(), Some("kv_get"));
+        assert_eq!(e.key(), Some(&[0xab, 0x01][..]));
+        assert_eq!(e.region_id(), Some(2));
+        assert_eq!(e.store_address(), Some("127.0.0.1:20160"));
+        assert_eq!(e.retry_attempts(), Some(3));
+        assert_eq!(
+            e.to_string(),
+            "Unimplemented feature (operation: kv_get, key: AB01, region: 2, store: 127.0.0.1:20160, retries: 3)"
+        );
+
+        assert_eq!(e.code(), ErrorCode::Unimplemented);
+
+        set_redaction(Redaction::Redact);
+        let e = Error::Unimplemented.with_context(ErrorContext {
+            key: Some(vec![1]),
+            ..Default::default()
+        });
+        set_redaction(Redaction::Off);
+        assert!(e.key().is_none());
+        assert!(matches!(e.into_root(), Error::Unimplemented));
+
+        // Redaction is process-wide, so it is tested here rather than concurrently with the above.
+        let key_error = tikv_client_proto::kvrpcpb::KeyError {
+            already_exist: Some(tikv_client_proto::kvrpcpb::AlreadyExist { key: vec![0xAB] }),
+            abort: "key 0xAB exists".to_owned(),
+            ..Default::default()
+        };
+        let errors = Error::MultipleKeyErrors(vec![Error::KeyError(Box::new(key_error))]);
+        let e = Error::PessimisticLockError {
+            inner: Box::new(errors),
+            success_keys: Vec::new(),
+        }
+        .with_context(ErrorContext {
+            key: Some(vec![0xAB]),
+            ..Default::default()
+        });
+        let shown = e.to_string();
+        assert!(shown.contains("0xAB exists") && shown.contains("171"), "{shown}");
+        assert!(shown.contains("key: AB"), "{shown}");
+        set_redaction(Redaction::Hash);
+        let hashed = e.to_string();
+        set_redaction(Redaction::Redact);
+        let redacted = Error::LockNotHeld { key: vec![0xAB] }.to_string();
+        let conflict = WriteConflict {
+            key: vec![0xAB],
+            primary: vec![0xCD],
+            start_ts: 1,
+            conflict_start_ts: 2,
+            conflict_commit_ts: 3,
+            kind: None,
+        };
+        let conflict = format!("{conflict:?}");
+        set_redaction(Redaction::Off);
+        assert!(!conflict.contains("171") && !conflict.contains("AB"), "{conflict}");
+        assert!(conflict.contains("key: ?, primary: ?"), "{conflict}");
+        assert!(!hashed.contains("0xAB"), "{hashed}");
+        assert!(!hashed.contains("171"), "{hashed}");
+        assert!(hashed.contains(&format!("key: {}", hash_of(&[0xAB]))), "{hashed}");
+        assert_eq!(redacted, "Lock ? is not held by this lease");
+        assert_eq!(e.key(), Some(&[0xAB][..]));
+    }
+
+    fn hash_of(bytes: &[u8]) -> String {
+        set_redaction(Redaction::Hash);
+        let hash = Redacted(bytes).to_string();
+        set_redaction(Redaction::Off);
+        hash
+    }
+
+    #[test]
+    fn test_error_code() {
+        let conflict = Error::KeyError(Box::new(tikv_client_proto::kvrpcpb::KeyError {
+            conflict: Some(Default::default()),
+            ..Default::default()
+        }));
+        assert_eq!(conflict.code(), ErrorCode::WriteConflict);
+        assert!(conflict.is_retryable() && conflict.is_conflict());
+        assert!(!conflict.is_cluster_unavailable());
+
+        let extracted = Error::ExtractedErrors(vec![conflict]).with_context(Default::default());
+        assert_eq!(extracted.code(), ErrorCode::WriteConflict);
+        assert_eq!(extracted.write_conflict().unwrap().kind, None);
+        let extracted = extracted.with_conflict_kind(ConflictKind::Optimistic);
+        assert_eq!(extracted.code(), ErrorCode::WriteConflict);
+        assert!(matches!(extracted.root(), Error::ExtractedErrors(_)));
+        let conflict = extracted.write_conflict().unwrap();
+        assert_eq!(conflict.kind, Some(ConflictKind::Optimistic));
+
+        let busy = Error::RegionError(Box::new(tikv_client_proto::errorpb::Error {
+            server_is_busy: Some(Default::default()),
+            ..Default::default()
+        }));
+        assert_eq!(busy.code(), ErrorCode::ServerBusy);
+        assert!(busy.is_retryable() && busy.is_cluster_unavailable() && !busy.is_conflict());
+
+        let unavailable = Error::GrpcAPI(tonic::Status::unavailable("store down"));
+        assert_eq!(unavailable.code(), ErrorCode::Unavailable);
+        assert!(unavailable.is_cluster_unavailable());
+
+        let undetermined = Error::UndeterminedError {
+            source: Box::new(Error::DeadlineExceeded),
+            primary_key: vec![1],
+            start_ts: 42,
+        };
+        assert_eq!(undetermined.code(), ErrorCode::Undetermined);
+        let shown = undetermined.to_string();
+        assert!(shown.contains("start_ts 42, primary key 01"), "{shown}");
+        assert!(std::error::Error::source(&undetermined).is_some());
+        assert!(!undetermined.is_retryable());
+        assert_eq!(Error::NoPrimaryKey.code(), ErrorCode::InvalidUsage);
+    }
+}
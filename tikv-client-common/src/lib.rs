@@ -1,11 +1,40 @@
 #[macro_use]
 mod errors;
+mod proxy;
+mod redaction;
 pub mod security;
+mod spawner;
 
 #[macro_use]
 extern crate log;
 
+#[doc(inline)]
+pub use crate::errors::ConflictKind;
 #[doc(inline)]
 pub use crate::errors::Error;
 #[doc(inline)]
+pub use crate::errors::ErrorCode;
+#[doc(inline)]
+pub use crate::errors::ErrorContext;
+#[doc(inline)]
 pub use crate::errors::Result;
+#[doc(inline)]
+pub use crate::errors::WriteConflict;
+#[doc(inline)]
+pub use crate::proxy::Proxy;
+#[doc(inline)]
+pub use crate::proxy::ProxyProtocol;
+#[doc(inline)]
+pub use crate::redaction::redaction;
+#[doc(inline)]
+pub use crate::redaction::set_redaction;
+#[doc(inline)]
+pub use crate::redaction::set_redaction_key;
+#[doc(inline)]
+pub use crate::redaction::Redacted;
+#[doc(inline)]
+pub use crate::redaction::Redaction;
+#[doc(inline)]
+pub use crate::spawner::spawn_with;
+#[doc(inline)]
+pub use crate::spawner::Spawner;
@@ -0,0 +1,398 @@
+// Copyright 2023 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Tunneling connections to PD and TiKV through a proxy.
+
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::task::Context;
+use std::task::Poll;
+
+use serde_derive::Deserialize;
+use serde_derive::Serialize;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+use tonic::codegen::http::Uri;
+use tonic::codegen::Service;
+
+/// The protocol a [`Proxy`] speaks.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ProxyProtocol {
+    /// SOCKS version 5, with no authentication or with a username and password.
+    Socks5,
+    /// HTTP's `CONNECT` method, with no authentication or with basic authentication.
+    HttpConnect,
+}
+
+/// A proxy to tunnel the connections to PD and TiKV through, e.g., to reach a cluster from
+/// outside its network through a bastion host.
+///
+/// # Examples
+///
+/// ```rust
+/// # use tikv_client_common::Proxy;
+/// let proxy = Proxy::socks5("bastion:1080").with_credentials("user", "secret");
+/// ```
+///
+/// The password is never shown in the proxy's `Debug` representation, and never serialized.
+#[derive(Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub struct Proxy {
+    pub protocol: ProxyProtocol,
+    /// The address of the proxy, `host:port`.
+    pub address: String,
+    pub username: Option<String>,
+    #[serde(skip_serializing)]
+    pub password: Option<String>,
+}
+
+impl Proxy {
+    /// A SOCKS5 proxy at `address`.
+    pub fn socks5(address: impl Into<String>) -> Proxy {
+        Proxy::new(ProxyProtocol::Socks5, address.into())
+    }
+
+    /// An HTTP proxy at `address`, which tunnels connections with `CONNECT`.
+    pub fn http_connect(address: impl Into<String>) -> Proxy {
+        Proxy::new(ProxyProtocol::HttpConnect, address.into())
+    }
+
+    fn new(protocol: ProxyProtocol, address: String) -> Proxy {
+        Proxy {
+            protocol,
+            address,
+            username: None,
+            password: None,
+        }
+    }
+
+    /// Authenticate to the proxy with `username` and `password`.
+    #[must_use]
+    pub fn with_credentials(
+        mut self,
+        username: impl Into<String>,
+        password: impl Into<String>,
+    ) -> Proxy {
+        self.username = Some(username.into());
+        self.password = Some(password.into());
+        self
+    }
+
+    /// Open a connection to `host:port` through the proxy.
+    pub async fn connect(&self, host: &str, port: u16) -> io::Result<TcpStream> {
+        let mut stream = TcpStream::connect(&self.address).await?;
+        stream.set_nodelay(true)?;
+        match self.protocol {
+            ProxyProtocol::Socks5 => self.socks5_handshake(&mut stream, host, port).await?,
+            ProxyProtocol::HttpConnect => self.http_handshake(&mut stream, host, port).await?,
+        }
+        Ok(stream)
+    }
+
+    async fn socks5_handshake(
+        &self,
+        stream: &mut TcpStream,
+        host: &str,
+        port: u16,
+    ) -> io::Result<()> {
+        const NO_AUTH: u8 = 0;
+        const PASSWORD: u8 = 2;
+
+        let credentials = self.credentials();
+        let methods: &[u8] = match credentials {
+            Some(_) => &[NO_AUTH, PASSWORD],
+            None => &[NO_AUTH],
+        };
+        let mut greeting = vec![5, methods.len() as u8];
+        greeting.extend_from_slice(methods);
+        stream.write_all(&greeting).await?;
+        let mut reply = [0; 2];
+        stream.read_exact(&mut reply).await?;
+        match (reply, credentials) {
+            ([5, NO_AUTH], _) => {}
+            ([5, PASSWORD], Some((username, password))) => {
+                let mut request = vec![1];
+                for field in [username, password] {
+                    let len = u8::try_from(field.len())
+                        .map_err(|_| proxy_error("SOCKS5 credentials are too long"))?;
+                    request.push(len);
+                    request.extend_from_slice(field.as_bytes());
+                }
+                stream.write_all(&request).await?;
+                stream.read_exact(&mut reply).await?;
+                if reply[1] != 0 {
+                    return Err(proxy_error("SOCKS5 proxy rejected the credentials"));
+                }
+            }
+            _ => return Err(proxy_error("SOCKS5 proxy accepts no offered method")),
+        }
+
+        let mut request = vec![5, 1, 0];
+        match host.parse::<IpAddr>() {
+            Ok(IpAddr::V4(ip)) => {
+                request.push(1);
+                request.extend_from_slice(&ip.octets());
+            }
+            Ok(IpAddr::V6(ip)) => {
+                request.push(4);
+                request.extend_from_slice(&ip.octets());
+            }
+            Err(_) => {
+                let len = u8::try_from(host.len())
+                    .map_err(|_| proxy_error("host name is too long for SOCKS5"))?;
+                request.push(3);
+                request.push(len);
+                request.extend_from_slice(host.as_bytes());
+            }
+        }
+        request.extend_from_slice(&port.to_be_bytes());
+        stream.write_all(&request).await?;
+
+        let mut reply = [0; 4];
+        stream.read_exact(&mut reply).await?;
+        if reply[1] != 0 {
+            return Err(proxy_error(&format!(
+                "SOCKS5 proxy failed to connect to {host}:{port}, reply {}",
+                reply[1]
+            )));
+        }
+        // Skip the address the proxy bound, and its port.
+        let len = match reply[3] {
+            1 => 4,
+            4 => 16,
+            3 => stream.read_u8().await? as usize,
+            _ => return Err(proxy_error("SOCKS5 proxy sent a bad address type")),
+        };
+        let mut bound = vec![0; len + 2];
+        stream.read_exact(&mut bound).await?;
+        Ok(())
+    }
+
+    async fn http_handshake(
+        &self,
+        stream: &mut TcpStream,
+        host: &str,
+        port: u16,
+    ) -> io::Result<()> {
+        // The longest response header accepted from the proxy.
+        const MAX_HEADER: usize = 8 * 1024;
+
+        let authority = match host.parse::<IpAddr>() {
+            Ok(IpAddr::V6(_)) => format!("[{host}]:{port}"),
+            _ => format!("{host}:{port}"),
+        };
+        let mut request = format!("CONNECT {authority} HTTP/1.1\r\nHost: {authority}\r\n");
+        if let Some((username, password)) = self.credentials() {
+            let token = base64(format!("{username}:{password}").as_bytes());
+            request.push_str(&format!("Proxy-Authorization: Basic {token}\r\n"));
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes()).await?;
+
+        // Read byte by byte, so as not to consume what the server sends through the tunnel.
+        let mut header = Vec::new();
+        while !header.ends_with(b"\r\n\r\n") {
+            if header.len() == MAX_HEADER {
+                return Err(proxy_error("HTTP proxy response header is too long"));
+            }
+            header.push(stream.read_u8().await?);
+        }
+        let status_line = String::from_utf8_lossy(&header);
+        let status_line = status_line.lines().next().unwrap_or_default();
+        match status_line.split_whitespace().nth(1) {
+            Some(status) if status.starts_with('2') => Ok(()),
+            _ => Err(proxy_error(&format!(
+                "HTTP proxy failed to connect to {authority}: {status_line}"
+            ))),
+        }
+    }
+
+    fn credentials(&self) -> Option<(&str, &str)> {
+        let password = self.password.as_deref().unwrap_or_default();
+        self.username
+            .as_deref()
+            .map(|username| (username, password))
+    }
+}
+
+impl fmt::Debug for Proxy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Proxy")
+            .field("protocol", &self.protocol)
+            .field("address", &self.address)
+            .field("username", &self.username)
+            .field("password", &self.password.as_ref().map(|_| "<redacted>"))
+            .finish()
+    }
+}
+
+fn proxy_error(message: &str) -> io::Error {
+    io::Error::other(message.to_owned())
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, byte)| n | (*byte as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i)) as usize & 63] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// Connects gRPC channels through a proxy.
+#[derive(Clone)]
+pub(crate) struct ProxyConnector(pub Proxy);
+
+impl Service<Uri> for ProxyConnector {
+    type Response = TcpStream;
+    type Error = io::Error;
+    type Future = Pin<Box<dyn Future<Output = io::Result<TcpStream>> + Send>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let proxy = self.0.clone();
+        Box::pin(async move {
+            let host = uri
+                .host()
+                .ok_or_else(|| proxy_error(&format!("{uri} has no host")))?;
+            let host = host.trim_start_matches('[').trim_end_matches(']');
+            let port = uri.port_u16().unwrap_or(80);
+            proxy.connect(host, port).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    /// Accept a connection, run `handshake` as the proxy, and then echo what the client sends.
+    async fn serve<F, Fut>(handshake: F) -> String
+    where
+        F: FnOnce(TcpStream) -> Fut + Send + 'static,
+        Fut: Future<Output = TcpStream> + Send,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = handshake(stream).await;
+            let mut buf = [0; 5];
+            // The client hangs up instead if the handshake fails.
+            if stream.read_exact(&mut buf).await.is_ok() {
+                let _ = stream.write_all(&buf).await;
+            }
+        });
+        address
+    }
+
+    async fn assert_echoes(mut stream: TcpStream) {
+        stream.write_all(b"hello").await.unwrap();
+        let mut buf = [0; 5];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+    }
+
+    #[tokio::test]
+    async fn test_socks5() {
+        let address = serve(|mut stream| async move {
+            let mut greeting = [0; 4];
+            stream.read_exact(&mut greeting).await.unwrap();
+            assert_eq!(greeting, [5, 2, 0, 2]);
+            stream.write_all(&[5, 2]).await.unwrap();
+            let mut auth = [0; 7];
+            stream.read_exact(&mut auth).await.unwrap();
+            assert_eq!(&auth, b"\x01\x02me\x02pw");
+            stream.write_all(&[1, 0]).await.unwrap();
+            let mut request = [0; 11];
+            stream.read_exact(&mut request).await.unwrap();
+            assert_eq!(&request, b"\x05\x01\x00\x03\x04pd-0\x09\x4b");
+            stream
+                .write_all(&[5, 0, 0, 1, 127, 0, 0, 1, 0, 1])
+                .await
+                .unwrap();
+            stream
+        })
+        .await;
+        let proxy = Proxy::socks5(address).with_credentials("me", "pw");
+        assert_echoes(proxy.connect("pd-0", 2379).await.unwrap()).await;
+
+        let address = serve(|mut stream| async move {
+            let mut greeting = [0; 3];
+            stream.read_exact(&mut greeting).await.unwrap();
+            stream.write_all(&[5, 0xff]).await.unwrap();
+            stream
+        })
+        .await;
+        assert!(Proxy::socks5(address).connect("pd-0", 2379).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_http_connect() {
+        let address = serve(|mut stream| async move {
+            let expected = "CONNECT [::1]:20160 HTTP/1.1\r\nHost: [::1]:20160\r\n\
+                Proxy-Authorization: Basic bWU6cHc=\r\n\r\n";
+            let mut request = vec![0; expected.len()];
+            stream.read_exact(&mut request).await.unwrap();
+            assert_eq!(String::from_utf8(request).unwrap(), expected);
+            stream
+                .write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+                .await
+                .unwrap();
+            stream
+        })
+        .await;
+        let proxy = Proxy::http_connect(address).with_credentials("me", "pw");
+        let mut connector = ProxyConnector(proxy);
+        let uri = Uri::from_static("http://[::1]:20160");
+        assert_echoes(connector.call(uri).await.unwrap()).await;
+
+        let address = serve(|mut stream| async move {
+            let mut request = [0; 16];
+            stream.read_exact(&mut request).await.unwrap();
+            stream
+                .write_all(b"HTTP/1.1 407 Proxy Authentication Required\r\n\r\n")
+                .await
+                .unwrap();
+            stream
+        })
+        .await;
+        let e = Proxy::http_connect(address).connect("pd-0", 2379).await;
+        assert!(e.unwrap_err().to_string().contains("407"));
+    }
+
+    #[test]
+    fn test_password_is_hidden() {
+        let proxy = Proxy::socks5("bastion:1080").with_credentials("me", "secret");
+        assert!(!format!("{proxy:?}").contains("secret"));
+        assert!(format!("{proxy:?}").contains("<redacted>"));
+    }
+
+    #[test]
+    fn test_base64() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"user:pass"), "dXNlcjpwYXNz");
+    }
+}
@@ -0,0 +1,205 @@
+// Copyright 2023 TiKV Project Authors. Licensed under Apache-2.0.
+
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::BuildHasher;
+use std::hash::Hasher;
+use std::sync::atomic::AtomicU8;
+use std::sync::atomic::Ordering;
+use std::sync::RwLock;
+
+use hmac::Hmac;
+use hmac::Mac;
+use serde_derive::Deserialize;
+use serde_derive::Serialize;
+use sha2::Sha256;
+use tikv_client_proto::errorpb;
+use tikv_client_proto::kvrpcpb;
+
+/// How the content of keys and values is shown in errors and logs.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Redaction {
+    /// Content is shown, in hexadecimal.
+    #[default]
+    Off,
+    /// Content is replaced by `?`, and keys are left out of errors' contexts.
+    Redact,
+    /// Content is replaced by a keyed hash of it, so that messages about the same key can be
+    /// matched up without revealing it. The key is random for each process, so hashes are only
+    /// comparable within a process, unless the key is set with [`set_redaction_key`].
+    Hash,
+}
+
+static REDACTION: AtomicU8 = AtomicU8::new(0);
+
+lazy_static::lazy_static! {
+    static ref HASH_KEY: RwLock<Vec<u8>> = RwLock::new(random_key());
+}
+
+/// 16 random bytes, from the random keys of the standard library's hash maps.
+fn random_key() -> Vec<u8> {
+    let mut key = Vec::with_capacity(16);
+    for _ in 0..2 {
+        key.extend_from_slice(&RandomState::new().build_hasher().finish().to_be_bytes());
+    }
+    key
+}
+
+/// Set the key of the hashes content is replaced by with [`Redaction::Hash`], for the whole
+/// process, so that hashes are comparable between processes using the same key. The key must be
+/// kept secret: anyone who knows it can confirm guesses of the content. By default, each process
+/// uses a random key.
+pub fn set_redaction_key(key: impl Into<Vec<u8>>) {
+    *HASH_KEY.write().unwrap() = key.into();
+}
+
+/// Set how the content of keys and values is shown in errors and logs, for the whole process.
+/// Content is shown by default.
+pub fn set_redaction(redaction: Redaction) {
+    let redaction = match redaction {
+        Redaction::Off => 0,
+        Redaction::Redact => 1,
+        Redaction::Hash => 2,
+    };
+    REDACTION.store(redaction, Ordering::Relaxed);
+}
+
+/// How the content of keys and values is currently shown, as set by [`set_redaction`].
+pub fn redaction() -> Redaction {
+    match REDACTION.load(Ordering::Relaxed) {
+        0 => Redaction::Off,
+        1 => Redaction::Redact,
+        _ => Redaction::Hash,
+    }
+}
+
+/// Shows a key or value in hexadecimal, or redacts it, as set by [`set_redaction`].
+pub struct Redacted<'a>(pub &'a [u8]);
+
+impl fmt::Display for Redacted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match redaction() {
+            Redaction::Off => {
+                for byte in self.0 {
+                    write!(f, "{byte:02X}")?;
+                }
+                Ok(())
+            }
+            Redaction::Redact => f.write_str("?"),
+            Redaction::Hash => write!(f, "#{:016x}", hash(self.0)),
+        }
+    }
+}
+
+fn hash(bytes: &[u8]) -> u64 {
+    keyed_hash(&HASH_KEY.read().unwrap(), bytes)
+}
+
+/// The first 8 bytes of the HMAC-SHA256 of `bytes` with `key`.
+fn keyed_hash(key: &[u8], bytes: &[u8]) -> u64 {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(bytes);
+    let digest = mac.finalize().into_bytes();
+    u64::from_be_bytes(digest[..8].try_into().unwrap())
+}
+
+/// Replace `bytes` with nothing, or with their hash.
+fn redact_bytes(bytes: &mut Vec<u8>) {
+    match redaction() {
+        Redaction::Off => {}
+        Redaction::Redact => bytes.clear(),
+        Redaction::Hash => *bytes = hash(bytes).to_be_bytes().to_vec(),
+    }
+}
+
+/// Shows a message from TiKV, which may quote keys or values, or redacts it.
+pub(crate) struct RedactedMessage<'a>(pub &'a str);
+
+impl fmt::Display for RedactedMessage<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match redaction() {
+            Redaction::Off => f.write_str(self.0),
+            _ => Redacted(self.0.as_bytes()).fmt(f),
+        }
+    }
+}
+
+fn redact_message(message: &mut String) {
+    if redaction() != Redaction::Off && !message.is_empty() {
+        *message = RedactedMessage(message).to_string();
+    }
+}
+
+/// `error`, with the keys it holds redacted.
+pub(crate) fn redact_key_error(error: &kvrpcpb::KeyError) -> kvrpcpb::KeyError {
+    let mut error = error.clone();
+    if redaction() == Redaction::Off {
+        return error;
+    }
+    redact_message(&mut error.retryable);
+    redact_message(&mut error.abort);
+    if let Some(locked) = &mut error.locked {
+        redact_bytes(&mut locked.key);
+        redact_bytes(&mut locked.primary_lock);
+        locked.secondaries.iter_mut().for_each(redact_bytes);
+    }
+    if let Some(conflict) = &mut error.conflict {
+        redact_bytes(&mut conflict.key);
+        redact_bytes(&mut conflict.primary);
+    }
+    if let Some(already_exist) = &mut error.already_exist {
+        redact_bytes(&mut already_exist.key);
+    }
+    if let Some(deadlock) = &mut error.deadlock {
+        redact_bytes(&mut deadlock.lock_key);
+        for entry in &mut deadlock.wait_chain {
+            redact_bytes(&mut entry.key);
+        }
+    }
+    if let Some(expired) = &mut error.commit_ts_expired {
+        redact_bytes(&mut expired.key);
+    }
+    if let Some(not_found) = &mut error.txn_not_found {
+        redact_bytes(&mut not_found.primary_key);
+    }
+    if let Some(failed) = &mut error.assertion_failed {
+        redact_bytes(&mut failed.key);
+    }
+    error
+}
+
+/// `error`, with the keys it holds redacted.
+pub(crate) fn redact_region_error(error: &errorpb::Error) -> errorpb::Error {
+    let mut error = error.clone();
+    if redaction() == Redaction::Off {
+        return error;
+    }
+    redact_message(&mut error.message);
+    if let Some(not_in_region) = &mut error.key_not_in_region {
+        redact_bytes(&mut not_in_region.key);
+        redact_bytes(&mut not_in_region.start_key);
+        redact_bytes(&mut not_in_region.end_key);
+    }
+    if let Some(epoch_not_match) = &mut error.epoch_not_match {
+        for region in &mut epoch_not_match.current_regions {
+            redact_bytes(&mut region.start_key);
+            redact_bytes(&mut region.end_key);
+        }
+    }
+    error
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keyed_hash() {
+        let hashed = keyed_hash(b"key", b"secret");
+        assert_eq!(keyed_hash(b"key", b"secret"), hashed);
+        assert_ne!(keyed_hash(b"key", b"other"), hashed);
+        assert_ne!(keyed_hash(b"another key", b"secret"), hashed);
+        assert_ne!(random_key(), random_key());
+    }
+}
@@ -2,6 +2,7 @@
 
 use std::fs::File;
 use std::io::Read;
+use std::net::Ipv6Addr;
 use std::path::Path;
 use std::path::PathBuf;
 use std::time::Duration;
@@ -13,12 +14,39 @@ use tonic::transport::Channel;
 use tonic::transport::ClientTlsConfig;
 use tonic::transport::Identity;
 
+use crate::proxy::ProxyConnector;
+use crate::Proxy;
 use crate::Result;
 
 lazy_static::lazy_static! {
     static ref SCHEME_REG: Regex = Regex::new(r"^\s*(https?://)").unwrap();
 }
 
+/// The URL to connect to `addr`, which is either a URL or an address `host:port`.
+///
+/// An IPv6 host should be bracketed, as in `[::1]:2379`. An unbracketed IPv6 address is taken to
+/// end with the port, so `fd00::1:2379` is connected to as `[fd00::1]:2379`, unless what precedes
+/// the last colon is not an address, as in `::1`, which is connected to as `[::1]`.
+pub fn endpoint_url(addr: &str) -> String {
+    let addr = SCHEME_REG.replace(addr, "");
+    let addr = addr.trim();
+    let (authority, path) = match addr.find('/') {
+        Some(i) => addr.split_at(i),
+        None => (addr, ""),
+    };
+    match authority.rsplit_once(':') {
+        Some((host, port))
+            if !host.starts_with('[')
+                && host.parse::<Ipv6Addr>().is_ok()
+                && port.parse::<u16>().is_ok() =>
+        {
+            format!("http://[{host}]:{port}{path}")
+        }
+        _ if authority.parse::<Ipv6Addr>().is_ok() => format!("http://[{authority}]{path}"),
+        _ => format!("http://{authority}{path}"),
+    }
+}
+
 fn check_pem_file(tag: &str, path: &Path) -> Result<File> {
     File::open(path)
         .map_err(|e| internal_err!("failed to open {} to load {}: {:?}", path.display(), tag, e))
@@ -48,6 +76,8 @@ pub struct SecurityManager {
     cert: Vec<u8>,
     /// The path to the file that contains the PEM encoding of the server’s private key.
     key: PathBuf,
+    /// The proxy connections are tunneled through, if any.
+    proxy: Option<Proxy>,
 }
 
 impl SecurityManager {
@@ -63,9 +93,36 @@ impl SecurityManager {
             ca: load_pem_file("ca", ca_path.as_ref())?,
             cert: load_pem_file("certificate", cert_path.as_ref())?,
             key: key_path,
+            proxy: None,
         })
     }
 
+    /// Tunnel connections through `proxy`, if it is set.
+    #[must_use]
+    pub fn with_proxy(mut self, proxy: Option<Proxy>) -> SecurityManager {
+        self.proxy = proxy;
+        self
+    }
+
+    /// Whether connections use TLS.
+    pub fn is_tls(&self) -> bool {
+        !self.ca.is_empty()
+    }
+
+    /// The PEM encoding of the server's CA certificates.
+    pub fn ca_pem(&self) -> &[u8] {
+        &self.ca
+    }
+
+    /// The PEM encoding of the certificate chain followed by the private key, the form HTTP
+    /// clients take a client identity in.
+    pub fn identity_pem(&self) -> Result<Vec<u8>> {
+        let mut pem = self.cert.clone();
+        pem.push(b'\n');
+        pem.extend(load_pem_file("private key", &self.key)?);
+        Ok(pem)
+    }
+
     /// Connect to gRPC server using TLS connection. If TLS is not configured, use normal connection.
     pub async fn connect<Factory, Client>(
         &self,
@@ -76,7 +133,7 @@ impl SecurityManager {
     where
         Factory: FnOnce(Channel) -> Client,
     {
-        let addr = "http://".to_string() + &SCHEME_REG.replace(addr, "");
+        let addr = endpoint_url(addr);
 
         info!("connect to rpc server at endpoint: {:?}", addr);
 
@@ -94,7 +151,14 @@ impl SecurityManager {
             builder = builder.tls_config(tls)?;
         };
 
-        let ch = builder.connect().await?;
+        let ch = match &self.proxy {
+            Some(proxy) => {
+                builder
+                    .connect_with_connector(ProxyConnector(proxy.clone()))
+                    .await?
+            }
+            None => builder.connect().await?,
+        };
 
         Ok(factory(ch))
     }
@@ -131,4 +195,22 @@ mod tests {
         let key = load_pem_file("private key", &key_path).unwrap();
         assert_eq!(key, vec![2]);
     }
+
+    #[test]
+    fn test_endpoint_url() {
+        let cases = [
+            ("127.0.0.1:2379", "http://127.0.0.1:2379"),
+            (" https://pd-0.pd:2379/", "http://pd-0.pd:2379/"),
+            ("[::1]:2379", "http://[::1]:2379"),
+            ("http://[fd00::1]:20160", "http://[fd00::1]:20160"),
+            ("fd00::1:2379", "http://[fd00::1]:2379"),
+            ("fe80::2:20160/pd", "http://[fe80::2]:20160/pd"),
+            ("::1", "http://[::1]"),
+            ("http://::1/pd", "http://[::1]/pd"),
+            ("pd-0.pd", "http://pd-0.pd"),
+        ];
+        for (addr, url) in cases {
+            assert_eq!(endpoint_url(addr), url, "{addr}");
+        }
+    }
 }
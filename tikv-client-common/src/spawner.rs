@@ -0,0 +1,35 @@
+// Copyright 2023 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Where the client runs its background tasks.
+
+use std::future::Future;
+
+use futures::future::BoxFuture;
+
+/// Runs the background tasks of a client: transaction heartbeats, the commit of secondary keys,
+/// the flushes of a buffered writer, and the tasks serving the streams of timestamps from PD and
+/// of batched requests to TiKV.
+///
+/// Register a spawner using `Config::with_spawner`. Without one, tasks are spawned on the tokio
+/// runtime of the task which starts them. A [`tokio::runtime::Handle`] is a spawner, so tasks can
+/// be placed on a runtime of choice.
+pub trait Spawner: Send + Sync {
+    /// Run `task` to completion in the background.
+    fn spawn(&self, task: BoxFuture<'static, ()>);
+}
+
+impl Spawner for tokio::runtime::Handle {
+    fn spawn(&self, task: BoxFuture<'static, ()>) {
+        tokio::runtime::Handle::spawn(self, task);
+    }
+}
+
+/// Run `task` in the background, with `spawner` if there is one.
+pub fn spawn_with(spawner: Option<&dyn Spawner>, task: impl Future<Output = ()> + Send + 'static) {
+    match spawner {
+        Some(spawner) => spawner.spawn(Box::pin(task)),
+        None => {
+            tokio::spawn(task);
+        }
+    }
+}
@@ -1,12 +1,19 @@
 // Copyright 2018 TiKV Project Authors. Licensed under Apache-2.0.
 
+use std::collections::BTreeSet;
 use std::collections::HashSet;
+use std::net::SocketAddr;
 use std::sync::Arc;
 use std::time::Duration;
 use std::time::Instant;
 
 use async_trait::async_trait;
 use tikv_client_common::internal_err;
+use tikv_client_common::security::endpoint_url;
+use tikv_client_common::Error;
+use tikv_client_common::Spawner;
+use tikv_client_proto::keyspacepb;
+use tikv_client_proto::keyspacepb::keyspace_client::KeyspaceClient;
 use tikv_client_proto::pdpb::Timestamp;
 use tikv_client_proto::pdpb::{self};
 use tonic::transport::Channel;
@@ -18,11 +25,62 @@ use crate::Result;
 use crate::SecurityManager;
 
 /// A PD cluster.
+///
+/// The cluster's id is recorded on connecting, and every response from PD is checked against it.
 pub struct Cluster {
     id: u64,
     client: pdpb::pd_client::PdClient<Channel>,
+    keyspace_client: KeyspaceClient<Channel>,
     members: pdpb::GetMembersResponse,
     tso: TimestampOracle,
+    /// The endpoints the cluster was first connected through.
+    endpoints: Vec<String>,
+    endpoint: Endpoint,
+}
+
+/// The URL of the PD member a [`Cluster`]'s clients are connected to, and the addresses its host
+/// resolved to when they connected.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Endpoint {
+    url: String,
+    addresses: BTreeSet<SocketAddr>,
+}
+
+impl Endpoint {
+    async fn resolve(url: &str) -> Endpoint {
+        Endpoint {
+            url: url.to_owned(),
+            addresses: resolve(url).await.unwrap_or_default(),
+        }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Whether the host of the URL now resolves to other addresses, e.g., because the member was
+    /// rescheduled to another machine. If the host cannot be resolved, it is assumed to not have
+    /// moved.
+    pub async fn has_moved(&self) -> bool {
+        match resolve(&self.url).await {
+            Some(addresses) => !addresses.is_empty() && addresses != self.addresses,
+            None => false,
+        }
+    }
+}
+
+/// The addresses the host of `url` resolves to, or `None` if it cannot be resolved.
+async fn resolve(url: &str) -> Option<BTreeSet<SocketAddr>> {
+    let url = endpoint_url(url);
+    let authority = url.trim_start_matches("http://").split('/').next()?;
+    let addresses = tokio::net::lookup_host(authority).await;
+    match addresses {
+        Ok(addresses) => Some(addresses.collect()),
+        Err(e) => {
+            warn!("failed to resolve PD endpoint {}: {:?}", url, e);
+            None
+        }
+    }
 }
 
 macro_rules! pd_request {
@@ -37,6 +95,11 @@ macro_rules! pd_request {
 
 // These methods make a single attempt to make a request.
 impl Cluster {
+    /// The id of the PD cluster.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
     pub async fn get_region(
         &mut self,
         key: Vec<u8>,
@@ -44,7 +107,7 @@ impl Cluster {
     ) -> Result<pdpb::GetRegionResponse> {
         let mut req = pd_request!(self.id, pdpb::GetRegionRequest);
         req.region_key = key.clone();
-        req.send(&mut self.client, timeout).await
+        req.send(&mut self.client, self.id, timeout).await
     }
 
     pub async fn get_region_by_id(
@@ -54,7 +117,7 @@ impl Cluster {
     ) -> Result<pdpb::GetRegionResponse> {
         let mut req = pd_request!(self.id, pdpb::GetRegionByIdRequest);
         req.region_id = id;
-        req.send(&mut self.client, timeout).await
+        req.send(&mut self.client, self.id, timeout).await
     }
 
     pub async fn get_store(
@@ -64,7 +127,7 @@ impl Cluster {
     ) -> Result<pdpb::GetStoreResponse> {
         let mut req = pd_request!(self.id, pdpb::GetStoreRequest);
         req.store_id = id;
-        req.send(&mut self.client, timeout).await
+        req.send(&mut self.client, self.id, timeout).await
     }
 
     pub async fn get_all_stores(
@@ -72,7 +135,7 @@ impl Cluster {
         timeout: Duration,
     ) -> Result<pdpb::GetAllStoresResponse> {
         let req = pd_request!(self.id, pdpb::GetAllStoresRequest);
-        req.send(&mut self.client, timeout).await
+        req.send(&mut self.client, self.id, timeout).await
     }
 
     pub async fn get_timestamp(&self) -> Result<Timestamp> {
@@ -86,18 +149,92 @@ impl Cluster {
     ) -> Result<pdpb::UpdateGcSafePointResponse> {
         let mut req = pd_request!(self.id, pdpb::UpdateGcSafePointRequest);
         req.safe_point = safepoint;
-        req.send(&mut self.client, timeout).await
+        req.send(&mut self.client, self.id, timeout).await
+    }
+
+    pub async fn update_service_safepoint(
+        &mut self,
+        service_id: String,
+        ttl: i64,
+        safepoint: u64,
+        timeout: Duration,
+    ) -> Result<pdpb::UpdateServiceGcSafePointResponse> {
+        let mut req = pd_request!(self.id, pdpb::UpdateServiceGcSafePointRequest);
+        req.service_id = service_id.into_bytes();
+        req.ttl = ttl;
+        req.safe_point = safepoint;
+        req.send(&mut self.client, self.id, timeout).await
+    }
+
+    pub async fn load_keyspace(
+        &mut self,
+        name: String,
+        timeout: Duration,
+    ) -> Result<keyspacepb::LoadKeyspaceResponse> {
+        let mut req = pd_request!(self.id, keyspacepb::LoadKeyspaceRequest);
+        req.name = name;
+        req.send(&mut self.keyspace_client, self.id, timeout).await
+    }
+
+    pub async fn get_all_keyspaces(
+        &mut self,
+        start_id: u32,
+        limit: u32,
+        timeout: Duration,
+    ) -> Result<keyspacepb::GetAllKeyspacesResponse> {
+        let mut req = pd_request!(self.id, keyspacepb::GetAllKeyspacesRequest);
+        req.start_id = start_id;
+        req.limit = limit;
+        req.send(&mut self.keyspace_client, self.id, timeout).await
+    }
+
+    pub async fn split_and_scatter_regions(
+        &mut self,
+        split_keys: Vec<Vec<u8>>,
+        group: String,
+        timeout: Duration,
+    ) -> Result<pdpb::SplitAndScatterRegionsResponse> {
+        let mut req = pd_request!(self.id, pdpb::SplitAndScatterRegionsRequest);
+        req.split_keys = split_keys;
+        req.group = group;
+        req.send(&mut self.client, self.id, timeout).await
+    }
+
+    /// The client URL of the PD leader, e.g., for using PD's HTTP API.
+    pub fn leader_url(&self) -> Option<&str> {
+        self.members
+            .leader
+            .as_ref()
+            .and_then(|leader| leader.client_urls.first())
+            .map(String::as_str)
+    }
+
+    /// The member the cluster's clients are connected to.
+    pub fn endpoint(&self) -> &Endpoint {
+        &self.endpoint
     }
 }
 
 /// An object for connecting and reconnecting to a PD cluster.
 pub struct Connection {
     security_mgr: Arc<SecurityManager>,
+    spawner: Option<Arc<dyn Spawner>>,
 }
 
 impl Connection {
     pub fn new(security_mgr: Arc<SecurityManager>) -> Connection {
-        Connection { security_mgr }
+        Connection {
+            security_mgr,
+            spawner: None,
+        }
+    }
+
+    /// Run the timestamp oracles of the clusters connected to with `spawner`, or with tokio if
+    /// there is none.
+    #[must_use]
+    pub fn with_spawner(mut self, spawner: Option<Arc<dyn Spawner>>) -> Self {
+        self.spawner = spawner;
+        self
     }
 
     pub async fn connect_cluster(
@@ -106,14 +243,18 @@ impl Connection {
         timeout: Duration,
     ) -> Result<Cluster> {
         let members = self.validate_endpoints(endpoints, timeout).await?;
-        let (client, members) = self.try_connect_leader(&members, timeout).await?;
+        let ((client, keyspace_client, members), endpoint) =
+            self.try_connect_leader(&members, timeout).await?;
         let id = members.header.as_ref().unwrap().cluster_id;
-        let tso = TimestampOracle::new(id, &client)?;
+        let tso = TimestampOracle::new(id, &client, self.spawner.as_deref())?;
         let cluster = Cluster {
             id,
             client,
+            keyspace_client,
             members,
             tso,
+            endpoints: endpoints.to_vec(),
+            endpoint,
         };
         Ok(cluster)
     }
@@ -122,13 +263,31 @@ impl Connection {
     pub async fn reconnect(&self, cluster: &mut Cluster, timeout: Duration) -> Result<()> {
         warn!("updating pd client");
         let start = Instant::now();
-        let (client, members) = self.try_connect_leader(&cluster.members, timeout).await?;
-        let tso = TimestampOracle::new(cluster.id, &client)?;
+        let ((client, keyspace_client, members), endpoint) =
+            match self.try_connect_leader(&cluster.members, timeout).await {
+                Ok(connected) => connected,
+                // Every member the client knows of may have moved, e.g., if they were all
+                // rescheduled. Find the current members through the original endpoints, whose
+                // hosts are resolved again.
+                Err(e) => {
+                    warn!("no known PD member is reachable, trying the original endpoints");
+                    let members = self
+                        .validate_endpoints(&cluster.endpoints, timeout)
+                        .await
+                        .map_err(|_| e)?;
+                    check_cluster_id(cluster.id, members.header.as_ref())?;
+                    self.try_connect_leader(&members, timeout).await?
+                }
+            };
+        let tso = TimestampOracle::new(cluster.id, &client, self.spawner.as_deref())?;
         *cluster = Cluster {
             id: cluster.id,
             client,
+            keyspace_client,
             members,
             tso,
+            endpoints: std::mem::take(&mut cluster.endpoints),
+            endpoint,
         };
 
         info!("updating PD client done, spent {:?}", start.elapsed());
@@ -145,11 +304,11 @@ impl Connection {
         let mut members = None;
         let mut cluster_id = None;
         for ep in endpoints {
-            if !endpoints_set.insert(ep) {
+            if !endpoints_set.insert(endpoint_url(ep)) {
                 return Err(internal_err!("duplicated PD endpoint {}", ep));
             }
 
-            let (_, resp) = match self.connect(ep, timeout).await {
+            let (_, _, resp) = match self.connect(ep, timeout).await {
                 Ok(resp) => resp,
                 // Ignore failed PD node.
                 Err(e) => {
@@ -162,11 +321,11 @@ impl Connection {
             let cid = resp.header.as_ref().unwrap().cluster_id;
             if let Some(sample) = cluster_id {
                 if sample != cid {
-                    return Err(internal_err!(
-                        "PD response cluster_id mismatch, want {}, got {}",
-                        sample,
-                        cid
-                    ));
+                    error!("PD endpoint {} belongs to cluster {}, want {}", ep, cid, sample);
+                    return Err(Error::ClusterMismatch {
+                        expected: sample,
+                        actual: cid,
+                    });
                 }
             } else {
                 cluster_id = Some(cid);
@@ -191,16 +350,21 @@ impl Connection {
         &self,
         addr: &str,
         _timeout: Duration,
-    ) -> Result<(pdpb::pd_client::PdClient<Channel>, pdpb::GetMembersResponse)> {
-        let mut client = self
+    ) -> Result<PdClients> {
+        let (mut client, keyspace_client) = self
             .security_mgr
-            .connect(addr, pdpb::pd_client::PdClient::<Channel>::new)
+            .connect(addr, |channel| {
+                (
+                    pdpb::pd_client::PdClient::new(channel.clone()),
+                    KeyspaceClient::new(channel),
+                )
+            })
             .await?;
         let resp: pdpb::GetMembersResponse = client
             .get_members(pdpb::GetMembersRequest::default())
             .await?
             .into_inner();
-        Ok((client, resp))
+        Ok((client, keyspace_client, resp))
     }
 
     async fn try_connect(
@@ -208,10 +372,10 @@ impl Connection {
         addr: &str,
         cluster_id: u64,
         timeout: Duration,
-    ) -> Result<(pdpb::pd_client::PdClient<Channel>, pdpb::GetMembersResponse)> {
-        let (client, r) = self.connect(addr, timeout).await?;
+    ) -> Result<PdClients> {
+        let (client, keyspace_client, r) = self.connect(addr, timeout).await?;
         Connection::validate_cluster_id(addr, &r, cluster_id)?;
-        Ok((client, r))
+        Ok((client, keyspace_client, r))
     }
 
     fn validate_cluster_id(
@@ -221,27 +385,27 @@ impl Connection {
     ) -> Result<()> {
         let new_cluster_id = members.header.as_ref().unwrap().cluster_id;
         if new_cluster_id != cluster_id {
-            Err(internal_err!(
+            error!(
                 "{} no longer belongs to cluster {}, it is in {}",
-                addr,
-                cluster_id,
-                new_cluster_id
-            ))
-        } else {
-            Ok(())
+                addr, cluster_id, new_cluster_id
+            );
         }
+        check_cluster_id(cluster_id, members.header.as_ref())
     }
 
+    /// Connect to the leader of the cluster, as found through the members of `previous`. Returns
+    /// the clients and the leader's endpoint.
     async fn try_connect_leader(
         &self,
         previous: &pdpb::GetMembersResponse,
         timeout: Duration,
-    ) -> Result<(pdpb::pd_client::PdClient<Channel>, pdpb::GetMembersResponse)> {
+    ) -> Result<(PdClients, Endpoint)> {
         let previous_leader = previous.leader.as_ref().unwrap();
         let members = &previous.members;
         let cluster_id = previous.header.as_ref().unwrap().cluster_id;
 
         let mut resp = None;
+        let mut mismatch = None;
         // Try to connect to other members, then the previous leader.
         'outer: for m in members
             .iter()
@@ -250,12 +414,15 @@ impl Connection {
         {
             for ep in &m.client_urls {
                 match self.try_connect(ep.as_str(), cluster_id, timeout).await {
-                    Ok((_, r)) => {
+                    Ok((_, _, r)) => {
                         resp = Some(r);
                         break 'outer;
                     }
                     Err(e) => {
                         error!("failed to connect to {}, {:?}", ep, e);
+                        if let Error::ClusterMismatch { .. } = e {
+                            mismatch = Some(e);
+                        }
                         continue;
                     }
                 }
@@ -266,36 +433,61 @@ impl Connection {
         if let Some(resp) = resp {
             let leader = resp.leader.as_ref().unwrap();
             for ep in &leader.client_urls {
-                let r = self.try_connect(ep.as_str(), cluster_id, timeout).await;
-                if r.is_ok() {
-                    return r;
+                match self.try_connect(ep.as_str(), cluster_id, timeout).await {
+                    Ok(clients) => return Ok((clients, Endpoint::resolve(ep).await)),
+                    Err(e) => {
+                        error!("failed to connect to leader {}, {:?}", ep, e);
+                        if let Error::ClusterMismatch { .. } = e {
+                            mismatch = Some(e);
+                        }
+                    }
                 }
             }
         }
 
-        Err(internal_err!("failed to connect to {:?}", members))
+        // If the members or the leader we could reach belong to another cluster, say so rather
+        // than reporting an unreachable cluster.
+        Err(mismatch.unwrap_or_else(|| internal_err!("failed to connect to {:?}", members)))
+    }
+}
+
+/// Check that a response was sent by a member of the cluster with id `cluster_id`.
+pub(crate) fn check_cluster_id(cluster_id: u64, header: Option<&pdpb::ResponseHeader>) -> Result<()> {
+    match header {
+        Some(header) if header.cluster_id != cluster_id => Err(Error::ClusterMismatch {
+            expected: cluster_id,
+            actual: header.cluster_id,
+        }),
+        _ => Ok(()),
     }
 }
 
 type GrpcResult<T> = std::result::Result<T, tonic::Status>;
 
+/// Clients connected to a PD node, and the node's view of the cluster's members.
+type PdClients = (
+    pdpb::pd_client::PdClient<Channel>,
+    KeyspaceClient<Channel>,
+    pdpb::GetMembersResponse,
+);
+
 #[async_trait]
 trait PdMessage: Sized {
+    type Client: Send;
     type Response: PdResponse;
 
-    async fn rpc(
-        req: Request<Self>,
-        client: &mut pdpb::pd_client::PdClient<Channel>,
-    ) -> GrpcResult<Self::Response>;
+    async fn rpc(req: Request<Self>, client: &mut Self::Client) -> GrpcResult<Self::Response>;
 
     async fn send(
         self,
-        client: &mut pdpb::pd_client::PdClient<Channel>,
+        client: &mut Self::Client,
+        cluster_id: u64,
         timeout: Duration,
     ) -> Result<Self::Response> {
         let mut req = self.into_request();
         req.set_timeout(timeout);
         let response = Self::rpc(req, client).await?;
+        check_cluster_id(cluster_id, Some(response.header()))?;
 
         if let Some(err) = &response.header().error {
             Err(internal_err!(err.message))
@@ -307,64 +499,94 @@ trait PdMessage: Sized {
 
 #[async_trait]
 impl PdMessage for pdpb::GetRegionRequest {
+    type Client = pdpb::pd_client::PdClient<Channel>;
     type Response = pdpb::GetRegionResponse;
 
-    async fn rpc(
-        req: Request<Self>,
-        client: &mut pdpb::pd_client::PdClient<Channel>,
-    ) -> GrpcResult<Self::Response> {
+    async fn rpc(req: Request<Self>, client: &mut Self::Client) -> GrpcResult<Self::Response> {
         Ok(client.get_region(req).await?.into_inner())
     }
 }
 
 #[async_trait]
 impl PdMessage for pdpb::GetRegionByIdRequest {
+    type Client = pdpb::pd_client::PdClient<Channel>;
     type Response = pdpb::GetRegionResponse;
 
-    async fn rpc(
-        req: Request<Self>,
-        client: &mut pdpb::pd_client::PdClient<Channel>,
-    ) -> GrpcResult<Self::Response> {
+    async fn rpc(req: Request<Self>, client: &mut Self::Client) -> GrpcResult<Self::Response> {
         Ok(client.get_region_by_id(req).await?.into_inner())
     }
 }
 
 #[async_trait]
 impl PdMessage for pdpb::GetStoreRequest {
+    type Client = pdpb::pd_client::PdClient<Channel>;
     type Response = pdpb::GetStoreResponse;
 
-    async fn rpc(
-        req: Request<Self>,
-        client: &mut pdpb::pd_client::PdClient<Channel>,
-    ) -> GrpcResult<Self::Response> {
+    async fn rpc(req: Request<Self>, client: &mut Self::Client) -> GrpcResult<Self::Response> {
         Ok(client.get_store(req).await?.into_inner())
     }
 }
 
 #[async_trait]
 impl PdMessage for pdpb::GetAllStoresRequest {
+    type Client = pdpb::pd_client::PdClient<Channel>;
     type Response = pdpb::GetAllStoresResponse;
 
-    async fn rpc(
-        req: Request<Self>,
-        client: &mut pdpb::pd_client::PdClient<Channel>,
-    ) -> GrpcResult<Self::Response> {
+    async fn rpc(req: Request<Self>, client: &mut Self::Client) -> GrpcResult<Self::Response> {
         Ok(client.get_all_stores(req).await?.into_inner())
     }
 }
 
 #[async_trait]
 impl PdMessage for pdpb::UpdateGcSafePointRequest {
+    type Client = pdpb::pd_client::PdClient<Channel>;
     type Response = pdpb::UpdateGcSafePointResponse;
 
-    async fn rpc(
-        req: Request<Self>,
-        client: &mut pdpb::pd_client::PdClient<Channel>,
-    ) -> GrpcResult<Self::Response> {
+    async fn rpc(req: Request<Self>, client: &mut Self::Client) -> GrpcResult<Self::Response> {
         Ok(client.update_gc_safe_point(req).await?.into_inner())
     }
 }
 
+#[async_trait]
+impl PdMessage for pdpb::UpdateServiceGcSafePointRequest {
+    type Client = pdpb::pd_client::PdClient<Channel>;
+    type Response = pdpb::UpdateServiceGcSafePointResponse;
+
+    async fn rpc(req: Request<Self>, client: &mut Self::Client) -> GrpcResult<Self::Response> {
+        Ok(client.update_service_gc_safe_point(req).await?.into_inner())
+    }
+}
+
+#[async_trait]
+impl PdMessage for pdpb::SplitAndScatterRegionsRequest {
+    type Client = pdpb::pd_client::PdClient<Channel>;
+    type Response = pdpb::SplitAndScatterRegionsResponse;
+
+    async fn rpc(req: Request<Self>, client: &mut Self::Client) -> GrpcResult<Self::Response> {
+        Ok(client.split_and_scatter_regions(req).await?.into_inner())
+    }
+}
+
+#[async_trait]
+impl PdMessage for keyspacepb::LoadKeyspaceRequest {
+    type Client = KeyspaceClient<Channel>;
+    type Response = keyspacepb::LoadKeyspaceResponse;
+
+    async fn rpc(req: Request<Self>, client: &mut Self::Client) -> GrpcResult<Self::Response> {
+        Ok(client.load_keyspace(req).await?.into_inner())
+    }
+}
+
+#[async_trait]
+impl PdMessage for keyspacepb::GetAllKeyspacesRequest {
+    type Client = KeyspaceClient<Channel>;
+    type Response = keyspacepb::GetAllKeyspacesResponse;
+
+    async fn rpc(req: Request<Self>, client: &mut Self::Client) -> GrpcResult<Self::Response> {
+        Ok(client.get_all_keyspaces(req).await?.into_inner())
+    }
+}
+
 trait PdResponse {
     fn header(&self) -> &pdpb::ResponseHeader;
 }
@@ -392,3 +614,88 @@ impl PdResponse for pdpb::UpdateGcSafePointResponse {
         self.header.as_ref().unwrap()
     }
 }
+
+impl PdResponse for pdpb::UpdateServiceGcSafePointResponse {
+    fn header(&self) -> &pdpb::ResponseHeader {
+        self.header.as_ref().unwrap()
+    }
+}
+
+impl PdResponse for pdpb::SplitAndScatterRegionsResponse {
+    fn header(&self) -> &pdpb::ResponseHeader {
+        self.header.as_ref().unwrap()
+    }
+}
+
+impl PdResponse for keyspacepb::LoadKeyspaceResponse {
+    fn header(&self) -> &pdpb::ResponseHeader {
+        self.header.as_ref().unwrap()
+    }
+}
+
+impl PdResponse for keyspacepb::GetAllKeyspacesResponse {
+    fn header(&self) -> &pdpb::ResponseHeader {
+        self.header.as_ref().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(cluster_id: u64) -> pdpb::ResponseHeader {
+        pdpb::ResponseHeader {
+            cluster_id,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_check_cluster_id() {
+        check_cluster_id(1, Some(&header(1))).unwrap();
+        // Responses without a header can't be checked.
+        check_cluster_id(1, None).unwrap();
+        assert!(matches!(
+            check_cluster_id(1, Some(&header(2))),
+            Err(Error::ClusterMismatch {
+                expected: 1,
+                actual: 2
+            })
+        ));
+    }
+
+    #[test]
+    fn test_validate_cluster_id() {
+        let members = pdpb::GetMembersResponse {
+            header: Some(header(1)),
+            ..Default::default()
+        };
+        Connection::validate_cluster_id("127.0.0.1:2379", &members, 1).unwrap();
+        assert!(matches!(
+            Connection::validate_cluster_id("127.0.0.1:2379", &members, 2),
+            Err(Error::ClusterMismatch {
+                expected: 2,
+                actual: 1
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_endpoint_has_moved() {
+        let endpoint = Endpoint::resolve("127.0.0.1:2379").await;
+        let addresses: BTreeSet<SocketAddr> = ["127.0.0.1:2379".parse().unwrap()].into();
+        assert_eq!(endpoint.addresses, addresses);
+        assert!(!endpoint.has_moved().await);
+
+        // A host may resolve to several addresses, in any order; only the set of them matters.
+        let endpoint = Endpoint::resolve("localhost:2379").await;
+        assert!(!endpoint.addresses.is_empty());
+        assert!(!endpoint.has_moved().await);
+
+        let endpoint = Endpoint {
+            url: "127.0.0.1:2379".to_owned(),
+            addresses: ["10.0.0.1:2379".parse().unwrap()].into(),
+        };
+        assert!(endpoint.has_moved().await);
+    }
+}
@@ -5,6 +5,8 @@ pub use cluster::Cluster;
 #[doc(inline)]
 pub use cluster::Connection;
 #[doc(inline)]
+pub use cluster::Endpoint;
+#[doc(inline)]
 pub use tikv_client_common::security::SecurityManager;
 #[doc(inline)]
 pub use tikv_client_common::Error;
@@ -23,6 +23,8 @@ use futures::task::Poll;
 use log::debug;
 use pin_project::pin_project;
 use tikv_client_common::internal_err;
+use tikv_client_common::spawn_with;
+use tikv_client_common::Spawner;
 use tikv_client_proto::pdpb::pd_client::PdClient;
 use tikv_client_proto::pdpb::*;
 use tokio::sync::mpsc;
@@ -30,6 +32,7 @@ use tokio::sync::oneshot;
 use tokio::sync::Mutex;
 use tonic::transport::Channel;
 
+use crate::cluster::check_cluster_id;
 use crate::Result;
 
 /// It is an empirical value.
@@ -52,12 +55,18 @@ pub(crate) struct TimestampOracle {
 }
 
 impl TimestampOracle {
-    pub(crate) fn new(cluster_id: u64, pd_client: &PdClient<Channel>) -> Result<TimestampOracle> {
+    pub(crate) fn new(
+        cluster_id: u64,
+        pd_client: &PdClient<Channel>,
+        spawner: Option<&dyn Spawner>,
+    ) -> Result<TimestampOracle> {
         let pd_client = pd_client.clone();
         let (request_tx, request_rx) = mpsc::channel(MAX_BATCH_SIZE);
 
-        // Start a background thread to handle TSO requests and responses
-        tokio::spawn(run_tso(cluster_id, pd_client, request_rx));
+        // Start a background task, with `spawner` if there is one, to handle TSO requests and
+        // responses
+        let tso = run_tso(cluster_id, pd_client, request_rx).map(|_| ());
+        spawn_with(spawner, tso);
 
         Ok(TimestampOracle { request_tx })
     }
@@ -105,6 +114,7 @@ async fn run_tso(
             sending_future_waker.wake();
         }
 
+        check_cluster_id(cluster_id, resp.header.as_ref())?;
         allocate_timestamps(&resp, &mut pending_requests)?;
     }
     // TODO: distinguish between unexpected stream termination and expected end of test
@@ -4,6 +4,9 @@
 
 pub use protos::*;
 
+#[cfg(feature = "rust-protobuf")]
+pub mod rust_protobuf;
+
 mod protos {
     include!(concat!(env!("OUT_DIR"), "/mod.rs"));
 }
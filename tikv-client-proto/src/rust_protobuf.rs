@@ -0,0 +1,84 @@
+// Copyright 2023 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Conversions between this crate's messages, which are generated by prost, and the same messages
+//! generated by rust-protobuf, e.g., by a project already using the `kvproto` crate.
+//!
+//! Messages are converted through their wire encoding, so a message converts to any message with
+//! the same definition, whichever crate generated it.
+
+use std::fmt;
+
+/// A message could not be converted.
+#[derive(Debug)]
+pub enum ConvertError {
+    Prost(prost::DecodeError),
+    Protobuf(protobuf::ProtobufError),
+}
+
+impl fmt::Display for ConvertError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConvertError::Prost(e) => write!(f, "failed to decode prost message: {e}"),
+            ConvertError::Protobuf(e) => write!(f, "failed to convert rust-protobuf message: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for ConvertError {}
+
+/// A prost message which can be converted to and from its rust-protobuf counterpart.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// use tikv_client_proto::kvrpcpb;
+/// use tikv_client_proto::rust_protobuf::RustProtobuf;
+///
+/// let request: kvproto::kvrpcpb::GetRequest = /* ... */;
+/// let request = kvrpcpb::GetRequest::from_rust_protobuf(&request)?;
+/// let request: kvproto::kvrpcpb::GetRequest = request.to_rust_protobuf()?;
+/// ```
+pub trait RustProtobuf: prost::Message + Default {
+    /// Convert `message`, generated by rust-protobuf, to this message.
+    fn from_rust_protobuf(message: &impl protobuf::Message) -> Result<Self, ConvertError> {
+        let bytes = message.write_to_bytes().map_err(ConvertError::Protobuf)?;
+        Self::decode(bytes.as_slice()).map_err(ConvertError::Prost)
+    }
+
+    /// Convert this message to `M`, generated by rust-protobuf.
+    fn to_rust_protobuf<M: protobuf::Message>(&self) -> Result<M, ConvertError> {
+        M::parse_from_bytes(&self.encode_to_vec()).map_err(ConvertError::Protobuf)
+    }
+}
+
+impl<T: prost::Message + Default> RustProtobuf for T {}
+
+#[cfg(test)]
+mod tests {
+    use protobuf::well_known_types::Timestamp;
+
+    use super::*;
+    use crate::pdpb;
+
+    #[test]
+    fn test_convert() {
+        // PD's timestamp has the fields of protobuf's well-known timestamp, with the same numbers
+        // and wire types, so it serves as a counterpart without generating rust-protobuf code.
+        let ts = pdpb::Timestamp {
+            physical: 1_600_000_000,
+            logical: 42,
+            suffix_bits: 0,
+        };
+        let converted: Timestamp = ts.to_rust_protobuf().unwrap();
+        assert_eq!((converted.seconds, converted.nanos), (1_600_000_000, 42));
+        assert_eq!(pdpb::Timestamp::from_rust_protobuf(&converted).unwrap(), ts);
+
+        // A message whose fields don't decode as the target's is an error.
+        let region = crate::metapb::Region {
+            id: 3,
+            start_key: b"a".to_vec(),
+            ..Default::default()
+        };
+        assert!(region.to_rust_protobuf::<Timestamp>().is_err());
+    }
+}
@@ -0,0 +1,404 @@
+// Copyright 2023 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Coalescing of requests to a store into the batches of a `BatchCommands` stream.
+
+use std::any::Any;
+use std::collections::HashMap;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use futures::channel::mpsc;
+use futures::channel::oneshot;
+use futures::prelude::*;
+use futures::stream::BoxStream;
+use tikv_client_common::internal_err;
+use tikv_client_common::spawn_with;
+use tikv_client_common::Spawner;
+use tikv_client_proto::tikvpb::batch_commands_request;
+use tikv_client_proto::tikvpb::batch_commands_response;
+use tikv_client_proto::tikvpb::tikv_client::TikvClient;
+use tikv_client_proto::tikvpb::BatchCommandsRequest;
+use tikv_client_proto::tikvpb::BatchCommandsResponse;
+use tokio::time::timeout;
+use tokio::time::Instant;
+use tonic::transport::Channel;
+use tonic::Status;
+
+use crate::Error;
+use crate::Result;
+
+/// How requests to a store are coalesced into batches.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BatchConfig {
+    /// How long the first request of a batch waits for more requests to join it.
+    pub max_delay: Duration,
+    /// The most requests sent in a batch.
+    pub max_batch_size: usize,
+}
+
+/// Opens `BatchCommands` streams to a store.
+#[async_trait]
+pub(crate) trait BatchTransport: Send + Sync + 'static {
+    /// Open a stream sending `requests`, and return the stream of their responses.
+    async fn open(
+        &self,
+        requests: mpsc::UnboundedReceiver<BatchCommandsRequest>,
+    ) -> Result<BoxStream<'static, Result<BatchCommandsResponse>>>;
+}
+
+#[async_trait]
+impl BatchTransport for TikvClient<Channel> {
+    async fn open(
+        &self,
+        requests: mpsc::UnboundedReceiver<BatchCommandsRequest>,
+    ) -> Result<BoxStream<'static, Result<BatchCommandsResponse>>> {
+        let responses = self
+            .clone()
+            .batch_commands(requests)
+            .await
+            .map_err(Error::GrpcAPI)?;
+        Ok(responses.into_inner().map_err(Error::GrpcAPI).boxed())
+    }
+}
+
+type ResponseSender = oneshot::Sender<Result<batch_commands_response::response::Cmd>>;
+
+/// The requests of a stream awaiting responses, by request id, or `None` once the stream has
+/// closed.
+type InFlight = Arc<Mutex<Option<HashMap<u64, ResponseSender>>>>;
+
+/// A request submitted to a batcher, with its id, or the cancellation of a request whose response
+/// is no longer awaited.
+enum Submission {
+    Request(u64, batch_commands_request::request::Cmd, ResponseSender),
+    Cancel(u64),
+}
+
+/// Sends the requests submitted to it to a store in batches, over a `BatchCommands` stream which is
+/// opened when the first batch is sent, and reopened if it closes.
+pub(crate) struct CommandBatcher {
+    submit: mpsc::UnboundedSender<Submission>,
+    next_id: AtomicU64,
+}
+
+impl CommandBatcher {
+    /// Start a batcher, whose tasks are run by `spawner`, or by tokio if there is none.
+    pub fn new(
+        config: BatchConfig,
+        transport: impl BatchTransport,
+        spawner: Option<Arc<dyn Spawner>>,
+    ) -> CommandBatcher {
+        let (submit, submitted) = mpsc::unbounded();
+        let batches = run_batches(config, transport, submitted, spawner.clone());
+        spawn_with(spawner.as_deref(), batches);
+        CommandBatcher {
+            submit,
+            next_id: AtomicU64::new(0),
+        }
+    }
+
+    /// Send `cmd` in the next batch, and wait at most `request_timeout` for its response.
+    pub async fn dispatch(
+        &self,
+        cmd: batch_commands_request::request::Cmd,
+        request_timeout: Duration,
+    ) -> Result<Box<dyn Any>> {
+        let (tx, rx) = oneshot::channel();
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        self.submit
+            .unbounded_send(Submission::Request(id, cmd, tx))
+            .map_err(|_| stream_closed())?;
+        let response = match timeout(request_timeout, rx).await {
+            Ok(response) => response.map_err(|_| stream_closed())??,
+            Err(_) => {
+                // Otherwise the request would be kept in flight until its stream closes.
+                let _ = self.submit.unbounded_send(Submission::Cancel(id));
+                let status = Status::deadline_exceeded("batched request timed out");
+                return Err(Error::GrpcAPI(status));
+            }
+        };
+        response_into_any(response)
+    }
+}
+
+/// Collect the submitted requests into batches, and send them, until the batcher is dropped.
+async fn run_batches(
+    config: BatchConfig,
+    transport: impl BatchTransport,
+    mut submitted: mpsc::UnboundedReceiver<Submission>,
+    spawner: Option<Arc<dyn Spawner>>,
+) {
+    let max_batch_size = config.max_batch_size.max(1);
+    let mut stream: Option<(mpsc::UnboundedSender<BatchCommandsRequest>, InFlight)> = None;
+    while let Some(first) = submitted.next().await {
+        let mut batch = match first {
+            Submission::Request(id, cmd, tx) => vec![(id, cmd, tx)],
+            Submission::Cancel(id) => {
+                cancel(&stream, id);
+                continue;
+            }
+        };
+        let deadline = Instant::now() + config.max_delay;
+        while batch.len() < max_batch_size {
+            match tokio::time::timeout_at(deadline, submitted.next()).await {
+                Ok(Some(Submission::Request(id, cmd, tx))) => batch.push((id, cmd, tx)),
+                Ok(Some(Submission::Cancel(id))) => {
+                    batch.retain(|(request, _, _)| *request != id);
+                    cancel(&stream, id);
+                }
+                Ok(None) | Err(_) => break,
+            }
+        }
+        if batch.is_empty() {
+            continue;
+        }
+
+        let open = stream.as_ref().is_some_and(|(requests, in_flight)| {
+            !requests.is_closed() && in_flight.lock().unwrap().is_some()
+        });
+        if !open {
+            stream = match open_stream(&transport, spawner.as_deref()).await {
+                Ok(opened) => Some(opened),
+                Err(e) => {
+                    for (_, _, tx) in batch {
+                        let _ = tx.send(Err(stream_error(&e)));
+                    }
+                    continue;
+                }
+            };
+        }
+        let (requests, in_flight) = stream.as_ref().expect("Unreachable: the stream is open");
+
+        let mut request = BatchCommandsRequest::default();
+        {
+            let mut in_flight = in_flight.lock().unwrap();
+            let pending = match in_flight.as_mut() {
+                Some(pending) => pending,
+                None => {
+                    for (_, _, tx) in batch {
+                        let _ = tx.send(Err(stream_closed()));
+                    }
+                    continue;
+                }
+            };
+            for (id, cmd, tx) in batch {
+                pending.insert(id, tx);
+                request.request_ids.push(id);
+                request
+                    .requests
+                    .push(batch_commands_request::Request { cmd: Some(cmd) });
+            }
+        }
+        if requests.unbounded_send(request).is_err() {
+            fail_in_flight(in_flight);
+        }
+    }
+}
+
+/// Open a stream, and spawn a task routing its responses to the requests awaiting them.
+async fn open_stream(
+    transport: &impl BatchTransport,
+    spawner: Option<&dyn Spawner>,
+) -> Result<(mpsc::UnboundedSender<BatchCommandsRequest>, InFlight)> {
+    let (requests, outgoing) = mpsc::unbounded();
+    let mut responses = transport.open(outgoing).await?;
+    let in_flight: InFlight = Arc::new(Mutex::new(Some(HashMap::new())));
+    let routed = in_flight.clone();
+    spawn_with(spawner, async move {
+        while let Some(Ok(batch)) = responses.next().await {
+            let mut in_flight = routed.lock().unwrap();
+            let pending = match in_flight.as_mut() {
+                Some(pending) => pending,
+                None => return,
+            };
+            for (id, response) in batch.request_ids.into_iter().zip(batch.responses) {
+                if let Some(tx) = pending.remove(&id) {
+                    let response = response
+                        .cmd
+                        .ok_or_else(|| internal_err!("empty response to a batched request"));
+                    let _ = tx.send(response);
+                }
+            }
+        }
+        fail_in_flight(&routed);
+    });
+    Ok((requests, in_flight))
+}
+
+/// Forget the request `id` of `stream`, if it is in flight, since its response is no longer
+/// awaited.
+fn cancel(stream: &Option<(mpsc::UnboundedSender<BatchCommandsRequest>, InFlight)>, id: u64) {
+    if let Some((_, in_flight)) = stream {
+        if let Some(pending) = in_flight.lock().unwrap().as_mut() {
+            pending.remove(&id);
+        }
+    }
+}
+
+/// Close the stream of `in_flight`, failing the requests awaiting responses.
+fn fail_in_flight(in_flight: &InFlight) {
+    let pending = in_flight.lock().unwrap().take();
+    for (_, tx) in pending.into_iter().flatten() {
+        let _ = tx.send(Err(stream_closed()));
+    }
+}
+
+fn stream_closed() -> Error {
+    Error::GrpcAPI(Status::unavailable("the batch commands stream closed"))
+}
+
+fn stream_error(e: &Error) -> Error {
+    match e {
+        Error::GrpcAPI(status) => Error::GrpcAPI(status.clone()),
+        _ => stream_closed(),
+    }
+}
+
+fn response_into_any(response: batch_commands_response::response::Cmd) -> Result<Box<dyn Any>> {
+    use batch_commands_response::response::Cmd;
+    match response {
+        Cmd::Prewrite(response) => Ok(Box::new(response)),
+        Cmd::Commit(response) => Ok(Box::new(response)),
+        _ => Err(internal_err!("unexpected response to a batched request")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tikv_client_proto::kvrpcpb;
+
+    use super::*;
+
+    /// Answers each request with a response of the same kind, recording the size of each batch.
+    struct EchoTransport {
+        batch_sizes: Arc<Mutex<Vec<usize>>>,
+    }
+
+    #[async_trait]
+    impl BatchTransport for EchoTransport {
+        async fn open(
+            &self,
+            requests: mpsc::UnboundedReceiver<BatchCommandsRequest>,
+        ) -> Result<BoxStream<'static, Result<BatchCommandsResponse>>> {
+            let batch_sizes = self.batch_sizes.clone();
+            let responses = requests.map(move |batch| {
+                batch_sizes.lock().unwrap().push(batch.requests.len());
+                let responses = batch.requests.into_iter().map(|request| {
+                    use batch_commands_request::request::Cmd;
+                    use batch_commands_response::response::Cmd as Response;
+                    let cmd = match request.cmd.unwrap() {
+                        Cmd::Prewrite(_) => Response::Prewrite(Default::default()),
+                        Cmd::Commit(req) => Response::Commit(kvrpcpb::CommitResponse {
+                            commit_version: req.commit_version,
+                            ..Default::default()
+                        }),
+                        _ => unreachable!(),
+                    };
+                    batch_commands_response::Response { cmd: Some(cmd) }
+                });
+                Ok(BatchCommandsResponse {
+                    responses: responses.collect(),
+                    request_ids: batch.request_ids,
+                    ..Default::default()
+                })
+            });
+            Ok(responses.boxed())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_batches() {
+        let batch_sizes = Arc::new(Mutex::new(Vec::new()));
+        let config = BatchConfig {
+            max_delay: Duration::from_millis(50),
+            max_batch_size: 3,
+        };
+        let transport = EchoTransport {
+            batch_sizes: batch_sizes.clone(),
+        };
+        let batcher = CommandBatcher::new(config, transport, None);
+
+        let commits = (1..=5).map(|version| {
+            let request = kvrpcpb::CommitRequest {
+                commit_version: version,
+                ..Default::default()
+            };
+            let cmd = batch_commands_request::request::Cmd::Commit(request);
+            batcher.dispatch(cmd, Duration::from_secs(10))
+        });
+        let responses = future::try_join_all(commits).await.unwrap();
+        let versions: Vec<u64> = responses
+            .into_iter()
+            .map(|response| {
+                let response = response.downcast::<kvrpcpb::CommitResponse>().unwrap();
+                response.commit_version
+            })
+            .collect();
+        assert_eq!(versions, vec![1, 2, 3, 4, 5]);
+        assert_eq!(*batch_sizes.lock().unwrap(), vec![3, 2]);
+
+        let cmd = batch_commands_request::request::Cmd::Prewrite(Default::default());
+        let response = batcher.dispatch(cmd, Duration::from_secs(10)).await;
+        assert!(response.unwrap().is::<kvrpcpb::PrewriteResponse>());
+        assert_eq!(*batch_sizes.lock().unwrap(), vec![3, 2, 1]);
+    }
+
+    #[tokio::test]
+    async fn test_timed_out_request() {
+        let batch_sizes = Arc::new(Mutex::new(Vec::new()));
+        let config = BatchConfig {
+            max_delay: Duration::from_millis(200),
+            max_batch_size: 3,
+        };
+        let transport = EchoTransport {
+            batch_sizes: batch_sizes.clone(),
+        };
+        let batcher = CommandBatcher::new(config, transport, None);
+
+        // The request times out while its batch waits for more requests, so it is never sent.
+        let cmd = batch_commands_request::request::Cmd::Prewrite(Default::default());
+        let err = batcher
+            .dispatch(cmd, Duration::from_millis(10))
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(err, Error::GrpcAPI(status) if status.code() == tonic::Code::DeadlineExceeded)
+        );
+        let cmd = batch_commands_request::request::Cmd::Prewrite(Default::default());
+        let response = batcher.dispatch(cmd, Duration::from_secs(10)).await;
+        assert!(response.unwrap().is::<kvrpcpb::PrewriteResponse>());
+        assert_eq!(*batch_sizes.lock().unwrap(), vec![1]);
+    }
+
+    /// Fails to open streams.
+    struct FailingTransport;
+
+    #[async_trait]
+    impl BatchTransport for FailingTransport {
+        async fn open(
+            &self,
+            _: mpsc::UnboundedReceiver<BatchCommandsRequest>,
+        ) -> Result<BoxStream<'static, Result<BatchCommandsResponse>>> {
+            Err(Error::GrpcAPI(Status::unavailable("connection refused")))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stream_failure() {
+        let config = BatchConfig {
+            max_delay: Duration::ZERO,
+            max_batch_size: 1,
+        };
+        let batcher = CommandBatcher::new(config, FailingTransport, None);
+        let cmd = batch_commands_request::request::Cmd::Prewrite(Default::default());
+        let err = batcher
+            .dispatch(cmd, Duration::from_secs(10))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::GrpcAPI(status) if status.message() == "connection refused"));
+    }
+}
@@ -1,15 +1,24 @@
 // Copyright 2020 TiKV Project Authors. Licensed under Apache-2.0.
 
 use std::any::Any;
+use std::convert::TryFrom;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::time::Duration;
 
 use async_trait::async_trait;
 use derive_new::new;
+use tikv_client_common::Spawner;
+use tikv_client_proto::kvrpcpb;
 use tikv_client_proto::tikvpb::tikv_client::TikvClient;
 use tonic::transport::Channel;
+use tonic::IntoRequest;
 
+use crate::batch::BatchConfig;
+use crate::batch::CommandBatcher;
 use crate::request::Request;
+use crate::Error;
 use crate::Result;
 use crate::SecurityManager;
 
@@ -21,10 +30,68 @@ pub trait KvConnect: Sized + Send + Sync + 'static {
     async fn connect(&self, address: &str) -> Result<Self::KvClient>;
 }
 
-#[derive(new, Clone)]
+/// The timeout of requests to TiKV, shared by the connections of a client so that it can be
+/// changed while they are open.
+#[derive(Clone, Debug)]
+pub struct RequestTimeout(Arc<AtomicU64>);
+
+impl RequestTimeout {
+    pub fn new(timeout: Duration) -> RequestTimeout {
+        RequestTimeout(Arc::new(AtomicU64::new(duration_to_nanos(timeout))))
+    }
+
+    pub fn get(&self) -> Duration {
+        Duration::from_nanos(self.0.load(Ordering::Relaxed))
+    }
+
+    /// Use `timeout` for the requests sent from now on.
+    pub fn set(&self, timeout: Duration) {
+        self.0.store(duration_to_nanos(timeout), Ordering::Relaxed);
+    }
+}
+
+fn duration_to_nanos(duration: Duration) -> u64 {
+    u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX)
+}
+
+#[derive(Clone)]
 pub struct TikvConnect {
     security_mgr: Arc<SecurityManager>,
-    timeout: Duration,
+    timeout: RequestTimeout,
+    batch: Option<BatchConfig>,
+    spawner: Option<Arc<dyn Spawner>>,
+}
+
+impl TikvConnect {
+    pub fn new(security_mgr: Arc<SecurityManager>, timeout: Duration) -> TikvConnect {
+        TikvConnect {
+            security_mgr,
+            timeout: RequestTimeout::new(timeout),
+            batch: None,
+            spawner: None,
+        }
+    }
+
+    /// The timeout of the requests sent through the connections made, which can be changed.
+    pub fn request_timeout(&self) -> RequestTimeout {
+        self.timeout.clone()
+    }
+
+    /// Send the requests which may be batched, such as prewrites and commits, to each store in
+    /// batches, as configured by `batch`.
+    #[must_use]
+    pub fn with_batching(mut self, batch: Option<BatchConfig>) -> Self {
+        self.batch = batch;
+        self
+    }
+
+    /// Run the tasks serving the batch streams of the connections made with `spawner`, or with
+    /// tokio if there is none.
+    #[must_use]
+    pub fn with_spawner(mut self, spawner: Option<Arc<dyn Spawner>>) -> Self {
+        self.spawner = spawner;
+        self
+    }
 }
 
 #[async_trait]
@@ -35,13 +102,24 @@ impl KvConnect for TikvConnect {
         self.security_mgr
             .connect(address, TikvClient::new)
             .await
-            .map(|c| KvRpcClient::new(c, self.timeout))
+            .map(|c| {
+                let batcher = self.batch.clone().map(|batch| {
+                    let batcher = CommandBatcher::new(batch, c.clone(), self.spawner.clone());
+                    Arc::new(batcher)
+                });
+                KvRpcClient::new(c, self.timeout.clone(), batcher)
+            })
     }
 }
 
 #[async_trait]
 pub trait KvClient {
     async fn dispatch(&self, req: &dyn Request) -> Result<Box<dyn Any>>;
+
+    /// Check that the store is reachable, failing if it does not respond within `timeout`.
+    async fn probe(&self, _timeout: Duration) -> Result<()> {
+        Ok(())
+    }
 }
 
 /// This client handles requests for a single TiKV node. It converts the data
@@ -49,12 +127,31 @@ pub trait KvClient {
 #[derive(new, Clone)]
 pub struct KvRpcClient {
     rpc_client: TikvClient<Channel>,
-    timeout: Duration,
+    timeout: RequestTimeout,
+    /// If set, the requests which may be batched are sent through it.
+    batcher: Option<Arc<CommandBatcher>>,
 }
 
 #[async_trait]
 impl KvClient for KvRpcClient {
     async fn dispatch(&self, request: &dyn Request) -> Result<Box<dyn Any>> {
-        request.dispatch(&self.rpc_client, self.timeout).await
+        if let Some(batcher) = &self.batcher {
+            if let Some(cmd) = request.to_batch_command() {
+                return batcher.dispatch(cmd, self.timeout.get()).await;
+            }
+        }
+        request.dispatch(&self.rpc_client, self.timeout.get()).await
+    }
+
+    async fn probe(&self, timeout: Duration) -> Result<()> {
+        // Asking for the store's safe ts is cheap, and doesn't involve any region.
+        let mut req = kvrpcpb::StoreSafeTsRequest::default().into_request();
+        req.set_timeout(timeout);
+        self.rpc_client
+            .clone()
+            .get_store_safe_ts(req)
+            .await
+            .map(|_| ())
+            .map_err(Error::GrpcAPI)
     }
 }
@@ -53,6 +53,7 @@ has_region_error!(kvrpcpb::CheckTxnStatusResponse);
 has_region_error!(kvrpcpb::CheckSecondaryLocksResponse);
 has_region_error!(kvrpcpb::DeleteRangeResponse);
 has_region_error!(kvrpcpb::GcResponse);
+has_region_error!(kvrpcpb::MvccGetByKeyResponse);
 has_region_error!(kvrpcpb::RawGetResponse);
 has_region_error!(kvrpcpb::RawBatchGetResponse);
 has_region_error!(kvrpcpb::RawPutResponse);
@@ -112,6 +113,7 @@ has_str_error!(kvrpcpb::RawCasResponse);
 has_str_error!(kvrpcpb::RawCoprocessorResponse);
 has_str_error!(kvrpcpb::ImportResponse);
 has_str_error!(kvrpcpb::DeleteRangeResponse);
+has_str_error!(kvrpcpb::MvccGetByKeyResponse);
 
 impl HasKeyErrors for kvrpcpb::ScanResponse {
     fn key_errors(&mut self) -> Option<Vec<Error>> {
@@ -120,8 +122,11 @@ impl HasKeyErrors for kvrpcpb::ScanResponse {
 }
 
 impl HasKeyErrors for kvrpcpb::BatchGetResponse {
+    // The errors of pairs which name their key are left in place, so they can be reported for that
+    // key rather than failing the reads of the other keys.
     fn key_errors(&mut self) -> Option<Vec<Error>> {
-        extract_errors(self.pairs.iter_mut().map(|pair| pair.error.take()))
+        let unnamed = self.pairs.iter_mut().filter(|pair| pair.key.is_empty());
+        extract_errors(unnamed.map(|pair| pair.error.take()))
     }
 }
 
@@ -1,5 +1,6 @@
 // Copyright 2018 TiKV Project Authors. Licensed under Apache-2.0.
 
+mod batch;
 mod client;
 mod errors;
 mod request;
@@ -8,11 +9,15 @@ pub use tikv_client_common::security::SecurityManager;
 pub use tikv_client_common::Error;
 pub use tikv_client_common::Result;
 
+#[doc(inline)]
+pub use crate::batch::BatchConfig;
 #[doc(inline)]
 pub use crate::client::KvClient;
 #[doc(inline)]
 pub use crate::client::KvConnect;
 #[doc(inline)]
+pub use crate::client::RequestTimeout;
+#[doc(inline)]
 pub use crate::client::TikvConnect;
 #[doc(inline)]
 pub use crate::errors::HasKeyErrors;
@@ -5,6 +5,7 @@ use std::time::Duration;
 
 use async_trait::async_trait;
 use tikv_client_proto::kvrpcpb;
+use tikv_client_proto::tikvpb::batch_commands_request;
 use tikv_client_proto::tikvpb::tikv_client::TikvClient;
 use tonic::transport::Channel;
 use tonic::IntoRequest;
@@ -22,10 +23,29 @@ pub trait Request: Any + Sync + Send + 'static {
     fn label(&self) -> &'static str;
     fn as_any(&self) -> &dyn Any;
     fn set_context(&mut self, context: kvrpcpb::Context);
+    /// Tag the request's context, so that TiKV attributes the resources it uses to `tag`, an
+    /// encoded `ResourceGroupTag`. Requests without a context ignore the tag.
+    fn set_resource_group_tag(&mut self, _tag: Vec<u8>) {}
+    /// The size of the request when encoded for sending.
+    fn encoded_len(&self) -> usize;
+    /// The request as a command of a `BatchCommands` stream, if it may be sent in a batch.
+    fn to_batch_command(&self) -> Option<batch_commands_request::request::Cmd> {
+        None
+    }
 }
 
 macro_rules! impl_request {
     ($name: ident, $fun: ident, $label: literal) => {
+        impl_request!($name, $fun, $label, {});
+    };
+    ($name: ident, $fun: ident, $label: literal, batch: $cmd: ident) => {
+        impl_request!($name, $fun, $label, {
+            fn to_batch_command(&self) -> Option<batch_commands_request::request::Cmd> {
+                Some(batch_commands_request::request::Cmd::$cmd(self.clone()))
+            }
+        });
+    };
+    ($name: ident, $fun: ident, $label: literal, { $($batch: tt)* }) => {
         #[async_trait]
         impl Request for kvrpcpb::$name {
             async fn dispatch(
@@ -54,6 +74,16 @@ macro_rules! impl_request {
             fn set_context(&mut self, context: kvrpcpb::Context) {
                 self.context = Some(context);
             }
+
+            fn set_resource_group_tag(&mut self, tag: Vec<u8>) {
+                self.context.get_or_insert_with(Default::default).resource_group_tag = tag;
+            }
+
+            fn encoded_len(&self) -> usize {
+                prost::Message::encoded_len(self)
+            }
+
+            $($batch)*
         }
     };
 }
@@ -72,8 +102,8 @@ impl_request!(RawCoprocessorRequest, raw_coprocessor, "raw_coprocessor");
 
 impl_request!(GetRequest, kv_get, "kv_get");
 impl_request!(ScanRequest, kv_scan, "kv_scan");
-impl_request!(PrewriteRequest, kv_prewrite, "kv_prewrite");
-impl_request!(CommitRequest, kv_commit, "kv_commit");
+impl_request!(PrewriteRequest, kv_prewrite, "kv_prewrite", batch: Prewrite);
+impl_request!(CommitRequest, kv_commit, "kv_commit", batch: Commit);
 impl_request!(CleanupRequest, kv_cleanup, "kv_cleanup");
 impl_request!(BatchGetRequest, kv_batch_get, "kv_batch_get");
 impl_request!(BatchRollbackRequest, kv_batch_rollback, "kv_batch_rollback");
@@ -102,3 +132,4 @@ impl_request!(
 );
 impl_request!(GcRequest, kv_gc, "kv_gc");
 impl_request!(DeleteRangeRequest, kv_delete_range, "kv_delete_range");
+impl_request!(MvccGetByKeyRequest, mvcc_get_by_key, "mvcc_get_by_key");